@@ -0,0 +1,89 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `uname -m` and btfhub-archive don't always agree on the name of an architecture,
+//! e.g. `aarch64` against `arm64`, and archives built by other pipelines may use either.
+
+/// (btfhub directory, other names of the architecture) of the known architectures
+const ARCHES: &[(&str, &[&str])] = &[
+    ("x86_64", &["amd64"]),
+    ("arm64", &["aarch64"]),
+    ("x86", &["i686", "i586", "i486", "i386"]),
+    ("arm", &["armv7l", "armv7", "armhf"]),
+    ("ppc64le", &["ppc64el"]),
+    ("s390x", &[]),
+    ("riscv64", &[]),
+];
+
+/// The btfhub directory name of the architecture `machine`, as reported by `uname -m`,
+/// e.g. `arm64` for `aarch64`, `arm` for `armv7l` and `x86` for `i686`
+///
+/// Unknown architectures are returned as is.
+pub fn normalize_arch(machine: &str) -> &str {
+    ARCHES
+        .iter()
+        .find(|(canonical, aliases)| *canonical == machine || aliases.contains(&machine))
+        .map_or(machine, |(canonical, _)| canonical)
+}
+
+/// Every directory name the architecture `machine` may be stored under, the btfhub one first
+pub fn arch_directories(machine: &str) -> Vec<&str> {
+    let canonical = normalize_arch(machine);
+    let aliases = ARCHES
+        .iter()
+        .find(|(v, _)| *v == canonical)
+        .map_or(&[][..], |(_, aliases)| aliases);
+    let mut directories = vec![canonical];
+    directories.extend_from_slice(aliases);
+    directories
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_alias_is_normalized() {
+        for (machine, expected) in [
+            ("x86_64", "x86_64"),
+            ("amd64", "x86_64"),
+            ("aarch64", "arm64"),
+            ("arm64", "arm64"),
+            ("i686", "x86"),
+            ("i386", "x86"),
+            ("armv7l", "arm"),
+            ("armhf", "arm"),
+            ("ppc64el", "ppc64le"),
+            ("ppc64le", "ppc64le"),
+            ("s390x", "s390x"),
+            ("riscv64", "riscv64"),
+        ] {
+            assert_eq!(normalize_arch(machine), expected, "{machine}");
+        }
+    }
+
+    #[test]
+    fn unknown_arch_is_passed_through() {
+        assert_eq!(normalize_arch("loongarch64"), "loongarch64");
+        assert_eq!(normalize_arch("AARCH64"), "AARCH64");
+        assert_eq!(arch_directories("loongarch64"), ["loongarch64"]);
+    }
+
+    #[test]
+    fn aliases_follow_the_btfhub_directory() {
+        assert_eq!(arch_directories("aarch64"), ["arm64", "aarch64"]);
+        assert_eq!(arch_directories("arm64"), ["arm64", "aarch64"]);
+        assert_eq!(arch_directories("x86_64"), ["x86_64", "amd64"]);
+        assert_eq!(arch_directories("s390x"), ["s390x"]);
+        assert_eq!(
+            arch_directories("i686"),
+            ["x86", "i686", "i586", "i486", "i386"]
+        );
+        assert_eq!(
+            arch_directories("armv7l"),
+            ["arm", "armv7l", "armv7", "armhf"]
+        );
+    }
+}
@@ -0,0 +1,1058 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! A btfhub archive held in memory, like the one linked into an executable.
+//!
+//! Besides the btf of the exact kernel release, an archive usually holds the btfs of
+//! neighbouring point releases, which may be tried in turn when the obvious one fails
+//! CO-RE relocation, e.g. for a kernel carrying backports.
+#[cfg(feature = "host")]
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(feature = "host")]
+use crate::{
+    arch::arch_directories,
+    btf::has_swapped_magic,
+    compression::{tar_archive, tar_entries, tar_reader_with_limit, DEFAULT_MAX_DECOMPRESSED_SIZE},
+    listing::{ArchiveListing, LISTING_ENTRY_NAME},
+    metadata::{ArchiveInfo, ArchiveMetadata, CountingReader, METADATA_ENTRY_NAME},
+    release::{rank_releases, release_variants, CandidateReason},
+    sparse::{entry_contents, entry_path, entry_size, is_file_entry, read_entry},
+    stream::BtfReader,
+    version::normalize_version,
+    Error, Result, SystemInfo,
+};
+use crate::{distro::GENERIC_DISTRO, join_archive_path, matcher::split_btf_name};
+
+/// Directory of the archive holding the btfs
+pub const BTFHUB_ARCHIVE_DIR: &str = "btfhub-archive";
+
+/// How the btf is stored in an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum BtfEncoding {
+    /// `<release>.btf`, the btf itself
+    Plain,
+    /// `<release>.btf.gz`, the btf gzipped on its own
+    Gzipped,
+    /// `<release>.btf.tar.xz` or `<release>.btf.tar.gz`, a tarball holding the btf, as in btfhub-archive
+    Tarball,
+}
+
+/// Suffixes of the entries holding a btf, and how the btf is stored in them
+pub(crate) const BTF_ENTRY_SUFFIXES: &[(&str, BtfEncoding)] = &[
+    (".btf", BtfEncoding::Plain),
+    (".btf.gz", BtfEncoding::Gzipped),
+    (".btf.tar.xz", BtfEncoding::Tarball),
+    (".btf.tar.gz", BtfEncoding::Tarball),
+];
+
+/// A btf entry of the archive, at `<prefix>/<distro>/<version>/<arch>/<kernel_release>.btf`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BtfEntry {
+    pub distro: String,
+    pub version: String,
+    pub arch: String,
+    pub kernel_release: String,
+    /// Path of the entry as stored in the archive, e.g. with a leading `./`
+    pub path: PathBuf,
+    /// Size of the entry in the tar, i.e. after decompressing the archive but not the entry
+    pub size: u64,
+    pub encoding: BtfEncoding,
+    /// Whether the entry is a hardlink or symlink to another entry, rather than a regular file
+    pub is_link: bool,
+    /// Whether the btf was generated on a host of the other byte order, see
+    /// [`has_swapped_magic`]; only checked for plain regular files
+    pub byte_swapped: bool,
+}
+
+impl BtfEntry {
+    /// `<distro>/<version>/<arch>/<kernel_release>`, or `generic/<arch>/<kernel_release>`
+    pub fn kernel(&self) -> String {
+        kernel_name(
+            &self.distro,
+            &self.version,
+            &self.arch,
+            &self.kernel_release,
+        )
+    }
+
+    /// The btf entry at `path`, relative to the archive root, if it is laid out as
+    /// `<prefix>/<distro>/<version>/<arch>/<release>.btf` (or compressed), see [`BtfEntryInfo`]
+    ///
+    /// Only the path is looked at, so `size` is 0 and `is_link` and `byte_swapped` are unset;
+    /// a leading `./` or `/` of `path` and `prefix` is ignored.
+    pub fn from_path(path: impl AsRef<Path>, prefix: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        let (distro, version, arch, kernel_release, encoding) = parse_btf_path(
+            &normalize_entry_path(path),
+            &normalize_entry_path(prefix.as_ref()),
+        )?;
+        Some(Self {
+            distro,
+            version,
+            arch,
+            kernel_release,
+            path: path.to_path_buf(),
+            size: 0,
+            encoding,
+            is_link: false,
+            byte_swapped: false,
+        })
+    }
+}
+
+/// An entry of the archive, see [`BtfhubArchive::entries`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum BtfEntryInfo {
+    Btf(BtfEntry),
+    /// A file or link that isn't a btf laid out as expected, e.g. `package.json` or
+    /// `btfhub-archive/ubuntu/5.4.0-40-generic.btf`, with its path as stored in the archive
+    Other(PathBuf),
+}
+
+/// A (possibly compressed) tar of btfs laid out as `btfhub-archive/<distro>/<version>/<arch>/<release>.btf`
+///
+/// The directory holding the btfs may be named otherwise, see [`BtfhubArchive::with_prefix`]
+#[cfg(feature = "host")]
+#[derive(Debug, Clone, Copy)]
+pub struct BtfhubArchive<'a> {
+    bytes: &'a [u8],
+    prefix: &'a Path,
+    max_decompressed_size: u64,
+}
+
+/// A btf of the archive that may be used for a system, see [`BtfhubArchive::lookup_candidates`]
+#[cfg(feature = "host")]
+#[derive(Debug, Clone)]
+pub struct BtfCandidate<'a> {
+    archive: BtfhubArchive<'a>,
+    /// Path of the entry, like `btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf`
+    pub path: PathBuf,
+    /// Kernel release the btf was generated for
+    pub release: String,
+    /// Why the btf was picked
+    pub reason: CandidateReason,
+}
+
+#[cfg(feature = "host")]
+impl BtfCandidate<'_> {
+    /// Read the contents of the btf from the archive
+    pub fn extract(&self) -> Result<Vec<u8>> {
+        self.archive.extract(&self.path)
+    }
+}
+
+/// The components of `path` under `prefix`, if it's there and they're all plain UTF-8 names
+fn relative_components<'a>(path: &'a Path, prefix: &Path) -> Option<Vec<&'a str>> {
+    path.strip_prefix(prefix)
+        .ok()?
+        .components()
+        .map(|v| match v {
+            Component::Normal(v) => v.to_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Same as [`parse_btf_path`], for the btf of a module, at
+/// `<prefix>/<distro>/<version>/<arch>/<release>/modules/<module>.btf`
+#[cfg(feature = "host")]
+pub(crate) fn parse_module_btf_path(path: &Path, prefix: &Path) -> Option<[String; 5]> {
+    let components = relative_components(path, prefix)?;
+    let [distro, version, arch, kernel_release, crate::MODULES_DIR, file_name] = components[..]
+    else {
+        return None;
+    };
+    let module = file_name.strip_suffix(".btf").filter(|v| !v.is_empty())?;
+    Some([distro, version, arch, kernel_release, module].map(str::to_string))
+}
+
+/// Split `path`, relative to the archive root, into the distro, version, arch, kernel
+/// release and encoding of the btf, if it is `<prefix>/<distro>/<version>/<arch>/<release>.btf`,
+/// or `<prefix>/generic/<arch>/<release>.btf` with an empty version
+pub(crate) fn parse_btf_path(
+    path: &Path,
+    prefix: &Path,
+) -> Option<(String, String, String, String, BtfEncoding)> {
+    let components = relative_components(path, prefix)?;
+    // generic/<arch>/<release>.btf 的条目不属于任何发行版版本
+    let (distro, version, arch, file_name) = match components[..] {
+        [distro, version, arch, file_name] => (distro, version, arch, file_name),
+        [GENERIC_DISTRO, arch, file_name] => (GENERIC_DISTRO, "", arch, file_name),
+        _ => return None,
+    };
+    // 与查找时对条目的匹配一致，见 matcher::split_btf_name
+    let (kernel_release, encoding) = split_btf_name(file_name)?;
+    Some((
+        distro.to_string(),
+        version.to_string(),
+        arch.to_string(),
+        kernel_release.to_string(),
+        encoding,
+    ))
+}
+
+/// Name of the kernel of a btf entry, see [`BtfEntry::kernel`]
+pub(crate) fn kernel_name(distro: &str, version: &str, arch: &str, kernel_release: &str) -> String {
+    // generic 目录下的条目没有版本，不留下空的路径组件
+    match version {
+        "" => join_archive_path(&[distro, arch, kernel_release]),
+        _ => join_archive_path(&[distro, version, arch, kernel_release]),
+    }
+}
+
+/// `path` with `.` and `..` resolved lexically and without leading `/`, so
+/// `./btfhub-archive/x`, `/btfhub-archive/x`, `btfhub-archive//x` and `btfhub-archive/y/../x`
+/// compare equal to `btfhub-archive/x`
+///
+/// `..` never climbs above the root of the archive. Entry paths, and the targets of links,
+/// are compared in this form by every lookup, here and in the C API.
+pub fn normalize_entry_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(v) => normalized.push(v),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    normalized
+}
+
+/// The path of an entry named `bytes`, as tar stores names
+#[cfg(all(feature = "host", target_os = "linux"))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// The path of an entry named `bytes`, read as UTF-8 where paths aren't bytes, lossily
+#[cfg(all(feature = "host", not(target_os = "linux")))]
+pub(crate) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(feature = "host")]
+impl<'a> BtfhubArchive<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            prefix: Path::new(BTFHUB_ARCHIVE_DIR),
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Look for the btfs under `prefix` instead of [`BTFHUB_ARCHIVE_DIR`], e.g. `btfs`
+    ///
+    /// An empty prefix means the entries start directly with `<distro>/`. A leading `./` or
+    /// `/`, of either the prefix or the entries, makes no difference.
+    pub fn with_prefix<P: AsRef<Path> + ?Sized>(mut self, prefix: &'a P) -> Self {
+        self.prefix = prefix.as_ref();
+        self
+    }
+
+    /// Fail reading a compressed archive once it decompresses to more than `max_size`
+    /// bytes, instead of [`DEFAULT_MAX_DECOMPRESSED_SIZE`]
+    pub fn with_max_decompressed_size(mut self, max_size: u64) -> Self {
+        self.max_decompressed_size = max_size;
+        self
+    }
+
+    /// The files and links of the archive, in archive order, parsed into [`BtfEntry`] where they are btfs
+    ///
+    /// Directories are skipped. A path that doesn't follow the layout is returned as
+    /// [`BtfEntryInfo::Other`] rather than failing. The iteration ends after the first
+    /// error reading the archive.
+    pub fn entries(&self) -> impl Iterator<Item = Result<BtfEntryInfo>> {
+        let mut entries = vec![];
+        if let Err(e) = self.for_each_entry(|v| entries.push(Ok(v))) {
+            entries.push(Err(e));
+        }
+        entries.into_iter()
+    }
+
+    /// Parse every file and link of the archive, see [`BtfhubArchive::entries`]
+    fn for_each_entry(&self, mut visit: impl FnMut(BtfEntryInfo)) -> Result<()> {
+        let prefix = normalize_entry_path(self.prefix);
+        let mut archive = tar_archive(tar_reader_with_limit(
+            self.bytes,
+            self.max_decompressed_size,
+        )?);
+        for entry in tar_entries(&mut archive).map_err(Error::from_read)? {
+            let mut entry = entry.map_err(Error::from_read)?;
+            let entry_type = entry.header().entry_type();
+            let is_link = entry_type.is_hard_link() || entry_type.is_symlink();
+            if !is_file_entry(entry_type) && !is_link {
+                continue;
+            }
+            let path = entry_path(&mut entry).map_err(Error::from_read)?;
+            let Some((distro, version, arch, kernel_release, encoding)) =
+                parse_btf_path(&normalize_entry_path(&path), &prefix)
+            else {
+                visit(BtfEntryInfo::Other(path));
+                continue;
+            };
+            let size = entry_size(&mut entry).map_err(Error::from_read)?;
+            // 只读取开头的魔数，用于识别其他字节序的主机上生成的 btf
+            let mut magic = [0; 2];
+            let byte_swapped = !is_link
+                && encoding == BtfEncoding::Plain
+                && entry_contents(&mut entry).is_ok_and(|mut v| v.read_exact(&mut magic).is_ok())
+                && has_swapped_magic(&magic);
+            visit(BtfEntryInfo::Btf(BtfEntry {
+                distro,
+                version,
+                arch,
+                kernel_release,
+                size,
+                path,
+                encoding,
+                is_link,
+                byte_swapped,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Walk the regular entries of the archive
+    fn for_each_file(
+        &self,
+        mut visit: impl FnMut(PathBuf, &mut dyn Read) -> Result<()>,
+    ) -> Result<()> {
+        let mut archive = tar_archive(tar_reader_with_limit(
+            self.bytes,
+            self.max_decompressed_size,
+        )?);
+        for entry in tar_entries(&mut archive).map_err(Error::from_read)? {
+            let mut entry = entry.map_err(Error::from_read)?;
+            if !is_file_entry(entry.header().entry_type()) {
+                continue;
+            }
+            let path = normalize_entry_path(&entry_path(&mut entry).map_err(Error::from_read)?);
+            visit(
+                path,
+                &mut entry_contents(&mut entry).map_err(Error::from_read)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The btfs of the archive that may be used for `info`, best first
+    ///
+    /// Only `<distro>/<version>/<arch>` of `info` is looked into, with the version
+    /// normalized by [`normalize_version`], then as given if that differs, and the
+    /// architecture under each name of [`arch_directories`], the btfhub one first. The btf of the
+    /// exact kernel release comes first, then the ones of the same major.minor and flavor,
+    /// ranked by [`rank_releases`]. Releases of RHEL-likes are matched with and without
+    /// their trailing architecture, see [`release_variants`]. Btfs of the other byte order
+    /// than the host's are left out.
+    pub fn lookup_candidates(&self, info: &SystemInfo) -> Result<Vec<BtfCandidate<'a>>> {
+        let versions = [
+            normalize_version(&info.distro_id, &info.version_id),
+            &info.version_id,
+        ];
+        let prefix = normalize_entry_path(self.prefix);
+        let dirs = arch_directories(&info.arch)
+            .into_iter()
+            .flat_map(|arch| versions.map(|version| (version, arch)))
+            .collect::<Vec<_>>();
+        let mut entries = vec![];
+        let mut seen_btfhub_entry = false;
+        for entry in self.entries() {
+            let entry = match entry? {
+                BtfEntryInfo::Btf(v) => v,
+                BtfEntryInfo::Other(path) => {
+                    seen_btfhub_entry |= normalize_entry_path(&path).starts_with(&prefix);
+                    continue;
+                }
+            };
+            seen_btfhub_entry = true;
+            // 其他字节序的主机上生成的 btf（如误打包进来的 s390x 的）无法使用，跳过以便其他候选胜出
+            if entry.encoding != BtfEncoding::Plain || entry.is_link || entry.byte_swapped {
+                continue;
+            }
+            if entry.distro != info.distro_id {
+                continue;
+            }
+            let Some(dir_index) = dirs
+                .iter()
+                .position(|(version, arch)| entry.version == *version && entry.arch == *arch)
+            else {
+                continue;
+            };
+            let path = normalize_entry_path(&entry.path);
+            log_at!(Debug, "Considering {}", path.display());
+            entries.push((entry.kernel_release, dir_index, path));
+        }
+        if !seen_btfhub_entry {
+            return Err(Error::NotBtfhubArchive);
+        }
+        // 同一内核版本在多个目录中都存在时，保留最靠前的目录中的
+        entries.sort_by_key(|(_, dir_index, _)| *dir_index);
+        let mut seen_releases = vec![];
+        entries.retain(|(release, _, _)| {
+            let first = !seen_releases.contains(release);
+            seen_releases.push(release.clone());
+            first
+        });
+        let releases = entries
+            .iter()
+            .map(|(v, _, _)| v.as_str())
+            .collect::<Vec<_>>();
+        // 依次以原样的和去掉架构后缀的内核版本排序，同一理由下原样的在前
+        let mut arches = arch_directories(&info.arch);
+        arches.push(&info.arch);
+        let mut ranked = release_variants(&info.kernel_release, &arches)
+            .into_iter()
+            .flat_map(|release| rank_releases(release, &releases))
+            .collect::<Vec<_>>();
+        ranked.sort_by_key(|(_, reason)| *reason);
+        let mut seen_releases = vec![];
+        ranked.retain(|(release, _)| {
+            let first = !seen_releases.contains(release);
+            seen_releases.push(*release);
+            first
+        });
+        if let Some((release, reason)) =
+            ranked.first().filter(|(_, v)| *v != CandidateReason::Exact)
+        {
+            log_at!(
+                Warn,
+                "No btf of {} in the archive, the best candidate is {} ({:?})",
+                info.kernel_release,
+                release,
+                reason
+            );
+        }
+        Ok(ranked
+            .into_iter()
+            .filter_map(|(release, reason)| {
+                let (_, _, path) = entries.iter().find(|(v, _, _)| v == release)?;
+                Some(BtfCandidate {
+                    archive: *self,
+                    path: path.clone(),
+                    release: release.to_string(),
+                    reason,
+                })
+            })
+            .collect())
+    }
+
+    /// The kernels the archive has a btf for, as `<distro>/<version>/<arch>/<release>`, in archive order
+    ///
+    /// Only entries under the prefix are looked at. Besides `.btf` files, btfs gzipped on
+    /// their own (`.btf.gz`) or packed as in btfhub-archive (`.btf.tar.xz`, `.btf.tar.gz`)
+    /// count, as do links to another btf; directories and other files are skipped. A kernel
+    /// listed more than once is only returned the first time. If the archive starts with
+    /// a listing, the kernels are taken from it instead, see [`BtfhubArchive::listing`].
+    pub fn kernels(&self) -> Result<Vec<String>> {
+        if let Some(listing) = self.listing() {
+            return Ok(listing.kernels(self.prefix));
+        }
+        let mut kernels = vec![];
+        for entry in self.entries() {
+            if let BtfEntryInfo::Btf(entry) = entry? {
+                let kernel = entry.kernel();
+                if !kernels.contains(&kernel) {
+                    kernels.push(kernel);
+                }
+            }
+        }
+        Ok(kernels)
+    }
+
+    /// The listing entry, [`LISTING_ENTRY_NAME`] under the prefix, if it comes before the btfs
+    ///
+    /// Only the start of the archive is read, up to the listing or the first btf. Returns
+    /// `None` if there is no listing before the btfs, or if it can't be read or parsed.
+    pub fn listing(&self) -> Option<ArchiveListing> {
+        let prefix = normalize_entry_path(self.prefix);
+        let listing_path = prefix.join(LISTING_ENTRY_NAME);
+        let mut archive =
+            tar_archive(tar_reader_with_limit(self.bytes, self.max_decompressed_size).ok()?);
+        for entry in tar_entries(&mut archive).ok()? {
+            let mut entry = entry.ok()?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = normalize_entry_path(&entry.path().ok()?);
+            if path == listing_path {
+                let mut contents = vec![];
+                entry.read_to_end(&mut contents).ok()?;
+                return ArchiveListing::parse(&contents);
+            }
+            // 清单需在所有 btf 之前，流式读取时才能不读完整个归档
+            if parse_btf_path(&path, &prefix).is_some() {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// The number of btfs of the archive, its sizes and its metadata entry
+    ///
+    /// The btfs are counted as by [`BtfhubArchive::entries`]. The metadata entry is
+    /// [`METADATA_ENTRY_NAME`] under the prefix, e.g. `btfhub-archive/.metadata`. The
+    /// archive is decompressed to the end to measure its size.
+    pub fn info(&self) -> Result<ArchiveInfo> {
+        let prefix = normalize_entry_path(self.prefix);
+        let metadata_path = prefix.join(METADATA_ENTRY_NAME);
+        let mut info = ArchiveInfo {
+            compressed_size: self.bytes.len() as u64,
+            ..Default::default()
+        };
+        let mut archive = tar_archive(CountingReader::new(tar_reader_with_limit(
+            self.bytes,
+            self.max_decompressed_size,
+        )?));
+        for entry in tar_entries(&mut archive).map_err(Error::from_read)? {
+            let mut entry = entry.map_err(Error::from_read)?;
+            let entry_type = entry.header().entry_type();
+            let is_link = entry_type.is_hard_link() || entry_type.is_symlink();
+            if !is_file_entry(entry_type) && !is_link {
+                continue;
+            }
+            let path = normalize_entry_path(&entry_path(&mut entry).map_err(Error::from_read)?);
+            if parse_btf_path(&path, &prefix).is_some() {
+                info.btf_entries += 1;
+            } else if path == metadata_path && !is_link {
+                let contents = read_entry(&mut entry).map_err(Error::from_read)?;
+                info.metadata = Some(ArchiveMetadata::parse(&contents));
+            }
+        }
+        // 读完结尾的空块和填充，得到解压后的完整大小
+        let mut reader = archive.into_inner();
+        std::io::copy(&mut reader, &mut std::io::sink()).map_err(Error::from_read)?;
+        info.uncompressed_size = reader.count;
+        Ok(info)
+    }
+
+    /// The btf of `entry`, decompressed as it's read rather than extracted up front
+    ///
+    /// Links are followed, and the btf is decoded according to its encoding; see
+    /// [`BtfReader`] for what is checked, and [`crate::stream`] for how the entry is read.
+    pub fn open_btf(&self, entry: &BtfEntry) -> Result<BtfReader<'a>> {
+        BtfReader::open(
+            self.bytes,
+            self.max_decompressed_size,
+            &entry.path,
+            entry.encoding,
+        )
+    }
+
+    /// Read the contents of the regular entry at `path`
+    ///
+    /// If the path occurs more than once, the last entry wins, as when unpacking the tar
+    pub fn extract(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
+        let path = normalize_entry_path(path.as_ref());
+        let mut contents = None;
+        self.for_each_file(|entry_path, entry| {
+            if entry_path == path {
+                let mut data = vec![];
+                entry.read_to_end(&mut data).map_err(Error::from_read)?;
+                contents = Some(data);
+            }
+            Ok(())
+        })?;
+        contents.ok_or_else(|| Error::EntryNotFound {
+            expected: path.clone(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use super::*;
+    use crate::fixture::{minimal_valid_btf, FixtureArchive};
+
+    #[test]
+    fn entry_paths_are_normalized_lexically() {
+        for path in [
+            "btfhub-archive/x",
+            "./btfhub-archive/x",
+            "/btfhub-archive/x",
+            "btfhub-archive//x",
+            "btfhub-archive/./x",
+            "btfhub-archive/y/../x",
+            "../btfhub-archive/x",
+            "/../../btfhub-archive/x",
+        ] {
+            assert_eq!(
+                normalize_entry_path(Path::new(path)),
+                Path::new("btfhub-archive/x")
+            );
+        }
+        assert_eq!(normalize_entry_path(Path::new("./..")), Path::new(""));
+    }
+
+    #[test]
+    fn entries_are_extracted_by_their_normalized_path() {
+        let tar = FixtureArchive::new()
+            .file("./btfhub-archive/ubuntu/20.04/x86_64/a.btf", b"a".to_vec())
+            .file(
+                "btfhub-archive/debian/../ubuntu/20.04/x86_64/b.btf",
+                b"b".to_vec(),
+            )
+            .tar();
+        let archive = BtfhubArchive::new(&tar);
+        assert_eq!(
+            archive
+                .extract("btfhub-archive/ubuntu/20.04/x86_64/a.btf")
+                .unwrap(),
+            b"a"
+        );
+        assert_eq!(
+            archive
+                .extract("/btfhub-archive/ubuntu/20.04/x86_64/b.btf")
+                .unwrap(),
+            b"b"
+        );
+        assert!(matches!(
+            archive.extract("btfhub-archive/debian/b.btf"),
+            Err(Error::EntryNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn kernels_are_listed_from_dotted_paths() {
+        let tar = FixtureArchive::new()
+            .with_prefix("./btfhub-archive")
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                minimal_valid_btf(),
+            )
+            .tar();
+        assert_eq!(
+            BtfhubArchive::new(&tar).kernels().unwrap(),
+            ["ubuntu/20.04/x86_64/5.4.0-40-generic"]
+        );
+    }
+
+    #[test]
+    fn generic_entries_have_no_version() {
+        let prefix = Path::new("btfhub-archive");
+        assert_eq!(
+            parse_btf_path(
+                Path::new("btfhub-archive/generic/x86_64/6.5.3-arch1-1.btf.gz"),
+                prefix
+            ),
+            Some((
+                "generic".into(),
+                "".into(),
+                "x86_64".into(),
+                "6.5.3-arch1-1".into(),
+                BtfEncoding::Gzipped
+            ))
+        );
+        // 只有 generic 目录可以省略版本
+        for path in [
+            "btfhub-archive/arch/x86_64/6.5.3-arch1-1.btf",
+            "btfhub-archive/generic/6.5.3-arch1-1.btf",
+            "btfhub-archive/generic/x86_64/.btf",
+        ] {
+            assert_eq!(parse_btf_path(Path::new(path), prefix), None, "{path}");
+        }
+        let tar = FixtureArchive::new()
+            .file(
+                "btfhub-archive/generic/x86_64/6.5.3-arch1-1.btf",
+                minimal_valid_btf(),
+            )
+            .tar();
+        assert_eq!(
+            BtfhubArchive::new(&tar).kernels().unwrap(),
+            ["generic/x86_64/6.5.3-arch1-1"]
+        );
+    }
+
+    #[test]
+    fn malformed_paths_are_returned_among_the_parsed_btfs() {
+        let btf = minimal_valid_btf();
+        let tar = FixtureArchive::new()
+            .dir("btfhub-archive/ubuntu/20.04/x86_64")
+            .file(
+                "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                btf.clone(),
+            )
+            .file("package.json", b"{}".to_vec())
+            .file("btfhub-archive/ubuntu/5.4.0-40-generic.btf", btf.clone())
+            .file("btfhub-archive/ubuntu/20.04/x86_64/.btf", vec![])
+            .file(
+                "btfhub-archive/debian/11/arm64/5.10.0-23-arm64.btf.gz",
+                vec![0; 3],
+            )
+            .tar();
+        let entries = BtfhubArchive::new(&tar)
+            .entries()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let other = |v: &str| BtfEntryInfo::Other(PathBuf::from(v));
+        let btf_entry = |path: &str, size, encoding| {
+            BtfEntryInfo::Btf(BtfEntry {
+                size,
+                encoding,
+                ..BtfEntry::from_path(path, "btfhub-archive").unwrap()
+            })
+        };
+        assert_eq!(
+            entries,
+            [
+                btf_entry(
+                    "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                    btf.len() as u64,
+                    BtfEncoding::Plain
+                ),
+                other("package.json"),
+                // 少了一级目录，或者没有内核版本
+                other("btfhub-archive/ubuntu/5.4.0-40-generic.btf"),
+                other("btfhub-archive/ubuntu/20.04/x86_64/.btf"),
+                btf_entry(
+                    "btfhub-archive/debian/11/arm64/5.10.0-23-arm64.btf.gz",
+                    3,
+                    BtfEncoding::Gzipped
+                ),
+            ]
+        );
+        let BtfEntryInfo::Btf(first) = &entries[0] else {
+            unreachable!()
+        };
+        assert_eq!(
+            (
+                first.distro.as_str(),
+                first.version.as_str(),
+                first.arch.as_str(),
+                first.kernel_release.as_str()
+            ),
+            ("ubuntu", "20.04", "x86_64", "5.4.0-40-generic")
+        );
+    }
+
+    #[test]
+    fn entries_end_with_the_error_reading_the_archive() {
+        let mut fixture = FixtureArchive::new();
+        for i in 0..64 {
+            fixture = fixture.btf("ubuntu", "20.04", "x86_64", &i.to_string(), vec![i; 4096]);
+        }
+        let tar = fixture.tar();
+        let entries = BtfhubArchive::new(&tar[..tar.len() / 2])
+            .entries()
+            .collect::<Vec<_>>();
+        // 截断之前的条目照常返回，错误只出现一次且在最后
+        assert!(entries.len() > 1);
+        assert!(entries[..entries.len() - 1].iter().all(|v| v.is_ok()));
+        assert!(entries.last().unwrap().is_err());
+        assert!(BtfhubArchive::new(b"not an archive")
+            .entries()
+            .next()
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn kernels_are_listed_under_the_prefix_only() {
+        let tar = FixtureArchive::new()
+            .with_prefix("custom")
+            .btf("fedora", "38", "x86_64", "6.2.9-300.fc38.x86_64", vec![])
+            .with_prefix("btfhub-archive")
+            .btf("ubuntu", "22.04", "x86_64", "5.15.0-25-generic", vec![])
+            .tar();
+        assert_eq!(
+            BtfhubArchive::new(&tar)
+                .with_prefix("custom")
+                .kernels()
+                .unwrap(),
+            ["fedora/38/x86_64/6.2.9-300.fc38.x86_64"]
+        );
+        assert_eq!(
+            BtfhubArchive::new(&tar).kernels().unwrap(),
+            ["ubuntu/22.04/x86_64/5.15.0-25-generic"]
+        );
+    }
+
+    #[test]
+    fn info_counts_btfs_and_reads_the_metadata() {
+        let fixture = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                minimal_valid_btf(),
+            )
+            .symlink(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+                "5.4.0-40-generic.btf",
+            )
+            .file("btfhub-archive/SHA256SUMS", b"".to_vec())
+            .dir("btfhub-archive/centos/8/x86_64")
+            .file(
+                "elsewhere/fedora/38/x86_64/6.2.9-300.fc38.x86_64.btf",
+                vec![],
+            );
+        let tar = fixture.tar();
+        let gz = fixture.gz();
+        let info = BtfhubArchive::new(&gz).info().unwrap();
+        assert_eq!(
+            info,
+            ArchiveInfo {
+                btf_entries: 2,
+                uncompressed_size: tar.len() as u64,
+                compressed_size: gz.len() as u64,
+                metadata: None,
+            }
+        );
+        // 未压缩的归档两个大小相同
+        let info = BtfhubArchive::new(&tar).info().unwrap();
+        assert_eq!(
+            (info.uncompressed_size, info.compressed_size),
+            (tar.len() as u64, tar.len() as u64)
+        );
+
+        let gz = fixture
+            .file(
+                "btfhub-archive/.metadata",
+                b"build_time=1700000000\nbuild_id=\"ci-42\"\n".to_vec(),
+            )
+            .gz();
+        let info = BtfhubArchive::new(&gz).info().unwrap();
+        assert_eq!(info.btf_entries, 2);
+        let metadata = info.metadata.unwrap();
+        assert_eq!(metadata.build_time, Some(1700000000));
+        assert_eq!(metadata.build_id.as_deref(), Some("ci-42"));
+        // 其他前缀下的元数据不属于该归档
+        let info = BtfhubArchive::new(&gz)
+            .with_prefix("elsewhere")
+            .info()
+            .unwrap();
+        assert_eq!((info.btf_entries, info.metadata), (1, None));
+        assert!(BtfhubArchive::new(&gz[..gz.len() / 2]).info().is_err());
+    }
+
+    #[test]
+    fn candidates_are_looked_up_in_the_directory_of_the_system() {
+        let mut fixture = FixtureArchive::new();
+        for release in ["5.4.0-140", "5.4.0-144", "5.4.0-150"] {
+            let name = format!("{}-generic", release);
+            fixture = fixture.btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                &name,
+                name.clone().into_bytes(),
+            );
+        }
+        let tar = fixture
+            .btf("ubuntu", "18.04", "x86_64", "5.4.0-146-generic", vec![])
+            .btf("ubuntu", "20.04", "arm64", "5.4.0-146-generic", vec![])
+            .btf("centos", "8", "x86_64", "5.4.0-146-generic", vec![])
+            .tar();
+        let info = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: "5.4.0-146-generic".into(),
+            ..Default::default()
+        };
+        let candidates = BtfhubArchive::new(&tar).lookup_candidates(&info).unwrap();
+        let ranked = candidates
+            .iter()
+            .map(|v| (v.release.as_str(), v.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranked,
+            [
+                ("5.4.0-144-generic", CandidateReason::LowerRevision),
+                ("5.4.0-140-generic", CandidateReason::LowerRevision),
+                ("5.4.0-150-generic", CandidateReason::HigherRevision),
+            ]
+        );
+        assert_eq!(
+            candidates[0].path,
+            Path::new("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-144-generic.btf")
+        );
+        for candidate in &candidates {
+            assert_eq!(candidate.extract().unwrap(), candidate.release.as_bytes());
+        }
+    }
+
+    #[test]
+    fn candidates_of_an_archive_without_btfhub_entries_are_an_error() {
+        let tar = FixtureArchive::new().file("other/x", vec![]).tar();
+        let info = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: "5.4.0-146-generic".into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            BtfhubArchive::new(&tar).lookup_candidates(&info),
+            Err(Error::NotBtfhubArchive)
+        ));
+    }
+
+    #[test]
+    fn rhel_releases_match_entries_with_and_without_their_arch() {
+        let centos = |release: &str| SystemInfo {
+            distro_id: "centos".into(),
+            version_id: "8".into(),
+            arch: "x86_64".into(),
+            kernel_release: release.into(),
+            ..Default::default()
+        };
+        let releases = |tar: &[u8], release: &str| {
+            BtfhubArchive::new(tar)
+                .lookup_candidates(&centos(release))
+                .unwrap()
+                .into_iter()
+                .map(|v| (v.release, v.reason))
+                .collect::<Vec<_>>()
+        };
+        let stripped = FixtureArchive::new()
+            .btf("centos", "8", "x86_64", "4.18.0-425.3.1.el8", vec![])
+            .tar();
+        assert_eq!(
+            releases(&stripped, "4.18.0-425.3.1.el8.x86_64"),
+            [("4.18.0-425.3.1.el8".to_string(), CandidateReason::Exact)]
+        );
+        // 两种形式都存在时原样的优先
+        let both = FixtureArchive::new()
+            .btf("centos", "8", "x86_64", "4.18.0-425.3.1.el8", vec![])
+            .btf("centos", "8", "x86_64", "4.18.0-425.3.1.el8.x86_64", vec![])
+            .tar();
+        assert_eq!(
+            releases(&both, "4.18.0-425.3.1.el8.x86_64"),
+            [
+                (
+                    "4.18.0-425.3.1.el8.x86_64".to_string(),
+                    CandidateReason::Exact
+                ),
+                ("4.18.0-425.3.1.el8".to_string(), CandidateReason::Exact),
+            ]
+        );
+        // 条目带架构后缀而内核版本不带时不匹配
+        let verbatim = FixtureArchive::new()
+            .btf("centos", "8", "x86_64", "4.18.0-425.3.1.el8.x86_64", vec![])
+            .tar();
+        assert_eq!(releases(&verbatim, "4.18.0-425.3.1.el8"), []);
+    }
+
+    #[test]
+    fn candidates_are_looked_up_under_the_prefix() {
+        let info = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: "5.4.0-40-generic".into(),
+            ..Default::default()
+        };
+        let paths = |tar: &[u8], prefix: &str| {
+            BtfhubArchive::new(tar)
+                .with_prefix(prefix)
+                .lookup_candidates(&info)
+                .map(|v| v.into_iter().map(|v| v.path).collect::<Vec<_>>())
+        };
+        let tar = FixtureArchive::new()
+            .file("./btfs/ubuntu/20.04/x86_64/5.4.0-40-generic.btf", vec![])
+            .file("ubuntu/20.04/x86_64/5.4.0-40-generic.btf", vec![])
+            .tar();
+        assert_eq!(
+            paths(&tar, "btfs").unwrap(),
+            [Path::new("btfs/ubuntu/20.04/x86_64/5.4.0-40-generic.btf")]
+        );
+        assert_eq!(
+            paths(&tar, "").unwrap(),
+            [Path::new("ubuntu/20.04/x86_64/5.4.0-40-generic.btf")]
+        );
+        assert!(matches!(
+            paths(&tar, "btfhub-archive"),
+            Err(Error::NotBtfhubArchive)
+        ));
+    }
+
+    #[test]
+    fn byte_swapped_candidates_are_left_out() {
+        let mut swapped = minimal_valid_btf();
+        swapped.swap(0, 1);
+        let tar = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", swapped)
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-42-generic",
+                minimal_valid_btf(),
+            )
+            .tar();
+        let info = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: "5.4.0-40-generic".into(),
+            ..Default::default()
+        };
+        let candidates = BtfhubArchive::new(&tar).lookup_candidates(&info).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].release, "5.4.0-42-generic");
+        assert_eq!(candidates[0].reason, CandidateReason::HigherRevision);
+    }
+
+    #[test]
+    fn listing_is_only_read_before_the_btfs() {
+        let mut listing = crate::listing::ArchiveListing::default();
+        listing.push(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-99-generic.btf",
+            b"",
+        );
+        let btf = minimal_valid_btf();
+        let listed = FixtureArchive::new()
+            .dir("btfhub-archive")
+            .file("./btfhub-archive/manifest.json", listing.to_json())
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", btf.clone())
+            .tar();
+        // 清单只是提示，列出内核时不再扫描归档
+        assert_eq!(BtfhubArchive::new(&listed).listing(), Some(listing.clone()));
+        assert_eq!(
+            BtfhubArchive::new(&listed).kernels().unwrap(),
+            ["ubuntu/20.04/x86_64/5.4.0-99-generic"]
+        );
+        let late = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", btf.clone())
+            .file("btfhub-archive/manifest.json", listing.to_json())
+            .tar();
+        let unparsable = FixtureArchive::new()
+            .file("btfhub-archive/manifest.json", b"{\"schema\": 2}".to_vec())
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", btf.clone())
+            .tar();
+        let elsewhere = FixtureArchive::new()
+            .file("manifest.json", listing.to_json())
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", btf)
+            .tar();
+        for tar in [late, unparsable, elsewhere] {
+            let archive = BtfhubArchive::new(&tar);
+            assert_eq!(archive.listing(), None);
+            assert_eq!(
+                archive.kernels().unwrap(),
+                ["ubuntu/20.04/x86_64/5.4.0-40-generic"]
+            );
+        }
+    }
+}
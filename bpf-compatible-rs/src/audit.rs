@@ -0,0 +1,339 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Result};
+
+/// Environment variable holding the path of the audit log
+pub const AUDIT_LOG_ENV: &str = "BPF_COMPATIBLE_AUDIT_LOG";
+/// Environment variable holding the size (in bytes) after which the audit log is rotated
+pub const AUDIT_LOG_MAX_SIZE_ENV: &str = "BPF_COMPATIBLE_AUDIT_LOG_MAX_SIZE";
+
+/// Default size after which the audit log is rotated
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+/// Default number of rotated files to keep
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// One resolution, as recorded in the audit log
+pub struct AuditRecord<'a> {
+    /// Kernel release the resolution was made for
+    pub release: &'a str,
+    /// Where the btf came from, e.g. `native` or `archive`
+    pub source: &'a str,
+    /// Path of the btf that was handed out, if any
+    pub matched_path: Option<&'a str>,
+    /// Outcome of the resolution, e.g. `ok` or an errno
+    pub result: &'a str,
+}
+
+/// An append-only log file recording every btf resolution, rotated by size
+///
+/// Once the file would grow past `max_size`, it is renamed to `<path>.1`, the
+/// previous `<path>.1` to `<path>.2` and so on, keeping at most `max_files` old files.
+/// Fields holding spaces, `=`, quotes or control characters are quoted and escaped, so
+/// every line splits into the same fields.
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl AuditLog {
+    /// Create an audit log writing to `path` with the default rotation settings
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            max_size: DEFAULT_MAX_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+        }
+    }
+
+    /// Build the audit log from `BPF_COMPATIBLE_AUDIT_LOG` and `BPF_COMPATIBLE_AUDIT_LOG_MAX_SIZE`
+    ///
+    /// Returns `None` if no log path was configured
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os(AUDIT_LOG_ENV).filter(|v| !v.is_empty())?;
+        let mut log = Self::new(path);
+        if let Some(size) = std::env::var(AUDIT_LOG_MAX_SIZE_ENV)
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            log = log.with_max_size(size);
+        }
+        Some(log)
+    }
+
+    /// Set the size in bytes after which the log is rotated
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Set how many rotated files are kept; 0 means the log is simply truncated
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Path of the active log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a line describing `record`, rotating the file beforehand if needed
+    ///
+    /// Processes and threads sharing the log take turns through an exclusive lock of
+    /// `<path>.lock`, so that only one of them rotates a full file and none appends to a
+    /// file being rotated.
+    pub fn append(&self, record: &AuditRecord) -> Result<()> {
+        let line = format!(
+            "{} release={} source={} path={} result={}\n",
+            format_timestamp(SystemTime::now()),
+            field(record.release),
+            field(record.source),
+            record.matched_path.map(field).unwrap_or(Cow::Borrowed("-")),
+            field(record.result)
+        );
+        let _lock = self.lock()?;
+        let current_size = std::fs::metadata(&self.path).map(|v| v.len()).unwrap_or(0);
+        if current_size > 0 && current_size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| Error::FileWriteError(self.path.display().to_string(), e))?;
+        file.write_all(line.as_bytes())
+            .map_err(|e| Error::FileWriteError(self.path.display().to_string(), e))
+    }
+
+    /// Take the lock of the log, held until the returned file is closed
+    ///
+    /// The lock file is never rotated, unlike the log, whose lock a rename would leave behind.
+    fn lock(&self) -> Result<File> {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".lock");
+        let path = PathBuf::from(name);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| Error::FileWriteError(path.display().to_string(), e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            // 被信号打断时重试
+            while unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(Error::FileWriteError(path.display().to_string(), e));
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> Result<()> {
+        let rename = |from: &Path, to: &Path| match std::fs::rename(from, to) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::FileWriteError(to.display().to_string(), e))
+            }
+            _ => Ok(()),
+        };
+        if self.max_files == 0 {
+            return std::fs::write(&self.path, b"")
+                .map_err(|e| Error::FileWriteError(self.path.display().to_string(), e));
+        }
+        // 从最旧的文件开始依次后移，超出 max_files 的文件被覆盖
+        for index in (1..self.max_files).rev() {
+            rename(&self.rotated_path(index), &self.rotated_path(index + 1))?;
+        }
+        rename(&self.path, &self.rotated_path(1))
+    }
+}
+
+/// A field of a log line, quoted and escaped unless it's a plain word
+///
+/// Empty values and `-`, which stands for a missing path, are quoted too.
+fn field(value: &str) -> Cow<'_, str> {
+    let plain = !value.is_empty()
+        && value != "-"
+        && !value
+            .chars()
+            .any(|v| v.is_whitespace() || v.is_control() || matches!(v, '"' | '\\' | '='));
+    if plain {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(format!("{:?}", value))
+    }
+}
+
+/// Format a timestamp as `YYYY-MM-DDTHH:MM:SSZ` (UTC)
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn record(result: &str) -> AuditRecord<'_> {
+        AuditRecord {
+            release: "5.4.0-40-generic",
+            source: "archive",
+            matched_path: Some("/tmp/eunomia.btf.abc123"),
+            result,
+        }
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_825_723);
+        assert_eq!(format_timestamp(leap_day), "2000-02-29T12:02:03Z");
+    }
+
+    #[test]
+    fn a_line_is_appended_per_resolution() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.log"));
+        log.append(&record("ok")).unwrap();
+        log.append(&AuditRecord {
+            matched_path: None,
+            ..record("-2")
+        })
+        .unwrap();
+        let contents = std::fs::read_to_string(log.path()).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(
+            " release=5.4.0-40-generic source=archive path=/tmp/eunomia.btf.abc123 result=ok"
+        ));
+        assert!(lines[1].ends_with(" path=- result=-2"));
+    }
+
+    #[test]
+    fn full_logs_are_rotated_keeping_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.log"))
+            .with_max_size(1)
+            .with_max_files(2);
+        for result in ["first", "second", "third", "fourth"] {
+            log.append(&record(result)).unwrap();
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert!(read("audit.log").ends_with("result=fourth\n"));
+        assert!(read("audit.log.1").ends_with("result=third\n"));
+        assert!(read("audit.log.2").ends_with("result=second\n"));
+        assert!(!dir.path().join("audit.log.3").exists());
+    }
+
+    #[test]
+    fn without_rotated_files_the_log_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.log"))
+            .with_max_size(1)
+            .with_max_files(0);
+        log.append(&record("first")).unwrap();
+        log.append(&record("second")).unwrap();
+        let contents = std::fs::read_to_string(log.path()).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.ends_with("result=second\n"));
+        assert!(!dir.path().join("audit.log.1").exists());
+    }
+
+    #[test]
+    fn fields_that_could_forge_a_line_are_quoted() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.log"));
+        log.append(&AuditRecord {
+            release: "5.4.0 result=ok\n2000-01-01T00:00:00Z release=forged",
+            source: "",
+            matched_path: Some("/tmp/a \"b\"\\c"),
+            result: "-",
+        })
+        .unwrap();
+        let contents = std::fs::read_to_string(log.path()).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.ends_with(concat!(
+            r#" release="5.4.0 result=ok\n2000-01-01T00:00:00Z release=forged""#,
+            r#" source="" path="/tmp/a \"b\"\\c" result="-""#,
+            "\n"
+        )));
+    }
+
+    #[test]
+    fn concurrent_appends_rotate_each_full_file_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let line_len = 1 + format!(
+            "{} release=5.4.0-40-generic source=archive path=/tmp/eunomia.btf.abc123 result=ok",
+            format_timestamp(UNIX_EPOCH)
+        )
+        .len() as u64;
+        let log = AuditLog::new(dir.path().join("audit.log"))
+            .with_max_size(4 * line_len)
+            .with_max_files(1000);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        log.append(&record("ok")).unwrap();
+                    }
+                });
+            }
+        });
+        // 没有行丢失，也没有文件超过上限
+        let mut lines = 0;
+        for entry in std::fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|v| v == "lock") {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path).unwrap();
+            assert!(contents.len() as u64 <= 4 * line_len, "{}", path.display());
+            lines += contents.lines().count();
+        }
+        assert_eq!(lines, 200);
+    }
+}
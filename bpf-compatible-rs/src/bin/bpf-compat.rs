@@ -0,0 +1,301 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `bpf-compat`: answer "is there a btf for this kernel?" from the shell, with the lookup
+//! the library does at runtime.
+//!
+//! Exit codes: 0 if covered (or done), 1 if not covered, 2 on errors.
+use std::{
+    fs::File,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    process::ExitCode,
+};
+
+use bpf_compatible_rs::{
+    archive::{BtfEntryInfo, BtfhubArchive, BTFHUB_ARCHIVE_DIR},
+    current_kernel_release, generate_btf_archive_path_for,
+    pack::filter_btf_archive,
+    release::KernelRelease,
+    section::{add_elf_section, BTF_SECTION_NAME},
+    tarball::TarballBtfArchive,
+    Error, NativeBtfStatus, SystemInfo, VMLINUX_BTF_PATH,
+};
+
+const USAGE: &str = "\
+Usage:
+  bpf-compat list <ARCHIVE>
+    print every kernel the archive has a btf for
+  bpf-compat check <ARCHIVE>
+    print the btf path of the running system and whether the kernel or the archive has it
+  bpf-compat extract <ARCHIVE> [-o OUT] [--kernel RELEASE] [--distro ID] [--version VERSION] [--arch ARCH]
+    extract the btf of the running system, or of the system the options describe
+  bpf-compat trim <ARCHIVE> -o OUT [--distro ID]... [--version VERSION]... [--arch ARCH]... [--min-kernel RELEASE]
+    write a tar.gz with only the btfs matching every option given, each option matching
+    any of its values; files outside btfhub-archive, like SHA256SUMS, are kept
+  bpf-compat embed <ARCHIVE> <EXECUTABLE> [-o OUT] [--section NAME]
+    add the archive to the linked executable as the section NAME (.bpf_compat_btfs), for
+    ensure_core_btf_with_self_section; the executable is replaced unless OUT is given
+
+Exit codes: 0 covered, 1 not covered, 2 error";
+
+/// The btf is there
+const COVERED: u8 = 0;
+/// Neither the kernel nor the archive has the btf
+const NOT_COVERED: u8 = 1;
+/// Bad usage, unreadable archive, failed detection...
+const FAILED: u8 = 2;
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let code = match args.first().map(String::as_str) {
+        Some("list") => with_archive(&args[1..], list),
+        Some("check") => with_archive(&args[1..], check),
+        Some("extract") => with_archive(&args[1..], extract),
+        Some("trim") => with_archive(&args[1..], trim),
+        Some("embed") => with_archive(&args[1..], embed),
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(COVERED)
+        }
+        _ => Err(usage()),
+    };
+    ExitCode::from(code.unwrap_or_else(|e| {
+        eprintln!("bpf-compat: {}", e);
+        FAILED
+    }))
+}
+
+fn usage() -> String {
+    format!("invalid arguments\n{}", USAGE)
+}
+
+/// Read the archive named by the first argument, and run `command` on it and the other arguments
+fn with_archive(
+    args: &[String],
+    command: fn(&[u8], &[String]) -> Result<u8, String>,
+) -> Result<u8, String> {
+    let (path, rest) = args.split_first().ok_or_else(usage)?;
+    let archive = std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    command(&archive, rest)
+}
+
+fn list(archive: &[u8], args: &[String]) -> Result<u8, String> {
+    if !args.is_empty() {
+        return Err(usage());
+    }
+    for kernel in BtfhubArchive::new(archive)
+        .kernels()
+        .map_err(|e| e.to_string())?
+    {
+        println!("{}", kernel);
+    }
+    Ok(COVERED)
+}
+
+fn check(archive: &[u8], args: &[String]) -> Result<u8, String> {
+    if !args.is_empty() {
+        return Err(usage());
+    }
+    let info = SystemInfo::detect().map_err(|e| e.to_string())?;
+    println!("path: {}", generate_btf_archive_path_for(&info).display());
+    // 与运行时一致，伪造的内核不使用当前内核的 btf
+    let native = NativeBtfStatus::probe(Path::new(VMLINUX_BTF_PATH)) == NativeBtfStatus::Usable;
+    if native {
+        println!("native: {}", VMLINUX_BTF_PATH);
+    } else {
+        println!("native: none");
+    }
+    // 即使内核自带 btf，也报告归档是否覆盖，便于在有 btf 的机器上检查归档
+    let archive = TarballBtfArchive::from_gzipped_bytes(archive).map_err(|e| e.to_string())?;
+    let covered = match archive.lookup(&info) {
+        Ok(entry) => {
+            println!("archive: {}", entry.path.display());
+            true
+        }
+        Err(Error::EntryNotFound { .. }) => {
+            println!("archive: none");
+            false
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    Ok(if native || covered {
+        COVERED
+    } else {
+        NOT_COVERED
+    })
+}
+
+fn extract(archive: &[u8], args: &[String]) -> Result<u8, String> {
+    let mut out = None;
+    let mut kernel = None;
+    let mut distro = None;
+    let mut version = None;
+    let mut arch = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let slot = match arg.as_str() {
+            "-o" | "--output" => &mut out,
+            "--kernel" => &mut kernel,
+            "--distro" => &mut distro,
+            "--version" => &mut version,
+            "--arch" => &mut arch,
+            _ => return Err(usage()),
+        };
+        *slot = Some(args.next().ok_or_else(usage)?.clone());
+    }
+
+    let mut info = match SystemInfo::detect() {
+        Ok(v) => v,
+        // 给出了发行版和版本时，无需识别当前系统
+        Err(_) if distro.is_some() && version.is_some() => SystemInfo {
+            arch: std::env::consts::ARCH.to_string(),
+            ..Default::default()
+        },
+        Err(e) => return Err(e.to_string()),
+    };
+    if distro.is_some() || version.is_some() {
+        // 代号属于原先的发行版版本，由生成路径时根据新的版本推断
+        info.version_codename.clear();
+    }
+    if let Some(v) = distro {
+        info.distro_id = v;
+    }
+    if let Some(v) = version {
+        info.version_id = v;
+    }
+    if let Some(v) = arch {
+        info.arch = v;
+    }
+    info.kernel_release = match kernel {
+        Some(v) => v,
+        None if info.kernel_release.is_empty() => {
+            current_kernel_release().map_err(|e| e.to_string())?
+        }
+        None => info.kernel_release,
+    };
+
+    let archive = TarballBtfArchive::from_gzipped_bytes(archive).map_err(|e| e.to_string())?;
+    let entry = match archive.lookup(&info) {
+        Ok(v) => v,
+        Err(Error::EntryNotFound { .. }) => {
+            eprintln!("bpf-compat: the archive has no btf for {}", info);
+            return Ok(NOT_COVERED);
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    let out = out
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(format!("{}.btf", entry.kernel_release)));
+    archive
+        .extract_to(&entry, &out)
+        .map_err(|e| e.to_string())?;
+    println!("{} -> {}", entry.path.display(), out.display());
+    Ok(COVERED)
+}
+
+fn trim(archive: &[u8], args: &[String]) -> Result<u8, String> {
+    let mut out = None;
+    let mut distros = vec![];
+    let mut versions = vec![];
+    let mut arches = vec![];
+    let mut min_kernel = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(usage)?.clone();
+        match arg.as_str() {
+            "-o" | "--output" => out = Some(PathBuf::from(value)),
+            "--distro" => distros.push(value),
+            "--version" => versions.push(value),
+            "--arch" => arches.push(value),
+            "--min-kernel" => min_kernel = Some(value),
+            _ => return Err(usage()),
+        }
+    }
+    let out = out.ok_or_else(usage)?;
+    let min_kernel = match &min_kernel {
+        Some(v) => Some(
+            KernelRelease::parse(v)
+                .ok_or_else(|| format!("invalid kernel release {}", v))?
+                .numbers,
+        ),
+        None => None,
+    };
+    let matches =
+        |values: &[String], value: &str| values.is_empty() || values.iter().any(|v| v == value);
+    let output =
+        File::create(&out).map_err(|e| format!("failed to create {}: {}", out.display(), e))?;
+    let report = filter_btf_archive(archive, output, |info| match info {
+        BtfEntryInfo::Btf(v) => {
+            matches(&distros, &v.distro)
+                && matches(&versions, &v.version)
+                && matches(&arches, &v.arch)
+                && min_kernel.as_ref().is_none_or(|min| {
+                    KernelRelease::parse(&v.kernel_release).is_some_and(|v| v.numbers >= *min)
+                })
+        }
+        // 归档目录下其余的文件（如模块的 btf）不属于筛选出的内核
+        BtfEntryInfo::Other(path) => path
+            .components()
+            .find(|v| matches!(v, Component::Normal(_)))
+            .is_none_or(|v| v.as_os_str() != BTFHUB_ARCHIVE_DIR),
+    });
+    let report = match report {
+        Ok(v) => v,
+        Err(e) => {
+            // 不留下写了一半的文件
+            let _ = std::fs::remove_file(&out);
+            return Err(e.to_string());
+        }
+    };
+    println!(
+        "kept {}, dropped {}, {} bytes -> {}",
+        report.kept,
+        report.dropped,
+        report.size,
+        out.display()
+    );
+    Ok(COVERED)
+}
+
+fn embed(archive: &[u8], args: &[String]) -> Result<u8, String> {
+    let (executable, args) = args.split_first().ok_or_else(usage)?;
+    let executable = PathBuf::from(executable);
+    let mut out = None;
+    let mut section = BTF_SECTION_NAME.to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(usage)?.clone();
+        match arg.as_str() {
+            "-o" | "--output" => out = Some(PathBuf::from(value)),
+            "--section" => section = value,
+            _ => return Err(usage()),
+        }
+    }
+    let out = out.unwrap_or_else(|| executable.clone());
+    let elf = std::fs::read(&executable)
+        .map_err(|e| format!("failed to read {}: {}", executable.display(), e))?;
+    let patched = add_elf_section(&elf, &section, archive).map_err(|e| e.to_string())?;
+    let permissions = std::fs::metadata(&executable)
+        .map_err(|e| format!("failed to read {}: {}", executable.display(), e))?
+        .permissions();
+    // 先写入同一目录下的临时文件再重命名，替换可执行文件时不会留下写了一半的文件
+    let dir = out
+        .parent()
+        .filter(|v| !v.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let write_error = |e: std::io::Error| format!("failed to write {}: {}", out.display(), e);
+    let mut file = tempfile::NamedTempFile::new_in(dir).map_err(write_error)?;
+    file.write_all(&patched).map_err(write_error)?;
+    std::fs::set_permissions(file.path(), permissions).map_err(write_error)?;
+    file.persist(&out).map_err(|e| write_error(e.error))?;
+    println!(
+        "added {} bytes as {} -> {}",
+        archive.len(),
+        section,
+        out.display()
+    );
+    Ok(COVERED)
+}
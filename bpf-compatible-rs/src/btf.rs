@@ -0,0 +1,598 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Minimal parsing of raw BTF blobs, enough to validate them without libbpf.
+//!
+//! A BTF blob starts with `struct btf_header`, followed by the type section and the
+//! string section, whose offsets are relative to the end of the header. Everything
+//! is in the byte order of the machine that produced it.
+use std::{fs::File, io::Read, path::Path};
+
+use crate::{arch::normalize_arch, Error, Result};
+
+/// Magic number at the start of every BTF blob
+pub const BTF_MAGIC: u16 = 0xeb9f;
+/// The magic as read on a host of the other byte order than the one producing the blob
+const BTF_MAGIC_SWAPPED: u16 = BTF_MAGIC.swap_bytes();
+/// The only BTF version defined so far
+pub const BTF_VERSION: u8 = 1;
+
+/// Size of `struct btf_header` as defined by version 1
+pub(crate) const BTF_HEADER_SIZE: u32 = 24;
+/// Size of `struct btf_type`
+pub(crate) const BTF_TYPE_SIZE: usize = 12;
+
+pub(crate) const BTF_KIND_INT: u8 = 1;
+pub(crate) const BTF_KIND_ARRAY: u8 = 3;
+pub(crate) const BTF_KIND_STRUCT: u8 = 4;
+pub(crate) const BTF_KIND_UNION: u8 = 5;
+pub(crate) const BTF_KIND_ENUM: u8 = 6;
+pub(crate) const BTF_KIND_TYPEDEF: u8 = 8;
+pub(crate) const BTF_KIND_VOLATILE: u8 = 9;
+pub(crate) const BTF_KIND_CONST: u8 = 10;
+pub(crate) const BTF_KIND_RESTRICT: u8 = 11;
+pub(crate) const BTF_KIND_TYPE_TAG: u8 = 18;
+pub(crate) const BTF_KIND_ENUM64: u8 = 19;
+
+/// Information from a validated BTF header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BtfHeaderInfo {
+    pub version: u8,
+    pub flags: u8,
+    pub hdr_len: u32,
+    pub type_off: u32,
+    pub type_len: u32,
+    pub str_off: u32,
+    pub str_len: u32,
+}
+
+impl BtfHeaderInfo {
+    /// Total size of the blob as described by the header
+    pub fn total_len(&self) -> u64 {
+        self.hdr_len as u64
+            + (self.type_off as u64 + self.type_len as u64)
+                .max(self.str_off as u64 + self.str_len as u64)
+    }
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(
+        bytes.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+/// Check the magic at the start of a BTF blob
+///
+/// A byte-swapped magic means the blob was generated on a host of the other byte order,
+/// e.g. a big-endian s390x, and fails with [`Error::BtfEndiannessMismatch`].
+fn check_magic(magic: [u8; 2]) -> Result<()> {
+    match u16::from_ne_bytes(magic) {
+        BTF_MAGIC => Ok(()),
+        BTF_MAGIC_SWAPPED if cfg!(target_endian = "little") => {
+            Err(Error::BtfEndiannessMismatch("big"))
+        }
+        BTF_MAGIC_SWAPPED => Err(Error::BtfEndiannessMismatch("little")),
+        magic => Err(Error::InvalidBtf(format!("bad magic {:#06x}", magic))),
+    }
+}
+
+/// Whether `bytes` start with the btf magic in the other byte order than the host's
+pub fn has_swapped_magic(bytes: &[u8]) -> bool {
+    bytes
+        .get(..2)
+        .is_some_and(|v| u16::from_ne_bytes([v[0], v[1]]) == BTF_MAGIC_SWAPPED)
+}
+
+/// Validate the header of a BTF blob
+///
+/// It checks the magic, in the byte order of the host, the version, and that the type
+/// and string sections lie within `bytes`. The types themselves are not checked. A blob
+/// of the other byte order fails with [`Error::BtfEndiannessMismatch`].
+pub fn validate_btf_bytes(bytes: &[u8]) -> Result<BtfHeaderInfo> {
+    if bytes.len() < BTF_HEADER_SIZE as usize {
+        return Err(Error::InvalidBtf("too short to hold a btf header".into()));
+    }
+    check_magic([bytes[0], bytes[1]])?;
+    let info = BtfHeaderInfo {
+        version: bytes[2],
+        flags: bytes[3],
+        hdr_len: read_u32(bytes, 4).unwrap_or_default(),
+        type_off: read_u32(bytes, 8).unwrap_or_default(),
+        type_len: read_u32(bytes, 12).unwrap_or_default(),
+        str_off: read_u32(bytes, 16).unwrap_or_default(),
+        str_len: read_u32(bytes, 20).unwrap_or_default(),
+    };
+    if info.version != BTF_VERSION {
+        return Err(Error::InvalidBtf(format!(
+            "unsupported version {}",
+            info.version
+        )));
+    }
+    if info.hdr_len < BTF_HEADER_SIZE {
+        return Err(Error::InvalidBtf(format!(
+            "header length {} is too small",
+            info.hdr_len
+        )));
+    }
+    if info.total_len() > bytes.len() as u64 {
+        return Err(Error::InvalidBtf(format!(
+            "sections end at {}, beyond the {} bytes of the blob",
+            info.total_len(),
+            bytes.len()
+        )));
+    }
+    Ok(info)
+}
+
+/// Check that the file at `path` can be read and starts with the btf magic
+///
+/// Only the magic is read, so this is cheap even for the kernel's own btf. A file that
+/// exists but can't be opened, e.g. `/sys/kernel/btf/vmlinux` in a locked-down container,
+/// fails with [`Error::FileReadError`].
+pub fn check_btf_file(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let mut magic = [0; 2];
+    File::open(path)
+        .and_then(|mut v| v.read_exact(&mut magic))
+        .map_err(|e| Error::FileReadError(path.display().to_string(), e))?;
+    check_magic(magic)
+}
+
+/// One `struct btf_type`
+pub(crate) struct RawType {
+    pub kind: u8,
+    pub name_off: u32,
+    pub size_or_type: u32,
+    /// Members of a struct or union, values of an enum, or the element type of an array
+    pub members: Vec<RawMember>,
+}
+
+/// A member of a struct or union, or a value of an enum
+pub(crate) struct RawMember {
+    pub name_off: u32,
+    /// Type of the member; for an enum value, its (low 32 bits of the) value
+    pub type_id: u32,
+}
+
+/// Size of the kind-specific data following a `struct btf_type`
+pub(crate) fn extra_size(kind: u8, vlen: u16) -> Option<usize> {
+    let vlen = vlen as usize;
+    Some(match kind {
+        // INT, VAR, DECL_TAG
+        1 | 14 | 17 => 4,
+        // PTR, FWD, TYPEDEF, VOLATILE, CONST, RESTRICT, FUNC, FLOAT, TYPE_TAG
+        2 | 7..=12 | 16 | 18 => 0,
+        // ARRAY
+        3 => 12,
+        // STRUCT, UNION, DATASEC, ENUM64
+        4 | 5 | 15 | 19 => 12 * vlen,
+        // ENUM, FUNC_PROTO
+        6 | 13 => 8 * vlen,
+        _ => return None,
+    })
+}
+
+/// Walk the type section of a validated blob
+pub(crate) fn raw_types(bytes: &[u8], info: &BtfHeaderInfo) -> Result<Vec<RawType>> {
+    // 在 usize 中计算，避免 u32 相加溢出
+    let start = info.hdr_len as usize + info.type_off as usize;
+    let section = &bytes[start..start + info.type_len as usize];
+    let mut types = vec![];
+    let mut offset = 0;
+    while offset < section.len() {
+        let truncated = || Error::InvalidBtf(format!("truncated type at offset {}", offset));
+        let name_off = read_u32(section, offset).ok_or_else(truncated)?;
+        let type_info = read_u32(section, offset + 4).ok_or_else(truncated)?;
+        let size_or_type = read_u32(section, offset + 8).ok_or_else(truncated)?;
+        let kind = ((type_info >> 24) & 0x1f) as u8;
+        let vlen = (type_info & 0xffff) as u16;
+        let extra = extra_size(kind, vlen)
+            .ok_or_else(|| Error::InvalidBtf(format!("unknown type kind {}", kind)))?;
+        if offset + BTF_TYPE_SIZE + extra > section.len() {
+            return Err(truncated());
+        }
+        let data = offset + BTF_TYPE_SIZE;
+        // 只解析检查 CO-RE 重定位需要的部分：成员的名字和类型
+        let members = match kind {
+            BTF_KIND_STRUCT | BTF_KIND_UNION | BTF_KIND_ENUM | BTF_KIND_ENUM64 => {
+                let size = extra / vlen.max(1) as usize;
+                (0..vlen as usize)
+                    .map(|i| RawMember {
+                        name_off: read_u32(section, data + i * size).unwrap_or_default(),
+                        type_id: read_u32(section, data + i * size + 4).unwrap_or_default(),
+                    })
+                    .collect()
+            }
+            BTF_KIND_ARRAY => vec![RawMember {
+                name_off: 0,
+                type_id: read_u32(section, data).unwrap_or_default(),
+            }],
+            _ => vec![],
+        };
+        types.push(RawType {
+            kind,
+            name_off,
+            size_or_type,
+            members,
+        });
+        offset += BTF_TYPE_SIZE + extra;
+    }
+    Ok(types)
+}
+
+/// Look up a NUL-terminated name in the string section of a validated blob
+pub(crate) fn name_at<'a>(bytes: &'a [u8], info: &BtfHeaderInfo, name_off: u32) -> Option<&'a str> {
+    let start = info.hdr_len as usize + info.str_off as usize;
+    let section = &bytes[start..start + info.str_len as usize];
+    let name = section.get(name_off as usize..)?;
+    let end = name.iter().position(|v| *v == 0)?;
+    std::str::from_utf8(&name[..end]).ok()
+}
+
+/// Determine the pointer size of the architecture the BTF was generated for, from the size of `long`
+///
+/// Returns `None` if the BTF doesn't describe `long`, which is typical for stripped-down BTFs
+pub fn btf_pointer_size(bytes: &[u8]) -> Result<Option<u32>> {
+    Ok(btf_arch(bytes)?.pointer_size)
+}
+
+/// Registers of `struct pt_regs` only one family of architectures has, and that family
+const PT_REGS_REGISTERS: &[(&str, &str)] = &[
+    ("r15", "x86"),
+    ("bx", "x86"),
+    ("orig_x0", "arm64"),
+    ("uregs", "arm"),
+    ("epc", "riscv"),
+    ("orig_gpr2", "s390"),
+    ("nip", "powerpc"),
+    ("csr_era", "loongarch"),
+    ("cp0_status", "mips"),
+];
+
+/// What a BTF tells of the architecture of its kernel, see [`btf_arch`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BtfArch {
+    /// Size of `long`, which is that of pointers
+    pub pointer_size: Option<u32>,
+    /// Family of the architecture, like `x86` for both `x86_64` and `i686`, see [`arch_family`]
+    pub family: Option<&'static str>,
+}
+
+impl BtfArch {
+    /// Fail with [`Error::BtfArchMismatch`] if the BTF is known not to be of `machine`,
+    /// named as by `uname -m` or btfhub-archive
+    ///
+    /// What isn't known, of the BTF or of `machine`, isn't compared.
+    pub fn check(&self, machine: &str) -> Result<()> {
+        let family = arch_family(machine);
+        let pointer_size = arch_pointer_size(normalize_arch(machine));
+        fn differs<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            a.zip(b).is_some_and(|(a, b)| a != b)
+        }
+        if differs(self.family, family) || differs(self.pointer_size, pointer_size) {
+            return Err(Error::BtfArchMismatch(
+                self.to_string(),
+                machine.to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for BtfArch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.family, self.pointer_size) {
+            (Some(family), Some(size)) => write!(f, "{} with {}-byte pointers", family, size),
+            (Some(family), None) => f.write_str(family),
+            (None, Some(size)) => write!(f, "an architecture with {}-byte pointers", size),
+            (None, None) => f.write_str("an unknown architecture"),
+        }
+    }
+}
+
+/// Tell the architecture of the kernel a BTF describes, without libbpf
+///
+/// The pointer size is that of `long`; the family is told by the registers of
+/// `struct pt_regs`, which differ across architectures of the same pointer size, e.g. a
+/// BTF of arm64 filed under `x86_64`. Stripped-down BTFs may describe neither, leaving
+/// them `None`.
+pub fn btf_arch(bytes: &[u8]) -> Result<BtfArch> {
+    let info = validate_btf_bytes(bytes)?;
+    let types = raw_types(bytes, &info)?;
+    let pointer_size = types
+        .iter()
+        .filter(|v| v.kind == BTF_KIND_INT)
+        .find(|v| {
+            matches!(
+                name_at(bytes, &info, v.name_off),
+                Some("long int" | "long unsigned int" | "unsigned long" | "long")
+            )
+        })
+        .map(|v| v.size_or_type);
+    let family = types
+        .iter()
+        .find(|v| v.kind == BTF_KIND_STRUCT && name_at(bytes, &info, v.name_off) == Some("pt_regs"))
+        .and_then(|pt_regs| {
+            let mut names = vec![];
+            member_names(bytes, &info, &types, pt_regs, 2, &mut names);
+            PT_REGS_REGISTERS
+                .iter()
+                .find(|(register, _)| names.contains(register))
+                .map(|(_, family)| *family)
+        });
+    Ok(BtfArch {
+        pointer_size,
+        family,
+    })
+}
+
+/// Collect the names of the members of `ty`, and of its anonymous structs and unions down to `depth` levels
+fn member_names<'a>(
+    bytes: &'a [u8],
+    info: &BtfHeaderInfo,
+    types: &[RawType],
+    ty: &RawType,
+    depth: usize,
+    names: &mut Vec<&'a str>,
+) {
+    for member in &ty.members {
+        match name_at(bytes, info, member.name_off) {
+            Some("") | None if depth > 0 => {
+                // 类型编号从 1 开始，0 为 void
+                let inner = (member.type_id as usize)
+                    .checked_sub(1)
+                    .and_then(|v| types.get(v))
+                    .filter(|v| matches!(v.kind, BTF_KIND_STRUCT | BTF_KIND_UNION));
+                if let Some(inner) = inner {
+                    member_names(bytes, info, types, inner, depth - 1, names);
+                }
+            }
+            Some(name) => names.push(name),
+            None => {}
+        }
+    }
+}
+
+/// Fail with [`Error::BtfArchMismatch`] unless `btf` may be of each of `machines`, e.g.
+/// the architecture directory it's filed under and the architecture looked up
+pub fn check_btf_arch(btf: &[u8], machines: &[&str]) -> Result<()> {
+    let arch = btf_arch(btf)?;
+    machines.iter().try_for_each(|v| arch.check(v))
+}
+
+/// Family of the architecture `machine`, named as by `uname -m` or btfhub-archive, e.g.
+/// `x86` for `x86_64`, `arm64` for `aarch64`, as told by [`btf_arch`]
+pub fn arch_family(machine: &str) -> Option<&'static str> {
+    Some(match normalize_arch(machine) {
+        "x86_64" | "x86" => "x86",
+        "arm64" => "arm64",
+        "arm" => "arm",
+        "riscv64" | "riscv32" => "riscv",
+        "s390x" | "s390" => "s390",
+        "loongarch64" => "loongarch",
+        v if v.starts_with("ppc") || v.starts_with("powerpc") => "powerpc",
+        v if v.starts_with("mips") => "mips",
+        _ => return None,
+    })
+}
+
+/// Pointer size of the architecture named by `uname -m`, if known
+pub fn arch_pointer_size(machine: &str) -> Option<u32> {
+    match machine {
+        "x86_64" | "aarch64" | "arm64" | "ppc64le" | "ppc64" | "s390x" | "riscv64"
+        | "loongarch64" | "mips64" | "sparc64" => Some(8),
+        "i386" | "i486" | "i586" | "i686" | "x86" | "arm" | "armv7l" | "armv6l" | "mips"
+        | "ppc" | "riscv32" => Some(4),
+        _ => None,
+    }
+}
+
+/// Check whether a caller-provided BTF is appropriate for the running kernel
+///
+/// Returns an error if `btf` is not a structurally valid BTF blob, `Ok(false)` if it is
+/// valid but was generated for another architecture, see [`btf_arch`], and `Ok(true)`
+/// otherwise.
+///
+/// Note that BTF carries no kernel release, so this can't tell apart BTFs of two
+/// kernels of the same architecture; picking the right one is what the archive
+/// lookup by `uname -r` is for.
+pub fn btf_matches_current(btf: &[u8]) -> Result<bool> {
+    let arch = btf_arch(btf)?;
+    let machine = crate::system::uname()?.machine;
+    Ok(arch.check(&machine).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{btf_of_arch, minimal_valid_btf};
+
+    #[test]
+    fn arch_is_told_by_long_and_pt_regs() {
+        for (long_size, register, family) in [
+            (8, "r15", "x86"),
+            (4, "bx", "x86"),
+            (8, "orig_x0", "arm64"),
+            (8, "orig_gpr2", "s390"),
+        ] {
+            let arch = btf_arch(&btf_of_arch(long_size, register)).unwrap();
+            assert_eq!(arch.pointer_size, Some(long_size));
+            assert_eq!(arch.family, Some(family));
+        }
+        assert_eq!(btf_arch(&minimal_valid_btf()).unwrap(), BtfArch::default());
+    }
+
+    #[test]
+    fn same_pointer_size_of_another_family_mismatches() {
+        let arm64 = btf_arch(&btf_of_arch(8, "orig_x0")).unwrap();
+        assert!(arm64.check("aarch64").is_ok());
+        assert!(arm64.check("arm64").is_ok());
+        assert!(matches!(
+            arm64.check("x86_64"),
+            Err(Error::BtfArchMismatch(..))
+        ));
+        assert!(matches!(
+            arm64.check("s390x"),
+            Err(Error::BtfArchMismatch(..))
+        ));
+        let i686 = btf_arch(&btf_of_arch(4, "bx")).unwrap();
+        assert!(matches!(
+            i686.check("x86_64"),
+            Err(Error::BtfArchMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn unknown_arch_isnt_compared() {
+        let arch = btf_arch(&minimal_valid_btf()).unwrap();
+        assert!(arch.check("x86_64").is_ok());
+        let x86_64 = btf_arch(&btf_of_arch(8, "r15")).unwrap();
+        assert!(x86_64.check("some-future-machine").is_ok());
+    }
+
+    #[test]
+    fn offsets_wrapping_around_u32_are_rejected() {
+        let btf = minimal_valid_btf();
+        let hdr_len = read_u32(&btf, 4).unwrap();
+        // hdr_len + off 在 u32 中回绕为 0，按 u32 计算会指回头部之内
+        for field in [8, 16] {
+            let mut wrapped = btf.clone();
+            wrapped[field..field + 4].copy_from_slice(&(u32::MAX - hdr_len + 1).to_ne_bytes());
+            let err = validate_btf_bytes(&wrapped).unwrap_err();
+            assert!(matches!(err, Error::InvalidBtf(_)), "{}", err);
+        }
+        let mut long = btf.clone();
+        long[12..16].copy_from_slice(&u32::MAX.to_ne_bytes());
+        assert!(validate_btf_bytes(&long).is_err());
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn btf_matches_current_compares_the_family() {
+        assert!(btf_matches_current(&btf_of_arch(8, "r15")).unwrap());
+        assert!(!btf_matches_current(&btf_of_arch(8, "orig_x0")).unwrap());
+        assert!(btf_matches_current(b"garbage").is_err());
+    }
+
+    #[cfg(all(feature = "host", target_os = "linux"))]
+    #[test]
+    fn btf_files_are_checked_by_reading_their_magic() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+        check_btf_file(write("valid", &minimal_valid_btf())).unwrap();
+        let mut swapped = minimal_valid_btf();
+        swapped.swap(0, 1);
+        assert!(matches!(
+            check_btf_file(write("swapped", &swapped)),
+            Err(Error::BtfEndiannessMismatch(_))
+        ));
+        assert!(matches!(
+            check_btf_file(write("garbage", b"garbage")),
+            Err(Error::InvalidBtf(_))
+        ));
+        // 存在但无法读出 magic 的文件与无法打开的一样处理
+        for (path, kind) in [
+            (write("empty", b""), std::io::ErrorKind::UnexpectedEof),
+            (dir.path().join("missing"), std::io::ErrorKind::NotFound),
+            (dir.path().to_path_buf(), std::io::ErrorKind::IsADirectory),
+        ] {
+            match check_btf_file(&path) {
+                Err(Error::FileReadError(name, e)) => {
+                    assert_eq!(name, path.display().to_string());
+                    assert_eq!(e.kind(), kind);
+                }
+                other => panic!("{}: {:?}", path.display(), other),
+            }
+        }
+    }
+
+    #[cfg(all(feature = "host", target_os = "linux"))]
+    #[test]
+    fn unreadable_btf_file_fails_with_eacces() {
+        use std::os::unix::fs::PermissionsExt;
+        // root 不受文件权限限制
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vmlinux");
+        std::fs::write(&path, minimal_valid_btf()).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+        match check_btf_file(&path) {
+            Err(Error::FileReadError(_, e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn valid_btf_header_is_returned() {
+        let btf = minimal_valid_btf();
+        let info = validate_btf_bytes(&btf).unwrap();
+        assert_eq!((info.version, info.flags), (BTF_VERSION, 0));
+        assert_eq!(info.hdr_len, BTF_HEADER_SIZE);
+        assert_eq!(info.total_len(), btf.len() as u64);
+        // 末尾多出的字节不影响
+        validate_btf_bytes(&[btf, vec![0; 3]].concat()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_btfs_are_rejected_with_the_reason() {
+        let btf = minimal_valid_btf();
+        let corrupt = |offset: usize, value: &[u8]| {
+            let mut v = btf.clone();
+            v[offset..offset + value.len()].copy_from_slice(value);
+            v
+        };
+        for (bytes, reason) in [
+            (btf[..BTF_HEADER_SIZE as usize - 1].to_vec(), "too short"),
+            (corrupt(0, &[0]), "bad magic"),
+            (corrupt(2, &[BTF_VERSION + 1]), "unsupported version"),
+            (
+                corrupt(4, &8u32.to_ne_bytes()),
+                "header length 8 is too small",
+            ),
+            (btf[..btf.len() - 1].to_vec(), "beyond the"),
+            (vec![], "too short"),
+        ] {
+            match validate_btf_bytes(&bytes) {
+                Err(Error::InvalidBtf(message)) => {
+                    assert!(message.contains(reason), "{message}")
+                }
+                other => panic!("{reason}: {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn byte_swapped_header_is_an_endianness_mismatch() {
+        let btf = minimal_valid_btf();
+        let mut swapped = btf.clone();
+        swapped.swap(0, 1);
+        for field in swapped[4..24].chunks_mut(4) {
+            field.reverse();
+        }
+        assert!(has_swapped_magic(&swapped));
+        assert!(!has_swapped_magic(&btf));
+        assert!(!has_swapped_magic(&swapped[..1]));
+        let other = if cfg!(target_endian = "little") {
+            "big"
+        } else {
+            "little"
+        };
+        match validate_btf_bytes(&swapped) {
+            Err(e @ Error::BtfEndiannessMismatch(_)) => assert_eq!(
+                e.to_string(),
+                format!("BTF endianness mismatch: the btf appears to be {other}-endian")
+            ),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+}
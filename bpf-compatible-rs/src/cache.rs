@@ -0,0 +1,199 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Persistent cache of extracted btfs, so short-lived programs don't decompress the
+//! archive on every run.
+//!
+//! A btf is cached at `<root>/<archive key>/<distro>/<version>/<arch>/<kernel>.btf`.
+//! The archive key (see [`crate::identity::archive_key`]) keeps btfs tailored for
+//! different programs apart, since they share the same kernel paths.
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    btf::validate_btf_bytes,
+    dedup::store_deduplicated,
+    sanitize::{prepare_file_within, sanitize_entry_path},
+    Error, Result,
+};
+
+/// Name of the cache directory under the XDG cache directory
+pub const CACHE_DIR_NAME: &str = "bpf-compatible";
+
+/// The default cache directory
+///
+/// It's `$XDG_CACHE_HOME/bpf-compatible` if `XDG_CACHE_HOME` is set, otherwise
+/// `/var/cache/bpf-compatible` for root and `$HOME/.cache/bpf-compatible` for other users
+pub fn default_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|v| !v.is_empty()) {
+        let dir = PathBuf::from(dir);
+        // XDG 规范要求忽略相对路径
+        if dir.is_absolute() {
+            return Some(dir.join(CACHE_DIR_NAME));
+        }
+    }
+    // /proc/self 的属主即当前进程的有效用户
+    #[cfg(target_os = "linux")]
+    let is_root = std::fs::metadata("/proc/self")
+        .map(|v| v.uid() == 0)
+        .unwrap_or(false);
+    #[cfg(not(target_os = "linux"))]
+    let is_root = false;
+    if is_root {
+        return Some(PathBuf::from("/var/cache").join(CACHE_DIR_NAME));
+    }
+    std::env::var_os("HOME")
+        .filter(|v| !v.is_empty())
+        .map(|v| PathBuf::from(v).join(".cache").join(CACHE_DIR_NAME))
+}
+
+/// A directory of cached btfs
+#[derive(Debug, Clone)]
+pub struct BtfCache {
+    root: PathBuf,
+}
+
+impl BtfCache {
+    /// Use `root` as the cache directory
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Use the directory given by [`default_cache_dir`]
+    pub fn from_default() -> Option<Self> {
+        default_cache_dir().map(Self::new)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the btf at `archive_path` of the archive identified by `archive_key` is cached
+    pub fn entry_path(&self, archive_key: &str, archive_path: &str) -> PathBuf {
+        self.root.join(archive_key).join(archive_path)
+    }
+
+    /// Return the cached btf, if there is a complete and valid one
+    pub fn lookup(&self, archive_key: &str, archive_path: &str) -> Option<PathBuf> {
+        // 路径的各部分来自 os-release，不能指向缓存目录之外
+        sanitize_entry_path(&Path::new(archive_key).join(archive_path)).ok()?;
+        let path = self.entry_path(archive_key, archive_path);
+        let bytes = std::fs::read(&path).ok()?;
+        // 文件大小必须与 btf 头部描述的大小一致，否则视为损坏
+        let info = validate_btf_bytes(&bytes).ok()?;
+        (info.total_len() == bytes.len() as u64).then_some(path)
+    }
+
+    /// Cache `btf`, returning the path it was stored to
+    ///
+    /// The file is written under a temporary name and renamed into place, so concurrent
+    /// readers never see a partial file. A path that would escape the cache directory, or
+    /// go through a symlink in it, fails with [`Error::UnsafePath`]. Identical btfs already cached for other archives
+    /// are hardlinked instead of stored twice.
+    pub fn store(&self, archive_key: &str, archive_path: &str, btf: &[u8]) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.root)
+            .map_err(|e| Error::FileWriteError(self.root.display().to_string(), e))?;
+        // 缓存目录之下的目录按组件逐个创建，不跟随其中已有的符号链接
+        let path = prepare_file_within(&self.root, &Path::new(archive_key).join(archive_path))?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = PathBuf::from(temp);
+        let stored = store_deduplicated(&self.root, &temp, btf).and_then(|_| {
+            std::fs::rename(&temp, &path)
+                .map_err(|e| Error::FileWriteError(path.display().to_string(), e))
+        });
+        // 若 temp 与 path 已是同一文件的硬链接，rename 不做任何事，temp 需要手动删除
+        let _ = std::fs::remove_file(&temp);
+        stored.map(|_| path)
+    }
+
+    /// Remove every cached btf
+    pub fn invalidate(&self) -> Result<()> {
+        match std::fs::remove_dir_all(&self.root) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::FileWriteError(self.root.display().to_string(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{btf_of_arch, minimal_valid_btf};
+
+    const PATH: &str = "ubuntu/20.04/x86_64/5.4.0-40-generic.btf";
+
+    #[test]
+    fn stored_btf_is_looked_up_per_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BtfCache::new(dir.path());
+        assert_eq!(cache.lookup("a", PATH), None);
+        let stored = cache.store("a", PATH, &minimal_valid_btf()).unwrap();
+        assert_eq!(stored, dir.path().join("a").join(PATH));
+        assert_eq!(cache.lookup("a", PATH), Some(stored.clone()));
+        assert_eq!(std::fs::read(&stored).unwrap(), minimal_valid_btf());
+        // 其他归档的 btf 互不影响
+        assert_eq!(cache.lookup("b", PATH), None);
+        // 没有留下写入时的临时文件
+        assert_eq!(
+            std::fs::read_dir(stored.parent().unwrap()).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn storing_again_replaces_the_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BtfCache::new(dir.path());
+        cache.store("a", PATH, &minimal_valid_btf()).unwrap();
+        let arm64 = btf_of_arch(8, "orig_x0");
+        let stored = cache.store("a", PATH, &arm64).unwrap();
+        assert_eq!(std::fs::read(stored).unwrap(), arm64);
+    }
+
+    #[test]
+    fn truncated_or_invalid_btf_is_a_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BtfCache::new(dir.path());
+        let stored = cache.store("a", PATH, &minimal_valid_btf()).unwrap();
+        let btf = minimal_valid_btf();
+        std::fs::write(&stored, &btf[..btf.len() - 1]).unwrap();
+        assert_eq!(cache.lookup("a", PATH), None);
+        std::fs::write(&stored, [btf.clone(), vec![0]].concat()).unwrap();
+        assert_eq!(cache.lookup("a", PATH), None);
+        std::fs::write(&stored, b"garbage").unwrap();
+        assert_eq!(cache.lookup("a", PATH), None);
+    }
+
+    #[test]
+    fn paths_escaping_the_cache_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BtfCache::new(dir.path().join("cache"));
+        let escaping = "../../outside.btf";
+        assert!(matches!(
+            cache.store("a", escaping, &minimal_valid_btf()),
+            Err(Error::UnsafePath(_))
+        ));
+        assert!(!dir.path().join("outside.btf").exists());
+        // 即使缓存目录之外恰好有合法的 btf，也不会被返回
+        std::fs::write(dir.path().join("outside.btf"), minimal_valid_btf()).unwrap();
+        assert_eq!(cache.lookup("a", escaping), None);
+    }
+
+    #[test]
+    fn invalidating_removes_every_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BtfCache::new(dir.path().join("cache"));
+        // 缓存目录不存在时也算成功
+        cache.invalidate().unwrap();
+        cache.store("a", PATH, &minimal_valid_btf()).unwrap();
+        cache.invalidate().unwrap();
+        assert_eq!(cache.lookup("a", PATH), None);
+        assert!(!cache.root().exists());
+    }
+}
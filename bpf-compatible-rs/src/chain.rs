@@ -0,0 +1,655 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The strategies of getting the btf of the kernel, tried in order until one has it.
+//!
+//! A strategy that finds nothing is a miss and the next one is tried; one that fails
+//! otherwise, e.g. on a corrupt archive, ends the chain with its error, so it isn't masked
+//! by a later strategy. Hardened builds can leave out the network, or any other strategy,
+//! by configuring a chain without it.
+use std::{fmt, path::PathBuf};
+
+use crate::{
+    archive::BtfhubArchive,
+    btf::{check_btf_arch, check_btf_file, validate_btf_bytes},
+    cache::BtfCache,
+    diagnose::BTF_PATH_ENV,
+    directory::BtfDirectory,
+    identity::archive_key,
+    kconfig::kernel_btf_config_of,
+    native::NativeBtfProbe,
+    system::under_root,
+    tarball::TarballBtfArchive,
+    write_ensured_btf, BtfSource, EnsureOptions, EnsuredBtf, Error, MatchInfo, NativeBtfStatus,
+    Result, SystemInfo, VMLINUX_BTF_PATH,
+};
+
+/// A way of getting the btf of the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Strategy {
+    /// The btf the kernel exports at [`VMLINUX_BTF_PATH`]
+    Native,
+    /// The btf file named by [`BTF_PATH_ENV`]
+    EnvOverride,
+    /// A btf installed along with the kernel, see [`NativeBtfProbe`]
+    Installed,
+    /// The btf kept in the persistent cache by an earlier lookup in the same archive
+    Cache,
+    /// The archive the lookup is given, embedded in the program or read from a file
+    EmbeddedArchive,
+    /// The unpacked btfhub-archive at the path, see [`BtfDirectory`]
+    ArchiveDir(PathBuf),
+    /// Downloading the btf from btfhub-archive; needs the `download` feature
+    Download,
+    /// Generating the btf from the vmlinux with pahole; needs the `pahole` feature
+    Pahole,
+}
+
+/// The chain of `bpf-compatible-sys`, where `Pahole` and `Download` are only tried if allowed
+pub const DEFAULT_CHAIN: &[Strategy] = &[
+    Strategy::EnvOverride,
+    Strategy::Native,
+    Strategy::Installed,
+    Strategy::EmbeddedArchive,
+    Strategy::Pahole,
+    Strategy::Download,
+];
+
+impl Strategy {
+    /// Short name, as in logs and diagnostics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Native => "native",
+            Strategy::EnvOverride => "override",
+            Strategy::Installed => "installed",
+            Strategy::Cache => "cache",
+            Strategy::EmbeddedArchive => "archive",
+            Strategy::ArchiveDir(_) => "dir",
+            Strategy::Download => "download",
+            Strategy::Pahole => "pahole",
+        }
+    }
+
+    /// The feature the strategy needs and this build doesn't have, if any
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        match self {
+            Strategy::Download if !cfg!(feature = "download") => Some("download"),
+            Strategy::Pahole if !cfg!(feature = "pahole") => Some("pahole"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::ArchiveDir(dir) => write!(f, "{} {}", self.name(), dir.display()),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+/// Fail with [`Error::StrategyUnavailable`] if a strategy of `chain` was compiled out
+pub fn check_chain(chain: &[Strategy]) -> Result<()> {
+    chain.iter().try_for_each(|v| match v.missing_feature() {
+        Some(feature) => Err(Error::StrategyUnavailable(v.to_string(), feature)),
+        None => Ok(()),
+    })
+}
+
+/// How a strategy fared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// It found the btf, ending the chain
+    Hit,
+    /// It has no btf for the kernel, so the next one is tried
+    Miss,
+    /// It failed, ending the chain
+    Error(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Hit => f.write_str("hit"),
+            Outcome::Miss => f.write_str("miss"),
+            Outcome::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+/// A strategy tried by a lookup, and how it fared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attempt {
+    pub strategy: Strategy,
+    pub outcome: Outcome,
+}
+
+/// Whether `e` only means the strategy has no btf for the kernel, rather than a failure
+pub fn is_miss(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::EntryNotFound { .. }
+            | Error::DownloadFailed(..)
+            | Error::VmlinuxNotFound(..)
+            | Error::PaholeFailed(..)
+    )
+}
+
+/// Try the strategies of `opts` in order, see [`crate::ensure_core_btf_traced`]
+///
+/// The btf is `None` if the kernel has native btf and `opts` don't ask for its path.
+pub(crate) fn run(
+    tar: &[u8],
+    opts: &EnsureOptions,
+) -> (Result<(Option<EnsuredBtf>, MatchInfo)>, Vec<Attempt>) {
+    let mut attempts = vec![];
+    if let Err(e) = check_chain(&opts.chain) {
+        return (Err(e), attempts);
+    }
+    // 所有策略都未命中时返回第一个未命中的原因，通常是归档中找不到该内核
+    let mut first_miss = None;
+    let mut native_btf = NativeBtfStatus::Unknown;
+    for strategy in &opts.chain {
+        let result = try_strategy(strategy, tar, opts, &mut native_btf);
+        let outcome = match &result {
+            Ok(Some(_)) => Outcome::Hit,
+            Ok(None) => Outcome::Miss,
+            Err(e) if is_miss(e) => Outcome::Miss,
+            Err(e) => Outcome::Error(e.to_string()),
+        };
+        log_at!(Debug, "{}: {}", strategy, outcome);
+        attempts.push(Attempt {
+            strategy: strategy.clone(),
+            outcome,
+        });
+        match result {
+            Ok(Some((btf, mut matched))) => {
+                matched.native_btf = native_btf;
+                // 内核自带的 btf 只在调用者要求时才以路径返回
+                let btf = (*strategy != Strategy::Native || opts.always_path).then_some(btf);
+                return (Ok((btf, matched)), attempts);
+            }
+            Ok(None) => {}
+            Err(e) if is_miss(&e) => {
+                first_miss.get_or_insert(e);
+            }
+            Err(e) => {
+                log_at!(Error, "{}", e);
+                return (Err(e), attempts);
+            }
+        }
+    }
+    let e = first_miss.unwrap_or_else(|| {
+        // 只有内核自带的 btf 可用却又没有时，如实说明
+        if native_btf != NativeBtfStatus::Unknown {
+            return Error::NoNativeBtf;
+        }
+        Error::EntryNotFound {
+            expected: SystemInfo::detect_with_root(&opts.sysroot)
+                .map(|v| crate::generate_btf_archive_path_for(&v))
+                .unwrap_or_default(),
+        }
+    });
+    log_at!(Error, "{}", e);
+    (Err(e), attempts)
+}
+
+/// Check that `btf`, filed under `entry_arch`, is of the architecture of `info`, unless `opts` turned it off
+fn check_arch(btf: &[u8], entry_arch: &str, info: &SystemInfo, opts: &EnsureOptions) -> Result<()> {
+    if !opts.check_arch {
+        return Ok(());
+    }
+    check_btf_arch(btf, &[entry_arch, &info.arch])
+}
+
+/// Release of the kernel looked up, empty if it can't be detected
+fn kernel_release_of(opts: &EnsureOptions) -> String {
+    SystemInfo::detect_with_root(&opts.sysroot)
+        .map(|v| v.kernel_release)
+        .unwrap_or_default()
+}
+
+/// The btf `strategy` finds and where it came from, `None` if it has none
+///
+/// What the native strategy made of the kernel's own btf is stored in `native_btf`.
+fn try_strategy(
+    strategy: &Strategy,
+    tar: &[u8],
+    opts: &EnsureOptions,
+    native_btf: &mut NativeBtfStatus,
+) -> Result<Option<(EnsuredBtf, MatchInfo)>> {
+    match strategy {
+        Strategy::Native => {
+            let vmlinux = under_root(&opts.sysroot, VMLINUX_BTF_PATH);
+            *native_btf = NativeBtfStatus::probe(&vmlinux);
+            if *native_btf != NativeBtfStatus::Usable {
+                return Ok(None);
+            }
+            let release = kernel_release_of(opts);
+            Ok(Some((
+                EnsuredBtf::borrowed(vmlinux),
+                MatchInfo::native(release),
+            )))
+        }
+        Strategy::EnvOverride => {
+            let Some(path) = std::env::var_os(BTF_PATH_ENV).filter(|v| !v.is_empty()) else {
+                return Ok(None);
+            };
+            let path = PathBuf::from(path);
+            let bytes = std::fs::read(&path)
+                .map_err(|e| Error::FileReadError(path.display().to_string(), e))?;
+            validate_btf_bytes(&bytes)?;
+            let matched = MatchInfo::of_source(
+                BtfSource::Override,
+                Some(path.clone()),
+                false,
+                kernel_release_of(opts),
+            );
+            Ok(Some((EnsuredBtf::borrowed(path), matched)))
+        }
+        Strategy::Installed => {
+            let release = SystemInfo::detect_with_root(&opts.sysroot)?.kernel_release;
+            let btf_config = kernel_btf_config_of(&release, &opts.sysroot);
+            Ok(NativeBtfProbe::default()
+                .with_root(&opts.sysroot)
+                .probe_with_config(&release, btf_config)
+                .map(|v| {
+                    let matched =
+                        MatchInfo::of_source(BtfSource::Installed, Some(v.clone()), true, release);
+                    (EnsuredBtf::borrowed(v), matched)
+                }))
+        }
+        Strategy::Cache => {
+            let Some(cache) = BtfCache::from_default() else {
+                return Ok(None);
+            };
+            let info = SystemInfo::detect_with_root(&opts.sysroot)?;
+            Ok(cache.lookup(&archive_key(tar), &info.to_string()).map(|v| {
+                let matched =
+                    MatchInfo::of_source(BtfSource::Cache, None, false, info.kernel_release);
+                (EnsuredBtf::borrowed(v), matched)
+            }))
+        }
+        Strategy::EmbeddedArchive => {
+            let info = SystemInfo::detect_with_root(&opts.sysroot)?;
+            // 严格模式下，归档的清单表明不支持该系统时，不再解压整个归档
+            if opts.strict_coverage {
+                let listing = BtfhubArchive::new(tar)
+                    .with_prefix(&opts.prefix)
+                    .with_max_decompressed_size(opts.max_decompressed_size)
+                    .listing();
+                if let Some(listing) = listing {
+                    listing.check_coverage(&info, &opts.prefix)?;
+                }
+            }
+            let archive = match &opts.progress {
+                Some(progress) => TarballBtfArchive::from_bytes_with_progress(
+                    tar,
+                    opts.max_decompressed_size,
+                    progress,
+                )?,
+                None => TarballBtfArchive::from_bytes_with_limit(tar, opts.max_decompressed_size)?,
+            }
+            .with_prefix(&opts.prefix);
+            let entry = archive.lookup_with_policy(&info, opts.policy)?;
+            let matched = MatchInfo::of_entry(&entry, &info, BtfSource::Archive);
+            let btf = archive.extract(&entry)?;
+            check_arch(&btf, &entry.arch, &info, opts)?;
+            Ok(Some((write_ensured_btf(&btf, opts)?, matched)))
+        }
+        Strategy::ArchiveDir(dir) => {
+            let directory = BtfDirectory::new(dir);
+            let info = SystemInfo::detect_with_root(&opts.sysroot)?;
+            let entry = directory.lookup(&info)?;
+            let matched = MatchInfo::of_entry(&entry, &info, BtfSource::Archive);
+            if entry.encoding == crate::archive::BtfEncoding::Plain {
+                check_btf_file(&entry.path)?;
+                if opts.check_arch {
+                    let btf = std::fs::read(&entry.path)
+                        .map_err(|e| Error::FileReadError(entry.path.display().to_string(), e))?;
+                    check_arch(&btf, &entry.arch, &info, opts)?;
+                }
+                return Ok(Some((EnsuredBtf::borrowed(entry.path), matched)));
+            }
+            let btf = directory.extract(&entry)?;
+            check_arch(&btf, &entry.arch, &info, opts)?;
+            Ok(Some((write_ensured_btf(&btf, opts)?, matched)))
+        }
+        #[cfg(feature = "download")]
+        Strategy::Download => {
+            use crate::download::{
+                btfhub_url, check_btfhub_arch, download_btf_with, DownloadConfig,
+                DEFAULT_URL_TEMPLATE,
+            };
+            let info = SystemInfo::detect_with_root(&opts.sysroot)?;
+            check_btfhub_arch(DEFAULT_URL_TEMPLATE, &info)?;
+            let url = btfhub_url(DEFAULT_URL_TEMPLATE, &info);
+            let btf = download_btf_with(&url, &DownloadConfig::from_env())?;
+            let matched = MatchInfo::of_source(
+                BtfSource::Download,
+                Some(PathBuf::from(url)),
+                true,
+                info.kernel_release,
+            );
+            Ok(Some((write_ensured_btf(&btf, opts)?, matched)))
+        }
+        #[cfg(feature = "pahole")]
+        Strategy::Pahole => {
+            use crate::pahole::{generate_btf, PaholeOptions};
+            let release = SystemInfo::detect_with_root(&opts.sysroot)?.kernel_release;
+            let pahole = PaholeOptions::default().with_root(&opts.sysroot);
+            let btf = generate_btf(&release, &pahole)?;
+            let matched = MatchInfo::of_source(BtfSource::Pahole, None, true, release);
+            Ok(Some((write_ensured_btf(&btf, opts)?, matched)))
+        }
+        // check_chain 已拒绝编译时未启用的策略
+        #[allow(unreachable_patterns)]
+        _ => Err(Error::StrategyUnavailable(
+            strategy.to_string(),
+            strategy.missing_feature().unwrap_or_default(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        arch::normalize_arch,
+        btf::{arch_family, btf_arch},
+        ensure_core_btf_traced, ensure_core_btf_with_match_opts,
+        fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+    };
+
+    /// A root of ubuntu 20.04 with native btf, a btf installed in `/boot` and an unpacked
+    /// archive without the kernel, along with an archive that has it
+    fn root_and_archive() -> (TempDir, SystemInfo, Vec<u8>) {
+        let root = tempfile::tempdir().unwrap();
+        let path = |v: &str| root.path().join(v);
+        fs::create_dir_all(path("etc")).unwrap();
+        fs::write(path("etc/os-release"), "ID=ubuntu\nVERSION_ID=\"20.04\"\n").unwrap();
+        let info = SystemInfo::detect_with_root(root.path()).unwrap();
+        fs::create_dir_all(path("sys/kernel/btf")).unwrap();
+        fs::write(path("sys/kernel/btf/vmlinux"), btf_of_arch(8, "native")).unwrap();
+        fs::create_dir_all(path("boot")).unwrap();
+        fs::write(
+            path(&format!("boot/vmlinux-{}", info.kernel_release)),
+            btf_of_arch(8, "installed"),
+        )
+        .unwrap();
+        fs::create_dir_all(path("unpacked/ubuntu/20.04/x86_64")).unwrap();
+        let tar = FixtureArchive::new()
+            .file(
+                &format!("btfhub-archive/{}", info),
+                btf_of_arch(8, "archived"),
+            )
+            .gz();
+        (root, info, tar)
+    }
+
+    fn opts_of(root: &TempDir, chain: impl IntoIterator<Item = Strategy>) -> EnsureOptions {
+        EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_tmpdir(root.path().join("tmp"))
+            .with_always_path(true)
+            .with_chain(chain)
+    }
+
+    fn outcomes(attempts: &[Attempt]) -> Vec<(&str, &Outcome)> {
+        attempts
+            .iter()
+            .map(|v| (v.strategy.name(), &v.outcome))
+            .collect()
+    }
+
+    #[test]
+    fn first_strategy_with_the_btf_wins() {
+        let (root, info, tar) = root_and_archive();
+        let unpacked = Strategy::ArchiveDir(root.path().join("unpacked"));
+        for (chain, btf, source, tried) in [
+            (
+                vec![Strategy::Native, Strategy::EmbeddedArchive],
+                "native",
+                BtfSource::Native,
+                vec![("native", Outcome::Hit)],
+            ),
+            (
+                vec![Strategy::EmbeddedArchive, Strategy::Native],
+                "archived",
+                BtfSource::Archive,
+                vec![("archive", Outcome::Hit)],
+            ),
+            // 目录中没有该内核，继续尝试下一个策略
+            (
+                vec![unpacked.clone(), Strategy::Installed, Strategy::Native],
+                "installed",
+                BtfSource::Installed,
+                vec![("dir", Outcome::Miss), ("installed", Outcome::Hit)],
+            ),
+        ] {
+            let opts = opts_of(&root, chain.clone());
+            let (result, attempts) = ensure_core_btf_traced(&tar, &opts);
+            let ensured = result.unwrap().unwrap();
+            assert_eq!(
+                fs::read(&*ensured).unwrap(),
+                btf_of_arch(8, btf),
+                "{chain:?}"
+            );
+            assert_eq!(
+                outcomes(&attempts),
+                tried.iter().map(|(s, o)| (*s, o)).collect::<Vec<_>>()
+            );
+            let (_, matched) = ensure_core_btf_with_match_opts(&tar, &opts).unwrap();
+            assert_eq!(matched.source, source);
+            assert_eq!(matched.kernel_release, info.kernel_release);
+        }
+    }
+
+    #[test]
+    fn native_btf_status_tells_why_the_archive_was_used() {
+        let (root, info, tar) = root_and_archive();
+        let vmlinux = root.path().join("sys/kernel/btf/vmlinux");
+        let chain = [Strategy::Native, Strategy::EmbeddedArchive];
+        let matched = |opts: &EnsureOptions| {
+            let (btf, matched) = ensure_core_btf_with_match_opts(&tar, opts).unwrap();
+            (btf.map(|v| fs::read(&*v).unwrap()), matched)
+        };
+
+        let (btf, native) = matched(&opts_of(&root, chain.clone()));
+        assert_eq!(btf, Some(btf_of_arch(8, "native")));
+        assert_eq!(
+            (native.source, native.native_btf, native.entry_path),
+            (BtfSource::Native, NativeBtfStatus::Usable, None)
+        );
+        // 不要求路径时不返回 btf，来源仍是内核自带的 btf
+        let (btf, native) = matched(&opts_of(&root, chain.clone()).with_always_path(false));
+        assert_eq!(btf, None);
+        assert_eq!(native.source, BtfSource::Native);
+
+        fs::write(&vmlinux, b"garbage").unwrap();
+        let (btf, archived) = matched(&opts_of(&root, chain.clone()));
+        assert_eq!(btf, Some(btf_of_arch(8, "archived")));
+        assert_eq!(archived.source, BtfSource::Archive);
+        assert_eq!(archived.native_btf, NativeBtfStatus::Unusable);
+        assert_eq!(
+            archived.entry_path,
+            Some(PathBuf::from(format!("btfhub-archive/{}", info)))
+        );
+        assert!(archived.exact && !archived.memoized);
+
+        fs::remove_file(&vmlinux).unwrap();
+        let (_, archived) = matched(&opts_of(&root, chain.clone()));
+        assert_eq!(archived.native_btf, NativeBtfStatus::Missing);
+        // 链中没有内核自带 btf 的策略时不检查它
+        let (_, archived) = matched(&opts_of(&root, [Strategy::EmbeddedArchive]));
+        assert_eq!(archived.native_btf, NativeBtfStatus::Unknown);
+    }
+
+    #[test]
+    fn kernels_without_native_btf_fail_with_no_native_btf() {
+        let (root, _, tar) = root_and_archive();
+        fs::remove_file(root.path().join("sys/kernel/btf/vmlinux")).unwrap();
+        let (result, _) = ensure_core_btf_traced(&tar, &opts_of(&root, [Strategy::Native]));
+        assert!(matches!(result, Err(Error::NoNativeBtf)), "{result:?}");
+        // 有其他策略未命中时报告它的原因
+        fs::create_dir(root.path().join("empty")).unwrap();
+        let (result, _) = ensure_core_btf_traced(
+            b"",
+            &opts_of(
+                &root,
+                [
+                    Strategy::Native,
+                    Strategy::ArchiveDir(root.path().join("empty")),
+                ],
+            ),
+        );
+        assert!(
+            matches!(result, Err(Error::EntryNotFound { .. })),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn btfs_filed_under_another_architecture_are_refused() {
+        let (root, info, _) = root_and_archive();
+        // 选一个与本机不同族的架构，放在本机架构的目录下
+        let foreign = match arch_family(&info.arch) {
+            Some("arm64") => btf_of_arch(8, "r15"),
+            _ => btf_of_arch(8, "orig_x0"),
+        };
+        let tar = FixtureArchive::new()
+            .file(&format!("btfhub-archive/{}", info), foreign.clone())
+            .gz();
+        let unpacked = root.path().join("unpacked").join(info.to_string());
+        fs::create_dir_all(unpacked.parent().unwrap()).unwrap();
+        fs::write(&unpacked, &foreign).unwrap();
+        let dir = Strategy::ArchiveDir(root.path().join("unpacked"));
+
+        for strategy in [Strategy::EmbeddedArchive, dir.clone()] {
+            let opts = opts_of(&root, [strategy.clone(), Strategy::Native]);
+            let (result, attempts) = ensure_core_btf_traced(&tar, &opts);
+            match result {
+                Err(Error::BtfArchMismatch(btf, machine)) => {
+                    assert!(
+                        btf.contains(btf_arch(&foreign).unwrap().family.unwrap()),
+                        "{btf}"
+                    );
+                    assert_eq!(machine, normalize_arch(&info.arch));
+                }
+                other => panic!("{strategy}: {other:?}"),
+            }
+            // 不匹配是错误而不是未命中，内核自带的 btf 不会掩盖它
+            assert_eq!(attempts.len(), 1, "{attempts:?}");
+            // 检查在写出临时文件之前
+            assert!(fs::read_dir(root.path().join("tmp"))
+                .map(|v| v.count() == 0)
+                .unwrap_or(true));
+
+            let unchecked = opts_of(&root, [strategy]).with_arch_check(false);
+            let ensured = ensure_core_btf_traced(&tar, &unchecked).0.unwrap().unwrap();
+            assert_eq!(fs::read(&*ensured).unwrap(), foreign);
+        }
+
+        // 同族且指针大小相同的 btf 照常使用，结构体中没有 pt_regs 的 btf 不比较
+        for btf in [btf_of_arch(8, "archived"), minimal_valid_btf()] {
+            fs::write(&unpacked, &btf).unwrap();
+            let ensured = ensure_core_btf_traced(&tar, &opts_of(&root, [dir.clone()]))
+                .0
+                .unwrap()
+                .unwrap();
+            assert_eq!(fs::read(&*ensured).unwrap(), btf);
+        }
+    }
+
+    #[test]
+    fn chain_missing_everywhere_fails_with_the_first_miss() {
+        let (root, info, _) = root_and_archive();
+        let other = FixtureArchive::new()
+            .btf(
+                "debian",
+                "11",
+                "x86_64",
+                "5.10.0-26-amd64",
+                btf_of_arch(8, "x"),
+            )
+            .gz();
+        let opts = opts_of(
+            &root,
+            [
+                Strategy::EmbeddedArchive,
+                Strategy::ArchiveDir(root.path().join("unpacked")),
+            ],
+        );
+        let (result, attempts) = ensure_core_btf_traced(&other, &opts);
+        match result {
+            Err(Error::EntryNotFound { expected }) => {
+                assert_eq!(expected, crate::generate_btf_archive_path_for(&info))
+            }
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(
+            outcomes(&attempts),
+            [("archive", &Outcome::Miss), ("dir", &Outcome::Miss)]
+        );
+        // 空的策略链什么也找不到
+        let (result, attempts) = ensure_core_btf_traced(&other, &opts_of(&root, []));
+        assert!(matches!(result, Err(Error::EntryNotFound { .. })));
+        assert!(attempts.is_empty());
+    }
+
+    #[test]
+    fn failure_ends_the_chain() {
+        let (root, _, tar) = root_and_archive();
+        // 损坏的归档是错误而不是未命中，后面的策略不会掩盖它
+        let corrupt = &tar[..tar.len() / 2];
+        let opts = opts_of(&root, [Strategy::EmbeddedArchive, Strategy::Native]);
+        let (result, attempts) = ensure_core_btf_traced(corrupt, &opts);
+        assert!(result.is_err());
+        assert_eq!(attempts.len(), 1);
+        assert!(
+            matches!(&attempts[0].outcome, Outcome::Error(_)),
+            "{attempts:?}"
+        );
+        let missing = Strategy::ArchiveDir(root.path().join("nonexistent"));
+        let (result, attempts) = ensure_core_btf_traced(&tar, &opts_of(&root, [missing]));
+        assert!(matches!(result, Err(Error::FileReadError(..))));
+        assert_eq!(
+            attempts[0].outcome.to_string(),
+            format!("error: {}", result.unwrap_err())
+        );
+    }
+
+    #[test]
+    fn strategies_compiled_out_are_rejected_before_any_is_tried() {
+        let (root, _, tar) = root_and_archive();
+        let chain = [Strategy::Native, Strategy::Download, Strategy::Pahole];
+        let (result, attempts) = ensure_core_btf_traced(&tar, &opts_of(&root, chain.clone()));
+        let expected = chain
+            .iter()
+            .find_map(|v| v.missing_feature().map(|f| (v.to_string(), f)));
+        match (result, expected) {
+            (Err(Error::StrategyUnavailable(strategy, feature)), Some(expected)) => {
+                assert_eq!((strategy, feature), expected);
+                assert!(attempts.is_empty());
+            }
+            // 两个特性都启用时，内核自带的 btf 先被找到
+            (Ok(Some(_)), None) => assert_eq!(outcomes(&attempts), [("native", &Outcome::Hit)]),
+            (result, expected) => panic!("{result:?} {expected:?}"),
+        }
+        assert_eq!(
+            check_chain(&[Strategy::Download]).is_ok(),
+            cfg!(feature = "download")
+        );
+        assert_eq!(
+            Strategy::ArchiveDir("/srv/btfs".into()).to_string(),
+            "dir /srv/btfs"
+        );
+    }
+}
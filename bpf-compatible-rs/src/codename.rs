@@ -0,0 +1,73 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Some archive layouts name the release directory after the codename
+//! (`ubuntu/focal`) instead of the version (`ubuntu/20.04`).
+
+/// (distro id, version id, codename) of common releases
+const CODENAMES: &[(&str, &str, &str)] = &[
+    ("ubuntu", "14.04", "trusty"),
+    ("ubuntu", "16.04", "xenial"),
+    ("ubuntu", "18.04", "bionic"),
+    ("ubuntu", "18.10", "cosmic"),
+    ("ubuntu", "19.04", "disco"),
+    ("ubuntu", "19.10", "eoan"),
+    ("ubuntu", "20.04", "focal"),
+    ("ubuntu", "20.10", "groovy"),
+    ("ubuntu", "21.04", "hirsute"),
+    ("ubuntu", "21.10", "impish"),
+    ("ubuntu", "22.04", "jammy"),
+    ("ubuntu", "22.10", "kinetic"),
+    ("ubuntu", "23.04", "lunar"),
+    ("ubuntu", "23.10", "mantic"),
+    ("ubuntu", "24.04", "noble"),
+    ("debian", "8", "jessie"),
+    ("debian", "9", "stretch"),
+    ("debian", "10", "buster"),
+    ("debian", "11", "bullseye"),
+    ("debian", "12", "bookworm"),
+    ("debian", "13", "trixie"),
+];
+
+/// Codename of a release, e.g. `focal` for `ubuntu` `20.04`
+pub fn codename_of(id: &str, version_id: &str) -> Option<&'static str> {
+    CODENAMES
+        .iter()
+        .find(|(i, v, _)| *i == id && *v == version_id)
+        .map(|(_, _, c)| *c)
+}
+
+/// Version of a release named by its codename, e.g. `20.04` for `ubuntu` `focal`
+pub fn version_of(id: &str, codename: &str) -> Option<&'static str> {
+    CODENAMES
+        .iter()
+        .find(|(i, _, c)| *i == id && *c == codename)
+        .map(|(_, v, _)| *v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codenames_map_both_ways() {
+        for (id, version, codename) in [
+            ("ubuntu", "20.04", "focal"),
+            ("ubuntu", "22.04", "jammy"),
+            ("debian", "11", "bullseye"),
+        ] {
+            assert_eq!(codename_of(id, version), Some(codename));
+            assert_eq!(version_of(id, codename), Some(version));
+        }
+    }
+
+    #[test]
+    fn codenames_are_per_distro() {
+        assert_eq!(codename_of("debian", "20.04"), None);
+        assert_eq!(version_of("debian", "focal"), None);
+        assert_eq!(codename_of("fedora", "38"), None);
+        assert_eq!(codename_of("ubuntu", "20.04.6"), None);
+    }
+}
@@ -0,0 +1,782 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Pre-flight check of the CO-RE relocations of a BPF object against a btf, to find the
+//! kernels a btf exists for but lacks a type or member the program reads.
+//!
+//! Only names and kinds are compared, as libbpf does to find the candidates of a
+//! relocation: sizes, offsets and bitfields are not checked. The relocations come from
+//! the `.BTF.ext` section of the object, the types they refer to from its `.BTF` section.
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+use crate::{
+    btf::{
+        name_at, raw_types, read_u32, validate_btf_bytes, BtfHeaderInfo, RawType, BTF_KIND_ARRAY,
+        BTF_KIND_CONST, BTF_KIND_ENUM, BTF_KIND_ENUM64, BTF_KIND_RESTRICT, BTF_KIND_STRUCT,
+        BTF_KIND_TYPEDEF, BTF_KIND_TYPE_TAG, BTF_KIND_UNION, BTF_KIND_VOLATILE, BTF_MAGIC,
+    },
+    Error, Result,
+};
+
+/// Size of `struct btf_ext_header` up to the CO-RE relocation fields
+const BTF_EXT_CORE_HEADER_SIZE: u32 = 32;
+/// Size of the fields of `struct bpf_core_relo` read here
+const CORE_RELO_MIN_SIZE: usize = 16;
+
+/// Kinds of `enum bpf_core_relo_kind` checked here; the others only test for existence,
+/// which libbpf resolves to 0 rather than failing
+const BPF_CORE_FIELD_BYTE_OFFSET: u32 = 0;
+const BPF_CORE_FIELD_RSHIFT_U64: u32 = 5;
+const BPF_CORE_FIELD_EXISTS: u32 = 2;
+const BPF_CORE_TYPE_ID_TARGET: u32 = 7;
+const BPF_CORE_TYPE_SIZE: u32 = 9;
+const BPF_CORE_ENUMVAL_VALUE: u32 = 11;
+
+/// Bound on the nesting of anonymous members, against cycles in a corrupt btf
+const MAX_ANONYMOUS_DEPTH: usize = 32;
+
+/// Something a relocation of the object refers to, which the btf doesn't have
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum MissingTarget {
+    /// No type of this kind and name, e.g. `struct task_struct`
+    Type(String),
+    /// The type exists, but none of its candidates has the member, e.g. `pid` of `struct task_struct`
+    ///
+    /// `member` is the path from the type, e.g. `sched_info.run_delay`.
+    Member { type_name: String, member: String },
+    /// The enum exists, but none of its candidates has the value
+    EnumValue { type_name: String, value: String },
+}
+
+impl fmt::Display for MissingTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingTarget::Type(name) => write!(f, "{}", name),
+            MissingTarget::Member { type_name, member } => write!(f, "{}.{}", type_name, member),
+            MissingTarget::EnumValue { type_name, value } => write!(f, "{}::{}", type_name, value),
+        }
+    }
+}
+
+/// Outcome of [`check_core_compat`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompatReport {
+    /// Number of CO-RE relocations in the object
+    pub relocations: usize,
+    /// What the btf lacks, sorted and without duplicates
+    pub missing: Vec<MissingTarget>,
+}
+
+impl CompatReport {
+    /// Whether every relocation checked can be resolved against the btf
+    pub fn is_compatible(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// Check the CO-RE relocations of the BPF object `object` against the btf `btf`
+///
+/// `object` is the ELF file the compiler produced, with `.BTF` and `.BTF.ext` sections;
+/// `btf` is a raw btf blob, e.g. one extracted from the archive. Relocations that only
+/// test for existence (`bpf_core_field_exists`, `bpf_core_type_exists`, ...) are counted
+/// but never reported, as the program is expected to handle the answer. An object
+/// without `.BTF.ext` has no relocation. A malformed object fails with
+/// [`Error::InvalidObject`], a malformed btf with [`Error::InvalidBtf`].
+pub fn check_core_compat(btf: &[u8], object: &[u8]) -> Result<CompatReport> {
+    let target = Btf::parse(btf)?;
+    let local_btf = elf_section(object, ".BTF")?
+        .ok_or_else(|| Error::InvalidObject("no .BTF section".into()))?;
+    let local = Btf::parse(local_btf)?;
+    let Some(ext) = elf_section(object, ".BTF.ext")? else {
+        return Ok(CompatReport::default());
+    };
+    let relos = core_relos(ext)?;
+    let target_names = target.by_essential_name();
+    let mut missing = BTreeSet::new();
+    for relo in &relos {
+        if let Err(e) = check_relo(&local, &target, &target_names, relo) {
+            log_at!(
+                Debug,
+                "Relocation of {} at {:#x} can't be resolved: {} is missing",
+                local.name(relo.sec_name_off),
+                relo.insn_off,
+                e
+            );
+            missing.insert(e);
+        }
+    }
+    Ok(CompatReport {
+        relocations: relos.len(),
+        missing: missing.into_iter().collect(),
+    })
+}
+
+/// The parsed type section of a btf blob
+struct Btf<'a> {
+    bytes: &'a [u8],
+    info: BtfHeaderInfo,
+    types: Vec<RawType>,
+}
+
+impl<'a> Btf<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let info = validate_btf_bytes(bytes)?;
+        let types = raw_types(bytes, &info)?;
+        Ok(Self { bytes, info, types })
+    }
+
+    fn name(&self, name_off: u32) -> &'a str {
+        name_at(self.bytes, &self.info, name_off).unwrap_or_default()
+    }
+
+    /// The type of id `id`; 0 is `void`, which has none
+    fn get(&self, id: u32) -> Option<&RawType> {
+        self.types.get((id as usize).checked_sub(1)?)
+    }
+
+    /// Follow `const`, `volatile` and the like, and typedefs, to the underlying type
+    fn skip_mods_and_typedefs(&self, mut id: u32) -> u32 {
+        for _ in 0..self.types.len() {
+            match self.get(id) {
+                Some(v)
+                    if matches!(
+                        v.kind,
+                        BTF_KIND_TYPEDEF
+                            | BTF_KIND_VOLATILE
+                            | BTF_KIND_CONST
+                            | BTF_KIND_RESTRICT
+                            | BTF_KIND_TYPE_TAG
+                    ) =>
+                {
+                    id = v.size_or_type
+                }
+                _ => break,
+            }
+        }
+        id
+    }
+
+    /// Ids of the named types, by the name without any `___flavor` suffix
+    fn by_essential_name(&self) -> HashMap<&'a str, Vec<u32>> {
+        let mut names: HashMap<_, Vec<_>> = HashMap::new();
+        for (i, ty) in self.types.iter().enumerate() {
+            let name = essential_name(self.name(ty.name_off));
+            if !name.is_empty() {
+                names.entry(name).or_default().push(i as u32 + 1);
+            }
+        }
+        names
+    }
+
+    /// The type of the member `name` of the struct or union `id`, looking into anonymous members
+    fn find_member(&self, id: u32, name: &str, depth: usize) -> Option<u32> {
+        let ty = self.get(id)?;
+        if !matches!(ty.kind, BTF_KIND_STRUCT | BTF_KIND_UNION) || depth > MAX_ANONYMOUS_DEPTH {
+            return None;
+        }
+        ty.members.iter().find_map(|v| match self.name(v.name_off) {
+            "" => self.find_member(self.skip_mods_and_typedefs(v.type_id), name, depth + 1),
+            member if member == name => Some(v.type_id),
+            _ => None,
+        })
+    }
+
+    /// E.g. `struct task_struct`, as the type is written in C
+    fn display_name(&self, ty: &RawType) -> String {
+        let name = self.name(ty.name_off);
+        match ty.kind {
+            BTF_KIND_STRUCT => format!("struct {}", name),
+            BTF_KIND_UNION => format!("union {}", name),
+            BTF_KIND_ENUM | BTF_KIND_ENUM64 => format!("enum {}", name),
+            _ => name.to_string(),
+        }
+    }
+}
+
+/// The name without the `___flavor` suffix, which CO-RE ignores when matching types
+fn essential_name(name: &str) -> &str {
+    match name.find("___") {
+        Some(end) if end > 0 => &name[..end],
+        _ => name,
+    }
+}
+
+/// Whether a local type of kind `local` may be relocated against one of kind `target`
+fn kinds_compatible(local: u8, target: u8) -> bool {
+    let is_enum = |v| matches!(v, BTF_KIND_ENUM | BTF_KIND_ENUM64);
+    local == target || (is_enum(local) && is_enum(target))
+}
+
+/// One `struct bpf_core_relo`, with the program section it belongs to
+struct CoreRelo {
+    sec_name_off: u32,
+    insn_off: u32,
+    type_id: u32,
+    access_str_off: u32,
+    kind: u32,
+}
+
+fn check_relo(
+    local: &Btf,
+    target: &Btf,
+    target_names: &HashMap<&str, Vec<u32>>,
+    relo: &CoreRelo,
+) -> std::result::Result<(), MissingTarget> {
+    let is_field = (BPF_CORE_FIELD_BYTE_OFFSET..=BPF_CORE_FIELD_RSHIFT_U64).contains(&relo.kind)
+        && relo.kind != BPF_CORE_FIELD_EXISTS;
+    let is_type = matches!(relo.kind, BPF_CORE_TYPE_ID_TARGET | BPF_CORE_TYPE_SIZE);
+    if !is_field && !is_type && relo.kind != BPF_CORE_ENUMVAL_VALUE {
+        return Ok(());
+    }
+    // 无名的本地类型无法按名字匹配，libbpf 也不会为其查找候选
+    let Some(local_type) = local.get(relo.type_id) else {
+        return Ok(());
+    };
+    let name = essential_name(local.name(local_type.name_off));
+    if name.is_empty() {
+        return Ok(());
+    }
+    let type_name = local.display_name(local_type);
+    let candidates: Vec<u32> = target_names
+        .get(name)
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|v| {
+            target
+                .get(*v)
+                .is_some_and(|v| kinds_compatible(local_type.kind, v.kind))
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(MissingTarget::Type(type_name));
+    }
+    let access: Vec<usize> = local
+        .name(relo.access_str_off)
+        .split(':')
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    if relo.kind == BPF_CORE_ENUMVAL_VALUE {
+        let Some(value) = access
+            .first()
+            .and_then(|v| local_type.members.get(*v))
+            .map(|v| local.name(v.name_off))
+        else {
+            return Ok(());
+        };
+        let found = candidates.iter().any(|v| {
+            target
+                .get(*v)
+                .is_some_and(|v| v.members.iter().any(|v| target.name(v.name_off) == value))
+        });
+        if !found {
+            return Err(MissingTarget::EnumValue {
+                type_name,
+                value: value.to_string(),
+            });
+        }
+        return Ok(());
+    }
+    if is_type {
+        return Ok(());
+    }
+    // 候选中任意一个能解析出完整的访问路径即可；否则报告最长的那条缺失路径
+    let mut longest = String::new();
+    for candidate in candidates {
+        match resolve_access(local, target, relo.type_id, candidate, &access) {
+            Ok(()) => return Ok(()),
+            Err(path) if path.len() > longest.len() => longest = path,
+            Err(_) => {}
+        }
+    }
+    Err(MissingTarget::Member {
+        type_name,
+        member: longest,
+    })
+}
+
+/// Follow the access string of a field relocation through both btfs
+///
+/// The first index is into an array of the root type, the others are member indices into
+/// the local types, matched by name in the target. Returns the member path up to the first
+/// member the target lacks.
+fn resolve_access(
+    local: &Btf,
+    target: &Btf,
+    local_root: u32,
+    target_root: u32,
+    access: &[usize],
+) -> std::result::Result<(), String> {
+    let mut local_id = local.skip_mods_and_typedefs(local_root);
+    let mut target_id = target.skip_mods_and_typedefs(target_root);
+    let mut path: Vec<&str> = vec![];
+    for index in access.iter().skip(1) {
+        let Some(local_type) = local.get(local_id) else {
+            return Ok(());
+        };
+        match local_type.kind {
+            BTF_KIND_ARRAY => {
+                local_id = local.skip_mods_and_typedefs(local_type.members[0].type_id);
+                match target.get(target_id) {
+                    Some(v) if v.kind == BTF_KIND_ARRAY => {
+                        target_id = target.skip_mods_and_typedefs(v.members[0].type_id)
+                    }
+                    _ => return Err(path.join(".")),
+                }
+            }
+            BTF_KIND_STRUCT | BTF_KIND_UNION => {
+                let Some(member) = local_type.members.get(*index) else {
+                    return Ok(());
+                };
+                local_id = local.skip_mods_and_typedefs(member.type_id);
+                let name = local.name(member.name_off);
+                // 匿名成员在目标中通过 find_member 向下查找，目标类型保持不变
+                if name.is_empty() {
+                    continue;
+                }
+                path.push(name);
+                match target.find_member(target_id, name, 0) {
+                    Some(v) => target_id = target.skip_mods_and_typedefs(v),
+                    None => return Err(path.join(".")),
+                }
+            }
+            _ => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// The CO-RE relocations of a `.BTF.ext` section, empty if it has none
+fn core_relos(ext: &[u8]) -> Result<Vec<CoreRelo>> {
+    let invalid = |what: &str| Error::InvalidObject(format!(".BTF.ext: {}", what));
+    let read = |offset: usize| read_u32(ext, offset).ok_or_else(|| invalid("truncated"));
+    if ext.len() < 8 || u16::from_ne_bytes([ext[0], ext[1]]) != BTF_MAGIC {
+        return Err(invalid("bad magic"));
+    }
+    let hdr_len = read(4)?;
+    // 较旧的编译器生成的头部没有 CO-RE 重定位的字段
+    if hdr_len < BTF_EXT_CORE_HEADER_SIZE {
+        return Ok(vec![]);
+    }
+    // 以 u64 计算，32 位平台上两个 u32 之和也不会溢出
+    let start = u64::from(hdr_len) + u64::from(read(24)?);
+    let len = u64::from(read(28)?);
+    if len == 0 {
+        return Ok(vec![]);
+    }
+    let section =
+        slice_at(ext, start, len).ok_or_else(|| invalid("CO-RE relocations beyond the section"))?;
+    let read = |offset: usize| read_u32(section, offset).ok_or_else(|| invalid("truncated"));
+    let record_size = read(0)? as usize;
+    if record_size < CORE_RELO_MIN_SIZE {
+        return Err(invalid("CO-RE relocation records too small"));
+    }
+    let mut relos = vec![];
+    let mut offset = 4;
+    while offset < section.len() {
+        let sec_name_off = read(offset)?;
+        let num_info = read(offset + 4)? as usize;
+        offset += 8;
+        for _ in 0..num_info {
+            relos.push(CoreRelo {
+                sec_name_off,
+                insn_off: read(offset)?,
+                type_id: read(offset + 4)?,
+                access_str_off: read(offset + 8)?,
+                kind: read(offset + 12)?,
+            });
+            offset += record_size;
+        }
+    }
+    Ok(relos)
+}
+
+/// `len` bytes of `bytes` from `offset`, `None` if they aren't all in it
+fn slice_at(bytes: &[u8], offset: u64, len: u64) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    bytes.get(start..end)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_ne_bytes(
+        bytes.get(offset..offset.checked_add(8)?)?.try_into().ok()?,
+    ))
+}
+
+/// The contents of the section `name` of a 64-bit ELF file in the host's byte order
+fn elf_section<'a>(object: &'a [u8], name: &str) -> Result<Option<&'a [u8]>> {
+    let invalid = |what: &str| Error::InvalidObject(what.to_string());
+    if object.get(..4) != Some(b"\x7fELF") {
+        return Err(invalid("not an ELF file"));
+    }
+    // e_ident[EI_CLASS] 为 2 表示 64 位，e_ident[EI_DATA] 为 1 表示小端、2 表示大端
+    if object.get(4) != Some(&2) {
+        return Err(invalid("not a 64-bit ELF file"));
+    }
+    let host_data = if cfg!(target_endian = "little") { 1 } else { 2 };
+    if object.get(5) != Some(&host_data) {
+        return Err(invalid("not in the byte order of the host"));
+    }
+    let truncated = || invalid("truncated ELF file");
+    let read_u16 = |offset: usize| {
+        object
+            .get(offset..offset + 2)
+            .map(|v| u16::from_ne_bytes([v[0], v[1]]))
+            .ok_or_else(truncated)
+    };
+    // 偏移在 32 位平台上可能超出 usize，不能直接截断
+    let shoff = read_u64(object, 0x28)
+        .and_then(|v| usize::try_from(v).ok())
+        .ok_or_else(truncated)?;
+    let shentsize = read_u16(0x3a)? as usize;
+    let shnum = read_u16(0x3c)? as usize;
+    let shstrndx = read_u16(0x3e)? as usize;
+    let header = |index: usize| shoff.checked_add(index.checked_mul(shentsize)?);
+    let name_off = |index: usize| {
+        header(index)
+            .and_then(|v| read_u32(object, v))
+            .ok_or_else(truncated)
+    };
+    let data = |index: usize| {
+        let header = header(index).ok_or_else(truncated)?;
+        let offset = read_u64(object, header.checked_add(24).ok_or_else(truncated)?);
+        let size = read_u64(object, header.checked_add(32).ok_or_else(truncated)?);
+        offset
+            .zip(size)
+            .and_then(|(offset, size)| slice_at(object, offset, size))
+            .ok_or_else(truncated)
+    };
+    let names = data(shstrndx)?;
+    for index in 1..shnum {
+        let section_name = names
+            .get(name_off(index)? as usize..)
+            .and_then(|v| v.split(|v| *v == 0).next())
+            .unwrap_or_default();
+        // 只读取名字匹配的节，.bss 等节在文件中没有内容
+        if section_name == name.as_bytes() {
+            return data(index).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        btf::{BTF_HEADER_SIZE, BTF_KIND_INT, BTF_VERSION},
+        embed::EmbeddedArchiveObject,
+        section::add_elf_section,
+    };
+
+    /// A btf built type by type, in the byte order of the host
+    #[derive(Default)]
+    struct TestBtf {
+        types: Vec<u32>,
+        strings: Vec<u8>,
+        count: u32,
+    }
+
+    impl TestBtf {
+        fn new() -> Self {
+            Self {
+                strings: vec![0],
+                ..Default::default()
+            }
+        }
+
+        fn string(&mut self, s: &str) -> u32 {
+            if s.is_empty() {
+                return 0;
+            }
+            let offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(s.as_bytes());
+            self.strings.push(0);
+            offset
+        }
+
+        fn push(&mut self, words: &[u32]) -> u32 {
+            self.types.extend_from_slice(words);
+            self.count += 1;
+            self.count
+        }
+
+        fn int(&mut self, name: &str) -> u32 {
+            let name = self.string(name);
+            self.push(&[name, (BTF_KIND_INT as u32) << 24, 4, 32])
+        }
+
+        fn composite(&mut self, kind: u8, name: &str, members: &[(&str, u32)]) -> u32 {
+            let mut words = vec![
+                self.string(name),
+                (kind as u32) << 24 | members.len() as u32,
+                4 * members.len() as u32,
+            ];
+            for (i, (name, type_id)) in members.iter().enumerate() {
+                words.extend([self.string(name), *type_id, i as u32 * 32]);
+            }
+            self.push(&words)
+        }
+
+        fn structure(&mut self, name: &str, members: &[(&str, u32)]) -> u32 {
+            self.composite(BTF_KIND_STRUCT, name, members)
+        }
+
+        fn enumeration(&mut self, name: &str, values: &[&str]) -> u32 {
+            let mut words = vec![
+                self.string(name),
+                (BTF_KIND_ENUM as u32) << 24 | values.len() as u32,
+                4,
+            ];
+            for (i, value) in values.iter().enumerate() {
+                words.extend([self.string(value), i as u32]);
+            }
+            self.push(&words)
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            let types: Vec<u8> = self.types.iter().flat_map(|v| v.to_ne_bytes()).collect();
+            let mut btf = vec![];
+            btf.extend(BTF_MAGIC.to_ne_bytes());
+            btf.extend([BTF_VERSION, 0]);
+            let (types_len, strings_len) = (types.len() as u32, self.strings.len() as u32);
+            for v in [BTF_HEADER_SIZE, 0, types_len, types_len, strings_len] {
+                btf.extend(v.to_ne_bytes());
+            }
+            btf.extend(types);
+            btf.extend(&self.strings);
+            btf
+        }
+    }
+
+    /// A BPF object whose `.BTF` is `local`, with the CO-RE relocations
+    /// `(type id, access string, kind)` in `.BTF.ext`
+    fn object(mut local: TestBtf, relos: &[(u32, &str, u32)]) -> Vec<u8> {
+        let sec_name = local.string("kprobe/do_exit");
+        let relos: Vec<_> = relos
+            .iter()
+            .map(|(type_id, access, kind)| [*type_id, local.string(access), *kind])
+            .collect();
+        let mut records = vec![CORE_RELO_MIN_SIZE as u32, sec_name, relos.len() as u32];
+        for (i, [type_id, access, kind]) in relos.into_iter().enumerate() {
+            records.extend([i as u32 * 8, type_id, access, kind]);
+        }
+        let mut ext = vec![];
+        ext.extend(BTF_MAGIC.to_ne_bytes());
+        ext.extend([BTF_VERSION, 0]);
+        let records_len = records.len() as u32 * 4;
+        // 没有 func_info 和 line_info，CO-RE 重定位紧跟在头部之后
+        for v in [BTF_EXT_CORE_HEADER_SIZE, 0, 0, 0, 0, 0, records_len] {
+            ext.extend(v.to_ne_bytes());
+        }
+        ext.extend(records.iter().flat_map(|v| v.to_ne_bytes()));
+        let elf = object_without_ext(&local);
+        add_elf_section(&elf, ".BTF.ext", &ext).unwrap()
+    }
+
+    fn object_without_ext(local: &TestBtf) -> Vec<u8> {
+        let elf = EmbeddedArchiveObject::new(&[], "x86_64")
+            .unwrap()
+            .to_bytes();
+        add_elf_section(&elf, ".BTF", &local.bytes()).unwrap()
+    }
+
+    /// The kernel side: `struct task_struct { int pid; }` and `enum task_state { RUNNING }`
+    fn kernel_btf() -> Vec<u8> {
+        let mut btf = TestBtf::new();
+        let int = btf.int("int");
+        btf.structure("task_struct", &[("pid", int)]);
+        btf.enumeration("task_state", &["RUNNING"]);
+        btf.bytes()
+    }
+
+    #[test]
+    fn missing_types_members_and_values_are_reported() {
+        let mut local = TestBtf::new();
+        let int = local.int("int");
+        let task = local.structure("task_struct", &[("pid", int), ("tgid", int)]);
+        let state = local.enumeration("task_state", &["RUNNING", "STOPPED"]);
+        let absent = local.structure("cgroup_bpf", &[("flags", int)]);
+        let object = object(
+            local,
+            &[
+                (task, "0:0", BPF_CORE_FIELD_BYTE_OFFSET),
+                (task, "0:1", BPF_CORE_FIELD_BYTE_OFFSET),
+                (state, "0", BPF_CORE_ENUMVAL_VALUE),
+                (state, "1", BPF_CORE_ENUMVAL_VALUE),
+                (absent, "0", BPF_CORE_TYPE_SIZE),
+                // 只检查是否存在的重定位由程序自己处理，不报告
+                (absent, "0:0", BPF_CORE_FIELD_EXISTS),
+            ],
+        );
+        let report = check_core_compat(&kernel_btf(), &object).unwrap();
+        assert_eq!(report.relocations, 6);
+        assert!(!report.is_compatible());
+        assert_eq!(
+            report.missing,
+            vec![
+                MissingTarget::Type("struct cgroup_bpf".into()),
+                MissingTarget::Member {
+                    type_name: "struct task_struct".into(),
+                    member: "tgid".into()
+                },
+                MissingTarget::EnumValue {
+                    type_name: "enum task_state".into(),
+                    value: "STOPPED".into()
+                },
+            ]
+        );
+        assert_eq!(
+            report
+                .missing
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "struct cgroup_bpf",
+                "struct task_struct.tgid",
+                "enum task_state::STOPPED"
+            ]
+        );
+    }
+
+    #[test]
+    fn flavors_anonymous_members_and_nested_paths_are_followed() {
+        let mut kernel = TestBtf::new();
+        let int = kernel.int("int");
+        let info = kernel.structure("sched_info", &[("pcount", int)]);
+        let anon = kernel.composite(BTF_KIND_UNION, "", &[("pid", int)]);
+        kernel.structure("task_struct", &[("", anon), ("sched_info", info)]);
+        let kernel = kernel.bytes();
+
+        let mut local = TestBtf::new();
+        let int = local.int("int");
+        let info = local.structure("sched_info", &[("pcount", int), ("run_delay", int)]);
+        let task = local.structure("task_struct___v5", &[("pid", int), ("sched_info", info)]);
+        let object = object(
+            local,
+            &[
+                (task, "0:0", BPF_CORE_FIELD_BYTE_OFFSET),
+                (task, "0:1:0", BPF_CORE_FIELD_BYTE_OFFSET),
+                (task, "0:1:1", BPF_CORE_FIELD_BYTE_OFFSET),
+            ],
+        );
+        let report = check_core_compat(&kernel, &object).unwrap();
+        assert_eq!(
+            report.missing,
+            vec![MissingTarget::Member {
+                type_name: "struct task_struct___v5".into(),
+                member: "sched_info.run_delay".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn btf_with_everything_is_compatible() {
+        let mut local = TestBtf::new();
+        let int = local.int("int");
+        let task = local.structure("task_struct", &[("pid", int)]);
+        let object = object(local, &[(task, "0:0", BPF_CORE_FIELD_BYTE_OFFSET)]);
+        let report = check_core_compat(&kernel_btf(), &object).unwrap();
+        assert_eq!(report.relocations, 1);
+        assert!(report.is_compatible());
+    }
+
+    #[test]
+    fn malformed_inputs_are_rejected() {
+        let mut local = TestBtf::new();
+        local.int("int");
+        // 没有 .BTF.ext 就没有重定位
+        let report = check_core_compat(&kernel_btf(), &object_without_ext(&local)).unwrap();
+        assert_eq!(report, CompatReport::default());
+        assert!(matches!(
+            check_core_compat(&kernel_btf(), b"not an elf"),
+            Err(Error::InvalidObject(_))
+        ));
+        let elf = EmbeddedArchiveObject::new(&[], "x86_64")
+            .unwrap()
+            .to_bytes();
+        assert!(matches!(
+            check_core_compat(&kernel_btf(), &elf),
+            Err(Error::InvalidObject(v)) if v.contains(".BTF")
+        ));
+        let object = object(local, &[]);
+        assert!(matches!(
+            check_core_compat(b"not a btf", &object),
+            Err(Error::InvalidBtf(_))
+        ));
+        // 截断的 .BTF.ext
+        let elf = object_without_ext(&TestBtf::new());
+        let ext = [BTF_MAGIC.to_ne_bytes().as_slice(), &[BTF_VERSION, 0], &[32]].concat();
+        let object = add_elf_section(&elf, ".BTF.ext", &ext).unwrap();
+        assert!(matches!(
+            check_core_compat(&kernel_btf(), &object),
+            Err(Error::InvalidObject(v)) if v.contains(".BTF.ext")
+        ));
+    }
+
+    #[test]
+    fn slices_out_of_range_are_none() {
+        let bytes = [1, 2, 3, 4];
+        assert_eq!(slice_at(&bytes, 1, 2), Some(&bytes[1..3]));
+        assert_eq!(slice_at(&bytes, 4, 0), Some(&[][..]));
+        assert_eq!(slice_at(&bytes, 3, 2), None);
+        assert_eq!(slice_at(&bytes, 5, 0), None);
+        // 相加溢出，或在 32 位平台上超出 usize 的值
+        assert_eq!(slice_at(&bytes, u64::MAX, 2), None);
+        assert_eq!(slice_at(&bytes, 1, u64::MAX), None);
+        assert_eq!(slice_at(&bytes, u64::from(u32::MAX) + 1, 0), None);
+    }
+
+    #[test]
+    fn offsets_out_of_range_are_rejected() {
+        let mut local = TestBtf::new();
+        local.int("int");
+        let elf = object_without_ext(&local);
+        let patched = |offset: usize, value: u64| {
+            let mut elf = elf.clone();
+            elf[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+            elf
+        };
+        let read_u16 = |offset: usize| u16::from_ne_bytes([elf[offset], elf[offset + 1]]) as usize;
+        let shoff = read_u64(&elf, 0x28).unwrap() as usize;
+        let names_header = shoff + read_u16(0x3e) * read_u16(0x3a);
+        for object in [
+            // 节头表的偏移
+            patched(0x28, u64::MAX),
+            patched(0x28, u64::from(u32::MAX) + 1),
+            // 节名字符串表的偏移与大小，两者之和溢出
+            patched(names_header + 24, u64::MAX - 1),
+            patched(names_header + 32, u64::MAX),
+        ] {
+            assert!(matches!(
+                check_core_compat(&kernel_btf(), &object),
+                Err(Error::InvalidObject(v)) if v.contains("truncated")
+            ));
+        }
+        // CO-RE 重定位的偏移超出 .BTF.ext，两个 u32 相加也不会回绕
+        let mut ext = vec![];
+        ext.extend(BTF_MAGIC.to_ne_bytes());
+        ext.extend([BTF_VERSION, 0]);
+        for v in [BTF_EXT_CORE_HEADER_SIZE, 0, 0, 0, 0, u32::MAX, 16] {
+            ext.extend(v.to_ne_bytes());
+        }
+        let object = add_elf_section(&elf, ".BTF.ext", &ext).unwrap();
+        assert!(matches!(
+            check_core_compat(&kernel_btf(), &object),
+            Err(Error::InvalidObject(v)) if v.contains("beyond the section")
+        ));
+    }
+}
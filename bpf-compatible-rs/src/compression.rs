@@ -0,0 +1,566 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Archives are built by different pipelines, so the embedded blob may be a gzipped,
+//! xz or zstd compressed, or plain tar. The format is told apart by its magic bytes.
+//!
+//! Archives may also have been extended by concatenating another one (e.g. a tar.gz of
+//! newly released kernels) and padded with zeros by objcopy, so every gzip member is
+//! decompressed, and the tar is read past the end-of-archive marker of each part.
+//!
+//! A small blob may decompress to an arbitrary size, so the decompressed stream is cut
+//! off at [`DEFAULT_MAX_DECOMPRESSED_SIZE`](crate::compression::DEFAULT_MAX_DECOMPRESSED_SIZE), or the limit given to [`tar_reader_with_limit`](crate::compression::tar_reader_with_limit).
+use std::{
+    fmt::Display,
+    io::{ErrorKind, Read},
+};
+
+use flate2::bufread::GzDecoder;
+use tar::{Archive, Entries, Entry};
+
+use crate::{Error, Result};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = &[0xfd, 0x37, 0x7a, 0x58, 0x5a];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+/// The ustar magic is found at offset 257 of a tar header
+const USTAR_MAGIC_OFFSET: usize = 257;
+const USTAR_MAGIC: &[u8] = b"ustar";
+
+/// Size a compressed archive or btf is allowed to decompress to by default, 4 GiB
+///
+/// Far more than any btfhub-archive, so only malformed or malicious blobs hit it.
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: u64 = 4 << 30;
+
+/// Format of an archive blob
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Gzip,
+    Xz,
+    Zstd,
+    /// An uncompressed tar
+    Tar,
+}
+
+impl Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveFormat::Gzip => write!(f, "gzip"),
+            ArchiveFormat::Xz => write!(f, "xz"),
+            ArchiveFormat::Zstd => write!(f, "zstd"),
+            ArchiveFormat::Tar => write!(f, "tar"),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    /// Detect the format of `bytes` by its magic bytes
+    pub fn detect(bytes: &[u8]) -> Result<Self> {
+        for (magic, format) in [
+            (GZIP_MAGIC, ArchiveFormat::Gzip),
+            (XZ_MAGIC, ArchiveFormat::Xz),
+            (ZSTD_MAGIC, ArchiveFormat::Zstd),
+        ] {
+            if bytes.starts_with(magic) {
+                return Ok(format);
+            }
+        }
+        if bytes
+            .get(USTAR_MAGIC_OFFSET..)
+            .is_some_and(|v| v.starts_with(USTAR_MAGIC))
+        {
+            return Ok(ArchiveFormat::Tar);
+        }
+        Err(Error::UnknownArchiveFormat(
+            bytes
+                .iter()
+                .take(8)
+                .map(|v| format!("{:02x}", v))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ))
+    }
+}
+
+/// The gzip members of a blob, decompressed one after another as a single stream
+///
+/// Zeros between or after the members are skipped.
+struct GzMembers<'a> {
+    member: Option<GzDecoder<&'a [u8]>>,
+}
+
+impl<'a> GzMembers<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self> {
+        // 构造时即解析 gzip 头部，头部无效时尽早报错
+        let decoder = GzDecoder::new(bytes);
+        if decoder.header().is_none() {
+            return Err(Error::InvalidGzipHeader);
+        }
+        Ok(Self {
+            member: Some(decoder),
+        })
+    }
+}
+
+impl Read for GzMembers<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while let Some(decoder) = self.member.as_mut() {
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            // 当前成员已读完，其后若还有非零字节，则是拼接上的下一个成员
+            let rest = *decoder.get_ref();
+            let rest = &rest[rest.iter().position(|v| *v != 0).unwrap_or(rest.len())..];
+            self.member = (!rest.is_empty()).then(|| GzDecoder::new(rest));
+        }
+        Ok(0)
+    }
+}
+
+/// An error of the decompressor of an archive, see [`Error::from_read`]
+#[derive(Debug)]
+struct DecompressError(std::io::Error);
+
+impl Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for DecompressError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// A decompressor whose errors are marked, so that they aren't taken for those of the tar
+struct Decompressing<R>(R);
+
+impl<R: Read> Read for Decompressing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0
+            .read(buf)
+            .map_err(|e| std::io::Error::new(e.kind(), DecompressError(e)))
+    }
+}
+
+impl Error {
+    /// The error of a read of the tar from [`tar_reader`]: [`Error::Decompress`] if the
+    /// decompressor failed, e.g. on corrupt data, [`Error::TarReadError`] otherwise
+    pub fn from_read(e: std::io::Error) -> Self {
+        if !e.get_ref().is_some_and(|v| v.is::<DecompressError>()) {
+            return Error::TarReadError(e);
+        }
+        match e.into_inner().map(|v| v.downcast::<DecompressError>()) {
+            Some(Ok(v)) => Error::Decompress(v.0),
+            // 上面已确认其类型
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A reader failing once more than `limit` bytes have been read from `inner`
+///
+/// The error is of kind [`ErrorKind::FileTooLarge`]. Reading exactly `limit` bytes is fine.
+#[derive(Debug)]
+pub struct LimitedReader<R> {
+    inner: R,
+    limit: u64,
+    remaining: u64,
+}
+
+impl<R: Read> LimitedReader<R> {
+    /// Read from `inner`, at most `limit` bytes
+    pub fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            remaining: limit,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            // 已读满上限，只有在后面确实还有数据时才报错
+            let mut probe = [0u8; 1];
+            if self.inner.read(&mut probe)? == 0 {
+                return Ok(0);
+            }
+            return Err(std::io::Error::new(
+                ErrorKind::FileTooLarge,
+                format!(
+                    "decompressed size exceeds the limit of {} bytes",
+                    self.limit
+                ),
+            ));
+        }
+        let len = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..len])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// A reader of the tar held by `bytes`, decompressing it according to its format
+///
+/// All the members of a gzipped blob are read, see the [module](self) documentation.
+/// The decompressed stream is limited to [`DEFAULT_MAX_DECOMPRESSED_SIZE`].
+pub fn tar_reader(bytes: &[u8]) -> Result<Box<dyn Read + '_>> {
+    tar_reader_with_limit(bytes, DEFAULT_MAX_DECOMPRESSED_SIZE)
+}
+
+/// Same as [`tar_reader`], failing with [`ErrorKind::FileTooLarge`] once a compressed
+/// blob decompresses to more than `max_size` bytes
+///
+/// Plain tars are read as they are, they can't be larger than `bytes`.
+pub fn tar_reader_with_limit(bytes: &[u8], max_size: u64) -> Result<Box<dyn Read + '_>> {
+    match ArchiveFormat::detect(bytes)? {
+        ArchiveFormat::Gzip => Ok(Box::new(LimitedReader::new(
+            Decompressing(GzMembers::new(bytes)?),
+            max_size,
+        ))),
+        #[cfg(feature = "zstd")]
+        ArchiveFormat::Zstd => Ok(Box::new(LimitedReader::new(
+            Decompressing(crate::zstd::ZstdDecoder::new(bytes).map_err(Error::Decompress)?),
+            max_size,
+        ))),
+        #[cfg(feature = "xz")]
+        ArchiveFormat::Xz => Ok(Box::new(LimitedReader::new(
+            Decompressing(crate::xz::XzDecoder::new(bytes).map_err(Error::Decompress)?),
+            max_size,
+        ))),
+        ArchiveFormat::Tar => Ok(Box::new(bytes)),
+        // 启用了全部解压特性时不可达
+        #[allow(unreachable_patterns)]
+        format => Err(Error::UnsupportedCompression(format)),
+    }
+}
+
+/// A tar archive reading from `reader`, which goes on past end-of-archive markers
+///
+/// Concatenated tars have the marker of every part but the last in the middle, so it
+/// has to be used instead of [`Archive::new`] for the btf archive.
+pub fn tar_archive<R: Read>(reader: R) -> Archive<R> {
+    let mut archive = Archive::new(reader);
+    archive.set_ignore_zeros(true);
+    archive
+}
+
+/// The entries of `archive`, as [`Archive::entries`] yields them, see [`tar_entries`]
+pub struct TarEntries<'a, R: 'a + Read> {
+    inner: Entries<'a, R>,
+    /// Path of the last entry read
+    last: Option<String>,
+}
+
+/// Iterate over the entries of `archive`, with errors naming the entry they follow
+///
+/// A corrupt size field only fails once the next header is read past the end of the
+/// entry, by an error that doesn't tell which entry it was, so the path of the last entry
+/// read is added. Errors the tar crate reports as [`ErrorKind::Other`], like a bad header
+/// checksum or the archive ending within an entry, are turned into
+/// [`ErrorKind::InvalidData`], telling a corrupt archive apart from a failed read.
+pub fn tar_entries<R: Read>(archive: &mut Archive<R>) -> std::io::Result<TarEntries<'_, R>> {
+    Ok(TarEntries {
+        inner: archive.entries().map_err(|e| corrupt_tar_error(e, None))?,
+        last: None,
+    })
+}
+
+impl<'a, R: Read> Iterator for TarEntries<'a, R> {
+    type Item = std::io::Result<Entry<'a, R>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Ok(entry) => {
+                self.last = Some(String::from_utf8_lossy(&entry.path_bytes()).into_owned());
+                Some(Ok(entry))
+            }
+            Err(e) => Some(Err(corrupt_tar_error(e, self.last.as_deref()))),
+        }
+    }
+}
+
+fn corrupt_tar_error(e: std::io::Error, last: Option<&str>) -> std::io::Error {
+    // 解压失败与 tar 本身无关，保留原样以便区分
+    if e.get_ref().is_some_and(|v| v.is::<DecompressError>()) {
+        return e;
+    }
+    let kind = match e.kind() {
+        ErrorKind::Other => ErrorKind::InvalidData,
+        v => v,
+    };
+    match last {
+        Some(path) => std::io::Error::new(
+            kind,
+            format!(
+                "failed to read the entry after `{}`, which may extend past the end of the archive: {}",
+                path, e
+            ),
+        ),
+        None if kind != e.kind() => std::io::Error::new(kind, e),
+        None => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::fixture::{minimal_valid_btf, FixtureArchive};
+
+    fn fixture() -> FixtureArchive {
+        FixtureArchive::new().file("a.btf", minimal_valid_btf())
+    }
+
+    /// Paths of the entries of the tar read from `bytes`
+    fn entry_paths(bytes: &[u8]) -> Vec<String> {
+        let mut archive = tar_archive(tar_reader(bytes).unwrap());
+        tar_entries(&mut archive)
+            .unwrap()
+            .map(|v| v.unwrap().path().unwrap().display().to_string())
+            .collect()
+    }
+
+    /// `tar` with the size field of the header at `offset` set to `size`, checksum fixed
+    fn with_size(mut tar: Vec<u8>, offset: usize, size: u64) -> Vec<u8> {
+        let mut header = tar::Header::from_byte_slice(&tar[offset..offset + 512]).clone();
+        header.set_size(size);
+        header.set_cksum();
+        tar[offset..offset + 512].copy_from_slice(header.as_bytes());
+        tar
+    }
+
+    #[test]
+    fn oversized_entries_are_named_by_the_error() {
+        let tar = fixture().file("b.btf", minimal_valid_btf()).tar();
+        for size in [4096, 1 << 40, u64::MAX >> 4] {
+            let corrupt = with_size(tar.clone(), 0, size);
+            let mut archive = tar_archive(&corrupt[..]);
+            let mut entries = tar_entries(&mut archive).unwrap();
+            // 头部本身可以读取，越界在读取下一个头部时才被发现
+            assert_eq!(entries.next().unwrap().unwrap().size(), size);
+            let e = entries.next().unwrap().err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidData, "{e}");
+            assert!(e.to_string().contains("the entry after `a.btf`"), "{e}");
+        }
+        // 第一个头部之前没有条目可以指明
+        let mut garbage = tar.clone();
+        garbage[148] ^= 1;
+        let mut archive = tar_archive(&garbage[..]);
+        let e = tar_entries(&mut archive)
+            .unwrap()
+            .next()
+            .unwrap()
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData, "{e}");
+        assert!(!e.to_string().contains("the entry after"), "{e}");
+    }
+
+    #[test]
+    fn decompression_failures_are_told_from_corrupt_tars() {
+        /// The error of reading every entry of `bytes`
+        fn read_all(bytes: &[u8]) -> Error {
+            let mut archive = tar_archive(tar_reader(bytes).unwrap());
+            let result = tar_entries(&mut archive).and_then(|entries| {
+                for entry in entries {
+                    std::io::copy(&mut entry?, &mut std::io::sink())?;
+                }
+                Ok(())
+            });
+            Error::from_read(result.unwrap_err())
+        }
+        // gzip 尾部的 CRC 与数据不符
+        let mut gz = fixture().gz();
+        let crc = gz.len() - 8;
+        gz[crc] ^= 1;
+        let e = read_all(&gz);
+        assert!(matches!(e, Error::Decompress(_)), "{e:?}");
+        let mut tar = fixture().tar();
+        tar[148] ^= 1;
+        assert!(matches!(read_all(&tar), Error::TarReadError(_)));
+    }
+
+    #[test]
+    fn each_magic_is_detected() {
+        for (bytes, format) in [
+            (&[0x1f, 0x8b, 0x08][..], ArchiveFormat::Gzip),
+            (&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00], ArchiveFormat::Xz),
+            (&[0x28, 0xb5, 0x2f, 0xfd, 0x00], ArchiveFormat::Zstd),
+        ] {
+            assert_eq!(ArchiveFormat::detect(bytes).unwrap(), format);
+        }
+        assert_eq!(
+            ArchiveFormat::detect(&fixture().gz()).unwrap(),
+            ArchiveFormat::Gzip
+        );
+        assert_eq!(
+            ArchiveFormat::detect(&fixture().tar()).unwrap(),
+            ArchiveFormat::Tar
+        );
+    }
+
+    #[test]
+    fn partial_magic_isnt_detected() {
+        // xz 的魔数只有前 4 字节时不能误判
+        for bytes in [&[0x1f][..], &[0xfd, 0x37, 0x7a, 0x58], &[0x28, 0xb5, 0x2f]] {
+            assert!(matches!(
+                ArchiveFormat::detect(bytes),
+                Err(Error::UnknownArchiveFormat(_))
+            ));
+        }
+        // ustar 魔数必须位于偏移 257
+        let mut shifted = vec![0; 512];
+        shifted[256..261].copy_from_slice(USTAR_MAGIC);
+        assert!(ArchiveFormat::detect(&shifted).is_err());
+    }
+
+    #[test]
+    fn garbage_is_reported_with_its_first_bytes() {
+        let err = ArchiveFormat::detect(b"PK\x03\x04garbage!").unwrap_err();
+        assert!(matches!(&err, Error::UnknownArchiveFormat(v) if v == "50 4b 03 04 67 61 72 62"));
+        assert!(err.to_string().contains("50 4b 03 04"), "{}", err);
+        assert!(matches!(
+            ArchiveFormat::detect(&[]),
+            Err(Error::UnknownArchiveFormat(v)) if v.is_empty()
+        ));
+        assert!(tar_reader(b"garbage").is_err());
+    }
+
+    #[test]
+    fn gzip_and_plain_tars_are_read_alike() {
+        assert_eq!(entry_paths(&fixture().gz()), ["a.btf"]);
+        assert_eq!(entry_paths(&fixture().tar()), ["a.btf"]);
+    }
+
+    #[test]
+    fn concatenated_archives_are_read_whole() {
+        let first = fixture();
+        let second = FixtureArchive::new().file("b.btf", minimal_valid_btf());
+        // 与 cat a.tar.gz b.tar.gz 一样拼接，再像 objcopy 那样补零对齐
+        let mut gz = [first.gz(), second.gz()].concat();
+        gz.resize(gz.len().next_multiple_of(4096), 0);
+        assert_eq!(entry_paths(&gz), ["a.btf", "b.btf"]);
+        // 未压缩的 tar 拼接时，中间的结束标记同样被跳过
+        let tar = [first.tar(), second.tar()].concat();
+        assert_eq!(entry_paths(&tar), ["a.btf", "b.btf"]);
+        // 解压后的 tar 是拼接而成的也一样
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(&tar).unwrap();
+        assert_eq!(entry_paths(&encoder.finish().unwrap()), ["a.btf", "b.btf"]);
+    }
+
+    #[test]
+    fn zeros_after_the_last_member_are_not_an_error() {
+        let mut gz = fixture().gz();
+        gz.extend_from_slice(&[0; 1000]);
+        let mut tar = Vec::new();
+        tar_reader(&gz).unwrap().read_to_end(&mut tar).unwrap();
+        assert_eq!(tar, fixture().tar());
+        // 零以外的尾部数据不是 gzip 成员，照常报错
+        let mut gz = fixture().gz();
+        gz.extend_from_slice(b"garbage");
+        assert!(tar_reader(&gz)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+
+    /// A gzip of `size` zeros, a few kilobytes for megabytes
+    fn bomb(size: usize) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        encoder.write_all(&vec![0; size]).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn reading_up_to_the_limit_is_fine() {
+        let mut read = Vec::new();
+        LimitedReader::new(&[1u8; 100][..], 100)
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read.len(), 100);
+        let err = LimitedReader::new(&[1u8; 101][..], 100)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+        assert!(err.to_string().contains("100 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn small_blobs_decompressing_to_much_more_fail() {
+        let bomb = bomb(16 << 20);
+        assert!(bomb.len() < 64 << 10, "{}", bomb.len());
+        let err = tar_reader_with_limit(&bomb, 1 << 20)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+        // 默认上限足够大
+        let mut read = Vec::new();
+        tar_reader(&bomb).unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read.len(), 16 << 20);
+        // 未压缩的 tar 不受限制
+        let tar = fixture().tar();
+        let mut read = Vec::new();
+        tar_reader_with_limit(&tar, 1)
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, tar);
+    }
+
+    #[test]
+    fn archive_of_exactly_the_limit_is_read() {
+        let gz = fixture().gz();
+        let size = fixture().tar().len() as u64;
+        let mut archive = tar_archive(tar_reader_with_limit(&gz, size).unwrap());
+        assert_eq!(tar_entries(&mut archive).unwrap().count(), 1);
+        let mut read = Vec::new();
+        let err = tar_reader_with_limit(&gz, size - 1)
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    }
+
+    #[test]
+    fn gzip_magic_with_a_bad_header_fails_early() {
+        // 魔数正确，但压缩方法不是 deflate
+        let mut gz = fixture().gz();
+        gz[2] = 0;
+        assert!(matches!(tar_reader(&gz), Err(Error::InvalidGzipHeader)));
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_without_the_feature_is_unsupported() {
+        assert!(matches!(
+            tar_reader(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Err(Error::UnsupportedCompression(ArchiveFormat::Zstd))
+        ));
+    }
+
+    #[cfg(not(feature = "xz"))]
+    #[test]
+    fn xz_without_the_feature_is_unsupported() {
+        assert!(matches!(
+            tar_reader(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]),
+            Err(Error::UnsupportedCompression(ArchiveFormat::Xz))
+        ));
+    }
+}
@@ -0,0 +1,133 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Containers share the kernel of the host, so `uname` reports the host's kernel
+//! release even inside them, while `/sys/kernel/btf/vmlinux` is only visible if
+//! `/sys` was mounted into the container.
+use std::{fmt::Display, path::Path};
+
+/// Container runtime the current process appears to run in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+    Kubernetes,
+    Containerd,
+    Lxc,
+    /// Announced through the `container` environment variable, e.g. by systemd-nspawn
+    Other(String),
+}
+
+impl Display for ContainerRuntime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerRuntime::Docker => write!(f, "docker"),
+            ContainerRuntime::Podman => write!(f, "podman"),
+            ContainerRuntime::Kubernetes => write!(f, "kubernetes"),
+            ContainerRuntime::Containerd => write!(f, "containerd"),
+            ContainerRuntime::Lxc => write!(f, "lxc"),
+            ContainerRuntime::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Detect whether the current process runs inside a container
+///
+/// This is a best-effort guess based on the marker files and cgroup names the common
+/// runtimes leave behind
+pub fn detect_container() -> Option<ContainerRuntime> {
+    detect_container_with_root(Path::new("/"))
+}
+
+/// Same as [`detect_container`], with the marker files and the cgroups of pid 1 looked for under `root`
+pub fn detect_container_with_root(root: &Path) -> Option<ContainerRuntime> {
+    if root.join(".dockerenv").exists() {
+        return Some(ContainerRuntime::Docker);
+    }
+    if root.join("run/.containerenv").exists() {
+        return Some(ContainerRuntime::Podman);
+    }
+    if let Ok(cgroup) = std::fs::read_to_string(root.join("proc/1/cgroup")) {
+        // kubepods 需要在 docker/containerd 之前判断，因为 k8s 的 cgroup 路径中同时包含容器运行时的名字
+        for (pattern, runtime) in [
+            ("kubepods", ContainerRuntime::Kubernetes),
+            ("docker", ContainerRuntime::Docker),
+            ("containerd", ContainerRuntime::Containerd),
+            ("libpod", ContainerRuntime::Podman),
+            ("lxc", ContainerRuntime::Lxc),
+        ] {
+            if cgroup.contains(pattern) {
+                return Some(runtime);
+            }
+        }
+    }
+    std::env::var("container")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .map(ContainerRuntime::Other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A root holding `files`, as paths relative to it and contents
+    fn root_with(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn marker_files_tell_the_runtime() {
+        let root = root_with(&[(".dockerenv", "")]);
+        assert_eq!(
+            detect_container_with_root(root.path()),
+            Some(ContainerRuntime::Docker)
+        );
+        let root = root_with(&[("run/.containerenv", "engine=\"podman-4.3.1\"\n")]);
+        assert_eq!(
+            detect_container_with_root(root.path()),
+            Some(ContainerRuntime::Podman)
+        );
+    }
+
+    #[test]
+    fn kubernetes_is_told_before_the_runtime_in_its_cgroups() {
+        for (cgroup, runtime) in [
+            (
+                "0::/kubepods/besteffort/pod1234/docker-abcd.scope\n",
+                ContainerRuntime::Kubernetes,
+            ),
+            ("12:pids:/docker/0123456789ab\n", ContainerRuntime::Docker),
+            (
+                "0::/system.slice/containerd.service\n",
+                ContainerRuntime::Containerd,
+            ),
+            (
+                "0::/machine.slice/libpod-0123.scope\n",
+                ContainerRuntime::Podman,
+            ),
+            ("0::/lxc.payload.web\n", ContainerRuntime::Lxc),
+        ] {
+            let root = root_with(&[("proc/1/cgroup", cgroup)]);
+            assert_eq!(detect_container_with_root(root.path()), Some(runtime));
+        }
+    }
+
+    #[test]
+    fn hosts_are_only_told_apart_by_the_environment() {
+        let root = root_with(&[("proc/1/cgroup", "0::/init.scope\n")]);
+        let announced = std::env::var("container")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(ContainerRuntime::Other);
+        assert_eq!(detect_container_with_root(root.path()), announced);
+    }
+}
@@ -0,0 +1,227 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Many kernels share byte-identical BTFs, so a directory caching the BTFs of many
+//! kernels can save space by hardlinking identical files together.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+/// Outcome of [`deduplicate_dir`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DedupReport {
+    /// Number of `.btf` files looked at
+    pub scanned: usize,
+    /// Files replaced by a hardlink to an identical file
+    pub linked: Vec<PathBuf>,
+    /// Bytes no longer stored twice
+    pub saved_bytes: u64,
+}
+
+/// 64-bit FNV-1a, only used to group candidates before comparing them byte by byte
+fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, v| {
+        (hash ^ *v as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| Error::FileReadError(path.display().to_string(), e))
+}
+
+fn btf_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| Error::FileReadError(dir.display().to_string(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::FileReadError(dir.display().to_string(), e))?;
+        let file_type = entry
+            .file_type()
+            .map_err(|e| Error::FileReadError(entry.path().display().to_string(), e))?;
+        if file_type.is_dir() {
+            btf_files(&entry.path(), files)?;
+        } else if file_type.is_file() && entry.path().extension() == Some("btf".as_ref()) {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Replace `dest` with a hardlink to `source`, atomically
+///
+/// Returns `Ok(false)` if hardlinks aren't supported between the two (e.g. they are on
+/// different filesystems), in which case `dest` is left as the copy it already is
+fn replace_with_link(source: &Path, dest: &Path) -> Result<bool> {
+    let mut temp = dest.as_os_str().to_owned();
+    temp.push(".link");
+    let temp = PathBuf::from(temp);
+    let _ = std::fs::remove_file(&temp);
+    if std::fs::hard_link(source, &temp).is_err() {
+        return Ok(false);
+    }
+    std::fs::rename(&temp, dest).map_err(|e| {
+        let _ = std::fs::remove_file(&temp);
+        Error::FileWriteError(dest.display().to_string(), e)
+    })?;
+    Ok(true)
+}
+
+/// Hardlink together every set of byte-identical `.btf` files under `dir`
+pub fn deduplicate_dir(dir: impl AsRef<Path>) -> Result<DedupReport> {
+    let mut files = vec![];
+    btf_files(dir.as_ref(), &mut files)?;
+    files.sort();
+    let mut report = DedupReport {
+        scanned: files.len(),
+        ..Default::default()
+    };
+    // (大小, 哈希) 相同的文件作为候选，再逐字节比较确认内容一致
+    let mut seen: HashMap<(u64, u64), Vec<PathBuf>> = HashMap::new();
+    for file in files {
+        let bytes = read(&file)?;
+        let candidates = seen
+            .entry((bytes.len() as u64, content_hash(&bytes)))
+            .or_default();
+        let mut original = None;
+        for candidate in candidates.iter() {
+            if same_file(candidate, &file) {
+                original = None;
+                break;
+            }
+            if read(candidate)? == bytes {
+                original = Some(candidate.clone());
+                break;
+            }
+        }
+        match original {
+            Some(original) => {
+                if replace_with_link(&original, &file)? {
+                    report.saved_bytes += bytes.len() as u64;
+                    report.linked.push(file);
+                }
+            }
+            None => candidates.push(file),
+        }
+    }
+    Ok(report)
+}
+
+/// Directory under the one given to [`store_deduplicated`] holding a hardlink of each
+/// stored btf, named after its content: hash and size, e.g. `8c7a...-1624`
+///
+/// Its files have no `.btf` extension, so [`deduplicate_dir`] doesn't count them.
+pub const BY_HASH_DIR: &str = ".by-hash";
+
+/// Name of the hardlink of `bytes` under [`BY_HASH_DIR`]
+fn hash_name(bytes: &[u8]) -> String {
+    format!("{:016x}-{}", content_hash(bytes), bytes.len())
+}
+
+/// Write `bytes` to `dest`, hardlinking it to an identical `.btf` file under `dir` if there is one
+///
+/// The identical file is looked up by name under [`BY_HASH_DIR`], and compared byte by
+/// byte, so storing doesn't read the other files of `dir`; a new btf is linked there in
+/// turn. Falls back to writing a plain copy if no identical file exists or linking fails.
+/// Returns whether `dest` ended up as a hardlink.
+pub fn store_deduplicated(
+    dir: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    bytes: &[u8],
+) -> Result<bool> {
+    let dest = dest.as_ref();
+    let by_hash = dir.as_ref().join(BY_HASH_DIR);
+    let known = by_hash.join(hash_name(bytes));
+    // 哈希只用于命名，链接前仍需逐字节比较，以防哈希冲突
+    if known.as_path() != dest
+        && read(&known).is_ok_and(|v| v == bytes)
+        && replace_with_link(&known, dest)?
+    {
+        return Ok(true);
+    }
+    std::fs::write(dest, bytes)
+        .map_err(|e| Error::FileWriteError(dest.display().to_string(), e))?;
+    // 索引只是加速手段，无法建立时（如只读目录、不支持硬链接）不影响写入本身
+    if std::fs::create_dir_all(&by_hash).is_ok() && !known.exists() {
+        let _ = replace_with_link(dest, &known);
+    }
+    Ok(false)
+}
+
+/// Whether the two paths are already hardlinks of each other
+#[cfg(target_os = "linux")]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Whether the two paths are already hardlinks of each other, never known without inodes
+#[cfg(not(target_os = "linux"))]
+fn same_file(_: &Path, _: &Path) -> bool {
+    false
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::*;
+
+    fn ino(path: &Path) -> u64 {
+        std::fs::metadata(path).unwrap().ino()
+    }
+
+    #[test]
+    fn identical_btfs_are_linked_through_the_hash_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.btf"), dir.path().join("b.btf"));
+        assert!(!store_deduplicated(dir.path(), &a, b"btf").unwrap());
+        assert!(store_deduplicated(dir.path(), &b, b"btf").unwrap());
+        assert_eq!(ino(&a), ino(&b));
+        assert_eq!(
+            ino(&a),
+            ino(&dir.path().join(BY_HASH_DIR).join(hash_name(b"btf")))
+        );
+        // 索引中的链接没有 .btf 扩展名，整理目录时不计入
+        assert_eq!(deduplicate_dir(dir.path()).unwrap().scanned, 2);
+    }
+
+    #[test]
+    fn different_btfs_are_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.btf"), dir.path().join("b.btf"));
+        assert!(!store_deduplicated(dir.path(), &a, b"one").unwrap());
+        assert!(!store_deduplicated(dir.path(), &b, b"two").unwrap());
+        assert_ne!(ino(&a), ino(&b));
+        assert_eq!(std::fs::read(&b).unwrap(), b"two");
+    }
+
+    #[test]
+    fn files_missing_from_the_hash_dir_arent_looked_for() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("unindexed.btf"), b"btf").unwrap();
+        assert!(!store_deduplicated(dir.path(), dir.path().join("new.btf"), b"btf").unwrap());
+    }
+
+    #[test]
+    fn hash_collision_falls_back_to_a_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let by_hash = dir.path().join(BY_HASH_DIR);
+        std::fs::create_dir(&by_hash).unwrap();
+        std::fs::write(by_hash.join(hash_name(b"btf")), b"BTF").unwrap();
+        let dest = dir.path().join("a.btf");
+        assert!(!store_deduplicated(dir.path(), &dest, b"btf").unwrap());
+        assert_eq!(std::fs::read(&dest).unwrap(), b"btf");
+        assert_eq!(
+            std::fs::read(by_hash.join(hash_name(b"btf"))).unwrap(),
+            b"BTF"
+        );
+    }
+}
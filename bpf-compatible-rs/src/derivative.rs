@@ -0,0 +1,156 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Derivative distros (Linux Mint, Pop!_OS, elementary, ...) run the kernels of the distro
+//! they derive from, but have no directory of their own in btfhub-archive. Their
+//! os-release names the upstream in `ID_LIKE`, so their btf is looked up there.
+
+use crate::distro;
+
+/// Distros with a directory in btfhub-archive
+pub const BTFHUB_DISTROS: &[&str] = &[
+    "amzn",
+    "centos",
+    "debian",
+    "fedora",
+    "ol",
+    "opensuse-leap",
+    "rhel",
+    "sles",
+    "ubuntu",
+];
+
+/// (derivative id, derivative major version, upstream id, upstream version) for derivatives
+/// whose os-release doesn't name the upstream release, e.g. by `UBUNTU_CODENAME`
+const UPSTREAM_VERSIONS: &[(&str, &str, &str, &str)] = &[
+    ("linuxmint", "19", "ubuntu", "18.04"),
+    ("linuxmint", "20", "ubuntu", "20.04"),
+    ("linuxmint", "21", "ubuntu", "22.04"),
+    ("linuxmint", "22", "ubuntu", "24.04"),
+    ("elementary", "5", "ubuntu", "18.04"),
+    ("elementary", "6", "ubuntu", "20.04"),
+    ("elementary", "7", "ubuntu", "22.04"),
+];
+
+/// Whether btfhub-archive has a directory for the distro
+pub fn is_btfhub_distro(id: &str) -> bool {
+    BTFHUB_DISTROS.contains(&id)
+}
+
+/// The distro to look up the btf of `id` under: `id` itself if btfhub covers it, it runs
+/// kernels of its own (see [`distro::has_own_kernels`]) or it's of the Enterprise Linux
+/// family (see [`distro::el_directories`]), else the first distro of `id_like` (the space
+/// separated `ID_LIKE` of os-release) that btfhub covers
+///
+/// `id` is returned if neither is covered.
+pub fn btfhub_distro<'a>(id: &'a str, id_like: &'a str) -> &'a str {
+    if is_btfhub_distro(id) || distro::has_own_kernels(id) || distro::is_el(id) {
+        return id;
+    }
+    id_like
+        .split_whitespace()
+        .find(|v| is_btfhub_distro(v))
+        .unwrap_or(id)
+}
+
+/// Version of `upstream` that release `version_id` of the derivative `id` is based on,
+/// e.g. `22.04` of `ubuntu` for `21.1` of `linuxmint`
+pub fn upstream_version(id: &str, version_id: &str, upstream: &str) -> Option<&'static str> {
+    let major = version_id.split('.').next()?;
+    UPSTREAM_VERSIONS
+        .iter()
+        .find(|(i, v, u, _)| *i == id && *v == major && *u == upstream)
+        .map(|(_, _, _, v)| *v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SystemInfo;
+
+    /// The distro and version the btf of the os-release `fixture` is looked up under
+    fn looked_up(fixture: &str) -> (String, String) {
+        let info = SystemInfo::from_os_release(
+            fixture,
+            "x86_64".into(),
+            "5.15.0-91-generic".into(),
+            String::new(),
+        )
+        .unwrap();
+        (info.distro_id, info.version_id)
+    }
+
+    #[test]
+    fn derivatives_are_looked_up_under_their_upstream() {
+        for (fixture, distro, version) in [
+            (
+                "NAME=\"Linux Mint\"\nVERSION=\"21.2 (Victoria)\"\nID=linuxmint\n\
+                 ID_LIKE=\"ubuntu debian\"\nVERSION_ID=\"21.2\"\nVERSION_CODENAME=victoria\n\
+                 UBUNTU_CODENAME=jammy\n",
+                "ubuntu",
+                "22.04",
+            ),
+            // 没有 UBUNTU_CODENAME 时按版本对照表
+            (
+                "ID=linuxmint\nID_LIKE=ubuntu\nVERSION_ID=\"20.3\"\n",
+                "ubuntu",
+                "20.04",
+            ),
+            (
+                "NAME=\"Pop!_OS\"\nID=pop\nID_LIKE=\"ubuntu debian\"\nVERSION_ID=\"22.04\"\n\
+                 VERSION_CODENAME=jammy\nUBUNTU_CODENAME=jammy\n",
+                "ubuntu",
+                "22.04",
+            ),
+            (
+                "ID=elementary\nID_LIKE=ubuntu\nVERSION_ID=\"7.1\"\n",
+                "ubuntu",
+                "22.04",
+            ),
+        ] {
+            assert_eq!(looked_up(fixture), (distro.into(), version.into()));
+        }
+    }
+
+    #[test]
+    fn enterprise_linux_rebuilds_keep_their_own_id() {
+        // Rocky 等有自己的目录优先级，之后再查 rhel 和 centos，而不是 ID_LIKE 中的第一个
+        for (fixture, distro, version) in [
+            (
+                "NAME=\"Rocky Linux\"\nID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\n\
+                 VERSION_ID=\"8.7\"\n",
+                "rocky",
+                "8.7",
+            ),
+            (
+                "NAME=\"AlmaLinux\"\nID=\"almalinux\"\nID_LIKE=\"rhel centos fedora\"\n\
+                 VERSION_ID=\"9.2\"\n",
+                "almalinux",
+                "9.2",
+            ),
+            (
+                "NAME=\"Oracle Linux Server\"\nID=\"ol\"\nID_LIKE=\"fedora\"\n\
+                 VERSION_ID=\"8.8\"\n",
+                "ol",
+                "8.8",
+            ),
+        ] {
+            assert_eq!(looked_up(fixture), (distro.into(), version.into()));
+        }
+    }
+
+    #[test]
+    fn first_covered_id_like_is_chosen() {
+        assert_eq!(btfhub_distro("pop", "nosuch ubuntu debian"), "ubuntu");
+        assert_eq!(btfhub_distro("custom", "nosuch other"), "custom");
+        assert_eq!(btfhub_distro("ubuntu", "debian"), "ubuntu");
+        assert_eq!(
+            upstream_version("linuxmint", "21.3", "ubuntu"),
+            Some("22.04")
+        );
+        assert_eq!(upstream_version("linuxmint", "21.3", "debian"), None);
+        assert_eq!(upstream_version("linuxmint", "99", "ubuntu"), None);
+    }
+}
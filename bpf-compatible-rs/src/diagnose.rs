@@ -0,0 +1,727 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Why a lookup provides no btf, for support tooling to collect when `ensure_core_btf`
+//! fails: the system as detected, the paths searched in the archive, the kernel's own and
+//! installed btfs, whether its config builds btf, the kernels the archive has nearby, and
+//! the fallbacks consulted.
+//!
+//! Diagnosing only reads: nothing is written, no temporary file is created, and the
+//! network is only reached if [`DiagnoseOptions::with_download_probe`](crate::diagnose::DiagnoseOptions::with_download_probe) asks for it.
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    arch::arch_directories,
+    archive::{BtfEntryInfo, BtfhubArchive, BTFHUB_ARCHIVE_DIR},
+    btf::check_btf_file,
+    cache::BtfCache,
+    compression::DEFAULT_MAX_DECOMPRESSED_SIZE,
+    generate_btf_archive_paths_for, join_archive_path,
+    kconfig::{kernel_btf_config_of, BTF_CONFIG_OPTION},
+    native::NativeBtfProbe,
+    release::KernelVersion,
+    SystemInfo, VMLINUX_BTF_PATH,
+};
+
+/// Environment variable naming a btf file to use instead of looking one up
+pub const BTF_PATH_ENV: &str = "BPF_COMPATIBLE_BTF_PATH";
+
+/// How many kernels of the archive [`Diagnosis::nearest`] lists at most
+pub const MAX_NEAREST: usize = 5;
+
+/// State of the kernel's own btf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NativeBtf {
+    /// The file exists and starts with the btf magic, so no btf is needed
+    Available,
+    /// The file doesn't exist
+    Missing,
+    /// The file exists but can't be used, e.g. because it isn't readable, with why
+    Unusable(String),
+    /// The file isn't relevant, e.g. because another system is looked up, with why
+    Ignored(String),
+}
+
+/// A fallback of the lookup, and what it holds for the system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackCheck {
+    /// `override`, `cache`, `download` or `pahole`
+    pub name: &'static str,
+    /// Whether the lookup is set to consult it, should the steps before fail, e.g. downloads only once allowed
+    pub consulted: bool,
+    /// What it holds, or why it isn't consulted
+    pub detail: String,
+}
+
+/// What [`diagnose`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    /// The system as detected, or why it couldn't be
+    pub system: std::result::Result<SystemInfo, String>,
+    /// Directory of the archive holding the btfs
+    pub prefix: PathBuf,
+    /// Paths looked up, relative to `prefix`, most preferred first, see [`generate_btf_archive_paths_for`]
+    pub searched_paths: Vec<String>,
+    /// State of the kernel's own btf
+    pub native_btf: NativeBtf,
+    /// Whether the kernel was built with `CONFIG_DEBUG_INFO_BTF`, `None` if its config
+    /// can't be read, see [`kernel_btf_config_of`]
+    pub btf_config: Option<bool>,
+    /// The btf of the kernel installed by the distro, see [`NativeBtfProbe::probe_with_config`]
+    pub installed_btf: Option<PathBuf>,
+    /// Number of files and links of the archive looked at
+    pub entries_scanned: usize,
+    /// Why the archive couldn't be read to the end, if it couldn't
+    pub archive_error: Option<String>,
+    /// Path of the entry holding the btf, as stored in the archive, if one of `searched_paths` is there
+    pub found: Option<PathBuf>,
+    /// Kernels of the archive under the same distro and arch, as
+    /// `<distro>/<version>/<arch>/<release>`, nearest first, see [`KernelVersion::distance`]
+    pub nearest: Vec<String>,
+    /// The fallbacks of the lookup, in the order they are consulted
+    pub fallbacks: Vec<FallbackCheck>,
+}
+
+impl Diagnosis {
+    /// Whether a btf would be provided: the kernel's own, an installed one, or the archive's
+    pub fn has_btf(&self) -> bool {
+        self.native_btf == NativeBtf::Available
+            || self.installed_btf.is_some()
+            || self.found.is_some()
+    }
+}
+
+/// Options of [`diagnose_with`], describing the lookup to explain
+#[derive(Debug, Clone)]
+pub struct DiagnoseOptions {
+    system: Option<SystemInfo>,
+    root: PathBuf,
+    prefix: PathBuf,
+    vmlinux_path: PathBuf,
+    native_probe: NativeBtfProbe,
+    max_decompressed_size: u64,
+    cache: Option<(BtfCache, String)>,
+    download_allowed: bool,
+    download_probe: bool,
+    #[cfg_attr(not(feature = "download"), allow(dead_code))]
+    download_url: Option<String>,
+    pahole_allowed: bool,
+    #[cfg(feature = "pahole")]
+    pahole: crate::pahole::PaholeOptions,
+}
+
+impl Default for DiagnoseOptions {
+    fn default() -> Self {
+        Self {
+            system: None,
+            root: PathBuf::from("/"),
+            prefix: PathBuf::from(BTFHUB_ARCHIVE_DIR),
+            vmlinux_path: PathBuf::from(VMLINUX_BTF_PATH),
+            native_probe: NativeBtfProbe::default(),
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            cache: None,
+            download_allowed: false,
+            download_probe: false,
+            download_url: None,
+            pahole_allowed: false,
+            #[cfg(feature = "pahole")]
+            pahole: Default::default(),
+        }
+    }
+}
+
+impl DiagnoseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Explain the lookup for `system` rather than the running one
+    pub fn with_system(mut self, system: SystemInfo) -> Self {
+        self.system = Some(system);
+        self
+    }
+
+    /// Detect the system relative to `root`, see [`SystemInfo::detect_with_root`]
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> Self {
+        self.root = root.as_ref().to_path_buf();
+        self
+    }
+
+    /// Look for the btfs under `prefix` instead of `btfhub-archive`
+    pub fn with_prefix(mut self, prefix: impl AsRef<Path>) -> Self {
+        self.prefix = prefix.as_ref().to_path_buf();
+        self
+    }
+
+    /// Check the kernel's own btf at `path` instead of [`VMLINUX_BTF_PATH`]
+    pub fn with_vmlinux_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.vmlinux_path = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Probe the installed btfs with `probe`
+    pub fn with_native_probe(mut self, probe: NativeBtfProbe) -> Self {
+        self.native_probe = probe;
+        self
+    }
+
+    /// Size the archive may decompress to, see [`BtfhubArchive::with_max_decompressed_size`]
+    pub fn with_max_decompressed_size(mut self, max_size: u64) -> Self {
+        self.max_decompressed_size = max_size;
+        self
+    }
+
+    /// Report whether `cache` holds the btf, under the key the lookup uses for the archive
+    pub fn with_cache(mut self, cache: BtfCache, archive_key: impl Into<String>) -> Self {
+        self.cache = Some((cache, archive_key.into()));
+        self
+    }
+
+    /// Whether the lookup would download a btf missing from the archive
+    pub fn with_download(mut self, allowed: bool) -> Self {
+        self.download_allowed = allowed;
+        self
+    }
+
+    /// Actually try the download, into memory, to tell whether it would succeed
+    ///
+    /// This is the only thing reaching the network; it needs the `download` feature.
+    pub fn with_download_probe(mut self, probe: bool) -> Self {
+        self.download_probe = probe;
+        self
+    }
+
+    /// Url template of the download, see `download::btfhub_url`
+    pub fn with_download_url(mut self, template: impl Into<String>) -> Self {
+        self.download_url = Some(template.into());
+        self
+    }
+
+    /// Whether the lookup would generate a btf missing from the archive with pahole
+    ///
+    /// pahole isn't run to generate it, only asked for its version.
+    pub fn with_pahole(mut self, allowed: bool) -> Self {
+        self.pahole_allowed = allowed;
+        self
+    }
+
+    /// Options of the generation with pahole, see [`crate::pahole::generate_btf`]
+    #[cfg(feature = "pahole")]
+    pub fn with_pahole_options(mut self, opts: crate::pahole::PaholeOptions) -> Self {
+        self.pahole = opts;
+        self
+    }
+}
+
+/// Explain the lookup of the btf of the running system in `archive`, with the defaults of [`DiagnoseOptions`]
+pub fn diagnose(archive: &[u8]) -> Diagnosis {
+    diagnose_with(archive, &DiagnoseOptions::default())
+}
+
+/// Explain the lookup of the btf in `archive` described by `opts`
+///
+/// Never fails: what can't be determined, like a system that can't be detected or an
+/// archive that can't be read, is recorded in the [`Diagnosis`].
+pub fn diagnose_with(archive: &[u8], opts: &DiagnoseOptions) -> Diagnosis {
+    let system = match &opts.system {
+        Some(v) => Ok(v.clone()),
+        None => SystemInfo::detect_with_root(&opts.root).map_err(|e| e.to_string()),
+    };
+    let searched_paths = system
+        .as_ref()
+        .map(generate_btf_archive_paths_for)
+        .unwrap_or_default();
+    let btf_config = system
+        .as_ref()
+        .ok()
+        .and_then(|v| kernel_btf_config_of(&v.kernel_release, &opts.root));
+    let mut diagnosis = Diagnosis {
+        native_btf: native_btf(opts),
+        btf_config,
+        installed_btf: system.as_ref().ok().and_then(|v| {
+            opts.native_probe
+                .probe_with_config(&v.kernel_release, btf_config)
+        }),
+        system,
+        prefix: opts.prefix.clone(),
+        searched_paths,
+        entries_scanned: 0,
+        archive_error: None,
+        found: None,
+        nearest: vec![],
+        fallbacks: vec![],
+    };
+    scan_archive(archive, opts, &mut diagnosis);
+    diagnosis.fallbacks = vec![
+        override_check(),
+        cache_check(opts, &diagnosis.system),
+        download_check(opts, &diagnosis.system),
+        pahole_check(opts, &diagnosis.system),
+    ];
+    diagnosis
+}
+
+fn native_btf(opts: &DiagnoseOptions) -> NativeBtf {
+    #[cfg(feature = "fake-system")]
+    if crate::fake::is_kernel_faked() {
+        return NativeBtf::Ignored("the kernel release is faked".into());
+    }
+    if let Some(system) = &opts.system {
+        if crate::current_kernel_release().is_ok_and(|v| v != system.kernel_release) {
+            return NativeBtf::Ignored(format!(
+                "{} isn't the running kernel",
+                system.kernel_release
+            ));
+        }
+    }
+    if !opts.vmlinux_path.exists() {
+        return NativeBtf::Missing;
+    }
+    match check_btf_file(&opts.vmlinux_path) {
+        Ok(()) => NativeBtf::Available,
+        Err(e) => NativeBtf::Unusable(e.to_string()),
+    }
+}
+
+/// Count the entries of the archive, and look for the searched paths and the kernels near the system
+fn scan_archive(archive: &[u8], opts: &DiagnoseOptions, diagnosis: &mut Diagnosis) {
+    let mut kernels = vec![];
+    let mut found: Option<(usize, PathBuf)> = None;
+    let entries = BtfhubArchive::new(archive)
+        .with_prefix(&opts.prefix)
+        .with_max_decompressed_size(opts.max_decompressed_size)
+        .entries();
+    for entry in entries {
+        let entry = match entry {
+            Ok(BtfEntryInfo::Btf(v)) => v,
+            Ok(BtfEntryInfo::Other(_)) => {
+                diagnosis.entries_scanned += 1;
+                continue;
+            }
+            Err(e) => {
+                diagnosis.archive_error = Some(e.to_string());
+                break;
+            }
+        };
+        diagnosis.entries_scanned += 1;
+        let kernel = entry.kernel();
+        let rank = diagnosis
+            .searched_paths
+            .iter()
+            .position(|v| *v == format!("{}.btf", kernel));
+        if let Some(rank) = rank.filter(|v| found.as_ref().is_none_or(|(best, _)| v < best)) {
+            found = Some((rank, entry.path.clone()));
+        }
+        if !kernels.contains(&kernel) {
+            kernels.push(kernel);
+        }
+    }
+    diagnosis.found = found.map(|(_, path)| path);
+    if let Ok(system) = &diagnosis.system {
+        diagnosis.nearest = nearest_kernels(system, &kernels);
+    }
+}
+
+/// The kernels among `kernels` under the distro and arch of `system`, nearest to its release first
+fn nearest_kernels(system: &SystemInfo, kernels: &[String]) -> Vec<String> {
+    let arches = arch_directories(&system.arch);
+    let release = KernelVersion::new(system.kernel_release.as_str());
+    let mut nearest = kernels
+        .iter()
+        .filter_map(|kernel| {
+            let mut parts = kernel.split('/');
+            let (distro, _, arch, kernel_release) =
+                (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+            if distro != system.distro_id || !arches.contains(&arch) {
+                return None;
+            }
+            // 不同系列的版本排在同一系列之后，按版本号由近及远
+            let version = KernelVersion::new(kernel_release);
+            let distance = version.distance(&release);
+            Some(((distance.is_none(), distance, version), kernel))
+        })
+        .collect::<Vec<_>>();
+    nearest.sort();
+    nearest
+        .into_iter()
+        .take(MAX_NEAREST)
+        .map(|(_, kernel)| kernel.clone())
+        .collect()
+}
+
+fn override_check() -> FallbackCheck {
+    let path = std::env::var_os(BTF_PATH_ENV).filter(|v| !v.is_empty());
+    let detail = match &path {
+        None => format!("{} isn't set", BTF_PATH_ENV),
+        Some(path) => match check_btf_file(path) {
+            Ok(()) => format!("{} is set to {}", BTF_PATH_ENV, Path::new(path).display()),
+            Err(e) => format!(
+                "{} is set to {}, which is unusable: {}",
+                BTF_PATH_ENV,
+                Path::new(path).display(),
+                e
+            ),
+        },
+    };
+    FallbackCheck {
+        name: "override",
+        consulted: path.is_some(),
+        detail,
+    }
+}
+
+fn cache_check(
+    opts: &DiagnoseOptions,
+    system: &std::result::Result<SystemInfo, String>,
+) -> FallbackCheck {
+    let (consulted, detail) = match (&opts.cache, system) {
+        (None, _) => (false, "not used".to_string()),
+        (Some(_), Err(_)) => (true, "unknown, as the system isn't".to_string()),
+        (Some((cache, key)), Ok(system)) => {
+            let archive_path = system.to_string();
+            let detail = match cache.lookup(key, &archive_path) {
+                Some(path) => format!("holds {}", path.display()),
+                None => format!(
+                    "no valid btf at {}",
+                    cache.entry_path(key, &archive_path).display()
+                ),
+            };
+            (true, detail)
+        }
+    };
+    FallbackCheck {
+        name: "cache",
+        consulted,
+        detail,
+    }
+}
+
+fn download_check(
+    opts: &DiagnoseOptions,
+    system: &std::result::Result<SystemInfo, String>,
+) -> FallbackCheck {
+    let detail = match system {
+        _ if !opts.download_allowed && !opts.download_probe => "not allowed".to_string(),
+        Err(_) => "unknown, as the system isn't".to_string(),
+        Ok(system) => download_detail(opts, system),
+    };
+    FallbackCheck {
+        name: "download",
+        consulted: opts.download_allowed,
+        detail,
+    }
+}
+
+#[cfg(feature = "download")]
+fn download_detail(opts: &DiagnoseOptions, system: &SystemInfo) -> String {
+    use crate::download::{btfhub_url, download_btf_with, DownloadConfig, DEFAULT_URL_TEMPLATE};
+    let template = opts.download_url.as_deref().unwrap_or(DEFAULT_URL_TEMPLATE);
+    let url = btfhub_url(template, system);
+    if !opts.download_probe {
+        return format!("from {}, not probed", url);
+    }
+    match download_btf_with(&url, &DownloadConfig::from_env()) {
+        Ok(btf) => format!("from {}, which has a btf of {} bytes", url, btf.len()),
+        Err(e) => format!("from {}, which failed: {}", url, e),
+    }
+}
+
+#[cfg(not(feature = "download"))]
+fn download_detail(_opts: &DiagnoseOptions, _system: &SystemInfo) -> String {
+    "unavailable, this build lacks the download feature".to_string()
+}
+
+fn pahole_check(
+    opts: &DiagnoseOptions,
+    system: &std::result::Result<SystemInfo, String>,
+) -> FallbackCheck {
+    let detail = match system {
+        _ if !opts.pahole_allowed => "not allowed".to_string(),
+        Err(_) => "unknown, as the system isn't".to_string(),
+        Ok(system) => pahole_detail(opts, system),
+    };
+    FallbackCheck {
+        name: "pahole",
+        consulted: opts.pahole_allowed,
+        detail,
+    }
+}
+
+#[cfg(feature = "pahole")]
+fn pahole_detail(opts: &DiagnoseOptions, system: &SystemInfo) -> String {
+    let vmlinux = match opts.pahole.find_vmlinux(&system.kernel_release) {
+        Ok(v) => v,
+        Err(e) => return e.to_string(),
+    };
+    let pahole = opts.pahole.pahole();
+    match std::process::Command::new(pahole).arg("--version").output() {
+        Ok(v) if v.status.success() => format!(
+            "from {} with pahole {}, not run",
+            vmlinux.display(),
+            String::from_utf8_lossy(&v.stdout).trim()
+        ),
+        Ok(v) => format!(
+            "from {}, but `{} --version` exited with {}",
+            vmlinux.display(),
+            pahole.display(),
+            v.status
+        ),
+        Err(e) => format!(
+            "from {}, but pahole `{}` can't be run: {}",
+            vmlinux.display(),
+            pahole.display(),
+            e
+        ),
+    }
+}
+
+#[cfg(not(feature = "pahole"))]
+fn pahole_detail(_opts: &DiagnoseOptions, _system: &SystemInfo) -> String {
+    "unavailable, this build lacks the pahole feature".to_string()
+}
+
+impl Display for NativeBtf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NativeBtf::Available => write!(f, "available"),
+            NativeBtf::Missing => write!(f, "missing"),
+            NativeBtf::Unusable(e) => write!(f, "unusable ({})", e),
+            NativeBtf::Ignored(e) => write!(f, "ignored ({})", e),
+        }
+    }
+}
+
+/// A report of a few lines, for humans and support tickets
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.system {
+            Ok(v) => writeln!(
+                f,
+                "system: distro {}, version {}{}, arch {}, kernel {}",
+                v.distro_id,
+                v.version_id,
+                Some(&v.version_codename)
+                    .filter(|v| !v.is_empty())
+                    .map(|v| format!(" ({})", v))
+                    .unwrap_or_default(),
+                v.arch,
+                v.kernel_release
+            )?,
+            Err(e) => writeln!(f, "system: not detected: {}", e)?,
+        }
+        writeln!(f, "native btf: {}", self.native_btf)?;
+        match (self.btf_config, &self.native_btf) {
+            // 内核带有 btf 却没有导出，多半是内核早于 5.4，或 sysfs 被隐藏
+            (Some(true), NativeBtf::Missing | NativeBtf::Unusable(_)) => writeln!(
+                f,
+                "kernel config: {}=y, but the btf isn't exported: the kernel predates 5.4, or sysfs is hidden",
+                BTF_CONFIG_OPTION
+            )?,
+            (Some(true), _) => writeln!(f, "kernel config: {}=y", BTF_CONFIG_OPTION)?,
+            (Some(false), _) => writeln!(f, "kernel config: {} not set", BTF_CONFIG_OPTION)?,
+            (None, _) => writeln!(f, "kernel config: unknown")?,
+        }
+        match &self.installed_btf {
+            Some(v) => writeln!(f, "installed btf: {}", v.display())?,
+            None => writeln!(f, "installed btf: none")?,
+        }
+        writeln!(f, "searched under {}/:", self.prefix.display())?;
+        for path in &self.searched_paths {
+            writeln!(f, "  {}", path)?;
+        }
+        write!(f, "archive: {} entries scanned", self.entries_scanned)?;
+        match (&self.found, &self.archive_error) {
+            (Some(v), _) => writeln!(f, ", found {}", v.display())?,
+            (None, Some(e)) => writeln!(f, ", then failed: {}", e)?,
+            (None, None) => writeln!(f, ", none of the searched paths")?,
+        }
+        if let Ok(system) = &self.system {
+            let arch = arch_directories(&system.arch)
+                .first()
+                .copied()
+                .unwrap_or(&system.arch)
+                .to_string();
+            let under = join_archive_path(&[&system.distro_id, "*", &arch]);
+            if self.nearest.is_empty() {
+                writeln!(f, "nearest under {}: none", under)?;
+            } else {
+                writeln!(f, "nearest under {}:", under)?;
+                for kernel in &self.nearest {
+                    writeln!(f, "  {}", kernel)?;
+                }
+            }
+        }
+        writeln!(f, "fallbacks:")?;
+        for fallback in &self.fallbacks {
+            writeln!(
+                f,
+                "  {}{}: {}",
+                fallback.name,
+                if fallback.consulted {
+                    ""
+                } else {
+                    " (not consulted)"
+                },
+                fallback.detail
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::fixture::{minimal_valid_btf, FixtureArchive};
+
+    fn ubuntu(kernel_release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: kernel_release.into(),
+            ..Default::default()
+        }
+    }
+
+    fn archive() -> Vec<u8> {
+        let mut fixture = FixtureArchive::new().file("btfhub-archive/README.md", b"".to_vec());
+        for (distro, version, arch, release) in [
+            ("ubuntu", "20.04", "x86_64", "5.4.0-140-generic"),
+            ("ubuntu", "20.04", "x86_64", "5.4.0-150-generic"),
+            ("ubuntu", "18.04", "x86_64", "5.4.0-144-generic"),
+            ("ubuntu", "20.04", "x86_64", "5.8.0-63-generic"),
+            ("ubuntu", "20.04", "arm64", "5.4.0-148-generic"),
+            ("centos", "8", "x86_64", "4.18.0-305.el8.x86_64"),
+        ] {
+            fixture = fixture.btf(distro, version, arch, release, minimal_valid_btf());
+        }
+        fixture.gz()
+    }
+
+    /// Options explaining the lookup of `system` under the empty root `root`
+    fn opts(root: &Path, system: SystemInfo) -> DiagnoseOptions {
+        DiagnoseOptions::new()
+            .with_system(system)
+            .with_root(root)
+            .with_vmlinux_path(root.join("sys/kernel/btf/vmlinux"))
+    }
+
+    #[test]
+    fn miss_is_explained_with_the_nearest_kernels() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = root.path().join("cache");
+        let opts =
+            opts(root.path(), ubuntu("5.4.0-148-generic")).with_cache(BtfCache::new(&cache), "key");
+        let diagnosis = diagnose_with(&archive(), &opts);
+        assert_eq!(diagnosis.system, Ok(ubuntu("5.4.0-148-generic")));
+        assert_eq!(
+            diagnosis.searched_paths[0],
+            "ubuntu/20.04/x86_64/5.4.0-148-generic.btf"
+        );
+        assert_eq!(diagnosis.entries_scanned, 7);
+        assert_eq!(diagnosis.found, None);
+        assert_eq!(diagnosis.archive_error, None);
+        // 同一发行版和架构的内核，同一系列的由近及远，之后才是其他系列
+        assert_eq!(
+            diagnosis.nearest,
+            [
+                "ubuntu/20.04/x86_64/5.4.0-150-generic",
+                "ubuntu/18.04/x86_64/5.4.0-144-generic",
+                "ubuntu/20.04/x86_64/5.4.0-140-generic",
+                "ubuntu/20.04/x86_64/5.8.0-63-generic",
+            ]
+        );
+        assert!(matches!(diagnosis.native_btf, NativeBtf::Ignored(_)));
+        assert!(!diagnosis.has_btf());
+        assert_eq!(
+            diagnosis
+                .fallbacks
+                .iter()
+                .map(|v| (v.name, v.consulted))
+                .collect::<Vec<_>>(),
+            [
+                ("override", false),
+                ("cache", true),
+                ("download", false),
+                ("pahole", false),
+            ]
+        );
+        assert!(diagnosis.fallbacks[1].detail.starts_with("no valid btf at"));
+        let report = diagnosis.to_string();
+        for line in [
+            "system: distro ubuntu, version 20.04, arch x86_64, kernel 5.4.0-148-generic\n",
+            "searched under btfhub-archive/:\n  ubuntu/20.04/x86_64/5.4.0-148-generic.btf\n",
+            "archive: 7 entries scanned, none of the searched paths\n",
+            "nearest under ubuntu/*/x86_64:\n  ubuntu/20.04/x86_64/5.4.0-150-generic\n",
+            "  download (not consulted): not allowed\n",
+        ] {
+            assert!(report.contains(line), "{line}\n{report}");
+        }
+        // 诊断不写入任何文件
+        assert!(!cache.exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn hit_is_reported_with_the_entry() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("boot")).unwrap();
+        fs::write(
+            root.path().join("boot/config-5.4.0-150-generic"),
+            "CONFIG_DEBUG_INFO_BTF=y\n",
+        )
+        .unwrap();
+        let diagnosis = diagnose_with(&archive(), &opts(root.path(), ubuntu("5.4.0-150-generic")));
+        assert_eq!(
+            diagnosis.found.as_deref(),
+            Some(Path::new(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-150-generic.btf"
+            ))
+        );
+        assert!(diagnosis.has_btf());
+        assert_eq!(diagnosis.btf_config, Some(true));
+        let report = diagnosis.to_string();
+        assert!(
+            report.contains(", found btfhub-archive/ubuntu/20.04/x86_64/5.4.0-150-generic.btf\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("kernel config: CONFIG_DEBUG_INFO_BTF=y"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn unknowns_are_recorded_rather_than_failing() {
+        let root = tempfile::tempdir().unwrap();
+        // 系统无法识别时，仍然统计归档
+        let diagnosis = diagnose_with(&archive(), &DiagnoseOptions::new().with_root(root.path()));
+        assert!(diagnosis.system.is_err());
+        assert!(diagnosis.searched_paths.is_empty());
+        assert_eq!(diagnosis.entries_scanned, 7);
+        assert!(diagnosis.nearest.is_empty());
+        let report = diagnosis.to_string();
+        assert!(report.contains("system: not detected: "), "{report}");
+        assert!(
+            report.contains("cache (not consulted): not used"),
+            "{report}"
+        );
+
+        let diagnosis = diagnose_with(
+            b"not an archive",
+            &opts(root.path(), ubuntu("5.4.0-150-generic")),
+        );
+        assert_eq!(diagnosis.entries_scanned, 0);
+        assert!(diagnosis.archive_error.is_some());
+        assert!(!diagnosis.has_btf());
+        assert!(diagnosis.to_string().contains(", then failed: "));
+    }
+}
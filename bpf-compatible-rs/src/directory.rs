@@ -0,0 +1,251 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Lookups in a btfhub-archive kept unpacked on disk, e.g. a clone of the btfhub-archive
+//! repository synced to `/var/lib/btfhub-archive`, without packing it into a tar first.
+use std::path::{Path, PathBuf};
+
+use crate::{
+    archive::{parse_btf_path, BtfEntry, BTF_ENTRY_SUFFIXES},
+    generate_btf_archive_paths_for,
+    sanitize::sanitize_entry_path,
+    tarball::decode_btf,
+    Error, Result, SystemInfo,
+};
+
+/// A directory laid out as `<distro>/<version>/<arch>/<release>.btf`, like the root of btfhub-archive
+///
+/// Besides plain btfs, `.btf.gz`, `.btf.tar.xz` and `.btf.tar.gz` files are recognized, as
+/// in archives; the btfhub-archive repository itself ships `.btf.tar.xz` ones.
+#[derive(Debug, Clone)]
+pub struct BtfDirectory {
+    root: PathBuf,
+}
+
+impl BtfDirectory {
+    /// The directory at `root`, which isn't read until a lookup
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The file holding the btf of `info`
+    ///
+    /// The paths of [`generate_btf_archive_paths_for`] are tried in turn, each in every
+    /// form, plain first. The `path` of the entry is the file on disk, under the root.
+    /// Fails with [`Error::FileReadError`] if the root can't be read, and
+    /// [`Error::EntryNotFound`] if there is no btf for `info`.
+    pub fn lookup(&self, info: &SystemInfo) -> Result<BtfEntry> {
+        std::fs::read_dir(&self.root)
+            .map_err(|e| Error::FileReadError(self.root.display().to_string(), e))?;
+        for relative in generate_btf_archive_paths_for(info) {
+            let Some(stem) = relative.strip_suffix(".btf") else {
+                continue;
+            };
+            for (suffix, _) in BTF_ENTRY_SUFFIXES {
+                let relative = format!("{}{}", stem, suffix);
+                let Some((distro, version, arch, kernel_release, encoding)) =
+                    parse_btf_path(Path::new(&relative), Path::new(""))
+                else {
+                    continue;
+                };
+                // 路径的各部分来自 os-release，不能指向目录之外
+                let Ok(relative) = sanitize_entry_path(Path::new(&relative)) else {
+                    continue;
+                };
+                let path = self.root.join(relative);
+                // 跟随符号链接，btfhub-archive 中共用 btf 的内核以链接的形式存在
+                let Ok(metadata) = std::fs::metadata(&path) else {
+                    continue;
+                };
+                if !metadata.is_file() {
+                    continue;
+                }
+                log_at!(Info, "Selected the btf {}", path.display());
+                return Ok(BtfEntry {
+                    distro,
+                    version,
+                    arch,
+                    kernel_release,
+                    is_link: path.symlink_metadata().is_ok_and(|v| v.is_symlink()),
+                    path,
+                    size: metadata.len(),
+                    encoding,
+                    byte_swapped: false,
+                });
+            }
+        }
+        Err(Error::EntryNotFound {
+            expected: crate::generate_btf_archive_path_for(info),
+        })
+    }
+
+    /// The btf of `entry`, decompressed according to its encoding and validated
+    pub fn extract(&self, entry: &BtfEntry) -> Result<Vec<u8>> {
+        let contents = std::fs::read(&entry.path)
+            .map_err(|e| Error::FileReadError(entry.path.display().to_string(), e))?;
+        decode_btf(&contents, entry.encoding, &entry.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::{archive::BtfEncoding, fixture::btf_of_arch};
+
+    fn ubuntu(kernel_release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: kernel_release.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A directory holding `files`, given by their path relative to it
+    fn tree(files: &[(&str, Vec<u8>)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A tarball like those of btfhub, holding the btf as `<release>.btf`
+    fn btfhub_tarball(kernel_release: &str, btf: Vec<u8>) -> Vec<u8> {
+        crate::fixture::FixtureArchive::new()
+            .file(&format!("{}.btf", kernel_release), btf)
+            .tar()
+    }
+
+    #[test]
+    fn plain_btfs_are_found_in_place() {
+        let dir = tree(&[(
+            "ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            btf_of_arch(8, "plain"),
+        )]);
+        let directory = BtfDirectory::new(dir.path());
+        let entry = directory.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(
+            entry.path,
+            dir.path().join("ubuntu/20.04/x86_64/5.4.0-40-generic.btf")
+        );
+        assert_eq!(entry.encoding, BtfEncoding::Plain);
+        assert_eq!(entry.kernel_release, "5.4.0-40-generic");
+        assert!(!entry.is_link);
+        assert_eq!(directory.extract(&entry).unwrap(), btf_of_arch(8, "plain"));
+    }
+
+    #[test]
+    fn compressed_btfs_are_decoded() {
+        let dir = tree(&[
+            (
+                "ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz",
+                gzip(&btf_of_arch(8, "gz")),
+            ),
+            (
+                "ubuntu/20.04/x86_64/5.4.0-42-generic.btf.tar.gz",
+                gzip(&btfhub_tarball("5.4.0-42-generic", btf_of_arch(8, "tgz"))),
+            ),
+        ]);
+        let directory = BtfDirectory::new(dir.path());
+        let entry = directory.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(entry.encoding, BtfEncoding::Gzipped);
+        assert_eq!(directory.extract(&entry).unwrap(), btf_of_arch(8, "gz"));
+        let entry = directory.lookup(&ubuntu("5.4.0-42-generic")).unwrap();
+        assert_eq!(entry.encoding, BtfEncoding::Tarball);
+        assert_eq!(directory.extract(&entry).unwrap(), btf_of_arch(8, "tgz"));
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn btfhub_tarballs_are_decoded() {
+        let tarball = btfhub_tarball("5.4.0-40-generic", btf_of_arch(8, "xz"));
+        let dir = tree(&[(
+            "ubuntu/20.04/x86_64/5.4.0-40-generic.btf.tar.xz",
+            crate::xz::tests::compress(&tarball),
+        )]);
+        let directory = BtfDirectory::new(dir.path());
+        let entry = directory.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(entry.encoding, BtfEncoding::Tarball);
+        assert_eq!(directory.extract(&entry).unwrap(), btf_of_arch(8, "xz"));
+    }
+
+    #[test]
+    fn plain_btfs_come_first_and_links_are_followed() {
+        let dir = tree(&[
+            (
+                "ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz",
+                gzip(&btf_of_arch(8, "gz")),
+            ),
+            (
+                "ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                btf_of_arch(8, "plain"),
+            ),
+        ]);
+        let directory = BtfDirectory::new(dir.path());
+        let entry = directory.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(entry.encoding, BtfEncoding::Plain);
+
+        // btfhub-archive 中共用 btf 的内核以链接的形式存在
+        std::os::unix::fs::symlink(
+            "5.4.0-40-generic.btf",
+            dir.path().join("ubuntu/20.04/x86_64/5.4.0-41-generic.btf"),
+        )
+        .unwrap();
+        let entry = directory.lookup(&ubuntu("5.4.0-41-generic")).unwrap();
+        assert!(entry.is_link);
+        assert_eq!(directory.extract(&entry).unwrap(), btf_of_arch(8, "plain"));
+    }
+
+    #[test]
+    fn missing_btfs_and_roots_fail() {
+        let dir = tree(&[
+            ("ubuntu/20.04/x86_64/5.4.0-40-generic.btf/README", vec![]),
+            ("secret.btf", btf_of_arch(8, "outside")),
+        ]);
+        let directory = BtfDirectory::new(dir.path());
+        // 同名的目录不是 btf
+        assert!(matches!(
+            directory.lookup(&ubuntu("5.4.0-40-generic")),
+            Err(Error::EntryNotFound { .. })
+        ));
+        // 来自 os-release 的路径不能指向目录之外
+        let escaping = SystemInfo {
+            distro_id: "..".into(),
+            version_id: "..".into(),
+            arch: "..".into(),
+            kernel_release: "secret".into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            BtfDirectory::new(dir.path().join("a/b/c")).lookup(&escaping),
+            Err(Error::FileReadError(..))
+        ));
+        fs::create_dir_all(dir.path().join("a/b/c")).unwrap();
+        assert!(matches!(
+            BtfDirectory::new(dir.path().join("a/b/c")).lookup(&escaping),
+            Err(Error::EntryNotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Some distros report an `ID` in os-release that differs from the directory their btfs
+//! are stored under, e.g. `openEuler` against `openeuler`, and some build kernels of their
+//! own though `ID_LIKE` names another distro, e.g. Anolis against RHEL. Rolling distros
+//! have no release to key a directory on at all, so their btfs are looked up by kernel.
+//!
+//! The Enterprise Linux family shares kernel releases: a kernel of Rocky 8 is named as
+//! the RHEL 8 one it's rebuilt from, which btfhub may only have under `rhel` or `centos`.
+//!
+//! The SUSE Linux Enterprise products (SLES for SAP, SLED, SLE HPC) run the kernels of
+//! SLES, and are stored under `sles`, whose directories are named by service pack as
+//! `15.4` for 15 SP4.
+use std::borrow::Cow;
+
+/// (os-release `ID`, directory) of distros whose `ID` isn't the directory name, matched
+/// case-insensitively
+const DISTRO_IDS: &[(&str, &str)] = &[
+    ("openeuler", "openeuler"),
+    ("kylin", "kylin"),
+    ("anolis", "anolis"),
+    // 没有 os-release 时，lsb_release -si 给出的是 OracleServer
+    ("oracleserver", "ol"),
+    // openSUSE Leap 42 之前的 ID
+    ("opensuse", "opensuse-leap"),
+    ("sles_sap", "sles"),
+    ("sled", "sles"),
+    ("sle_hpc", "sles"),
+];
+
+/// Distros of SUSE, whose releases are named by service pack, see [`suse_service_pack`]
+const SUSE_DISTROS: &[&str] = &["sles", "opensuse-leap"];
+
+/// Distros running kernels of their own, looked up under their own directory rather than
+/// under the distro of their `ID_LIKE`, see [`crate::derivative::btfhub_distro`]
+pub const OWN_KERNEL_DISTROS: &[&str] = &["anolis", "openeuler", "kylin", CENTOS_STREAM];
+
+/// Directory of CentOS Stream, whose os-release reports `ID=centos` too, though its
+/// kernels run ahead of CentOS Linux and RHEL
+pub const CENTOS_STREAM: &str = "centos-stream";
+
+/// (distro, the directories of the Enterprise Linux family its btfs may be in after its
+/// own, in order) of the distros of the family
+const EL_DIRECTORIES: &[(&str, &[&str])] = &[
+    ("rhel", &["centos"]),
+    ("centos", &["rhel"]),
+    (CENTOS_STREAM, &["centos", "rhel"]),
+    ("rocky", &["rhel", "centos"]),
+    ("almalinux", &["rhel", "centos"]),
+    ("ol", &["rhel", "centos"]),
+];
+
+/// Rolling distros, whose btfs are looked up by kernel release alone, see [`GENERIC_DISTRO`]
+pub const ROLLING_DISTROS: &[&str] = &[
+    "arch",
+    "manjaro",
+    "endeavouros",
+    "artix",
+    "gentoo",
+    "nixos",
+    "void",
+    "opensuse-tumbleweed",
+];
+
+/// Directory of the btfs keyed by kernel release alone, as `generic/<arch>/<release>.btf`
+pub const GENERIC_DISTRO: &str = "generic";
+
+/// The directory of the distro of os-release `ID` `id`, e.g. `openeuler` for `openEuler`
+///
+/// Unknown IDs are returned as is.
+pub fn btfhub_distro_id(id: &str) -> &str {
+    DISTRO_IDS
+        .iter()
+        .find(|(v, _)| v.eq_ignore_ascii_case(id))
+        .map_or(id, |(_, directory)| directory)
+}
+
+/// Whether the distro `id`, as returned by [`btfhub_distro_id`], runs kernels of its own
+pub fn has_own_kernels(id: &str) -> bool {
+    OWN_KERNEL_DISTROS.contains(&id)
+}
+
+/// The other Enterprise Linux directories the btf of the distro `id` may be in, in order,
+/// e.g. `rhel` then `centos` for `rocky`; empty for distros outside the family
+///
+/// Each is looked up with the same major version, see [`crate::generate_el_btf_paths_for`].
+pub fn el_directories(id: &str) -> &'static [&'static str] {
+    EL_DIRECTORIES
+        .iter()
+        .find(|(v, _)| *v == id)
+        .map_or(&[], |(_, directories)| directories)
+}
+
+/// Whether the distro `id` belongs to the Enterprise Linux family, see [`el_directories`]
+pub fn is_el(id: &str) -> bool {
+    EL_DIRECTORIES.iter().any(|(v, _)| *v == id)
+}
+
+/// Every directory of the Enterprise Linux family
+pub fn el_distros() -> impl Iterator<Item = &'static str> {
+    EL_DIRECTORIES.iter().map(|(v, _)| *v)
+}
+
+/// Whether the distro `id` is a rolling one, see [`ROLLING_DISTROS`]
+pub fn is_rolling(id: &str) -> bool {
+    ROLLING_DISTROS.contains(&id)
+}
+
+/// The version of the distro `id` to look up, from `VERSION_ID` and `VERSION` of os-release
+///
+/// openEuler reports the LTS release in `VERSION`, e.g. `22.03 (LTS-SP1)` for `VERSION_ID`
+/// `22.03`, which is appended as `22.03-LTS-SP1`; [`crate::version::normalize_version`]
+/// gives `22.03` back. SUSE releases named by service pack, like `15-SP4`, in `VERSION_ID`
+/// or in `VERSION` with a `VERSION_ID` of the major version alone, become `15.4`, see
+/// [`suse_service_pack`]. Other distros keep `VERSION_ID`.
+pub fn btfhub_version<'a>(id: &str, version_id: &'a str, version: Option<&str>) -> Cow<'a, str> {
+    if SUSE_DISTROS.contains(&id) {
+        let service_pack = suse_service_pack(version_id).or_else(|| {
+            // SLES 15 SP4 的 VERSION_ID 应为 15.4，部分镜像只给出 15
+            version
+                .and_then(suse_service_pack)
+                .filter(|v| v.split('.').next() == Some(version_id))
+        });
+        return service_pack.map_or(Cow::Borrowed(version_id), Cow::Owned);
+    }
+    if id != "openeuler" {
+        return Cow::Borrowed(version_id);
+    }
+    let suffix = version
+        .and_then(|v| v.strip_prefix(version_id))
+        .map(|v| {
+            v.trim()
+                .trim_start_matches('(')
+                .trim_end_matches(')')
+                .trim()
+        })
+        .filter(|v| !v.is_empty());
+    match suffix {
+        Some(suffix) => Cow::Owned(format!("{}-{}", version_id, suffix.replace(' ', "-"))),
+        None => Cow::Borrowed(version_id),
+    }
+}
+
+/// The directory of a SUSE release named by service pack, e.g. `15.4` for `15-SP4` and
+/// `12.5` for `12 SP5`; `None` for other versions
+pub fn suse_service_pack(version: &str) -> Option<String> {
+    let (major, service_pack) = version
+        .trim()
+        .split_once("SP")
+        .map(|(major, v)| (major.trim_end_matches(['-', ' ']), v))?;
+    let number = |v: &str| !v.is_empty() && v.bytes().all(|v| v.is_ascii_digit());
+    (number(major) && number(service_pack)).then(|| format!("{}.{}", major, service_pack))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_mapped_case_insensitively() {
+        for (id, expected) in [
+            ("openEuler", "openeuler"),
+            ("openeuler", "openeuler"),
+            ("Kylin", "kylin"),
+            ("anolis", "anolis"),
+            ("OracleServer", "ol"),
+            ("ol", "ol"),
+            ("amzn", "amzn"),
+            ("opensuse", "opensuse-leap"),
+            ("sles_sap", "sles"),
+            // 未知的 ID 原样返回，大小写也不变
+            ("NoSuchOS", "NoSuchOS"),
+        ] {
+            assert_eq!(btfhub_distro_id(id), expected, "{id}");
+        }
+    }
+
+    #[test]
+    fn openeuler_lts_releases_carry_their_suffix() {
+        for (version_id, version, expected) in [
+            ("22.03", Some("22.03 (LTS-SP1)"), "22.03-LTS-SP1"),
+            ("20.03", Some("20.03 (LTS SP3)"), "20.03-LTS-SP3"),
+            ("22.03", Some("22.03 LTS"), "22.03-LTS"),
+            // 非 LTS 版本，或 VERSION 与 VERSION_ID 不一致时不附加
+            ("23.09", Some("23.09"), "23.09"),
+            ("22.03", Some("21.09"), "22.03"),
+            ("22.03", None, "22.03"),
+        ] {
+            assert_eq!(
+                btfhub_version("openeuler", version_id, version),
+                expected,
+                "{version:?}"
+            );
+        }
+        // 其他发行版不受影响
+        assert_eq!(btfhub_version("kylin", "V10", Some("V10 (Sword)")), "V10");
+        assert_eq!(btfhub_version("amzn", "2023", Some("2023")), "2023");
+    }
+
+    #[test]
+    fn suse_service_packs_become_minor_versions() {
+        assert_eq!(suse_service_pack("15-SP4").as_deref(), Some("15.4"));
+        assert_eq!(suse_service_pack("12 SP5").as_deref(), Some("12.5"));
+        assert_eq!(suse_service_pack("15.4"), None);
+        assert_eq!(suse_service_pack("SP4"), None);
+        assert_eq!(btfhub_version("sles", "15", Some("15-SP4")), "15.4");
+        // VERSION 中的主版本号与 VERSION_ID 不符时不采用
+        assert_eq!(btfhub_version("sles", "12", Some("15-SP4")), "12");
+    }
+
+    #[test]
+    fn distros_with_kernels_of_their_own() {
+        for id in ["anolis", "openeuler", "kylin", CENTOS_STREAM] {
+            assert!(has_own_kernels(id), "{id}");
+        }
+        for id in ["rocky", "ubuntu", "openEuler"] {
+            assert!(!has_own_kernels(id), "{id}");
+        }
+        assert_eq!(el_directories("ol"), ["rhel", "centos"]);
+        assert!(el_directories("anolis").is_empty());
+        assert!(is_el("rocky") && !is_el("openeuler"));
+    }
+}
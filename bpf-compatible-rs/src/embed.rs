@@ -0,0 +1,379 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! A relocatable object holding the archive, to link into an executable for
+//! `ensure_core_btf_with_linked_tar`, instead of running `ld -r -b binary`, whose symbol
+//! names depend on the linker and the name of the input file.
+//!
+//! The object is a minimal ELF64 with the archive in `.rodata` and the symbols `ld -r -b
+//! binary min_core_btfs.tar.gz` would define.
+use std::path::Path;
+
+use crate::{Error, Result};
+
+/// Symbol at the first byte of the archive
+pub const START_SYMBOL: &str = "_binary_min_core_btfs_tar_gz_start";
+/// Symbol right after the last byte of the archive
+pub const END_SYMBOL: &str = "_binary_min_core_btfs_tar_gz_end";
+/// Absolute symbol whose value is the size of the archive
+pub const SIZE_SYMBOL: &str = "_binary_min_core_btfs_tar_gz_size";
+
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+const ELF_HEADER_SIZE: usize = 64;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHF_ALLOC: u64 = 2;
+const SHN_ABS: u16 = 0xfff1;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+
+/// Index of `.rodata` among the section headers
+const RODATA_INDEX: u16 = 1;
+/// Index of `.strtab` among the section headers
+const STRTAB_INDEX: u32 = 3;
+/// Index of `.shstrtab` among the section headers
+const SHSTRTAB_INDEX: u16 = 4;
+
+/// A relocatable object embedding an archive, see [`write_embedded_archive_object`]
+#[derive(Debug, Clone)]
+pub struct EmbeddedArchiveObject<'a> {
+    archive: &'a [u8],
+    machine: u16,
+    size_symbol: bool,
+}
+
+impl<'a> EmbeddedArchiveObject<'a> {
+    /// An object for the architecture of `target`, e.g. `x86_64-unknown-linux-gnu` or `aarch64`
+    ///
+    /// Only 64-bit little-endian ELF targets are supported: x86_64 and aarch64 (`arm64`).
+    /// Others fail with [`Error::UnsupportedTarget`].
+    pub fn new(archive: &'a [u8], target: &str) -> Result<Self> {
+        let machine = match target.split('-').next().unwrap_or_default() {
+            "x86_64" | "amd64" => EM_X86_64,
+            "aarch64" | "arm64" => EM_AARCH64,
+            _ => return Err(Error::UnsupportedTarget(target.to_string())),
+        };
+        Ok(Self {
+            archive,
+            machine,
+            size_symbol: false,
+        })
+    }
+
+    /// Also define [`SIZE_SYMBOL`], so the size needn't be computed from the other two
+    pub fn with_size_symbol(mut self, size_symbol: bool) -> Self {
+        self.size_symbol = size_symbol;
+        self
+    }
+
+    /// The contents of the object file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = self.archive.len() as u64;
+        let mut strtab = StringTable::default();
+        let mut symtab = vec![];
+        push_symbol(&mut symtab, 0, STB_LOCAL, STT_NOTYPE, 0, 0);
+        push_symbol(&mut symtab, 0, STB_LOCAL, STT_SECTION, RODATA_INDEX, 0);
+        let start = strtab.add(START_SYMBOL);
+        push_symbol(&mut symtab, start, STB_GLOBAL, STT_NOTYPE, RODATA_INDEX, 0);
+        let end = strtab.add(END_SYMBOL);
+        push_symbol(&mut symtab, end, STB_GLOBAL, STT_NOTYPE, RODATA_INDEX, len);
+        if self.size_symbol {
+            let size = strtab.add(SIZE_SYMBOL);
+            push_symbol(&mut symtab, size, STB_GLOBAL, STT_NOTYPE, SHN_ABS, len);
+        }
+        let mut shstrtab = StringTable::default();
+        let names = [
+            ".rodata",
+            ".symtab",
+            ".strtab",
+            ".shstrtab",
+            ".note.GNU-stack",
+        ]
+        .map(|v| shstrtab.add(v));
+
+        // 依次排列：ELF 头、各节的内容、节头表
+        let mut out = vec![0; ELF_HEADER_SIZE];
+        let rodata_offset = append_aligned(&mut out, self.archive, 8);
+        let symtab_offset = append_aligned(&mut out, &symtab, 8);
+        let strtab_offset = append_aligned(&mut out, &strtab.bytes, 1);
+        let shstrtab_offset = append_aligned(&mut out, &shstrtab.bytes, 1);
+        let note_offset = out.len() as u64;
+        let shoff = append_aligned(&mut out, &[], 8);
+
+        let sections = [
+            SectionHeader::default(),
+            SectionHeader {
+                name: names[0],
+                kind: SHT_PROGBITS,
+                flags: SHF_ALLOC,
+                offset: rodata_offset,
+                size: len,
+                align: 8,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: names[1],
+                kind: SHT_SYMTAB,
+                offset: symtab_offset,
+                size: symtab.len() as u64,
+                link: STRTAB_INDEX,
+                // 第一个全局符号的序号，之前的都是局部符号
+                info: 2,
+                align: 8,
+                entsize: SYMBOL_SIZE as u64,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: names[2],
+                kind: SHT_STRTAB,
+                offset: strtab_offset,
+                size: strtab.bytes.len() as u64,
+                align: 1,
+                ..Default::default()
+            },
+            SectionHeader {
+                name: names[3],
+                kind: SHT_STRTAB,
+                offset: shstrtab_offset,
+                size: shstrtab.bytes.len() as u64,
+                align: 1,
+                ..Default::default()
+            },
+            // 空的 .note.GNU-stack 表明不需要可执行的栈，否则链接器会给出警告
+            SectionHeader {
+                name: names[4],
+                kind: SHT_PROGBITS,
+                offset: note_offset,
+                align: 1,
+                ..Default::default()
+            },
+        ];
+        for section in &sections {
+            section.write(&mut out);
+        }
+
+        let header = &mut out[..ELF_HEADER_SIZE];
+        header[..16].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // e_type = ET_REL
+        header[16..18].copy_from_slice(&1u16.to_le_bytes());
+        header[18..20].copy_from_slice(&self.machine.to_le_bytes());
+        header[20..24].copy_from_slice(&1u32.to_le_bytes());
+        header[40..48].copy_from_slice(&shoff.to_le_bytes());
+        header[52..54].copy_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+        header[58..60].copy_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+        header[60..62].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        header[62..64].copy_from_slice(&SHSTRTAB_INDEX.to_le_bytes());
+        out
+    }
+
+    /// Write the object to `path`
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes())
+            .map_err(|e| Error::FileWriteError(path.display().to_string(), e))
+    }
+}
+
+/// Write a relocatable object for `target` to `out`, defining [`START_SYMBOL`], [`END_SYMBOL`] and [`SIZE_SYMBOL`] over `archive`
+///
+/// Link it into the executable instead of the output of `ld -r -b binary`.
+pub fn write_embedded_archive_object(archive: &[u8], target: &str, out: &Path) -> Result<()> {
+    EmbeddedArchiveObject::new(archive, target)?
+        .with_size_symbol(true)
+        .write_to(out)
+}
+
+/// NUL-separated names, starting with the empty one
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        Self { bytes: vec![0] }
+    }
+}
+
+impl StringTable {
+    /// Append `name`, returning its offset
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+#[derive(Default)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.name.to_le_bytes());
+        out.extend_from_slice(&self.kind.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        // sh_addr
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.link.to_le_bytes());
+        out.extend_from_slice(&self.info.to_le_bytes());
+        out.extend_from_slice(&self.align.to_le_bytes());
+        out.extend_from_slice(&self.entsize.to_le_bytes());
+    }
+}
+
+fn push_symbol(symtab: &mut Vec<u8>, name: u32, bind: u8, kind: u8, shndx: u16, value: u64) {
+    symtab.extend_from_slice(&name.to_le_bytes());
+    symtab.push(bind << 4 | kind);
+    // st_other
+    symtab.push(0);
+    symtab.extend_from_slice(&shndx.to_le_bytes());
+    symtab.extend_from_slice(&value.to_le_bytes());
+    // st_size
+    symtab.extend_from_slice(&0u64.to_le_bytes());
+}
+
+/// Pad `out` to a multiple of `align`, then append `data`, returning where it starts
+///
+/// Also lays out the sections [`crate::section::add_elf_section`] appends.
+pub(crate) fn append_aligned(out: &mut Vec<u8>, data: &[u8], align: usize) -> u64 {
+    out.resize(out.len().next_multiple_of(align), 0);
+    let offset = out.len() as u64;
+    out.extend_from_slice(data);
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, process::Command};
+
+    use super::*;
+    use crate::{fixture::FixtureArchive, section::read_elf_section};
+
+    fn archive() -> Vec<u8> {
+        FixtureArchive::new()
+            .file(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                vec![0x5a; 333],
+            )
+            .gz()
+    }
+
+    /// The global symbols of the object at `path`: section index and value by name
+    fn symbols(path: &Path) -> HashMap<String, (u16, u64)> {
+        let symtab = read_elf_section(path, ".symtab").unwrap();
+        let strtab = read_elf_section(path, ".strtab").unwrap();
+        symtab
+            .chunks_exact(SYMBOL_SIZE)
+            .filter(|v| v[4] >> 4 == STB_GLOBAL)
+            .map(|v| {
+                let name = u32::from_le_bytes(v[..4].try_into().unwrap()) as usize;
+                let name = strtab[name..].split(|v| *v == 0).next().unwrap();
+                let shndx = u16::from_le_bytes(v[6..8].try_into().unwrap());
+                let value = u64::from_le_bytes(v[8..16].try_into().unwrap());
+                (String::from_utf8(name.to_vec()).unwrap(), (shndx, value))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn object_defines_the_symbols_over_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("min_core_btfs_tar.o");
+        let tar = archive();
+        write_embedded_archive_object(&tar, "x86_64-unknown-linux-gnu", &path).unwrap();
+        assert_eq!(read_elf_section(&path, ".rodata").unwrap(), tar);
+        let len = tar.len() as u64;
+        assert_eq!(
+            symbols(&path),
+            HashMap::from([
+                (START_SYMBOL.to_string(), (RODATA_INDEX, 0)),
+                (END_SYMBOL.to_string(), (RODATA_INDEX, len)),
+                (SIZE_SYMBOL.to_string(), (SHN_ABS, len)),
+            ])
+        );
+        // 不要求大小符号时不定义它
+        EmbeddedArchiveObject::new(&tar, "x86_64")
+            .unwrap()
+            .write_to(&path)
+            .unwrap();
+        assert!(!symbols(&path).contains_key(SIZE_SYMBOL));
+    }
+
+    #[test]
+    fn machine_follows_the_target() {
+        let tar = archive();
+        let machine = |target| {
+            let bytes = EmbeddedArchiveObject::new(&tar, target).unwrap().to_bytes();
+            u16::from_le_bytes([bytes[18], bytes[19]])
+        };
+        assert_eq!(machine("x86_64-unknown-linux-musl"), EM_X86_64);
+        assert_eq!(machine("amd64"), EM_X86_64);
+        assert_eq!(machine("aarch64-unknown-linux-gnu"), EM_AARCH64);
+        assert_eq!(machine("arm64"), EM_AARCH64);
+        for target in ["riscv64gc-unknown-linux-gnu", "i686-unknown-linux-gnu", ""] {
+            assert!(matches!(
+                EmbeddedArchiveObject::new(&tar, target),
+                Err(Error::UnsupportedTarget(v)) if v == target
+            ));
+        }
+    }
+
+    #[test]
+    fn object_links_into_an_executable() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = archive();
+        let object = dir.path().join("min_core_btfs_tar.o");
+        write_embedded_archive_object(&tar, std::env::consts::ARCH, &object).unwrap();
+        let main = dir.path().join("main.c");
+        std::fs::write(
+            &main,
+            format!(
+                "#include <stdio.h>\n\
+                 extern const char {START_SYMBOL}[], {END_SYMBOL}[], {SIZE_SYMBOL}[];\n\
+                 int main(void) {{\n\
+                 \tif ((unsigned long){SIZE_SYMBOL} != (unsigned long)({END_SYMBOL} - {START_SYMBOL}))\n\
+                 \t\treturn 1;\n\
+                 \tfwrite({START_SYMBOL}, 1, {END_SYMBOL} - {START_SYMBOL}, stdout);\n\
+                 \treturn 0;\n\
+                 }}\n"
+            ),
+        )
+        .unwrap();
+        let exe = dir.path().join("main");
+        // 没有 C 编译器时无法链接，跳过
+        let Ok(status) = Command::new("cc")
+            .args(["-no-pie", "-o"])
+            .args([&exe, &main, &object])
+            .status()
+        else {
+            return;
+        };
+        assert!(status.success());
+        let output = Command::new(&exe).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, tar);
+    }
+}
@@ -0,0 +1,497 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The btf returned by [`crate::ensure_core_btf`], which removes the file it extracted once
+//! dropped, so an early return doesn't leave it behind, and the options of
+//! [`crate::ensure_core_btf_with`].
+use std::{
+    io::ErrorKind,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::BTFHUB_ARCHIVE_DIR,
+    chain::Strategy,
+    compression::DEFAULT_MAX_DECOMPRESSED_SIZE,
+    gc::{self, TempfileTemplate},
+    progress::Progress,
+    release::MatchPolicy,
+};
+
+/// A btf usable as `btf_custom_path`, removed on drop if it was extracted from the archive
+///
+/// Derefs to its path. The kernel's native btf is never removed. Use [`EnsuredBtf::keep`]
+/// to leave an extracted file in place.
+#[derive(Debug)]
+pub struct EnsuredBtf {
+    path: PathBuf,
+    size: u64,
+    /// Whether the file was extracted by this library, and is to be removed on drop
+    owned: bool,
+}
+
+impl EnsuredBtf {
+    /// A file extracted by this library, holding `size` bytes
+    pub(crate) fn extracted(path: PathBuf, size: u64) -> Self {
+        Self {
+            path,
+            size,
+            owned: true,
+        }
+    }
+
+    /// A file that is not ours, e.g. the kernel's native btf
+    pub(crate) fn borrowed(path: PathBuf) -> Self {
+        // 调用者已检查过该文件，读取失败时大小记为 0
+        let size = std::fs::metadata(&path).map_or(0, |v| v.len());
+        Self {
+            path,
+            size,
+            owned: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the btf in bytes, e.g. to map the file without a `stat`
+    ///
+    /// For an extracted btf, that's the number of bytes written, i.e. after decompressing
+    /// an entry like `.btf.tar.xz`, not the size of the entry in the archive; for a
+    /// borrowed one, the size of the file, e.g. of [`crate::VMLINUX_BTF_PATH`].
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether the file is left in place on drop, e.g. because it is the kernel's native btf
+    pub fn is_borrowed(&self) -> bool {
+        !self.owned
+    }
+
+    /// Leave the file in place, handing its removal over to the caller
+    pub fn keep(mut self) -> PathBuf {
+        if self.owned {
+            gc::forget_created(&self.path);
+        }
+        self.owned = false;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Deref for EnsuredBtf {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for EnsuredBtf {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for EnsuredBtf {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        gc::forget_created(&self.path);
+        // 文件已被其他人删除时无需处理
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                log_at!(Warn, "Failed to remove {}: {}", self.path.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Options of [`crate::ensure_core_btf_with`], those of `struct bpf_compat_opts` of `bpf-compatible-sys`
+#[derive(Debug, Clone)]
+pub struct EnsureOptions {
+    pub(crate) tmpdir: Option<PathBuf>,
+    pub(crate) tempfile_template: TempfileTemplate,
+    pub(crate) prefix: PathBuf,
+    pub(crate) policy: MatchPolicy,
+    pub(crate) max_decompressed_size: u64,
+    pub(crate) sysroot: PathBuf,
+    pub(crate) always_path: bool,
+    pub(crate) chain: Vec<Strategy>,
+    pub(crate) progress: Option<Progress>,
+    pub(crate) strict_coverage: bool,
+    pub(crate) check_arch: bool,
+}
+
+impl Default for EnsureOptions {
+    fn default() -> Self {
+        Self {
+            tmpdir: None,
+            tempfile_template: TempfileTemplate::default(),
+            prefix: PathBuf::from(BTFHUB_ARCHIVE_DIR),
+            policy: MatchPolicy::Exact,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            sysroot: PathBuf::from("/"),
+            always_path: false,
+            chain: vec![Strategy::Native, Strategy::EmbeddedArchive],
+            progress: None,
+            strict_coverage: false,
+            check_arch: true,
+        }
+    }
+}
+
+impl EnsureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extract the btf into `dir`, created with mode 0700 if missing, instead of `$TMPDIR`
+    pub fn with_tmpdir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.tmpdir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Name the extracted btf after `template` instead of `eunomia.btf.XXXXXX`
+    ///
+    /// Collect the files left behind with [`gc::gc_stale_btf_tempfiles_in_with`] and the
+    /// same template.
+    pub fn with_tempfile_template(mut self, template: TempfileTemplate) -> Self {
+        self.tempfile_template = template;
+        self
+    }
+
+    /// Look for the btfs under `prefix` instead of `btfhub-archive`; an empty prefix means
+    /// the entries start directly with `<distro>/`
+    pub fn with_prefix(mut self, prefix: impl AsRef<Path>) -> Self {
+        self.prefix = prefix.as_ref().to_path_buf();
+        self
+    }
+
+    /// How strictly the btf must match the kernel, see [`crate::TarballBtfArchive::lookup_with_policy`]
+    pub fn with_match_policy(mut self, policy: MatchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Fail if the archive decompresses to more than `size` bytes, see [`crate::parsed::ParsedArchive::parse_with_limit`]
+    pub fn with_max_decompressed_size(mut self, size: u64) -> Self {
+        self.max_decompressed_size = size;
+        self
+    }
+
+    /// Detect the system and look for the native btf relative to `root`, e.g. the host's
+    /// root mounted into a container, see [`crate::SystemInfo::detect_with_root`]
+    pub fn with_sysroot(mut self, root: impl AsRef<Path>) -> Self {
+        self.sysroot = root.as_ref().to_path_buf();
+        self
+    }
+
+    /// Return the native btf, borrowed, rather than `None` if the kernel has it, as
+    /// [`crate::ensure_core_btf_always_path`] does
+    pub fn with_always_path(mut self, always_path: bool) -> Self {
+        self.always_path = always_path;
+        self
+    }
+
+    /// Try `chain` in order instead of the native btf then the archive, e.g. without
+    /// [`Strategy::Download`] for builds that must never reach the network
+    ///
+    /// The archive passed to the lookup is [`Strategy::EmbeddedArchive`]. A strategy this
+    /// build doesn't have fails the lookup with [`crate::Error::StrategyUnavailable`].
+    pub fn with_chain(mut self, chain: impl IntoIterator<Item = Strategy>) -> Self {
+        self.chain = chain.into_iter().collect();
+        self
+    }
+
+    /// Tell `progress` how much of the archive was decompressed, every
+    /// [`crate::progress::PROGRESS_INTERVAL`] bytes, e.g. to reset a watchdog
+    ///
+    /// Returning [`std::ops::ControlFlow::Break`] cancels the lookup, which fails with
+    /// [`crate::Error::Cancelled`] without leaving a file behind. It's only called during
+    /// the lookup, on the thread calling it.
+    pub fn with_progress(
+        mut self,
+        progress: impl Fn(u64, u64) -> std::ops::ControlFlow<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Progress::new(progress));
+        self
+    }
+
+    /// Fail with [`crate::Error::SystemNotCovered`] before decompressing the archive if its
+    /// listing says it has no btfs for the distro, version and architecture of the system
+    ///
+    /// See [`crate::listing::ArchiveListing::check_coverage`]; an archive without a listing
+    /// is looked up as usual. The strategies after the archive aren't tried then.
+    pub fn with_strict_coverage(mut self, strict: bool) -> Self {
+        self.strict_coverage = strict;
+        self
+    }
+
+    /// Whether to check that the btf extracted is of the architecture it's filed under and
+    /// of the one looked up, see [`crate::btf::check_btf_arch`]; on by default
+    ///
+    /// A mismatch, e.g. an arm64 btf packed under `x86_64` by mistake, fails with
+    /// [`Error::BtfArchMismatch`](crate::Error::BtfArchMismatch) rather than handing
+    /// libbpf a btf whose CO-RE relocations would be wrong.
+    pub fn with_arch_check(mut self, check: bool) -> Self {
+        self.check_arch = check;
+        self
+    }
+
+    /// The strategies tried, in order
+    pub fn chain(&self) -> &[Strategy] {
+        &self.chain
+    }
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{ensure_core_btf_with, fixture::btf_of_arch, fixture::FixtureArchive, SystemInfo};
+
+    /// A root of ubuntu 20.04, and an archive with a btf for its running kernel
+    fn root_and_archive() -> (TempDir, Vec<u8>) {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join("etc/os-release"),
+            "ID=ubuntu\nVERSION_ID=\"20.04\"\n",
+        )
+        .unwrap();
+        let info = SystemInfo::detect_with_root(root.path()).unwrap();
+        let tar = FixtureArchive::new()
+            .file(
+                &format!("btfhub-archive/{}", info),
+                btf_of_arch(8, "guarded"),
+            )
+            .gz();
+        (root, tar)
+    }
+
+    fn extract(root: &TempDir, tar: &[u8]) -> EnsuredBtf {
+        let opts = EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_tmpdir(root.path().join("tmp"))
+            .with_chain([Strategy::EmbeddedArchive]);
+        ensure_core_btf_with(tar, &opts).unwrap().unwrap()
+    }
+
+    #[test]
+    fn extracted_btf_is_removed_on_drop() {
+        let (root, tar) = root_and_archive();
+        let btf = extract(&root, &tar);
+        assert!(!btf.is_borrowed());
+        assert!(btf.starts_with(root.path().join("tmp")));
+        assert_eq!(fs::read(&*btf).unwrap(), btf_of_arch(8, "guarded"));
+        assert_eq!(btf.size(), btf_of_arch(8, "guarded").len() as u64);
+        let path = btf.path().to_path_buf();
+        drop(btf);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn kept_btf_is_left_in_place() {
+        let (root, tar) = root_and_archive();
+        let path = extract(&root, &tar).keep();
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "guarded"));
+    }
+
+    #[test]
+    fn btf_removed_by_someone_else_is_dropped_quietly() {
+        let (root, tar) = root_and_archive();
+        let btf = extract(&root, &tar);
+        fs::remove_file(&*btf).unwrap();
+        drop(btf);
+    }
+
+    #[test]
+    fn native_btf_is_never_removed() {
+        let (root, tar) = root_and_archive();
+        let vmlinux = root.path().join("sys/kernel/btf/vmlinux");
+        fs::create_dir_all(vmlinux.parent().unwrap()).unwrap();
+        fs::write(&vmlinux, btf_of_arch(8, "native")).unwrap();
+        let opts = EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_tmpdir(root.path().join("tmp"))
+            .with_always_path(true)
+            .with_chain([Strategy::Native, Strategy::EmbeddedArchive]);
+        let btf = ensure_core_btf_with(&tar, &opts).unwrap().unwrap();
+        assert!(btf.is_borrowed());
+        assert_eq!(btf.path(), vmlinux);
+        assert_eq!(btf.size(), btf_of_arch(8, "native").len() as u64);
+        drop(btf);
+        assert!(vmlinux.exists());
+        assert_eq!(EnsuredBtf::borrowed(vmlinux.clone()).keep(), vmlinux);
+        assert!(vmlinux.exists());
+    }
+
+    #[test]
+    fn guard_can_be_sent_to_another_thread() {
+        fn assert_send<T: Send>(_: &T) {}
+        let (root, tar) = root_and_archive();
+        let btf = extract(&root, &tar);
+        assert_send(&btf);
+        let path = btf.path().to_path_buf();
+        std::thread::spawn(move || drop(btf)).join().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn progress_callback_can_cancel_the_extraction() {
+        use std::{
+            ops::ControlFlow,
+            sync::{
+                atomic::{AtomicU64, Ordering},
+                Arc,
+            },
+        };
+
+        let (root, _) = root_and_archive();
+        let info = SystemInfo::detect_with_root(root.path()).unwrap();
+        // btf 在前，取消时已写出部分结果
+        let tar = FixtureArchive::new()
+            .file(
+                &format!("btfhub-archive/{}", info),
+                btf_of_arch(8, "progress"),
+            )
+            .file(
+                "padding",
+                vec![0; 4 * crate::progress::PROGRESS_INTERVAL as usize],
+            )
+            .gz();
+        let len = tar.len() as u64;
+        let calls = Arc::new(AtomicU64::new(0));
+        let counted = calls.clone();
+        let opts = EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_tmpdir(root.path().join("tmp"))
+            .with_chain([Strategy::EmbeddedArchive]);
+        let btf = ensure_core_btf_with(
+            &tar,
+            &opts.clone().with_progress(move |_, total| {
+                assert_eq!(total, len);
+                counted.fetch_add(1, Ordering::SeqCst);
+                ControlFlow::Continue(())
+            }),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(fs::read(&*btf).unwrap(), btf_of_arch(8, "progress"));
+        drop(btf);
+        assert!(calls.load(Ordering::SeqCst) >= 4);
+
+        let calls = Arc::new(AtomicU64::new(0));
+        let counted = calls.clone();
+        let opts = opts.with_progress(move |_, _| match counted.fetch_add(1, Ordering::SeqCst) {
+            0 => ControlFlow::Continue(()),
+            _ => ControlFlow::Break(()),
+        });
+        assert!(matches!(
+            ensure_core_btf_with(&tar, &opts),
+            Err(crate::Error::Cancelled)
+        ));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let left = fs::read_dir(root.path().join("tmp"))
+            .map(|v| v.count())
+            .unwrap_or_default();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn strict_coverage_refuses_systems_the_listing_lacks() {
+        use crate::pack::BtfArchiveBuilder;
+
+        let (root, _) = root_and_archive();
+        let info = SystemInfo::detect_with_root(root.path()).unwrap();
+        let archive = |version: &str, listing: bool| {
+            let mut builder = BtfArchiveBuilder::new().with_listing(listing);
+            builder
+                .add_bytes(
+                    &info.distro_id,
+                    version,
+                    &info.arch,
+                    &info.kernel_release,
+                    btf_of_arch(8, "strict"),
+                )
+                .unwrap();
+            let mut tar = vec![];
+            builder.write_gz(&mut tar, Default::default()).unwrap();
+            tar
+        };
+        let opts = EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_tmpdir(root.path().join("tmp"))
+            .with_chain([Strategy::EmbeddedArchive])
+            .with_strict_coverage(true);
+        let btf = ensure_core_btf_with(&archive(&info.version_id, true), &opts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fs::read(&*btf).unwrap(), btf_of_arch(8, "strict"));
+        drop(btf);
+
+        let uncovered = archive("18.04", true);
+        assert!(matches!(
+            ensure_core_btf_with(&uncovered, &opts),
+            Err(crate::Error::SystemNotCovered(system, supported))
+                if system == format!("{}/{}/{}", info.distro_id, info.version_id, info.arch)
+                    && supported == format!("{}/18.04/{}", info.distro_id, info.arch)
+        ));
+        // 非严格模式或没有清单时照常查找，找不到
+        for (tar, opts) in [
+            (&uncovered, opts.clone().with_strict_coverage(false)),
+            (&archive("18.04", false), opts.clone()),
+        ] {
+            assert!(matches!(
+                ensure_core_btf_with(tar, &opts),
+                Err(crate::Error::EntryNotFound { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn options_replace_the_defaults() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (root, _) = root_and_archive();
+        let info = SystemInfo::detect_with_root(root.path()).unwrap();
+        let tar = FixtureArchive::new()
+            .file(&format!("btfs/{}", info), btf_of_arch(8, "prefixed"))
+            .gz();
+        let tmpdir = root.path().join("run/bpf");
+        let opts = EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_tmpdir(&tmpdir)
+            .with_chain([Strategy::EmbeddedArchive]);
+        // 默认的前缀下没有 btf
+        assert!(ensure_core_btf_with(&tar, &opts).is_err());
+        assert!(!tmpdir.exists());
+        let opts = opts.with_prefix("btfs");
+        let btf = ensure_core_btf_with(&tar, &opts).unwrap().unwrap();
+        assert_eq!(fs::read(&*btf).unwrap(), btf_of_arch(8, "prefixed"));
+        assert_eq!(
+            fs::metadata(&tmpdir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        drop(btf);
+        assert!(ensure_core_btf_with(&tar, &opts.with_max_decompressed_size(512)).is_err());
+
+        // 内核自带 btf 时，只有要求时才返回其路径
+        let vmlinux = root.path().join("sys/kernel/btf/vmlinux");
+        fs::create_dir_all(vmlinux.parent().unwrap()).unwrap();
+        fs::write(&vmlinux, btf_of_arch(8, "native")).unwrap();
+        let opts = EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_chain([Strategy::Native, Strategy::EmbeddedArchive]);
+        assert!(ensure_core_btf_with(&tar, &opts).unwrap().is_none());
+    }
+}
@@ -3,19 +3,114 @@
 //! Copyright (c) 2023, eunomia-bpf
 //! All rights reserved.
 //!
+use std::path::PathBuf;
+
 use thiserror::Error;
 
+#[cfg(feature = "host")]
+use crate::compression::ArchiveFormat;
+
 #[derive(Error, Debug)]
 /// Error of this library
 pub enum Error {
     #[error("Failed to read os-release: {0}")]
     OsReleaseError(std::io::Error),
+    #[error("There is no os-release at `{}`, nor any other file naming the distro", .0.display())]
+    OsReleaseMissing(PathBuf),
+    #[error("os-release has no `{0}` field")]
+    MissingOsReleaseField(&'static str),
+    #[error("Failed to detect the distro, tried {0}")]
+    DistroNotDetected(String),
     #[error("Failed to call uname: {0}")]
     UnameError(std::io::Error),
     #[error("Failed to create temporary directrory: {0}")]
     TempDirError(std::io::Error),
     #[error("Failed to unpack tar archive: {0}")]
     TarUnpackError(std::io::Error),
+    #[error("Failed to read tar archive: {0}")]
+    TarReadError(std::io::Error),
+    #[error("Failed to decompress the archive: {0}")]
+    Decompress(std::io::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
     #[error("Failed to read `{0}`: {1}")]
     FileReadError(String, std::io::Error),
+    #[error("Failed to write `{0}`: {1}")]
+    FileWriteError(String, std::io::Error),
+    #[error("Invalid btf: {0}")]
+    InvalidBtf(String),
+    #[error("BTF endianness mismatch: the btf appears to be {0}-endian")]
+    BtfEndiannessMismatch(&'static str),
+    #[error("BTF architecture mismatch: the btf is of {0}, not of `{1}`")]
+    BtfArchMismatch(String, String),
+    #[error(
+        "The archive contains no `btfhub-archive` directory, it doesn't look like a btfhub archive"
+    )]
+    NotBtfhubArchive,
+    #[error("Unknown archive format, the first bytes are `{0}`")]
+    UnknownArchiveFormat(String),
+    #[error("The archive is {0} compressed, which this build can't decompress")]
+    #[cfg(feature = "host")]
+    UnsupportedCompression(ArchiveFormat),
+    #[error("Failed to decompress: invalid gzip header")]
+    InvalidGzipHeader,
+    #[error("The archive has no entry `{}`", expected.display())]
+    EntryNotFound {
+        /// Path of the entry looked for, e.g. the btf of the system
+        expected: PathBuf,
+    },
+    #[error("The kernel has no usable native btf, and nothing else provided one")]
+    NoNativeBtf,
+    #[error("btfhub-archive has no btfs of the architecture `{0}`")]
+    UnsupportedArch(String),
+    #[error("`{0}` changed while it was being read")]
+    ArchiveChanged(String),
+    #[error("Too many levels of links resolving `{0}`")]
+    TooManyLinks(String),
+    #[error("`{0}` is not listed in the manifest")]
+    NotInManifest(String),
+    #[error("Digest mismatch of `{0}`: the manifest says {1}, got {2}")]
+    DigestMismatch(String, String, String),
+    #[error("Invalid BPF object: {0}")]
+    InvalidObject(String),
+    #[error("Can't write objects for the target `{0}`, only x86_64 and aarch64 are supported")]
+    UnsupportedTarget(String),
+    #[error("The archive already has a btf for `{0}`")]
+    DuplicateEntry(String),
+    #[error("Invalid entry name: `{0}`")]
+    InvalidEntryName(String),
+    #[error("Failed to run bpftool `{0}`: {1}")]
+    BpftoolUnavailable(String, std::io::Error),
+    #[error("Failed to download `{0}`: {1}")]
+    DownloadFailed(String, String),
+    #[error("Refusing to write `{0}`, which would escape the destination directory or go through a symlink")]
+    UnsafePath(String),
+    #[error("Invalid ELF file: {0}")]
+    InvalidElf(String),
+    #[error("`{0}` has no section `{1}`")]
+    SectionNotFound(String, String),
+    #[error("`{0}` has no section headers, e.g. because it was stripped with sstrip")]
+    NoSectionHeaders(String),
+    #[error("Failed to run pahole `{0}`: {1}")]
+    PaholeUnavailable(String, std::io::Error),
+    #[error("No vmlinux of kernel {0} to generate its btf from, tried {1}")]
+    VmlinuxNotFound(String, String),
+    #[error("pahole failed to generate the btf of `{0}`: {1}")]
+    PaholeFailed(String, String),
+    #[error("The `{0}` strategy needs the `{1}` feature, which this build doesn't have")]
+    StrategyUnavailable(String, &'static str),
+    #[error("Not supported on this platform, only Linux kernels have btfs to look up")]
+    UnsupportedPlatform,
+    #[error("The lookup was cancelled by its progress callback")]
+    Cancelled,
+    #[error("This build does not support {0}; supported: {1}")]
+    SystemNotCovered(String, String),
+    #[error("Invalid template of the temporary btf files: `{0}`")]
+    InvalidTempfileTemplate(String),
+    #[error("libbpf failed to open the object: {0}")]
+    #[cfg(feature = "libbpf-rs")]
+    ObjectOpenError(libbpf_rs::Error),
+    #[error("aya failed to parse the btf `{0}`: {1}")]
+    #[cfg(feature = "aya")]
+    AyaBtfError(String, aya_obj::btf::BtfError),
 }
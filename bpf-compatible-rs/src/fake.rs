@@ -0,0 +1,72 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! A made-up identity of the running system, to drive lookups for other systems end to
+//! end, the C API included, from a single machine.
+//!
+//! Only built with the `fake-system` feature, so the variables have no effect on builds
+//! shipped without it. Unset (or empty) variables leave the value of the running system.
+use std::collections::HashMap;
+
+/// Environment variable replacing the kernel release reported by uname, e.g. `5.4.0-40-generic`
+pub const FAKE_KERNEL_ENV: &str = "BPF_COMPATIBLE_FAKE_KERNEL";
+/// Environment variable replacing the machine reported by uname, e.g. `aarch64`
+pub const FAKE_ARCH_ENV: &str = "BPF_COMPATIBLE_FAKE_ARCH";
+/// Environment variable replacing the `ID` of os-release, e.g. `ubuntu`
+///
+/// If set, os-release and the other sources of the distro aren't read at all.
+pub const FAKE_DISTRO_ENV: &str = "BPF_COMPATIBLE_FAKE_DISTRO";
+/// Environment variable holding the `VERSION_ID` of the distro of [`FAKE_DISTRO_ENV`]
+///
+/// Ignored without [`FAKE_DISTRO_ENV`]. May be left unset for rolling distros.
+pub const FAKE_VERSION_ENV: &str = "BPF_COMPATIBLE_FAKE_VERSION";
+
+fn fake_value(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Whether the kernel release is faked
+///
+/// The btf the running kernel exposes isn't that of the faked kernel, so it's ignored then.
+pub fn is_kernel_faked() -> bool {
+    fake_value(FAKE_KERNEL_ENV).is_some()
+}
+
+/// Replace the release and machine of `uname` by the faked ones
+#[cfg(target_os = "linux")]
+pub(crate) fn fake_uname(uname: &mut crate::system::Uname) {
+    if let Some(v) = fake_value(FAKE_KERNEL_ENV) {
+        log_at!(
+            Debug,
+            "Faking the kernel release {} as {}",
+            uname.release,
+            v
+        );
+        uname.release = v;
+        // 版本字符串描述的是真实内核，不应与伪造的版本号一起出现
+        uname.version.clear();
+    }
+    if let Some(v) = fake_value(FAKE_ARCH_ENV) {
+        log_at!(Debug, "Faking the machine {} as {}", uname.machine, v);
+        uname.machine = v;
+    }
+}
+
+/// The os-release fields of the faked distro, if there is one
+pub(crate) fn fake_os_release() -> Option<HashMap<String, String>> {
+    let distro = fake_value(FAKE_DISTRO_ENV)?;
+    let version = fake_value(FAKE_VERSION_ENV);
+    log_at!(
+        Debug,
+        "Faking the distro as {} {}",
+        distro,
+        version.as_deref().unwrap_or("")
+    );
+    let mut fields = HashMap::from([("ID".to_string(), distro)]);
+    if let Some(v) = version {
+        fields.insert("VERSION_ID".to_string(), v);
+    }
+    Some(fields)
+}
@@ -0,0 +1,649 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Archives built in memory for tests, so fixtures are code a reviewer can read and extend
+//! rather than checked in tarballs.
+//!
+//! ```ignore
+//! let tar = FixtureArchive::new()
+//!     .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", minimal_valid_btf())
+//!     .symlink("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf", "5.4.0-40-generic.btf")
+//!     .gz();
+//! ```
+//!
+//! Unlike [`crate::pack::BtfArchiveBuilder`], nothing is validated or sorted: the entries
+//! are written as given, in order, so archives the lookups must cope with or reject (long
+//! names, links, GNU and PAX sparse files, paths outside of `btfhub-archive/` or with `..`) can be
+//! expressed too.
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression};
+use tar::{Builder, EntryType, Header};
+
+use crate::{
+    archive::BTFHUB_ARCHIVE_DIR,
+    btf::{BTF_HEADER_SIZE, BTF_KIND_INT, BTF_KIND_STRUCT, BTF_MAGIC, BTF_VERSION},
+    join_archive_path, MODULES_DIR,
+};
+
+/// Capacity of the name and link name fields of a tar header
+const NAME_FIELD_SIZE: usize = 100;
+/// Name of the GNU entries holding the long name or link name of the next entry
+const GNU_LONG_LINK: &[u8] = b"././@LongLink";
+/// Number of chunks an old GNU sparse header holds without extension headers
+const GNU_SPARSE_CHUNKS: usize = 4;
+/// Size of a tar block, to which the map of a PAX sparse file is padded
+const BLOCK_SIZE: usize = 512;
+
+/// A tiny btf, with a single `int` type, in the byte order of the host
+///
+/// It passes [`crate::btf::validate_btf_bytes`], and is enough wherever the content of
+/// the btf doesn't matter.
+pub fn minimal_valid_btf() -> Vec<u8> {
+    // name_off、info（kind 位于第 24-28 位）、size，之后是 INT 的编码（32 位，无符号）
+    btf_of(&[1, (BTF_KIND_INT as u32) << 24, 4, 32], b"\0int\0")
+}
+
+/// A tiny btf of the architecture with `long_size`-byte pointers and the register
+/// `register` in `struct pt_regs`, e.g. 8 and `orig_x0` for arm64
+///
+/// See [`crate::btf::btf_arch`], which tells the architecture from these two types.
+pub fn btf_of_arch(long_size: u32, register: &str) -> Vec<u8> {
+    let strings = [b"\0long\0pt_regs\0", register.as_bytes(), b"\0"].concat();
+    // long 的名字位于偏移 1，pt_regs 位于 6，寄存器位于 14；pt_regs 只有一个 long 类型的成员
+    let types = [
+        1,
+        (BTF_KIND_INT as u32) << 24,
+        long_size,
+        long_size * 8,
+        6,
+        (BTF_KIND_STRUCT as u32) << 24 | 1,
+        long_size,
+        14,
+        1,
+        0,
+    ];
+    btf_of(&types, &strings)
+}
+
+/// A btf whose string section is zeros from byte 512 to 1536, two blocks a sparse entry
+/// stores as a hole, see [`FixtureArchive::sparse`]: its chunks are `..512` and `1536..`
+pub fn btf_with_hole() -> Vec<u8> {
+    let mut strings = b"\0int\0".to_vec();
+    // 头部 24 字节，类型 16 字节，字符串节从 40 开始
+    strings.resize(1536 - 40, 0);
+    strings.extend(b"tail\0");
+    btf_of(&[1, (BTF_KIND_INT as u32) << 24, 4, 32], &strings)
+}
+
+/// A btf of the given type section, as words, and string section, in the byte order of the host
+pub fn btf_of(types: &[u32], strings: &[u8]) -> Vec<u8> {
+    let types: Vec<u8> = types.iter().flat_map(|v| v.to_ne_bytes()).collect();
+    let mut btf = vec![];
+    btf.extend(BTF_MAGIC.to_ne_bytes());
+    btf.extend([BTF_VERSION, 0]);
+    let (types_len, strings_len) = (types.len() as u32, strings.len() as u32);
+    for v in [BTF_HEADER_SIZE, 0, types_len, types_len, strings_len] {
+        btf.extend(v.to_ne_bytes());
+    }
+    btf.extend(types);
+    btf.extend(strings);
+    btf
+}
+
+#[derive(Debug, Clone)]
+enum FixtureEntry {
+    File {
+        path: String,
+        contents: Vec<u8>,
+        long_name: bool,
+    },
+    Dir(String),
+    Link {
+        path: String,
+        target: String,
+        entry_type: EntryType,
+    },
+    Sparse {
+        path: String,
+        chunks: Vec<(u64, Vec<u8>)>,
+        size: u64,
+    },
+    PaxSparse {
+        path: String,
+        chunks: Vec<(u64, Vec<u8>)>,
+        size: u64,
+    },
+}
+
+/// A tar archive described entry by entry, see the [module](self) documentation
+#[derive(Debug, Clone)]
+pub struct FixtureArchive {
+    prefix: String,
+    entries: Vec<FixtureEntry>,
+}
+
+impl Default for FixtureArchive {
+    fn default() -> Self {
+        Self {
+            prefix: BTFHUB_ARCHIVE_DIR.to_string(),
+            entries: vec![],
+        }
+    }
+}
+
+impl FixtureArchive {
+    /// An archive without any entry, whose btfs go under `btfhub-archive`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put the btfs of the later calls to [`FixtureArchive::btf`] and
+    /// [`FixtureArchive::module_btf`] under `prefix` instead, e.g. `./btfhub-archive`, or a
+    /// wrong one
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Add `btf` as the btf of a kernel, at `<prefix>/<distro>/<version>/<arch>/<release>.btf`
+    pub fn btf(
+        self,
+        distro: &str,
+        version: &str,
+        arch: &str,
+        kernel_release: &str,
+        btf: Vec<u8>,
+    ) -> Self {
+        let file_name = format!("{}.btf", kernel_release);
+        let path = join_archive_path(&[&self.prefix, distro, version, arch, &file_name]);
+        self.file(&path, btf)
+    }
+
+    /// Add `btf` as the split btf of the kernel module `module`, at
+    /// `<prefix>/<distro>/<version>/<arch>/<release>/modules/<module>.btf`
+    pub fn module_btf(
+        self,
+        distro: &str,
+        version: &str,
+        arch: &str,
+        kernel_release: &str,
+        module: &str,
+        btf: Vec<u8>,
+    ) -> Self {
+        let file_name = format!("{}.btf", module);
+        let path = join_archive_path(&[
+            &self.prefix,
+            distro,
+            version,
+            arch,
+            kernel_release,
+            MODULES_DIR,
+            &file_name,
+        ]);
+        self.file(&path, btf)
+    }
+
+    /// Add a regular file at `path`, taken verbatim
+    ///
+    /// Paths longer than a tar header holds are written with a GNU long name entry.
+    pub fn file(mut self, path: &str, contents: Vec<u8>) -> Self {
+        self.entries.push(FixtureEntry::File {
+            path: path.to_string(),
+            contents,
+            long_name: false,
+        });
+        self
+    }
+
+    /// Same as [`FixtureArchive::file`], with the path written with a GNU long name entry
+    /// even if it would fit in the header
+    pub fn longname_entry(mut self, path: &str, contents: Vec<u8>) -> Self {
+        self.entries.push(FixtureEntry::File {
+            path: path.to_string(),
+            contents,
+            long_name: true,
+        });
+        self
+    }
+
+    /// Add a directory entry at `path`
+    pub fn dir(mut self, path: &str) -> Self {
+        self.entries.push(FixtureEntry::Dir(path.to_string()));
+        self
+    }
+
+    /// Add a symlink at `path` pointing to `target`, which is written verbatim
+    pub fn symlink(mut self, path: &str, target: &str) -> Self {
+        self.entries.push(FixtureEntry::Link {
+            path: path.to_string(),
+            target: target.to_string(),
+            entry_type: EntryType::Symlink,
+        });
+        self
+    }
+
+    /// Add a hardlink at `path` to the entry `target` of the archive
+    pub fn hardlink(mut self, path: &str, target: &str) -> Self {
+        self.entries.push(FixtureEntry::Link {
+            path: path.to_string(),
+            target: target.to_string(),
+            entry_type: EntryType::Link,
+        });
+        self
+    }
+
+    /// Add a GNU sparse file at `path` of `size` bytes, holding the data of `chunks` at
+    /// their offset and zeros elsewhere
+    ///
+    /// Readers expect every chunk but the last to span whole 512 byte blocks, as GNU tar
+    /// writes them; this isn't checked, so misaligned ones can be expressed too. A hole at
+    /// the end is described by an empty chunk at `size`, also as GNU tar does. Panics if
+    /// that makes more than 4 chunks, which would need extension headers.
+    pub fn sparse(mut self, path: &str, chunks: &[(u64, &[u8])], size: u64) -> Self {
+        let mut chunks = chunks
+            .iter()
+            .map(|(o, v)| (*o, v.to_vec()))
+            .collect::<Vec<_>>();
+        let end = chunks.last().map(|(o, v)| o + v.len() as u64);
+        if end.is_none_or(|v| v < size) {
+            chunks.push((size, vec![]));
+        }
+        assert!(
+            chunks.len() <= GNU_SPARSE_CHUNKS,
+            "at most {} chunks are supported, the hole at the end included",
+            GNU_SPARSE_CHUNKS
+        );
+        self.entries.push(FixtureEntry::Sparse {
+            path: path.to_string(),
+            chunks,
+            size,
+        });
+        self
+    }
+
+    /// Add a sparse file at `path` of `size` bytes in the PAX format 1.0 of GNU tar, holding
+    /// the data of `chunks` at their offset and zeros elsewhere
+    ///
+    /// The entry is named `GNUSparseFile.0/<name>` in the directory of `path`, which only its
+    /// `GNU.sparse.name` record gives; the map of the chunks leads its data. A hole at the
+    /// end is described by an empty chunk at `size`, as for [`FixtureArchive::sparse`].
+    /// Chunks needn't be aligned to blocks, nor in order, so malformed maps can be expressed
+    /// too.
+    pub fn pax_sparse(mut self, path: &str, chunks: &[(u64, &[u8])], size: u64) -> Self {
+        let mut chunks = chunks
+            .iter()
+            .map(|(o, v)| (*o, v.to_vec()))
+            .collect::<Vec<_>>();
+        let end = chunks.last().map(|(o, v)| o + v.len() as u64);
+        if end.is_none_or(|v| v < size) {
+            chunks.push((size, vec![]));
+        }
+        self.entries.push(FixtureEntry::PaxSparse {
+            path: path.to_string(),
+            chunks,
+            size,
+        });
+        self
+    }
+
+    /// The archive as a plain tar
+    pub fn tar(&self) -> Vec<u8> {
+        let mut builder = Builder::new(vec![]);
+        for entry in &self.entries {
+            match entry {
+                FixtureEntry::File {
+                    path,
+                    contents,
+                    long_name,
+                } => {
+                    let header = new_header(EntryType::Regular, contents.len() as u64);
+                    append(&mut builder, header, path, None, *long_name, contents);
+                }
+                FixtureEntry::Dir(path) => {
+                    let mut header = new_header(EntryType::Directory, 0);
+                    header.set_mode(0o755);
+                    append(&mut builder, header, path, None, false, &[]);
+                }
+                FixtureEntry::Link {
+                    path,
+                    target,
+                    entry_type,
+                } => {
+                    let header = new_header(*entry_type, 0);
+                    append(&mut builder, header, path, Some(target), false, &[]);
+                }
+                FixtureEntry::Sparse { path, chunks, size } => {
+                    let data = chunks
+                        .iter()
+                        .flat_map(|(_, v)| v.clone())
+                        .collect::<Vec<_>>();
+                    let mut header = new_header(EntryType::GNUSparse, data.len() as u64);
+                    if let Some(gnu) = header.as_gnu_mut() {
+                        for (sparse, (offset, chunk)) in gnu.sparse.iter_mut().zip(chunks) {
+                            set_octal(&mut sparse.offset, *offset);
+                            set_octal(&mut sparse.numbytes, chunk.len() as u64);
+                        }
+                        set_octal(&mut gnu.realsize, *size);
+                    }
+                    append(&mut builder, header, path, None, false, &data);
+                }
+                FixtureEntry::PaxSparse { path, chunks, size } => {
+                    let mut data = format!("{}\n", chunks.len()).into_bytes();
+                    for (offset, chunk) in chunks {
+                        data.extend(format!("{}\n{}\n", offset, chunk.len()).bytes());
+                    }
+                    data.resize(data.len().next_multiple_of(BLOCK_SIZE), 0);
+                    data.extend(chunks.iter().flat_map(|(_, v)| v.clone()));
+                    let size = size.to_string();
+                    let records = [
+                        ("GNU.sparse.major", "1"),
+                        ("GNU.sparse.minor", "0"),
+                        ("GNU.sparse.name", path.as_str()),
+                        ("GNU.sparse.realsize", size.as_str()),
+                    ];
+                    let records = pax_records(&records);
+                    let header = new_ustar_header(EntryType::XHeader, records.len() as u64);
+                    append(
+                        &mut builder,
+                        header,
+                        "././@PaxHeader",
+                        None,
+                        false,
+                        &records,
+                    );
+                    let name = match path.rsplit_once('/') {
+                        Some((dir, file)) => format!("{}/GNUSparseFile.0/{}", dir, file),
+                        None => format!("GNUSparseFile.0/{}", path),
+                    };
+                    let header = new_ustar_header(EntryType::Regular, data.len() as u64);
+                    append(&mut builder, header, &name, None, false, &data);
+                }
+            }
+        }
+        builder
+            .into_inner()
+            .expect("writing a tar to memory doesn't fail")
+    }
+
+    /// The archive as a tar compressed with gzip, the format of the embedded archive
+    pub fn gz(&self) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder
+            .write_all(&self.tar())
+            .and_then(|_| encoder.finish())
+            .expect("compressing to memory doesn't fail")
+    }
+}
+
+/// A GNU header of `entry_type` and `size`, see [`fill_header`]
+fn new_header(entry_type: EntryType, size: u64) -> Header {
+    fill_header(Header::new_gnu(), entry_type, size)
+}
+
+/// Same as [`new_header`], but a ustar header, as GNU tar only honors PAX records in those
+pub(crate) fn new_ustar_header(entry_type: EntryType, size: u64) -> Header {
+    fill_header(Header::new_ustar(), entry_type, size)
+}
+
+/// `header` set to `entry_type` and `size`, with a fixed mtime, owner and mode
+fn fill_header(mut header: Header, entry_type: EntryType, size: u64) -> Header {
+    header.set_entry_type(entry_type);
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header
+}
+
+/// Append `header` for `path` (and `link`), followed by `data`
+///
+/// The names are written as raw bytes, so paths the tar crate would refuse, like ones with
+/// `..`, are kept; those too long for the header are preceded by GNU long name entries.
+fn append(
+    builder: &mut Builder<Vec<u8>>,
+    mut header: Header,
+    path: &str,
+    link: Option<&str>,
+    long_name: bool,
+    data: &[u8],
+) {
+    if long_name || path.len() > NAME_FIELD_SIZE {
+        append_long_name(builder, EntryType::GNULongName, path);
+    }
+    copy_name(&mut header.as_old_mut().name, path);
+    if let Some(link) = link {
+        if link.len() > NAME_FIELD_SIZE {
+            append_long_name(builder, EntryType::GNULongLink, link);
+        }
+        copy_name(&mut header.as_old_mut().linkname, link);
+    }
+    header.set_cksum();
+    builder
+        .append(&header, data)
+        .expect("writing a tar to memory doesn't fail");
+}
+
+/// Append a GNU entry of `entry_type` holding `name` for the next entry
+fn append_long_name(builder: &mut Builder<Vec<u8>>, entry_type: EntryType, name: &str) {
+    let mut data = name.as_bytes().to_vec();
+    data.push(0);
+    let mut header = new_header(entry_type, data.len() as u64);
+    header.as_old_mut().name[..GNU_LONG_LINK.len()].copy_from_slice(GNU_LONG_LINK);
+    header.set_cksum();
+    builder
+        .append(&header, data.as_slice())
+        .expect("writing a tar to memory doesn't fail");
+}
+
+/// The data of a PAX extended header holding `records`, each as `<len> <key>=<value>\n`
+pub(crate) fn pax_records(records: &[(&str, &str)]) -> Vec<u8> {
+    let mut data = vec![];
+    for (key, value) in records {
+        let record = format!(" {}={}\n", key, value);
+        // 长度字段包含其自身的位数
+        let mut len = record.len();
+        while len != record.len() + len.to_string().len() {
+            len = record.len() + len.to_string().len();
+        }
+        data.extend(format!("{}{}", len, record).bytes());
+    }
+    data
+}
+
+/// Copy `name` into the header field `field`, truncated to its size
+fn copy_name(field: &mut [u8], name: &str) {
+    let len = name.len().min(field.len());
+    field.fill(0);
+    field[..len].copy_from_slice(&name.as_bytes()[..len]);
+}
+
+/// Write `value` into the numeric header field `field`, as NUL-terminated octal
+fn set_octal(field: &mut [u8], value: u64) {
+    let octal = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(&octal.as_bytes()[octal.len() - field.len()..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use tar::Archive;
+
+    use super::*;
+    use crate::{
+        btf::{btf_arch, validate_btf_bytes},
+        sparse::{entry_path, entry_size, read_entry},
+    };
+
+    /// (path, type, link name, contents) of each entry of `tar`, as the lookups read them
+    fn entries(tar: &[u8]) -> Vec<(String, EntryType, Option<String>, Vec<u8>)> {
+        let mut archive = Archive::new(tar);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry_path(&mut entry).unwrap().display().to_string();
+                let link = entry.link_name().unwrap().map(|v| v.display().to_string());
+                let entry_type = entry.header().entry_type();
+                let contents = read_entry(&mut entry).unwrap();
+                (path, entry_type, link, contents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn btfs_are_valid() {
+        validate_btf_bytes(&minimal_valid_btf()).unwrap();
+        for (long_size, register, family) in [(8, "r15", "x86"), (8, "orig_x0", "arm64")] {
+            let btf = btf_of_arch(long_size, register);
+            validate_btf_bytes(&btf).unwrap();
+            let arch = btf_arch(&btf).unwrap();
+            assert_eq!(arch.pointer_size, Some(long_size));
+            assert_eq!(arch.family, Some(family));
+        }
+        // 寄存器名不同，内容就不同，测试以此区分选中的 btf
+        assert_ne!(btf_of_arch(8, "a"), btf_of_arch(8, "b"));
+        assert_eq!(
+            btf_arch(&btf_of_arch(4, "x")).unwrap().pointer_size,
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn entries_are_written_as_given_in_order() {
+        let tar = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                minimal_valid_btf(),
+            )
+            .module_btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                "nf_tables",
+                vec![1],
+            )
+            .dir("btfhub-archive/empty")
+            .symlink("btfhub-archive/link.btf", "../outside.btf")
+            .hardlink("btfhub-archive/hard.btf", "btfhub-archive/link.btf")
+            .file("/etc/passwd", b"abs".to_vec())
+            .with_prefix("./other")
+            .btf("centos", "8", "x86_64", "4.18.0", vec![2])
+            .tar();
+        let entries = entries(&tar);
+        let summary = entries
+            .iter()
+            .map(|(path, entry_type, link, _)| (path.as_str(), *entry_type, link.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                    EntryType::Regular,
+                    None
+                ),
+                (
+                    "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic/modules/nf_tables.btf",
+                    EntryType::Regular,
+                    None
+                ),
+                ("btfhub-archive/empty", EntryType::Directory, None),
+                (
+                    "btfhub-archive/link.btf",
+                    EntryType::Symlink,
+                    Some("../outside.btf")
+                ),
+                (
+                    "btfhub-archive/hard.btf",
+                    EntryType::Link,
+                    Some("btfhub-archive/link.btf")
+                ),
+                ("/etc/passwd", EntryType::Regular, None),
+                (
+                    "./other/centos/8/x86_64/4.18.0.btf",
+                    EntryType::Regular,
+                    None
+                ),
+            ]
+        );
+        assert_eq!(entries[0].3, minimal_valid_btf());
+        assert_eq!(entries[5].3, b"abs");
+    }
+
+    #[test]
+    fn long_names_are_kept() {
+        let long = format!("btfhub-archive/{}.btf", "x".repeat(200));
+        let tar = FixtureArchive::new()
+            .file(&long, vec![1])
+            .longname_entry("short.btf", vec![2])
+            .symlink("link.btf", &long)
+            .tar();
+        let entries = entries(&tar);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            (entries[0].0.as_str(), &*entries[0].3),
+            (long.as_str(), &[1][..])
+        );
+        assert_eq!(
+            (entries[1].0.as_str(), &*entries[1].3),
+            ("short.btf", &[2][..])
+        );
+        assert_eq!(entries[2].2.as_deref(), Some(long.as_str()));
+        // 短名字也写入 GNU 长名字条目
+        assert!(
+            tar.windows(GNU_LONG_LINK.len())
+                .filter(|v| *v == GNU_LONG_LINK)
+                .count()
+                >= 2
+        );
+    }
+
+    #[test]
+    fn sparse_files_read_back_with_their_holes() {
+        let data = vec![7; BLOCK_SIZE];
+        let expected = |size: usize| {
+            let mut v = vec![0; size];
+            v[BLOCK_SIZE..2 * BLOCK_SIZE].copy_from_slice(&data);
+            v[3 * BLOCK_SIZE..3 * BLOCK_SIZE + 3].copy_from_slice(b"end");
+            v
+        };
+        let chunks: &[(u64, &[u8])] = &[(512, &data), (1536, b"end")];
+        let tar = FixtureArchive::new()
+            .sparse("gnu.btf", chunks, 4096)
+            .pax_sparse("dir/pax.btf", chunks, 4096)
+            .tar();
+        let mut archive = Archive::new(&tar[..]);
+        let mut found = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry_path(&mut entry).unwrap();
+            assert_eq!(entry_size(&mut entry).unwrap(), 4096, "{}", path.display());
+            assert_eq!(read_entry(&mut entry).unwrap(), expected(4096));
+            found.push(path.display().to_string());
+        }
+        // PAX 格式的条目以 GNU.sparse.name 记录的名字读出
+        assert_eq!(found, ["gnu.btf", "dir/pax.btf"]);
+    }
+
+    #[test]
+    fn gz_is_the_compressed_tar() {
+        let fixture = FixtureArchive::new().btf("ubuntu", "20.04", "x86_64", "5.4.0", vec![1]);
+        let mut tar = vec![];
+        GzDecoder::new(&fixture.gz()[..])
+            .read_to_end(&mut tar)
+            .unwrap();
+        assert_eq!(tar, fixture.tar());
+        assert!(entries(&FixtureArchive::new().tar()).is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Archives of btfs named after the kernel release alone, at their root.
+//!
+//! Vendors of embedded systems, whose distro and hardware are fixed, ship flat tarballs of
+//! `<release>.btf` files without the `<distro>/<version>/<arch>` directories of btfhub.
+//! Their btfs are looked up by release once none of the btfhub paths matched, so an archive
+//! with both prefers the btfhub tree. A [`ARCH_MARKER_NAME`](crate::flat::ARCH_MARKER_NAME) entry at the root, holding the
+//! name of an architecture, e.g. `aarch64`, restricts them to that architecture.
+use std::path::Path;
+
+use crate::{arch, archive::BtfEncoding, matcher::split_btf_name, release, SystemInfo};
+
+/// Name of the entry at the root of a flat archive naming the architecture of its btfs
+pub const ARCH_MARKER_NAME: &str = ".arch";
+
+/// Generate the paths the btf of `info` may have at the root of a flat archive, most preferred first
+///
+/// The kernel release is tried in the forms of [`release::release_variants`], e.g.
+/// `5.4.0-40-generic.btf`.
+pub fn generate_flat_btf_paths_for(info: &SystemInfo) -> Vec<String> {
+    let mut arches = arch::arch_directories(&info.arch);
+    arches.push(&info.arch);
+    release::release_variants(&info.kernel_release, &arches)
+        .into_iter()
+        .map(|v| format!("{}.btf", v))
+        .collect()
+}
+
+/// Whether the [`ARCH_MARKER_NAME`] entry holding `contents` admits the btfs of a flat archive for `info`
+///
+/// Surrounding whitespace is ignored, and any name of the architecture is accepted, e.g.
+/// `arm64` as well as `aarch64`.
+pub fn arch_marker_allows(contents: &[u8], info: &SystemInfo) -> bool {
+    std::str::from_utf8(contents)
+        .is_ok_and(|v| arch::normalize_arch(v.trim()) == arch::normalize_arch(&info.arch))
+}
+
+/// The kernel release and encoding of the btf at `path` if it's at the root of the archive,
+/// e.g. `5.4.0-40-generic.btf`; `path` is normalized, without a leading `./`
+pub fn parse_flat_btf_path(path: &Path) -> Option<(String, BtfEncoding)> {
+    if !path.parent()?.as_os_str().is_empty() {
+        return None;
+    }
+    let file_name = path.file_name()?.to_str()?;
+    let (kernel_release, encoding) = split_btf_name(file_name)?;
+    Some((kernel_release.to_string(), encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aarch64(release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "poky".into(),
+            version_id: "4.0".into(),
+            arch: "aarch64".into(),
+            kernel_release: release.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn paths_are_the_release_variants_at_the_root() {
+        assert_eq!(
+            generate_flat_btf_paths_for(&aarch64("5.15.0-1034-raspi")),
+            ["5.15.0-1034-raspi.btf"]
+        );
+        // 去掉架构后缀和本地版本的形式排在后面
+        let paths = generate_flat_btf_paths_for(&SystemInfo {
+            arch: "x86_64".into(),
+            ..aarch64("5.14.0-284.11.1.el9_2.x86_64")
+        });
+        assert_eq!(paths[0], "5.14.0-284.11.1.el9_2.x86_64.btf");
+        assert!(paths.contains(&"5.14.0-284.11.1.el9_2.btf".to_string()));
+        assert!(paths.iter().all(|v| !v.contains('/')));
+    }
+
+    #[test]
+    fn marker_accepts_any_name_of_the_architecture() {
+        let info = aarch64("5.15.0");
+        for marker in [&b"aarch64"[..], b"arm64\n", b"  aarch64 \r\n"] {
+            assert!(
+                arch_marker_allows(marker, &info),
+                "{}",
+                String::from_utf8_lossy(marker)
+            );
+        }
+        for marker in [&b"x86_64"[..], b"", b"aarch64 x86_64", b"\xffaarch64"] {
+            assert!(
+                !arch_marker_allows(marker, &info),
+                "{}",
+                String::from_utf8_lossy(marker)
+            );
+        }
+    }
+
+    #[test]
+    fn only_btfs_at_the_root_are_flat() {
+        assert_eq!(
+            parse_flat_btf_path(Path::new("5.4.0-40-generic.btf")),
+            Some(("5.4.0-40-generic".into(), BtfEncoding::Plain))
+        );
+        assert_eq!(
+            parse_flat_btf_path(Path::new("5.4.0-40-generic.btf.gz")),
+            Some(("5.4.0-40-generic".into(), BtfEncoding::Gzipped))
+        );
+        assert_eq!(
+            parse_flat_btf_path(Path::new("6.1.0.btf.tar.xz")),
+            Some(("6.1.0".into(), BtfEncoding::Tarball))
+        );
+        for path in [
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            "vendor/5.4.0-40-generic.btf",
+            ".btf",
+            ARCH_MARKER_NAME,
+            "5.4.0-40-generic.txt",
+            "",
+        ] {
+            assert_eq!(parse_flat_btf_path(Path::new(path)), None, "{path}");
+        }
+    }
+}
@@ -0,0 +1,504 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Removal of the temporary btf files processes leave behind.
+//!
+//! A file like `/tmp/eunomia.btf.XXXXXX` is removed once the btf was loaded, but not if
+//! the process crashed or was killed first, nor if it exited without dropping it.
+//! [`gc_stale_btf_tempfiles`](crate::gc::gc_stale_btf_tempfiles) removes those of earlier processes, recognized by their name,
+//! owner and age; [`register_cleanup_at_exit`](crate::gc::register_cleanup_at_exit) has the process remove its own on `exit`.
+//! Both only do something on Linux: elsewhere, where no btf is ever extracted, the
+//! collection fails with [`Error::UnsupportedPlatform`](crate::Error::UnsupportedPlatform)
+//! and registering does nothing.
+//!
+//! The names follow a [`TempfileTemplate`](crate::gc::TempfileTemplate), `eunomia.btf.` and 6 random letters or digits
+//! by default, which is part of the API: a service may choose its own, e.g. to tell its
+//! files apart, and collect them with [`gc_stale_btf_tempfiles_with`](crate::gc::gc_stale_btf_tempfiles_with).
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+#[cfg(target_os = "linux")]
+use std::{io::ErrorKind, os::unix::fs::MetadataExt, sync::Once, time::SystemTime};
+
+use crate::{Error, Result};
+
+/// Prefix of the names of the temporary btf files, followed by [`TEMPFILE_SUFFIX_LEN`] random letters or digits
+pub const TEMPFILE_PREFIX: &str = "eunomia.btf.";
+/// Length of the random suffix of the names of the temporary btf files
+pub const TEMPFILE_SUFFIX_LEN: usize = 6;
+/// Prefix of the private directory of a user under the temporary directory, followed by
+/// their uid, where `bpf-compatible-sys` creates its temporary files
+pub const PRIVATE_DIR_PREFIX: &str = "bpf-compatible-";
+
+/// Temporary files this process created and hasn't removed or handed over yet
+static CREATED: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+#[cfg(target_os = "linux")]
+static REGISTER_CLEANUP: Once = Once::new();
+
+/// What [`gc_stale_btf_tempfiles`] did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GcReport {
+    /// Stale files removed
+    pub removed: usize,
+    /// Bytes the removed files held
+    pub freed_bytes: u64,
+    /// Files left in place as they're newer than the age given, or of this process
+    pub recent: usize,
+    /// Stale files that couldn't be removed, e.g. for lack of permission on the directory
+    pub failed: usize,
+}
+
+impl GcReport {
+    #[cfg(target_os = "linux")]
+    fn merge(&mut self, other: GcReport) {
+        self.removed += other.removed;
+        self.freed_bytes += other.freed_bytes;
+        self.recent += other.recent;
+        self.failed += other.failed;
+    }
+}
+
+/// Names of the temporary btf files: a prefix, [`TEMPFILE_SUFFIX_LEN`] random letters or
+/// digits, and a suffix
+///
+/// The default is [`TEMPFILE_PREFIX`] without a suffix, as in `eunomia.btf.XXXXXX`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TempfileTemplate {
+    prefix: String,
+    suffix: String,
+}
+
+impl Default for TempfileTemplate {
+    fn default() -> Self {
+        Self {
+            prefix: TEMPFILE_PREFIX.to_string(),
+            suffix: String::new(),
+        }
+    }
+}
+
+impl TempfileTemplate {
+    /// Names made of `prefix`, the random letters and `suffix`, like `myservice.XXXXXX.btf`
+    ///
+    /// Fails with [`Error::InvalidTempfileTemplate`] if `prefix` is empty, so that
+    /// collecting the files can't remove those of other programs, or if `prefix` or
+    /// `suffix` has a `/` or a NUL, so the files stay in the directory they're created in.
+    pub fn new(prefix: impl Into<String>, suffix: impl Into<String>) -> Result<Self> {
+        let template = Self {
+            prefix: prefix.into(),
+            suffix: suffix.into(),
+        };
+        let invalid = |v: &str| v.contains(['/', '\0']);
+        if template.prefix.is_empty() || invalid(&template.prefix) || invalid(&template.suffix) {
+            return Err(Error::InvalidTempfileTemplate(template.to_string()));
+        }
+        Ok(template)
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn suffix(&self) -> &str {
+        &self.suffix
+    }
+
+    /// Whether `name` follows the template
+    pub fn matches(&self, name: &OsStr) -> bool {
+        name.to_str()
+            .and_then(|v| v.strip_prefix(self.prefix.as_str()))
+            .and_then(|v| v.strip_suffix(self.suffix.as_str()))
+            .is_some_and(|v| {
+                v.len() == TEMPFILE_SUFFIX_LEN && v.bytes().all(|v| v.is_ascii_alphanumeric())
+            })
+    }
+}
+
+impl std::fmt::Display for TempfileTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.prefix,
+            "X".repeat(TEMPFILE_SUFFIX_LEN),
+            self.suffix
+        )
+    }
+}
+
+/// Whether `name` is that of a temporary btf file, `eunomia.btf.` and 6 letters or digits
+pub fn is_btf_tempfile_name(name: &OsStr) -> bool {
+    TempfileTemplate::default().matches(name)
+}
+
+/// The directories temporary btf files are created in by default: `$TMPDIR` (or `/tmp`),
+/// and the private directory of the user under it
+pub fn default_tempfile_dirs() -> Vec<PathBuf> {
+    let base = std::env::var_os("TMPDIR")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+    #[cfg(target_os = "linux")]
+    {
+        let uid = unsafe { libc::geteuid() };
+        let private = base.join(format!("{}{}", PRIVATE_DIR_PREFIX, uid));
+        vec![base, private]
+    }
+    #[cfg(not(target_os = "linux"))]
+    vec![base]
+}
+
+/// Remove the temporary btf files of [`default_tempfile_dirs`] older than `max_age`
+///
+/// See [`gc_stale_btf_tempfiles_in`]; a missing private directory is skipped.
+#[cfg(target_os = "linux")]
+pub fn gc_stale_btf_tempfiles(max_age: Duration) -> Result<GcReport> {
+    gc_stale_btf_tempfiles_with(max_age, &TempfileTemplate::default())
+}
+
+/// Same as [`gc_stale_btf_tempfiles`], for the files named after `template`
+#[cfg(target_os = "linux")]
+pub fn gc_stale_btf_tempfiles_with(
+    max_age: Duration,
+    template: &TempfileTemplate,
+) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    for dir in default_tempfile_dirs() {
+        match gc_stale_btf_tempfiles_in_with(&dir, max_age, template) {
+            Ok(v) => report.merge(v),
+            Err(Error::FileReadError(_, e)) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    log_at!(
+        Info,
+        "Removed {} stale btf files, {} bytes",
+        report.removed,
+        report.freed_bytes
+    );
+    Ok(report)
+}
+
+/// Remove the temporary btf files directly under `dir` older than `max_age`, e.g. the
+/// `tmpdir` of [`crate::EnsureOptions::with_tmpdir`]
+///
+/// Only regular files named like [`is_btf_tempfile_name`], owned by the effective user and
+/// last modified more than `max_age` ago are removed; symlinks, other files and those
+/// this process created are left alone. A file already gone, e.g. because another process
+/// collected it at the same time, isn't an error. Fails with [`Error::FileReadError`] if
+/// `dir` can't be listed.
+#[cfg(target_os = "linux")]
+pub fn gc_stale_btf_tempfiles_in(dir: &Path, max_age: Duration) -> Result<GcReport> {
+    gc_stale_btf_tempfiles_in_with(dir, max_age, &TempfileTemplate::default())
+}
+
+/// Same as [`gc_stale_btf_tempfiles_in`], for the files named after `template`
+#[cfg(target_os = "linux")]
+pub fn gc_stale_btf_tempfiles_in_with(
+    dir: &Path,
+    max_age: Duration,
+    template: &TempfileTemplate,
+) -> Result<GcReport> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| Error::FileReadError(dir.display().to_string(), e))?;
+    let uid = unsafe { libc::geteuid() };
+    let now = SystemTime::now();
+    let mut report = GcReport::default();
+    for entry in entries {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !template.matches(&entry.file_name()) {
+            continue;
+        }
+        let path = entry.path();
+        // 不跟随符号链接；文件可能已被其他进程删除
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if !metadata.is_file() || metadata.uid() != uid {
+            continue;
+        }
+        // 修改时间在未来时视为较新的文件
+        let stale = metadata
+            .modified()
+            .ok()
+            .and_then(|v| now.duration_since(v).ok())
+            .is_some_and(|v| v > max_age);
+        if !stale || is_created(&path) {
+            report.recent += 1;
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                log_at!(Debug, "Removed the stale btf {}", path.display());
+                report.removed += 1;
+                report.freed_bytes += metadata.len();
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                log_at!(Warn, "Failed to remove {}: {}", path.display(), e);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Remove the temporary btf files older than `max_age`
+#[cfg(not(target_os = "linux"))]
+pub fn gc_stale_btf_tempfiles(_max_age: Duration) -> Result<GcReport> {
+    Err(Error::UnsupportedPlatform)
+}
+
+/// Remove the temporary btf files directly under `dir` older than `max_age`
+#[cfg(not(target_os = "linux"))]
+pub fn gc_stale_btf_tempfiles_in(_dir: &Path, _max_age: Duration) -> Result<GcReport> {
+    Err(Error::UnsupportedPlatform)
+}
+
+/// Same as [`gc_stale_btf_tempfiles`], for the files named after `template`
+#[cfg(not(target_os = "linux"))]
+pub fn gc_stale_btf_tempfiles_with(
+    _max_age: Duration,
+    _template: &TempfileTemplate,
+) -> Result<GcReport> {
+    Err(Error::UnsupportedPlatform)
+}
+
+/// Same as [`gc_stale_btf_tempfiles_in`], for the files named after `template`
+#[cfg(not(target_os = "linux"))]
+pub fn gc_stale_btf_tempfiles_in_with(
+    _dir: &Path,
+    _max_age: Duration,
+    _template: &TempfileTemplate,
+) -> Result<GcReport> {
+    Err(Error::UnsupportedPlatform)
+}
+
+/// Remember that this process created the temporary file `path`, to remove it at exit
+/// once [`register_cleanup_at_exit`] was called, unless [`forget_created`] is called first
+///
+/// Crates creating temporary btf files of their own, like `bpf-compatible-sys`, call this
+/// too. Only files named like [`is_btf_tempfile_name`] are removed at exit.
+pub fn record_created(path: &Path) {
+    record_created_with(path, &TempfileTemplate::default())
+}
+
+/// Same as [`record_created`], for a file named after `template`
+///
+/// A file not named after it isn't remembered, so it's never removed at exit.
+pub fn record_created_with(path: &Path, template: &TempfileTemplate) {
+    if !path.file_name().is_some_and(|v| template.matches(v)) {
+        return;
+    }
+    if let Ok(mut paths) = CREATED.lock() {
+        paths
+            .get_or_insert_with(HashSet::new)
+            .insert(path.to_path_buf());
+    }
+}
+
+/// Forget a file [`record_created`] remembered, once it's removed or handed over
+pub fn forget_created(path: &Path) {
+    if let Ok(mut paths) = CREATED.lock() {
+        if let Some(paths) = paths.as_mut() {
+            paths.remove(path);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_created(path: &Path) -> bool {
+    CREATED
+        .lock()
+        .is_ok_and(|v| v.as_ref().is_some_and(|v| v.contains(path)))
+}
+
+/// Remove the temporary btf files this process created and didn't remove, when it exits
+///
+/// This is opt-in: files an [`crate::EnsuredBtf`] still holds, or which were returned by
+/// `bpf-compatible-sys` and not cleaned, are removed by `exit`, including a return from
+/// `main`, though not if the process is killed or aborts. Files handed over with
+/// [`crate::EnsuredBtf::keep`] are left alone. Calling it again has no effect, nor does
+/// calling it on other systems than Linux, where no btf is ever extracted.
+pub fn register_cleanup_at_exit() {
+    #[cfg(target_os = "linux")]
+    REGISTER_CLEANUP.call_once(|| {
+        if unsafe { libc::atexit(cleanup_at_exit) } != 0 {
+            log_at!(Warn, "Failed to register the removal of the btfs at exit");
+        }
+    });
+}
+
+#[cfg(target_os = "linux")]
+extern "C" fn cleanup_at_exit() {
+    // 退出时其他线程可能仍持有锁，此时不再等待
+    let Ok(mut paths) = CREATED.try_lock() else {
+        return;
+    };
+    // 记录时已检查文件名符合模板
+    for path in paths.take().unwrap_or_default() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{fs::File, io::ErrorKind};
+
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// Write `len` bytes to `dir/name`, last modified `age` ago
+    fn aged(dir: &Path, name: &str, len: usize, age: Duration) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, vec![0; len]).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn only_stale_btf_tempfiles_of_others_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = aged(dir.path(), "eunomia.btf.a1B2c3", 100, 2 * HOUR);
+        let fresh = aged(dir.path(), "eunomia.btf.d4E5f6", 10, Duration::ZERO);
+        // 修改时间在未来，例如时钟被调整过
+        let future = aged(dir.path(), "eunomia.btf.future", 10, Duration::ZERO);
+        File::options()
+            .write(true)
+            .open(&future)
+            .unwrap()
+            .set_modified(SystemTime::now() + HOUR)
+            .unwrap();
+        let others = [
+            aged(dir.path(), "eunomia.btf.short", 10, 2 * HOUR),
+            aged(dir.path(), "eunomia.btf.toolong", 10, 2 * HOUR),
+            aged(dir.path(), "eunomia.btf.a-b_c.", 10, 2 * HOUR),
+            aged(dir.path(), "vmlinux.btf", 10, 2 * HOUR),
+        ];
+        std::fs::create_dir(dir.path().join("eunomia.btf.subdir")).unwrap();
+        let target = aged(dir.path(), "target", 10, 2 * HOUR);
+        std::os::unix::fs::symlink(&target, dir.path().join("eunomia.btf.link00")).unwrap();
+
+        let report = gc_stale_btf_tempfiles_in(dir.path(), HOUR).unwrap();
+        assert_eq!(
+            report,
+            GcReport {
+                removed: 1,
+                freed_bytes: 100,
+                recent: 2,
+                failed: 0,
+            }
+        );
+        assert!(!stale.exists());
+        for path in others.iter().chain([&fresh, &future, &target]) {
+            assert!(path.exists(), "{}", path.display());
+        }
+        assert!(dir.path().join("eunomia.btf.subdir").is_dir());
+        assert!(dir.path().join("eunomia.btf.link00").exists());
+
+        // 更短的期限下，较新的文件同样过期
+        let report = gc_stale_btf_tempfiles_in(dir.path(), Duration::ZERO).unwrap();
+        assert_eq!((report.removed, report.freed_bytes), (1, 10));
+        assert!(!fresh.exists());
+        assert_eq!(report.recent, 1);
+    }
+
+    #[test]
+    fn files_of_this_process_are_kept_until_forgotten() {
+        let dir = tempfile::tempdir().unwrap();
+        let ours = aged(dir.path(), "eunomia.btf.Ours00", 10, 2 * HOUR);
+        record_created(&ours);
+        assert!(is_created(&ours));
+        let report = gc_stale_btf_tempfiles_in(dir.path(), HOUR).unwrap();
+        assert_eq!((report.removed, report.recent), (0, 1));
+        assert!(ours.exists());
+
+        forget_created(&ours);
+        assert!(!is_created(&ours));
+        let report = gc_stale_btf_tempfiles_in(dir.path(), HOUR).unwrap();
+        assert_eq!((report.removed, report.recent), (1, 0));
+        assert!(!ours.exists());
+
+        // 不符合模板的文件不会被记录，也就不会在退出时删除
+        let named = dir.path().join("named.btf");
+        record_created(&named);
+        assert!(!is_created(&named));
+        let template = TempfileTemplate::new("svc.", ".btf").unwrap();
+        let custom = dir.path().join("svc.Ab12Cd.btf");
+        record_created_with(&custom, &template);
+        assert!(is_created(&custom));
+        forget_created(&custom);
+    }
+
+    #[test]
+    fn templates_select_their_own_files() {
+        let template = TempfileTemplate::new("svc.", ".btf").unwrap();
+        assert_eq!(template.to_string(), "svc.XXXXXX.btf");
+        assert_eq!((template.prefix(), template.suffix()), ("svc.", ".btf"));
+        assert!(template.matches(OsStr::new("svc.Ab12Cd.btf")));
+        for name in [
+            "svc.Ab12Cd",
+            "svc.Ab1-Cd.btf",
+            "svc.Ab12Cd.btf.gz",
+            "eunomia.btf.Ab12Cd",
+        ] {
+            assert!(!template.matches(OsStr::new(name)), "{name}");
+        }
+        assert_eq!(
+            TempfileTemplate::default().to_string(),
+            "eunomia.btf.XXXXXX"
+        );
+        assert!(is_btf_tempfile_name(OsStr::new("eunomia.btf.Ab12Cd")));
+        for (prefix, suffix) in [("", ".btf"), ("a/b.", ""), ("svc.", "\0"), ("svc.", "/..")] {
+            assert!(
+                matches!(
+                    TempfileTemplate::new(prefix, suffix),
+                    Err(Error::InvalidTempfileTemplate(_))
+                ),
+                "{prefix:?} {suffix:?}"
+            );
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let theirs = aged(dir.path(), "svc.Ab12Cd.btf", 7, 2 * HOUR);
+        let default = aged(dir.path(), "eunomia.btf.Ab12Cd", 7, 2 * HOUR);
+        let report = gc_stale_btf_tempfiles_in_with(dir.path(), HOUR, &template).unwrap();
+        // 其他模板的文件既不删除，也不计入
+        assert_eq!((report.removed, report.recent), (1, 0));
+        assert!(!theirs.exists());
+        assert!(default.exists());
+    }
+
+    #[test]
+    fn unlistable_dir_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        assert!(matches!(
+            gc_stale_btf_tempfiles_in(&missing, HOUR),
+            Err(Error::FileReadError(path, e))
+                if path == missing.display().to_string() && e.kind() == ErrorKind::NotFound
+        ));
+        let file = aged(dir.path(), "eunomia.btf.Ab12Cd", 1, 2 * HOUR);
+        assert!(matches!(
+            gc_stale_btf_tempfiles_in(&file, HOUR),
+            Err(Error::FileReadError(..))
+        ));
+        assert!(file.exists());
+    }
+}
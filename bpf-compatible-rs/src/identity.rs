@@ -0,0 +1,105 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! A short description of which build of an archive is in use, for bug reports.
+use flate2::read::GzDecoder;
+
+/// Describe a gzipped tar archive by its gzip metadata
+///
+/// The result looks like `crc32=1a2b3c4d isize=123456 size=7890 mtime=1690000000 name=min_core_btfs.tar`.
+/// `crc32` and `isize` come from the gzip trailer, so they identify the archive contents
+/// without decompressing them. `mtime`, `name` and `comment` are only present if the
+/// gzip header carries them.
+pub fn archive_identity(tar_gz: &[u8]) -> String {
+    let mut identity = match gzip_trailer(tar_gz) {
+        Some((crc32, isize)) => {
+            format!("crc32={:08x} isize={} size={}", crc32, isize, tar_gz.len())
+        }
+        None => format!("size={}", tar_gz.len()),
+    };
+    let decoder = GzDecoder::new(tar_gz);
+    if let Some(header) = decoder.header() {
+        if header.mtime() != 0 {
+            identity.push_str(&format!(" mtime={}", header.mtime()));
+        }
+        if let Some(name) = header.filename() {
+            identity.push_str(&format!(" name={}", String::from_utf8_lossy(name)));
+        }
+        if let Some(comment) = header.comment() {
+            identity.push_str(&format!(" comment={}", String::from_utf8_lossy(comment)));
+        }
+    }
+    identity
+}
+
+/// A short, filesystem-safe key telling apart the contents of gzipped archives
+///
+/// It's built from the CRC32 and size in the gzip trailer, e.g. `1a2b3c4d-0001e240`
+pub fn archive_key(tar_gz: &[u8]) -> String {
+    match gzip_trailer(tar_gz) {
+        Some((crc32, isize)) => format!("{:08x}-{:08x}", crc32, isize),
+        None => format!("short-{}", tar_gz.len()),
+    }
+}
+
+/// CRC32 and size (modulo 2^32) of the decompressed data, from the gzip trailer
+fn gzip_trailer(tar_gz: &[u8]) -> Option<(u32, u32)> {
+    let trailer = &tar_gz[tar_gz.len().checked_sub(8)?..];
+    Some((
+        u32::from_le_bytes(trailer[..4].try_into().ok()?),
+        u32::from_le_bytes(trailer[4..].try_into().ok()?),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression, Crc, GzBuilder};
+
+    use super::*;
+
+    #[test]
+    fn identity_has_the_trailer_and_header_fields() {
+        let data = b"not really a tar, the identity doesn't care";
+        let mut encoder = GzBuilder::new()
+            .mtime(1_690_000_000)
+            .filename("min_core_btfs.tar")
+            .comment("build 42")
+            .write(vec![], Compression::best());
+        encoder.write_all(data).unwrap();
+        let tar_gz = encoder.finish().unwrap();
+        let mut crc = Crc::new();
+        crc.update(data);
+        assert_eq!(
+            archive_identity(&tar_gz),
+            format!(
+                "crc32={:08x} isize={} size={} mtime=1690000000 name=min_core_btfs.tar comment=build 42",
+                crc.sum(),
+                data.len(),
+                tar_gz.len()
+            )
+        );
+        assert_eq!(
+            archive_key(&tar_gz),
+            format!("{:08x}-{:08x}", crc.sum(), data.len())
+        );
+    }
+
+    #[test]
+    fn missing_header_fields_are_left_out() {
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(b"tar").unwrap();
+        let identity = archive_identity(&encoder.finish().unwrap());
+        assert!(identity.starts_with("crc32="), "{identity}");
+        assert!(!identity.contains("mtime=") && !identity.contains("name="));
+    }
+
+    #[test]
+    fn input_shorter_than_a_trailer_only_has_its_size() {
+        assert_eq!(archive_identity(b"abc"), "size=3");
+        assert_eq!(archive_key(b"abc"), "short-3");
+    }
+}
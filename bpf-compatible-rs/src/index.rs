@@ -0,0 +1,256 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! An optional `INDEX` entry placed first in the tar, mapping entry paths to the
+//! position of their contents.
+//!
+//! In an uncompressed tar, see [`crate::layout`], [`ArchiveIndex::locate`](crate::index::ArchiveIndex::locate) slices the
+//! contents out directly. A compressed tar can't be seeked, so there the index only lets
+//! the scan stop at the matching entry instead of reading the archive to its end: the
+//! stream is still decompressed, and its headers walked, up to that entry.
+//!
+//! The index is a text file with one line per regular entry:
+//! `<offset> <size> <path>\n`, where `offset` is counted from the first byte
+//! following the `INDEX` entry itself. This way the index built for a tar stays
+//! valid once it was prepended to that tar, see [`prepend_index`](crate::index::prepend_index).
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use tar::{Archive, Entry, EntryType, Header};
+
+use crate::{
+    archive::path_from_bytes,
+    compression::{tar_archive, tar_entries},
+    sparse::{entry_layout, EntryLayout},
+    Error, Result,
+};
+
+/// Name of the index entry
+pub const INDEX_ENTRY_NAME: &str = "INDEX";
+
+const BLOCK_SIZE: u64 = 512;
+
+/// Build the contents of an `INDEX` entry describing every regular file in `tar`
+///
+/// Sparse entries, see [`crate::sparse`], are left out like links: their contents can't be
+/// sliced out of the tar, so they're only found by a scan.
+pub fn build_index(tar: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = tar_archive(tar);
+    let mut index = vec![];
+    for entry in tar_entries(&mut archive).map_err(Error::from_read)? {
+        let mut entry = entry.map_err(Error::from_read)?;
+        if !entry.header().entry_type().is_file()
+            || entry_layout(&mut entry).map_err(Error::from_read)? != EntryLayout::Contiguous
+        {
+            continue;
+        }
+        index.extend_from_slice(
+            format!("{} {} ", entry.raw_file_position(), entry.size()).as_bytes(),
+        );
+        index.extend_from_slice(&entry.path_bytes());
+        index.push(b'\n');
+    }
+    Ok(index)
+}
+
+/// Return a copy of `tar` with an `INDEX` entry describing it placed in front
+pub fn prepend_index(tar: &[u8]) -> Result<Vec<u8>> {
+    prepend_entry(INDEX_ENTRY_NAME, &build_index(tar)?, tar)
+}
+
+/// Return a copy of `tar` with a regular entry `name` holding `contents` placed in front
+pub(crate) fn prepend_entry(name: &str, contents: &[u8], tar: &[u8]) -> Result<Vec<u8>> {
+    let mut header = Header::new_gnu();
+    header.set_path(name).map_err(Error::Io)?;
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_entry_type(EntryType::Regular);
+    header.set_cksum();
+    let padding = (BLOCK_SIZE - contents.len() as u64 % BLOCK_SIZE) % BLOCK_SIZE;
+    let mut result = Vec::with_capacity(
+        tar.len()
+            .saturating_add(contents.len())
+            .saturating_add(2 * BLOCK_SIZE as usize),
+    );
+    result.extend_from_slice(header.as_bytes());
+    result.extend_from_slice(contents);
+    result.resize(result.len() + padding as usize, 0);
+    result.extend_from_slice(tar);
+    Ok(result)
+}
+
+/// A parsed `INDEX` entry, with offsets made absolute within the tar it was read from
+pub struct ArchiveIndex {
+    entries: Vec<(PathBuf, u64, u64)>,
+}
+
+impl ArchiveIndex {
+    /// Read the index from the first entry of `tar`
+    ///
+    /// Returns `None` if the first entry is not an index, or if it can't be parsed;
+    /// callers are expected to fall back to scanning the archive then
+    pub fn read(tar: &[u8]) -> Option<Self> {
+        let mut archive = Archive::new(tar);
+        let mut entry = archive.entries().ok()?.next()?.ok()?;
+        Self::from_entry(&mut entry)
+    }
+
+    /// Read the index from an entry of a tar being streamed, which should be the first one
+    ///
+    /// The contents of `entry` are only consumed if it is named `INDEX`, so the entry can
+    /// still be processed as usual when `None` is returned because it isn't an index
+    pub fn from_entry<R: Read>(entry: &mut Entry<R>) -> Option<Self> {
+        if entry.path().ok()?.as_ref() != Path::new(INDEX_ENTRY_NAME) {
+            return None;
+        }
+        let base = entry
+            .raw_file_position()
+            .checked_add(entry.size().checked_next_multiple_of(BLOCK_SIZE)?)?;
+        let mut data = vec![];
+        entry.read_to_end(&mut data).ok()?;
+        let mut entries = vec![];
+        for line in data.split(|v| *v == b'\n').filter(|v| !v.is_empty()) {
+            let mut fields = line.splitn(3, |v| *v == b' ');
+            let mut number =
+                || -> Option<u64> { std::str::from_utf8(fields.next()?).ok()?.parse().ok() };
+            let (offset, size) = (number()?, number()?);
+            let path = path_from_bytes(fields.next()?);
+            entries.push((path, base.checked_add(offset)?, size));
+        }
+        Some(Self { entries })
+    }
+
+    /// Position and size of the contents of `path`, as recorded by the index
+    ///
+    /// The position is absolute within the tar, i.e. comparable to
+    /// [`tar::Entry::raw_file_position`] of the entry while streaming the tar. If the path
+    /// is indexed more than once, the last entry wins, as when unpacking the tar
+    pub fn lookup(&self, path: impl AsRef<Path>) -> Option<(u64, u64)> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(entry_path, _, _)| entry_path == path.as_ref())
+            .map(|(_, offset, size)| (*offset, *size))
+    }
+
+    /// Iterate over the indexed paths, in archive order
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter().map(|(path, _, _)| path.as_path())
+    }
+
+    /// Locate the contents of `path` in `tar`
+    ///
+    /// The tar header preceding the contents is checked against the index, so a
+    /// stale index yields `None` rather than wrong bytes
+    pub fn locate<'a>(&self, tar: &'a [u8], path: impl AsRef<Path>) -> Option<&'a [u8]> {
+        let (offset, size) = self.lookup(path)?;
+        let header_start = usize::try_from(offset.checked_sub(BLOCK_SIZE)?).ok()?;
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(size).ok()?)?;
+        if end > tar.len() {
+            return None;
+        }
+        let header = Header::from_byte_slice(&tar[header_start..start]);
+        if header.entry_size().ok()? != size || !header.entry_type().is_file() {
+            return None;
+        }
+        Some(&tar[start..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{minimal_valid_btf, FixtureArchive};
+
+    const BTF_PATH: &str = "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf";
+
+    fn fixture() -> Vec<u8> {
+        FixtureArchive::new()
+            .file("btfhub-archive/README", b"readme".to_vec())
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                minimal_valid_btf(),
+            )
+            .symlink(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+                "5.4.0-40-generic.btf",
+            )
+            .tar()
+    }
+
+    #[test]
+    fn build_index_round_trips_through_lookup() {
+        let tar = prepend_index(&fixture()).unwrap();
+        let index = ArchiveIndex::read(&tar).unwrap();
+        assert_eq!(
+            index.paths().collect::<Vec<_>>(),
+            [Path::new("btfhub-archive/README"), Path::new(BTF_PATH)]
+        );
+        assert_eq!(
+            index.locate(&tar, "btfhub-archive/README"),
+            Some(&b"readme"[..])
+        );
+        assert_eq!(index.locate(&tar, BTF_PATH), Some(&minimal_valid_btf()[..]));
+        // 链接没有内容，不写入索引
+        assert_eq!(
+            index.lookup("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf"),
+            None
+        );
+    }
+
+    #[test]
+    fn lookup_positions_match_the_streamed_entries() {
+        let tar = prepend_index(&fixture()).unwrap();
+        let mut archive = Archive::new(&tar[..]);
+        let mut entries = archive.entries().unwrap();
+        let index = ArchiveIndex::from_entry(&mut entries.next().unwrap().unwrap()).unwrap();
+        let mut seen = 0;
+        for entry in entries {
+            let entry = entry.unwrap();
+            if let Some(position) = index.lookup(entry.path().unwrap()) {
+                assert_eq!(position, (entry.raw_file_position(), entry.size()));
+                seen += 1;
+            }
+        }
+        assert_eq!(seen, 2);
+    }
+
+    #[test]
+    fn path_stored_twice_locates_the_last_entry() {
+        let tar = prepend_index(
+            &FixtureArchive::new()
+                .file(BTF_PATH, b"stale".to_vec())
+                .file(BTF_PATH, minimal_valid_btf())
+                .tar(),
+        )
+        .unwrap();
+        let index = ArchiveIndex::read(&tar).unwrap();
+        assert_eq!(index.paths().count(), 2);
+        assert_eq!(index.locate(&tar, BTF_PATH), Some(&minimal_valid_btf()[..]));
+    }
+
+    #[test]
+    fn stale_index_locates_nothing() {
+        let index = ArchiveIndex::read(&prepend_index(&fixture()).unwrap()).unwrap();
+        let other = prepend_index(
+            &FixtureArchive::new()
+                .file("btfhub-archive/README", b"a longer readme".to_vec())
+                .tar(),
+        )
+        .unwrap();
+        assert_eq!(index.locate(&other, BTF_PATH), None);
+    }
+
+    #[test]
+    fn tar_without_index_has_none() {
+        assert!(ArchiveIndex::read(&fixture()).is_none());
+    }
+}
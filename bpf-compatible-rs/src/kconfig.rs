@@ -0,0 +1,209 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Whether the kernel was built with `CONFIG_DEBUG_INFO_BTF`, from its config.
+//!
+//! `/sys/kernel/btf/vmlinux` may be missing although the kernel has btf: it's only exported
+//! since 5.4, and may be hidden from a container or by an LSM. The config tells these
+//! apart from kernels without btf, and whether the kernel images on disk carry a `.BTF`
+//! section worth looking for. It's read from `/proc/config.gz` (with `CONFIG_IKCONFIG_PROC`)
+//! or `/boot/config-<release>`; when neither can be read, the answer is unknown.
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{current_kernel_release, native::RELEASE_PLACEHOLDER, system::under_root};
+
+/// Config of the running kernel, exported with `CONFIG_IKCONFIG_PROC`
+pub const PROC_CONFIG_PATH: &str = "/proc/config.gz";
+
+/// Config installed along with the kernel, with `{release}` standing for its release
+pub const BOOT_CONFIG_PATH: &str = "/boot/config-{release}";
+
+/// The option building the btf of the kernel
+pub const BTF_CONFIG_OPTION: &str = "CONFIG_DEBUG_INFO_BTF";
+
+/// Size a config may decompress to; configs are a few hundred KiB
+const MAX_CONFIG_SIZE: u64 = 16 << 20;
+
+/// Whether the running kernel was built with `CONFIG_DEBUG_INFO_BTF=y`
+///
+/// `None` if the kernel release or its config can't be read, see [`kernel_btf_config_of`].
+pub fn kernel_btf_config() -> Option<bool> {
+    kernel_btf_config_of(&current_kernel_release().ok()?, "/")
+}
+
+/// Whether the kernel `release` was built with `CONFIG_DEBUG_INFO_BTF=y`
+///
+/// `/proc/config.gz` is only read if `release` is the running kernel, whatever `root`;
+/// `/boot/config-<release>` is read under `root`, e.g. the host's root mounted into a
+/// container. The first config that can be read answers; `None` if there's none, which
+/// is never an error, since the config is only a hint.
+pub fn kernel_btf_config_of(release: &str, root: impl AsRef<Path>) -> Option<bool> {
+    let mut paths = vec![];
+    if is_running_kernel(release) {
+        paths.push(PathBuf::from(PROC_CONFIG_PATH));
+    }
+    paths.push(under_root(
+        root.as_ref(),
+        &BOOT_CONFIG_PATH.replace(RELEASE_PLACEHOLDER, release),
+    ));
+    paths.iter().find_map(|path| {
+        let config = read_config_file(path)?;
+        let enabled = parse_btf_config(&config);
+        log_at!(
+            Debug,
+            "{} of {}: {}",
+            BTF_CONFIG_OPTION,
+            path.display(),
+            match enabled {
+                Some(true) => "set",
+                Some(false) => "not set",
+                None => "not a kernel config",
+            }
+        );
+        enabled
+    })
+}
+
+/// Whether `release` is that of the running kernel, and not faked, see `fake`
+fn is_running_kernel(release: &str) -> bool {
+    #[cfg(feature = "fake-system")]
+    if crate::fake::is_kernel_faked() {
+        return false;
+    }
+    current_kernel_release().is_ok_and(|v| v == release)
+}
+
+/// The contents of the config at `path`, decompressed if it's gzipped
+pub fn read_config_file(path: impl AsRef<Path>) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    if !bytes.starts_with(&[0x1f, 0x8b]) {
+        return Some(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    let mut config = vec![];
+    GzDecoder::new(bytes.as_slice())
+        .take(MAX_CONFIG_SIZE)
+        .read_to_end(&mut config)
+        .ok()?;
+    Some(String::from_utf8_lossy(&config).into_owned())
+}
+
+/// Whether the kernel config `contents` sets `CONFIG_DEBUG_INFO_BTF=y`
+///
+/// A config that doesn't mention the option, like those of kernels predating it, doesn't
+/// set it. `None` if `contents` doesn't look like a kernel config at all.
+pub fn parse_btf_config(contents: &str) -> Option<bool> {
+    let mut is_config = false;
+    for line in contents.lines().map(str::trim) {
+        if let Some((name, value)) = line.split_once('=') {
+            if name == BTF_CONFIG_OPTION {
+                return Some(value == "y");
+            }
+            is_config |= name.starts_with("CONFIG_");
+        } else if let Some(name) = line
+            .strip_prefix("# ")
+            .and_then(|v| v.strip_suffix(" is not set"))
+        {
+            if name == BTF_CONFIG_OPTION {
+                return Some(false);
+            }
+            is_config |= name.starts_with("CONFIG_");
+        }
+    }
+    is_config.then_some(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    /// Not the running kernel, so `/proc/config.gz` is never read
+    const RELEASE: &str = "5.4.0-40-generic";
+
+    const UBUNTU_CONFIG: &str = "#\n\
+        # Automatically generated file; DO NOT EDIT.\n\
+        # Linux/x86 5.4.0-40-generic Kernel Configuration\n\
+        #\n\
+        CONFIG_CC_IS_GCC=y\n\
+        CONFIG_DEBUG_INFO=y\n\
+        CONFIG_DEBUG_INFO_BTF=y\n\
+        # CONFIG_DEBUG_INFO_BTF_MODULES is not set\n";
+
+    fn gzipped(contents: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(contents.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A root with `/boot/config-<RELEASE>` holding `contents`
+    fn root_with_config(contents: &[u8]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("boot")).unwrap();
+        fs::write(root.path().join(format!("boot/config-{RELEASE}")), contents).unwrap();
+        root
+    }
+
+    #[test]
+    fn option_is_read_whatever_its_form() {
+        assert_eq!(parse_btf_config(UBUNTU_CONFIG), Some(true));
+        for (config, expected) in [
+            ("CONFIG_DEBUG_INFO_BTF=n\n", Some(false)),
+            ("  CONFIG_DEBUG_INFO_BTF=y  \r\n", Some(true)),
+            ("# CONFIG_DEBUG_INFO_BTF is not set\n", Some(false)),
+            // 选项名只匹配整个名字
+            ("CONFIG_DEBUG_INFO_BTF_MODULES=y\n", Some(false)),
+            // 早于 btf 的内核的配置里没有这个选项
+            (
+                "CONFIG_DEBUG_INFO=y\n# CONFIG_KPROBES is not set\n",
+                Some(false),
+            ),
+            ("", None),
+            ("#\n# just comments\n", None),
+            ("PRETTY_NAME=\"Ubuntu 20.04\"\n", None),
+        ] {
+            assert_eq!(parse_btf_config(config), expected, "{config:?}");
+        }
+    }
+
+    #[test]
+    fn boot_config_is_read_plain_or_gzipped() {
+        for contents in [UBUNTU_CONFIG.as_bytes().to_vec(), gzipped(UBUNTU_CONFIG)] {
+            let root = root_with_config(&contents);
+            assert_eq!(kernel_btf_config_of(RELEASE, root.path()), Some(true));
+            assert_eq!(
+                read_config_file(root.path().join(format!("boot/config-{RELEASE}"))).as_deref(),
+                Some(UBUNTU_CONFIG)
+            );
+            // 只读取给定内核版本的配置
+            assert_eq!(kernel_btf_config_of("5.4.0-42-generic", root.path()), None);
+        }
+        let root = root_with_config(&gzipped("# CONFIG_DEBUG_INFO_BTF is not set\n"));
+        assert_eq!(kernel_btf_config_of(RELEASE, root.path()), Some(false));
+    }
+
+    #[test]
+    fn unreadable_configs_are_unknown() {
+        assert_eq!(kernel_btf_config_of(RELEASE, "/nonexistent"), None);
+        // 截断的压缩文件、目录或无关的内容都不会报错
+        let mut truncated = gzipped(UBUNTU_CONFIG);
+        truncated.truncate(truncated.len() / 2);
+        let root = root_with_config(&truncated);
+        assert_eq!(kernel_btf_config_of(RELEASE, root.path()), None);
+        let root = root_with_config(b"\x7fELF\x02\x01\x01");
+        assert_eq!(kernel_btf_config_of(RELEASE, root.path()), None);
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join(format!("boot/config-{RELEASE}"))).unwrap();
+        assert_eq!(read_config_file(root.path().join("boot")), None);
+        assert_eq!(kernel_btf_config_of(RELEASE, root.path()), None);
+    }
+}
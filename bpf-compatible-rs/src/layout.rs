@@ -0,0 +1,243 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! A random-access layout of the archive: an uncompressed tar whose btfs are gzipped one
+//! by one (`<release>.btf.gz`), starting with an `INDEX` entry. A lookup reads the index,
+//! jumps to the matching entry and only decompresses that one, instead of decompressing
+//! everything that comes before it in a classic `.tar.gz`.
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use tar::Builder;
+
+use crate::{
+    archive::{normalize_entry_path, BTFHUB_ARCHIVE_DIR},
+    compression::{tar_archive, tar_entries, tar_reader, ArchiveFormat},
+    index::{prepend_index, ArchiveIndex, INDEX_ENTRY_NAME},
+    listing::LISTING_ENTRY_NAME,
+    manifest::{prepend_manifest, Manifest, MANIFEST_ENTRY_NAME},
+    sparse::{entry_path, is_file_entry, read_entry, regular_header},
+    Error, Result,
+};
+
+/// Suffix of the btf entries to compress one by one
+const BTF_SUFFIX: &str = ".btf";
+/// Suffix appended to them once compressed
+const GZ_SUFFIX: &str = ".gz";
+
+/// Whether `bytes` is in the random-access layout, i.e. an uncompressed tar starting with an `INDEX` entry
+pub fn is_random_access(bytes: &[u8]) -> bool {
+    ArchiveFormat::detect(bytes).is_ok_and(|v| v == ArchiveFormat::Tar)
+        && ArchiveIndex::read(bytes).is_some()
+}
+
+/// Rewrite `archive` (any format of [`tar_reader`]) into the random-access layout
+///
+/// Every `.btf` file is gzipped with `level` into `.btf.gz`, and links to a `.btf` are
+/// renamed likewise; other entries are copied as they are. The `INDEX` is rebuilt, as is
+/// the `SHA256SUMS` manifest if `archive` had one, since the stored bytes change; the files
+/// it lists are checked against it first, so a corrupt archive isn't given a valid manifest.
+/// The listing `btfhub-archive/manifest.json`, of the paths before renaming, is left out.
+pub fn to_random_access(archive: &[u8], level: Compression) -> Result<Vec<u8>> {
+    let mut input = tar_archive(tar_reader(archive)?);
+    let mut builder = Builder::new(vec![]);
+    let mut manifest = None;
+    for entry in tar_entries(&mut input).map_err(Error::from_read)? {
+        let mut entry = entry.map_err(Error::from_read)?;
+        let path = entry_path(&mut entry).map_err(Error::from_read)?;
+        let name = normalize_entry_path(&path);
+        // 索引与摘要清单描述的是原先的条目，最后重新生成
+        if name == Path::new(INDEX_ENTRY_NAME) {
+            continue;
+        }
+        if name == Path::new(BTFHUB_ARCHIVE_DIR).join(LISTING_ENTRY_NAME) {
+            continue;
+        }
+        if name == Path::new(MANIFEST_ENTRY_NAME) {
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).map_err(Error::from_read)?;
+            manifest = Some(Manifest::parse(&contents));
+            continue;
+        }
+        // 稀疏条目重组后写为普通条目
+        let mut header = regular_header(entry.header());
+        let entry_type = header.entry_type();
+        if entry_type.is_hard_link() || entry_type.is_symlink() {
+            let Some(target) = entry.link_name().map_err(Error::from_read)? else {
+                continue;
+            };
+            builder
+                .append_link(&mut header, with_gz_suffix(&path), with_gz_suffix(&target))
+                .map_err(Error::Io)?;
+            continue;
+        }
+        let contents = read_entry(&mut entry).map_err(Error::from_read)?;
+        if let Some(manifest) = &manifest {
+            match manifest.verify(&path, &contents) {
+                Ok(()) | Err(Error::NotInManifest(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let (path, contents) = if is_file_entry(entry_type) && is_btf(&path) {
+            let mut encoder = GzEncoder::new(vec![], level);
+            encoder
+                .write_all(&contents)
+                .map_err(|e| Error::FileWriteError(path.display().to_string(), e))?;
+            let compressed = encoder
+                .finish()
+                .map_err(|e| Error::FileWriteError(path.display().to_string(), e))?;
+            (with_gz_suffix(&path), compressed)
+        } else {
+            (path, contents)
+        };
+        header.set_size(contents.len() as u64);
+        builder
+            .append_data(&mut header, &path, &contents[..])
+            .map_err(Error::Io)?;
+    }
+    let tar = builder.into_inner().map_err(Error::Io)?;
+    let tar = if manifest.is_some() {
+        prepend_manifest(&tar)?
+    } else {
+        tar
+    };
+    prepend_index(&tar)
+}
+
+fn is_btf(path: &Path) -> bool {
+    path.to_str().is_some_and(|v| v.ends_with(BTF_SUFFIX))
+}
+
+/// `path` with `.gz` appended if it names a btf
+fn with_gz_suffix(path: &Path) -> PathBuf {
+    if !is_btf(path) {
+        return path.to_path_buf();
+    }
+    let mut path = path.as_os_str().to_os_string();
+    path.push(GZ_SUFFIX);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::{
+        fixture::{btf_of_arch, FixtureArchive},
+        manifest::build_manifest,
+    };
+
+    /// Path, link target and contents of each entry of `tar`, in order
+    fn entries_of(tar: &[u8]) -> Vec<(String, Option<String>, Vec<u8>)> {
+        let mut archive = tar::Archive::new(tar);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().display().to_string();
+                let target = entry.link_name().unwrap().map(|v| v.display().to_string());
+                let mut contents = vec![];
+                entry.read_to_end(&mut contents).unwrap();
+                (path, target, contents)
+            })
+            .collect()
+    }
+
+    fn gunzip(bytes: &[u8]) -> Vec<u8> {
+        let mut contents = vec![];
+        GzDecoder::new(bytes).read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn btfs_are_gzipped_one_by_one_behind_an_index() {
+        let (a, b) = (btf_of_arch(8, "a"), btf_of_arch(8, "b"));
+        let classic = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", a.clone())
+            .file("btfhub-archive/README", b"readme".to_vec())
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-42-generic", b.clone())
+            .symlink(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+                "5.4.0-40-generic.btf",
+            )
+            .gz();
+        assert!(!is_random_access(&classic));
+        let converted = to_random_access(&classic, Compression::best()).unwrap();
+        assert!(is_random_access(&converted));
+
+        let entries = entries_of(&converted);
+        assert_eq!(entries[0].0, INDEX_ENTRY_NAME);
+        let dir = "btfhub-archive/ubuntu/20.04/x86_64";
+        let rest = entries[1..]
+            .iter()
+            .map(|(path, target, _)| (path.as_str(), target.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rest,
+            [
+                (format!("{dir}/5.4.0-40-generic.btf.gz").as_str(), None),
+                ("btfhub-archive/README", None),
+                (format!("{dir}/5.4.0-42-generic.btf.gz").as_str(), None),
+                (
+                    format!("{dir}/5.4.0-41-generic.btf.gz").as_str(),
+                    Some("5.4.0-40-generic.btf.gz")
+                ),
+            ]
+        );
+        assert_eq!(gunzip(&entries[1].2), a);
+        assert_eq!(entries[2].2, b"readme");
+        assert_eq!(gunzip(&entries[3].2), b);
+
+        // 索引指向压缩后的内容，可以直接定位
+        let index = ArchiveIndex::read(&converted).unwrap();
+        let path = format!("{dir}/5.4.0-42-generic.btf.gz");
+        assert_eq!(index.locate(&converted, &path), Some(&entries[3].2[..]));
+    }
+
+    #[test]
+    fn manifest_is_checked_then_rebuilt() {
+        let fixture = FixtureArchive::new().btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "a"),
+        );
+        let converted = to_random_access(
+            &prepend_manifest(&fixture.tar()).unwrap(),
+            Compression::fast(),
+        )
+        .unwrap();
+        let entries = entries_of(&converted);
+        assert_eq!(
+            [entries[0].0.as_str(), entries[1].0.as_str()],
+            [INDEX_ENTRY_NAME, MANIFEST_ENTRY_NAME]
+        );
+        // 新的清单描述压缩后的条目
+        let manifest = Manifest::parse(&entries[1].2);
+        manifest.verify(&entries[2].0, &entries[2].2).unwrap();
+
+        // 与清单不符的归档不会得到新的有效清单
+        let tampered = FixtureArchive::new()
+            .file(MANIFEST_ENTRY_NAME, build_manifest(&fixture.tar()).unwrap())
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "tampered"),
+            )
+            .tar();
+        assert!(matches!(
+            to_random_access(&tampered, Compression::fast()),
+            Err(Error::DigestMismatch(..))
+        ));
+    }
+}
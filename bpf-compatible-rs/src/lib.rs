@@ -3,32 +3,922 @@
 //! Copyright (c) 2023, eunomia-bpf
 //! All rights reserved.
 //!
-use std::path::{Path, PathBuf};
+#[cfg(all(feature = "host", target_os = "linux"))]
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+#[cfg(feature = "host")]
+use std::{io::Write, path::Path};
 
 pub use crate::error::Error;
+#[cfg(feature = "host")]
 pub use tar;
+#[cfg(feature = "host")]
 use tar::Archive;
+#[cfg(feature = "host")]
 pub use tempfile;
-use tempfile::{tempdir, TempDir};
+
+/// Third-party types that appear in the public API, re-exported so users don't need
+/// to depend on (possibly mismatching versions of) these crates themselves
+#[cfg(feature = "host")]
+pub mod reexport {
+    pub use flate2::{self, Compression};
+    pub use tar::{self, EntryType, Header};
+}
+#[cfg(feature = "host")]
+use tempfile::{tempdir, NamedTempFile, TempDir};
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Messages about what the lookup decided, for the application's logger
+#[macro_use]
+pub mod log;
+
 /// Errors of this library
 pub mod error;
 
+/// Validation of raw btf blobs
+#[cfg(feature = "host")]
+pub mod btf;
+
+/// Merging the split btf of a kernel module into the btf of its kernel
+#[cfg(feature = "host")]
+pub mod split;
+
+/// Names of architectures in btfhub-archive
+pub mod arch;
+
+/// Keeping the files written from an archive inside their destination
+#[cfg(feature = "host")]
+pub mod sanitize;
+
+/// Persistent cache of extracted btfs
+#[cfg(feature = "host")]
+pub mod cache;
+
+/// Btfs extracted once for the processes of a user, under content-derived names
+#[cfg(feature = "host")]
+pub mod shared;
+
+/// Detection of the compression format of an archive
+#[cfg(feature = "host")]
+pub mod compression;
+
+/// Progress of the decompression of large archives, and its cancellation
+#[cfg(feature = "host")]
+pub mod progress;
+
+/// Sparse entries, reassembled rather than sliced out of the tar
+#[cfg(feature = "host")]
+pub mod sparse;
+
+/// Streaming zstd decoder on top of the system libzstd
+#[cfg(feature = "zstd")]
+mod zstd;
+
+/// Streaming xz decoder on top of the system liblzma
+#[cfg(feature = "xz")]
+mod xz;
+
+/// Mapping between release versions and codenames
+pub mod codename;
+
+/// Mapping of derivative distros onto the distros btfhub covers
+pub mod derivative;
+
+/// Mapping of os-release IDs onto the directory names of distros
+pub mod distro;
+
+/// Detection of container runtimes sharing the host kernel
+#[cfg(feature = "host")]
+pub mod container;
+
+/// Hardlink-based deduplication of identical btfs on disk
+#[cfg(feature = "host")]
+pub mod dedup;
+
+/// Identity of an archive build
+#[cfg(feature = "host")]
+pub mod identity;
+
+/// Optional index entry for direct lookups in the tar archive
+#[cfg(feature = "host")]
+pub mod index;
+
+/// Random-access layout of the archive, with the btfs compressed one by one
+#[cfg(feature = "host")]
+pub mod layout;
+
+/// Pre-flight check of the CO-RE relocations of a BPF object against a btf
+#[cfg(feature = "host")]
+pub mod compat;
+
+/// Relocatable objects embedding the archive, instead of `ld -r -b binary`
+#[cfg(feature = "host")]
+pub mod embed;
+
+/// Deterministic packing of a directory of btfs into an archive, for build scripts
+#[cfg(feature = "host")]
+pub mod pack;
+
+/// Minimization of the btfs of an archive for given BPF objects, with bpftool
+#[cfg(feature = "minimize")]
+pub mod minimize;
+
+/// SHA-256, for the manifest
+#[cfg(feature = "host")]
+pub mod sha256;
+
+/// Optional manifest of the digests of the entries of the archive
+#[cfg(feature = "host")]
+pub mod manifest;
+
+/// Counts, sizes and the optional metadata entry of an archive
+#[cfg(feature = "host")]
+pub mod metadata;
+
+/// Optional `manifest.json` listing the btfs of an archive
+#[cfg(feature = "host")]
+pub mod listing;
+
+/// Lookups of btf candidates in an in-memory btfhub archive
+pub mod archive;
+
+/// Archives of btfs named after the kernel release alone, without the directories of btfhub
+pub mod flat;
+
+/// Ranking of the entries of an archive for a system, and the fallbacks of the match policies
+pub mod matcher;
+
+/// Archives read from a file, mapped into memory
+#[cfg(feature = "host")]
+pub mod mapped;
+
+/// Several archives searched in order
+#[cfg(feature = "host")]
+pub mod source;
+#[cfg(feature = "host")]
+pub use source::ArchiveSource;
+
+/// Lookups in a btfhub-archive unpacked on disk
+#[cfg(feature = "host")]
+pub mod directory;
+
+/// Archives decompressed and indexed once for repeated lookups
+#[cfg(feature = "host")]
+pub mod parsed;
+
+/// The archive embedded as a section of the executable, read back from `/proc/self/exe`
+#[cfg(feature = "host")]
+pub mod section;
+
+/// Reading the btf of an entry as it's decompressed
+#[cfg(feature = "host")]
+pub mod stream;
+
+/// Lookup and extraction of the btf of a system, the Rust counterpart of `bpf-compatible-sys`
+#[cfg(feature = "host")]
+pub mod tarball;
+#[cfg(feature = "host")]
+pub use tarball::TarballBtfArchive;
+
+/// The btf returned by [`ensure_core_btf`], removed on drop
+#[cfg(feature = "host")]
+pub mod ensured;
+
+#[cfg(feature = "host")]
+pub use ensured::{EnsureOptions, EnsuredBtf};
+
+/// Removal of the temporary btf files left behind
+#[cfg(feature = "host")]
+pub mod gc;
+
+/// The strategies of getting the btf, tried in order
+#[cfg(feature = "host")]
+pub mod chain;
+
+/// Setting the btf as `btf_custom_path` of a bpf object, e.g. with libbpf-rs
+#[cfg(feature = "host")]
+pub mod loader;
+
+/// Which btf a lookup settled on, and where it came from
+pub mod match_info;
+pub use match_info::{BtfSource, MatchInfo, NativeBtfStatus};
+
+/// Why a lookup provides no btf, for support tooling
+#[cfg(feature = "host")]
+pub mod diagnose;
+#[cfg(feature = "host")]
+pub use diagnose::{diagnose, Diagnosis};
+
+/// Parsing and comparison of kernel releases
+pub mod release;
+
+/// Normalization of distro versions to the directory names of btfhub
+pub mod version;
+
+/// Btfs of the kernel installed on disk
+#[cfg(feature = "host")]
+pub mod native;
+
+/// Whether the kernel was built with btf, from its config
+#[cfg(feature = "host")]
+pub mod kconfig;
+#[cfg(feature = "host")]
+pub use kconfig::kernel_btf_config;
+
+/// Identity of the running system, from uname and os-release
+pub mod system;
+pub use system::SystemInfo;
+
+/// Download of btfs from btfhub-archive
+#[cfg(feature = "download")]
+pub mod download;
+
+/// Generation of btfs from the vmlinux of a kernel with pahole
+#[cfg(feature = "pahole")]
+pub mod pahole;
+
+/// Durable audit trail of btf resolutions
+#[cfg(feature = "audit-log")]
+pub mod audit;
+
+/// A made-up identity of the running system, for testing lookups of other systems
+#[cfg(feature = "fake-system")]
+pub mod fake;
+
+/// Archives built in memory, for tests
+#[cfg(any(feature = "test-util", all(test, feature = "host")))]
+pub mod fixture;
+
+/// Get the release of the running kernel, as reported by uname
+#[cfg(feature = "host")]
+pub fn current_kernel_release() -> Result<String> {
+    Ok(system::uname()?.release)
+}
+
 /// Generate the btf archive path of the running kernel
 /// It returns somethings like `ubuntu/20.04/x86_64/xxxxxxx.btf
+///
+/// See [`generate_btf_archive_path_for`], which this calls with [`SystemInfo::detect`]
+#[cfg(feature = "host")]
 pub fn generate_current_system_btf_archive_path() -> Result<String> {
-    let release_info = os_release::OsRelease::new().map_err(Error::OsReleaseError)?;
-    let uname = uname_rs::Uname::new().map_err(Error::UnameError)?;
-    let btf_path = format!(
-        "{}/{}/{}/{}.btf",
-        release_info.id, release_info.version_id, uname.machine, uname.release
-    );
-    Ok(btf_path)
+    let path = SystemInfo::detect()?.to_string();
+    log_at!(Debug, "The btf of the running system is at {}", path);
+    Ok(path)
+}
+
+/// Generate the btf archive path of the system identified by `info`
+///
+/// The path is `<distro>/<version>/<arch>/<release>.btf`, e.g.
+/// `ubuntu/20.04/x86_64/5.4.0-40-generic.btf` or `centos/8/aarch64/4.18.0-348.el8.aarch64.btf`,
+/// relative to the `btfhub-archive` directory. The components are always joined with `/`,
+/// regardless of the platform separator, since that is what tar entry paths use.
+/// This is the `Display` of [`SystemInfo`].
+pub fn generate_btf_archive_path_for(info: &SystemInfo) -> PathBuf {
+    PathBuf::from(info.to_string())
+}
+
+/// Generate every btf archive path the running kernel may be stored under, most preferred first
+///
+/// Besides the version based path like `ubuntu/20.04/x86_64/xxxxxxx.btf`, archives may
+/// use the codename of the release, like `ubuntu/focal/x86_64/xxxxxxx.btf`, see
+/// [`generate_btf_archive_paths_for`].
+#[cfg(feature = "host")]
+pub fn generate_current_system_btf_archive_paths() -> Result<Vec<String>> {
+    Ok(generate_btf_archive_paths_for(&SystemInfo::detect()?))
+}
+
+/// Generate every btf archive path of the system identified by `info`, most preferred first
+///
+/// The architecture is named as in btfhub with [`arch::normalize_arch`], e.g. `arm64` for
+/// `aarch64`; the paths under its other names follow. For each architecture, the version
+/// based paths come first, then the codename based one. In each directory, the kernel
+/// release is tried verbatim, then without the trailing architecture of RHEL-likes, see
+/// [`release::release_variants`]. The version is
+/// normalized to the directory btfhub uses with [`version::normalize_version`], e.g.
+/// `centos/8` for `8.7`; if that changes it, the path of the raw `VERSION_ID` follows.
+/// The codename is `info.version_codename`, or from [`codename::codename_of`] if that's empty.
+/// Distros of the Enterprise Linux family get the paths of [`generate_el_btf_paths_for`]
+/// next, Ubuntu HWE kernels the paths of [`generate_hwe_btf_paths_for`], and rolling
+/// distros the paths of [`generate_generic_btf_paths_for`] last.
+pub fn generate_btf_archive_paths_for(info: &SystemInfo) -> Vec<String> {
+    let id = info.distro_id.as_str();
+    let raw_version_id = Some(info.version_id.as_str()).filter(|v| !v.is_empty());
+    let version_id = raw_version_id.map(|v| version::normalize_version(id, v));
+    let codename = Some(info.version_codename.as_str())
+        .filter(|v| !v.is_empty())
+        .or_else(|| version_id.and_then(|v| codename::codename_of(id, v)));
+    let mut arches = arch::arch_directories(&info.arch);
+    arches.push(&info.arch);
+    let file_names = release::release_variants(&info.kernel_release, &arches)
+        .into_iter()
+        .map(|v| format!("{}.btf", v))
+        .collect::<Vec<_>>();
+    let versions = [version_id, raw_version_id, codename]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let versions = if versions.is_empty() {
+        vec![""]
+    } else {
+        versions
+    };
+    let mut paths = vec![];
+    // 其他架构目录名（如 aarch64）排在 btfhub 使用的名称之后
+    for arch in arch::arch_directories(&info.arch) {
+        for version in &versions {
+            for file_name in &file_names {
+                let path = join_archive_path(&[id, version, arch, file_name]);
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+    for path in generate_el_btf_paths_for(info)
+        .into_iter()
+        .chain(generate_hwe_btf_paths_for(info))
+    {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    if distro::is_rolling(id) {
+        paths.extend(generate_generic_btf_paths_for(info));
+    }
+    paths
+}
+
+/// Generate the paths of the btf of `info` under the other directories of the Enterprise Linux family
+///
+/// Empty unless `info` is a distro of the family, see [`distro::el_directories`], e.g.
+/// `rhel/8/x86_64/4.18.0-477.10.1.el8_8.x86_64.btf` then the same under `centos/8` for
+/// Rocky 8.8. The version is the major one, normalized for each directory.
+pub fn generate_el_btf_paths_for(info: &SystemInfo) -> Vec<String> {
+    let Some(version_id) = Some(info.version_id.as_str()).filter(|v| !v.is_empty()) else {
+        return vec![];
+    };
+    let mut arches = arch::arch_directories(&info.arch);
+    arches.push(&info.arch);
+    let file_names = release::release_variants(&info.kernel_release, &arches);
+    let mut paths = vec![];
+    for arch in arch::arch_directories(&info.arch) {
+        for distro in distro::el_directories(&info.distro_id) {
+            let version = version::normalize_version(distro, version_id);
+            for file_name in &file_names {
+                let path =
+                    join_archive_path(&[distro, version, arch, &format!("{}.btf", file_name)]);
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+    paths
+}
+
+/// Generate the paths of the btf of `info` under the Ubuntu release its kernel is the series of
+///
+/// Empty unless `info` is an Ubuntu release running an HWE kernel, see
+/// [`version::ubuntu_hwe_version`], e.g. `ubuntu/22.04/x86_64/5.15.0-76-generic.btf` for
+/// `5.15.0-76-generic` on `20.04`. The paths under the version, then the codename, follow
+/// the paths of the release itself in [`generate_btf_archive_paths_for`].
+pub fn generate_hwe_btf_paths_for(info: &SystemInfo) -> Vec<String> {
+    if info.distro_id != "ubuntu" {
+        return vec![];
+    }
+    let Some(version) = version::ubuntu_hwe_version(&info.version_id, &info.kernel_release) else {
+        return vec![];
+    };
+    let versions = [Some(version), codename::codename_of("ubuntu", version)];
+    let mut arches = arch::arch_directories(&info.arch);
+    arches.push(&info.arch);
+    let file_names = release::release_variants(&info.kernel_release, &arches);
+    let mut paths = vec![];
+    for arch in arch::arch_directories(&info.arch) {
+        for version in versions.iter().flatten() {
+            for file_name in &file_names {
+                let path =
+                    join_archive_path(&["ubuntu", version, arch, &format!("{}.btf", file_name)]);
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+    paths
+}
+
+/// Generate the paths of the btf of the kernel a Debian backports kernel is built from
+///
+/// Empty unless `info` is Debian running a backports kernel of a known series, see
+/// [`version::debian_backport`], e.g. `debian/12/x86_64/6.1.0-13-amd64.btf` for
+/// `6.1.0-0.deb11.13-amd64` on `11`. The btf of that kernel isn't that of the backport,
+/// only the closest there is, so these paths aren't part of
+/// [`generate_btf_archive_paths_for`].
+pub fn generate_backport_btf_paths_for(info: &SystemInfo) -> Vec<String> {
+    if info.distro_id != "debian" {
+        return vec![];
+    }
+    let Some(version::DebianBackport {
+        release,
+        version: Some(version),
+    }) = version::debian_backport(&info.kernel_release)
+    else {
+        return vec![];
+    };
+    let versions = [Some(version), codename::codename_of("debian", version)];
+    let mut paths = vec![];
+    for arch in arch::arch_directories(&info.arch) {
+        for version in versions.iter().flatten() {
+            let path = join_archive_path(&["debian", version, arch, &format!("{}.btf", release)]);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+/// Generate the paths of the btf of `info` keyed by kernel release alone, as `generic/<arch>/<release>.btf`
+///
+/// These follow the paths of the distro for rolling distros, see [`distro::is_rolling`].
+/// The architecture and release are tried as in [`generate_btf_archive_paths_for`].
+pub fn generate_generic_btf_paths_for(info: &SystemInfo) -> Vec<String> {
+    let mut arches = arch::arch_directories(&info.arch);
+    arches.push(&info.arch);
+    let file_names = release::release_variants(&info.kernel_release, &arches);
+    let mut paths = vec![];
+    for arch in arch::arch_directories(&info.arch) {
+        for file_name in &file_names {
+            let path =
+                join_archive_path(&[distro::GENERIC_DISTRO, arch, &format!("{}.btf", file_name)]);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths
+}
+
+/// Generate every archive path the split btf of `module` may be stored under, for the system `info`
+///
+/// The btfs of the modules of a kernel are in the directory named after its btf, without
+/// the `.btf`: `<distro>/<version>/<arch>/<release>/modules/<module>.btf`, e.g.
+/// `ubuntu/20.04/x86_64/5.4.0-40-generic/modules/nf_tables.btf`. The paths follow the
+/// order of [`generate_btf_archive_paths_for`].
+pub fn generate_module_btf_paths_for(info: &SystemInfo, module: &str) -> Vec<String> {
+    generate_btf_archive_paths_for(info)
+        .into_iter()
+        .filter_map(|v| {
+            let kernel_dir = v.strip_suffix(".btf")?;
+            Some(join_archive_path(&[
+                kernel_dir,
+                MODULES_DIR,
+                &format!("{}.btf", module),
+            ]))
+        })
+        .collect()
+}
+
+/// Name of the directory holding the btfs of the modules of a kernel, see [`generate_module_btf_paths_for`]
+pub const MODULES_DIR: &str = "modules";
+
+/// Join path components of a tar entry with `/`
+///
+/// Backslashes inside the components are turned into `/` too, so the result never
+/// depends on how the platform would spell a path
+pub fn join_archive_path(components: &[&str]) -> String {
+    components
+        .iter()
+        .map(|v| v.replace('\\', "/"))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Where kernels built with `CONFIG_DEBUG_INFO_BTF` expose their own btf
+pub const VMLINUX_BTF_PATH: &str = "/sys/kernel/btf/vmlinux";
+
+/// Where kernels with btf expose it, `vmlinux` for the kernel and the name of each loaded module for its split btf
+pub const BTF_SYSFS_DIR: &str = "/sys/kernel/btf";
+
+/// Write a raw btf blob to a temporary file, unless the running kernel has native btf
+///
+/// This is for deployments where the kernel is known at build time, so a single btf is
+/// embedded instead of an archive. Returns `None` if the kernel exposes its own btf at
+/// [`VMLINUX_BTF_PATH`], and it's readable, see [`btf::check_btf_file`]. A `btf` that doesn't pass [`btf::validate_btf_bytes`] is
+/// rejected, rather than handing libbpf a file it can't parse.
+///
+/// Note: the file is deleted once the returned handle is dropped. Fails with
+/// [`Error::UnsupportedPlatform`] on other systems than Linux, which load no btf.
+#[cfg(feature = "host")]
+pub fn ensure_raw_btf(btf: &[u8]) -> Result<Option<NamedTempFile>> {
+    ensure_raw_btf_with_vmlinux(btf, Path::new(VMLINUX_BTF_PATH))
+}
+
+/// Same as [`ensure_raw_btf`], with the native btf of the kernel at `vmlinux`
+#[cfg(feature = "host")]
+fn ensure_raw_btf_with_vmlinux(btf: &[u8], vmlinux: &Path) -> Result<Option<NamedTempFile>> {
+    if cfg!(not(target_os = "linux")) {
+        return Err(Error::UnsupportedPlatform);
+    }
+    if is_native_btf(vmlinux) {
+        log_at!(Debug, "The kernel has native btf at {}", vmlinux.display());
+        return Ok(None);
+    }
+    btf::validate_btf_bytes(btf)?;
+    let mut file = tempfile::Builder::new()
+        .prefix(gc::TEMPFILE_PREFIX)
+        .tempfile()
+        .map_err(Error::TempDirError)?;
+    file.write_all(btf)
+        .map_err(|e| Error::FileWriteError(file.path().display().to_string(), e))?;
+    Ok(Some(file))
+}
+
+/// Write the btf of the running system from `tar` to a temporary file, unless the running kernel has native btf
+///
+/// This is what `ensure_core_btf_with_tar_binary` of `bpf-compatible-sys` does, without its
+/// options: returns `None` if the kernel exposes a readable btf at [`VMLINUX_BTF_PATH`],
+/// otherwise looks up [`SystemInfo::detect`] in `tar` with [`TarballBtfArchive`] and
+/// returns a file named like `/tmp/eunomia.btf.XXXXXX` holding the btf.
+///
+/// The file is removed when the returned [`EnsuredBtf`] is dropped, so keep it until the
+/// bpf object is loaded, or take it over with [`EnsuredBtf::keep`].
+#[cfg(feature = "host")]
+pub fn ensure_core_btf(tar: &[u8]) -> Result<Option<EnsuredBtf>> {
+    let btf = ensure_core_btf_always_path(tar)?;
+    Ok((!btf.is_borrowed()).then_some(btf))
+}
+
+/// Same as [`ensure_core_btf`], returning [`VMLINUX_BTF_PATH`] if the kernel has native btf
+///
+/// Like `always_path` of `bpf-compatible-sys`, for loaders that set `btf_custom_path`
+/// unconditionally. The native btf is left in place on drop.
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_always_path(tar: &[u8]) -> Result<EnsuredBtf> {
+    Ok(ensure_core_btf_matched(tar)?.0)
+}
+
+/// Same as [`ensure_core_btf`], also telling which btf was used
+///
+/// The [`MatchInfo`] names the entry of the archive and whether it's of the exact kernel
+/// release; if the kernel has native btf, the btf is `None` and the source
+/// [`BtfSource::Native`].
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_with_match(tar: &[u8]) -> Result<(Option<EnsuredBtf>, MatchInfo)> {
+    let (btf, matched) = ensure_core_btf_matched(tar)?;
+    Ok(((!btf.is_borrowed()).then_some(btf), matched))
+}
+
+/// Same as [`ensure_core_btf`], searching `sources` in order and using the first archive with a btf for the kernel
+///
+/// E.g. a base archive embedded into the binary, then a supplement for newly released
+/// kernels next to it. A missing file is skipped like an archive without the btf. If none
+/// has it, the first failure other than a miss is returned, see [`source`], or else the
+/// miss of the last archive.
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_multi(sources: &[ArchiveSource]) -> Result<Option<EnsuredBtf>> {
+    if has_native_btf() {
+        log_at!(Debug, "The kernel has native btf at {}", VMLINUX_BTF_PATH);
+        return Ok(None);
+    }
+    let mut first_error = None;
+    let mut last_miss = None;
+    for source in sources {
+        match source.extract_core_btf() {
+            Ok((btf, _)) => {
+                log_at!(Info, "Wrote the btf of {} to {}", source, btf.display());
+                return Ok(Some(btf));
+            }
+            Err(e) if source::is_miss(&e) => {
+                log_at!(Debug, "No btf in {}: {}", source, e);
+                last_miss = Some(e);
+            }
+            Err(e) => {
+                log_at!(Error, "Failed to look up {}: {}", source, e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    // 没有任何归档时，报告当前系统的 btf 路径
+    Err(first_error
+        .or(last_miss)
+        .unwrap_or_else(|| Error::EntryNotFound {
+            expected: generate_current_system_btf_archive_path()
+                .map(PathBuf::from)
+                .unwrap_or_default(),
+        }))
+}
+
+/// Same as [`ensure_core_btf`], with the options `ensure_core_btf_with_tar_binary_opts` of `bpf-compatible-sys` takes
+///
+/// The native btf is looked for, and the system detected, under the sysroot of `opts`.
+/// `EnsureOptions::default()` behaves like [`ensure_core_btf`]; the strategies tried are
+/// those of [`EnsureOptions::with_chain`].
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_with(tar: &[u8], opts: &EnsureOptions) -> Result<Option<EnsuredBtf>> {
+    ensure_core_btf_traced(tar, opts).0
+}
+
+/// Same as [`ensure_core_btf_with`], with how each strategy tried fared, for diagnostics
+///
+/// The attempts are in the order they were made; the strategies after the one ending the
+/// chain aren't tried, so they're missing. A chain with a strategy this build doesn't
+/// have fails with [`Error::StrategyUnavailable`] before any is tried.
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_traced(
+    tar: &[u8],
+    opts: &EnsureOptions,
+) -> (Result<Option<EnsuredBtf>>, Vec<chain::Attempt>) {
+    let (result, attempts) = chain::run(tar, opts);
+    (result.map(|(btf, _)| btf), attempts)
+}
+
+/// Same as [`ensure_core_btf_with`], also telling which btf was used, like [`ensure_core_btf_with_match`]
+///
+/// The source is that of the strategy which found the btf; if the kernel has native btf,
+/// the btf is `None` unless the options ask for the path, and the source
+/// [`BtfSource::Native`]. [`MatchInfo::native_btf`] tells why the native btf wasn't used.
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_with_match_opts(
+    tar: &[u8],
+    opts: &EnsureOptions,
+) -> Result<(Option<EnsuredBtf>, MatchInfo)> {
+    chain::run(tar, opts).0
+}
+
+/// Write `btf` to a temporary file in the tmpdir of `opts`, created if missing
+#[cfg(feature = "host")]
+fn write_ensured_btf(btf: &[u8], opts: &EnsureOptions) -> Result<EnsuredBtf> {
+    let btf = match &opts.tmpdir {
+        Some(dir) => {
+            let mut builder = std::fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(target_os = "linux")]
+            builder.mode(0o700);
+            builder
+                .create(dir)
+                .map_err(|e| Error::FileWriteError(dir.display().to_string(), e))?;
+            write_btf_tempfile_in(btf, dir, &opts.tempfile_template)?
+        }
+        None => write_btf_tempfile_in(btf, &std::env::temp_dir(), &opts.tempfile_template)?,
+    };
+    log_at!(Info, "Wrote the btf to {}", btf.display());
+    Ok(btf)
+}
+
+/// The lookup of [`ensure_core_btf_always_path`], with the btf it settled on
+#[cfg(feature = "host")]
+fn ensure_core_btf_matched(tar: &[u8]) -> Result<(EnsuredBtf, MatchInfo)> {
+    let native_btf = NativeBtfStatus::probe(Path::new(VMLINUX_BTF_PATH));
+    if native_btf == NativeBtfStatus::Usable {
+        log_at!(Debug, "The kernel has native btf at {}", VMLINUX_BTF_PATH);
+        return Ok((
+            EnsuredBtf::borrowed(PathBuf::from(VMLINUX_BTF_PATH)),
+            MatchInfo::native(current_kernel_release().unwrap_or_default()),
+        ));
+    }
+    let (btf, mut matched) = extract_core_btf(tar).inspect_err(|e| log_at!(Error, "{}", e))?;
+    matched.native_btf = native_btf;
+    log_at!(Info, "Wrote the btf to {}", btf.display());
+    Ok((btf, matched))
+}
+
+/// Get the split btf of the kernel module `module` of the running system, from `tar` if the kernel has none
+///
+/// Returns [`BTF_SYSFS_DIR`]`/<module>` if the kernel exposes it and it's readable. If
+/// the kernel has native btf but not for the module, e.g. because the module isn't
+/// loaded, returns `None`, leaving libbpf to find it once loaded. Otherwise the btf is
+/// looked up in `tar` as [`TarballBtfArchive::extract_module`] does, and written to a
+/// temporary file removed on drop. The module btf is split: libbpf needs the btf of the
+/// kernel, e.g. from [`ensure_core_btf`], to use it.
+#[cfg(feature = "host")]
+pub fn ensure_module_btf(tar: &[u8], module: &str) -> Result<Option<EnsuredBtf>> {
+    ensure_module_btf_in(tar, module, Path::new(BTF_SYSFS_DIR))
+}
+
+/// Same as [`ensure_module_btf`], with the btfs of the kernel looked for in `sysfs_dir` instead of [`BTF_SYSFS_DIR`]
+///
+/// E.g. for the host's sysfs mounted into a container.
+#[cfg(feature = "host")]
+pub fn ensure_module_btf_in(
+    tar: &[u8],
+    module: &str,
+    sysfs_dir: &Path,
+) -> Result<Option<EnsuredBtf>> {
+    pack::check_identity(&[module])?;
+    let sysfs = sysfs_dir.join(module);
+    if btf::check_btf_file(&sysfs).is_ok() {
+        log_at!(
+            Debug,
+            "The kernel has native btf for {} at {}",
+            module,
+            sysfs.display()
+        );
+        return Ok(Some(EnsuredBtf::borrowed(sysfs)));
+    }
+    let vmlinux = sysfs_dir.join("vmlinux");
+    if btf::check_btf_file(&vmlinux).is_ok() {
+        log_at!(
+            Debug,
+            "The kernel has native btf at {}, but none for {}",
+            vmlinux.display(),
+            module
+        );
+        return Ok(None);
+    }
+    let archive = TarballBtfArchive::from_gzipped_bytes(tar)?;
+    let btf = archive
+        .extract_module(&SystemInfo::detect()?, module)
+        .inspect_err(|e| log_at!(Error, "{}", e))?;
+    let btf = write_btf_tempfile(&btf)?;
+    log_at!(Info, "Wrote the btf of {} to {}", module, btf.display());
+    Ok(Some(btf))
+}
+
+/// Same as [`ensure_core_btf`], returning the btf instead of writing it to a file
+///
+/// For loaders that parse the btf themselves rather than taking a path, like aya:
+///
+/// ```ignore
+/// let btf = match ensure_core_btf_bytes(BTF_ARCHIVE)? {
+///     Some((entry, btf)) => Btf::parse(&btf, Endianness::default())
+///         .map_err(|e| anyhow!("{}: {}", entry.path.display(), e))?,
+///     None => Btf::from_sys_fs()?,
+/// };
+/// ```
+///
+/// With the `aya` feature, `ensure_btf_for_aya` does this.
+///
+/// Returns `None` if the kernel has native btf, otherwise the entry the btf comes from,
+/// to name it in errors, and the btf, decompressed and validated.
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_bytes(tar: &[u8]) -> Result<Option<(archive::BtfEntry, Vec<u8>)>> {
+    if has_native_btf() {
+        log_at!(Debug, "The kernel has native btf at {}", VMLINUX_BTF_PATH);
+        return Ok(None);
+    }
+    let archive = TarballBtfArchive::from_gzipped_bytes(tar)?;
+    let entry = archive
+        .lookup(&SystemInfo::detect()?)
+        .inspect_err(|e| log_at!(Error, "{}", e))?;
+    let btf = archive.extract(&entry)?;
+    Ok(Some((entry, btf)))
+}
+
+/// Same as [`ensure_core_btf_bytes`], parsing the btf into the [`Btf`](aya_obj::btf::Btf) of aya
+///
+/// Returns `None` if the kernel has native btf, for which aya is to be left to use
+/// `Btf::from_sys_fs()`. Fails with [`Error::AyaBtfError`], naming the entry, if aya can't
+/// parse the btf.
+#[cfg(feature = "aya")]
+pub fn ensure_btf_for_aya(tar: &[u8]) -> Result<Option<aya_obj::btf::Btf>> {
+    let Some((entry, btf)) = ensure_core_btf_bytes(tar)? else {
+        return Ok(None);
+    };
+    // 校验过的 btf 与本机字节序相同
+    aya_obj::btf::Btf::parse(&btf, object::Endianness::default())
+        .map(Some)
+        .map_err(|e| Error::AyaBtfError(entry.path.display().to_string(), e))
+}
+
+/// Same as [`ensure_core_btf_bytes`], writing the btf to `out` instead of returning it
+///
+/// For a file opened by the caller, e.g. with `O_TMPFILE` by a privileged helper, so no
+/// path is involved. The btf is written in full from the current position of `out`, which
+/// isn't rewound nor flushed. Returns `None` if the kernel has native btf, otherwise the
+/// entry and the number of bytes written; fails with [`Error::FileWriteError`], naming the
+/// entry, if writing fails.
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_write<W: Write>(
+    tar: &[u8],
+    out: &mut W,
+) -> Result<Option<(archive::BtfEntry, u64)>> {
+    let Some((entry, btf)) = ensure_core_btf_bytes(tar)? else {
+        return Ok(None);
+    };
+    // write_all 会重试部分写入和被信号中断的写入
+    out.write_all(&btf)
+        .map_err(|e| Error::FileWriteError(entry.path.display().to_string(), e))?;
+    Ok(Some((entry, btf.len() as u64)))
+}
+
+/// The lookup and extraction of [`ensure_core_btf`]
+#[cfg(feature = "host")]
+fn extract_core_btf(tar: &[u8]) -> Result<(EnsuredBtf, MatchInfo)> {
+    let archive = TarballBtfArchive::from_gzipped_bytes(tar)?;
+    let info = SystemInfo::detect()?;
+    let entry = archive.lookup(&info)?;
+    let btf = archive.extract(&entry)?;
+    btf::check_btf_arch(&btf, &[&entry.arch, &info.arch])?;
+    let btf = write_btf_tempfile(&btf)?;
+    Ok((btf, MatchInfo::of_entry(&entry, &info, BtfSource::Archive)))
+}
+
+/// Same as [`ensure_core_btf`], looking the btf up in the unpacked btfhub-archive at `dir`
+///
+/// See [`directory::BtfDirectory`]. A plain `<release>.btf` is returned as it is, borrowed,
+/// so it's never removed; a compressed one, like btfhub's `<release>.btf.tar.xz`, is
+/// extracted to a temporary file removed on drop. Returns `None` if the kernel has native btf.
+#[cfg(feature = "host")]
+pub fn ensure_core_btf_from_dir(dir: impl AsRef<Path>) -> Result<Option<EnsuredBtf>> {
+    if has_native_btf() {
+        log_at!(Debug, "The kernel has native btf at {}", VMLINUX_BTF_PATH);
+        return Ok(None);
+    }
+    let directory = directory::BtfDirectory::new(dir);
+    let entry = directory
+        .lookup(&SystemInfo::detect()?)
+        .inspect_err(|e| log_at!(Error, "{}", e))?;
+    if entry.encoding == archive::BtfEncoding::Plain {
+        btf::check_btf_file(&entry.path)?;
+        return Ok(Some(EnsuredBtf::borrowed(entry.path)));
+    }
+    let btf = write_btf_tempfile(&directory.extract(&entry)?)?;
+    log_at!(Info, "Wrote the btf to {}", btf.display());
+    Ok(Some(btf))
+}
+
+/// Whether the running kernel exposes a readable btf at [`VMLINUX_BTF_PATH`]
+///
+/// Never if the kernel release is faked, see `fake`, since the btf is that of the real kernel.
+#[cfg(feature = "host")]
+fn has_native_btf() -> bool {
+    is_native_btf(Path::new(VMLINUX_BTF_PATH))
+}
+
+/// Whether `path`, the native btf of the kernel, is readable, see [`has_native_btf`]
+#[cfg(feature = "host")]
+fn is_native_btf(path: &Path) -> bool {
+    NativeBtfStatus::probe(path) == NativeBtfStatus::Usable
+}
+
+/// Write `btf` to a file named like `/tmp/eunomia.btf.XXXXXX`, removed when the returned [`EnsuredBtf`] is dropped
+#[cfg(feature = "host")]
+fn write_btf_tempfile(btf: &[u8]) -> Result<EnsuredBtf> {
+    write_btf_tempfile_in(btf, &std::env::temp_dir(), &gc::TempfileTemplate::default())
+}
+
+/// Same as [`write_btf_tempfile`], creating the file in `dir` named after `template`
+#[cfg(feature = "host")]
+fn write_btf_tempfile_in(
+    btf: &[u8],
+    dir: &Path,
+    template: &gc::TempfileTemplate,
+) -> Result<EnsuredBtf> {
+    let mut file = tempfile::Builder::new()
+        .prefix(template.prefix())
+        .suffix(template.suffix())
+        .rand_bytes(gc::TEMPFILE_SUFFIX_LEN)
+        .tempfile_in(dir)
+        .map_err(Error::TempDirError)?;
+    file.write_all(btf)
+        .map_err(|e| Error::FileWriteError(file.path().display().to_string(), e))?;
+    let (_, path) = file
+        .keep()
+        .map_err(|e| Error::FileWriteError(e.file.path().display().to_string(), e.error))?;
+    gc::record_created_with(&path, template);
+    Ok(EnsuredBtf::extracted(path, btf.len() as u64))
+}
+
+/// Embed an archive in the crate and define `BTF_ARCHIVE` and `ensure_core_btf()` over it
+///
+/// The path is relative to the directory of the `Cargo.toml` of the crate using the macro,
+/// like `include_btf_archive!("assets/min_core_btfs.tar.gz")`; a missing file fails the
+/// build. This needs neither `ld -r -b binary` nor [`embed`]: the archive is an ordinary
+/// static, and the generated function calls [`ensure_core_btf`](fn@crate::ensure_core_btf) on it.
+///
+/// ```ignore
+/// bpf_compatible_rs::include_btf_archive!("assets/min_core_btfs.tar.gz");
+///
+/// let btf = ensure_core_btf()?;
+/// let btf_path = btf.as_deref();
+/// ```
+///
+/// The items are private; give a visibility first, e.g. `include_btf_archive!(pub, "...")`,
+/// to use them from other modules.
+#[cfg(feature = "host")]
+#[macro_export]
+macro_rules! include_btf_archive {
+    ($path:literal) => {
+        $crate::include_btf_archive!(, $path);
+    };
+    ($vis:vis, $path:literal) => {
+        /// The btf archive embedded by `include_btf_archive!`
+        $vis static BTF_ARCHIVE: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path));
+
+        /// The btf of the running system from [`BTF_ARCHIVE`], `None` if the kernel has native btf
+        $vis fn ensure_core_btf(
+        ) -> $crate::Result<::std::option::Option<$crate::EnsuredBtf>> {
+            $crate::ensure_core_btf(BTF_ARCHIVE)
+        }
+    };
 }
 
 /// Try to get the btf file of the running system under the archive directory
 // impl AsRef<Path> 将 archive_path 类型转为 &Path 类型
+#[cfg(feature = "host")]
 pub fn get_current_system_btf_file(archive_path: impl AsRef<Path>) -> Result<PathBuf> {
     Ok(archive_path
         .as_ref()
@@ -36,6 +926,7 @@ pub fn get_current_system_btf_file(archive_path: impl AsRef<Path>) -> Result<Pat
 }
 /// A helper type definition for simplicity
 // type 定义类型别名，这里 BtfArchive 定义为 Option<(PathBuf, TempDir)> 类型
+#[cfg(feature = "host")]
 pub type BtfArchive = Option<(PathBuf, TempDir)>;
 
 /// Unpack a tar archive, returning the contents of `package.json`;
@@ -45,14 +936,14 @@ pub type BtfArchive = Option<(PathBuf, TempDir)>;
 /// It will return the btf archive path and the temporary path to hold it, if applies
 ///
 /// Note: once the tempdir was destructed, the btf archive will be deleted
+#[cfg(feature = "host")]
 pub fn unpack_tar(tar_data: &[u8]) -> Result<(Vec<u8>, BtfArchive)> {
     // 创建一个针对于读者的存档对象
     let mut archive = Archive::new(tar_data);
     // tempdir
     let tmp_dir = tempdir().map_err(Error::TempDirError)?;
-    archive
-        .unpack(tmp_dir.path())
-        .map_err(Error::TarUnpackError)?;
+    // 条目路径不可信，不能写到临时目录之外
+    sanitize::unpack_within(&mut archive, tmp_dir.path())?;
 
     let json_object_buffer = std::fs::read(tmp_dir.path().join("package.json"))
         .map_err(|e| Error::FileReadError("package.json".to_string(), e))?;
@@ -64,3 +955,680 @@ pub fn unpack_tar(tar_data: &[u8]) -> Result<(Vec<u8>, BtfArchive)> {
     };
     Ok((json_object_buffer, btf_archive_path))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn ubuntu(kernel_release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: kernel_release.into(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "host")]
+    #[test]
+    fn native_btf_is_left_to_the_loader() {
+        // 内核自带 btf 时不读取归档
+        let result = ensure_core_btf_bytes(b"not an archive");
+        if has_native_btf() {
+            assert!(result.unwrap().is_none());
+        } else {
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn archive_paths_are_joined_with_forward_slashes() {
+        assert_eq!(
+            generate_btf_archive_path_for(&ubuntu("5.4.0-40-generic")),
+            Path::new("ubuntu/20.04/x86_64/5.4.0-40-generic.btf")
+        );
+        assert_eq!(
+            join_archive_path(&["./btfhub-archive", "ubuntu\\20.04", "x86_64"]),
+            "./btfhub-archive/ubuntu/20.04/x86_64"
+        );
+    }
+
+    #[test]
+    fn archive_paths_parse_back_into_entries() {
+        use crate::archive::{BtfEncoding, BtfEntry};
+
+        // 路径的计算和解析互为逆运算，都不需要文件系统
+        let info = SystemInfo::from_fields(
+            &[("ID", "debian"), ("VERSION_ID", "11")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+            "aarch64".into(),
+            "5.10.0-23-arm64".into(),
+            String::new(),
+        )
+        .unwrap();
+        let path = Path::new("btfhub-archive").join(generate_btf_archive_path_for(&info));
+        let entry = BtfEntry::from_path(&path, "btfhub-archive").unwrap();
+        assert_eq!(entry.kernel(), "debian/11/arm64/5.10.0-23-arm64");
+        assert_eq!(entry.kernel_release, info.kernel_release);
+        assert_eq!((entry.size, entry.encoding), (0, BtfEncoding::Plain));
+        assert_eq!(entry.path, path);
+
+        for (path, kernel, encoding) in [
+            (
+                "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz",
+                "ubuntu/20.04/x86_64/5.4.0-40-generic",
+                BtfEncoding::Gzipped,
+            ),
+            (
+                "/btfhub-archive/fedora/34/x86_64/5.11.12-300.fc34.x86_64.btf.tar.xz",
+                "fedora/34/x86_64/5.11.12-300.fc34.x86_64",
+                BtfEncoding::Tarball,
+            ),
+            (
+                "btfhub-archive/generic/x86_64/6.1.0.btf",
+                "generic/x86_64/6.1.0",
+                BtfEncoding::Plain,
+            ),
+        ] {
+            let entry = BtfEntry::from_path(path, "./btfhub-archive").unwrap();
+            assert_eq!((entry.kernel(), entry.encoding), (kernel.into(), encoding));
+        }
+        for path in [
+            "btfhub-archive/ubuntu/5.4.0-40-generic.btf",
+            "btfhub-archive/ubuntu/20.04/x86_64/.btf",
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.ko",
+            "other/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+        ] {
+            assert_eq!(BtfEntry::from_path(path, "btfhub-archive"), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn archive_path_of_each_distro() {
+        for (os_release, arch, release, expected) in [
+            (
+                "ID=ubuntu\nVERSION_ID=\"20.04\"",
+                "x86_64",
+                "5.4.0-40-generic",
+                "ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            ),
+            (
+                "ID=debian\nVERSION_ID=\"11\"",
+                "aarch64",
+                "5.10.0-23-arm64",
+                "debian/11/arm64/5.10.0-23-arm64.btf",
+            ),
+            (
+                "ID=\"centos\"\nVERSION_ID=\"8\"",
+                "x86_64",
+                "4.18.0-348.el8.x86_64",
+                "centos/8/x86_64/4.18.0-348.el8.x86_64.btf",
+            ),
+            (
+                "ID=fedora\nVERSION_ID=34",
+                "x86_64",
+                "5.11.12-300.fc34.x86_64",
+                "fedora/34/x86_64/5.11.12-300.fc34.x86_64.btf",
+            ),
+            // openEuler 的目录名为小写，LTS 版本附在版本号之后
+            (
+                "ID=\"openEuler\"\nVERSION=\"22.03 (LTS-SP1)\"\nVERSION_ID=\"22.03\"",
+                "aarch64",
+                "5.10.0-136.12.0.86.oe2203sp1.aarch64",
+                "openeuler/22.03-LTS-SP1/arm64/5.10.0-136.12.0.86.oe2203sp1.aarch64.btf",
+            ),
+        ] {
+            let info =
+                SystemInfo::from_os_release(os_release, arch.into(), release.into(), String::new())
+                    .unwrap();
+            assert_eq!(generate_btf_archive_path_for(&info), Path::new(expected));
+            assert_eq!(info.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn archive_path_of_cloud_and_china_region_distros() {
+        // 系统的标识保留 os-release 中的版本号，查找时先用目录名的形式
+        for (os_release, arch, release, identity, first) in [
+            (
+                "NAME=\"Amazon Linux\"\nVERSION=\"2\"\nID=\"amzn\"\nID_LIKE=\"centos rhel fedora\"\n\
+                 VERSION_ID=\"2\"\nPRETTY_NAME=\"Amazon Linux 2\"\n",
+                "x86_64",
+                "5.10.184-175.731.amzn2.x86_64",
+                "amzn/2/x86_64/5.10.184-175.731.amzn2.x86_64.btf",
+                "amzn/2/x86_64/5.10.184-175.731.amzn2.x86_64.btf",
+            ),
+            (
+                "NAME=\"Amazon Linux\"\nVERSION=\"2023\"\nID=\"amzn\"\nID_LIKE=\"fedora\"\n\
+                 VERSION_ID=\"2023\"\n",
+                "aarch64",
+                "6.1.41-63.114.amzn2023.aarch64",
+                "amzn/2023/arm64/6.1.41-63.114.amzn2023.aarch64.btf",
+                "amzn/2023/arm64/6.1.41-63.114.amzn2023.aarch64.btf",
+            ),
+            (
+                "NAME=\"openEuler\"\nVERSION=\"20.03 (LTS-SP3)\"\nID=\"openEuler\"\n\
+                 VERSION_ID=\"20.03\"\n",
+                "x86_64",
+                "4.19.90-2112.8.0.0131.oe1.x86_64",
+                "openeuler/20.03-LTS-SP3/x86_64/4.19.90-2112.8.0.0131.oe1.x86_64.btf",
+                "openeuler/20.03/x86_64/4.19.90-2112.8.0.0131.oe1.x86_64.btf",
+            ),
+            // Anolis 的 ID_LIKE 是 rhel，但内核是自己的
+            (
+                "NAME=\"Anolis OS\"\nVERSION=\"8.6\"\nID=\"anolis\"\nID_LIKE=\"rhel fedora centos\"\n\
+                 VERSION_ID=\"8.6\"\n",
+                "x86_64",
+                "5.10.134-12.an8.x86_64",
+                "anolis/8.6/x86_64/5.10.134-12.an8.x86_64.btf",
+                "anolis/8/x86_64/5.10.134-12.an8.x86_64.btf",
+            ),
+            (
+                "NAME=\"Kylin Linux Advanced Server\"\nVERSION=\"V10 (Sword)\"\nID=\"kylin\"\n\
+                 VERSION_ID=\"V10\"\n",
+                "aarch64",
+                "4.19.90-24.4.v2101.ky10.aarch64",
+                "kylin/V10/arm64/4.19.90-24.4.v2101.ky10.aarch64.btf",
+                "kylin/10/arm64/4.19.90-24.4.v2101.ky10.aarch64.btf",
+            ),
+            (
+                "NAME=\"Oracle Linux Server\"\nVERSION=\"8.8\"\nID=\"ol\"\nID_LIKE=\"fedora\"\n\
+                 VERSION_ID=\"8.8\"\n",
+                "x86_64",
+                "5.15.0-101.103.2.1.el8uek.x86_64",
+                "ol/8.8/x86_64/5.15.0-101.103.2.1.el8uek.x86_64.btf",
+                "ol/8/x86_64/5.15.0-101.103.2.1.el8uek.x86_64.btf",
+            ),
+            // 未知的发行版保持原样
+            (
+                "ID=nosuchos\nVERSION_ID=\"1.2\"\n",
+                "x86_64",
+                "6.1.0",
+                "nosuchos/1.2/x86_64/6.1.0.btf",
+                "nosuchos/1.2/x86_64/6.1.0.btf",
+            ),
+        ] {
+            let info =
+                SystemInfo::from_os_release(os_release, arch.into(), release.into(), String::new())
+                    .unwrap();
+            assert_eq!(info.to_string(), identity);
+            let paths = generate_btf_archive_paths_for(&info);
+            assert_eq!(paths[0], first);
+            assert!(paths.iter().any(|v| v == identity), "{paths:?}");
+        }
+    }
+
+    #[test]
+    fn raw_version_paths_follow_the_normalized_ones() {
+        let info = SystemInfo {
+            distro_id: "centos".into(),
+            version_id: "8.7".into(),
+            kernel_release: "4.18.0-425.3.1.el8.x86_64".into(),
+            ..ubuntu("")
+        };
+        let paths = generate_btf_archive_paths_for(&info);
+        let position = |path: &str| paths.iter().position(|v| v == path);
+        let normalized = position("centos/8/x86_64/4.18.0-425.3.1.el8.x86_64.btf");
+        let raw = position("centos/8.7/x86_64/4.18.0-425.3.1.el8.x86_64.btf");
+        assert_eq!(normalized, Some(0));
+        assert!(raw > normalized, "{paths:?}");
+        // 归一化不改变版本号时不重复
+        let paths = generate_btf_archive_paths_for(&ubuntu("5.4.0-40-generic"));
+        assert_eq!(
+            paths
+                .iter()
+                .filter(|v| *v == "ubuntu/20.04/x86_64/5.4.0-40-generic.btf")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn alias_arch_paths_follow_the_btfhub_ones() {
+        let info = SystemInfo {
+            arch: "aarch64".into(),
+            ..ubuntu("5.4.0-40-generic")
+        };
+        assert_eq!(
+            generate_btf_archive_path_for(&info),
+            Path::new("ubuntu/20.04/arm64/5.4.0-40-generic.btf")
+        );
+        let paths = generate_btf_archive_paths_for(&info);
+        let position = |path: &str| paths.iter().position(|v| v == path);
+        let canonical = position("ubuntu/20.04/arm64/5.4.0-40-generic.btf");
+        let alias = position("ubuntu/20.04/aarch64/5.4.0-40-generic.btf");
+        assert_eq!(canonical, Some(0));
+        assert!(alias > canonical, "{paths:?}");
+    }
+
+    #[test]
+    fn paths_of_32_bit_machines() {
+        let i686 = SystemInfo {
+            distro_id: "debian".into(),
+            version_id: "10".into(),
+            arch: "i686".into(),
+            kernel_release: "4.19.0-21-686-pae".into(),
+            ..Default::default()
+        };
+        let paths = generate_btf_archive_paths_for(&i686);
+        assert_eq!(paths[0], "debian/10/x86/4.19.0-21-686-pae.btf");
+        // uname 的名字排在 btfhub 的目录名之后，其他的 x86 名字也会尝试
+        for arch in ["i686", "i386"] {
+            assert!(
+                paths.contains(&format!("debian/10/{arch}/4.19.0-21-686-pae.btf")),
+                "{paths:?}"
+            );
+        }
+        assert!(paths.iter().all(|v| !v.contains("x86_64")), "{paths:?}");
+
+        let armv7l = SystemInfo {
+            arch: "armv7l".into(),
+            ..ubuntu("5.4.0-1069-raspi")
+        };
+        let paths = generate_btf_archive_paths_for(&armv7l);
+        assert_eq!(paths[0], "ubuntu/20.04/arm/5.4.0-1069-raspi.btf");
+        let position = |path: &str| paths.iter().position(|v| v == path);
+        let mirror = position("ubuntu/20.04/armv7l/5.4.0-1069-raspi.btf");
+        assert!(mirror.is_some_and(|v| v > 0), "{paths:?}");
+        assert!(paths.iter().all(|v| !v.contains("arm64")), "{paths:?}");
+
+        // 以架构结尾的 RHEL 系内核版本，去掉的是 uname 报告的后缀
+        let centos = SystemInfo {
+            distro_id: "centos".into(),
+            version_id: "7".into(),
+            arch: "i686".into(),
+            kernel_release: "3.10.0-1160.el7.i686".into(),
+            ..Default::default()
+        };
+        let paths = generate_btf_archive_paths_for(&centos);
+        assert_eq!(paths[0], "centos/7/x86/3.10.0-1160.el7.i686.btf");
+        assert!(
+            paths.contains(&"centos/7/x86/3.10.0-1160.el7.btf".to_string()),
+            "{paths:?}"
+        );
+    }
+
+    #[test]
+    fn rolling_distros_fall_back_to_the_generic_tree() {
+        let arch = SystemInfo::from_os_release(
+            "NAME=\"Arch Linux\"\nID=arch\nBUILD_ID=rolling\n",
+            "x86_64".into(),
+            "6.5.3-arch1-1".into(),
+            String::new(),
+        )
+        .unwrap();
+        assert_eq!(arch.version_id, "");
+        let paths = generate_btf_archive_paths_for(&arch);
+        let generic = generate_generic_btf_paths_for(&arch);
+        assert_eq!(
+            generic,
+            [
+                "generic/x86_64/6.5.3-arch1-1.btf",
+                "generic/amd64/6.5.3-arch1-1.btf"
+            ]
+        );
+        // generic 目录排在发行版自己的路径之后
+        assert!(paths.ends_with(&generic), "{paths:?}");
+        assert!(paths[0].starts_with("arch/"), "{paths:?}");
+        // 其他发行版只在设置了 match_any_distro 时才查找 generic 目录
+        let ubuntu = ubuntu("5.4.0-40-generic");
+        assert!(generate_btf_archive_paths_for(&ubuntu)
+            .iter()
+            .all(|v| !v.starts_with("generic/")));
+        // 非滚动发行版仍然需要版本号
+        assert!(matches!(
+            SystemInfo::from_os_release(
+                "ID=ubuntu\n",
+                "x86_64".into(),
+                "5.4.0".into(),
+                String::new()
+            ),
+            Err(Error::MissingOsReleaseField("VERSION_ID"))
+        ));
+    }
+
+    #[test]
+    fn hwe_kernels_are_also_looked_up_under_their_series() {
+        let info = ubuntu("5.15.0-1041-azure");
+        assert_eq!(
+            generate_hwe_btf_paths_for(&info),
+            [
+                "ubuntu/22.04/x86_64/5.15.0-1041-azure.btf",
+                "ubuntu/jammy/x86_64/5.15.0-1041-azure.btf",
+                "ubuntu/22.04/amd64/5.15.0-1041-azure.btf",
+                "ubuntu/jammy/amd64/5.15.0-1041-azure.btf",
+            ]
+        );
+        // 排在系统自己的版本目录之后
+        let paths = generate_btf_archive_paths_for(&info);
+        assert_eq!(paths[0], "ubuntu/20.04/x86_64/5.15.0-1041-azure.btf");
+        let position = |path: &str| paths.iter().position(|v| v == path).unwrap();
+        assert!(
+            position("ubuntu/22.04/x86_64/5.15.0-1041-azure.btf")
+                > position("ubuntu/focal/x86_64/5.15.0-1041-azure.btf"),
+            "{paths:?}"
+        );
+        // 原生内核、其他发行版没有 HWE 路径
+        assert!(generate_hwe_btf_paths_for(&ubuntu("5.4.0-40-generic")).is_empty());
+        let debian = SystemInfo {
+            distro_id: "debian".into(),
+            version_id: "11".into(),
+            ..ubuntu("5.15.0-76-generic")
+        };
+        assert!(generate_hwe_btf_paths_for(&debian).is_empty());
+    }
+
+    #[test]
+    fn debian_is_keyed_by_its_major_release() {
+        let debian = |os_release: &str, release: &str| {
+            SystemInfo::from_os_release(os_release, "x86_64".into(), release.into(), String::new())
+                .unwrap()
+        };
+        let bullseye = debian(
+            "PRETTY_NAME=\"Debian GNU/Linux 11 (bullseye)\"\nID=debian\nVERSION_ID=\"11\"\nVERSION_CODENAME=bullseye\n",
+            "5.10.0-23-amd64",
+        );
+        let paths = generate_btf_archive_paths_for(&bullseye);
+        assert_eq!(paths[0], "debian/11/x86_64/5.10.0-23-amd64.btf");
+        assert!(
+            paths.contains(&"debian/bullseye/x86_64/5.10.0-23-amd64.btf".to_string()),
+            "{paths:?}"
+        );
+        // 点版本归入主版本的目录
+        let bookworm = debian(
+            "ID=debian\nVERSION_ID=\"12.5\"\nVERSION_CODENAME=bookworm\n",
+            "6.1.0-18-amd64",
+        );
+        let paths = generate_btf_archive_paths_for(&bookworm);
+        assert_eq!(paths[0], "debian/12/x86_64/6.1.0-18-amd64.btf");
+        assert!(
+            paths.contains(&"debian/bookworm/x86_64/6.1.0-18-amd64.btf".to_string()),
+            "{paths:?}"
+        );
+        // 以 - 连接架构的 flavor 属于内核版本，不被去掉
+        assert!(
+            paths.iter().all(|v| v.ends_with("/6.1.0-18-amd64.btf")),
+            "{paths:?}"
+        );
+    }
+
+    #[test]
+    fn backports_kernels_fall_back_to_the_release_they_come_from() {
+        let backport = SystemInfo {
+            distro_id: "debian".into(),
+            version_id: "11".into(),
+            arch: "x86_64".into(),
+            kernel_release: "6.1.0-0.deb11.13-amd64".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            generate_backport_btf_paths_for(&backport),
+            [
+                "debian/12/x86_64/6.1.0-13-amd64.btf",
+                "debian/bookworm/x86_64/6.1.0-13-amd64.btf",
+                "debian/12/amd64/6.1.0-13-amd64.btf",
+                "debian/bookworm/amd64/6.1.0-13-amd64.btf",
+            ]
+        );
+        // 不属于精确匹配的路径
+        let paths = generate_btf_archive_paths_for(&backport);
+        assert_eq!(paths[0], "debian/11/x86_64/6.1.0-0.deb11.13-amd64.btf");
+        assert!(
+            paths.iter().all(|v| !v.starts_with("debian/12/")),
+            "{paths:?}"
+        );
+        // 未知系列、发行版自己的内核和其他发行版都没有
+        for (distro_id, release) in [
+            ("debian", "5.16.0-0.bpo.4-amd64"),
+            ("debian", "5.10.0-23-amd64"),
+            ("ubuntu", "6.1.0-0.deb11.13-amd64"),
+        ] {
+            let info = SystemInfo {
+                distro_id: distro_id.into(),
+                kernel_release: release.into(),
+                ..backport.clone()
+            };
+            assert!(
+                generate_backport_btf_paths_for(&info).is_empty(),
+                "{release}"
+            );
+        }
+    }
+
+    #[test]
+    fn enterprise_linux_is_looked_up_across_the_family() {
+        let rhel_8_8 = "NAME=\"Red Hat Enterprise Linux\"\nVERSION=\"8.8 (Ootpa)\"\nID=\"rhel\"\n\
+                        ID_LIKE=\"fedora\"\nVERSION_ID=\"8.8\"\nPLATFORM_ID=\"platform:el8\"\n\
+                        PRETTY_NAME=\"Red Hat Enterprise Linux 8.8 (Ootpa)\"\n";
+        let rocky_9 = "NAME=\"Rocky Linux\"\nVERSION=\"9.2 (Blue Onyx)\"\nID=\"rocky\"\n\
+                       ID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"9.2\"\n\
+                       PLATFORM_ID=\"platform:el9\"\nPRETTY_NAME=\"Rocky Linux 9.2 (Blue Onyx)\"\n";
+        let alma_8 = "NAME=\"AlmaLinux\"\nVERSION=\"8.9 (Midnight Oncilla)\"\nID=\"almalinux\"\n\
+                      ID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"8.9\"\n\
+                      PLATFORM_ID=\"platform:el8\"\n";
+        let stream_9 = "NAME=\"CentOS Stream\"\nVERSION=\"9\"\nID=\"centos\"\n\
+                        ID_LIKE=\"rhel fedora\"\nVERSION_ID=\"9\"\nPLATFORM_ID=\"platform:el9\"\n\
+                        PRETTY_NAME=\"CentOS Stream 9\"\n";
+        for (os_release, release, distro, expected) in [
+            (
+                rhel_8_8,
+                "4.18.0-477.10.1.el8_8.x86_64",
+                "rhel",
+                &[
+                    "rhel/8/x86_64/4.18.0-477.10.1.el8_8.x86_64.btf",
+                    "centos/8/x86_64/4.18.0-477.10.1.el8_8.x86_64.btf",
+                ][..],
+            ),
+            (
+                rocky_9,
+                "5.14.0-284.11.1.el9_2.x86_64",
+                "rocky",
+                &[
+                    "rocky/9/x86_64/5.14.0-284.11.1.el9_2.x86_64.btf",
+                    "rhel/9/x86_64/5.14.0-284.11.1.el9_2.x86_64.btf",
+                    "centos/9/x86_64/5.14.0-284.11.1.el9_2.x86_64.btf",
+                ],
+            ),
+            (
+                alma_8,
+                "4.18.0-513.5.1.el8_9.x86_64",
+                "almalinux",
+                &[
+                    "almalinux/8/x86_64/4.18.0-513.5.1.el8_9.x86_64.btf",
+                    "rhel/8/x86_64/4.18.0-513.5.1.el8_9.x86_64.btf",
+                    "centos/8/x86_64/4.18.0-513.5.1.el8_9.x86_64.btf",
+                ],
+            ),
+            // Stream 的内核有自己的目录，之后才是 centos 和 rhel
+            (
+                stream_9,
+                "5.14.0-378.el9.x86_64",
+                distro::CENTOS_STREAM,
+                &[
+                    "centos-stream/9/x86_64/5.14.0-378.el9.x86_64.btf",
+                    "centos/9/x86_64/5.14.0-378.el9.x86_64.btf",
+                    "rhel/9/x86_64/5.14.0-378.el9.x86_64.btf",
+                ],
+            ),
+        ] {
+            let info = SystemInfo::from_os_release(
+                os_release,
+                "x86_64".into(),
+                release.into(),
+                String::new(),
+            )
+            .unwrap();
+            assert_eq!(info.distro_id, distro);
+            let paths = generate_btf_archive_paths_for(&info);
+            let position = |path: &str| {
+                paths
+                    .iter()
+                    .position(|v| v == path)
+                    .unwrap_or_else(|| panic!("{path} not in {paths:?}"))
+            };
+            // 按家族中的顺序排列
+            let positions = expected.iter().map(|v| position(v)).collect::<Vec<_>>();
+            assert_eq!(positions[0], 0, "{paths:?}");
+            assert!(positions.windows(2).all(|v| v[0] < v[1]), "{paths:?}");
+            // 去掉架构后缀的版本同样查找
+            let stripped = expected[1].replace(".x86_64.btf", ".btf");
+            assert!(paths.contains(&stripped), "{paths:?}");
+        }
+        // 家族之外的发行版没有这些路径
+        assert!(generate_el_btf_paths_for(&ubuntu("5.4.0-40-generic")).is_empty());
+        let anolis = SystemInfo {
+            distro_id: "anolis".into(),
+            version_id: "8".into(),
+            ..ubuntu("4.18.0-477.13.1.0.1.an8.x86_64")
+        };
+        assert!(generate_el_btf_paths_for(&anolis).is_empty());
+    }
+
+    #[test]
+    fn no_archive_path_holds_a_backslash() {
+        let info = ubuntu("5.4.0\\40-generic");
+        let path = generate_btf_archive_path_for(&info);
+        assert_eq!(path, Path::new("ubuntu/20.04/x86_64/5.4.0/40-generic.btf"));
+        for path in generate_btf_archive_paths_for(&info) {
+            assert!(!path.contains('\\'), "{path}");
+        }
+    }
+
+    #[test]
+    fn codename_paths_follow_the_version_ones() {
+        let paths = generate_btf_archive_paths_for(&ubuntu("5.4.0-40-generic"));
+        let version = paths
+            .iter()
+            .position(|v| v == "ubuntu/20.04/x86_64/5.4.0-40-generic.btf");
+        let codename = paths
+            .iter()
+            .position(|v| v == "ubuntu/focal/x86_64/5.4.0-40-generic.btf");
+        assert_eq!(version, Some(0));
+        assert!(codename > version, "{paths:?}");
+        // os-release 给出的代号优先于内置的映射
+        let info = SystemInfo {
+            version_codename: "custom".into(),
+            ..ubuntu("5.4.0-40-generic")
+        };
+        assert!(generate_btf_archive_paths_for(&info)
+            .contains(&"ubuntu/custom/x86_64/5.4.0-40-generic.btf".to_string()));
+    }
+
+    #[cfg(all(feature = "host", target_os = "linux"))]
+    #[test]
+    fn raw_btf_is_written_without_native_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let btf = fixture::btf_of_arch(8, "r15");
+        let file = ensure_raw_btf_with_vmlinux(&btf, &dir.path().join("missing"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(std::fs::read(file.path()).unwrap(), btf);
+        let name = file.path().file_name().unwrap();
+        assert!(gc::is_btf_tempfile_name(name), "{name:?}");
+        let path = file.path().to_path_buf();
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(all(feature = "host", target_os = "linux"))]
+    #[test]
+    fn raw_buffers_that_arent_btf_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        for buf in [&b""[..], b"garbage", &fixture::FixtureArchive::new().gz()] {
+            assert!(matches!(
+                ensure_raw_btf_with_vmlinux(buf, &missing),
+                Err(Error::InvalidBtf(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "host")]
+    #[test]
+    fn native_btf_is_reported_as_such() {
+        if btf::check_btf_file(VMLINUX_BTF_PATH).is_err() {
+            return;
+        }
+        let (btf, matched) = ensure_core_btf_with_match(b"not an archive").unwrap();
+        assert!(btf.is_none());
+        assert_eq!(
+            matched,
+            MatchInfo::native(current_kernel_release().unwrap())
+        );
+    }
+
+    #[cfg(all(feature = "host", not(target_os = "linux")))]
+    #[test]
+    fn only_what_needs_the_running_system_is_unsupported() {
+        let btf = fixture::btf_of_arch(8, "rip");
+        let tar = fixture::FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", btf.clone())
+            .gz();
+        assert!(matches!(
+            SystemInfo::detect(),
+            Err(Error::UnsupportedPlatform)
+        ));
+        assert!(matches!(
+            ensure_core_btf(&tar),
+            Err(Error::UnsupportedPlatform)
+        ));
+        assert!(matches!(
+            ensure_raw_btf(&btf),
+            Err(Error::UnsupportedPlatform)
+        ));
+        assert!(matches!(
+            section::read_self_section(section::BTF_SECTION_NAME),
+            Err(Error::UnsupportedPlatform)
+        ));
+        assert!(matches!(
+            gc::gc_stale_btf_tempfiles(std::time::Duration::ZERO),
+            Err(Error::UnsupportedPlatform)
+        ));
+
+        // 给出系统信息时，路径的生成、内核版本的解析和归档的读取照常工作
+        let info = ubuntu("5.4.0-40-generic");
+        assert_eq!(
+            generate_btf_archive_paths_for(&info)[0],
+            "ubuntu/20.04/x86_64/5.4.0-40-generic.btf"
+        );
+        let release = release::KernelVersion::new(&info.kernel_release);
+        assert_eq!(
+            (release.major(), release.minor(), release.patch()),
+            (Some(5), Some(4), Some(0))
+        );
+        let entries = archive::BtfhubArchive::new(&tar)
+            .entries()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        let archive = TarballBtfArchive::from_bytes_with_limit(
+            &tar,
+            compression::DEFAULT_MAX_DECOMPRESSED_SIZE,
+        )
+        .unwrap();
+        assert_eq!(
+            archive.extract(&archive.lookup(&info).unwrap()).unwrap(),
+            btf
+        );
+        // 写到给定的路径也不依赖运行中的系统
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vmlinux.btf");
+        tarball::write_btf_to(&path, &btf, &Default::default()).unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), btf);
+    }
+
+    #[cfg(all(feature = "host", target_os = "linux"))]
+    #[test]
+    fn raw_btf_is_unused_with_native_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let vmlinux = dir.path().join("vmlinux");
+        std::fs::write(&vmlinux, fixture::minimal_valid_btf()).unwrap();
+        assert!(ensure_raw_btf_with_vmlinux(b"garbage", &vmlinux)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,717 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! An optional `manifest.json` entry next to the distro directories, e.g.
+//! `btfhub-archive/manifest.json`, listing every btf of the archive with its size and
+//! digest, so listing the kernels doesn't have to walk (and decompress) the whole tar.
+//!
+//! [`crate::pack::BtfArchiveBuilder`] writes it as the first entry:
+//!
+//! ```json
+//! {
+//!   "schema": 1,
+//!   "coverage": ["ubuntu/20.04/x86_64"],
+//!   "entries": [
+//!     {"path": "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf", "size": 1234, "sha256": "..."}
+//!   ]
+//! }
+//! ```
+//!
+//! Paths are relative to the root of the archive, sizes and digests those of the entries
+//! as stored. `coverage` names the `<distro>/<version>/<arch>` directories holding btfs,
+//! for a strict lookup to tell a system the archive isn't meant for, see
+//! [`ArchiveListing::check_coverage`](crate::listing::ArchiveListing::check_coverage); listings written before it existed lack it. Unknown fields are ignored, and a listing of a later schema isn't used at all.
+//! The listing is only honored if it comes before the btfs, since the archive is read as a
+//! stream. It is a hint: whatever the tar holds wins, and a listing that disagrees with it
+//! is only warned about, see [`ArchiveListing::check`](crate::listing::ArchiveListing::check).
+use std::path::{Path, PathBuf};
+
+use crate::{
+    archive::{kernel_name, normalize_entry_path, parse_btf_path},
+    flat::parse_flat_btf_path,
+    generate_btf_archive_paths_for, join_archive_path,
+    sha256::{from_hex, sha256, to_hex, DIGEST_SIZE},
+    Error, Result, SystemInfo,
+};
+
+/// Name of the listing entry, under the directory holding the btfs
+pub const LISTING_ENTRY_NAME: &str = "manifest.json";
+
+/// Version of the schema of the listing written and understood
+pub const LISTING_SCHEMA: u64 = 1;
+
+/// A btf of the listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedEntry {
+    /// Path of the entry, relative to the root of the archive
+    pub path: PathBuf,
+    /// Size of the entry as stored, i.e. before decoding a compressed btf
+    pub size: u64,
+    pub sha256: [u8; DIGEST_SIZE],
+}
+
+/// Contents of the listing entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveListing {
+    pub entries: Vec<ListedEntry>,
+    /// The `<distro>/<version>/<arch>` directories holding btfs, e.g. `ubuntu/20.04/x86_64`;
+    /// empty if the listing doesn't say, see [`ArchiveListing::covered_trees`]
+    pub coverage: Vec<String>,
+}
+
+impl ArchiveListing {
+    /// Parse the contents of a listing
+    ///
+    /// Returns `None` if it isn't valid JSON, lacks `schema` or `entries`, or is of another
+    /// schema than [`LISTING_SCHEMA`]; callers are expected to scan the archive then.
+    /// Entries without a usable `path`, `size` or `sha256` are skipped.
+    pub fn parse(contents: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(contents).ok()?;
+        let mut parser = JsonParser { text, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != text.len() {
+            return None;
+        }
+        let schema = value.get("schema")?.as_u64()?;
+        if schema != LISTING_SCHEMA {
+            log_at!(
+                Warn,
+                "Ignored the {} of schema {}, only {} is understood",
+                LISTING_ENTRY_NAME,
+                schema,
+                LISTING_SCHEMA
+            );
+            return None;
+        }
+        let Json::Array(items) = value.get("entries")? else {
+            return None;
+        };
+        let entries = items
+            .iter()
+            .filter_map(|item| {
+                let Json::String(path) = item.get("path")? else {
+                    return None;
+                };
+                let Json::String(digest) = item.get("sha256")? else {
+                    return None;
+                };
+                Some(ListedEntry {
+                    path: PathBuf::from(path),
+                    size: item.get("size")?.as_u64()?,
+                    sha256: from_hex(digest)?,
+                })
+            })
+            .collect();
+        let coverage = match value.get("coverage") {
+            Some(Json::Array(items)) => items
+                .iter()
+                .filter_map(|item| match item {
+                    Json::String(v) => Some(v.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        Some(Self { entries, coverage })
+    }
+
+    /// Record the entry at `path` holding `contents`
+    pub fn push(&mut self, path: impl Into<PathBuf>, contents: &[u8]) {
+        self.entries.push(ListedEntry {
+            path: path.into(),
+            size: contents.len() as u64,
+            sha256: sha256(contents),
+        });
+    }
+
+    /// The contents of the listing entry, one btf per line
+    ///
+    /// Paths that aren't UTF-8 can't be written to JSON and are left out.
+    pub fn to_json(&self) -> Vec<u8> {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                Some(format!(
+                    "    {{\"path\": {}, \"size\": {}, \"sha256\": \"{}\"}}",
+                    json_string(entry.path.to_str()?),
+                    entry.size,
+                    to_hex(&entry.sha256)
+                ))
+            })
+            .collect::<Vec<_>>();
+        let coverage = self
+            .coverage
+            .iter()
+            .map(|v| json_string(v))
+            .collect::<Vec<_>>();
+        format!(
+            "{{\n  \"schema\": {},\n  \"coverage\": [{}],\n  \"entries\": [\n{}\n  ]\n}}\n",
+            LISTING_SCHEMA,
+            coverage.join(", "),
+            entries.join(",\n")
+        )
+        .into_bytes()
+    }
+
+    /// The entry listed at `path`; a leading `./` or `/` of either side makes no difference
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&ListedEntry> {
+        let path = normalize_entry_path(path.as_ref());
+        self.entries
+            .iter()
+            .find(|v| normalize_entry_path(&v.path) == path)
+    }
+
+    /// The kernels listed under `prefix`, as `<distro>/<version>/<arch>/<release>` or
+    /// `generic/<arch>/<release>`, each once, in listing order
+    pub fn kernels(&self, prefix: &Path) -> Vec<String> {
+        let prefix = normalize_entry_path(prefix);
+        let mut kernels = vec![];
+        for entry in &self.entries {
+            let Some((distro, version, arch, kernel_release, _)) =
+                parse_btf_path(&normalize_entry_path(&entry.path), &prefix)
+            else {
+                continue;
+            };
+            let kernel = kernel_name(&distro, &version, &arch, &kernel_release);
+            if !kernels.contains(&kernel) {
+                kernels.push(kernel);
+            }
+        }
+        kernels
+    }
+
+    /// The `<distro>/<version>/<arch>` directories under `prefix` holding btfs, each once
+    ///
+    /// Those of `coverage`, or if the listing doesn't say, those of the btfs listed; btfs of
+    /// `generic/<arch>` give `generic/<arch>`.
+    pub fn covered_trees(&self, prefix: &Path) -> Vec<String> {
+        if !self.coverage.is_empty() {
+            return self.coverage.clone();
+        }
+        let prefix = normalize_entry_path(prefix);
+        let mut trees = vec![];
+        for entry in &self.entries {
+            let Some((distro, version, arch, _, _)) =
+                parse_btf_path(&normalize_entry_path(&entry.path), &prefix)
+            else {
+                continue;
+            };
+            let tree = tree_of(&[&distro, &version, &arch]);
+            if !trees.contains(&tree) {
+                trees.push(tree);
+            }
+        }
+        trees
+    }
+
+    /// Fail with [`Error::SystemNotCovered`] if the archive has no directory `info` may find its btf in
+    ///
+    /// The directories are those of [`ArchiveListing::covered_trees`], looked up with the
+    /// versions and architecture names of [`generate_btf_archive_paths_for`]. Nothing is
+    /// checked if the listing names no directory, or lists btfs at the root of a flat
+    /// archive, which don't depend on the distro.
+    pub fn check_coverage(&self, info: &SystemInfo, prefix: &Path) -> Result<()> {
+        let trees = self.covered_trees(prefix);
+        if trees.is_empty()
+            || self
+                .entries
+                .iter()
+                .any(|v| parse_flat_btf_path(&normalize_entry_path(&v.path)).is_some())
+        {
+            return Ok(());
+        }
+        let covered = generate_btf_archive_paths_for(info).iter().any(|path| {
+            Path::new(path)
+                .parent()
+                .and_then(|v| v.to_str())
+                .is_some_and(|v| trees.iter().any(|tree| tree == v))
+        });
+        if covered {
+            return Ok(());
+        }
+        Err(Error::SystemNotCovered(
+            tree_of(&[&info.distro_id, &info.version_id, &info.arch]),
+            trees.join(", "),
+        ))
+    }
+
+    /// Whether the entry at `path`, holding `contents` as stored, is listed as it is
+    ///
+    /// A missing or different entry means the listing is stale; callers should warn about
+    /// it, but go on with the contents of the tar.
+    pub fn check(&self, path: impl AsRef<Path>, contents: &[u8]) -> bool {
+        self.get(path)
+            .is_some_and(|v| v.size == contents.len() as u64 && v.sha256 == sha256(contents))
+    }
+}
+
+/// The directory of `components`, without the empty version of `generic/<arch>`
+fn tree_of(components: &[&str]) -> String {
+    join_archive_path(
+        &components
+            .iter()
+            .copied()
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// `value` as a JSON string literal
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A parsed JSON value; numbers are kept as written, and the value of literals isn't needed
+enum Json {
+    /// `true`, `false` or `null`
+    Literal,
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The member `key` of an object; the last one wins if it is repeated
+    fn get(&self, key: &str) -> Option<&Json> {
+        let Json::Object(members) = self else {
+            return None;
+        };
+        members.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        let Json::Number(v) = self else {
+            return None;
+        };
+        v.parse().ok()
+    }
+}
+
+/// How deep arrays and objects may nest, so a hostile listing can't overflow the stack
+const MAX_JSON_DEPTH: usize = 32;
+
+/// A recursive-descent parser of the JSON of RFC 8259
+struct JsonParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn value(&mut self) -> Option<Json> {
+        self.nested_value(0)
+    }
+
+    fn nested_value(&mut self, depth: usize) -> Option<Json> {
+        if depth > MAX_JSON_DEPTH {
+            return None;
+        }
+        self.skip_whitespace();
+        match self.peek()? {
+            b'{' => {
+                self.pos += 1;
+                let mut members = vec![];
+                if self.eat(b'}') {
+                    return Some(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    if !self.eat(b':') {
+                        return None;
+                    }
+                    members.push((key, self.nested_value(depth + 1)?));
+                    if self.eat(b'}') {
+                        return Some(Json::Object(members));
+                    }
+                    if !self.eat(b',') {
+                        return None;
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = vec![];
+                if self.eat(b']') {
+                    return Some(Json::Array(items));
+                }
+                loop {
+                    items.push(self.nested_value(depth + 1)?);
+                    if self.eat(b']') {
+                        return Some(Json::Array(items));
+                    }
+                    if !self.eat(b',') {
+                        return None;
+                    }
+                }
+            }
+            b'"' => self.string().map(Json::String),
+            b't' => self.literal("true"),
+            b'f' => self.literal("false"),
+            b'n' => self.literal("null"),
+            b'-' | b'0'..=b'9' => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|v| matches!(v, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+                {
+                    self.pos += 1;
+                }
+                Some(Json::Number(self.text[start..self.pos].to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.peek()? != b'"' {
+            return None;
+        }
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            let rest = &self.text[self.pos..];
+            let end = rest.find(['"', '\\'])?;
+            if rest[..end].bytes().any(|v| v < 0x20) {
+                return None;
+            }
+            value.push_str(&rest[..end]);
+            self.pos += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Some(value);
+            }
+            let escaped = match self.bump()? {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => {
+                    let high = self.hex4()?;
+                    // 基本多文种平面之外的字符以一对代理项表示
+                    let code = if (0xd800..0xdc00).contains(&high) {
+                        if !self.text[self.pos..].starts_with("\\u") {
+                            return None;
+                        }
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        if !(0xdc00..0xe000).contains(&low) {
+                            return None;
+                        }
+                        0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                    } else {
+                        high
+                    };
+                    char::from_u32(code)?
+                }
+                _ => return None,
+            };
+            value.push(escaped);
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.pos..self.pos + 4)?;
+        if !digits.bytes().all(|v| v.is_ascii_hexdigit()) {
+            return None;
+        }
+        self.pos += 4;
+        u32::from_str_radix(digits, 16).ok()
+    }
+
+    fn literal(&mut self, word: &str) -> Option<Json> {
+        if !self.text[self.pos..].starts_with(word) {
+            return None;
+        }
+        self.pos += word.len();
+        Some(Json::Literal)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|v| matches!(v, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    /// Skip whitespace, then `byte` if it comes next
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: &str = "btfhub-archive";
+
+    fn listing() -> ArchiveListing {
+        let mut listing = ArchiveListing::default();
+        listing.push(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            b"40",
+        );
+        listing.push(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf.gz",
+            b"42",
+        );
+        listing.push(
+            "btfhub-archive/centos/8/x86_64/4.18.0-305.el8.x86_64.btf",
+            b"el8",
+        );
+        listing
+    }
+
+    #[test]
+    fn listing_round_trips_through_json() {
+        let mut listing = listing();
+        // 需要转义的路径
+        listing.push("btfhub-archive/odd \"name\"\\\t.btf", b"");
+        assert_eq!(ArchiveListing::parse(&listing.to_json()), Some(listing));
+    }
+
+    #[test]
+    fn entries_are_parsed_field_by_field() {
+        let digest = to_hex(&sha256(b"40"));
+        let json = format!(
+            r#"{{"schema": 1, "generator": {{"name": "btfhub", "tags": [true, null]}},
+                "entries": [
+                  {{"path": "./btfhub-archive/a.btf", "size": 2, "sha256": "{digest}", "extra": 1.5e3}},
+                  {{"path": "btfhub-archive/b.btf", "size": 2}},
+                  {{"path": "btfhub-archive/c.btf", "size": -2, "sha256": "{digest}"}},
+                  {{"path": "btfhub-archive/d.btf", "size": 2, "sha256": "xyz"}},
+                  {{"path": "btfhub-\u00e9\ud83d\ude00.btf", "size": 0, "sha256": "{digest}"}}
+                ]}}"#
+        );
+        let listing = ArchiveListing::parse(json.as_bytes()).unwrap();
+        // 缺少或无法解析的字段只跳过该条目
+        assert_eq!(
+            listing.entries,
+            [
+                ListedEntry {
+                    path: "./btfhub-archive/a.btf".into(),
+                    size: 2,
+                    sha256: sha256(b"40"),
+                },
+                ListedEntry {
+                    path: "btfhub-\u{e9}\u{1f600}.btf".into(),
+                    size: 0,
+                    sha256: sha256(b"40"),
+                },
+            ]
+        );
+        assert!(listing.coverage.is_empty());
+    }
+
+    #[test]
+    fn unusable_listings_are_not_parsed() {
+        let nested = format!("{}{}", "[".repeat(100), "]".repeat(100));
+        for contents in [
+            "",
+            "[]",
+            "{\"schema\": 1}",
+            "{\"entries\": []}",
+            "{\"schema\": 2, \"entries\": []}",
+            "{\"schema\": \"1\", \"entries\": []}",
+            "{\"schema\": 1, \"entries\": {}}",
+            "{\"schema\": 1, \"entries\": []} {}",
+            "{\"schema\": 1, \"entries\": [],}",
+            "{\"schema\": 1, \"entries\": [\"\\ud83d\"]}",
+            "{\"schema\": 1, \"entries\": [\"tab\there\"]}",
+            &format!("{{\"schema\": 1, \"entries\": [], \"x\": {nested}}}"),
+        ] {
+            assert_eq!(
+                ArchiveListing::parse(contents.as_bytes()),
+                None,
+                "{contents}"
+            );
+        }
+        assert_eq!(
+            ArchiveListing::parse(b"{\"schema\": 1, \"entries\": [\xff]}"),
+            None
+        );
+        assert_eq!(
+            ArchiveListing::parse(b" {\"schema\": 1, \"entries\": []}\n"),
+            Some(ArchiveListing::default())
+        );
+    }
+
+    #[test]
+    fn entries_are_found_whatever_their_leading_dots() {
+        let listing = listing();
+        for path in [
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            "/btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+        ] {
+            assert_eq!(listing.get(path).unwrap().size, 2, "{path}");
+        }
+        assert_eq!(
+            listing.get("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn kernels_are_listed_once_in_order() {
+        let mut listing = listing();
+        listing.push(
+            "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz",
+            b"",
+        );
+        listing.push("btfhub-archive/README.md", b"");
+        listing.push("btfhub-archive/generic/aarch64/6.5.3-arch1-1.btf", b"");
+        listing.push("other/ubuntu/20.04/x86_64/5.4.0-99-generic.btf", b"");
+        assert_eq!(
+            listing.kernels(Path::new(PREFIX)),
+            [
+                "ubuntu/20.04/x86_64/5.4.0-40-generic",
+                "ubuntu/20.04/x86_64/5.4.0-42-generic",
+                "centos/8/x86_64/4.18.0-305.el8.x86_64",
+                "generic/aarch64/6.5.3-arch1-1",
+            ]
+        );
+        assert_eq!(
+            listing.kernels(Path::new("./other")),
+            ["ubuntu/20.04/x86_64/5.4.0-99-generic"]
+        );
+    }
+
+    fn system(distro: &str, version: &str, arch: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: distro.into(),
+            version_id: version.into(),
+            arch: arch.into(),
+            kernel_release: "5.4.0-40-generic".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn coverage_is_recorded_or_derived_from_the_btfs() {
+        let mut listing = listing();
+        listing.push("btfhub-archive/generic/aarch64/6.5.3-arch1-1.btf", b"");
+        listing.push("btfhub-archive/README.md", b"");
+        // 没有 coverage 时由列出的 btf 得出，每个目录一次
+        assert_eq!(
+            listing.covered_trees(Path::new(PREFIX)),
+            ["ubuntu/20.04/x86_64", "centos/8/x86_64", "generic/aarch64"]
+        );
+        listing.coverage = vec!["debian/11/x86_64".into()];
+        assert_eq!(
+            listing.covered_trees(Path::new(PREFIX)),
+            ["debian/11/x86_64"]
+        );
+        let json = listing.to_json();
+        assert!(String::from_utf8_lossy(&json).contains("\"coverage\": [\"debian/11/x86_64\"]"));
+        assert_eq!(ArchiveListing::parse(&json), Some(listing));
+        // 不是字符串的元素被忽略
+        let listing = ArchiveListing::parse(
+            br#"{"schema": 1, "coverage": ["ubuntu/22.04/x86_64", 3, null], "entries": []}"#,
+        )
+        .unwrap();
+        assert_eq!(listing.coverage, ["ubuntu/22.04/x86_64"]);
+    }
+
+    #[test]
+    fn systems_the_archive_has_no_directory_for_are_refused() {
+        let listing = listing();
+        let prefix = Path::new(PREFIX);
+        assert!(listing
+            .check_coverage(&system("ubuntu", "20.04", "x86_64"), prefix)
+            .is_ok());
+        // 查找时尝试的架构名称也算在内
+        assert!(listing
+            .check_coverage(&system("ubuntu", "20.04", "amd64"), prefix)
+            .is_ok());
+        let error = listing
+            .check_coverage(&system("debian", "12", "x86_64"), prefix)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "This build does not support debian/12/x86_64; supported: ubuntu/20.04/x86_64, centos/8/x86_64"
+        );
+        assert!(matches!(
+            listing.check_coverage(&system("ubuntu", "20.04", "aarch64"), prefix),
+            Err(Error::SystemNotCovered(v, _)) if v == "ubuntu/20.04/aarch64"
+        ));
+        // 以代号命名的目录同样覆盖该版本
+        let codename = ArchiveListing {
+            coverage: vec!["ubuntu/focal/x86_64".into()],
+            ..Default::default()
+        };
+        assert!(codename
+            .check_coverage(&system("ubuntu", "20.04", "x86_64"), prefix)
+            .is_ok());
+        // 22.04 自己的内核不在 20.04 的目录下查找
+        let jammy = SystemInfo {
+            kernel_release: "5.15.0-76-generic".into(),
+            ..system("ubuntu", "22.04", "x86_64")
+        };
+        assert!(codename.check_coverage(&jammy, prefix).is_err());
+        // 其他前缀下没有目录时不作判断
+        assert!(listing
+            .check_coverage(&system("debian", "12", "x86_64"), Path::new("other"))
+            .is_ok());
+        // 平坦归档的 btf 与发行版无关
+        let mut flat = listing.clone();
+        flat.push("5.4.0-40-generic.btf", b"");
+        assert!(flat
+            .check_coverage(&system("debian", "12", "x86_64"), prefix)
+            .is_ok());
+    }
+
+    #[test]
+    fn stale_entries_fail_the_check() {
+        let listing = listing();
+        let path = "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf";
+        assert!(listing.check(path, b"40"));
+        assert!(listing.check(format!("./{path}"), b"40"));
+        // 大小相同但内容不同，以及大小不同
+        assert!(!listing.check(path, b"41"));
+        assert!(!listing.check(path, b"400"));
+        assert!(!listing.check(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+            b"40"
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Wiring the btf into `btf_custom_path` of a bpf object, e.g. opened with libbpf-rs, with
+//! the btf kept until the object is loaded.
+//!
+//! libbpf only records `btf_custom_path` when the object is opened, and parses the file
+//! when it is loaded, so the btf has to outlive both. With libbpf-rs:
+//!
+//! ```ignore
+//! let obj = with_compat_btf(BTF_ARCHIVE, |btf| {
+//!     let opts = libbpf_sys::bpf_object_open_opts {
+//!         sz: std::mem::size_of::<libbpf_sys::bpf_object_open_opts>() as _,
+//!         btf_custom_path: btf.as_ptr(),
+//!         ..Default::default()
+//!     };
+//!     skel_builder.open_opts(opts)?.load()
+//! })??;
+//! ```
+//!
+//! With the `libbpf-rs` feature, [`open_with_compat_btf`] opens the object itself:
+//!
+//! ```ignore
+//! let (obj, _btf) = open_with_compat_btf(&ObjectBuilder::default(), BPF_OBJECT, BTF_ARCHIVE)?;
+//! let obj = obj.load()?;
+//! ```
+#[cfg(feature = "libbpf-rs")]
+use std::ptr::NonNull;
+use std::{
+    ffi::{c_char, CString},
+    path::Path,
+};
+
+#[cfg(feature = "libbpf-rs")]
+use libbpf_rs::{libbpf_sys, AsRawLibbpf, ObjectBuilder, OpenObject};
+
+#[cfg(feature = "libbpf-rs")]
+use crate::Error;
+use crate::{ensure_core_btf, EnsuredBtf, Result};
+
+/// The btf of the running system, as the `btf_custom_path` of libbpf, removed on drop
+///
+/// Holds nothing if the kernel has native btf, in which case libbpf is to be left to find
+/// it: [`CompatBtf::as_ptr`] is NULL and [`CompatBtf::path`] is `None`.
+#[derive(Debug)]
+pub struct CompatBtf {
+    btf: Option<EnsuredBtf>,
+    c_path: Option<CString>,
+}
+
+impl CompatBtf {
+    /// Look the btf of the running system up in `archive`, see [`ensure_core_btf`]
+    pub fn ensure(archive: &[u8]) -> Result<Self> {
+        Ok(Self::from_ensured(ensure_core_btf(archive)?))
+    }
+
+    /// Wrap a btf from [`ensure_core_btf`] or the like
+    pub fn from_ensured(btf: Option<EnsuredBtf>) -> Self {
+        // 文件系统的路径中不会含有 NUL
+        let c_path = btf.as_ref().map(|v| {
+            CString::new(v.as_os_str().as_encoded_bytes()).expect("a path never contains NUL")
+        });
+        Self { btf, c_path }
+    }
+
+    /// Path of the btf, `None` if the kernel has native btf
+    pub fn path(&self) -> Option<&Path> {
+        self.btf.as_deref()
+    }
+
+    /// The path for `bpf_object_open_opts.btf_custom_path`, NULL if the kernel has native btf
+    ///
+    /// The pointer is valid as long as `self` is.
+    pub fn as_ptr(&self) -> *const c_char {
+        self.c_path
+            .as_ref()
+            .map_or(std::ptr::null(), |v| v.as_ptr())
+    }
+
+    /// Whether the kernel has native btf, so nothing is to be set
+    pub fn is_native(&self) -> bool {
+        self.btf.is_none()
+    }
+
+    /// The open options of `builder`, with `btf_custom_path` set to the btf
+    ///
+    /// Left as they are if the kernel has native btf. The options point into `builder` and
+    /// `self`, and are valid as long as both are.
+    #[cfg(feature = "libbpf-rs")]
+    pub fn open_opts(&self, builder: &ObjectBuilder) -> libbpf_sys::bpf_object_open_opts {
+        // 复制而不是修改 builder 的选项，名称等字符串仍由 builder 持有
+        let mut opts = unsafe { *builder.as_libbpf_object().as_ptr() };
+        if !self.is_native() {
+            opts.btf_custom_path = self.as_ptr();
+        }
+        opts
+    }
+
+    /// Open the bpf object `object` with the options of `builder` and the btf, see [`Self::open_opts`]
+    ///
+    /// libbpf parses the btf only when the object is loaded, so keep `self` until then.
+    #[cfg(feature = "libbpf-rs")]
+    pub fn open_object(
+        &self,
+        builder: &ObjectBuilder,
+        object: &[u8],
+    ) -> libbpf_rs::Result<OpenObject> {
+        let opts = self.open_opts(builder);
+        let ptr = unsafe {
+            libbpf_sys::bpf_object__open_mem(object.as_ptr().cast(), object.len() as _, &opts)
+        };
+        // 与 libbpf-rs 一样用 libbpf_get_error 判断，兼容旧版 libbpf 返回错误指针的模式
+        match unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) } {
+            0 => Ok(unsafe { OpenObject::from_ptr(NonNull::new_unchecked(ptr)) }),
+            err => Err(libbpf_rs::Error::from_raw_os_error(-err as i32)),
+        }
+    }
+}
+
+/// Run `open`, which opens and loads a bpf object, with the btf of the running system from `archive`
+///
+/// `open` is given the [`CompatBtf`] to set as `btf_custom_path`; the btf is removed once
+/// `open` returns, so the object has to be loaded in it. Nothing is extracted if the
+/// kernel has native btf. Fails only if the btf can't be found, the result of `open` is
+/// passed through.
+pub fn with_compat_btf<T>(archive: &[u8], open: impl FnOnce(&CompatBtf) -> T) -> Result<T> {
+    let btf = CompatBtf::ensure(archive)?;
+    Ok(open(&btf))
+}
+
+/// Open the bpf object `object` with `builder`, with the btf of the running system from `archive`
+///
+/// The options of `builder`, e.g. the name of the object, are kept, and `btf_custom_path`
+/// is set to the btf, or left NULL if the kernel has native btf. Returns the object and
+/// the btf, which is removed once dropped, so drop it only after loading the object.
+/// Fails with [`Error::ObjectOpenError`] if libbpf can't open the object.
+#[cfg(feature = "libbpf-rs")]
+pub fn open_with_compat_btf(
+    builder: &ObjectBuilder,
+    object: &[u8],
+    archive: &[u8],
+) -> Result<(OpenObject, CompatBtf)> {
+    let btf = CompatBtf::ensure(archive)?;
+    let object = btf
+        .open_object(builder, object)
+        .map_err(Error::ObjectOpenError)?;
+    Ok((object, btf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CStr, fs};
+
+    use super::*;
+    use crate::{fixture::btf_of_arch, VMLINUX_BTF_PATH};
+
+    /// A btf written to a temporary file, as if extracted from an archive
+    fn extracted(dir: &Path) -> EnsuredBtf {
+        let path = dir.join("extracted.btf");
+        let btf = btf_of_arch(8, "custom");
+        fs::write(&path, &btf).unwrap();
+        EnsuredBtf::extracted(path, btf.len() as u64)
+    }
+
+    /// What libbpf does with `btf_custom_path` when loading: read the file it names
+    fn read_custom_path(btf_custom_path: *const c_char) -> Vec<u8> {
+        let path = unsafe { CStr::from_ptr(btf_custom_path) }.to_str().unwrap();
+        fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn extracted_btf_is_the_custom_path_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let btf = CompatBtf::from_ensured(Some(extracted(dir.path())));
+        let path = dir.path().join("extracted.btf");
+        assert!(!btf.is_native());
+        assert_eq!(btf.path(), Some(path.as_path()));
+        assert!(!btf.as_ptr().is_null());
+        assert_eq!(
+            unsafe { CStr::from_ptr(btf.as_ptr()) }.to_str().unwrap(),
+            path.to_str().unwrap()
+        );
+        assert_eq!(read_custom_path(btf.as_ptr()), btf_of_arch(8, "custom"));
+        drop(btf);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn native_btf_sets_nothing() {
+        let btf = CompatBtf::from_ensured(None);
+        assert!(btf.is_native());
+        assert_eq!(btf.path(), None);
+        assert!(btf.as_ptr().is_null());
+    }
+
+    /// An empty bpf object, which libbpf opens without any program
+    #[cfg(feature = "libbpf-rs")]
+    fn empty_bpf_object() -> Vec<u8> {
+        let mut object = crate::embed::EmbeddedArchiveObject::new(&[], "x86_64")
+            .unwrap()
+            .to_bytes();
+        // e_machine = EM_BPF
+        object[18..20].copy_from_slice(&247u16.to_le_bytes());
+        object
+    }
+
+    #[cfg(feature = "libbpf-rs")]
+    #[test]
+    fn open_opts_set_only_the_custom_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = ObjectBuilder::default();
+        builder.name("compat").unwrap().relaxed_maps(true);
+        let btf = CompatBtf::from_ensured(Some(extracted(dir.path())));
+        let opts = btf.open_opts(&builder);
+        assert_eq!(opts.btf_custom_path, btf.as_ptr());
+        assert_eq!(
+            read_custom_path(opts.btf_custom_path),
+            btf_of_arch(8, "custom")
+        );
+        assert_eq!(
+            unsafe { CStr::from_ptr(opts.object_name) }
+                .to_str()
+                .unwrap(),
+            "compat"
+        );
+        assert!(opts.relaxed_maps);
+        // 内核自带 btf 时选项与 builder 的相同
+        let opts = CompatBtf::from_ensured(None).open_opts(&builder);
+        assert!(opts.btf_custom_path.is_null());
+        assert_eq!(
+            opts.object_name,
+            unsafe { builder.as_libbpf_object().as_ref() }.object_name
+        );
+    }
+
+    #[cfg(feature = "libbpf-rs")]
+    #[test]
+    fn opened_object_keeps_the_btf_until_it_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("extracted.btf");
+        let mut builder = ObjectBuilder::default();
+        builder.name("compat").unwrap();
+        let btf = CompatBtf::from_ensured(Some(extracted(dir.path())));
+        let object = btf.open_object(&builder, &empty_bpf_object()).unwrap();
+        assert_eq!(object.name().unwrap(), "compat");
+        assert!(path.exists());
+        drop(btf);
+        assert!(!path.exists());
+        drop(object);
+        // libbpf 拒绝的对象返回它的错误
+        let btf = CompatBtf::from_ensured(None);
+        assert!(btf.open_object(&builder, b"\x7fELF but truncated").is_err());
+    }
+
+    #[cfg(feature = "libbpf-rs")]
+    #[test]
+    fn open_with_compat_btf_passes_native_btf_through() {
+        let archive = crate::fixture::FixtureArchive::new().gz();
+        let result = open_with_compat_btf(&ObjectBuilder::default(), &empty_bpf_object(), &archive);
+        if crate::is_native_btf(Path::new(VMLINUX_BTF_PATH)) {
+            let (_, btf) = result.unwrap();
+            assert!(btf.is_native());
+        } else {
+            // 归档中没有任何 btf，查找失败，不会打开对象
+            assert!(result.is_err_and(|e| !matches!(e, Error::ObjectOpenError(_))));
+        }
+    }
+
+    #[test]
+    fn result_of_the_open_is_passed_through() {
+        let archive = crate::fixture::FixtureArchive::new().gz();
+        let result = with_compat_btf(&archive, |btf| {
+            // 内核自带 btf 时不设置 btf_custom_path
+            assert!(btf.is_native());
+            assert!(btf.as_ptr().is_null());
+            Err::<(), _>("load failed")
+        });
+        if crate::is_native_btf(Path::new(VMLINUX_BTF_PATH)) {
+            assert_eq!(result.unwrap(), Err("load failed"));
+        } else {
+            // 归档中没有任何 btf，查找失败，open 不会被调用
+            assert!(result.is_err());
+        }
+    }
+}
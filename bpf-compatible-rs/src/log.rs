@@ -0,0 +1,217 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! What the lookup decided, for diagnosing a missing btf in the field: the paths
+//! generated for the system, the entries considered, the btf selected and fallbacks taken.
+//! Nothing is logged until a [`Logger`](crate::log::Logger) is set with [`set_logger`](crate::log::set_logger); forward the messages
+//! to whatever logging the application uses.
+use std::sync::{Arc, RwLock};
+
+/// Importance of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// A failure
+    Error,
+    /// A fallback, e.g. to the distro of `ID_LIKE`
+    Warn,
+    /// The btf selected, and where it was written
+    Info,
+    /// The paths generated and the entries considered
+    Debug,
+}
+
+/// Receiver of the messages of this library
+pub trait Logger: Send + Sync {
+    fn log(&self, level: Level, message: &str);
+}
+
+impl<F: Fn(Level, &str) + Send + Sync> Logger for F {
+    fn log(&self, level: Level, message: &str) {
+        self(level, message)
+    }
+}
+
+static LOGGER: RwLock<Option<Arc<dyn Logger>>> = RwLock::new(None);
+
+/// Send the messages of this library to `logger`, or stop logging with `None`
+pub fn set_logger(logger: Option<Arc<dyn Logger>>) {
+    *LOGGER.write().unwrap_or_else(|e| e.into_inner()) = logger;
+}
+
+/// Hand `message` to the logger, if any
+pub fn log(level: Level, message: &str) {
+    // 先取出 logger 再调用，回调中再次设置 logger 不会死锁
+    let logger = LOGGER.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(logger) = logger {
+        logger.log(level, message);
+    }
+}
+
+/// Log a message with the format arguments, only formatting it if a logger is set
+macro_rules! log_at {
+    ($level:ident, $($arg:tt)*) => {
+        if $crate::log::is_enabled() {
+            $crate::log::log($crate::log::Level::$level, &format!($($arg)*))
+        }
+    };
+}
+
+/// Whether a logger is set, to skip formatting messages nobody reads
+pub fn is_enabled() -> bool {
+    LOGGER.read().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        fixture::{btf_of_arch, FixtureArchive},
+        release::MatchPolicy,
+        tarball::TarballBtfArchive,
+        SystemInfo,
+    };
+
+    // logger 是全局的，设置它的测试逐个运行
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// Run `f` with a logger set, returning what it logged
+    fn logged(f: impl FnOnce()) -> Vec<(Level, String)> {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let sink = messages.clone();
+        set_logger(Some(Arc::new(move |level, message: &str| {
+            sink.lock().unwrap().push((level, message.to_string()))
+        })));
+        f();
+        set_logger(None);
+        let messages = messages.lock().unwrap().clone();
+        messages
+    }
+
+    fn has(messages: &[(Level, String)], level: Level, text: &str) -> bool {
+        messages
+            .iter()
+            .any(|(l, m)| *l == level && m.contains(text))
+    }
+
+    fn ubuntu(kernel_release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: kernel_release.into(),
+            ..Default::default()
+        }
+    }
+
+    fn archive() -> TarballBtfArchive {
+        let gz = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "40"),
+            )
+            .gz();
+        TarballBtfArchive::from_gzipped_bytes(&gz).unwrap()
+    }
+
+    #[test]
+    fn lookup_logs_the_paths_and_the_selected_btf() {
+        let _serial = SERIAL.lock().unwrap();
+        let archive = archive();
+        let messages = logged(|| {
+            archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        });
+        assert!(
+            has(
+                &messages,
+                Level::Debug,
+                "ubuntu/20.04/x86_64/5.4.0-40-generic"
+            ),
+            "{messages:?}"
+        );
+        assert!(
+            has(
+                &messages,
+                Level::Info,
+                "Selected the btf btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf"
+            ),
+            "{messages:?}"
+        );
+    }
+
+    #[test]
+    fn fallbacks_are_warned_about() {
+        let _serial = SERIAL.lock().unwrap();
+        let archive = archive();
+        let messages = logged(|| {
+            let entry = archive
+                .lookup_with_policy(&ubuntu("5.4.0-42-generic"), MatchPolicy::SameFlavorNearest)
+                .unwrap();
+            assert_eq!(entry.kernel_release, "5.4.0-40-generic");
+        });
+        assert!(
+            has(&messages, Level::Warn, "5.4.0-42-generic"),
+            "{messages:?}"
+        );
+        // 衍生发行版按 ID_LIKE 查找
+        let messages = logged(|| {
+            let info = SystemInfo::from_os_release(
+                "ID=linuxmint\nID_LIKE=ubuntu\nVERSION_ID=\"20.3\"\n",
+                "x86_64".into(),
+                "5.4.0-40-generic".into(),
+                String::new(),
+            )
+            .unwrap();
+            assert_eq!(info.distro_id, "ubuntu");
+        });
+        assert!(has(&messages, Level::Warn, "linuxmint"), "{messages:?}");
+    }
+
+    #[test]
+    fn failures_are_logged_as_errors() {
+        let _serial = SERIAL.lock().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("etc")).unwrap();
+        std::fs::write(
+            root.path().join("etc/os-release"),
+            "ID=ubuntu\nVERSION_ID=\"20.04\"\n",
+        )
+        .unwrap();
+        // 归档中没有这台机器的内核
+        let gz = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "0.0.0-0-none",
+                btf_of_arch(8, "0"),
+            )
+            .gz();
+        let opts = crate::EnsureOptions::new()
+            .with_sysroot(root.path())
+            .with_tmpdir(root.path().join("tmp"))
+            .with_chain([crate::chain::Strategy::EmbeddedArchive]);
+        let messages = logged(|| {
+            assert!(crate::ensure_core_btf_with(&gz, &opts).is_err());
+        });
+        assert!(
+            messages.iter().any(|(level, _)| *level == Level::Error),
+            "{messages:?}"
+        );
+    }
+
+    #[test]
+    fn nothing_is_formatted_without_a_logger() {
+        let _serial = SERIAL.lock().unwrap();
+        assert!(!is_enabled());
+        let messages = logged(|| assert!(is_enabled()));
+        assert!(messages.is_empty());
+        assert!(!is_enabled());
+    }
+}
@@ -0,0 +1,213 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! An optional `SHA256SUMS` entry of the archive, in the format of `sha256sum`:
+//! one `<hex digest>  <path>` line per file. When present, the extracted btf is checked
+//! against it, proving it is exactly what was packaged.
+//!
+//! The manifest is only honored if it comes before the btfs, i.e. first in the tar (or
+//! right after the `INDEX` entry), since the archive is read as a stream; see
+//! [`prepend_manifest`](crate::manifest::prepend_manifest).
+use std::path::{Path, PathBuf};
+
+use crate::{
+    archive::{normalize_entry_path, path_from_bytes},
+    compression::{tar_archive, tar_entries},
+    index::{prepend_entry, INDEX_ENTRY_NAME},
+    sha256::{from_hex, sha256, to_hex, DIGEST_SIZE},
+    sparse::{entry_path, is_file_entry, read_entry},
+    Error, Result,
+};
+
+/// Name of the manifest entry
+pub const MANIFEST_ENTRY_NAME: &str = "SHA256SUMS";
+
+/// Digests of the files of an archive, by path
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    digests: Vec<(PathBuf, [u8; DIGEST_SIZE])>,
+}
+
+impl Manifest {
+    /// Parse the contents of a manifest; lines that aren't `<digest>  <path>` are ignored
+    ///
+    /// `<digest> *<path>`, as written by `sha256sum --binary`, is accepted too.
+    pub fn parse(contents: &[u8]) -> Self {
+        let mut digests = vec![];
+        for line in contents.split(|v| *v == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let Some(split) = line.iter().position(|v| *v == b' ') else {
+                continue;
+            };
+            let (Some(digest), Some(path)) = (
+                std::str::from_utf8(&line[..split]).ok().and_then(from_hex),
+                line[split + 1..]
+                    .strip_prefix(b" ")
+                    .or_else(|| line[split + 1..].strip_prefix(b"*")),
+            ) else {
+                continue;
+            };
+            let path = normalize_entry_path(&path_from_bytes(path));
+            digests.push((path, digest));
+        }
+        Self { digests }
+    }
+
+    /// Digest recorded for `path`; a leading `./` or `/` of either side makes no difference
+    pub fn digest_of(&self, path: impl AsRef<Path>) -> Option<&[u8; DIGEST_SIZE]> {
+        let path = normalize_entry_path(path.as_ref());
+        self.digests
+            .iter()
+            .find(|(v, _)| *v == path)
+            .map(|(_, digest)| digest)
+    }
+
+    /// Check `contents`, the bytes of the entry at `path` as stored in the archive, against the manifest
+    pub fn verify(&self, path: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+        let path = path.as_ref();
+        let expected = self
+            .digest_of(path)
+            .ok_or_else(|| Error::NotInManifest(path.display().to_string()))?;
+        let actual = sha256(contents);
+        if actual != *expected {
+            return Err(Error::DigestMismatch(
+                path.display().to_string(),
+                to_hex(expected),
+                to_hex(&actual),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Build the contents of a `SHA256SUMS` entry covering every regular file in `tar`
+///
+/// The `INDEX` and `SHA256SUMS` entries themselves are left out.
+pub fn build_manifest(tar: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = tar_archive(tar);
+    let mut manifest = vec![];
+    for entry in tar_entries(&mut archive).map_err(Error::from_read)? {
+        let mut entry = entry.map_err(Error::from_read)?;
+        if !is_file_entry(entry.header().entry_type()) {
+            continue;
+        }
+        let path = entry_path(&mut entry).map_err(Error::from_read)?;
+        let name = normalize_entry_path(&path);
+        if name == Path::new(INDEX_ENTRY_NAME) || name == Path::new(MANIFEST_ENTRY_NAME) {
+            continue;
+        }
+        let contents = read_entry(&mut entry).map_err(Error::from_read)?;
+        manifest.extend_from_slice(format!("{}  ", to_hex(&sha256(&contents))).as_bytes());
+        manifest.extend_from_slice(path.as_os_str().as_encoded_bytes());
+        manifest.push(b'\n');
+    }
+    Ok(manifest)
+}
+
+/// Return a copy of `tar` with a `SHA256SUMS` entry describing it placed in front
+///
+/// To have both, prepend the manifest first, then the index with
+/// [`crate::index::prepend_index`], which must stay the very first entry.
+pub fn prepend_manifest(tar: &[u8]) -> Result<Vec<u8>> {
+    prepend_entry(MANIFEST_ENTRY_NAME, &build_manifest(tar)?, tar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixture::FixtureArchive, index::prepend_index};
+
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    /// Name and contents of each file entry of `tar`, in order
+    fn files_of(tar: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut archive = tar::Archive::new(tar);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().display().to_string();
+                (path, read_entry(&mut entry).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sha256sum_lines_are_parsed() {
+        let manifest = Manifest::parse(
+            format!(
+                "{ABC}  a.btf\n{} *b/b.btf\r\n{ABC}  ./c.btf\n\nnot a digest  d.btf\n{ABC} e.btf\n{ABC}",
+                ABC.to_uppercase()
+            )
+            .as_bytes(),
+        );
+        let abc = from_hex(ABC).unwrap();
+        assert_eq!(manifest.digest_of("a.btf"), Some(&abc));
+        // --binary 的 `*` 和 CRLF 行尾
+        assert_eq!(manifest.digest_of("b/b.btf"), Some(&abc));
+        // 两边的 ./ 和 / 前缀都不影响匹配
+        assert_eq!(manifest.digest_of("/c.btf"), Some(&abc));
+        assert_eq!(manifest.digest_of("./a.btf"), Some(&abc));
+        for ignored in ["d.btf", "e.btf", "b/b.btf\r"] {
+            assert_eq!(manifest.digest_of(ignored), None, "{ignored}");
+        }
+    }
+
+    #[test]
+    fn entries_are_verified_against_their_digest() {
+        let manifest = Manifest::parse(format!("{ABC}  dir/abc.btf\n").as_bytes());
+        manifest.verify("./dir/abc.btf", b"abc").unwrap();
+        match manifest.verify("dir/abc.btf", b"abd") {
+            Err(Error::DigestMismatch(path, expected, actual)) => {
+                assert_eq!(path, "dir/abc.btf");
+                assert_eq!(expected, ABC);
+                assert_eq!(actual, to_hex(&sha256(b"abd")));
+            }
+            v => panic!("{v:?}"),
+        }
+        assert!(matches!(
+            manifest.verify("dir/other.btf", b"abc"),
+            Err(Error::NotInManifest(path)) if path == "dir/other.btf"
+        ));
+    }
+
+    #[test]
+    fn prepended_manifest_describes_every_file() {
+        let tar = FixtureArchive::new()
+            .dir("btfhub-archive/ubuntu")
+            .file("btfhub-archive/ubuntu/a.btf", b"abc".to_vec())
+            .file("./btfhub-archive/ubuntu/b.btf", b"other".to_vec())
+            .tar();
+        let files = files_of(&prepend_manifest(&tar).unwrap());
+        assert_eq!(files[0].0, MANIFEST_ENTRY_NAME);
+        let manifest = Manifest::parse(&files[0].1);
+        assert_eq!(manifest.digests.len(), 2);
+        for (path, contents) in &files[1..] {
+            if !contents.is_empty() {
+                manifest.verify(path, contents).unwrap();
+            }
+        }
+        assert_eq!(
+            manifest.digest_of("btfhub-archive/ubuntu/a.btf"),
+            Some(&from_hex(ABC).unwrap())
+        );
+
+        // 清单和索引本身不会被列入
+        let indexed = prepend_index(&prepend_manifest(&tar).unwrap()).unwrap();
+        assert_eq!(
+            files_of(&indexed)
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .take(2)
+                .collect::<Vec<_>>(),
+            [INDEX_ENTRY_NAME, MANIFEST_ENTRY_NAME]
+        );
+        assert_eq!(
+            build_manifest(&indexed).unwrap(),
+            build_manifest(&tar).unwrap()
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! An archive shipped as a file next to the binary, rather than linked into it. The file is
+//! mapped into memory, so the page cache decides what stays resident instead of a buffer
+//! as large as the archive; it is read into memory if it can't be mapped.
+//!
+//! The mapping is only ever accessed within the size the file had when it was opened.
+//! A file that changes in place during a lookup is reported by
+//! [`ArchiveFile::check_unchanged`](crate::mapped::ArchiveFile::check_unchanged); one that shrinks under the mapping may still raise
+//! `SIGBUS`, so archives should be updated by renaming a new file over the old one, which
+//! leaves the mapped file intact. Files are only mapped on Linux, and read elsewhere.
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{archive::BtfhubArchive, Error, Result};
+
+/// Where the bytes of an [`ArchiveFile`] live
+enum Contents {
+    #[cfg(target_os = "linux")]
+    Mapped(*mut libc::c_void),
+    Buffered(Vec<u8>),
+}
+
+/// An archive file mapped (or read) into memory
+pub struct ArchiveFile {
+    path: PathBuf,
+    file: File,
+    len: usize,
+    modified: Option<SystemTime>,
+    contents: Contents,
+}
+
+// 映射区域只读，且只在 Drop 时解除映射
+unsafe impl Send for ArchiveFile {}
+unsafe impl Sync for ArchiveFile {}
+
+impl ArchiveFile {
+    /// Map the file at `path`, falling back to reading it if that fails, e.g. on a filesystem without mmap support
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut archive = Self::open_file(path.as_ref())?;
+        if archive.len == 0 {
+            return Ok(archive);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    archive.len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    archive.file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr != libc::MAP_FAILED {
+                archive.contents = Contents::Mapped(ptr);
+                return Ok(archive);
+            }
+        }
+        archive.contents = Contents::Buffered(archive.read_contents()?);
+        Ok(archive)
+    }
+
+    /// Read the file at `path` into memory, without trying to map it
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let mut archive = Self::open_file(path.as_ref())?;
+        archive.contents = Contents::Buffered(archive.read_contents()?);
+        Ok(archive)
+    }
+
+    fn open_file(path: &Path) -> Result<Self> {
+        let error = |e| Error::FileReadError(path.display().to_string(), e);
+        let file = File::open(path).map_err(error)?;
+        let metadata = file.metadata().map_err(error)?;
+        let len = usize::try_from(metadata.len()).map_err(|_| {
+            error(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the file is too large to be mapped",
+            ))
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            len,
+            modified: metadata.modified().ok(),
+            contents: Contents::Buffered(vec![]),
+        })
+    }
+
+    /// Read exactly the size the file had when it was opened
+    fn read_contents(&mut self) -> Result<Vec<u8>> {
+        let mut contents = vec![0; self.len];
+        // 读取过程中文件变短时 read_exact 返回 UnexpectedEof
+        self.file
+            .read_exact(&mut contents)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => {
+                    Error::ArchiveChanged(self.path.display().to_string())
+                }
+                _ => Error::FileReadError(self.path.display().to_string(), e),
+            })?;
+        Ok(contents)
+    }
+
+    /// Whether the file is mapped, rather than read into memory
+    pub fn is_mapped(&self) -> bool {
+        match self.contents {
+            #[cfg(target_os = "linux")]
+            Contents::Mapped(_) => true,
+            Contents::Buffered(_) => false,
+        }
+    }
+
+    /// The contents of the file, as of when it was opened
+    pub fn bytes(&self) -> &[u8] {
+        match &self.contents {
+            #[cfg(target_os = "linux")]
+            Contents::Mapped(ptr) => unsafe {
+                std::slice::from_raw_parts(*ptr as *const u8, self.len)
+            },
+            Contents::Buffered(v) => v,
+        }
+    }
+
+    /// The file as a btfhub archive
+    pub fn archive(&self) -> BtfhubArchive<'_> {
+        BtfhubArchive::new(self.bytes())
+    }
+
+    /// Fail with [`Error::ArchiveChanged`] if the size or mtime of the file changed since it was opened
+    ///
+    /// Meant to be called after a lookup, whose result can't be trusted otherwise.
+    pub fn check_unchanged(&self) -> Result<()> {
+        let metadata = self
+            .file
+            .metadata()
+            .map_err(|e| Error::FileReadError(self.path.display().to_string(), e))?;
+        if metadata.len() != self.len as u64 || metadata.modified().ok() != self.modified {
+            return Err(Error::ArchiveChanged(self.path.display().to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ArchiveFile {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Contents::Mapped(ptr) = self.contents {
+            unsafe { libc::munmap(ptr, self.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use super::*;
+    use crate::{
+        fixture::{btf_of_arch, FixtureArchive},
+        SystemInfo,
+    };
+
+    fn write_archive(dir: &Path, bytes: &[u8]) -> PathBuf {
+        let path = dir.join("min_core_btfs.tar.gz");
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn mapped_and_read_files_give_the_same_lookups() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut fixture = FixtureArchive::new();
+        for release in ["5.4.0-26-generic", "5.4.0-40-generic"] {
+            fixture = fixture.btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                release,
+                btf_of_arch(8, release),
+            );
+        }
+        let gz = fixture.gz();
+        let path = write_archive(dir.path(), &gz);
+        let info = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: "5.4.0-40-generic".into(),
+            ..Default::default()
+        };
+
+        let mapped = ArchiveFile::open(&path).unwrap();
+        let read = ArchiveFile::read(&path).unwrap();
+        assert_eq!(mapped.is_mapped(), cfg!(target_os = "linux"));
+        assert!(!read.is_mapped());
+        for file in [&mapped, &read] {
+            assert_eq!(file.bytes(), gz);
+            let candidates = file.archive().lookup_candidates(&info).unwrap();
+            assert_eq!(
+                candidates[0].extract().unwrap(),
+                btf_of_arch(8, "5.4.0-40-generic")
+            );
+            file.check_unchanged().unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_and_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        // 空文件无法映射，按长度为 0 的内容处理
+        let empty = ArchiveFile::open(write_archive(dir.path(), b"")).unwrap();
+        assert!(!empty.is_mapped());
+        assert!(empty.bytes().is_empty());
+        assert!(empty.archive().kernels().is_err());
+        match ArchiveFile::open(dir.path().join("missing")) {
+            Err(Error::FileReadError(_, e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            v => panic!("{:?}", v.map(|v| v.len)),
+        }
+    }
+
+    #[test]
+    fn changes_in_place_are_detected_but_renames_are_not() {
+        let dir = tempfile::tempdir().unwrap();
+        let gz = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "a"),
+            )
+            .gz();
+        let path = write_archive(dir.path(), &gz);
+
+        // 替换为新文件时，已映射的旧文件保持不变
+        let renamed = ArchiveFile::open(&path).unwrap();
+        let replacement = dir.path().join("new.tar.gz");
+        fs::write(&replacement, b"new contents").unwrap();
+        fs::rename(&replacement, &path).unwrap();
+        renamed.check_unchanged().unwrap();
+        assert_eq!(renamed.bytes(), gz);
+
+        fs::write(&path, &gz).unwrap();
+        for file in [
+            ArchiveFile::open(&path).unwrap(),
+            ArchiveFile::read(&path).unwrap(),
+        ] {
+            fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .unwrap()
+                .write_all(b"appended")
+                .unwrap();
+            // 只访问打开时的长度以内的内容
+            assert_eq!(file.bytes().len(), gz.len());
+            assert!(matches!(
+                file.check_unchanged(),
+                Err(Error::ArchiveChanged(v)) if v == path.display().to_string()
+            ));
+        }
+    }
+}
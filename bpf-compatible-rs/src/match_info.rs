@@ -0,0 +1,363 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Which btf a lookup settled on and where it came from, for callers recording it, e.g. in
+//! their telemetry, beyond the path handed to libbpf.
+use std::path::{Path, PathBuf};
+
+use crate::{
+    arch,
+    archive::{normalize_entry_path, BtfEntry},
+    flat::{generate_flat_btf_paths_for, parse_flat_btf_path},
+    generate_btf_archive_paths_for, release, SystemInfo,
+};
+
+/// Where the btf handed out came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum BtfSource {
+    /// The kernel's own btf, at [`crate::VMLINUX_BTF_PATH`]
+    Native,
+    /// A btf of the kernel installed by the distro, see [`crate::native`]
+    Installed,
+    /// An entry of the archive
+    Archive,
+    /// A btf extracted or downloaded by an earlier call, from the persistent cache
+    Cache,
+    /// A btf downloaded from btfhub-archive
+    Download,
+    /// A btf generated from the vmlinux of the kernel with pahole, see `pahole`
+    Pahole,
+    /// The file named by the operator, e.g. through `BPF_COMPATIBLE_BTF_PATH`
+    Override,
+}
+
+/// What the lookup made of the kernel's own btf, at [`crate::VMLINUX_BTF_PATH`]
+///
+/// Tells hosts whose kernel has no btf from those where it's there but can't be used, e.g.
+/// in a container without the permission to read it, which needed the archive too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum NativeBtfStatus {
+    /// It wasn't looked at, e.g. as a strategy before it found the btf
+    #[default]
+    Unknown,
+    /// It's there and valid, so it was used
+    Usable,
+    /// The kernel doesn't export it
+    Missing,
+    /// It's there, but couldn't be read or isn't a valid btf
+    Unusable,
+    /// It isn't that of the kernel looked up, e.g. as the system is faked
+    Ignored,
+}
+
+impl NativeBtfStatus {
+    /// What `path`, the native btf of the running kernel, is like
+    #[cfg(feature = "host")]
+    pub fn probe(path: &Path) -> Self {
+        #[cfg(feature = "fake-system")]
+        if crate::fake::is_kernel_faked() {
+            return NativeBtfStatus::Ignored;
+        }
+        // 文件不存在与存在但无法访问需要区分
+        match std::fs::symlink_metadata(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => NativeBtfStatus::Missing,
+            _ => match crate::btf::check_btf_file(path) {
+                Ok(()) => NativeBtfStatus::Usable,
+                Err(_) => NativeBtfStatus::Unusable,
+            },
+        }
+    }
+}
+
+/// The btf a lookup settled on
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchInfo {
+    /// Path of the entry in the archive, or of the file on disk; `None` for the kernel's
+    /// own btf, and for btfs from the cache, whose entry isn't recorded
+    pub entry_path: Option<PathBuf>,
+    pub source: BtfSource,
+    /// Whether the btf is that of the kernel release itself, rather than of a close one,
+    /// see [`is_release_of`]; `false` if unknown, for btfs from the cache or named by the
+    /// operator
+    pub exact: bool,
+    /// Release of the kernel the btf is of, e.g. the nearest one if it isn't exact
+    pub kernel_release: String,
+    /// Whether the release only matched once the local version of a custom build was
+    /// stripped from that of the running kernel, see [`release::strip_local_version`]
+    pub local_version_stripped: bool,
+    /// Position of the entry among the paths of [`generate_btf_archive_paths_for`], then
+    /// those of a flat archive, 0 for the most preferred one; `None` if it's none of them,
+    /// e.g. the btf of a nearby release, or not from the archive. See [`candidate_of`]
+    pub candidate: Option<usize>,
+    /// What the lookup made of the kernel's own btf
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub native_btf: NativeBtfStatus,
+    /// Whether the btf was extracted by an earlier call of the process and reused; the
+    /// rest describes it as that call found it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub memoized: bool,
+}
+
+impl MatchInfo {
+    /// The kernel's own btf, of the running kernel `kernel_release`
+    pub fn native(kernel_release: impl Into<String>) -> Self {
+        Self {
+            entry_path: None,
+            source: BtfSource::Native,
+            exact: true,
+            kernel_release: kernel_release.into(),
+            local_version_stripped: false,
+            candidate: None,
+            native_btf: NativeBtfStatus::Usable,
+            memoized: false,
+        }
+    }
+
+    /// A btf from `source` other than an entry of an archive, at `entry_path` if known
+    pub fn of_source(
+        source: BtfSource,
+        entry_path: Option<PathBuf>,
+        exact: bool,
+        kernel_release: impl Into<String>,
+    ) -> Self {
+        Self {
+            entry_path,
+            source,
+            exact,
+            kernel_release: kernel_release.into(),
+            local_version_stripped: false,
+            candidate: None,
+            native_btf: NativeBtfStatus::Unknown,
+            memoized: false,
+        }
+    }
+
+    /// The btf of `entry`, found from `source` for the system `info`
+    pub fn of_entry(entry: &BtfEntry, info: &SystemInfo, source: BtfSource) -> Self {
+        Self {
+            entry_path: Some(entry.path.clone()),
+            source,
+            exact: is_release_of(&entry.kernel_release, info),
+            kernel_release: entry.kernel_release.clone(),
+            local_version_stripped: is_stripped_release_of(&entry.kernel_release, info),
+            candidate: candidate_of(&entry.path, info),
+            native_btf: NativeBtfStatus::Unknown,
+            memoized: false,
+        }
+    }
+}
+
+/// Whether `release` names the kernel of `info`, in any of the forms of [`release::release_variants`]
+pub fn is_release_of(release: &str, info: &SystemInfo) -> bool {
+    let mut arches = arch::arch_directories(&info.arch);
+    arches.push(&info.arch);
+    release::release_variants(&info.kernel_release, &arches).contains(&release)
+}
+
+/// Whether `release` only names the kernel of `info` once its local version is stripped, see [`release::is_local_version_stripped`]
+pub fn is_stripped_release_of(release: &str, info: &SystemInfo) -> bool {
+    let mut arches = arch::arch_directories(&info.arch);
+    arches.push(&info.arch);
+    release::is_local_version_stripped(&info.kernel_release, release, &arches)
+}
+
+/// Position of the archive entry `path` among the paths of [`generate_btf_archive_paths_for`] for `info`
+///
+/// The paths are relative to the `btfhub-archive` directory, so `path` matches one if it
+/// ends with its components, whatever directories the archive puts it under. The paths
+/// of [`generate_flat_btf_paths_for`] follow, for entries at the root of the archive.
+pub fn candidate_of(path: &Path, info: &SystemInfo) -> Option<usize> {
+    let paths = generate_btf_archive_paths_for(info);
+    if let Some(v) = paths.iter().position(|v| path.ends_with(v)) {
+        return Some(v);
+    }
+    let path = normalize_entry_path(path);
+    parse_flat_btf_path(&path)?;
+    generate_flat_btf_paths_for(info)
+        .iter()
+        .position(|v| path == Path::new(v))
+        .map(|v| paths.len() + v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::BtfEncoding;
+
+    fn centos(release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "centos".into(),
+            version_id: "8".into(),
+            arch: "x86_64".into(),
+            kernel_release: release.into(),
+            ..Default::default()
+        }
+    }
+
+    fn entry(path: &str, kernel_release: &str) -> BtfEntry {
+        BtfEntry {
+            distro: "centos".into(),
+            version: "8".into(),
+            arch: "x86_64".into(),
+            kernel_release: kernel_release.into(),
+            path: path.into(),
+            size: 0,
+            encoding: BtfEncoding::Plain,
+            is_link: false,
+            byte_swapped: false,
+        }
+    }
+
+    #[test]
+    fn releases_match_in_any_form() {
+        let info = centos("4.18.0-425.3.1.el8.x86_64");
+        assert!(is_release_of("4.18.0-425.3.1.el8.x86_64", &info));
+        // 去掉架构后缀的版本同样是该内核
+        assert!(is_release_of("4.18.0-425.3.1.el8", &info));
+        assert!(!is_release_of("4.18.0-425.10.1.el8", &info));
+        assert!(!is_release_of("4.18.0-425.3.1.el8.aarch64", &info));
+    }
+
+    #[test]
+    fn release_without_the_local_version_is_exact_but_noted() {
+        let info = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: "5.4.0-40-generic-mycorp1".into(),
+            ..Default::default()
+        };
+        assert!(is_release_of("5.4.0-40-generic", &info));
+        assert!(is_stripped_release_of("5.4.0-40-generic", &info));
+        assert!(!is_stripped_release_of("5.4.0-40-generic-mycorp1", &info));
+        let mut stripped = entry(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            "5.4.0-40-generic",
+        );
+        stripped.distro = "ubuntu".into();
+        stripped.version = "20.04".into();
+        let matched = MatchInfo::of_entry(&stripped, &info, BtfSource::Archive);
+        assert!(matched.exact);
+        assert!(matched.local_version_stripped);
+    }
+
+    #[cfg(feature = "host")]
+    #[test]
+    fn exact_match_of_the_archive() {
+        use crate::{
+            fixture::{minimal_valid_btf, FixtureArchive},
+            TarballBtfArchive,
+        };
+
+        let info = centos("4.18.0-425.3.1.el8.x86_64");
+        let tar = FixtureArchive::new()
+            .btf(
+                "centos",
+                "8",
+                "x86_64",
+                "4.18.0-425.3.1.el8.x86_64",
+                minimal_valid_btf(),
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&tar).unwrap();
+        let matched =
+            MatchInfo::of_entry(&archive.lookup(&info).unwrap(), &info, BtfSource::Archive);
+        assert_eq!(
+            matched,
+            MatchInfo {
+                entry_path: Some(
+                    "btfhub-archive/centos/8/x86_64/4.18.0-425.3.1.el8.x86_64.btf".into()
+                ),
+                source: BtfSource::Archive,
+                exact: true,
+                kernel_release: "4.18.0-425.3.1.el8.x86_64".into(),
+                local_version_stripped: false,
+                candidate: Some(0),
+                native_btf: NativeBtfStatus::Unknown,
+                memoized: false,
+            }
+        );
+    }
+
+    #[test]
+    fn nearest_release_is_not_exact_nor_a_candidate() {
+        let info = centos("4.18.0-425.3.1.el8.x86_64");
+        let nearest = entry(
+            "btfhub-archive/centos/8/x86_64/4.18.0-425.1.1.el8.x86_64.btf",
+            "4.18.0-425.1.1.el8.x86_64",
+        );
+        let matched = MatchInfo::of_entry(&nearest, &info, BtfSource::Archive);
+        assert!(!matched.exact);
+        assert_eq!(matched.kernel_release, "4.18.0-425.1.1.el8.x86_64");
+        assert_eq!(matched.candidate, None);
+        assert_eq!(matched.entry_path, Some(nearest.path));
+    }
+
+    #[test]
+    fn candidates_are_counted_from_the_most_preferred() {
+        let info = centos("4.18.0-425.3.1.el8.x86_64");
+        let paths = generate_btf_archive_paths_for(&info);
+        // 归档中的前缀目录不影响
+        for (i, path) in paths.iter().enumerate() {
+            for prefix in ["btfhub-archive", "./btfs/btfhub-archive", ""] {
+                let path = Path::new(prefix).join(path);
+                assert_eq!(candidate_of(&path, &info), Some(i), "{}", path.display());
+            }
+        }
+        // 平铺归档的条目排在后面
+        assert_eq!(
+            candidate_of(Path::new("./4.18.0-425.3.1.el8.x86_64.btf"), &info),
+            Some(paths.len())
+        );
+        assert_eq!(
+            candidate_of(Path::new("4.18.0-425.3.1.el8.btf"), &info),
+            Some(paths.len() + 1)
+        );
+        assert_eq!(
+            candidate_of(
+                Path::new("centos/7/x86_64/4.18.0-425.3.1.el8.x86_64.btf"),
+                &info
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn native_and_other_sources() {
+        let native = MatchInfo::native("5.15.0-76-generic");
+        assert_eq!(native.source, BtfSource::Native);
+        assert!(native.exact && native.entry_path.is_none());
+        assert_eq!(native.native_btf, NativeBtfStatus::Usable);
+        let cached = MatchInfo::of_source(BtfSource::Cache, None, false, "5.15.0-76-generic");
+        assert_eq!(cached.native_btf, NativeBtfStatus::Unknown);
+        assert_eq!(cached.candidate, None);
+        assert!(!cached.exact);
+    }
+
+    #[cfg(all(feature = "host", target_os = "linux"))]
+    #[test]
+    fn missing_and_unusable_native_btfs() {
+        use crate::fixture::minimal_valid_btf;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vmlinux");
+        assert_eq!(NativeBtfStatus::probe(&path), NativeBtfStatus::Missing);
+        std::fs::write(&path, b"not btf").unwrap();
+        assert_eq!(NativeBtfStatus::probe(&path), NativeBtfStatus::Unusable);
+        std::fs::write(&path, minimal_valid_btf()).unwrap();
+        assert_eq!(NativeBtfStatus::probe(&path), NativeBtfStatus::Usable);
+    }
+}
@@ -0,0 +1,621 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Which entries of an archive hold the btf of a system, and which to fall back to.
+//!
+//! The lookups of `bpf-compatible-sys`, streaming or indexed, and of
+//! [`TarballBtfArchive`](crate::TarballBtfArchive) rank the entries they come across with a
+//! [`BtfMatcher`], and ask it for a [`Fallback`] once none matched, so they can't disagree.
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::{normalize_entry_path, BtfEncoding, BTF_ENTRY_SUFFIXES},
+    distro::{el_distros, is_el, is_rolling},
+    flat::generate_flat_btf_paths_for,
+    generate_backport_btf_paths_for, generate_btf_archive_paths_for,
+    generate_generic_btf_paths_for, generate_hwe_btf_paths_for,
+    release::{nearest_release, MatchPolicy},
+    SystemInfo,
+};
+
+/// The paths the btf of a system may be at in an archive, best first, and the fallbacks `policy` allows
+///
+/// The candidates are the paths of [`generate_btf_archive_paths_for`] under the prefix,
+/// then those of [`generate_generic_btf_paths_for`] when matching any distro, then, for
+/// `BestEffort`, those of [`generate_backport_btf_paths_for`], and last the btfs at the
+/// root of a flat archive, see [`crate::flat`]. Each may be stored in any encoding of
+/// [`BtfEncoding`].
+#[derive(Debug, Clone)]
+pub struct BtfMatcher {
+    info: SystemInfo,
+    prefix: PathBuf,
+    policy: MatchPolicy,
+    any_distro: bool,
+    candidates: Vec<PathBuf>,
+    /// Rank of the first btf at the root of a flat archive
+    flat_start: usize,
+    hwe_paths: Vec<PathBuf>,
+    backport_paths: Vec<PathBuf>,
+}
+
+impl BtfMatcher {
+    /// Match the btf of `info` under `prefix`, e.g. `btfhub-archive`, falling back as `policy` allows
+    ///
+    /// Rolling distros, see [`is_rolling`], are matched by kernel release under any distro.
+    pub fn new(info: &SystemInfo, prefix: impl AsRef<Path>, policy: MatchPolicy) -> Self {
+        let mut matcher = Self {
+            info: info.clone(),
+            prefix: normalize_entry_path(prefix.as_ref()),
+            policy,
+            any_distro: false,
+            candidates: vec![],
+            flat_start: 0,
+            hwe_paths: vec![],
+            backport_paths: vec![],
+        };
+        matcher.generate_candidates();
+        matcher
+    }
+
+    /// Also match the btf of the kernel release under `generic` and any other distro, as for rolling distros
+    pub fn with_any_distro(mut self, any_distro: bool) -> Self {
+        self.any_distro = any_distro;
+        self.generate_candidates();
+        self
+    }
+
+    fn generate_candidates(&mut self) {
+        let info = &self.info;
+        let prefix = &self.prefix;
+        let mut paths = generate_btf_archive_paths_for(info);
+        if self.any_distro && !is_rolling(&info.distro_id) {
+            paths.extend(generate_generic_btf_paths_for(info));
+        }
+        // Debian 的 backports 内核不在 btfhub 中，BestEffort 时退而使用其来源版本中同一 ABI 的内核
+        self.backport_paths = match self.policy {
+            MatchPolicy::BestEffort => generate_backport_btf_paths_for(info)
+                .iter()
+                .map(|v| prefix.join(v))
+                .collect(),
+            _ => vec![],
+        };
+        // 平坦归档的候选排在最后，同时含 btfhub 目录的归档优先使用目录中的条目
+        let flat_paths = generate_flat_btf_paths_for(info);
+        self.candidates = paths
+            .iter()
+            .map(|v| prefix.join(v))
+            .chain(self.backport_paths.iter().cloned())
+            .chain(flat_paths.iter().map(PathBuf::from))
+            .collect();
+        self.flat_start = self.candidates.len() - flat_paths.len();
+        self.hwe_paths = generate_hwe_btf_paths_for(info)
+            .iter()
+            .map(|v| prefix.join(v))
+            .collect();
+    }
+
+    /// The system matched
+    pub fn info(&self) -> &SystemInfo {
+        &self.info
+    }
+
+    /// The directory of the btfhub tree, normalized
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    pub fn policy(&self) -> MatchPolicy {
+        self.policy
+    }
+
+    /// Whether only the btf of the exact kernel release may match
+    pub fn is_exact(&self) -> bool {
+        self.policy == MatchPolicy::Exact
+    }
+
+    /// Whether the kernel release is looked for under any distro, see [`BtfMatcher::with_any_distro`]
+    pub fn any_distro(&self) -> bool {
+        self.any_distro || is_rolling(&self.info.distro_id)
+    }
+
+    /// Whether the kernel release is looked for under every distro of the Enterprise Linux
+    /// family last, which `BestEffort` does for them
+    pub fn el_fallback(&self) -> bool {
+        self.policy == MatchPolicy::BestEffort && is_el(&self.info.distro_id)
+    }
+
+    /// Whether [`BtfMatcher::fallback`] needs the entries of the release under other distros
+    pub fn wants_other_distros(&self) -> bool {
+        self.any_distro() || self.el_fallback()
+    }
+
+    /// Every candidate path, normalized and best first, without any encoding suffix
+    pub fn candidates(&self) -> &[PathBuf] {
+        &self.candidates
+    }
+
+    /// The candidates in the btfhub tree, i.e. without the btfs at the root of a flat archive
+    pub fn btfhub_candidates(&self) -> &[PathBuf] {
+        &self.candidates[..self.flat_start]
+    }
+
+    /// Whether the candidate of `rank` is a btf at the root of a flat archive
+    pub fn is_flat(&self, rank: usize) -> bool {
+        rank >= self.flat_start
+    }
+
+    /// Rank of the candidate the entry at `path` holds, and how the btf is stored in it
+    ///
+    /// `path` is normalized, see [`normalize_entry_path`]; `<candidate>`, `<candidate>.gz`,
+    /// `<candidate>.tar.xz` and `<candidate>.tar.gz` all match.
+    pub fn rank(&self, path: &Path) -> Option<(usize, BtfEncoding)> {
+        let path = path.to_str()?;
+        BTF_ENTRY_SUFFIXES.iter().find_map(|(suffix, encoding)| {
+            let stem = path.strip_suffix(suffix)?;
+            self.candidates
+                .iter()
+                .position(|v| btf_stem(v) == Some(stem))
+                .map(|rank| (rank, *encoding))
+        })
+    }
+
+    /// Whether `path` is a btf under `<distro>/<any version>/<arch>` of a candidate, i.e.
+    /// one [`BtfMatcher::fallback`] may pick as the nearest release
+    pub fn is_sibling(&self, path: &Path) -> bool {
+        self.candidates
+            .iter()
+            .any(|v| same_distro_and_arch(v, path))
+    }
+
+    /// Whether `path` is the btf of the release of a candidate under the prefix, in a
+    /// directory of the same architecture, i.e. one [`BtfMatcher::fallback`] may pick under another distro
+    pub fn is_other_distro(&self, path: &Path) -> bool {
+        path.starts_with(&self.prefix) && self.candidates.iter().any(|v| same_release(v, path))
+    }
+
+    /// Whether an archive of the btfs at `paths`, e.g. those of its listing, leaves a chance to match
+    ///
+    /// Unless the match is exact, any btf next to a candidate may be the nearest release.
+    pub fn may_match<'a>(&self, mut paths: impl Iterator<Item = &'a Path>) -> bool {
+        paths.any(|path| {
+            let path = normalize_entry_path(path);
+            if self.is_exact() {
+                self.rank(&path).is_some()
+            } else {
+                self.is_sibling(&path)
+            }
+        })
+    }
+
+    /// Whether `path` is a candidate under the Ubuntu release an HWE kernel comes from, see [`generate_hwe_btf_paths_for`]
+    pub fn is_hwe(&self, path: &Path) -> bool {
+        self.hwe_paths.iter().any(|v| v == path)
+    }
+
+    /// Whether `path` is in the directory of the release a Debian backports kernel is built from, see [`generate_backport_btf_paths_for`]
+    pub fn is_backport(&self, path: &Path) -> bool {
+        self.backport_paths
+            .iter()
+            .any(|v| v.parent() == path.parent())
+    }
+
+    /// The btf to use when no candidate is in the archive, as the policy allows
+    ///
+    /// Unless the match is exact, the nearest release of `siblings`, the btfs for which
+    /// [`BtfMatcher::is_sibling`] holds, see [`nearest_release`]. Directories are tried
+    /// in the order of the candidates, and releases of the same flavor are looked for in
+    /// every directory before `BestEffort` crosses flavors; as a last resort,
+    /// `BestEffort` takes the exact release under another version of the distro.
+    ///
+    /// Then, when matching any distro, or for `BestEffort` on the Enterprise Linux
+    /// family, the release of a candidate among `other_distros`, the btfs for which
+    /// [`BtfMatcher::is_other_distro`] holds. One under the directory of the running distro
+    /// is preferred; otherwise the smallest path is taken, so the choice doesn't depend
+    /// on the order of the archive.
+    pub fn fallback(&self, siblings: &[PathBuf], other_distros: &[PathBuf]) -> Option<Fallback> {
+        if !self.is_exact() {
+            if let Some(v) = self.nearest(siblings) {
+                return Some(v);
+            }
+        }
+        let distro_dir = self.prefix.join(&self.info.distro_id);
+        if self.any_distro() {
+            return self.other_distro(other_distros, &distro_dir);
+        }
+        if self.el_fallback() {
+            let el_dirs = el_distros()
+                .map(|v| self.prefix.join(v))
+                .collect::<Vec<_>>();
+            let other_els = other_distros
+                .iter()
+                .filter(|v| el_dirs.iter().any(|dir| v.starts_with(dir)))
+                .cloned()
+                .collect::<Vec<_>>();
+            return self.other_distro(&other_els, &distro_dir);
+        }
+        None
+    }
+
+    fn nearest(&self, siblings: &[PathBuf]) -> Option<Fallback> {
+        let siblings = siblings
+            .iter()
+            .filter_map(|path| {
+                let (release, encoding) = split_btf_name(path.file_name()?.to_str()?)?;
+                Some((release, path, encoding))
+            })
+            .collect::<Vec<_>>();
+        // 先在所有候选目录（如 HWE 内核所属版本的目录）中查找同一 flavor 的版本，之后才跨 flavor
+        let passes = match self.policy {
+            MatchPolicy::BestEffort => {
+                &[MatchPolicy::SameFlavorNearest, MatchPolicy::BestEffort][..]
+            }
+            _ => std::slice::from_ref(&self.policy),
+        };
+        for pass in passes {
+            for candidate in &self.candidates {
+                let (release, _) = split_btf_name(candidate.file_name()?.to_str()?)?;
+                // 同一目录下各条目对应的内核版本，以及其存储方式
+                let available = siblings
+                    .iter()
+                    .filter(|(_, path, _)| path.parent() == candidate.parent())
+                    .collect::<Vec<_>>();
+                let releases = available.iter().map(|(v, _, _)| *v).collect::<Vec<_>>();
+                let Some(nearest) = nearest_release(release, &releases, *pass) else {
+                    continue;
+                };
+                let (_, path, encoding) = available.iter().find(|(v, _, _)| *v == nearest)?;
+                return Some(Fallback {
+                    path: normalize_entry_path(path),
+                    encoding: *encoding,
+                    reason: FallbackReason::NearestRelease {
+                        release: release.to_string(),
+                        same_flavor: *pass != MatchPolicy::BestEffort,
+                    },
+                });
+            }
+        }
+        if self.policy != MatchPolicy::BestEffort {
+            return None;
+        }
+        // 最后的手段：同一发行版其他版本目录下的同一内核，按路径排序保证结果确定
+        let (release, _) = split_btf_name(self.candidates.first()?.file_name()?.to_str()?)?;
+        let (_, path, encoding) = siblings
+            .iter()
+            .filter(|(v, _, _)| *v == release)
+            .min_by_key(|(_, path, _)| *path)?;
+        Some(Fallback {
+            path: normalize_entry_path(path),
+            encoding: *encoding,
+            reason: FallbackReason::OtherVersion {
+                release: release.to_string(),
+            },
+        })
+    }
+
+    fn other_distro(&self, other_distros: &[PathBuf], distro_dir: &Path) -> Option<Fallback> {
+        for candidate in &self.candidates {
+            let mut matches = other_distros
+                .iter()
+                .filter(|v| same_release(candidate, v))
+                .collect::<Vec<_>>();
+            matches.sort();
+            matches.dedup();
+            let Some(path) = matches
+                .iter()
+                .find(|v| v.starts_with(distro_dir))
+                .or(matches.first())
+            else {
+                continue;
+            };
+            // 发行版目录是 <prefix>/<distro>，各条目的前缀相同，比较去掉后三个组件后的目录即可
+            let distro_of = |path: &Path| path.parent()?.parent()?.parent().map(Path::to_path_buf);
+            let distros = matches
+                .iter()
+                .filter_map(|v| distro_of(v))
+                .collect::<BTreeSet<_>>();
+            let (_, encoding) = split_btf_name(path.file_name()?.to_str()?)?;
+            return Some(Fallback {
+                path: normalize_entry_path(path),
+                encoding,
+                reason: FallbackReason::OtherDistro {
+                    name: candidate.file_name()?.to_string_lossy().into_owned(),
+                    distros: match path.starts_with(distro_dir) {
+                        true => 1,
+                        false => distros.len(),
+                    },
+                },
+            });
+        }
+        None
+    }
+}
+
+/// A btf picked by [`BtfMatcher::fallback`], for want of the one of the system
+///
+/// The `Display` explains the choice, for the logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fallback {
+    /// Path of the entry, normalized and without following links
+    pub path: PathBuf,
+    pub encoding: BtfEncoding,
+    pub reason: FallbackReason,
+}
+
+/// Why a [`Fallback`] was picked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackReason {
+    /// The nearest release of `release`, of the same flavor or not, next to a candidate
+    NearestRelease { release: String, same_flavor: bool },
+    /// `release` itself, under another version of the distro
+    OtherVersion { release: String },
+    /// The btf `name`, under another distro; `distros` is how many distros have it, 1 if
+    /// the one of the running distro was picked
+    OtherDistro { name: String, distros: usize },
+}
+
+impl fmt::Display for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match &self.reason {
+            FallbackReason::NearestRelease {
+                release,
+                same_flavor,
+            } => write!(
+                f,
+                "No btf for {} in the archive, falling back to {}, the nearest release of {}",
+                release,
+                path,
+                match same_flavor {
+                    true => "the same flavor",
+                    false => "another flavor",
+                }
+            ),
+            FallbackReason::OtherVersion { release } => write!(
+                f,
+                "No btf for {} in the archive, falling back to {}",
+                release, path
+            ),
+            FallbackReason::OtherDistro { name, distros } if *distros > 1 => write!(
+                f,
+                "The btf of {} is in the archive under {} distros, using {}",
+                name, distros, path
+            ),
+            FallbackReason::OtherDistro { name, .. } => write!(
+                f,
+                "No btf for {} at the paths of the system, using {}",
+                name, path
+            ),
+        }
+    }
+}
+
+/// The entry paths `candidate` may be stored at, with the encoding of each, see [`BtfMatcher::rank`]
+pub fn encoded_paths(candidate: &Path) -> impl Iterator<Item = (PathBuf, BtfEncoding)> + '_ {
+    BTF_ENTRY_SUFFIXES.iter().filter_map(|(suffix, encoding)| {
+        Some((
+            PathBuf::from(format!("{}{}", btf_stem(candidate)?, suffix)),
+            *encoding,
+        ))
+    })
+}
+
+/// Split the file name of a btf entry into the kernel release and the encoding, e.g.
+/// `5.4.0-40-generic` and [`BtfEncoding::Gzipped`] for `5.4.0-40-generic.btf.gz`
+pub fn split_btf_name(name: &str) -> Option<(&str, BtfEncoding)> {
+    BTF_ENTRY_SUFFIXES
+        .iter()
+        .find_map(|(suffix, encoding)| Some((name.strip_suffix(suffix)?, *encoding)))
+        .filter(|(release, _)| !release.is_empty())
+}
+
+/// Kernel release of the btf at `path`, whatever its encoding
+pub fn btf_release(path: &Path) -> Option<&str> {
+    Some(split_btf_name(path.file_name()?.to_str()?)?.0)
+}
+
+/// `candidate` without its `.btf`
+fn btf_stem(candidate: &Path) -> Option<&str> {
+    candidate.to_str()?.strip_suffix(".btf")
+}
+
+/// Whether `path` is under `<distro>/<any version>/<arch>` of `candidate`
+fn same_distro_and_arch(candidate: &Path, path: &Path) -> bool {
+    let (Some(candidate_dir), Some(dir)) = (candidate.parent(), path.parent()) else {
+        return false;
+    };
+    candidate_dir.file_name() == dir.file_name()
+        && candidate_dir.parent().and_then(Path::parent) == dir.parent().and_then(Path::parent)
+}
+
+/// Whether `path` is the btf of the release of `candidate`, in a directory of the same architecture
+fn same_release(candidate: &Path, path: &Path) -> bool {
+    let (Some(candidate_dir), Some(dir)) = (candidate.parent(), path.parent()) else {
+        return false;
+    };
+    candidate_dir.file_name() == dir.file_name()
+        && btf_release(candidate).is_some_and(|v| btf_release(path) == Some(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(distro_id: &str, version_id: &str, release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: distro_id.into(),
+            version_id: version_id.into(),
+            arch: "x86_64".into(),
+            kernel_release: release.into(),
+            ..Default::default()
+        }
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn entries_are_ranked_by_candidate_whatever_their_encoding() {
+        let matcher = BtfMatcher::new(
+            &system("ubuntu", "20.04", "5.4.0-40-generic"),
+            "./btfhub-archive",
+            MatchPolicy::Exact,
+        );
+        let hub = "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf";
+        assert_eq!(matcher.candidates()[0], Path::new(hub));
+        for (suffix, encoding) in [
+            ("", BtfEncoding::Plain),
+            (".gz", BtfEncoding::Gzipped),
+            (".tar.xz", BtfEncoding::Tarball),
+            (".tar.gz", BtfEncoding::Tarball),
+        ] {
+            let path = PathBuf::from(format!("{hub}{suffix}"));
+            assert_eq!(matcher.rank(&path), Some((0, encoding)), "{suffix}");
+        }
+        // 代号目录排在版本目录之后，平坦归档的 btf 排在最后
+        let (rank, _) = matcher
+            .rank(Path::new(
+                "btfhub-archive/ubuntu/focal/x86_64/5.4.0-40-generic.btf",
+            ))
+            .unwrap();
+        assert!(rank > 0 && !matcher.is_flat(rank));
+        let (rank, _) = matcher.rank(Path::new("5.4.0-40-generic.btf")).unwrap();
+        assert!(matcher.is_flat(rank));
+        assert_eq!(matcher.btfhub_candidates().len(), rank);
+        assert_eq!(
+            matcher.rank(Path::new(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf"
+            )),
+            None
+        );
+        assert_eq!(
+            encoded_paths(Path::new(hub)).map(|(v, _)| v).nth(1),
+            Some(PathBuf::from(format!("{hub}.gz")))
+        );
+    }
+
+    #[test]
+    fn nearest_release_is_looked_for_next_to_the_candidates() {
+        let info = system("ubuntu", "20.04", "5.4.0-42-generic");
+        let siblings = paths(&[
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-azure.btf.gz",
+            "btfhub-archive/ubuntu/18.04/x86_64/5.4.0-42-generic.btf",
+        ]);
+        let fallback = |policy| {
+            let matcher = BtfMatcher::new(&info, "btfhub-archive", policy);
+            assert!(siblings.iter().all(|v| matcher.is_sibling(v)));
+            matcher.fallback(&siblings, &[])
+        };
+        assert_eq!(fallback(MatchPolicy::Exact), None);
+        let nearest = fallback(MatchPolicy::SameFlavorNearest).unwrap();
+        assert_eq!(nearest.path, siblings[0]);
+        assert!(nearest
+            .to_string()
+            .contains("the nearest release of the same flavor"));
+        // 只有其他 flavor 时，BestEffort 跨 flavor；都没有时取其他版本目录下的同一内核
+        let crossed = BtfMatcher::new(&info, "btfhub-archive", MatchPolicy::BestEffort)
+            .fallback(&siblings[1..], &[])
+            .unwrap();
+        assert_eq!(
+            (crossed.path.as_path(), crossed.encoding),
+            (siblings[1].as_path(), BtfEncoding::Gzipped)
+        );
+        let other_version = BtfMatcher::new(&info, "btfhub-archive", MatchPolicy::BestEffort)
+            .fallback(&siblings[2..], &[])
+            .unwrap();
+        assert_eq!(
+            other_version.reason,
+            FallbackReason::OtherVersion {
+                release: "5.4.0-42-generic".into()
+            }
+        );
+    }
+
+    #[test]
+    fn release_under_other_distros_is_matched_when_asked() {
+        let info = system("arch", "", "6.1.0-1-default");
+        let others = paths(&[
+            "btfhub-archive/opensuse/15.5/x86_64/6.1.0-1-default.btf",
+            "btfhub-archive/fedora/38/x86_64/6.1.0-1-default.btf",
+            "btfhub-archive/fedora/38/arm64/6.1.0-1-default.btf",
+        ]);
+        // 滚动发行版总是跨发行版查找
+        let matcher = BtfMatcher::new(&info, "btfhub-archive", MatchPolicy::Exact);
+        assert!(matcher.any_distro());
+        let kept = others
+            .iter()
+            .filter(|v| matcher.is_other_distro(v))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(kept, others[..2]);
+        let fallback = matcher.fallback(&[], &kept).unwrap();
+        assert_eq!(fallback.path, others[1], "the smallest path is taken");
+        assert!(fallback.to_string().contains("under 2 distros"));
+
+        let ubuntu = system("ubuntu", "22.04", "6.1.0-1-default");
+        let matcher = BtfMatcher::new(&ubuntu, "btfhub-archive", MatchPolicy::Exact);
+        assert!(!matcher.wants_other_distros());
+        assert_eq!(matcher.fallback(&[], &kept), None);
+        let matcher = matcher.with_any_distro(true);
+        assert!(matcher.candidates().contains(&PathBuf::from(
+            "btfhub-archive/generic/x86_64/6.1.0-1-default.btf"
+        )));
+        assert!(matcher.fallback(&[], &kept).is_some());
+    }
+
+    #[test]
+    fn best_effort_crosses_the_el_family_only() {
+        let info = system("rocky", "8.6", "4.18.0-372.9.1.el8.x86_64");
+        let others = paths(&[
+            "btfhub-archive/ubuntu/20.04/x86_64/4.18.0-372.9.1.el8.x86_64.btf",
+            "btfhub-archive/centos/8/x86_64/4.18.0-372.9.1.el8.x86_64.btf",
+        ]);
+        let matcher = BtfMatcher::new(&info, "btfhub-archive", MatchPolicy::SameFlavorNearest);
+        assert!(!matcher.el_fallback());
+        let matcher = BtfMatcher::new(&info, "btfhub-archive", MatchPolicy::BestEffort);
+        assert!(matcher.el_fallback());
+        assert_eq!(matcher.fallback(&[], &others).unwrap().path, others[1]);
+        assert_eq!(matcher.fallback(&[], &others[..1]), None);
+    }
+
+    #[test]
+    fn backports_and_hwe_kernels_are_told_apart() {
+        let debian = system("debian", "11", "6.1.0-0.deb11.13-amd64");
+        let matcher = BtfMatcher::new(&debian, "btfhub-archive", MatchPolicy::Exact);
+        assert!(matcher
+            .rank(Path::new(
+                "btfhub-archive/debian/12/x86_64/6.1.0-13-amd64.btf"
+            ))
+            .is_none());
+        let matcher = BtfMatcher::new(&debian, "btfhub-archive", MatchPolicy::BestEffort);
+        let backport = Path::new("btfhub-archive/debian/12/x86_64/6.1.0-13-amd64.btf");
+        assert!(matcher.rank(backport).is_some());
+        assert!(matcher.is_backport(backport));
+
+        let hwe = system("ubuntu", "20.04", "5.15.0-76-generic");
+        let matcher = BtfMatcher::new(&hwe, "btfhub-archive", MatchPolicy::Exact);
+        let path = Path::new("btfhub-archive/ubuntu/22.04/x86_64/5.15.0-76-generic.btf");
+        assert!(matcher.rank(path).is_some());
+        assert!(matcher.is_hwe(path));
+        assert!(!matcher.is_hwe(&matcher.candidates()[0]));
+    }
+
+    #[test]
+    fn listings_without_a_chance_to_match_are_told() {
+        let info = system("ubuntu", "20.04", "5.4.0-42-generic");
+        let listed = [Path::new(
+            "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+        )];
+        let exact = BtfMatcher::new(&info, "btfhub-archive", MatchPolicy::Exact);
+        assert!(!exact.may_match(listed.into_iter()));
+        let nearest = BtfMatcher::new(&info, "btfhub-archive", MatchPolicy::SameFlavorNearest);
+        assert!(nearest.may_match(listed.into_iter()));
+    }
+}
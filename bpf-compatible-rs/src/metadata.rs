@@ -0,0 +1,140 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! What a btf bundle is, for answering "which archive is in this binary?" without listing
+//! every kernel: counts and sizes, plus an optional `.metadata` entry written by `btfgen`
+//! next to the distro directories, e.g. `btfhub-archive/.metadata`.
+//!
+//! The entry holds `key=value` lines, like os-release. `build_time` is the packing time in
+//! seconds since the epoch and `build_id` an identifier chosen by the packer; other keys
+//! are kept as is.
+use std::io::Read;
+
+/// Name of the metadata entry, under the directory holding the btfs
+pub const METADATA_ENTRY_NAME: &str = ".metadata";
+
+/// Contents of the metadata entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveMetadata {
+    /// When the archive was packed, in seconds since the epoch
+    pub build_time: Option<u64>,
+    /// Identifier of the build, e.g. a CI pipeline id or a version
+    pub build_id: Option<String>,
+    /// Every `key=value` line, in order, the two above included
+    pub fields: Vec<(String, String)>,
+}
+
+impl ArchiveMetadata {
+    /// Parse the `key=value` lines of the entry; other lines are ignored
+    ///
+    /// Values may be quoted, as in os-release. A `build_time` that isn't a number is
+    /// ignored, but kept in `fields`.
+    pub fn parse(contents: &[u8]) -> Self {
+        let mut metadata = Self::default();
+        for line in String::from_utf8_lossy(contents).lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim();
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            match key {
+                "build_time" => metadata.build_time = value.parse().ok(),
+                "build_id" => metadata.build_id = Some(value.to_string()),
+                _ => {}
+            }
+            metadata.fields.push((key.to_string(), value.to_string()));
+        }
+        metadata
+    }
+}
+
+/// Summary of an archive, see [`crate::archive::BtfhubArchive::info`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveInfo {
+    /// Number of btf entries under the prefix, links included, as counted by
+    /// [`crate::archive::BtfhubArchive::entries`]
+    pub btf_entries: usize,
+    /// Size of the tar, i.e. of the archive once decompressed
+    pub uncompressed_size: u64,
+    /// Size of the archive as given, the same as `uncompressed_size` for a plain tar
+    pub compressed_size: u64,
+    /// The metadata entry, if the archive has one
+    pub metadata: Option<ArchiveMetadata>,
+}
+
+/// A reader counting the bytes read through it
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    pub count: u64,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_value_lines_are_parsed() {
+        let metadata = ArchiveMetadata::parse(
+            b"# packed by btfgen\nbuild_time = 1700000000\nbuild_id=\"nightly 2023-11-14\"\n\
+              \nsource=btfhub\nnot a field\n",
+        );
+        assert_eq!(metadata.build_time, Some(1700000000));
+        assert_eq!(metadata.build_id.as_deref(), Some("nightly 2023-11-14"));
+        assert_eq!(
+            metadata.fields,
+            [
+                ("build_time", "1700000000"),
+                ("build_id", "nightly 2023-11-14"),
+                ("source", "btfhub"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_build_time_is_kept_as_a_field_only() {
+        let metadata = ArchiveMetadata::parse(b"build_time=yesterday\n");
+        assert_eq!(metadata.build_time, None);
+        assert_eq!(metadata.build_id, None);
+        assert_eq!(
+            metadata.fields,
+            [("build_time".to_string(), "yesterday".to_string())]
+        );
+        assert_eq!(ArchiveMetadata::parse(b""), ArchiveMetadata::default());
+        // 不是 UTF-8 的内容按有损方式解析
+        let metadata = ArchiveMetadata::parse(b"build_id=\xff42\n");
+        assert_eq!(metadata.build_id.as_deref(), Some("\u{fffd}42"));
+    }
+
+    #[test]
+    fn reader_counts_the_bytes_read() {
+        let mut reader = CountingReader::new(&[7u8; 1000][..]);
+        let mut buf = [0; 300];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.count, 300);
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(reader.count, 1000);
+    }
+}
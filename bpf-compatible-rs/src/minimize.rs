@@ -0,0 +1,380 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Tailoring an archive to BPF objects with `bpftool gen min_core_btf`, which strips every
+//! btf down to the types the objects relocate against, as `btfgen` does from the shell.
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use flate2::Compression;
+
+use crate::{
+    archive::{BtfEntryInfo, BtfhubArchive},
+    pack::BtfArchiveBuilder,
+    tarball::TarballBtfArchive,
+    Error, Result,
+};
+
+/// Command run when [`MinimizeOptions::with_bpftool`] isn't called, looked up in `PATH`
+pub const DEFAULT_BPFTOOL: &str = "bpftool";
+
+/// Options of [`minimize_btf_archive`]
+#[derive(Debug, Clone)]
+pub struct MinimizeOptions {
+    bpftool: PathBuf,
+    level: Compression,
+}
+
+impl Default for MinimizeOptions {
+    fn default() -> Self {
+        Self {
+            bpftool: PathBuf::from(DEFAULT_BPFTOOL),
+            level: Compression::best(),
+        }
+    }
+}
+
+impl MinimizeOptions {
+    /// Run `bpftool` instead of [`DEFAULT_BPFTOOL`]
+    pub fn with_bpftool(mut self, bpftool: impl AsRef<Path>) -> Self {
+        self.bpftool = bpftool.as_ref().to_path_buf();
+        self
+    }
+
+    /// Compress the output with `level` instead of [`Compression::best`]
+    pub fn with_level(mut self, level: Compression) -> Self {
+        self.level = level;
+        self
+    }
+}
+
+/// A btf that was minimized
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MinimizedEntry {
+    /// `<distro>/<version>/<arch>/<release>`
+    pub kernel: String,
+    /// Size of the btf before, once decompressed
+    pub original_size: u64,
+    /// Size of the btf bpftool wrote
+    pub minimized_size: u64,
+}
+
+/// A btf bpftool failed on, left out of the output
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MinimizeFailure {
+    /// `<distro>/<version>/<arch>/<release>`
+    pub kernel: String,
+    /// What went wrong, e.g. the exit status and stderr of bpftool
+    pub message: String,
+}
+
+/// Outcome of [`minimize_btf_archive`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MinimizeReport {
+    /// The btfs written to the output, in archive order
+    pub minimized: Vec<MinimizedEntry>,
+    /// The btfs that couldn't be minimized
+    pub failures: Vec<MinimizeFailure>,
+    /// Size of the input archive
+    pub input_size: u64,
+    /// Size of the output archive, 0 if nothing was written
+    pub output_size: u64,
+}
+
+impl MinimizeReport {
+    /// Total size of the minimized btfs before
+    pub fn original_size(&self) -> u64 {
+        self.minimized.iter().map(|v| v.original_size).sum()
+    }
+
+    /// Total size of the minimized btfs after
+    pub fn minimized_size(&self) -> u64 {
+        self.minimized.iter().map(|v| v.minimized_size).sum()
+    }
+}
+
+/// Minimize every btf of the archive at `input` for `objects`, and write the result to `output`
+///
+/// Each btf is extracted (following links and decompressing `.btf.gz` and `.btf.tar.xz`
+/// entries), then `bpftool gen min_core_btf <btf> <out> <objects...>` is run on it. The
+/// results are packed with [`BtfArchiveBuilder`], so the output is deterministic. A kernel
+/// listed more than once is only minimized the first time.
+///
+/// A btf that can't be extracted, or that bpftool fails on or turns into an invalid btf, is
+/// recorded in [`MinimizeReport::failures`] and left out. If bpftool can't be run at all,
+/// e.g. it isn't installed, this fails with [`Error::BpftoolUnavailable`]. If no btf could
+/// be minimized, `output` isn't written.
+pub fn minimize_btf_archive(
+    input: &Path,
+    objects: &[PathBuf],
+    output: &Path,
+    opts: &MinimizeOptions,
+) -> Result<MinimizeReport> {
+    let bytes =
+        std::fs::read(input).map_err(|e| Error::FileReadError(input.display().to_string(), e))?;
+    let archive = TarballBtfArchive::from_gzipped_bytes(&bytes)?;
+    let workdir = tempfile::tempdir().map_err(Error::TempDirError)?;
+    let mut report = MinimizeReport {
+        input_size: bytes.len() as u64,
+        ..Default::default()
+    };
+    let mut builder = BtfArchiveBuilder::new();
+    let mut seen = vec![];
+    for entry in BtfhubArchive::new(&bytes).entries() {
+        let BtfEntryInfo::Btf(entry) = entry? else {
+            continue;
+        };
+        let kernel = entry.kernel();
+        if seen.contains(&kernel) {
+            continue;
+        }
+        seen.push(kernel.clone());
+        let btf = match archive.extract(&entry) {
+            Ok(v) => v,
+            Err(e) => {
+                report.failures.push(MinimizeFailure {
+                    kernel,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let minimized = match run_bpftool(&opts.bpftool, workdir.path(), &btf, objects)? {
+            Ok(v) => v,
+            Err(message) => {
+                log_at!(Warn, "Failed to minimize {}: {}", kernel, message);
+                report.failures.push(MinimizeFailure { kernel, message });
+                continue;
+            }
+        };
+        let minimized_size = minimized.len() as u64;
+        if let Err(e) = builder.add_bytes(
+            &entry.distro,
+            &entry.version,
+            &entry.arch,
+            &entry.kernel_release,
+            minimized,
+        ) {
+            report.failures.push(MinimizeFailure {
+                kernel,
+                message: e.to_string(),
+            });
+            continue;
+        }
+        report.minimized.push(MinimizedEntry {
+            kernel,
+            original_size: btf.len() as u64,
+            minimized_size,
+        });
+    }
+    if !report.minimized.is_empty() {
+        let mut out = vec![];
+        builder.write_gz(&mut out, opts.level)?;
+        report.output_size = out.len() as u64;
+        std::fs::write(output, out)
+            .map_err(|e| Error::FileWriteError(output.display().to_string(), e))?;
+    }
+    log_at!(
+        Info,
+        "Minimized {} btfs ({} failed), {} bytes to {}",
+        report.minimized.len(),
+        report.failures.len(),
+        report.original_size(),
+        report.minimized_size()
+    );
+    Ok(report)
+}
+
+/// Run `bpftool gen min_core_btf` on `btf`, in `workdir`
+///
+/// The outer error is for bpftool not running at all, the inner one for a failure on this btf.
+fn run_bpftool(
+    bpftool: &Path,
+    workdir: &Path,
+    btf: &[u8],
+    objects: &[PathBuf],
+) -> Result<std::result::Result<Vec<u8>, String>> {
+    let input = workdir.join("input.btf");
+    let output = workdir.join("output.btf");
+    std::fs::write(&input, btf)
+        .map_err(|e| Error::FileWriteError(input.display().to_string(), e))?;
+    // 上一个条目的输出不能被误当作本次的结果
+    let _ = std::fs::remove_file(&output);
+    let result = Command::new(bpftool)
+        .args(["gen", "min_core_btf"])
+        .arg(&input)
+        .arg(&output)
+        .args(objects)
+        .output()
+        .map_err(|e| Error::BpftoolUnavailable(bpftool.display().to_string(), e))?;
+    if !result.status.success() {
+        return Ok(Err(format!(
+            "bpftool exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    match std::fs::read(&output) {
+        Ok(v) => Ok(Ok(v)),
+        Err(e) => Ok(Err(format!("bpftool wrote no btf: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive};
+
+    /// A directory with a stand-in for bpftool, `bpftool.sh`, logging its arguments to `args`
+    ///
+    /// It fails on btfs containing `broken`, writes nothing for `silent` and garbage for
+    /// `garbage`; other btfs are "minimized" to [`minimal_valid_btf`].
+    fn fake_bpftool() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("min.btf"), minimal_valid_btf()).unwrap();
+        let script = format!(
+            "#!/bin/sh\n\
+             echo \"$@\" >> {dir}/args\n\
+             if grep -q broken \"$3\"; then echo 'no types found' >&2; exit 3; fi\n\
+             if grep -q silent \"$3\"; then exit 0; fi\n\
+             if grep -q garbage \"$3\"; then echo garbage > \"$4\"; exit 0; fi\n\
+             cp {dir}/min.btf \"$4\"\n",
+            dir = dir.path().display()
+        );
+        let path = dir.path().join("bpftool.sh");
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        dir
+    }
+
+    fn archive(dir: &Path, registers: &[(&str, &str)]) -> PathBuf {
+        let mut fixture = FixtureArchive::new();
+        for (release, register) in registers {
+            fixture = fixture.btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                release,
+                btf_of_arch(8, register),
+            );
+        }
+        let path = dir.join("input.tar.gz");
+        fs::write(&path, fixture.gz()).unwrap();
+        path
+    }
+
+    #[test]
+    fn every_btf_is_minimized_and_repacked() {
+        let dir = fake_bpftool();
+        let input = archive(
+            dir.path(),
+            &[
+                ("5.4.0-40-generic", "rip"),
+                ("5.4.0-42-generic", "broken"),
+                ("5.4.0-44-generic", "silent"),
+                ("5.4.0-46-generic", "garbage"),
+                ("5.4.0-48-generic", "rsp"),
+            ],
+        );
+        let output = dir.path().join("output.tar.gz");
+        let objects = [PathBuf::from("a.bpf.o"), PathBuf::from("b.bpf.o")];
+        let opts = MinimizeOptions::default().with_bpftool(dir.path().join("bpftool.sh"));
+        let report = minimize_btf_archive(&input, &objects, &output, &opts).unwrap();
+
+        let kernel = |release: &str| format!("ubuntu/20.04/x86_64/{}", release);
+        assert_eq!(
+            report.minimized,
+            ["5.4.0-40-generic", "5.4.0-48-generic"].map(|v| MinimizedEntry {
+                kernel: kernel(v),
+                original_size: btf_of_arch(8, "rip").len() as u64,
+                minimized_size: minimal_valid_btf().len() as u64,
+            })
+        );
+        // 单个条目的失败记录在报告中，不影响其他条目
+        let failures = report
+            .failures
+            .iter()
+            .map(|v| (v.kernel.as_str(), v.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(failures.len(), 3, "{:?}", failures);
+        assert_eq!(failures[0].0, kernel("5.4.0-42-generic"));
+        assert!(failures[0].1.contains("no types found"), "{:?}", failures);
+        assert_eq!(failures[1].0, kernel("5.4.0-44-generic"));
+        assert!(failures[1].1.contains("wrote no btf"), "{:?}", failures);
+        assert_eq!(failures[2].0, kernel("5.4.0-46-generic"));
+
+        assert_eq!(report.input_size, fs::metadata(&input).unwrap().len());
+        assert_eq!(report.output_size, fs::metadata(&output).unwrap().len());
+        assert_eq!(
+            report.original_size(),
+            2 * btf_of_arch(8, "rip").len() as u64
+        );
+        assert_eq!(
+            report.minimized_size(),
+            2 * minimal_valid_btf().len() as u64
+        );
+
+        let packed = fs::read(&output).unwrap();
+        assert_eq!(
+            BtfhubArchive::new(&packed).kernels().unwrap(),
+            [kernel("5.4.0-40-generic"), kernel("5.4.0-48-generic")]
+        );
+        assert_eq!(
+            BtfhubArchive::new(&packed)
+                .extract("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-48-generic.btf")
+                .unwrap(),
+            minimal_valid_btf()
+        );
+
+        // 每个 btf 各运行一次，对象文件跟在输入与输出之后
+        let args = fs::read_to_string(dir.path().join("args")).unwrap();
+        let lines = args.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        for line in lines {
+            let args = line.split(' ').collect::<Vec<_>>();
+            assert_eq!(args[..2], ["gen", "min_core_btf"]);
+            assert_eq!(args[4..], ["a.bpf.o", "b.bpf.o"]);
+        }
+    }
+
+    #[test]
+    fn nothing_is_written_if_every_btf_fails() {
+        let dir = fake_bpftool();
+        let input = archive(dir.path(), &[("5.4.0-42-generic", "broken")]);
+        let output = dir.path().join("output.tar.gz");
+        let opts = MinimizeOptions::default().with_bpftool(dir.path().join("bpftool.sh"));
+        let report = minimize_btf_archive(&input, &[], &output, &opts).unwrap();
+        assert!(report.minimized.is_empty());
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.output_size, 0);
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn missing_bpftool_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = archive(dir.path(), &[("5.4.0-40-generic", "rip")]);
+        let opts = MinimizeOptions::default().with_bpftool(dir.path().join("no-bpftool"));
+        let output = dir.path().join("output.tar.gz");
+        match minimize_btf_archive(&input, &[], &output, &opts) {
+            Err(Error::BpftoolUnavailable(path, _)) => {
+                assert_eq!(path, dir.path().join("no-bpftool").display().to_string())
+            }
+            v => panic!("{:?}", v),
+        }
+        assert!(!output.exists());
+        assert!(matches!(
+            minimize_btf_archive(&dir.path().join("missing.tar.gz"), &[], &output, &opts),
+            Err(Error::FileReadError(..))
+        ));
+    }
+}
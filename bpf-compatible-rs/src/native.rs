@@ -0,0 +1,287 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Kernels built without `CONFIG_DEBUG_INFO_BTF` may still have a btf installed on disk,
+//! e.g. by a btf package of the distro. libbpf looks for it in a few well-known places
+//! besides `/sys/kernel/btf/vmlinux`, and so do we, before turning to the archive.
+use std::path::{Path, PathBuf};
+
+use crate::{
+    btf::{check_btf_file, validate_btf_bytes},
+    section::read_elf_section,
+    system::under_root,
+};
+
+/// Section of the kernel image holding its btf, with `CONFIG_DEBUG_INFO_BTF`
+pub const ELF_BTF_SECTION: &str = ".BTF";
+
+/// Placeholder of the kernel release in the locations
+pub const RELEASE_PLACEHOLDER: &str = "{release}";
+
+/// Locations of installed btfs, in the order libbpf tries them
+pub const NATIVE_BTF_LOCATIONS: &[&str] = &[
+    "/boot/vmlinux-{release}",
+    "/lib/modules/{release}/vmlinux-{release}",
+    "/lib/modules/{release}/build/vmlinux",
+    "/usr/lib/modules/{release}/kernel/vmlinux",
+    "/usr/lib/debug/boot/vmlinux-{release}",
+    "/usr/lib/debug/boot/vmlinux-{release}.debug",
+    "/usr/lib/debug/lib/modules/{release}/vmlinux",
+];
+
+/// Locations of files of a kernel, with `{release}` standing for its release, looked
+/// for under a root
+#[derive(Debug, Clone)]
+pub(crate) struct KernelPaths {
+    locations: Vec<String>,
+    root: PathBuf,
+}
+
+impl KernelPaths {
+    /// `locations` under `/`
+    pub(crate) fn new(locations: &[&str]) -> Self {
+        Self {
+            locations: locations.iter().map(|v| v.to_string()).collect(),
+            root: PathBuf::from("/"),
+        }
+    }
+
+    pub(crate) fn set_locations<S: Into<String>>(
+        &mut self,
+        locations: impl IntoIterator<Item = S>,
+    ) {
+        self.locations = locations.into_iter().map(Into::into).collect();
+    }
+
+    pub(crate) fn add_location(&mut self, location: impl Into<String>) {
+        self.locations.push(location.into());
+    }
+
+    pub(crate) fn set_root(&mut self, root: &Path) {
+        self.root = root.to_path_buf();
+    }
+
+    pub(crate) fn locations(&self) -> &[String] {
+        &self.locations
+    }
+
+    /// The locations for the kernel `release`, under the root, in order
+    pub(crate) fn resolve<'a>(&'a self, release: &'a str) -> impl Iterator<Item = PathBuf> + 'a {
+        self.locations
+            .iter()
+            .map(move |v| under_root(&self.root, &v.replace(RELEASE_PLACEHOLDER, release)))
+    }
+}
+
+/// Where to look for an installed btf of a kernel
+#[derive(Debug, Clone)]
+pub struct NativeBtfProbe {
+    paths: KernelPaths,
+}
+
+impl Default for NativeBtfProbe {
+    fn default() -> Self {
+        Self {
+            paths: KernelPaths::new(NATIVE_BTF_LOCATIONS),
+        }
+    }
+}
+
+impl NativeBtfProbe {
+    /// Probe the locations of [`NATIVE_BTF_LOCATIONS`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Probe `locations` instead, each with `{release}` standing for the kernel release
+    pub fn with_locations<S: Into<String>>(
+        mut self,
+        locations: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.paths.set_locations(locations);
+        self
+    }
+
+    /// Probe `location` too, after the others
+    pub fn add_location(mut self, location: impl Into<String>) -> Self {
+        self.paths.add_location(location);
+        self
+    }
+
+    /// Look for the locations under `root`, e.g. the host's root mounted into a container
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> Self {
+        self.paths.set_root(root.as_ref());
+        self
+    }
+
+    /// The locations, in the order they are probed
+    pub fn locations(&self) -> &[String] {
+        self.paths.locations()
+    }
+
+    /// The first location holding a btf of the kernel `release`
+    ///
+    /// A file is only taken if it can be read and starts with the btf magic, see
+    /// [`check_btf_file`]; ELF images with a `.BTF` section, which libbpf can parse, are skipped.
+    pub fn probe(&self, release: &str) -> Option<PathBuf> {
+        self.paths
+            .resolve(release)
+            .find(|v| check_btf_file(v).is_ok())
+    }
+
+    /// Same as [`NativeBtfProbe::probe`], knowing whether the kernel was built with btf
+    ///
+    /// If `btf_config` says so, see [`crate::kconfig::kernel_btf_config_of`], the kernel
+    /// images at the locations carry a `.BTF` section, which libbpf parses as well: a
+    /// location is then also taken if it's an ELF file whose `.BTF` section passes
+    /// [`validate_btf_bytes`]. Otherwise reading the images isn't worth it, and only raw
+    /// btfs are taken.
+    pub fn probe_with_config(&self, release: &str, btf_config: Option<bool>) -> Option<PathBuf> {
+        self.paths.resolve(release).find(|v| {
+            check_btf_file(v).is_ok() || (btf_config == Some(true) && v.is_file() && has_elf_btf(v))
+        })
+    }
+}
+
+/// Whether the ELF file at `path` has a valid `.BTF` section
+fn has_elf_btf(path: &Path) -> bool {
+    read_elf_section(path, ELF_BTF_SECTION).is_ok_and(|v| validate_btf_bytes(&v).is_ok())
+}
+
+#[cfg(all(test, feature = "host"))]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::{btf_of_arch, minimal_valid_btf},
+        section::add_elf_section,
+    };
+
+    const RELEASE: &str = "5.4.0-40-generic";
+
+    /// A root holding the given files, by their absolute paths
+    fn root_with(files: &[(&str, Vec<u8>)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = under_root(root.path(), path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn first_location_holding_a_btf_is_taken() {
+        let root = root_with(&[
+            (
+                "/lib/modules/5.4.0-40-generic/vmlinux-5.4.0-40-generic",
+                minimal_valid_btf(),
+            ),
+            (
+                "/usr/lib/debug/boot/vmlinux-5.4.0-40-generic",
+                btf_of_arch(8, "r15"),
+            ),
+        ]);
+        let probe = NativeBtfProbe::new().with_root(root.path());
+        assert_eq!(
+            probe.probe(RELEASE),
+            Some(
+                root.path()
+                    .join("lib/modules/5.4.0-40-generic/vmlinux-5.4.0-40-generic")
+            )
+        );
+        // 其他内核版本的位置不会被使用
+        assert_eq!(probe.probe("5.4.0-42-generic"), None);
+    }
+
+    #[test]
+    fn files_without_the_btf_magic_are_skipped() {
+        let root = root_with(&[
+            (
+                "/boot/vmlinux-5.4.0-40-generic",
+                b"\x7fELF not a btf".to_vec(),
+            ),
+            (
+                "/usr/lib/debug/boot/vmlinux-5.4.0-40-generic",
+                minimal_valid_btf(),
+            ),
+        ]);
+        let probe = NativeBtfProbe::new().with_root(root.path());
+        assert_eq!(
+            probe.probe(RELEASE),
+            Some(
+                root.path()
+                    .join("usr/lib/debug/boot/vmlinux-5.4.0-40-generic")
+            )
+        );
+        assert_eq!(
+            NativeBtfProbe::new()
+                .with_root(root_with(&[]).path())
+                .probe(RELEASE),
+            None
+        );
+    }
+
+    #[test]
+    fn locations_can_be_replaced_and_extended() {
+        let root = root_with(&[
+            ("/boot/vmlinux-5.4.0-40-generic", minimal_valid_btf()),
+            ("/opt/btf/5.4.0-40-generic.btf", minimal_valid_btf()),
+        ]);
+        let custom = NativeBtfProbe::new()
+            .with_root(root.path())
+            .with_locations(["/opt/btf/{release}.btf"]);
+        assert_eq!(custom.locations(), ["/opt/btf/{release}.btf"]);
+        assert_eq!(
+            custom.probe(RELEASE),
+            Some(root.path().join("opt/btf/5.4.0-40-generic.btf"))
+        );
+        // 追加的位置排在最后
+        let extended = NativeBtfProbe::new()
+            .with_root(root.path())
+            .add_location("/opt/btf/{release}.btf");
+        assert_eq!(extended.locations().len(), NATIVE_BTF_LOCATIONS.len() + 1);
+        assert_eq!(
+            extended.probe(RELEASE),
+            Some(root.path().join("boot/vmlinux-5.4.0-40-generic"))
+        );
+    }
+
+    #[test]
+    fn kernel_images_are_read_when_the_config_builds_btf() {
+        // 内核镜像的 .BTF 节中的 btf，只有配置表明内核带有 btf 时才去读取
+        let object = crate::embed::EmbeddedArchiveObject::new(b"tar", "x86_64")
+            .unwrap()
+            .to_bytes();
+        let image = add_elf_section(&object, ELF_BTF_SECTION, &btf_of_arch(8, "r15")).unwrap();
+        let root = root_with(&[
+            ("/boot/vmlinux-5.4.0-40-generic", image.clone()),
+            (
+                "/usr/lib/debug/boot/vmlinux-5.4.0-40-generic",
+                minimal_valid_btf(),
+            ),
+        ]);
+        let probe = NativeBtfProbe::new().with_root(root.path());
+        let debug = Some(
+            root.path()
+                .join("usr/lib/debug/boot/vmlinux-5.4.0-40-generic"),
+        );
+        assert_eq!(probe.probe(RELEASE), debug);
+        assert_eq!(probe.probe_with_config(RELEASE, None), debug);
+        assert_eq!(probe.probe_with_config(RELEASE, Some(false)), debug);
+        assert_eq!(
+            probe.probe_with_config(RELEASE, Some(true)),
+            Some(root.path().join("boot/vmlinux-5.4.0-40-generic"))
+        );
+
+        // 没有 .BTF 节，或节中的内容不是有效的 btf 时不被采用
+        let broken = add_elf_section(&object, ELF_BTF_SECTION, b"not a btf").unwrap();
+        let root = root_with(&[
+            ("/boot/vmlinux-5.4.0-40-generic", broken),
+            ("/lib/modules/5.4.0-40-generic/build/vmlinux", object),
+        ]);
+        let probe = NativeBtfProbe::new().with_root(root.path());
+        assert_eq!(probe.probe_with_config(RELEASE, Some(true)), None);
+    }
+}
@@ -0,0 +1,1258 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Writing the archive `ensure_core_btf_with_tar_binary` expects from Rust, e.g. from a
+//! `build.rs` or CI, instead of a `tar czf` pipeline in a Makefile: [`pack_btf_archive`](crate::pack::pack_btf_archive)
+//! packs a directory, [`BtfArchiveBuilder`](crate::pack::BtfArchiveBuilder) takes the btfs one by one, and
+//! [`filter_btf_archive`](crate::pack::filter_btf_archive) trims an existing archive down to some of its btfs.
+//!
+//! The output is deterministic: the entries are sorted by path and their headers carry a
+//! fixed mtime, owner and mode, so the same btfs always give the same bytes.
+use std::{
+    collections::{btree_map, BTreeMap},
+    fmt,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder, Compression};
+use tar::{Builder, EntryType, Header};
+
+use crate::{
+    archive::{
+        normalize_entry_path, parse_btf_path, parse_module_btf_path, BtfEncoding, BtfEntry,
+        BtfEntryInfo, BTFHUB_ARCHIVE_DIR,
+    },
+    btf::{has_swapped_magic, validate_btf_bytes},
+    compression::{tar_archive, tar_entries, LimitedReader, DEFAULT_MAX_DECOMPRESSED_SIZE},
+    index::INDEX_ENTRY_NAME,
+    join_archive_path,
+    listing::{ArchiveListing, LISTING_ENTRY_NAME},
+    sparse::{entry_path, is_file_entry, read_entry, regular_header},
+    Error, Result, MODULES_DIR,
+};
+
+/// Filter of the btfs to pack, given their path relative to the source directory
+pub type PackFilter = Box<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// Options of [`pack_btf_archive`]
+pub struct PackOptions {
+    level: Compression,
+    filter: Option<PackFilter>,
+    mtime: u64,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            level: Compression::best(),
+            filter: None,
+            mtime: 0,
+        }
+    }
+}
+
+impl fmt::Debug for PackOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PackOptions")
+            .field("level", &self.level)
+            .field("filter", &self.filter.is_some())
+            .field("mtime", &self.mtime)
+            .finish()
+    }
+}
+
+impl PackOptions {
+    /// Compress with `level` instead of [`Compression::best`]
+    pub fn with_level(mut self, level: Compression) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Only pack the btfs for which `filter` returns true
+    ///
+    /// It is given the path relative to the source directory, e.g.
+    /// `ubuntu/20.04/x86_64/5.4.0-40-generic.btf`.
+    pub fn with_filter(mut self, filter: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// See [`BtfArchiveBuilder::with_mtime`]
+    pub fn with_mtime(mut self, mtime: u64) -> Self {
+        self.mtime = mtime;
+        self
+    }
+}
+
+impl From<&PackOptions> for BtfArchiveBuilder {
+    /// An empty builder, stamping the entries as `opts` asks
+    fn from(opts: &PackOptions) -> Self {
+        Self::new().with_mtime(opts.mtime)
+    }
+}
+
+/// Pack the btfs of `src_dir` into a tar.gz written to `out`, see [`pack_btf_archive_bytes`]
+pub fn pack_btf_archive(src_dir: &Path, out: &Path, opts: &PackOptions) -> Result<()> {
+    let archive = pack_btf_archive_bytes(src_dir, opts)?;
+    std::fs::write(out, archive).map_err(|e| Error::FileWriteError(out.display().to_string(), e))
+}
+
+/// Pack the btfs of `src_dir`, laid out as `<distro>/<version>/<arch>/<release>.btf`, into a tar.gz
+///
+/// The btfs are added with [`BtfArchiveBuilder::add_tree`], keeping those `opts` selects.
+/// Fails with [`Error::NotBtfhubArchive`] if no btf is left to pack.
+pub fn pack_btf_archive_bytes(src_dir: &Path, opts: &PackOptions) -> Result<Vec<u8>> {
+    let mut builder = BtfArchiveBuilder::from(opts);
+    builder.add_tree_filtered(src_dir, opts.filter.as_ref())?;
+    let mut archive = vec![];
+    builder.write_gz(&mut archive, opts.level)?;
+    log_at!(
+        Info,
+        "Packed {} btfs of {}",
+        builder.entries.len(),
+        src_dir.display()
+    );
+    Ok(archive)
+}
+
+/// A btf to pack, under the directory of its kernel
+#[derive(Debug, Clone)]
+struct PackedEntry {
+    /// `<release>.btf`, or the name of a compressed btf as found by `add_tree`
+    file_name: String,
+    contents: Vec<u8>,
+}
+
+/// Writer of the archives `ensure_core_btf_with_tar_binary` reads, one btf at a time
+///
+/// ```ignore
+/// BtfArchiveBuilder::new()
+///     .add_file("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", "vmlinux.btf")?
+///     .add_tree("btfs")?
+///     .write_gz(File::create("min_core_btfs.tar.gz")?, Compression::best())?;
+/// ```
+///
+/// Each btf is identified by its distro, version, arch and kernel release, and stored as
+/// `btfhub-archive/<distro>/<version>/<arch>/<release>.btf`. The output is sorted by that
+/// identity, and the entries carry a fixed mtime (0 unless [`BtfArchiveBuilder::with_mtime`]
+/// is called), owner and mode, so the same btfs always give the same bytes.
+#[derive(Debug, Clone, Default)]
+pub struct BtfArchiveBuilder {
+    entries: BTreeMap<(String, String, String, String), PackedEntry>,
+    /// Split btfs of kernel modules, by kernel and module name
+    modules: BTreeMap<(String, String, String, String, String), Vec<u8>>,
+    mtime: u64,
+    /// Whether the listing is left out, see [`BtfArchiveBuilder::with_listing`]
+    without_listing: bool,
+}
+
+impl BtfArchiveBuilder {
+    /// An archive without any btf yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stamp the entries with `mtime`, in seconds since the epoch, instead of 0
+    pub fn with_mtime(mut self, mtime: u64) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Write the listing of the btfs first, as by default, or leave it out
+    pub fn with_listing(mut self, listing: bool) -> Self {
+        self.without_listing = !listing;
+        self
+    }
+
+    /// Add the btf of a kernel
+    ///
+    /// `btf` must pass [`validate_btf_bytes`]. The components must be non-empty names,
+    /// without `/` or `\`, or it fails with [`Error::InvalidEntryName`]; a second btf for the
+    /// same kernel fails with [`Error::DuplicateEntry`].
+    pub fn add_bytes(
+        &mut self,
+        distro: &str,
+        version: &str,
+        arch: &str,
+        kernel_release: &str,
+        btf: Vec<u8>,
+    ) -> Result<&mut Self> {
+        validate_btf_bytes(&btf)?;
+        self.insert(
+            [distro, version, arch, kernel_release],
+            format!("{}.btf", kernel_release),
+            btf,
+        )?;
+        Ok(self)
+    }
+
+    /// Add the split btf of the kernel module `module`
+    ///
+    /// It is stored as `btfhub-archive/<distro>/<version>/<arch>/<release>/modules/<module>.btf`,
+    /// see [`crate::ensure_module_btf`]. The kernel's own btf doesn't have to be added.
+    /// Validation and errors are those of [`BtfArchiveBuilder::add_bytes`].
+    pub fn add_module_btf(
+        &mut self,
+        distro: &str,
+        version: &str,
+        arch: &str,
+        kernel_release: &str,
+        module: &str,
+        btf: Vec<u8>,
+    ) -> Result<&mut Self> {
+        validate_btf_bytes(&btf)?;
+        self.insert_module([distro, version, arch, kernel_release, module], btf)?;
+        Ok(self)
+    }
+
+    /// Same as [`BtfArchiveBuilder::add_bytes`], with the btf read from `path`
+    pub fn add_file(
+        &mut self,
+        distro: &str,
+        version: &str,
+        arch: &str,
+        kernel_release: &str,
+        path: impl AsRef<Path>,
+    ) -> Result<&mut Self> {
+        let path = path.as_ref();
+        let btf =
+            std::fs::read(path).map_err(|e| Error::FileReadError(path.display().to_string(), e))?;
+        self.add_bytes(distro, version, arch, kernel_release, btf)
+    }
+
+    /// Add every btf of `dir`, laid out as `<distro>/<version>/<arch>/<release>.btf`
+    ///
+    /// `.btf.gz` and `.btf.tar.xz` files are added as they are, without being validated;
+    /// plain btfs are validated. Other files, or btfs at the wrong depth, are skipped.
+    /// Links are followed, so a btf shared by several releases is stored once per release.
+    /// Two files for the same kernel, e.g. `<release>.btf` and `<release>.btf.gz`, fail
+    /// with [`Error::DuplicateEntry`]. Module btfs, at `<release>/modules/<module>.btf`, are
+    /// added as by [`BtfArchiveBuilder::add_module_btf`].
+    pub fn add_tree(&mut self, dir: impl AsRef<Path>) -> Result<&mut Self> {
+        self.add_tree_filtered(dir.as_ref(), None)?;
+        Ok(self)
+    }
+
+    fn add_tree_filtered(&mut self, dir: &Path, filter: Option<&PackFilter>) -> Result<()> {
+        let prefix = Path::new(BTFHUB_ARCHIVE_DIR);
+        let mut files = vec![];
+        walk(dir, Path::new(""), &mut files)?;
+        // 按路径排序，出错时报告的文件与目录的遍历顺序无关
+        files.sort();
+        for relative in files {
+            if let Some(identity) = parse_module_btf_path(&prefix.join(&relative), prefix) {
+                if filter.is_some_and(|v| !v(&relative)) {
+                    continue;
+                }
+                let path = dir.join(&relative);
+                let contents = std::fs::read(&path)
+                    .map_err(|e| Error::FileReadError(path.display().to_string(), e))?;
+                validate_btf_bytes(&contents)?;
+                self.insert_module(identity.each_ref().map(String::as_str), contents)?;
+                continue;
+            }
+            let Some((distro, version, arch, kernel_release, encoding)) =
+                parse_btf_path(&prefix.join(&relative), prefix)
+            else {
+                log_at!(
+                    Debug,
+                    "Skipped {}, not a btf of the layout",
+                    relative.display()
+                );
+                continue;
+            };
+            if filter.is_some_and(|v| !v(&relative)) {
+                continue;
+            }
+            let path = dir.join(&relative);
+            let contents = std::fs::read(&path)
+                .map_err(|e| Error::FileReadError(path.display().to_string(), e))?;
+            if encoding == BtfEncoding::Plain {
+                validate_btf_bytes(&contents)?;
+            }
+            let file_name = relative
+                .file_name()
+                .and_then(|v| v.to_str())
+                .unwrap_or_default()
+                .to_string();
+            self.insert(
+                [&distro, &version, &arch, &kernel_release],
+                file_name,
+                contents,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, identity: [&str; 4], file_name: String, contents: Vec<u8>) -> Result<()> {
+        check_identity(&identity)?;
+        let [distro, version, arch, kernel_release] = identity.map(str::to_string);
+        match self.entries.entry((distro, version, arch, kernel_release)) {
+            btree_map::Entry::Occupied(_) => {
+                Err(Error::DuplicateEntry(join_archive_path(&identity)))
+            }
+            btree_map::Entry::Vacant(v) => {
+                v.insert(PackedEntry {
+                    file_name,
+                    contents,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn insert_module(&mut self, identity: [&str; 5], contents: Vec<u8>) -> Result<()> {
+        check_identity(&identity)?;
+        let [distro, version, arch, kernel_release, module] = identity.map(str::to_string);
+        match self
+            .modules
+            .entry((distro, version, arch, kernel_release, module))
+        {
+            btree_map::Entry::Occupied(_) => {
+                Err(Error::DuplicateEntry(join_archive_path(&identity)))
+            }
+            btree_map::Entry::Vacant(v) => {
+                v.insert(contents);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the archive as a tar compressed with gzip at `level`
+    ///
+    /// Fails with [`Error::NotBtfhubArchive`] if no btf was added, since the lookups would
+    /// reject the archive.
+    pub fn write_gz(&self, writer: impl Write, level: Compression) -> Result<()> {
+        let encoder = self.write_tar(GzEncoder::new(writer, level))?;
+        encoder.finish().map_err(Error::Io)?;
+        Ok(())
+    }
+
+    /// Write the archive as a plain tar
+    ///
+    /// Unless left out with [`BtfArchiveBuilder::with_listing`], the first entry is a
+    /// listing of the btfs, `btfhub-archive/manifest.json`, see [`crate::listing`].
+    pub fn write_tar<W: Write>(&self, writer: W) -> Result<W> {
+        if self.entries.is_empty() && self.modules.is_empty() {
+            return Err(Error::NotBtfhubArchive);
+        }
+        let mut files = vec![];
+        for ((distro, version, arch, _), entry) in &self.entries {
+            let path =
+                join_archive_path(&[BTFHUB_ARCHIVE_DIR, distro, version, arch, &entry.file_name]);
+            files.push((path, &entry.contents[..]));
+        }
+        // 模块的 btf 排在所有内核的 btf 之后
+        for ((distro, version, arch, kernel_release, module), contents) in &self.modules {
+            let path = join_archive_path(&[
+                BTFHUB_ARCHIVE_DIR,
+                distro,
+                version,
+                arch,
+                kernel_release,
+                MODULES_DIR,
+                &format!("{}.btf", module),
+            ]);
+            files.push((path, &contents[..]));
+        }
+        let mut builder = Builder::new(writer);
+        if !self.without_listing {
+            let mut listing = ArchiveListing::default();
+            for (path, contents) in &files {
+                listing.push(path, contents);
+            }
+            listing.coverage = listing.covered_trees(Path::new(BTFHUB_ARCHIVE_DIR));
+            let path = join_archive_path(&[BTFHUB_ARCHIVE_DIR, LISTING_ENTRY_NAME]);
+            self.append(&mut builder, path, &listing.to_json())?;
+        }
+        for (path, contents) in files {
+            self.append(&mut builder, path, contents)?;
+        }
+        builder.into_inner().map_err(Error::Io)
+    }
+
+    fn append<W: Write>(
+        &self,
+        builder: &mut Builder<W>,
+        path: String,
+        contents: &[u8],
+    ) -> Result<()> {
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        builder
+            .append_data(&mut header, path, contents)
+            .map_err(Error::Io)
+    }
+}
+
+/// What [`filter_btf_archive`] did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FilterReport {
+    /// Number of files and links copied to the output
+    pub kept: usize,
+    /// Number of files and links the predicate rejected
+    pub dropped: usize,
+    /// Size of the written tar.gz
+    pub size: u64,
+}
+
+/// Copy the files and links of the tar.gz `input` for which `predicate` returns true to a new tar.gz written to `output`
+///
+/// ```ignore
+/// let report = filter_btf_archive(File::open("master.tar.gz")?, File::create("slim.tar.gz")?, |v| {
+///     matches!(v, BtfEntryInfo::Btf(v) if v.distro == "ubuntu" && v.arch == "x86_64")
+/// })?;
+/// ```
+///
+/// The predicate is given entries as [`crate::archive::BtfhubArchive::entries`] parses
+/// them, btfs under `btfhub-archive` as [`BtfEntryInfo::Btf`]. The input is streamed, so
+/// only one entry is held in memory at a time; gzip members and tars concatenated to it
+/// are read too. Kept entries are written in input order, with their headers and contents
+/// as they are: compressed btfs aren't recompressed, so their bytes, and their digests in
+/// a `SHA256SUMS` manifest, stay the same. Long names and link targets, from GNU or PAX
+/// entries of the input, are written with GNU long name entries where the header can't
+/// hold them. Directories are left out, as is an `INDEX` entry, whose offsets would be
+/// wrong; rebuild it with [`crate::index::prepend_index`] if needed. So is the listing
+/// `btfhub-archive/manifest.json`, which would still list the dropped btfs. The output is
+/// compressed with [`Compression::best`], so the same input and predicate always give the
+/// same bytes. Fails with [`Error::NotBtfhubArchive`] if no btf is kept, since the lookups
+/// would reject the archive.
+pub fn filter_btf_archive(
+    input: impl Read,
+    output: impl Write,
+    predicate: impl Fn(&BtfEntryInfo) -> bool,
+) -> Result<FilterReport> {
+    let prefix = Path::new(BTFHUB_ARCHIVE_DIR);
+    let decoder = MultiGzDecoder::new(BufReader::new(input));
+    let mut input = tar_archive(LimitedReader::new(decoder, DEFAULT_MAX_DECOMPRESSED_SIZE));
+    let mut builder = Builder::new(GzEncoder::new(
+        CountingWriter::new(output),
+        Compression::best(),
+    ));
+    let mut report = FilterReport::default();
+    let mut kept_btfs = 0;
+    for entry in tar_entries(&mut input).map_err(Error::from_read)? {
+        let mut entry = entry.map_err(Error::from_read)?;
+        let entry_type = entry.header().entry_type();
+        let is_link = entry_type.is_hard_link() || entry_type.is_symlink();
+        if !is_file_entry(entry_type) && !is_link {
+            continue;
+        }
+        let path = entry_path(&mut entry).map_err(Error::from_read)?;
+        let name = normalize_entry_path(&path);
+        if name == Path::new(INDEX_ENTRY_NAME) || name == prefix.join(LISTING_ENTRY_NAME) {
+            continue;
+        }
+        let contents = if is_link {
+            vec![]
+        } else {
+            read_entry(&mut entry).map_err(Error::from_read)?
+        };
+        let info = match parse_btf_path(&name, prefix) {
+            Some((distro, version, arch, kernel_release, encoding)) => {
+                BtfEntryInfo::Btf(BtfEntry {
+                    distro,
+                    version,
+                    arch,
+                    kernel_release,
+                    size: contents.len() as u64,
+                    path: path.clone(),
+                    encoding,
+                    is_link,
+                    byte_swapped: !is_link
+                        && encoding == BtfEncoding::Plain
+                        && has_swapped_magic(&contents),
+                })
+            }
+            None => BtfEntryInfo::Other(path.clone()),
+        };
+        if !predicate(&info) {
+            log_at!(Debug, "Dropped {}", path.display());
+            report.dropped += 1;
+            continue;
+        }
+        let mut header = regular_header(entry.header());
+        if is_link {
+            let Some(target) = entry.link_name().map_err(Error::from_read)? else {
+                continue;
+            };
+            builder
+                .append_link(&mut header, &path, &target)
+                .map_err(Error::Io)?;
+        } else {
+            // 超出头部字段的大小保存在 PAX 扩展头中，写出时以实际内容为准
+            header.set_size(contents.len() as u64);
+            builder
+                .append_data(&mut header, &path, &contents[..])
+                .map_err(Error::Io)?;
+        }
+        report.kept += 1;
+        if matches!(info, BtfEntryInfo::Btf(_)) {
+            kept_btfs += 1;
+        }
+    }
+    if kept_btfs == 0 {
+        return Err(Error::NotBtfhubArchive);
+    }
+    let encoder = builder.into_inner().map_err(Error::Io)?;
+    let writer = encoder.finish().map_err(Error::Io)?;
+    report.size = writer.count;
+    log_at!(
+        Info,
+        "Kept {} entries and dropped {}, {} bytes written",
+        report.kept,
+        report.dropped,
+        report.size
+    );
+    Ok(report)
+}
+
+/// A writer counting the bytes written through it
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fail with [`Error::InvalidEntryName`] unless each component can name a directory or file
+pub(crate) fn check_identity(identity: &[&str]) -> Result<()> {
+    for component in identity {
+        if component.is_empty()
+            || *component == "."
+            || *component == ".."
+            || component.contains(['/', '\\'])
+        {
+            return Err(Error::InvalidEntryName(join_archive_path(identity)));
+        }
+    }
+    Ok(())
+}
+
+/// Collect the files under `dir`, as paths relative to the root of the walk
+fn walk(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let full = root.join(dir);
+    let entries = std::fs::read_dir(&full)
+        .map_err(|e| Error::FileReadError(full.display().to_string(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::FileReadError(full.display().to_string(), e))?;
+        let relative = dir.join(entry.file_name());
+        let path = entry.path();
+        // 跟随符号链接，按其指向的内容打包
+        let metadata = std::fs::metadata(&path)
+            .map_err(|e| Error::FileReadError(path.display().to_string(), e))?;
+        if metadata.is_dir() {
+            walk(root, &relative, files)?;
+        } else if metadata.is_file() {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        archive::BtfhubArchive,
+        compression::tar_reader,
+        fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+        tarball::TarballBtfArchive,
+        SystemInfo,
+    };
+
+    /// A directory holding `files`, given by their relative path
+    fn tree(files: &[(&str, Vec<u8>)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn ubuntu(kernel_release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: kernel_release.into(),
+            ..Default::default()
+        }
+    }
+
+    fn btfs() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            (
+                "ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                btf_of_arch(8, "40"),
+            ),
+            (
+                "ubuntu/20.04/x86_64/5.4.0-42-generic.btf",
+                btf_of_arch(8, "42"),
+            ),
+            (
+                "centos/8/x86_64/4.18.0-305.el8.x86_64.btf",
+                minimal_valid_btf(),
+            ),
+            // 不符合布局的文件被跳过
+            ("README.md", b"btfs of the fleet".to_vec()),
+            ("ubuntu/20.04/5.4.0-40-generic.btf", b"wrong depth".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn packed_tree_round_trips_through_the_lookup() {
+        let dir = tree(&btfs());
+        let out = dir.path().join("min_core_btfs.tar.gz");
+        pack_btf_archive(dir.path(), &out, &PackOptions::default()).unwrap();
+        let packed = fs::read(&out).unwrap();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&packed).unwrap();
+        for (release, btf) in [
+            ("5.4.0-40-generic", btf_of_arch(8, "40")),
+            ("5.4.0-42-generic", btf_of_arch(8, "42")),
+        ] {
+            let entry = archive.lookup(&ubuntu(release)).unwrap();
+            assert_eq!(archive.extract(&entry).unwrap(), btf);
+        }
+        assert_eq!(
+            BtfhubArchive::new(&packed).kernels().unwrap(),
+            [
+                "centos/8/x86_64/4.18.0-305.el8.x86_64",
+                "ubuntu/20.04/x86_64/5.4.0-40-generic",
+                "ubuntu/20.04/x86_64/5.4.0-42-generic",
+            ]
+        );
+    }
+
+    #[test]
+    fn entries_are_sorted_with_fixed_headers() {
+        let dir = tree(&btfs());
+        let packed = pack_btf_archive_bytes(dir.path(), &PackOptions::default()).unwrap();
+        let mut archive = tar_archive(tar_reader(&packed).unwrap());
+        let headers: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|v| {
+                let v = v.unwrap();
+                let header = v.header();
+                (
+                    v.path().unwrap().display().to_string(),
+                    header.mtime().unwrap(),
+                    header.mode().unwrap(),
+                    header.uid().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                "btfhub-archive/manifest.json",
+                "btfhub-archive/centos/8/x86_64/4.18.0-305.el8.x86_64.btf",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf",
+            ]
+            .map(|v| (v.to_string(), 0, 0o644, 0))
+        );
+    }
+
+    #[test]
+    fn packing_is_deterministic() {
+        let files = btfs();
+        let first = tree(&files);
+        // 以相反的顺序、在不同的时间创建同样的文件
+        let mut reversed = files.clone();
+        reversed.reverse();
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        let second = tree(&reversed);
+        let pack = |dir: &Path| pack_btf_archive_bytes(dir, &PackOptions::default()).unwrap();
+        assert_eq!(pack(first.path()), pack(second.path()));
+        let stamped =
+            pack_btf_archive_bytes(first.path(), &PackOptions::default().with_mtime(7)).unwrap();
+        assert_ne!(stamped, pack(first.path()));
+        let mut archive = tar_archive(tar_reader(&stamped).unwrap());
+        for entry in archive.entries().unwrap() {
+            assert_eq!(entry.unwrap().header().mtime().unwrap(), 7);
+        }
+    }
+
+    #[test]
+    fn filter_and_level_are_applied() {
+        let dir = tree(&btfs());
+        let opts = PackOptions::default().with_filter(|v| {
+            v.starts_with("ubuntu") && v != Path::new("ubuntu/20.04/x86_64/5.4.0-42-generic.btf")
+        });
+        let packed = pack_btf_archive_bytes(dir.path(), &opts).unwrap();
+        assert_eq!(
+            BtfhubArchive::new(&packed).kernels().unwrap(),
+            ["ubuntu/20.04/x86_64/5.4.0-40-generic"]
+        );
+        let stored = pack_btf_archive_bytes(
+            dir.path(),
+            &PackOptions::default().with_level(Compression::none()),
+        )
+        .unwrap();
+        let best = pack_btf_archive_bytes(dir.path(), &PackOptions::default()).unwrap();
+        assert!(stored.len() > best.len());
+        assert_eq!(
+            BtfhubArchive::new(&stored).kernels().unwrap(),
+            BtfhubArchive::new(&best).kernels().unwrap()
+        );
+    }
+
+    #[test]
+    fn trees_without_valid_btfs_are_rejected() {
+        let dir = tree(&[("README.md", b"nothing".to_vec())]);
+        assert!(matches!(
+            pack_btf_archive_bytes(dir.path(), &PackOptions::default()),
+            Err(Error::NotBtfhubArchive)
+        ));
+        // 过滤掉所有的 btf 同样如此
+        let dir = tree(&btfs());
+        assert!(matches!(
+            pack_btf_archive_bytes(dir.path(), &PackOptions::default().with_filter(|_| false)),
+            Err(Error::NotBtfhubArchive)
+        ));
+        let dir = tree(&[(
+            "ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            b"not a btf".to_vec(),
+        )]);
+        assert!(matches!(
+            pack_btf_archive_bytes(dir.path(), &PackOptions::default()),
+            Err(Error::InvalidBtf(_))
+        ));
+        let out = dir.path().join("missing/out.tar.gz");
+        let dir = tree(&btfs());
+        assert!(matches!(
+            pack_btf_archive(dir.path(), &out, &PackOptions::default()),
+            Err(Error::FileWriteError(..))
+        ));
+    }
+
+    /// Paths of the entries of the plain tar `tar`, in order
+    fn entry_names(tar: &[u8]) -> Vec<String> {
+        tar_archive(tar)
+            .entries()
+            .unwrap()
+            .map(|v| v.unwrap().path().unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn builder_round_trips_through_the_lookup() {
+        let dir = tree(&btfs());
+        let file = dir.path().join("ubuntu/20.04/x86_64/5.4.0-40-generic.btf");
+        let mut builder = BtfArchiveBuilder::new();
+        builder
+            .add_bytes(
+                "debian",
+                "11",
+                "arm64",
+                "5.10.0-9-arm64",
+                btf_of_arch(8, "x0"),
+            )
+            .unwrap()
+            .add_file("fedora", "38", "x86_64", "6.2.9-300.fc38.x86_64", &file)
+            .unwrap()
+            .add_tree(dir.path())
+            .unwrap();
+        let mut packed = vec![];
+        builder.write_gz(&mut packed, Compression::best()).unwrap();
+
+        let archive = TarballBtfArchive::from_gzipped_bytes(&packed).unwrap();
+        let fedora = SystemInfo {
+            distro_id: "fedora".into(),
+            version_id: "38".into(),
+            arch: "x86_64".into(),
+            kernel_release: "6.2.9-300.fc38.x86_64".into(),
+            ..Default::default()
+        };
+        let entry = archive.lookup(&fedora).unwrap();
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "40"));
+        let entry = archive.lookup(&ubuntu("5.4.0-42-generic")).unwrap();
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "42"));
+        assert_eq!(BtfhubArchive::new(&packed).kernels().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn builder_output_doesnt_depend_on_the_order_of_the_adds() {
+        let btfs = [
+            ("5.4.0-40-generic", btf_of_arch(8, "40")),
+            ("5.4.0-42-generic", btf_of_arch(8, "42")),
+            ("5.4.0-9-generic", minimal_valid_btf()),
+        ];
+        let build = |btfs: &mut dyn Iterator<Item = &(&str, Vec<u8>)>| {
+            let mut builder = BtfArchiveBuilder::new();
+            for (release, btf) in btfs {
+                builder
+                    .add_bytes("ubuntu", "20.04", "x86_64", release, btf.clone())
+                    .unwrap();
+            }
+            builder.write_tar(vec![]).unwrap()
+        };
+        let forward = build(&mut btfs.iter());
+        assert_eq!(forward, build(&mut btfs.iter().rev()));
+        // 按字符串排序，而不是按版本号
+        assert_eq!(
+            entry_names(&forward),
+            [
+                "btfhub-archive/manifest.json",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-9-generic.btf",
+            ]
+        );
+        for entry in tar_archive(&forward[..]).entries().unwrap() {
+            let entry = entry.unwrap();
+            assert_eq!(entry.header().mtime().unwrap(), 0);
+            assert_eq!(entry.header().gid().unwrap(), 0);
+        }
+
+        let mut stamped = BtfArchiveBuilder::new().with_mtime(1_700_000_000);
+        stamped
+            .add_bytes(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                minimal_valid_btf(),
+            )
+            .unwrap();
+        let stamped = stamped.write_tar(vec![]).unwrap();
+        for entry in tar_archive(&stamped[..]).entries().unwrap() {
+            assert_eq!(entry.unwrap().header().mtime().unwrap(), 1_700_000_000);
+        }
+    }
+
+    #[test]
+    fn builder_rejects_invalid_btfs_and_names() {
+        let mut builder = BtfArchiveBuilder::new();
+        assert!(matches!(
+            builder.add_bytes(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                b"ELF".to_vec()
+            ),
+            Err(Error::InvalidBtf(_))
+        ));
+        for identity in [
+            ["", "20.04", "x86_64", "5.4.0-40-generic"],
+            ["ubuntu", "20.04/..", "x86_64", "5.4.0-40-generic"],
+            ["ubuntu", "20.04", "..", "5.4.0-40-generic"],
+            ["ubuntu", "20.04", "x86_64", "."],
+            ["ubuntu", "20.04", "x86_64", "5.4\\0"],
+        ] {
+            let [distro, version, arch, release] = identity;
+            assert!(
+                matches!(
+                    builder.add_bytes(distro, version, arch, release, minimal_valid_btf()),
+                    Err(Error::InvalidEntryName(_))
+                ),
+                "{:?}",
+                identity
+            );
+        }
+        assert!(matches!(
+            builder.add_file(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                "/nonexistent.btf"
+            ),
+            Err(Error::FileReadError(..))
+        ));
+        // 出错的添加没有留下条目
+        assert!(matches!(
+            builder.write_tar(vec![]),
+            Err(Error::NotBtfhubArchive)
+        ));
+    }
+
+    #[test]
+    fn builder_rejects_a_second_btf_of_a_kernel() {
+        let mut builder = BtfArchiveBuilder::new();
+        builder
+            .add_bytes(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "a"),
+            )
+            .unwrap();
+        match builder.add_bytes(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        ) {
+            Err(Error::DuplicateEntry(v)) => assert_eq!(v, "ubuntu/20.04/x86_64/5.4.0-40-generic"),
+            v => panic!("{:?}", v),
+        }
+        // 同一内核的 .btf 与 .btf.gz 同样冲突
+        let dir = tree(&[
+            (
+                "ubuntu/20.04/x86_64/5.4.0-42-generic.btf",
+                minimal_valid_btf(),
+            ),
+            (
+                "ubuntu/20.04/x86_64/5.4.0-42-generic.btf.gz",
+                b"gzipped".to_vec(),
+            ),
+        ]);
+        assert!(matches!(
+            builder.add_tree(dir.path()),
+            Err(Error::DuplicateEntry(_))
+        ));
+        // 先添加的 btf 保持不变
+        let packed = builder.write_tar(vec![]).unwrap();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&packed).unwrap();
+        let entry = archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "a"));
+    }
+
+    #[test]
+    fn module_btfs_are_packed_after_the_kernels() {
+        let mut builder = BtfArchiveBuilder::new();
+        builder
+            .add_module_btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                "nf_tables",
+                btf_of_arch(8, "nft"),
+            )
+            .unwrap()
+            .add_bytes(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "40"),
+            )
+            .unwrap();
+        assert!(matches!(
+            builder.add_module_btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                "nf_tables",
+                minimal_valid_btf()
+            ),
+            Err(Error::DuplicateEntry(_))
+        ));
+        assert!(matches!(
+            builder.add_module_btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                "../vmlinux",
+                minimal_valid_btf()
+            ),
+            Err(Error::InvalidEntryName(_))
+        ));
+        let packed = builder.write_tar(vec![]).unwrap();
+        assert_eq!(
+            entry_names(&packed),
+            [
+                "btfhub-archive/manifest.json",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic/modules/nf_tables.btf",
+            ]
+        );
+        let archive = TarballBtfArchive::from_gzipped_bytes(&packed).unwrap();
+        assert_eq!(
+            archive
+                .extract_module(&ubuntu("5.4.0-40-generic"), "nf_tables")
+                .unwrap(),
+            btf_of_arch(8, "nft")
+        );
+
+        // 目录中的模块 btf 同样被打包
+        let dir = tree(&[(
+            "ubuntu/20.04/x86_64/5.4.0-42-generic/modules/xfs.btf",
+            btf_of_arch(8, "xfs"),
+        )]);
+        let packed = pack_btf_archive_bytes(dir.path(), &PackOptions::default()).unwrap();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&packed).unwrap();
+        assert_eq!(
+            archive
+                .extract_module(&ubuntu("5.4.0-42-generic"), "xfs")
+                .unwrap(),
+            btf_of_arch(8, "xfs")
+        );
+    }
+
+    #[test]
+    fn builder_lists_the_btfs_first_unless_told_not_to() {
+        let btfs = [
+            ("ubuntu", "20.04", "5.4.0-40-generic", btf_of_arch(8, "40")),
+            ("centos", "8", "4.18.0-305.el8.x86_64", minimal_valid_btf()),
+        ];
+        let builder = |listing| {
+            let mut builder = BtfArchiveBuilder::new().with_listing(listing);
+            for (distro, version, release, btf) in &btfs {
+                builder
+                    .add_bytes(distro, version, "x86_64", release, btf.clone())
+                    .unwrap();
+            }
+            builder
+        };
+        let build = |listing| builder(listing).write_tar(vec![]).unwrap();
+        let listed = build(true);
+        let listing = BtfhubArchive::new(&listed).listing().unwrap();
+        assert_eq!(listing.entries.len(), btfs.len());
+        for (distro, version, release, btf) in &btfs {
+            let path = format!("btfhub-archive/{distro}/{version}/x86_64/{release}.btf");
+            assert!(listing.check(&path, btf), "{path}");
+        }
+        assert_eq!(listing.coverage, ["centos/8/x86_64", "ubuntu/20.04/x86_64"]);
+
+        let unlisted = build(false);
+        assert_eq!(BtfhubArchive::new(&unlisted).listing(), None);
+        assert_eq!(entry_names(&unlisted).len(), btfs.len());
+        assert_eq!(
+            BtfhubArchive::new(&unlisted).kernels().unwrap(),
+            BtfhubArchive::new(&listed).kernels().unwrap()
+        );
+        // 过滤后清单不再准确，不会被复制
+        let mut gz = vec![];
+        builder(true)
+            .write_gz(&mut gz, Compression::fast())
+            .unwrap();
+        let mut filtered = vec![];
+        filter_btf_archive(&gz[..], &mut filtered, |_| true).unwrap();
+        let mut tar = vec![];
+        tar_reader(&filtered)
+            .unwrap()
+            .read_to_end(&mut tar)
+            .unwrap();
+        assert_eq!(entry_names(&tar), entry_names(&unlisted));
+    }
+
+    /// A master archive of several distros, arches and kernels
+    fn master() -> FixtureArchive {
+        let mut fixture = FixtureArchive::new().file("SHA256SUMS", b"digests".to_vec());
+        for (version, arch, release) in [
+            ("20.04", "x86_64", "5.4.0-40-generic"),
+            ("20.04", "arm64", "5.4.0-40-generic"),
+            ("20.04", "x86_64", "5.8.0-63-generic"),
+            ("18.04", "x86_64", "4.15.0-20-generic"),
+            ("22.04", "x86_64", "5.15.0-76-generic"),
+        ] {
+            fixture = fixture.btf(
+                "ubuntu",
+                version,
+                arch,
+                release,
+                btf_of_arch(8, &format!("{version}-{arch}-{release}")),
+            );
+        }
+        fixture.btf(
+            "centos",
+            "8",
+            "x86_64",
+            "4.18.0-305.el8.x86_64",
+            minimal_valid_btf(),
+        )
+    }
+
+    fn filter(input: &[u8], predicate: impl Fn(&BtfEntryInfo) -> bool) -> (Vec<u8>, FilterReport) {
+        let mut output = vec![];
+        let report = filter_btf_archive(input, &mut output, predicate).unwrap();
+        (output, report)
+    }
+
+    /// Keeps ubuntu 20.04 and 22.04 of x86_64, from 5.4 on, and the files outside btfs
+    fn slim(info: &BtfEntryInfo) -> bool {
+        match info {
+            BtfEntryInfo::Btf(v) => {
+                v.distro == "ubuntu"
+                    && ["20.04", "22.04"].contains(&v.version.as_str())
+                    && v.arch == "x86_64"
+            }
+            BtfEntryInfo::Other(_) => true,
+        }
+    }
+
+    #[test]
+    fn filtered_archive_round_trips_through_the_lookup() {
+        let (slim_archive, report) = filter(&master().gz(), slim);
+        assert_eq!(
+            report,
+            FilterReport {
+                kept: 4,
+                dropped: 3,
+                size: slim_archive.len() as u64,
+            }
+        );
+        assert_eq!(
+            BtfhubArchive::new(&slim_archive).kernels().unwrap(),
+            [
+                "ubuntu/20.04/x86_64/5.4.0-40-generic",
+                "ubuntu/20.04/x86_64/5.8.0-63-generic",
+                "ubuntu/22.04/x86_64/5.15.0-76-generic",
+            ]
+        );
+        let archive = TarballBtfArchive::from_gzipped_bytes(&slim_archive).unwrap();
+        let entry = archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(
+            archive.extract(&entry).unwrap(),
+            btf_of_arch(8, "20.04-x86_64-5.4.0-40-generic")
+        );
+        let mut dropped = ubuntu("5.4.0-40-generic");
+        dropped.arch = "arm64".into();
+        assert!(archive.lookup(&dropped).is_err());
+        // 同样的输入与条件得到同样的字节
+        assert_eq!(filter(&master().gz(), slim).0, slim_archive);
+    }
+
+    #[test]
+    fn entries_are_copied_byte_for_byte() {
+        let btf = btf_of_arch(8, "gz");
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(&btf).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let entry = "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz";
+        let input = master().file(entry, gzipped.clone());
+        let (output, _) = filter(&input.gz(), slim);
+        // 单独压缩的 btf 不会被重新压缩
+        let mut tar = vec![];
+        tar_reader(&output).unwrap().read_to_end(&mut tar).unwrap();
+        let mut archive = tar_archive(&tar[..]);
+        let mut copied = archive
+            .entries()
+            .unwrap()
+            .map(|v| v.unwrap())
+            .find(|v| v.path().unwrap() == Path::new(entry))
+            .unwrap();
+        let mut contents = vec![];
+        copied.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, gzipped);
+    }
+
+    /// A PAX extended header record `key=value`, prefixed with its own length
+    fn pax_record(key: &str, value: &str) -> Vec<u8> {
+        let body = format!(" {}={}\n", key, value);
+        let mut len = body.len() + 1;
+        while len.to_string().len() + body.len() != len {
+            len += 1;
+        }
+        format!("{}{}", len, body).into_bytes()
+    }
+
+    #[test]
+    fn long_names_survive_the_copy() {
+        let long_dir = format!("btfhub-archive/ubuntu/20.04/x86_64/{}", "d".repeat(90));
+        let gnu_name = format!("{}/gnu.txt", long_dir);
+        let pax_name = format!("{}/pax.txt", long_dir);
+        let tar = master()
+            .longname_entry(&gnu_name, b"gnu".to_vec())
+            .symlink(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+                &format!("{}5.4.0-40-generic.btf", "./".repeat(60)),
+            )
+            .tar();
+        // 追加一个以 PAX 扩展头给出路径的条目
+        let mut builder = Builder::new(vec![]);
+        let records = pax_record("path", &pax_name);
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_size(records.len() as u64);
+        header.set_path("PaxHeaders/pax.txt").unwrap();
+        header.set_cksum();
+        builder.append(&header, &records[..]).unwrap();
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(3);
+        header.set_path("pax.txt").unwrap();
+        header.set_cksum();
+        builder.append(&header, &b"pax"[..]).unwrap();
+        let tar = [&tar[..tar.len() - 1024], &builder.into_inner().unwrap()].concat();
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(&tar).unwrap();
+
+        let (output, _) = filter(&encoder.finish().unwrap(), |_| true);
+        let mut tar = vec![];
+        tar_reader(&output).unwrap().read_to_end(&mut tar).unwrap();
+        let names = entry_names(&tar);
+        assert!(names.contains(&gnu_name), "{names:?}");
+        assert!(names.contains(&pax_name), "{names:?}");
+        let archive = TarballBtfArchive::from_gzipped_bytes(&output).unwrap();
+        let entry = archive.lookup(&ubuntu("5.4.0-41-generic")).unwrap();
+        assert_eq!(
+            archive.extract(&entry).unwrap(),
+            btf_of_arch(8, "20.04-x86_64-5.4.0-40-generic")
+        );
+    }
+
+    #[test]
+    fn index_and_directories_are_left_out() {
+        let tar = master().dir("btfhub-archive/ubuntu").tar();
+        let indexed = crate::index::prepend_index(&tar).unwrap();
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(&indexed).unwrap();
+        let (output, report) = filter(&encoder.finish().unwrap(), |_| true);
+        assert_eq!(report.dropped, 0);
+        let mut tar = vec![];
+        tar_reader(&output).unwrap().read_to_end(&mut tar).unwrap();
+        let names = entry_names(&tar);
+        assert!(!names.contains(&INDEX_ENTRY_NAME.to_string()), "{names:?}");
+        assert!(
+            names.iter().all(|v| v != "btfhub-archive/ubuntu"),
+            "{names:?}"
+        );
+        assert_eq!(names.len(), report.kept);
+    }
+
+    #[test]
+    fn filtering_out_every_btf_fails() {
+        let mut output = vec![];
+        assert!(matches!(
+            filter_btf_archive(&master().gz()[..], &mut output, |v| matches!(
+                v,
+                BtfEntryInfo::Other(_)
+            )),
+            Err(Error::NotBtfhubArchive)
+        ));
+        assert!(matches!(
+            filter_btf_archive(&b"garbage"[..], &mut output, |_| true),
+            Err(Error::TarReadError(_))
+        ));
+    }
+}
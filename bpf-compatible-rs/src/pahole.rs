@@ -0,0 +1,287 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Generating the btf of a kernel the archive doesn't cover, like a self-built one, from
+//! its vmlinux with DWARF, by running `pahole --btf_encode_detached` as the kernel build
+//! does. This takes a few seconds, so nothing here is called unless the caller asks for it.
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{btf::validate_btf_bytes, native::KernelPaths, Error, Result};
+
+/// Command run when [`PaholeOptions::with_pahole`] isn't called, looked up in `PATH`
+pub const DEFAULT_PAHOLE: &str = "pahole";
+
+/// Locations of vmlinux images with DWARF, in the order they are tried, with `{release}`
+/// standing for the kernel release: those of debug packages, then those of kernels built
+/// and installed by hand
+pub const DEBUG_VMLINUX_LOCATIONS: &[&str] = &[
+    "/usr/lib/debug/boot/vmlinux-{release}",
+    "/usr/lib/debug/lib/modules/{release}/vmlinux",
+    "/usr/lib/debug/vmlinux-{release}",
+    "/boot/vmlinux-{release}",
+    "/lib/modules/{release}/build/vmlinux",
+    "/lib/modules/{release}/vmlinux-{release}",
+];
+
+/// Options of [`generate_btf`]
+#[derive(Debug, Clone)]
+pub struct PaholeOptions {
+    pahole: PathBuf,
+    paths: KernelPaths,
+}
+
+impl Default for PaholeOptions {
+    fn default() -> Self {
+        Self {
+            pahole: PathBuf::from(DEFAULT_PAHOLE),
+            paths: KernelPaths::new(DEBUG_VMLINUX_LOCATIONS),
+        }
+    }
+}
+
+impl PaholeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `pahole` instead of [`DEFAULT_PAHOLE`]
+    pub fn with_pahole(mut self, pahole: impl AsRef<Path>) -> Self {
+        self.pahole = pahole.as_ref().to_path_buf();
+        self
+    }
+
+    /// Look for the vmlinux at `locations` instead, see [`NativeBtfProbe::with_locations`](crate::native::NativeBtfProbe::with_locations)
+    pub fn with_locations<S: Into<String>>(
+        mut self,
+        locations: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.paths.set_locations(locations);
+        self
+    }
+
+    /// Look for the vmlinux at `location` too, see [`NativeBtfProbe::add_location`](crate::native::NativeBtfProbe::add_location)
+    pub fn add_location(mut self, location: impl Into<String>) -> Self {
+        self.paths.add_location(location);
+        self
+    }
+
+    /// See [`NativeBtfProbe::with_root`](crate::native::NativeBtfProbe::with_root)
+    pub fn with_root(mut self, root: impl AsRef<Path>) -> Self {
+        self.paths.set_root(root.as_ref());
+        self
+    }
+
+    /// The command run
+    pub fn pahole(&self) -> &Path {
+        &self.pahole
+    }
+
+    /// The locations, in the order they are tried
+    pub fn locations(&self) -> &[String] {
+        self.paths.locations()
+    }
+
+    /// The first location holding an ELF file for the kernel `release`
+    ///
+    /// Whether it has DWARF is left to pahole. Fails with [`Error::VmlinuxNotFound`],
+    /// listing the paths tried, if there's none.
+    pub fn find_vmlinux(&self, release: &str) -> Result<PathBuf> {
+        let paths = self.paths.resolve(release).collect::<Vec<_>>();
+        paths
+            .iter()
+            .find(|v| is_elf_file(v))
+            .cloned()
+            .ok_or_else(|| {
+                Error::VmlinuxNotFound(
+                    release.to_string(),
+                    paths
+                        .iter()
+                        .map(|v| v.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                )
+            })
+    }
+}
+
+/// Whether `path` is a file starting with the ELF magic, unlike the raw btfs installed at the same places
+fn is_elf_file(path: &Path) -> bool {
+    use std::io::Read;
+    let mut magic = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut v| v.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+/// Generate the btf of the kernel `release` from its vmlinux, see [`PaholeOptions::find_vmlinux`]
+///
+/// pahole writes the btf to a temporary directory, removed before returning; the btf is
+/// validated with [`validate_btf_bytes`]. Fails with [`Error::VmlinuxNotFound`] if there's
+/// no vmlinux, [`Error::PaholeUnavailable`] if pahole can't be run, e.g. it isn't
+/// installed, and [`Error::PaholeFailed`] if it fails, e.g. on a vmlinux without DWARF, or
+/// writes no valid btf.
+pub fn generate_btf(release: &str, opts: &PaholeOptions) -> Result<Vec<u8>> {
+    let vmlinux = opts.find_vmlinux(release)?;
+    let failed = |reason: String| Error::PaholeFailed(vmlinux.display().to_string(), reason);
+    let workdir = tempfile::tempdir().map_err(Error::TempDirError)?;
+    let output = workdir.path().join("vmlinux.btf");
+    log_at!(
+        Info,
+        "Generating the btf of {} from {} with pahole",
+        release,
+        vmlinux.display()
+    );
+    let result = Command::new(&opts.pahole)
+        .arg("--btf_encode_detached")
+        .arg(&output)
+        .arg(&vmlinux)
+        .output()
+        .map_err(|e| Error::PaholeUnavailable(opts.pahole.display().to_string(), e))?;
+    if !result.status.success() {
+        return Err(failed(format!(
+            "pahole exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    let btf = std::fs::read(&output).map_err(|e| failed(format!("pahole wrote no btf: {}", e)))?;
+    validate_btf_bytes(&btf).map_err(|e| failed(format!("pahole wrote an invalid btf: {}", e)))?;
+    Ok(btf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::*;
+    use crate::fixture::{btf_of_arch, minimal_valid_btf};
+
+    const RELEASE: &str = "6.1.0-custom";
+
+    /// A vmlinux as far as the lookup is concerned; its DWARF is left to pahole
+    const VMLINUX: &[u8] = b"\x7fELF\x02\x01\x01\0with dwarf";
+
+    /// A root with the vmlinux of `RELEASE` at `location`, and an executable `pahole` script
+    /// running `script` with the arguments it got written to `args`
+    fn root_with(location: &str, script: &str) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let vmlinux = root.path().join(
+            location
+                .trim_start_matches('/')
+                .replace("{release}", RELEASE),
+        );
+        fs::create_dir_all(vmlinux.parent().unwrap()).unwrap();
+        fs::write(vmlinux, VMLINUX).unwrap();
+        let pahole = root.path().join("pahole");
+        fs::write(
+            &pahole,
+            format!(
+                "#!/bin/sh\necho \"$@\" > {}\n{}\n",
+                root.path().join("args").display(),
+                script
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&pahole, fs::Permissions::from_mode(0o755)).unwrap();
+        root
+    }
+
+    fn opts_of(root: &tempfile::TempDir) -> PaholeOptions {
+        PaholeOptions::new()
+            .with_root(root.path())
+            .with_pahole(root.path().join("pahole"))
+    }
+
+    #[test]
+    fn btf_written_by_pahole_is_returned() {
+        // 桩程序把固定的 btf 复制到 pahole 的输出位置
+        let btf = tempfile::NamedTempFile::new().unwrap();
+        fs::write(btf.path(), btf_of_arch(8, "rip")).unwrap();
+        let root = root_with(
+            "/lib/modules/{release}/build/vmlinux",
+            &format!("cp {} \"$2\"", btf.path().display()),
+        );
+        let opts = opts_of(&root);
+        assert_eq!(generate_btf(RELEASE, &opts).unwrap(), btf_of_arch(8, "rip"));
+        let vmlinux = root.path().join("lib/modules/6.1.0-custom/build/vmlinux");
+        let args = fs::read_to_string(root.path().join("args")).unwrap();
+        let args = args.split_whitespace().collect::<Vec<_>>();
+        assert_eq!(args[0], "--btf_encode_detached");
+        assert!(args[1].ends_with("/vmlinux.btf"), "{args:?}");
+        assert_eq!(args[2], vmlinux.to_str().unwrap());
+        // 输出所在的临时目录在返回前删除
+        assert!(!Path::new(args[1]).parent().unwrap().exists());
+    }
+
+    #[test]
+    fn vmlinux_is_the_first_elf_file_found() {
+        let root = root_with("/boot/vmlinux-{release}", "");
+        // 调试包中的原始 btf 不是 pahole 的输入
+        let debug = root.path().join("usr/lib/debug/boot");
+        fs::create_dir_all(&debug).unwrap();
+        fs::write(debug.join("vmlinux-6.1.0-custom"), minimal_valid_btf()).unwrap();
+        let opts = opts_of(&root);
+        assert_eq!(
+            opts.find_vmlinux(RELEASE).unwrap(),
+            root.path().join("boot/vmlinux-6.1.0-custom")
+        );
+        let custom = opts.clone().with_locations(["/opt/{release}/vmlinux"]);
+        assert_eq!(custom.locations(), ["/opt/{release}/vmlinux"]);
+        match custom.find_vmlinux(RELEASE) {
+            Err(Error::VmlinuxNotFound(release, tried)) => {
+                assert_eq!(release, RELEASE);
+                assert_eq!(
+                    tried,
+                    root.path()
+                        .join("opt/6.1.0-custom/vmlinux")
+                        .display()
+                        .to_string()
+                );
+            }
+            other => panic!("{other:?}"),
+        }
+        let extended = custom.add_location("/boot/vmlinux-{release}");
+        assert!(extended.find_vmlinux(RELEASE).is_ok());
+    }
+
+    #[test]
+    fn each_failure_has_its_own_error() {
+        let root = root_with("/boot/vmlinux-{release}", "exit 0");
+        // 没有 vmlinux 时不运行 pahole
+        assert!(matches!(
+            generate_btf("6.1.0-other", &opts_of(&root)),
+            Err(Error::VmlinuxNotFound(..))
+        ));
+        assert!(!root.path().join("args").exists());
+
+        let missing = opts_of(&root).with_pahole(root.path().join("no-pahole"));
+        assert!(matches!(
+            generate_btf(RELEASE, &missing),
+            Err(Error::PaholeUnavailable(..))
+        ));
+
+        for (script, reason) in [
+            (
+                "echo 'no DWARF found' >&2; exit 2",
+                "pahole exited with exit status: 2: no DWARF found",
+            ),
+            ("exit 0", "pahole wrote no btf"),
+            ("echo garbage > \"$2\"", "pahole wrote an invalid btf"),
+        ] {
+            let root = root_with("/boot/vmlinux-{release}", script);
+            match generate_btf(RELEASE, &opts_of(&root)) {
+                Err(Error::PaholeFailed(vmlinux, message)) => {
+                    assert!(vmlinux.ends_with("boot/vmlinux-6.1.0-custom"), "{vmlinux}");
+                    assert!(message.starts_with(reason), "{message}");
+                }
+                other => panic!("{other:?}"),
+            }
+        }
+    }
+}
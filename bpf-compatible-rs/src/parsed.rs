@@ -0,0 +1,479 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Every lookup in a compressed archive decompresses it again up to the matching entry.
+//! A process loading several objects may rather decompress the archive once, keep the tar
+//! in memory, and index its entries, so later lookups are a map access and a slice.
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::{normalize_entry_path, BtfEncoding, BtfhubArchive, BTFHUB_ARCHIVE_DIR},
+    btf::has_swapped_magic,
+    compression::{tar_archive, tar_entries, tar_reader_with_limit, DEFAULT_MAX_DECOMPRESSED_SIZE},
+    flat::{arch_marker_allows, parse_flat_btf_path, ARCH_MARKER_NAME},
+    generate_module_btf_paths_for,
+    matcher::{encoded_paths, BtfMatcher},
+    progress::{Progress, ProgressTracker},
+    release::MatchPolicy,
+    sparse::{entry_layout, entry_path, is_file_entry, read_entry, EntryLayout},
+    Error, Result, SystemInfo,
+};
+
+/// Links followed at most when resolving an entry
+pub(crate) const MAX_LINK_DEPTH: usize = 8;
+
+/// A file or link of a [`ParsedArchive`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedEntry {
+    /// Path of the entry as stored in the archive, e.g. with a leading `./`
+    pub path: PathBuf,
+    /// Position of the contents within the decompressed tar; for a sparse entry, see
+    /// [`crate::sparse`], that of its encoded contents, which [`ParsedArchive::extract`] reassembles
+    pub offset: u64,
+    /// Size of the contents, holes of sparse entries included; 0 for links
+    pub size: u64,
+    /// Path within the archive a hardlink or symlink points to, `None` for regular files
+    ///
+    /// Hardlink targets are relative to the root of the archive, relative symlink targets
+    /// to the directory holding the link.
+    pub link_target: Option<PathBuf>,
+}
+
+/// A decompressed archive with an index of its entries, for repeated lookups
+#[derive(Debug, Clone)]
+pub struct ParsedArchive {
+    tar: Vec<u8>,
+    entries: Vec<IndexedEntry>,
+    by_path: HashMap<PathBuf, usize>,
+    /// Reassembled contents of the sparse entries, by offset
+    sparse: HashMap<u64, Vec<u8>>,
+    prefix: PathBuf,
+}
+
+impl ParsedArchive {
+    /// Decompress `bytes` (any format of [`tar_reader`](crate::compression::tar_reader)) and index its files and links
+    ///
+    /// If a path occurs more than once, the last entry wins, as when unpacking the tar.
+    /// Directories, metadata entries and links without a target are left out.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        Self::parse_with_limit(bytes, DEFAULT_MAX_DECOMPRESSED_SIZE)
+    }
+
+    /// Same as [`ParsedArchive::parse`], failing if `bytes` decompresses to more than
+    /// `max_size` bytes, see [`tar_reader_with_limit`]
+    pub fn parse_with_limit(bytes: &[u8], max_size: u64) -> Result<Self> {
+        let mut tar = vec![];
+        tar_reader_with_limit(bytes, max_size)?
+            .read_to_end(&mut tar)
+            .map_err(Error::from_read)?;
+        Self::index(tar)
+    }
+
+    /// Same as [`ParsedArchive::parse_with_limit`], telling `progress` how much of the tar
+    /// was decompressed; fails with [`Error::Cancelled`] if it cancels the decompression
+    pub fn parse_with_progress(bytes: &[u8], max_size: u64, progress: &Progress) -> Result<Self> {
+        let tracker = ProgressTracker::new(progress.clone(), bytes.len() as u64);
+        let mut tar = vec![];
+        let read = tracker
+            .reader(tar_reader_with_limit(bytes, max_size)?)
+            .read_to_end(&mut tar);
+        if tracker.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        read.map_err(Error::from_read)?;
+        Self::index(tar)
+    }
+
+    /// Index the files and links of the decompressed `tar`
+    fn index(tar: Vec<u8>) -> Result<Self> {
+        let mut entries = vec![];
+        let mut sparse = HashMap::new();
+        let mut archive = tar_archive(&tar[..]);
+        for entry in tar_entries(&mut archive).map_err(Error::from_read)? {
+            let mut entry = entry.map_err(Error::from_read)?;
+            let entry_type = entry.header().entry_type();
+            let path = entry_path(&mut entry).map_err(Error::from_read)?;
+            let link_target = if entry_type.is_hard_link() || entry_type.is_symlink() {
+                let Some(target) = entry.link_name().map_err(Error::from_read)? else {
+                    continue;
+                };
+                let target = if entry_type.is_symlink() && target.is_relative() {
+                    path.parent().unwrap_or(Path::new("")).join(target)
+                } else {
+                    target.into_owned()
+                };
+                Some(normalize_entry_path(&target))
+            } else if is_file_entry(entry_type) {
+                None
+            } else {
+                continue;
+            };
+            let mut size = entry.size();
+            // 稀疏条目的内容不能直接从 tar 中截取，解析时重组并保存下来
+            if link_target.is_none()
+                && entry_layout(&mut entry).map_err(Error::from_read)? != EntryLayout::Contiguous
+            {
+                let contents = read_entry(&mut entry).map_err(Error::from_read)?;
+                size = contents.len() as u64;
+                sparse.insert(entry.raw_file_position(), contents);
+            }
+            entries.push(IndexedEntry {
+                offset: entry.raw_file_position(),
+                size,
+                path,
+                link_target,
+            });
+        }
+        // 同一路径出现多次时后出现的条目覆盖之前的
+        let by_path = entries
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (normalize_entry_path(&v.path), i))
+            .collect();
+        Ok(Self {
+            tar,
+            entries,
+            by_path,
+            sparse,
+            prefix: PathBuf::from(BTFHUB_ARCHIVE_DIR),
+        })
+    }
+
+    /// Look up the btfs of [`ParsedArchive::lookup`] under `prefix` instead of `btfhub-archive`
+    pub fn with_prefix(mut self, prefix: impl AsRef<Path>) -> Self {
+        self.prefix = prefix.as_ref().to_path_buf();
+        self
+    }
+
+    /// The decompressed tar
+    pub fn tar(&self) -> &[u8] {
+        &self.tar
+    }
+
+    /// The decompressed tar as a [`BtfhubArchive`], e.g. to rank candidates without decompressing again
+    pub fn archive(&self) -> BtfhubArchive<'_> {
+        BtfhubArchive::new(&self.tar).with_prefix(&self.prefix)
+    }
+
+    /// The files and links of the archive, in archive order, duplicates included
+    pub fn entries(&self) -> &[IndexedEntry] {
+        &self.entries
+    }
+
+    /// The entry at `path`, without following links; a leading `./` or `/` makes no difference
+    pub fn entry(&self, path: impl AsRef<Path>) -> Option<&IndexedEntry> {
+        self.by_path
+            .get(&normalize_entry_path(path.as_ref()))
+            .map(|v| &self.entries[*v])
+    }
+
+    /// The regular entry `path` stands for, following links
+    pub fn resolve(&self, path: impl AsRef<Path>) -> Result<&IndexedEntry> {
+        let mut path = path.as_ref().to_path_buf();
+        // 限制跟随链接的次数，避免链接成环时无限循环
+        for _ in 0..MAX_LINK_DEPTH {
+            let entry = self.entry(&path).ok_or_else(|| Error::EntryNotFound {
+                expected: path.clone(),
+            })?;
+            match &entry.link_target {
+                Some(target) => path = target.clone(),
+                None => return Ok(entry),
+            }
+        }
+        Err(Error::TooManyLinks(path.display().to_string()))
+    }
+
+    /// The contents of the entry at `path`, following links
+    pub fn extract(&self, path: impl AsRef<Path>) -> Result<&[u8]> {
+        let entry = self.resolve(path)?;
+        if let Some(contents) = self.sparse.get(&entry.offset) {
+            return Ok(contents);
+        }
+        // 偏移和大小来自解析时的 tar 头部，截断的归档中最后一个条目可能超出 tar 的末尾
+        let range = usize::try_from(entry.offset)
+            .ok()
+            .zip(usize::try_from(entry.size).ok())
+            .and_then(|(start, size)| Some(start..start.checked_add(size)?));
+        match range.and_then(|v| self.tar.get(v)) {
+            Some(v) => Ok(v),
+            None => Err(Error::TarReadError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "`{}` extends past the end of the archive",
+                    entry.path.display()
+                ),
+            ))),
+        }
+    }
+
+    /// The entry holding the btf of `info`, matched exactly, see [`ParsedArchive::lookup_with`]
+    pub fn lookup(&self, info: &SystemInfo) -> Option<&IndexedEntry> {
+        self.lookup_with(&BtfMatcher::new(info, &self.prefix, MatchPolicy::Exact))
+    }
+
+    /// The entry of the best candidate of `matcher`, or else the fallback it picks, see [`BtfMatcher::fallback`]
+    ///
+    /// The entries are matched as `bpf-compatible-sys` matches them: the last of the
+    /// encodings of a candidate wins, and a plain btf of the other byte order than the
+    /// host's is skipped for the next candidate. The btfs at the root of a flat archive
+    /// are left out if its architecture marker names another architecture. The returned
+    /// entry may be a link; [`ParsedArchive::extract`] its path to read the btf.
+    pub fn lookup_with(&self, matcher: &BtfMatcher) -> Option<&IndexedEntry> {
+        let allows_flat = self.allows_flat(matcher.info());
+        let candidates = match allows_flat {
+            true => matcher.candidates(),
+            false => matcher.btfhub_candidates(),
+        };
+        for candidate in candidates {
+            log_at!(Debug, "Looking for {}", candidate.display());
+            let Some((entry, encoding)) = encoded_paths(candidate)
+                .filter_map(|(path, encoding)| Some((self.entry(path)?, encoding)))
+                .max_by_key(|(entry, _)| entry.offset)
+            else {
+                continue;
+            };
+            // 其他字节序的主机上生成的 btf 无法使用，排在后面的候选仍可胜出
+            if encoding == BtfEncoding::Plain
+                && self.extract(&entry.path).is_ok_and(has_swapped_magic)
+            {
+                log_at!(
+                    Debug,
+                    "Skipped {}, of the other byte order",
+                    entry.path.display()
+                );
+                continue;
+            }
+            return Some(entry);
+        }
+        let paths = self
+            .entries
+            .iter()
+            .map(|v| normalize_entry_path(&v.path))
+            .filter(|v| allows_flat || parse_flat_btf_path(v).is_none())
+            .collect::<Vec<_>>();
+        let siblings = paths
+            .iter()
+            .filter(|v| matcher.is_sibling(v))
+            .cloned()
+            .collect::<Vec<_>>();
+        let other_distros = paths
+            .iter()
+            .filter(|v| matcher.wants_other_distros() && matcher.is_other_distro(v))
+            .cloned()
+            .collect::<Vec<_>>();
+        let fallback = matcher.fallback(&siblings, &other_distros)?;
+        log_at!(Warn, "{}", fallback);
+        self.entry(&fallback.path)
+    }
+
+    /// Whether the btfs at the root of the archive may be those of `info`, i.e. unless its
+    /// architecture marker names another architecture, see [`arch_marker_allows`]
+    pub fn allows_flat(&self, info: &SystemInfo) -> bool {
+        let Ok(marker) = self.extract(ARCH_MARKER_NAME) else {
+            return true;
+        };
+        let allows = arch_marker_allows(marker, info);
+        if !allows {
+            log_at!(
+                Debug,
+                "Skipped the btfs at the root of the archive, for the architecture {}",
+                String::from_utf8_lossy(marker).trim()
+            );
+        }
+        allows
+    }
+
+    /// The entry holding the split btf of `module` for `info`, see [`generate_module_btf_paths_for`]
+    pub fn lookup_module(&self, info: &SystemInfo, module: &str) -> Option<&IndexedEntry> {
+        generate_module_btf_paths_for(info, module)
+            .into_iter()
+            .find_map(|v| {
+                log_at!(Debug, "Looking for {}", self.prefix.join(&v).display());
+                self.entry(self.prefix.join(v))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::{
+        archive::BtfEntryInfo,
+        fixture::{btf_of_arch, btf_with_hole, FixtureArchive},
+        index::{prepend_index, ArchiveIndex},
+    };
+
+    #[test]
+    fn sparse_entries_are_reassembled_once_parsed() {
+        let btf = btf_with_hole();
+        let chunks: &[(u64, &[u8])] = &[(0, &btf[..512]), (1536, &btf[1536..])];
+        let parsed = ParsedArchive::parse(
+            &FixtureArchive::new()
+                .sparse("btfhub-archive/gnu.btf", chunks, btf.len() as u64)
+                .pax_sparse("btfhub-archive/pax.btf", chunks, btf.len() as u64)
+                .hardlink("btfhub-archive/link.btf", "btfhub-archive/gnu.btf")
+                .file("btfhub-archive/plain.btf", btf.clone())
+                .gz(),
+        )
+        .unwrap();
+        for path in ["gnu.btf", "pax.btf", "link.btf", "plain.btf"] {
+            let path = Path::new("btfhub-archive").join(path);
+            assert_eq!(parsed.extract(&path).unwrap(), btf, "{}", path.display());
+        }
+        // 稀疏条目的大小包括空洞，偏移为编码后内容的位置
+        let gnu = parsed.entry("btfhub-archive/gnu.btf").unwrap();
+        assert_eq!(gnu.size, btf.len() as u64);
+        assert_ne!(
+            parsed.tar()[gnu.offset as usize..][..btf.len()],
+            btf[..],
+            "a sparse entry must not be sliced out of the tar"
+        );
+        // PAX 格式的条目以记录的名字索引，而不是 GNUSparseFile.0/
+        assert!(parsed
+            .entries()
+            .iter()
+            .all(|v| !v.path.to_string_lossy().contains("GNUSparseFile")));
+    }
+
+    #[test]
+    fn lookups_match_entries_as_the_c_api_does() {
+        let info = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: "5.4.0-42-generic".into(),
+            ..Default::default()
+        };
+        let parsed = ParsedArchive::parse(
+            &FixtureArchive::new()
+                .file(
+                    "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.tar.xz",
+                    vec![],
+                )
+                .btf(
+                    "ubuntu",
+                    "20.04",
+                    "x86_64",
+                    "5.4.0-41-generic",
+                    btf_of_arch(8, "41"),
+                )
+                .tar(),
+        )
+        .unwrap();
+        assert_eq!(parsed.lookup(&info), None);
+        let nearest = |policy| {
+            let matcher = BtfMatcher::new(&info, BTFHUB_ARCHIVE_DIR, policy);
+            parsed.lookup_with(&matcher).map(|v| v.path.clone())
+        };
+        assert_eq!(
+            nearest(MatchPolicy::SameFlavorNearest),
+            Some(PathBuf::from(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf"
+            ))
+        );
+        // 压缩的条目与 btf 本身同样匹配
+        let info = SystemInfo {
+            kernel_release: "5.4.0-40-generic".into(),
+            ..info
+        };
+        assert_eq!(
+            parsed.lookup(&info).map(|v| v.path.as_path()),
+            Some(Path::new(
+                "./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.tar.xz"
+            ))
+        );
+    }
+
+    /// Read everything `bytes` holds, by each of the readers; errors are expected, panics aren't
+    fn read_all(bytes: &[u8]) {
+        if let Ok(parsed) = ParsedArchive::parse_with_limit(bytes, 1 << 20) {
+            for entry in parsed.entries() {
+                let _ = parsed.extract(&entry.path);
+            }
+        }
+        let archive = BtfhubArchive::new(bytes).with_max_decompressed_size(1 << 20);
+        let _ = archive.kernels();
+        for entry in archive.entries().flatten() {
+            let BtfEntryInfo::Btf(entry) = entry else {
+                continue;
+            };
+            let _ = archive.extract(&entry.path);
+            if let Ok(mut reader) = archive.open_btf(&entry) {
+                let _ = reader.read_to_end(&mut vec![]);
+            }
+        }
+        if let Some(index) = ArchiveIndex::read(bytes) {
+            for path in index.paths() {
+                let _ = index.locate(bytes, path);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_archives_fail_without_panicking() {
+        let fixture = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "rip"),
+            )
+            .sparse(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+                &[(0, &btf_with_hole()[..512])],
+                2048,
+            )
+            .hardlink(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf",
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            );
+        let tar = fixture.tar();
+        let archives = [prepend_index(&tar).unwrap(), tar, fixture.gz()];
+        // 固定种子的线性同余序列，失败时可以复现
+        let mut seed = 0x2545_f491_u64;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 33) as usize % bound
+        };
+        for archive in &archives {
+            for len in (0..archive.len()).step_by(97) {
+                read_all(&archive[..len]);
+            }
+            for _ in 0..300 {
+                let mut flipped = archive.clone();
+                let at = next(flipped.len().min(4096));
+                flipped[at] ^= 1 << next(8);
+                read_all(&flipped);
+            }
+        }
+        // 每个头部的大小都可能越过归档的结尾，甚至在相加时溢出
+        let tar = &archives[1];
+        let headers = tar::Archive::new(&tar[..])
+            .entries()
+            .unwrap()
+            .map(|v| v.unwrap().raw_header_position() as usize)
+            .collect::<Vec<_>>();
+        for at in headers {
+            for size in [tar.len() as u64, u64::MAX >> 4] {
+                let mut header = tar::Header::from_byte_slice(&tar[at..at + 512]).clone();
+                header.set_size(size);
+                header.set_cksum();
+                let mut corrupt = tar.clone();
+                corrupt[at..at + 512].copy_from_slice(header.as_bytes());
+                read_all(&corrupt);
+                assert!(ParsedArchive::parse(&corrupt).is_err());
+            }
+        }
+        // 截断在条目中间的归档不会被当作完整的归档
+        assert!(ParsedArchive::parse(&tar[..1024 + 100]).is_err());
+    }
+}
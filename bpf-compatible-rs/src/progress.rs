@@ -0,0 +1,176 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Progress of the decompression of large archives, and its cancellation.
+//!
+//! Decompressing a large archive on a slow machine takes seconds, during which a service
+//! manager may want to reset its watchdog, or stop the unit. A [`Progress`](crate::progress::Progress) callback is
+//! told how many bytes of the decompressed tar were read every [`PROGRESS_INTERVAL`](crate::progress::PROGRESS_INTERVAL)
+//! bytes, and cancels the lookup by returning [`ControlFlow::Break`](std::ops::ControlFlow::Break).
+use std::{cell::Cell, fmt::Debug, io::Read, ops::ControlFlow, sync::Arc};
+
+/// Bytes read between two calls of the callback
+pub const PROGRESS_INTERVAL: u64 = 1 << 20;
+
+/// Callback told how far a lookup got, see the [module](self) documentation
+///
+/// It's given the bytes of the decompressed tar read so far, and a hint of the total:
+/// the size of the archive as given, which is that of the tar unless it's compressed, so
+/// the bytes read may exceed it. An archive read more than once, e.g. to follow a link,
+/// is counted again.
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(u64, u64) -> ControlFlow<()> + Send + Sync>);
+
+impl Progress {
+    pub fn new(progress: impl Fn(u64, u64) -> ControlFlow<()> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(progress))
+    }
+}
+
+impl Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// The progress of one lookup, shared by the readers of the archives it decompresses
+#[derive(Debug)]
+pub struct ProgressTracker {
+    progress: Progress,
+    total_hint: u64,
+    processed: Cell<u64>,
+    reported: Cell<u64>,
+    cancelled: Cell<bool>,
+}
+
+impl ProgressTracker {
+    /// Track a lookup in an archive of `total_hint` bytes, see [`Progress`]
+    pub fn new(progress: Progress, total_hint: u64) -> Self {
+        Self {
+            progress,
+            total_hint,
+            processed: Cell::new(0),
+            reported: Cell::new(0),
+            cancelled: Cell::new(false),
+        }
+    }
+
+    /// Count the bytes read from `inner` towards the progress
+    pub fn reader<R: Read>(&self, inner: R) -> ProgressReader<'_, R> {
+        ProgressReader {
+            inner,
+            tracker: self,
+        }
+    }
+
+    /// Whether the callback cancelled the lookup; reads fail from then on
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+
+    fn advance(&self, n: usize) -> std::io::Result<()> {
+        let processed = self.processed.get() + n as u64;
+        self.processed.set(processed);
+        if processed - self.reported.get() >= PROGRESS_INTERVAL {
+            self.reported.set(processed);
+            if (self.progress.0)(processed, self.total_hint).is_break() {
+                log_at!(Info, "Cancelled after reading {} bytes", processed);
+                self.cancelled.set(true);
+            }
+        }
+        self.check()
+    }
+
+    fn check(&self) -> std::io::Result<()> {
+        match self.cancelled.get() {
+            true => Err(std::io::Error::other("cancelled by the progress callback")),
+            false => Ok(()),
+        }
+    }
+}
+
+/// A reader counting what is read from it towards a [`ProgressTracker`]
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    tracker: &'a ProgressTracker,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.tracker.check()?;
+        // 一次读取很多时也按间隔调用回调
+        let len = buf.len().min(PROGRESS_INTERVAL as usize);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.tracker.advance(n)?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Mutex};
+
+    use super::*;
+
+    type Calls = Arc<Mutex<Vec<(u64, u64)>>>;
+
+    /// A tracker of `total_hint` bytes recording its calls, breaking once `calls` were made
+    fn recording(total_hint: u64, calls: Option<usize>) -> (ProgressTracker, Calls) {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let recorded = seen.clone();
+        let progress = Progress::new(move |processed, total| {
+            let mut seen = recorded.lock().unwrap();
+            seen.push((processed, total));
+            match calls.is_some_and(|v| seen.len() >= v) {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            }
+        });
+        (ProgressTracker::new(progress, total_hint), seen)
+    }
+
+    #[test]
+    fn callback_is_told_every_interval() {
+        let data = vec![7; 3 * PROGRESS_INTERVAL as usize + 100];
+        let (tracker, seen) = recording(42, None);
+        let mut read = vec![];
+        // 一次读取全部时也按间隔调用
+        tracker
+            .reader(Cursor::new(&data))
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [1, 2, 3].map(|v| (v * PROGRESS_INTERVAL, 42))
+        );
+        assert!(!tracker.is_cancelled());
+
+        // 不足一个间隔的读取不调用回调
+        let (tracker, seen) = recording(42, None);
+        tracker
+            .reader(Cursor::new(&data[..1000]))
+            .read_to_end(&mut vec![])
+            .unwrap();
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn break_fails_every_later_read() {
+        let data = vec![7; 4 * PROGRESS_INTERVAL as usize];
+        let (tracker, seen) = recording(data.len() as u64, Some(2));
+        let error = tracker
+            .reader(Cursor::new(&data))
+            .read_to_end(&mut vec![])
+            .unwrap_err();
+        assert!(error.to_string().contains("cancelled"), "{error}");
+        assert!(tracker.is_cancelled());
+        assert_eq!(seen.lock().unwrap().len(), 2);
+        // 同一次查找的其他读取者也不再读取，回调不再调用
+        let mut buf = [0; 16];
+        assert!(tracker.reader(Cursor::new(&data)).read(&mut buf).is_err());
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,1128 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Kernel releases like `5.4.0-148-generic`, split into the numbers that tell point
+//! releases apart and the flavor that follows them.
+use std::{cmp::Ordering, convert::Infallible, fmt::Display, str::FromStr};
+
+/// A parsed kernel release
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelRelease<'a> {
+    /// The leading numeric components, e.g. `[5, 4, 0, 148]` for `5.4.0-148-generic`
+    pub numbers: Vec<u64>,
+    /// Whatever follows the numbers, e.g. `generic`, or `el8.x86_64` for `4.18.0-425.3.1.el8.x86_64`
+    pub flavor: &'a str,
+}
+
+impl<'a> KernelRelease<'a> {
+    /// Parse a release, which must start with at least `major.minor`
+    pub fn parse(release: &'a str) -> Option<Self> {
+        let mut numbers = vec![];
+        let mut rest = release;
+        loop {
+            let end = rest.find(['.', '-']).unwrap_or(rest.len());
+            match rest[..end].parse() {
+                Ok(v) => numbers.push(v),
+                Err(_) => break,
+            }
+            rest = rest.get(end + 1..).unwrap_or_default();
+        }
+        (numbers.len() >= 2).then_some(Self {
+            numbers,
+            flavor: rest,
+        })
+    }
+
+    /// Whether both releases share major and minor version
+    pub fn same_major_minor(&self, other: &KernelRelease) -> bool {
+        self.numbers[..2] == other.numbers[..2]
+    }
+}
+
+/// A kernel release, ordered and compared the way the nearest kernel fallback does
+///
+/// A release is the upstream version (`major.minor[.patch]`), then after a `-` the
+/// ABI or package revision of the distro (numbers separated by `.`), then an optional
+/// distro-specific tail and flavor:
+///
+/// | release                         | upstream   | abi            | tail           | flavor    |
+/// |---------------------------------|------------|----------------|----------------|-----------|
+/// | `5.4.0-148-generic`             | `5.4.0`    | `148`          |                | `generic` |
+/// | `4.18.0-425.3.1.el8.x86_64`     | `4.18.0`   | `425.3.1`      | `el8.x86_64`   |           |
+/// | `6.1.0-0.deb11.6-amd64`         | `6.1.0`    | `0`            | `deb11.6`      | `amd64`   |
+/// | `5.14.21-150400.24.46-default`  | `5.14.21`  | `150400.24.46` |                | `default` |
+/// | `4.12.14-lp151.28.36-default`   | `4.12.14`  | `28.36`        | `lp151`        | `default` |
+/// | `5.10.184-175.731.amzn2.x86_64` | `5.10.184` | `175.731`      | `amzn2.x86_64` |           |
+///
+/// The tail is what follows the numbers after a `.`, or after a `-` if its first word
+/// holds a `.`, up to the next `-`; the flavor is the rest. openSUSE Leap puts the release
+/// it's built for, like `lp151` for Leap 15.1, before the revision of the package: that
+/// word is the tail, and the numbers after it the ABI.
+///
+/// Versions are ordered by their numbers, numerically, so `5.10` is above `5.4` and
+/// `-148` above `-99`; then by tail and flavor, comparing runs of digits as numbers;
+/// then by the release string. Releases that don't start with `major.minor` are kept
+/// as is: they are ordered after all others, and among themselves by their string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KernelVersion {
+    release: String,
+    parts: Option<VersionParts>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct VersionParts {
+    upstream: Vec<u64>,
+    abi: Vec<u64>,
+    tail: String,
+    flavor: String,
+}
+
+impl KernelVersion {
+    /// Parse `release`, which is kept as is if it can't be
+    pub fn new(release: impl Into<String>) -> Self {
+        let release = release.into();
+        let parts = VersionParts::parse(&release);
+        Self { release, parts }
+    }
+
+    /// The release string
+    pub fn as_str(&self) -> &str {
+        &self.release
+    }
+
+    /// Whether the release could be parsed; accessors return `None` or nothing otherwise
+    pub fn is_parsed(&self) -> bool {
+        self.parts.is_some()
+    }
+
+    pub fn major(&self) -> Option<u64> {
+        self.upstream().first().copied()
+    }
+
+    pub fn minor(&self) -> Option<u64> {
+        self.upstream().get(1).copied()
+    }
+
+    /// The patch level, `None` for a release like `6.6-rc1` without one
+    pub fn patch(&self) -> Option<u64> {
+        self.upstream().get(2).copied()
+    }
+
+    /// The ABI or package revision after the upstream version, e.g. `[425, 3, 1]` for `4.18.0-425.3.1.el8.x86_64`
+    pub fn abi(&self) -> &[u64] {
+        self.parts.as_ref().map(|v| &v.abi[..]).unwrap_or_default()
+    }
+
+    /// The distro-specific tail, e.g. `el8.x86_64` or `deb11.6`, empty if there's none
+    pub fn tail(&self) -> &str {
+        self.parts.as_ref().map(|v| &v.tail[..]).unwrap_or_default()
+    }
+
+    /// The flavor, e.g. `generic`, `cloud-amd64` or `default`, empty if there's none
+    pub fn flavor(&self) -> &str {
+        self.parts
+            .as_ref()
+            .map(|v| &v.flavor[..])
+            .unwrap_or_default()
+    }
+
+    /// How far apart two point releases of the same `major.minor` are, `None` for
+    /// different series or releases that couldn't be parsed
+    ///
+    /// Compares the numbers after `major.minor`, patch level first, then the ABI;
+    /// missing ones count as 0. Any difference of the patch level is farther than one
+    /// of the ABI, and so on, and the same numbers are at distance 0. Tails and flavors
+    /// aren't considered, callers filter on them as they see fit.
+    pub fn distance(&self, other: &KernelVersion) -> Option<u64> {
+        const LEVEL_SHIFT: u32 = 48;
+        let (a, b) = (self.parts.as_ref()?, other.parts.as_ref()?);
+        if a.upstream[..2] != b.upstream[..2] {
+            return None;
+        }
+        let (a, b) = (a.point_numbers(), b.point_numbers());
+        let at = |v: &[u64], i: usize| v.get(i).copied().unwrap_or_default();
+        let Some(level) = (0..a.len().max(b.len())).find(|i| at(&a, *i) != at(&b, *i)) else {
+            return Some(0);
+        };
+        let difference = at(&a, level)
+            .abs_diff(at(&b, level))
+            .min((1 << LEVEL_SHIFT) - 1);
+        Some(((16 - level.min(15)) as u64) << LEVEL_SHIFT | difference)
+    }
+
+    fn upstream(&self) -> &[u64] {
+        self.parts
+            .as_ref()
+            .map(|v| &v.upstream[..])
+            .unwrap_or_default()
+    }
+
+    /// The numbers of the release, upstream version then ABI
+    fn numbers(&self) -> Vec<u64> {
+        [self.upstream(), self.abi()].concat()
+    }
+}
+
+impl VersionParts {
+    fn parse(release: &str) -> Option<Self> {
+        let mut upstream = vec![];
+        let mut abi = vec![];
+        let mut rest = release;
+        let mut separator = None;
+        loop {
+            let end = rest.find(['.', '-']).unwrap_or(rest.len());
+            let word = &rest[..end];
+            if word.is_empty() || !word.bytes().all(|v| v.is_ascii_digit()) {
+                break;
+            }
+            let Ok(number) = word.parse() else {
+                break;
+            };
+            if separator == Some('-') || !abi.is_empty() {
+                abi.push(number);
+            } else {
+                upstream.push(number);
+            }
+            separator = rest[end..].chars().next();
+            rest = rest.get(end + 1..).unwrap_or_default();
+        }
+        if upstream.len() < 2 {
+            return None;
+        }
+        let (first, after) = rest.split_once('-').unwrap_or((rest, ""));
+        if let Some((tail, revision)) = abi.is_empty().then(|| leap_tail(first)).flatten() {
+            return Some(Self {
+                upstream,
+                abi: revision,
+                tail: tail.to_string(),
+                flavor: after.to_string(),
+            });
+        }
+        let (tail, flavor) = if separator == Some('.') || first.contains('.') {
+            (first, after)
+        } else {
+            ("", rest)
+        };
+        Some(Self {
+            upstream,
+            abi,
+            tail: tail.to_string(),
+            flavor: flavor.to_string(),
+        })
+    }
+
+    /// The numbers after `major.minor`
+    fn point_numbers(&self) -> Vec<u64> {
+        [&self.upstream[2..], &self.abi[..]].concat()
+    }
+}
+
+impl FromStr for KernelVersion {
+    type Err = Infallible;
+
+    fn from_str(release: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(Self::new(release))
+    }
+}
+
+impl Display for KernelVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.release)
+    }
+}
+
+impl PartialOrd for KernelVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KernelVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_parts = match (&self.parts, &other.parts) {
+            (Some(a), Some(b)) => self
+                .numbers()
+                .cmp(&other.numbers())
+                .then_with(|| natural_cmp(&a.tail, &b.tail))
+                .then_with(|| natural_cmp(&a.flavor, &b.flavor)),
+            // 无法解析的版本排在最后
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        by_parts.then_with(|| self.release.cmp(&other.release))
+    }
+}
+
+/// Prefix of the first word after the upstream version of openSUSE Leap kernels, followed by
+/// the release they're built for, e.g. `lp151` for Leap 15.1
+const LEAP_TAIL_PREFIX: &str = "lp";
+
+/// Split a word like `lp151.28.36` of an openSUSE Leap release into its tail `lp151` and the
+/// revision `[28, 36]` that follows, see [`KernelVersion`]; `None` for other words
+fn leap_tail(word: &str) -> Option<(&str, Vec<u64>)> {
+    let (tail, revision) = word.split_once('.')?;
+    let number = |v: &str| {
+        (!v.is_empty() && v.bytes().all(|v| v.is_ascii_digit()))
+            .then(|| v.parse().ok())
+            .flatten()
+    };
+    number(tail.strip_prefix(LEAP_TAIL_PREFIX)?)?;
+    let revision = revision
+        .split('.')
+        .map(number)
+        .collect::<Option<Vec<_>>>()?;
+    Some((tail, revision))
+}
+
+/// Compare strings with runs of digits compared as numbers, so `deb11.13` is above `deb11.6`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let digits = |v: &[u8]| v.iter().take_while(|v| v.is_ascii_digit()).count();
+                let (len_a, len_b) = (digits(a), digits(b));
+                // 去掉前导零后，位数多者大，位数相同时逐位比较
+                let trim = |v: &[u8]| {
+                    let zeros = v.iter().take_while(|v| **v == b'0').count();
+                    v[zeros..].to_vec()
+                };
+                let (x, y) = (trim(&a[..len_a]), trim(&b[..len_b]));
+                let order = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                (a, b) = (&a[len_a..], &b[len_b..]);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+                (a, b) = (&a[1..], &b[1..]);
+            }
+        }
+    }
+}
+
+/// How strictly the btf of an archive must match the running kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum MatchPolicy {
+    /// Only the btf of the exact kernel release
+    #[default]
+    Exact,
+    /// Fall back to the nearest point release with the same major.minor and flavor
+    SameFlavorNearest,
+    /// Like `SameFlavorNearest`, then allow other flavors (e.g. `-azure` for `-generic`),
+    /// and as a last resort the exact release under another version of the distro
+    BestEffort,
+}
+
+impl MatchPolicy {
+    /// The policy numbered `value` in the C API: 0 for `Exact`, 1 for `SameFlavorNearest`, 2 for `BestEffort`
+    pub fn from_raw(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(MatchPolicy::Exact),
+            1 => Some(MatchPolicy::SameFlavorNearest),
+            2 => Some(MatchPolicy::BestEffort),
+            _ => None,
+        }
+    }
+}
+
+/// Pick the release among `available` closest to `release`, for when `release` itself isn't available
+///
+/// Only releases with the same major.minor qualify, and with the same flavor unless
+/// `policy` is `BestEffort`, which tries other flavors if none of the same flavor is
+/// available. The nearest lower release is preferred, since the btf of an older point
+/// release is the most likely to work, then the nearest higher one. Ties are broken by
+/// the smaller string, so the choice doesn't depend on the order of `available`.
+///
+/// `Exact` only accepts `release` itself.
+pub fn nearest_release<'a>(
+    release: &str,
+    available: &[&'a str],
+    policy: MatchPolicy,
+) -> Option<&'a str> {
+    match policy {
+        MatchPolicy::Exact => available.iter().find(|v| **v == release).copied(),
+        MatchPolicy::SameFlavorNearest => nearest_of_flavor(release, available, true),
+        MatchPolicy::BestEffort => nearest_of_flavor(release, available, true)
+            .or_else(|| nearest_of_flavor(release, available, false)),
+    }
+}
+
+fn nearest_of_flavor<'a>(
+    release: &str,
+    available: &[&'a str],
+    same_flavor: bool,
+) -> Option<&'a str> {
+    let current = KernelVersion::new(release);
+    let mut lower: Option<(u64, Vec<u64>, &str)> = None;
+    let mut higher: Option<(u64, Vec<u64>, &str)> = None;
+    for name in available {
+        let parsed = KernelVersion::new(*name);
+        let Some(distance) = parsed.distance(&current) else {
+            continue;
+        };
+        if same_flavor && (parsed.tail(), parsed.flavor()) != (current.tail(), current.flavor()) {
+            continue;
+        }
+        let numbers = parsed.numbers();
+        let (slot, closer) = match numbers.cmp(&current.numbers()) {
+            Ordering::Less => (&mut lower, Ordering::Greater),
+            Ordering::Greater => (&mut higher, Ordering::Less),
+            Ordering::Equal => return Some(name),
+        };
+        // 距离相同时取数字更接近者，再取较小的字符串
+        let replace = match slot {
+            Some((best_distance, best_numbers, best_name)) => {
+                let by_numbers = numbers.cmp(best_numbers);
+                distance
+                    .cmp(best_distance)
+                    .then(if closer.is_gt() {
+                        by_numbers.reverse()
+                    } else {
+                        by_numbers
+                    })
+                    .then_with(|| name.cmp(best_name))
+                    .is_lt()
+            }
+            None => true,
+        };
+        if replace {
+            *slot = Some((distance, numbers, name));
+        }
+    }
+    lower.or(higher).map(|(_, _, name)| name)
+}
+
+/// Why a release was picked as a candidate for the running kernel, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CandidateReason {
+    /// The exact release of the running kernel
+    Exact,
+    /// An older point release with the same major.minor and flavor
+    LowerRevision,
+    /// A newer point release with the same major.minor and flavor
+    HigherRevision,
+}
+
+/// Rank the releases among `available` that may be used for `release`, best first
+///
+/// The exact release comes first, then the lower point releases from the nearest one
+/// down, then the higher ones from the nearest one up. Only releases with the same
+/// major.minor, tail and flavor are ranked, see [`KernelVersion`]; ties are broken by the
+/// smaller string.
+pub fn rank_releases<'a>(release: &str, available: &[&'a str]) -> Vec<(&'a str, CandidateReason)> {
+    let current = KernelVersion::new(release);
+    if !current.is_parsed() {
+        return available
+            .iter()
+            .filter(|v| **v == release)
+            .map(|v| (*v, CandidateReason::Exact))
+            .collect();
+    }
+    let mut ranked = available
+        .iter()
+        .filter_map(|name| {
+            let parsed = KernelVersion::new(*name);
+            parsed.distance(&current)?;
+            if (parsed.tail(), parsed.flavor()) != (current.tail(), current.flavor()) {
+                return None;
+            }
+            let numbers = parsed.numbers();
+            let reason = match numbers.cmp(&current.numbers()) {
+                Ordering::Equal => CandidateReason::Exact,
+                Ordering::Less => CandidateReason::LowerRevision,
+                Ordering::Greater => CandidateReason::HigherRevision,
+            };
+            Some((reason, numbers, *name))
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by(
+        |(reason_a, numbers_a, name_a), (reason_b, numbers_b, name_b)| {
+            let by_distance = match reason_a {
+                // 较低的版本中越新越接近
+                CandidateReason::LowerRevision => numbers_b.cmp(numbers_a),
+                _ => numbers_a.cmp(numbers_b),
+            };
+            reason_a
+                .cmp(reason_b)
+                .then(by_distance)
+                .then(name_a.cmp(name_b))
+        },
+    );
+    ranked
+        .into_iter()
+        .map(|(reason, _, name)| (name, reason))
+        .collect()
+}
+
+/// Words that may follow the first word of a flavor in a release of a distro kernel, like
+/// `amd64` in Debian's `5.10.0-23-cloud-amd64` or `64k` in Ubuntu's `6.5.0-14-generic-64k`
+const FLAVOR_WORDS: &[&str] = &[
+    "amd64", "arm64", "i386", "686", "pae", "armmp", "lpae", "64k", "ppc64el", "s390x", "riscv64",
+    "fde", "rpi", "v7", "v7l", "v8", "2712",
+];
+
+/// `release` without the local version a custom build of a distro kernel appends to it, if it has one
+///
+/// Kernels rebuilt with `CONFIG_LOCALVERSION` (or from a dirty tree) report e.g.
+/// `5.4.0-40-generic-mycorp1` or `5.10.0-23-amd64+` for the distro's `5.4.0-40-generic`
+/// and `5.10.0-23-amd64`, whose btf describes the same types. Trailing `+`s are removed,
+/// then the words of the flavor after the first one, from the first that is neither one
+/// distros use there (see `FLAVOR_WORDS`) nor a number, like the package release of Arch's
+/// `6.5.9-arch2-1`. A flavor of a single word, like SUSE's `default` or a `custom`
+/// without a distro flavor before it, is kept, as it can't be told from a real one; so is
+/// the `default` or `preempt` after the `lp151.28.36` of openSUSE Leap.
+pub fn strip_local_version(release: &str) -> Option<&str> {
+    let trimmed = release.trim_end_matches('+');
+    let parsed = KernelRelease::parse(trimmed)?;
+    let flavor_start = trimmed.len() - parsed.flavor.len();
+    let mut end = trimmed.len();
+    let mut words = parsed.flavor.split('-');
+    if let Some(first) = words.next() {
+        let mut offset = flavor_start + first.len();
+        // openSUSE Leap 的 lp151.28.36 之后才是 flavor
+        if let Some(flavor) = leap_tail(first).and_then(|_| words.next()) {
+            offset += flavor.len() + 1;
+        }
+        for word in words {
+            if !FLAVOR_WORDS.contains(&word) && !word.bytes().all(|v| v.is_ascii_digit()) {
+                end = offset;
+                break;
+            }
+            offset += word.len() + 1;
+        }
+    }
+    let stripped = &trimmed[..end];
+    (stripped != release).then_some(stripped)
+}
+
+/// The forms `release` may be stored under in an archive, the verbatim one first
+///
+/// RHEL-likes end the release with the architecture, like `4.18.0-425.3.1.el8.x86_64`,
+/// which some archives drop since the directory already names it. If `release` ends with
+/// `.<arch>` for one of `arches`, the release without it follows. Only that one suffix is
+/// removed, so `4.18.0-425.13.1.el8_7.x86_64` becomes `4.18.0-425.13.1.el8_7`. Flavors
+/// naming the architecture after a `-`, like Debian's `5.10.0-23-amd64`, are part of the
+/// release and kept. The forms of the release without its local version, see
+/// [`strip_local_version`], come last.
+pub fn release_variants<'a>(release: &'a str, arches: &[&str]) -> Vec<&'a str> {
+    let mut variants = arch_variants(release, arches);
+    for variant in strip_local_version(release)
+        .map(|v| arch_variants(v, arches))
+        .unwrap_or_default()
+    {
+        if !variants.contains(&variant) {
+            variants.push(variant);
+        }
+    }
+    variants
+}
+
+/// Whether `candidate`, one of the [`release_variants`] of `release`, only names it once
+/// its local version is stripped
+pub fn is_local_version_stripped(release: &str, candidate: &str, arches: &[&str]) -> bool {
+    !arch_variants(release, arches).contains(&candidate)
+        && release_variants(release, arches).contains(&candidate)
+}
+
+/// `release`, then `release` without its trailing `.<arch>`, see [`release_variants`]
+fn arch_variants<'a>(release: &'a str, arches: &[&str]) -> Vec<&'a str> {
+    let stripped = arches.iter().find_map(|arch| {
+        release
+            .strip_suffix(arch)
+            .and_then(|v| v.strip_suffix('.'))
+            .filter(|v| !v.is_empty())
+    });
+    [Some(release), stripped].into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UBUNTU: &[&str] = &[
+        "5.4.0-26-generic",
+        "5.4.0-140-generic",
+        "5.4.0-144-generic",
+        "5.4.0-150-generic",
+        "5.4.0-152-generic",
+        "5.4.0-147-azure",
+        "5.8.0-63-generic",
+    ];
+
+    #[test]
+    fn nearest_lower_revision_is_preferred() {
+        assert_eq!(
+            nearest_release("5.4.0-148-generic", UBUNTU, MatchPolicy::SameFlavorNearest),
+            Some("5.4.0-144-generic")
+        );
+        // 比所有可用版本都旧时，取最接近的较新版本
+        assert_eq!(
+            nearest_release("5.4.0-10-generic", UBUNTU, MatchPolicy::SameFlavorNearest),
+            Some("5.4.0-26-generic")
+        );
+    }
+
+    #[test]
+    fn choice_doesnt_depend_on_the_order() {
+        let mut reversed = UBUNTU.to_vec();
+        reversed.reverse();
+        for release in [
+            "5.4.0-148-generic",
+            "5.4.0-142-generic",
+            "5.4.0-151-generic",
+        ] {
+            assert_eq!(
+                nearest_release(release, UBUNTU, MatchPolicy::SameFlavorNearest),
+                nearest_release(release, &reversed, MatchPolicy::SameFlavorNearest),
+            );
+        }
+        // 与 140 和 144 距离相同时，较低的版本优先
+        assert_eq!(
+            nearest_release("5.4.0-142-generic", UBUNTU, MatchPolicy::SameFlavorNearest),
+            Some("5.4.0-140-generic")
+        );
+    }
+
+    #[test]
+    fn patch_level_is_farther_than_the_abi() {
+        let available = ["5.4.1-1-generic", "5.4.0-100-generic"];
+        assert_eq!(
+            nearest_release(
+                "5.4.0-148-generic",
+                &available,
+                MatchPolicy::SameFlavorNearest
+            ),
+            Some("5.4.0-100-generic")
+        );
+        assert_eq!(
+            nearest_release(
+                "5.4.2-1-generic",
+                &available,
+                MatchPolicy::SameFlavorNearest
+            ),
+            Some("5.4.1-1-generic")
+        );
+    }
+
+    #[test]
+    fn no_release_of_the_same_major_minor_is_no_match() {
+        for release in ["5.5.0-10-generic", "4.4.0-148-generic", "5.15.0-76-generic"] {
+            assert_eq!(
+                nearest_release(release, UBUNTU, MatchPolicy::SameFlavorNearest),
+                None
+            );
+            assert_eq!(
+                nearest_release(release, UBUNTU, MatchPolicy::BestEffort),
+                None
+            );
+        }
+        // 5.4 与 5.40 不是同一系列
+        assert_eq!(
+            nearest_release("5.40.0-1-generic", UBUNTU, MatchPolicy::SameFlavorNearest),
+            None
+        );
+    }
+
+    #[test]
+    fn unparsable_releases_are_skipped() {
+        let available = ["vmlinux", "5.4", "5-4-0-144-generic", "5.4.0-144-generic"];
+        assert_eq!(
+            nearest_release(
+                "5.4.0-148-generic",
+                &available,
+                MatchPolicy::SameFlavorNearest
+            ),
+            Some("5.4.0-144-generic")
+        );
+        assert_eq!(
+            nearest_release("custom", &available, MatchPolicy::SameFlavorNearest),
+            None
+        );
+    }
+
+    #[test]
+    fn best_effort_crosses_flavors_only_without_the_same_one() {
+        assert_eq!(
+            nearest_release("5.4.0-146-azure", UBUNTU, MatchPolicy::SameFlavorNearest),
+            Some("5.4.0-147-azure")
+        );
+        assert_eq!(
+            nearest_release("5.4.0-148-aws", UBUNTU, MatchPolicy::SameFlavorNearest),
+            None
+        );
+        // 没有同一 flavor 时才取其他 flavor 中最接近的版本
+        assert_eq!(
+            nearest_release("5.4.0-148-aws", UBUNTU, MatchPolicy::BestEffort),
+            Some("5.4.0-147-azure")
+        );
+        assert_eq!(
+            nearest_release("5.4.0-148-generic", UBUNTU, MatchPolicy::BestEffort),
+            Some("5.4.0-144-generic")
+        );
+    }
+
+    #[test]
+    fn exact_policy_only_takes_the_release_itself() {
+        assert_eq!(
+            nearest_release("5.4.0-144-generic", UBUNTU, MatchPolicy::Exact),
+            Some("5.4.0-144-generic")
+        );
+        assert_eq!(
+            nearest_release("5.4.0-145-generic", UBUNTU, MatchPolicy::Exact),
+            None
+        );
+    }
+
+    #[test]
+    fn policies_are_numbered_as_in_the_c_api() {
+        assert_eq!(MatchPolicy::from_raw(0), Some(MatchPolicy::Exact));
+        assert_eq!(
+            MatchPolicy::from_raw(1),
+            Some(MatchPolicy::SameFlavorNearest)
+        );
+        assert_eq!(MatchPolicy::from_raw(2), Some(MatchPolicy::BestEffort));
+        for value in [-1, 3, i32::MAX] {
+            assert_eq!(MatchPolicy::from_raw(value), None);
+        }
+        assert_eq!(MatchPolicy::default(), MatchPolicy::Exact);
+    }
+
+    #[test]
+    fn candidates_are_ranked_exact_then_lower_then_higher() {
+        let available = [
+            "5.15.0-91-generic",
+            "5.15.0-88-generic",
+            "5.15.0-1051-azure",
+            "5.15.0-94-generic",
+            "5.15.0-86-generic",
+            "5.15.0-91-lowlatency",
+            "5.15.0-101-generic",
+            "5.19.0-50-generic",
+            "6.2.0-39-generic",
+        ];
+        assert_eq!(
+            rank_releases("5.15.0-91-generic", &available),
+            [
+                ("5.15.0-91-generic", CandidateReason::Exact),
+                ("5.15.0-88-generic", CandidateReason::LowerRevision),
+                ("5.15.0-86-generic", CandidateReason::LowerRevision),
+                ("5.15.0-94-generic", CandidateReason::HigherRevision),
+                ("5.15.0-101-generic", CandidateReason::HigherRevision),
+            ]
+        );
+        // 版本号按数值而不是字符串比较
+        assert_eq!(
+            rank_releases("5.15.0-99-generic", &available),
+            [
+                ("5.15.0-94-generic", CandidateReason::LowerRevision),
+                ("5.15.0-91-generic", CandidateReason::LowerRevision),
+                ("5.15.0-88-generic", CandidateReason::LowerRevision),
+                ("5.15.0-86-generic", CandidateReason::LowerRevision),
+                ("5.15.0-101-generic", CandidateReason::HigherRevision),
+            ]
+        );
+        assert!(rank_releases("5.15.0-91-aws", &available).is_empty());
+        assert!(rank_releases("6.5.0-14-generic", &available).is_empty());
+    }
+
+    #[test]
+    fn suse_releases_are_ranked_within_their_flavor_and_leap_release() {
+        let available = [
+            "5.14.21-150400.24.60-default",
+            "5.14.21-150400.24.66-default",
+            "5.14.21-150400.24.63-preempt",
+            "4.12.14-lp151.28.32-default",
+            "4.12.14-lp151.28.40-default",
+            "4.12.14-lp152.28.36-default",
+            "4.12.14-lp151.28.36-preempt",
+        ];
+        // SLES 的 default 与 preempt 是不同的 flavor
+        assert_eq!(
+            rank_releases("5.14.21-150400.24.63-default", &available),
+            [
+                (
+                    "5.14.21-150400.24.60-default",
+                    CandidateReason::LowerRevision
+                ),
+                (
+                    "5.14.21-150400.24.66-default",
+                    CandidateReason::HigherRevision
+                ),
+            ]
+        );
+        // lp152 是 Leap 15.2 的内核，不与 15.1 的混用
+        assert_eq!(
+            rank_releases("4.12.14-lp151.28.36-default", &available),
+            [
+                (
+                    "4.12.14-lp151.28.32-default",
+                    CandidateReason::LowerRevision
+                ),
+                (
+                    "4.12.14-lp151.28.40-default",
+                    CandidateReason::HigherRevision
+                ),
+            ]
+        );
+        assert_eq!(
+            rank_releases("4.12.14-lp151.28.36-preempt", &available),
+            [("4.12.14-lp151.28.36-preempt", CandidateReason::Exact)]
+        );
+    }
+
+    #[test]
+    fn unparsable_release_only_ranks_itself() {
+        assert_eq!(
+            rank_releases("custom", &["custom", "5.4.0-26-generic"]),
+            [("custom", CandidateReason::Exact)]
+        );
+    }
+
+    #[test]
+    fn rhel_releases_are_also_tried_without_their_arch() {
+        let x86 = ["x86_64", "amd64"];
+        assert_eq!(
+            release_variants("3.10.0-1160.el7.x86_64", &x86),
+            ["3.10.0-1160.el7.x86_64", "3.10.0-1160.el7"]
+        );
+        // 只去掉一次架构后缀，el8_7 保留
+        assert_eq!(
+            release_variants("4.18.0-425.13.1.el8_7.x86_64", &x86),
+            ["4.18.0-425.13.1.el8_7.x86_64", "4.18.0-425.13.1.el8_7"]
+        );
+        assert_eq!(
+            release_variants("4.18.0-348.el8.aarch64", &["arm64", "aarch64"]),
+            ["4.18.0-348.el8.aarch64", "4.18.0-348.el8"]
+        );
+        // 已经去掉架构的，以及 `-` 之后的 flavor 不变
+        assert_eq!(
+            release_variants("4.18.0-425.13.1.el8_7", &x86),
+            ["4.18.0-425.13.1.el8_7"]
+        );
+        assert_eq!(
+            release_variants("5.10.0-23-amd64", &x86),
+            ["5.10.0-23-amd64"]
+        );
+        // 其他架构的后缀不去掉
+        assert_eq!(
+            release_variants("4.18.0-348.el8.aarch64", &x86),
+            ["4.18.0-348.el8.aarch64"]
+        );
+    }
+
+    #[test]
+    fn local_versions_of_custom_builds_are_stripped() {
+        for (release, stripped) in [
+            ("5.4.0-40-generic-mycorp1", "5.4.0-40-generic"),
+            ("5.10.0-23-amd64+", "5.10.0-23-amd64"),
+            ("5.10.0-23-cloud-amd64-custom", "5.10.0-23-cloud-amd64"),
+            ("6.5.0-14-generic-64k-mycorp", "6.5.0-14-generic-64k"),
+            ("4.19.0-21-686-pae-dirty+", "4.19.0-21-686-pae"),
+            (
+                "5.14.21-150400.24.46-default-custom",
+                "5.14.21-150400.24.46-default",
+            ),
+            ("5.4.0-40-generic++", "5.4.0-40-generic"),
+            (
+                "4.12.14-lp151.28.36-default-custom",
+                "4.12.14-lp151.28.36-default",
+            ),
+            (
+                "4.12.14-lp151.28.36-preempt-mycorp1+",
+                "4.12.14-lp151.28.36-preempt",
+            ),
+        ] {
+            assert_eq!(strip_local_version(release), Some(stripped), "{release}");
+        }
+    }
+
+    #[test]
+    fn flavors_are_not_taken_for_local_versions() {
+        for release in [
+            "5.4.0-40-generic",
+            "5.10.0-23-cloud-amd64",
+            "6.5.0-14-generic-64k",
+            "4.19.0-21-686-pae",
+            // SUSE 的 default 与单独的 custom 无法和真正的 flavor 区分，保留
+            "5.14.21-150400.24.46-default",
+            "5.4.0-40-custom",
+            // openSUSE Leap 的 flavor 在 lp151.28.36 之后
+            "4.12.14-lp151.28.36-default",
+            "4.12.14-lp151.28.36-preempt",
+            // Arch 的包版本号是数字
+            "6.5.9-arch2-1",
+            "3.10.0-1160.el7.x86_64",
+        ] {
+            assert_eq!(strip_local_version(release), None, "{release}");
+        }
+        assert_eq!(strip_local_version("not a release"), None);
+    }
+
+    #[test]
+    fn stripped_releases_are_tried_after_the_verbatim_one() {
+        let x86 = ["x86_64", "amd64"];
+        assert_eq!(
+            release_variants("5.4.0-40-generic-mycorp1", &x86),
+            ["5.4.0-40-generic-mycorp1", "5.4.0-40-generic"]
+        );
+        assert!(is_local_version_stripped(
+            "5.4.0-40-generic-mycorp1",
+            "5.4.0-40-generic",
+            &x86
+        ));
+        // 原样的版本不算去掉了本地版本
+        assert!(!is_local_version_stripped(
+            "5.4.0-40-generic-mycorp1",
+            "5.4.0-40-generic-mycorp1",
+            &x86
+        ));
+        assert!(!is_local_version_stripped(
+            "3.10.0-1160.el7.x86_64",
+            "3.10.0-1160.el7",
+            &x86
+        ));
+        assert!(!is_local_version_stripped(
+            "5.4.0-40-generic-mycorp1",
+            "5.4.0-42-generic",
+            &x86
+        ));
+    }
+
+    #[test]
+    fn real_world_releases_are_parsed() {
+        type Parts<'a> = (
+            Option<u64>,
+            Option<u64>,
+            Option<u64>,
+            &'a [u64],
+            &'a str,
+            &'a str,
+        );
+        let table: &[(&str, Parts)] = &[
+            // Ubuntu
+            (
+                "5.4.0-148-generic",
+                (Some(5), Some(4), Some(0), &[148], "", "generic"),
+            ),
+            (
+                "5.15.0-1034-aws",
+                (Some(5), Some(15), Some(0), &[1034], "", "aws"),
+            ),
+            (
+                "4.15.0-20-generic",
+                (Some(4), Some(15), Some(0), &[20], "", "generic"),
+            ),
+            (
+                "5.19.0-50-generic-64k",
+                (Some(5), Some(19), Some(0), &[50], "", "generic-64k"),
+            ),
+            // Debian
+            (
+                "6.1.0-0.deb11.6-amd64",
+                (Some(6), Some(1), Some(0), &[0], "deb11.6", "amd64"),
+            ),
+            (
+                "6.1.0-18-cloud-amd64",
+                (Some(6), Some(1), Some(0), &[18], "", "cloud-amd64"),
+            ),
+            (
+                "4.19.0-25-arm64",
+                (Some(4), Some(19), Some(0), &[25], "", "arm64"),
+            ),
+            // RHEL、CentOS、Oracle Linux
+            (
+                "3.10.0-1160.el7.x86_64",
+                (Some(3), Some(10), Some(0), &[1160], "el7.x86_64", ""),
+            ),
+            (
+                "4.18.0-425.3.1.el8.x86_64",
+                (Some(4), Some(18), Some(0), &[425, 3, 1], "el8.x86_64", ""),
+            ),
+            (
+                "5.14.0-284.11.1.el9_2.aarch64",
+                (
+                    Some(5),
+                    Some(14),
+                    Some(0),
+                    &[284, 11, 1],
+                    "el9_2.aarch64",
+                    "",
+                ),
+            ),
+            (
+                "5.15.0-101.103.2.1.el8uek.x86_64",
+                (
+                    Some(5),
+                    Some(15),
+                    Some(0),
+                    &[101, 103, 2, 1],
+                    "el8uek.x86_64",
+                    "",
+                ),
+            ),
+            // SUSE、openSUSE Leap
+            (
+                "5.14.21-150400.24.46-default",
+                (
+                    Some(5),
+                    Some(14),
+                    Some(21),
+                    &[150400, 24, 46],
+                    "",
+                    "default",
+                ),
+            ),
+            (
+                "4.12.14-lp151.28.36-default",
+                (Some(4), Some(12), Some(14), &[28, 36], "lp151", "default"),
+            ),
+            // Amazon Linux
+            (
+                "5.10.184-175.731.amzn2.x86_64",
+                (
+                    Some(5),
+                    Some(10),
+                    Some(184),
+                    &[175, 731],
+                    "amzn2.x86_64",
+                    "",
+                ),
+            ),
+            (
+                "6.1.41-63.114.amzn2023.x86_64",
+                (
+                    Some(6),
+                    Some(1),
+                    Some(41),
+                    &[63, 114],
+                    "amzn2023.x86_64",
+                    "",
+                ),
+            ),
+            // 上游和滚动发行版
+            ("6.6-rc1", (Some(6), Some(6), None, &[], "", "rc1")),
+            (
+                "6.5.3-arch1-1",
+                (Some(6), Some(5), Some(3), &[], "", "arch1-1"),
+            ),
+            ("6.7.0", (Some(6), Some(7), Some(0), &[], "", "")),
+        ];
+        for (release, expected) in table {
+            let version: KernelVersion = release.parse().unwrap();
+            assert!(version.is_parsed(), "{release}");
+            assert_eq!(
+                (
+                    version.major(),
+                    version.minor(),
+                    version.patch(),
+                    version.abi(),
+                    version.tail(),
+                    version.flavor()
+                ),
+                *expected,
+                "{release}"
+            );
+            assert_eq!(version.to_string(), *release);
+        }
+    }
+
+    #[test]
+    fn unparsable_releases_are_kept_as_they_are() {
+        for release in ["", "linux", "6", "v5.4.0-148-generic", "5.-4"] {
+            let version = KernelVersion::new(release);
+            assert!(!version.is_parsed(), "{release}");
+            assert_eq!(version.as_str(), release);
+            assert_eq!(
+                (
+                    version.major(),
+                    version.abi(),
+                    version.tail(),
+                    version.flavor()
+                ),
+                (None, &[][..], "", "")
+            );
+        }
+    }
+
+    #[test]
+    fn versions_are_ordered_numerically_then_by_tail_and_flavor() {
+        let ordered = [
+            "3.10.0-1160.el7.x86_64",
+            "4.18.0-425.3.1.el8.x86_64",
+            "4.18.0-425.10.1.el8.x86_64",
+            "4.18.0-477.10.1.el8_8.x86_64",
+            "5.4.0-99-generic",
+            "5.4.0-148-azure",
+            "5.4.0-148-generic",
+            "5.10.0-26-amd64",
+            "5.10.0-26-arm64",
+            "6.1.0-0.deb11.6-amd64",
+            "6.1.0-0.deb11.13-amd64",
+            "6.1.0-18-amd64",
+            "6.6-rc1",
+            "6.6.1-arch1-1",
+            // 无法解析的排在最后，按字符串排序
+            "linux",
+            "v5.4.0",
+        ];
+        let mut shuffled = ordered.map(KernelVersion::new);
+        shuffled.reverse();
+        shuffled.swap(3, 11);
+        shuffled.sort();
+        assert_eq!(shuffled.map(|v| v.to_string()), ordered);
+        // 数字相同，只有写法不同的版本也不相等
+        let (a, b) = (
+            KernelVersion::new("5.4.0-148-generic"),
+            KernelVersion::new("5.4.00-148-generic"),
+        );
+        assert_ne!(a.cmp(&b), Ordering::Equal);
+        assert_eq!(a.cmp(&a.clone()), Ordering::Equal);
+    }
+
+    #[test]
+    fn distance_grows_with_the_level_of_the_first_difference() {
+        let distance = |a: &str, b: &str| {
+            let (a, b) = (KernelVersion::new(a), KernelVersion::new(b));
+            assert_eq!(a.distance(&b), b.distance(&a));
+            a.distance(&b)
+        };
+        assert_eq!(distance("5.4.0-148-generic", "5.4.0-148-azure"), Some(0));
+        let near = distance("5.4.0-148-generic", "5.4.0-144-generic").unwrap();
+        let far = distance("5.4.0-148-generic", "5.4.0-26-generic").unwrap();
+        let patch = distance("5.4.0-148-generic", "5.4.1-148-generic").unwrap();
+        assert!(0 < near && near < far && far < patch);
+        // ABI 中越靠后的数字，差异越小
+        let minor = distance("4.18.0-425.3.1.el8.x86_64", "4.18.0-425.10.1.el8.x86_64").unwrap();
+        let major = distance("4.18.0-425.3.1.el8.x86_64", "4.18.0-426.3.1.el8.x86_64").unwrap();
+        assert!(minor < major);
+        // 缺少的数字按 0 计
+        assert_eq!(distance("6.6", "6.6.0-0"), Some(0));
+        for (a, b) in [
+            ("5.4.0-148-generic", "5.40.0-148-generic"),
+            ("5.4.0-148-generic", "6.4.0-148-generic"),
+            ("5.4.0-148-generic", "linux"),
+        ] {
+            assert_eq!(distance(a, b), None, "{a} {b}");
+        }
+    }
+}
@@ -0,0 +1,446 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Keeping the files written from an archive inside the directory they're written to.
+//!
+//! Entry paths come from the archive, and the components of cache paths from os-release,
+//! so neither is trusted: a path is rejected if it is absolute or has a `..` component,
+//! and nothing is created through a symlink already in the destination.
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    compression::tar_entries,
+    sparse::{entry_path, is_file_entry, read_entry},
+    Error, Result,
+};
+
+/// `path` without its `.` components, if it stays below the directory it's relative to
+///
+/// Fails with [`Error::UnsafePath`] if `path` is absolute, has a `..` component, or is
+/// empty once the `.` components are dropped.
+pub fn sanitize_entry_path(path: &Path) -> Result<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(v) => sanitized.push(v),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(Error::UnsafePath(path.display().to_string()));
+            }
+        }
+    }
+    if sanitized.as_os_str().is_empty() {
+        return Err(Error::UnsafePath(path.display().to_string()));
+    }
+    Ok(sanitized)
+}
+
+/// Create the directory `relative` under `dest`, and its parents, without following symlinks
+///
+/// `dest` itself must exist; it may be a symlink, since the caller picked it. Below it, an
+/// existing symlink fails with [`Error::UnsafePath`], even if it points to a directory
+/// inside `dest`.
+pub fn create_dir_within(dest: &Path, relative: &Path) -> Result<PathBuf> {
+    let relative = sanitize_entry_path(relative)?;
+    let mut path = dest.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match path.symlink_metadata() {
+            Ok(v) if v.is_dir() => continue,
+            Ok(v) if v.is_symlink() => return Err(Error::UnsafePath(path.display().to_string())),
+            _ => {}
+        }
+        match std::fs::create_dir(&path) {
+            // 并发创建时，确认对方创建的是目录而不是链接
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                if !path.symlink_metadata().is_ok_and(|v| v.is_dir()) {
+                    return Err(Error::UnsafePath(path.display().to_string()));
+                }
+            }
+            Err(e) => return Err(Error::FileWriteError(path.display().to_string(), e)),
+            Ok(()) => {}
+        }
+    }
+    Ok(path)
+}
+
+/// Where the file `relative` goes under `dest`, with its parent directories created
+///
+/// See [`create_dir_within`]. Fails with [`Error::UnsafePath`] if the file itself is an
+/// existing symlink.
+pub fn prepare_file_within(dest: &Path, relative: &Path) -> Result<PathBuf> {
+    let relative = sanitize_entry_path(relative)?;
+    let dir = match relative.parent().filter(|v| !v.as_os_str().is_empty()) {
+        Some(parent) => create_dir_within(dest, parent)?,
+        None => dest.to_path_buf(),
+    };
+    let path = dir.join(relative.file_name().unwrap_or_default());
+    if path.symlink_metadata().is_ok_and(|v| v.is_symlink()) {
+        return Err(Error::UnsafePath(path.display().to_string()));
+    }
+    Ok(path)
+}
+
+/// Write `contents` to the file `relative` under `dest`, see [`prepare_file_within`]
+///
+/// The file is opened with `O_NOFOLLOW` on Linux, so a symlink put in its place meanwhile
+/// isn't followed either.
+pub fn write_file_within(dest: &Path, relative: &Path, contents: &[u8]) -> Result<PathBuf> {
+    let path = prepare_file_within(dest, relative)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(target_os = "linux")]
+    options.custom_flags(libc::O_NOFOLLOW);
+    options
+        .open(&path)
+        .and_then(|mut v| v.write_all(contents))
+        .map_err(|e| Error::FileWriteError(path.display().to_string(), e))?;
+    Ok(path)
+}
+
+/// Whether the symlink at `link`, relative to `dest`, pointing to `target` stays inside `dest`
+///
+/// Only relative targets are accepted, resolved lexically from the directory of the link.
+pub fn link_target_within(link: &Path, target: &Path) -> bool {
+    if target.is_absolute() {
+        return false;
+    }
+    let mut depth = 0usize;
+    let parent = link.parent().unwrap_or(Path::new(""));
+    for component in parent.components().chain(target.components()) {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(v) => depth = v,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Unpack `archive` into `dest`, refusing any entry that would land outside of it
+///
+/// Entries are checked with [`sanitize_entry_path`] and written with [`write_file_within`].
+/// Symlinks are created only if [`link_target_within`] accepts their target; hardlinks only
+/// if their target is a file already unpacked, reached without going through a symlink.
+/// Other entry types, like devices, are skipped. The first unsafe entry fails the whole
+/// unpack with [`Error::UnsafePath`], leaving what was unpacked so far.
+pub fn unpack_within<R: std::io::Read>(archive: &mut tar::Archive<R>, dest: &Path) -> Result<()> {
+    for entry in tar_entries(archive).map_err(Error::TarUnpackError)? {
+        let mut entry = entry.map_err(Error::TarUnpackError)?;
+        let path = entry_path(&mut entry).map_err(Error::TarUnpackError)?;
+        // 归档根目录自身（如 `./`）无需创建
+        if path.components().all(|v| v == Component::CurDir) {
+            continue;
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            create_dir_within(dest, &path)?;
+        } else if is_file_entry(entry_type) {
+            let contents = read_entry(&mut entry).map_err(Error::TarUnpackError)?;
+            write_file_within(dest, &path, &contents)?;
+        } else if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .map_err(Error::TarUnpackError)?
+                .ok_or_else(|| Error::UnsafePath(path.display().to_string()))?
+                .into_owned();
+            if entry_type.is_symlink() && !link_target_within(&sanitize_entry_path(&path)?, &target)
+            {
+                return Err(Error::UnsafePath(format!(
+                    "{} -> {}",
+                    path.display(),
+                    target.display()
+                )));
+            }
+            let link = prepare_file_within(dest, &path)?;
+            let linked = if entry_type.is_symlink() {
+                symlink(&target, &link)
+            } else {
+                std::fs::hard_link(existing_file_within(dest, &target)?, &link)
+            };
+            linked.map_err(|e| Error::FileWriteError(link.display().to_string(), e))?;
+        } else {
+            log_at!(Warn, "Skipped {}, of an unsupported type", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// The file `relative` under `dest`, if no component of it is a symlink
+fn existing_file_within(dest: &Path, relative: &Path) -> Result<PathBuf> {
+    let relative = sanitize_entry_path(relative)?;
+    let mut path = dest.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        if path.symlink_metadata().is_ok_and(|v| v.is_symlink()) {
+            return Err(Error::UnsafePath(path.display().to_string()));
+        }
+    }
+    Ok(path)
+}
+
+/// Create the symlink `link` to `target`
+#[cfg(target_os = "linux")]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Symlinks of archives are only recreated on Linux
+#[cfg(not(target_os = "linux"))]
+fn symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::fixture::{minimal_valid_btf, FixtureArchive};
+
+    /// Unpack `fixture` into `dest`
+    fn unpack(fixture: FixtureArchive, dest: &Path) -> Result<()> {
+        let tar = fixture.tar();
+        unpack_within(&mut tar::Archive::new(tar.as_slice()), dest)
+    }
+
+    /// A destination `dest` inside a directory of its own, to see what lands next to it
+    fn destination() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("dest");
+        std::fs::create_dir(&dest).unwrap();
+        (dir, dest)
+    }
+
+    /// Names of the entries of `dir`, sorted
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn relative_paths_are_normalized() {
+        assert_eq!(
+            sanitize_entry_path(Path::new("./a/./b.btf")).unwrap(),
+            Path::new("a/b.btf")
+        );
+        for unsafe_path in ["../a", "a/../../b", "a/../b", "/etc/passwd", ".", "", "./"] {
+            assert!(
+                matches!(
+                    sanitize_entry_path(Path::new(unsafe_path)),
+                    Err(Error::UnsafePath(_))
+                ),
+                "{unsafe_path}"
+            );
+        }
+    }
+
+    #[test]
+    fn link_targets_are_resolved_from_the_link() {
+        assert!(link_target_within(Path::new("a/b/link"), Path::new("../c")));
+        assert!(link_target_within(
+            Path::new("a/b/link"),
+            Path::new("../../c")
+        ));
+        assert!(link_target_within(Path::new("link"), Path::new("./c")));
+        assert!(!link_target_within(
+            Path::new("a/b/link"),
+            Path::new("../../../c")
+        ));
+        assert!(!link_target_within(Path::new("link"), Path::new("..")));
+        // 先下降再上升也不能越过目标目录
+        assert!(!link_target_within(
+            Path::new("link"),
+            Path::new("c/../../d")
+        ));
+        assert!(!link_target_within(Path::new("a/link"), Path::new("/a/c")));
+    }
+
+    #[test]
+    fn archive_is_unpacked_in_place() {
+        let (_dir, dest) = destination();
+        let fixture = FixtureArchive::new()
+            .dir("./")
+            .dir("./btfhub-archive")
+            .file("./btfhub-archive/a.btf", minimal_valid_btf())
+            .symlink("btfhub-archive/b.btf", "a.btf")
+            .hardlink("btfhub-archive/c.btf", "btfhub-archive/a.btf")
+            .file("package.json", b"{}".to_vec());
+        unpack(fixture, &dest).unwrap();
+        assert_eq!(names(&dest), ["btfhub-archive", "package.json"]);
+        let archive = dest.join("btfhub-archive");
+        assert_eq!(names(&archive), ["a.btf", "b.btf", "c.btf"]);
+        for name in ["a.btf", "b.btf", "c.btf"] {
+            assert_eq!(
+                std::fs::read(archive.join(name)).unwrap(),
+                minimal_valid_btf()
+            );
+        }
+        assert!(archive
+            .join("b.btf")
+            .symlink_metadata()
+            .unwrap()
+            .is_symlink());
+        // 解压的目标目录自身可以是符号链接，它是调用者选的
+        let (dir, dest) = destination();
+        let linked = dir.path().join("linked");
+        std::os::unix::fs::symlink(&dest, &linked).unwrap();
+        unpack(FixtureArchive::new().file("a/b", vec![1]), &linked).unwrap();
+        assert_eq!(std::fs::read(dest.join("a/b")).unwrap(), [1]);
+    }
+
+    #[test]
+    fn traversal_attempts_are_refused() {
+        let (dir, dest) = destination();
+        let absolute = dir.path().join("absolute.btf");
+        for path in [
+            "../escaped.btf",
+            "btfhub-archive/../../escaped.btf",
+            "./../escaped.btf",
+            absolute.to_str().unwrap(),
+        ] {
+            let fixture = FixtureArchive::new().file(path, minimal_valid_btf());
+            assert!(
+                matches!(unpack(fixture, &dest), Err(Error::UnsafePath(_))),
+                "{path}"
+            );
+            let fixture = FixtureArchive::new().dir(path);
+            assert!(
+                matches!(unpack(fixture, &dest), Err(Error::UnsafePath(_))),
+                "{path}"
+            );
+        }
+        assert_eq!(names(dir.path()), ["dest"]);
+        assert!(names(&dest).is_empty());
+    }
+
+    #[test]
+    fn links_leaving_the_destination_are_refused() {
+        let (dir, dest) = destination();
+        std::fs::write(dir.path().join("outside"), b"outside").unwrap();
+        for fixture in [
+            FixtureArchive::new().symlink("link", "../outside"),
+            FixtureArchive::new().symlink("a/link", "../../outside"),
+            FixtureArchive::new().symlink("link", dir.path().join("outside").to_str().unwrap()),
+            FixtureArchive::new().symlink("../link", "outside"),
+            FixtureArchive::new().hardlink("link", "../outside"),
+            FixtureArchive::new().hardlink("link", dir.path().join("outside").to_str().unwrap()),
+        ] {
+            assert!(
+                matches!(unpack(fixture.clone(), &dest), Err(Error::UnsafePath(_))),
+                "{fixture:?}"
+            );
+        }
+        assert_eq!(names(dir.path()), ["dest", "outside"]);
+        assert!(names(&dest).iter().all(|v| v == "a"), "{:?}", names(&dest));
+        assert!(!dest.join("a/link").exists());
+    }
+
+    #[test]
+    fn nothing_is_written_through_unpacked_symlinks() {
+        // 链接本身指向目标目录之内，但之后的条目不能经由它写入
+        let (dir, dest) = destination();
+        let fixture = FixtureArchive::new()
+            .dir("real")
+            .symlink("alias", "real")
+            .file("alias/a.btf", minimal_valid_btf());
+        assert!(matches!(unpack(fixture, &dest), Err(Error::UnsafePath(_))));
+        assert!(names(&dest.join("real")).is_empty());
+        // 同名文件也不能覆盖链接所指的文件
+        let fixture = FixtureArchive::new()
+            .file("real.btf", minimal_valid_btf())
+            .symlink("alias.btf", "real.btf")
+            .file("alias.btf", b"replaced".to_vec());
+        assert!(matches!(unpack(fixture, &dest), Err(Error::UnsafePath(_))));
+        assert_eq!(
+            std::fs::read(dest.join("real.btf")).unwrap(),
+            minimal_valid_btf()
+        );
+        // 硬链接也不能经由符号链接指向文件
+        let fixture = FixtureArchive::new().hardlink("hard.btf", "alias/a.btf");
+        assert!(matches!(unpack(fixture, &dest), Err(Error::UnsafePath(_))));
+        assert_eq!(names(dir.path()), ["dest"]);
+    }
+
+    #[test]
+    fn symlinked_subdirectories_of_the_destination_are_not_followed() {
+        let (dir, dest) = destination();
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, dest.join("btfhub-archive")).unwrap();
+        std::os::unix::fs::symlink(outside.join("file"), dest.join("package.json")).unwrap();
+        for fixture in [
+            FixtureArchive::new().btf("ubuntu", "20.04", "x86_64", "5.4.0", minimal_valid_btf()),
+            FixtureArchive::new().dir("btfhub-archive/ubuntu"),
+            FixtureArchive::new().file("package.json", b"{}".to_vec()),
+        ] {
+            assert!(
+                matches!(unpack(fixture.clone(), &dest), Err(Error::UnsafePath(_))),
+                "{fixture:?}"
+            );
+        }
+        assert!(names(&outside).is_empty());
+        // 直接调用时同样如此
+        assert!(matches!(
+            write_file_within(&dest, Path::new("btfhub-archive/a.btf"), b"a"),
+            Err(Error::UnsafePath(_))
+        ));
+        assert!(matches!(
+            create_dir_within(&dest, Path::new("btfhub-archive/ubuntu")),
+            Err(Error::UnsafePath(_))
+        ));
+        assert!(names(&outside).is_empty());
+    }
+
+    #[test]
+    fn unsupported_entries_are_skipped() {
+        let (_dir, dest) = destination();
+        let fixture = FixtureArchive::new()
+            .file("a.btf", minimal_valid_btf())
+            .file("b.btf", minimal_valid_btf());
+        let mut tar = fixture.tar();
+        // 把第二个条目改成 FIFO，并重新计算校验和
+        let mut header = tar::Header::new_old();
+        header.as_mut_bytes().copy_from_slice(&tar[1024..1536]);
+        header.set_entry_type(tar::EntryType::Fifo);
+        header.set_cksum();
+        tar[1024..1536].copy_from_slice(header.as_bytes());
+        unpack_within(&mut tar::Archive::new(tar.as_slice()), &dest).unwrap();
+        assert_eq!(names(&dest), ["a.btf"]);
+    }
+
+    #[cfg(feature = "host")]
+    #[test]
+    fn packages_are_unpacked_within_their_tempdir() {
+        let package = FixtureArchive::new()
+            .file("package.json", b"{}".to_vec())
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0", minimal_valid_btf());
+        let (json, archive) = crate::unpack_tar(&package.tar()).unwrap();
+        assert_eq!(json, b"{}");
+        let (path, _tmp) = archive.unwrap();
+        assert!(path.join("ubuntu/20.04/x86_64/5.4.0.btf").is_file());
+        let crafted = FixtureArchive::new()
+            .file("package.json", b"{}".to_vec())
+            .file("../../escaped.json", b"{}".to_vec());
+        assert!(matches!(
+            crate::unpack_tar(&crafted.tar()),
+            Err(Error::UnsafePath(_))
+        ));
+    }
+}
@@ -0,0 +1,416 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The archive embedded as a section of the executable itself, e.g. `.bpf_compat_btfs`,
+//! as an alternative to the `_binary_*` symbols of [`crate::embed`], which LTO or the
+//! garbage collection of some linkers may drop along with the archive.
+//!
+//! The section is added to the linked executable, with [`add_elf_section`](crate::section::add_elf_section) (`bpf-compat
+//! embed`) or `objcopy --add-section .bpf_compat_btfs=min_core_btfs.tar.gz`, and read back
+//! at runtime from `/proc/self/exe` by its name in the section headers. Sections are found
+//! by file offset, so it doesn't matter where a PIE is loaded. Stripping keeps the section,
+//! but the section headers must be left in place: tools like `sstrip` that remove them
+//! make the archive unreachable.
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{embed::append_aligned, Error, Result};
+
+/// Name of the section holding the archive, unless another one is given
+pub const BTF_SECTION_NAME: &str = ".bpf_compat_btfs";
+
+/// The executable of the running process
+pub const SELF_EXE_PATH: &str = "/proc/self/exe";
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_NOBITS: u32 = 8;
+/// `e_shstrndx` meaning the index is in `sh_link` of the first section header
+const SHN_XINDEX: u16 = 0xffff;
+/// First reserved section index; `e_shstrndx` and `e_shnum` must stay below it
+const SHN_LORESERVE: u16 = 0xff00;
+
+/// The layout of an ELF file, from its header
+#[derive(Debug, Clone, Copy)]
+struct ElfLayout {
+    is_64: bool,
+    little_endian: bool,
+    shoff: u64,
+    shentsize: u64,
+    shnum: u64,
+    shstrndx: u64,
+}
+
+/// A section header, with the fields needed to find a section
+#[derive(Debug, Clone, Copy)]
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+impl ElfLayout {
+    /// Parse the ELF header at the start of `header`, at least 64 bytes unless the file is shorter
+    fn parse(header: &[u8]) -> Result<Self> {
+        if header.len() < 16 || header[..4] != ELF_MAGIC {
+            return Err(Error::InvalidElf("not an ELF file".into()));
+        }
+        let is_64 = match header[4] {
+            ELFCLASS32 => false,
+            ELFCLASS64 => true,
+            v => return Err(Error::InvalidElf(format!("unknown ELF class {}", v))),
+        };
+        let little_endian = match header[5] {
+            ELFDATA2LSB => true,
+            ELFDATA2MSB => false,
+            v => {
+                return Err(Error::InvalidElf(format!(
+                    "unknown ELF data encoding {}",
+                    v
+                )))
+            }
+        };
+        let mut layout = Self {
+            is_64,
+            little_endian,
+            shoff: 0,
+            shentsize: 0,
+            shnum: 0,
+            shstrndx: 0,
+        };
+        let (shoff, rest) = if is_64 { (0x28, 0x3a) } else { (0x20, 0x2e) };
+        if header.len() < rest + 6 {
+            return Err(Error::InvalidElf("truncated ELF header".into()));
+        }
+        layout.shoff = layout.word(header, shoff);
+        layout.shentsize = layout.read(header, rest, 2);
+        layout.shnum = layout.read(header, rest + 2, 2);
+        layout.shstrndx = layout.read(header, rest + 4, 2);
+        let min_entsize = if is_64 { 64 } else { 40 };
+        if layout.shoff != 0 && layout.shentsize < min_entsize {
+            return Err(Error::InvalidElf(format!(
+                "section headers of {} bytes are too small",
+                layout.shentsize
+            )));
+        }
+        Ok(layout)
+    }
+
+    /// The unsigned integer of `size` bytes at `offset` of `bytes`, which must hold it
+    fn read(&self, bytes: &[u8], offset: usize, size: usize) -> u64 {
+        let bytes = &bytes[offset..offset + size];
+        let fold = |acc: u64, v: &u8| acc << 8 | *v as u64;
+        if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        }
+    }
+
+    /// An address or offset at `offset`, 8 bytes in ELF64 and 4 in ELF32
+    fn word(&self, bytes: &[u8], offset: usize) -> u64 {
+        self.read(bytes, offset, if self.is_64 { 8 } else { 4 })
+    }
+
+    /// Write `value` as `size` bytes at `offset` of `bytes`
+    fn write(&self, bytes: &mut [u8], offset: usize, size: usize, value: u64) {
+        let encoded = if self.little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        };
+        let encoded = if self.little_endian {
+            &encoded[..size]
+        } else {
+            &encoded[8 - size..]
+        };
+        bytes[offset..offset + size].copy_from_slice(encoded);
+    }
+
+    /// Parse the section header `entry`, of `shentsize` bytes
+    fn section(&self, entry: &[u8]) -> Section {
+        let word_size = if self.is_64 { 8 } else { 4 };
+        // sh_name、sh_type、sh_flags、sh_addr、sh_offset、sh_size、sh_link
+        let offset = 8 + 2 * word_size;
+        Section {
+            name: self.read(entry, 0, 4) as u32,
+            kind: self.read(entry, 4, 4) as u32,
+            offset: self.word(entry, offset),
+            size: self.word(entry, offset + word_size),
+            link: self.read(entry, offset + 2 * word_size, 4) as u32,
+        }
+    }
+}
+
+/// The contents of the section `name` of the ELF file at `path`
+///
+/// Only the headers and the section are read, not the whole file. Fails with
+/// [`Error::SectionNotFound`] if the file has no such section, [`Error::NoSectionHeaders`]
+/// if it has no section headers at all, and [`Error::InvalidElf`] if it isn't an ELF
+/// file, or the section has no contents in the file.
+pub fn read_elf_section(path: impl AsRef<Path>, name: &str) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let read_error = |e| Error::FileReadError(path.display().to_string(), e);
+    let file = File::open(path).map_err(read_error)?;
+    let file_size = file.metadata().map_err(read_error)?.len();
+    let read_at = |offset: u64, size: u64| -> Result<Vec<u8>> {
+        match offset.checked_add(size) {
+            Some(end) if end <= file_size => {}
+            _ => {
+                return Err(Error::InvalidElf(format!(
+                    "{} bytes at offset {} are past the end of the file",
+                    size, offset
+                )))
+            }
+        }
+        let mut buf = vec![0; size as usize];
+        (&file)
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| (&file).read_exact(&mut buf))
+            .map_err(read_error)?;
+        Ok(buf)
+    };
+    let layout = ElfLayout::parse(&read_at(0, file_size.min(64))?)?;
+    let (sections, shstrtab) = section_headers(&layout, read_at)?
+        .ok_or_else(|| Error::NoSectionHeaders(path.display().to_string()))?;
+    let section = sections
+        .iter()
+        .find(|v| section_name(&shstrtab, v.name) == Some(name.as_bytes()))
+        .ok_or_else(|| Error::SectionNotFound(path.display().to_string(), name.to_string()))?;
+    if section.kind == SHT_NOBITS {
+        return Err(Error::InvalidElf(format!(
+            "the section {} has no contents in the file",
+            name
+        )));
+    }
+    read_at(section.offset, section.size)
+}
+
+/// The contents of the section `name` of the executable of the running process, see [`read_elf_section`]
+///
+/// Fails with [`Error::UnsupportedPlatform`] on other systems than Linux.
+#[cfg(target_os = "linux")]
+pub fn read_self_section(name: &str) -> Result<Vec<u8>> {
+    read_elf_section(SELF_EXE_PATH, name)
+}
+
+/// The contents of the section `name` of the executable of the running process, see [`read_elf_section`]
+///
+/// Fails with [`Error::UnsupportedPlatform`] on other systems than Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn read_self_section(_name: &str) -> Result<Vec<u8>> {
+    Err(Error::UnsupportedPlatform)
+}
+
+/// The section headers of the file of `layout`, and the contents of its section name table
+///
+/// `None` if the file has no section headers.
+fn section_headers(
+    layout: &ElfLayout,
+    read_at: impl Fn(u64, u64) -> Result<Vec<u8>>,
+) -> Result<Option<(Vec<Section>, Vec<u8>)>> {
+    if layout.shoff == 0 {
+        return Ok(None);
+    }
+    // 节的数目或节名表的序号过大时，实际的值记录在第一个节头中
+    let first = layout.section(&read_at(layout.shoff, layout.shentsize)?);
+    let shnum = match layout.shnum {
+        0 => first.size,
+        v => v,
+    };
+    let shstrndx = match layout.shstrndx as u16 {
+        SHN_XINDEX => first.link as u64,
+        v => v as u64,
+    };
+    let table_size = shnum
+        .checked_mul(layout.shentsize)
+        .ok_or_else(|| Error::InvalidElf(format!("{} section headers is too many", shnum)))?;
+    let table = read_at(layout.shoff, table_size)?;
+    let sections = table
+        .chunks_exact(layout.shentsize as usize)
+        .map(|v| layout.section(v))
+        .collect::<Vec<_>>();
+    let names = sections.get(shstrndx as usize).ok_or_else(|| {
+        Error::InvalidElf(format!(
+            "the section name table {} is beyond the {} sections",
+            shstrndx, shnum
+        ))
+    })?;
+    let shstrtab = read_at(names.offset, names.size)?;
+    Ok(Some((sections, shstrtab)))
+}
+
+/// The NUL-terminated name at `offset` of the section name table
+fn section_name(shstrtab: &[u8], offset: u32) -> Option<&[u8]> {
+    let rest = shstrtab.get(offset as usize..)?;
+    let end = rest.iter().position(|v| *v == 0)?;
+    Some(&rest[..end])
+}
+
+/// `elf` with the section `name` holding `data` added, like `objcopy --add-section`
+///
+/// The section isn't loaded into memory, it's read from the file, see [`read_elf_section`].
+/// `data`, a new section name table and a new section header table are appended to the
+/// file; the old ones are left in place, unreferenced, so nothing else moves. Fails with
+/// [`Error::InvalidElf`] if `elf` isn't an ELF file with section headers, or already has a
+/// section `name`.
+pub fn add_elf_section(elf: &[u8], name: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let layout = ElfLayout::parse(elf)?;
+    let read_at = |offset: u64, size: u64| -> Result<Vec<u8>> {
+        let range = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(size).ok())
+            .and_then(|(start, size)| Some(start..start.checked_add(size)?));
+        range
+            .and_then(|v| elf.get(v))
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| {
+                Error::InvalidElf(format!(
+                    "{} bytes at offset {} are past the end of the file",
+                    size, offset
+                ))
+            })
+    };
+    let (sections, shstrtab) = section_headers(&layout, read_at)?
+        .ok_or_else(|| Error::InvalidElf("the file has no section headers".into()))?;
+    if sections
+        .iter()
+        .any(|v| section_name(&shstrtab, v.name) == Some(name.as_bytes()))
+    {
+        return Err(Error::InvalidElf(format!(
+            "the file already has a section {}",
+            name
+        )));
+    }
+    // 新的节数目或节名表序号需要扩展编号时，改写第一个节头的逻辑不值得支持
+    let shnum = sections.len() as u64 + 1;
+    if layout.shnum == 0 || shnum >= SHN_LORESERVE as u64 || layout.shstrndx >= SHN_LORESERVE as u64
+    {
+        return Err(Error::InvalidElf(format!(
+            "can't add a section to a file of {} sections",
+            sections.len()
+        )));
+    }
+    let mut out = elf.to_vec();
+    let mut names = shstrtab;
+    let name_offset = names.len() as u64;
+    names.extend_from_slice(name.as_bytes());
+    names.push(0);
+    let names_offset = append_aligned(&mut out, &names, 1);
+    let data_offset = append_aligned(&mut out, data, 8);
+
+    let entsize = layout.shentsize as usize;
+    let table_offset = layout.shoff as usize;
+    let mut table = elf[table_offset..table_offset + sections.len() * entsize].to_vec();
+    let word_size = if layout.is_64 { 8 } else { 4 };
+    // sh_offset 和 sh_size 的位置
+    let offset_field = 8 + 2 * word_size;
+    let strtab_entry = layout.shstrndx as usize * entsize;
+    layout.write(
+        &mut table,
+        strtab_entry + offset_field,
+        word_size,
+        names_offset,
+    );
+    layout.write(
+        &mut table,
+        strtab_entry + offset_field + word_size,
+        word_size,
+        names.len() as u64,
+    );
+    let mut entry = vec![0; entsize];
+    layout.write(&mut entry, 0, 4, name_offset);
+    layout.write(&mut entry, 4, 4, SHT_PROGBITS as u64);
+    layout.write(&mut entry, offset_field, word_size, data_offset);
+    layout.write(
+        &mut entry,
+        offset_field + word_size,
+        word_size,
+        data.len() as u64,
+    );
+    // sh_addralign，位于 sh_link 和 sh_info 之后
+    layout.write(&mut entry, offset_field + 2 * word_size + 8, word_size, 1);
+    table.extend_from_slice(&entry);
+    let shoff = append_aligned(&mut out, &table, 8);
+    if !layout.is_64 && out.len() as u64 > u32::MAX as u64 {
+        return Err(Error::InvalidElf(
+            "the section doesn't fit in a 32-bit ELF file".into(),
+        ));
+    }
+
+    let (shoff_field, shnum_field) = if layout.is_64 {
+        (0x28, 0x3c)
+    } else {
+        (0x20, 0x30)
+    };
+    layout.write(&mut out, shoff_field, word_size, shoff);
+    layout.write(&mut out, shnum_field, 2, shnum);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(elf: &[u8]) -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), elf).unwrap();
+        file
+    }
+
+    #[test]
+    fn added_section_is_read_back_from_the_test_binary() {
+        let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let archive = b"an archive of an odd length".to_vec();
+        let elf = add_elf_section(&exe, BTF_SECTION_NAME, &archive).unwrap();
+        // 除 ELF 头部外原有内容保持不动，新的节、节名表与节头表追加在文件末尾
+        assert!(elf.len() > exe.len() && elf[64..exe.len()] == exe[64..]);
+        let file = written(&elf);
+        assert_eq!(
+            read_elf_section(file.path(), BTF_SECTION_NAME).unwrap(),
+            archive
+        );
+        assert!(matches!(
+            read_elf_section(file.path(), ".bpf_compat_other"),
+            Err(Error::SectionNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn sections_can_be_added_one_after_another_but_not_twice() {
+        let object = crate::embed::EmbeddedArchiveObject::new(b"tar", "x86_64")
+            .unwrap()
+            .to_bytes();
+        let elf = add_elf_section(&object, ".first", b"1").unwrap();
+        let elf = add_elf_section(&elf, ".second", b"22").unwrap();
+        let file = written(&elf);
+        assert_eq!(read_elf_section(file.path(), ".first").unwrap(), b"1");
+        assert_eq!(read_elf_section(file.path(), ".second").unwrap(), b"22");
+        assert!(matches!(
+            add_elf_section(&elf, ".first", b"again"),
+            Err(Error::InvalidElf(_))
+        ));
+    }
+
+    #[test]
+    fn non_elf_input_is_rejected() {
+        assert!(matches!(
+            add_elf_section(b"#!/bin/sh\n", BTF_SECTION_NAME, b""),
+            Err(Error::InvalidElf(_))
+        ));
+        let file = written(b"\x7fELF but truncated");
+        assert!(read_elf_section(file.path(), BTF_SECTION_NAME).is_err());
+    }
+}
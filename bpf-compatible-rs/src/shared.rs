@@ -0,0 +1,171 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Btfs extracted to a directory shared by the processes of a user, under names derived
+//! from the kernel release and the contents, so that processes extracting the same btf,
+//! e.g. several services started at boot, end up with a single file.
+use std::path::{Path, PathBuf};
+
+use crate::{
+    sanitize::prepare_file_within,
+    sha256::{sha256, to_hex},
+    tarball::{write_btf_to, ExtractOptions},
+    Result,
+};
+
+/// Hex digits of the sha256 of the btf in the name of a shared file
+pub const SHARED_HASH_LEN: usize = 8;
+
+/// Name of the shared file holding `btf` of `kernel_release`, e.g. `5.4.0-40-generic-ab12cd34.btf`
+pub fn shared_btf_name(kernel_release: &str, btf: &[u8]) -> String {
+    let hash = to_hex(&sha256(btf));
+    format!("{}-{}.btf", kernel_release, &hash[..SHARED_HASH_LEN])
+}
+
+/// Store `btf` of `kernel_release` in `dir`, returning the path of the shared file
+///
+/// If the file is already there with the same contents, it's returned as it is.
+/// Otherwise the btf is written under a temporary name and renamed into place, see
+/// [`write_btf_to`]: concurrent callers all succeed, and readers only ever see a complete
+/// file, since it only gets replaced by an identical one. A name that would escape `dir`,
+/// or a symlink in place of the file, fails with [`crate::Error::UnsafePath`].
+pub fn store_shared(dir: &Path, kernel_release: &str, btf: &[u8]) -> Result<PathBuf> {
+    let path = prepare_file_within(dir, Path::new(&shared_btf_name(kernel_release, btf)))?;
+    if is_same_file_contents(&path, btf) {
+        log_at!(Debug, "Reusing the shared btf {}", path.display());
+        return Ok(path);
+    }
+    write_btf_to(&path, btf, &ExtractOptions::default().with_overwrite(true))?;
+    Ok(path)
+}
+
+/// Whether `path` is a regular file holding exactly `btf`
+fn is_same_file_contents(path: &Path, btf: &[u8]) -> bool {
+    // 先比较大小，不同时无需读取文件
+    let Ok(metadata) = path.symlink_metadata() else {
+        return false;
+    };
+    if !metadata.is_file() || metadata.len() != btf.len() as u64 {
+        return false;
+    }
+    std::fs::read(path).is_ok_and(|v| v == btf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::MetadataExt, sync::Barrier};
+
+    use super::*;
+    use crate::{
+        fixture::{btf_of_arch, minimal_valid_btf},
+        Error,
+    };
+
+    #[test]
+    fn name_is_derived_from_the_release_and_the_contents() {
+        let btf = minimal_valid_btf();
+        let name = shared_btf_name("5.4.0-40-generic", &btf);
+        let hash = to_hex(&sha256(&btf));
+        assert_eq!(name, format!("5.4.0-40-generic-{}.btf", &hash[..8]));
+        assert_eq!(shared_btf_name("5.4.0-40-generic", &btf), name);
+        assert_ne!(
+            shared_btf_name("5.4.0-40-generic", &btf_of_arch(8, "other")),
+            name
+        );
+        assert_ne!(shared_btf_name("5.4.0-42-generic", &btf), name);
+    }
+
+    #[test]
+    fn stored_file_is_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let btf = btf_of_arch(8, "shared");
+        let path = store_shared(dir.path(), "5.4.0-40-generic", &btf).unwrap();
+        assert_eq!(
+            path,
+            dir.path().join(shared_btf_name("5.4.0-40-generic", &btf))
+        );
+        assert_eq!(fs::read(&path).unwrap(), btf);
+        let inode = fs::metadata(&path).unwrap().ino();
+        // 内容相同时不再写入，文件保持不变
+        assert_eq!(
+            store_shared(dir.path(), "5.4.0-40-generic", &btf).unwrap(),
+            path
+        );
+        assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+        // 内容不同的 btf 放在另一个文件中
+        let other =
+            store_shared(dir.path(), "5.4.0-40-generic", &btf_of_arch(8, "others")).unwrap();
+        assert_ne!(other, path);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn damaged_file_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let btf = btf_of_arch(8, "damaged");
+        let path = dir.path().join(shared_btf_name("5.4.0-40-generic", &btf));
+        // 大小相同但内容不同，或者被截断
+        for damaged in [vec![0; btf.len()], btf[..btf.len() / 2].to_vec()] {
+            fs::write(&path, &damaged).unwrap();
+            assert_eq!(
+                store_shared(dir.path(), "5.4.0-40-generic", &btf).unwrap(),
+                path
+            );
+            assert_eq!(fs::read(&path).unwrap(), btf);
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn unsafe_names_and_symlinks_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        let btf = minimal_valid_btf();
+        assert!(matches!(
+            store_shared(&shared, "../5.4.0-40-generic", &btf),
+            Err(Error::UnsafePath(_))
+        ));
+        // 其他用户预先放置的链接不会被跟随
+        let target = dir.path().join("target");
+        let name = shared_btf_name("5.4.0-40-generic", &btf);
+        std::os::unix::fs::symlink(&target, shared.join(&name)).unwrap();
+        assert!(matches!(
+            store_shared(&shared, "5.4.0-40-generic", &btf),
+            Err(Error::UnsafePath(_))
+        ));
+        assert!(!target.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn concurrent_stores_converge_on_one_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let btf = btf_of_arch(8, "concurrent");
+        let barrier = Barrier::new(8);
+        let paths = std::thread::scope(|s| {
+            let handles = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        store_shared(dir.path(), "5.4.0-40-generic", &btf).unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|v| v.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert!(paths.iter().all(|v| v == &paths[0]), "{paths:?}");
+        assert_eq!(fs::read(&paths[0]).unwrap(), btf);
+        // 没有留下临时文件
+        let names = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|v| v.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [paths[0].file_name().unwrap()]);
+    }
+}
@@ -0,0 +1,164 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Several archives searched in order, e.g. a base archive embedded into the binary and a
+//! supplement with newly released kernels dropped next to it, see
+//! [`crate::ensure_core_btf_multi`].
+//!
+//! An archive without a btf for the kernel, or a file that doesn't exist, is a miss and the
+//! next archive is tried. Other failures are logged and the search goes on as well, but the
+//! first of them is what a search without any hit fails with, so a corrupt base archive
+//! isn't hidden behind the miss of a later one.
+use std::{fmt::Display, io::ErrorKind, path::Path};
+
+use crate::{mapped::ArchiveFile, EnsuredBtf, Error, MatchInfo, Result};
+
+/// An archive to look the btf up in
+#[derive(Debug, Clone, Copy)]
+pub enum ArchiveSource<'a> {
+    /// An archive in memory, e.g. the one of [`crate::include_btf_archive`]
+    Bytes(&'a [u8]),
+    /// An archive file, mapped into memory, see [`ArchiveFile`]
+    File(&'a Path),
+}
+
+impl Display for ArchiveSource<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveSource::Bytes(v) => write!(f, "the archive of {} bytes", v.len()),
+            ArchiveSource::File(v) => write!(f, "the archive `{}`", v.display()),
+        }
+    }
+}
+
+impl ArchiveSource<'_> {
+    /// Look the btf of the running system up in the archive, as [`crate::ensure_core_btf`] does
+    pub(crate) fn extract_core_btf(&self) -> Result<(EnsuredBtf, MatchInfo)> {
+        match self {
+            ArchiveSource::Bytes(v) => crate::extract_core_btf(v),
+            ArchiveSource::File(v) => {
+                let file = ArchiveFile::open(v)?;
+                let extracted = crate::extract_core_btf(file.bytes())?;
+                // 查找期间文件被原地修改时，结果不可信，丢弃时会删除已写入的文件
+                file.check_unchanged()?;
+                Ok(extracted)
+            }
+        }
+    }
+}
+
+/// Whether `e` only means the archive has no btf for the system, so the next one may be tried
+pub(crate) fn is_miss(e: &Error) -> bool {
+    match e {
+        Error::EntryNotFound { .. } => true,
+        Error::FileReadError(_, e) => e.kind() == ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+        SystemInfo,
+    };
+
+    #[test]
+    fn misses_are_told_from_failures() {
+        assert!(is_miss(&Error::EntryNotFound {
+            expected: "x".into()
+        }));
+        let not_found = std::io::Error::from(ErrorKind::NotFound);
+        assert!(is_miss(&Error::FileReadError("a.tar.gz".into(), not_found)));
+        let denied = std::io::Error::from(ErrorKind::PermissionDenied);
+        assert!(!is_miss(&Error::FileReadError("a.tar.gz".into(), denied)));
+        assert!(!is_miss(&Error::InvalidGzipHeader));
+    }
+
+    #[test]
+    fn sources_are_named_in_messages() {
+        assert_eq!(
+            ArchiveSource::Bytes(&[0; 12]).to_string(),
+            "the archive of 12 bytes"
+        );
+        assert_eq!(
+            ArchiveSource::File(Path::new("/opt/extra.tar.gz")).to_string(),
+            "the archive `/opt/extra.tar.gz`"
+        );
+    }
+
+    #[test]
+    fn each_kind_of_source_is_looked_up() {
+        let Ok(info) = SystemInfo::detect() else {
+            return;
+        };
+        let tar = FixtureArchive::new()
+            .file(&format!("btfhub-archive/{}", info), minimal_valid_btf())
+            .gz();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("extra.tar.gz");
+        std::fs::write(&file, &tar).unwrap();
+        for source in [ArchiveSource::Bytes(&tar), ArchiveSource::File(&file)] {
+            let (btf, matched) = source.extract_core_btf().unwrap();
+            assert_eq!(std::fs::read(btf.path()).unwrap(), minimal_valid_btf());
+            assert!(matched.exact, "{source}");
+        }
+        // 没有该内核的归档与不存在的文件都是未命中，损坏的归档不是
+        let other = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-0-none",
+                btf_of_arch(8, "rip"),
+            )
+            .gz();
+        let missing = dir.path().join("missing.tar.gz");
+        for source in [ArchiveSource::Bytes(&other), ArchiveSource::File(&missing)] {
+            assert!(is_miss(&source.extract_core_btf().unwrap_err()), "{source}");
+        }
+        let corrupt = &tar[..tar.len() / 2];
+        assert!(!is_miss(
+            &ArchiveSource::Bytes(corrupt)
+                .extract_core_btf()
+                .unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn first_source_with_the_btf_is_used() {
+        let Ok(info) = SystemInfo::detect() else {
+            return;
+        };
+        let base = FixtureArchive::new().gz();
+        let supplement = FixtureArchive::new()
+            .file(&format!("btfhub-archive/{}", info), minimal_valid_btf())
+            .gz();
+        let found = crate::ensure_core_btf_multi(&[
+            ArchiveSource::Bytes(&base),
+            ArchiveSource::Bytes(&supplement),
+        ]);
+        // 内核自带 btf 时不读取任何归档
+        if crate::has_native_btf() {
+            assert!(found.unwrap().is_none());
+            return;
+        }
+        let btf = found.unwrap().unwrap();
+        assert_eq!(std::fs::read(btf.path()).unwrap(), minimal_valid_btf());
+        // 都未命中时是找不到；损坏的归档在前时返回它的错误
+        let corrupt = &supplement[..supplement.len() / 2];
+        assert!(is_miss(
+            &crate::ensure_core_btf_multi(&[ArchiveSource::Bytes(&base)]).unwrap_err()
+        ));
+        assert!(!is_miss(
+            &crate::ensure_core_btf_multi(&[
+                ArchiveSource::Bytes(corrupt),
+                ArchiveSource::Bytes(&base)
+            ])
+            .unwrap_err()
+        ));
+    }
+}
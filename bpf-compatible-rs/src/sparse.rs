@@ -0,0 +1,370 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Sparse entries, which store runs of zeros as holes rather than bytes, as some tools do
+//! for the string tables of btfs.
+//!
+//! The contents of a sparse entry don't lie at [`tar::Entry::raw_file_position`] as they
+//! are, so they must never be sliced out of the tar. GNU sparse entries (type `S`) are
+//! reassembled by the tar crate when read. Those of the PAX format 1.0 of GNU tar are
+//! regular entries, named `GNUSparseFile.<n>/<name>` unless a `path` record says
+//! otherwise, whose data starts with the map of the fragments; they're reassembled here,
+//! under their `GNU.sparse.name`. The older PAX formats 0.0 and 0.1 are rejected rather
+//! than read as they are.
+use std::{
+    io::{Cursor, Error as IoError, ErrorKind, Read},
+    path::PathBuf,
+};
+
+use tar::{Entry, EntryType, Header};
+
+use crate::compression::{LimitedReader, DEFAULT_MAX_DECOMPRESSED_SIZE};
+
+/// PAX record of the major version of the sparse format
+const PAX_SPARSE_MAJOR: &str = "GNU.sparse.major";
+/// PAX record of the minor version of the sparse format
+const PAX_SPARSE_MINOR: &str = "GNU.sparse.minor";
+/// PAX record of the name of the file, in the format 1.0
+const PAX_SPARSE_NAME: &str = "GNU.sparse.name";
+/// PAX record of the size of the file, in the format 1.0
+const PAX_SPARSE_REAL_SIZE: &str = "GNU.sparse.realsize";
+/// PAX records only the formats 0.0 and 0.1 have
+const PAX_SPARSE_V0_RECORDS: &[&str] = &["GNU.sparse.size", "GNU.sparse.map", "GNU.sparse.offset"];
+
+const BLOCK_SIZE: u64 = 512;
+
+/// How the contents of an entry are stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryLayout {
+    /// As they are, at [`tar::Entry::raw_file_position`]
+    Contiguous,
+    /// A GNU sparse entry, reassembled by the tar crate
+    GnuSparse,
+    /// A sparse entry of the PAX format 1.0, with its name and size
+    PaxSparse { name: Option<PathBuf>, size: u64 },
+}
+
+/// Whether entries of `entry_type` hold the contents of a file, sparse ones included
+pub fn is_file_entry(entry_type: EntryType) -> bool {
+    entry_type.is_file() || entry_type.is_gnu_sparse()
+}
+
+/// How the contents of `entry` are stored
+///
+/// Fails with [`ErrorKind::InvalidData`] on a PAX sparse format other than 1.0.
+pub fn entry_layout<R: Read>(entry: &mut Entry<R>) -> std::io::Result<EntryLayout> {
+    if entry.header().entry_type().is_gnu_sparse() {
+        return Ok(EntryLayout::GnuSparse);
+    }
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(EntryLayout::Contiguous);
+    };
+    let (mut version, mut name, mut size, mut v0) = ((None, None), None, None, false);
+    for extension in extensions {
+        let extension = extension?;
+        let Ok(key) = extension.key() else {
+            continue;
+        };
+        let value = || String::from_utf8_lossy(extension.value_bytes()).into_owned();
+        match key {
+            PAX_SPARSE_MAJOR => version.0 = Some(value()),
+            PAX_SPARSE_MINOR => version.1 = Some(value()),
+            PAX_SPARSE_NAME => name = Some(PathBuf::from(value())),
+            PAX_SPARSE_REAL_SIZE => size = Some(value()),
+            v => v0 |= PAX_SPARSE_V0_RECORDS.contains(&v),
+        }
+    }
+    match (version, size) {
+        ((Some(major), Some(minor)), Some(size)) if major == "1" && minor == "0" => {
+            let size = size
+                .parse()
+                .map_err(|_| invalid(format!("invalid {} `{}`", PAX_SPARSE_REAL_SIZE, size)))?;
+            Ok(EntryLayout::PaxSparse { name, size })
+        }
+        ((None, None), _) if !v0 => Ok(EntryLayout::Contiguous),
+        // 格式 0.0 和 0.1 没有版本记录
+        ((Some(major), Some(minor)), _) => Err(invalid(format!(
+            "unsupported PAX sparse format {}.{}",
+            major, minor
+        ))),
+        _ => Err(invalid("unsupported PAX sparse format 0.x".to_string())),
+    }
+}
+
+/// Path of the file `entry` holds: the name a PAX sparse entry records, else its path
+pub fn entry_path<R: Read>(entry: &mut Entry<R>) -> std::io::Result<PathBuf> {
+    match entry_layout(entry)? {
+        EntryLayout::PaxSparse {
+            name: Some(name), ..
+        } => Ok(name),
+        _ => Ok(entry.path()?.into_owned()),
+    }
+}
+
+/// Size of the file `entry` holds, holes of sparse entries included
+pub fn entry_size<R: Read>(entry: &mut Entry<R>) -> std::io::Result<u64> {
+    match entry_layout(entry)? {
+        EntryLayout::PaxSparse { size, .. } => Ok(size),
+        // tar crate 已将 GNU 稀疏条目的大小设为文件的实际大小
+        _ => Ok(entry.size()),
+    }
+}
+
+/// The contents of the file `entry` holds, with the holes of sparse entries filled with zeros
+///
+/// Sparse entries fail with [`ErrorKind::FileTooLarge`] past [`DEFAULT_MAX_DECOMPRESSED_SIZE`],
+/// like compressed btfs, and with [`ErrorKind::InvalidData`] if their map is malformed.
+pub fn entry_contents<'a, R: Read + 'a>(
+    entry: &'a mut Entry<'_, R>,
+) -> std::io::Result<Box<dyn Read + 'a>> {
+    match entry_layout(entry)? {
+        EntryLayout::Contiguous => Ok(Box::new(entry)),
+        EntryLayout::GnuSparse => Ok(Box::new(LimitedReader::new(
+            entry,
+            DEFAULT_MAX_DECOMPRESSED_SIZE,
+        ))),
+        EntryLayout::PaxSparse { size, .. } => {
+            Ok(Box::new(Cursor::new(read_pax_sparse(entry, size)?)))
+        }
+    }
+}
+
+/// Read the whole contents of `entry`, see [`entry_contents`]
+pub fn read_entry<R: Read>(entry: &mut Entry<R>) -> std::io::Result<Vec<u8>> {
+    let mut contents = vec![];
+    entry_contents(entry)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// A copy of `header` to write the file it describes back out whole, as a regular entry
+///
+/// A GNU sparse header is replaced by a regular one with the same mode, owners and mtime;
+/// other headers, PAX sparse ones included, are regular already.
+pub fn regular_header(header: &Header) -> Header {
+    if !header.entry_type().is_gnu_sparse() {
+        return header.clone();
+    }
+    let mut regular = Header::new_gnu();
+    regular.set_entry_type(EntryType::Regular);
+    if let Ok(mode) = header.mode() {
+        regular.set_mode(mode);
+    }
+    if let Ok(mtime) = header.mtime() {
+        regular.set_mtime(mtime);
+    }
+    if let (Ok(uid), Ok(gid)) = (header.uid(), header.gid()) {
+        regular.set_uid(uid);
+        regular.set_gid(gid);
+    }
+    if let (Ok(Some(user)), Ok(Some(group))) = (header.username(), header.groupname()) {
+        let _ = regular.set_username(user);
+        let _ = regular.set_groupname(group);
+    }
+    regular
+}
+
+/// Reassemble the file of `size` bytes stored in `data` in the PAX sparse format 1.0
+///
+/// The data starts with the map, decimal numbers on lines of their own: the number of
+/// fragments, then the offset and size of each, padded to a whole block. The fragments
+/// follow, one after the other.
+pub fn read_pax_sparse(mut data: impl Read, size: u64) -> std::io::Result<Vec<u8>> {
+    if size > DEFAULT_MAX_DECOMPRESSED_SIZE {
+        return Err(IoError::new(
+            ErrorKind::FileTooLarge,
+            format!("the sparse file holds {} bytes, past the limit", size),
+        ));
+    }
+    let mut map_len = 0;
+    let mut read_number = |data: &mut dyn Read| -> std::io::Result<u64> {
+        let mut digits = vec![];
+        let mut byte = [0];
+        loop {
+            data.read_exact(&mut byte)?;
+            map_len += 1;
+            match byte[0] {
+                b'\n' => break,
+                v if v.is_ascii_digit() && digits.len() < 20 => digits.push(v),
+                _ => return Err(invalid("malformed sparse map".to_string())),
+            }
+        }
+        std::str::from_utf8(&digits)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| invalid("malformed sparse map".to_string()))
+    };
+    let count = read_number(&mut data)?;
+    let mut fragments = vec![];
+    let mut end = 0;
+    for _ in 0..count {
+        let (offset, len) = (read_number(&mut data)?, read_number(&mut data)?);
+        // 片段须按顺序排列、互不重叠，且不超出文件的大小
+        if offset < end || offset.checked_add(len).is_none_or(|v| v > size) {
+            return Err(invalid(format!(
+                "sparse fragment of {} bytes at {} out of order or past the end of the file",
+                len, offset
+            )));
+        }
+        end = offset + len;
+        fragments.push((offset, len));
+    }
+    let padding = (BLOCK_SIZE - map_len % BLOCK_SIZE) % BLOCK_SIZE;
+    std::io::copy(&mut (&mut data).take(padding), &mut std::io::sink())?;
+    let mut contents = vec![0; size as usize];
+    for (offset, len) in fragments {
+        data.read_exact(&mut contents[offset as usize..(offset + len) as usize])?;
+    }
+    Ok(contents)
+}
+
+fn invalid(message: String) -> IoError {
+    IoError::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use tar::{Archive, Builder};
+
+    use super::*;
+    use crate::fixture::{new_ustar_header, pax_records, FixtureArchive};
+
+    /// A PAX sparse map of `map`, padded to a block, followed by `data`
+    fn encoded(map: &str, data: &[u8]) -> Vec<u8> {
+        let mut encoded = map.as_bytes().to_vec();
+        encoded.resize(encoded.len().next_multiple_of(BLOCK_SIZE as usize), 0);
+        encoded.extend(data);
+        encoded
+    }
+
+    /// The layout of the single file of a tar holding `records` then `data`
+    fn layout_with_records(records: &[(&str, &str)], data: &[u8]) -> std::io::Result<EntryLayout> {
+        let mut builder = Builder::new(vec![]);
+        let records = pax_records(records);
+        for (entry_type, data) in [
+            (EntryType::XHeader, &records[..]),
+            (EntryType::Regular, data),
+        ] {
+            let mut header = new_ustar_header(entry_type, data.len() as u64);
+            header.set_path("GNUSparseFile.0/a.btf").unwrap();
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        let mut archive = Archive::new(&tar[..]);
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        entry_layout(&mut entry)
+    }
+
+    #[test]
+    fn fragments_are_put_back_at_their_offsets() {
+        let data = encoded("3\n0\n3\n10\n2\n12\n0\n", b"abcde");
+        assert_eq!(
+            read_pax_sparse(&data[..], 12).unwrap(),
+            b"abc\0\0\0\0\0\0\0de"
+        );
+        // 没有片段的文件全是空洞
+        assert_eq!(
+            read_pax_sparse(&encoded("0\n", b"")[..], 4).unwrap(),
+            [0; 4]
+        );
+    }
+
+    #[test]
+    fn malformed_maps_are_refused_rather_than_read() {
+        for (map, size) in [
+            ("2\n10\n2\n0\n3\n", 12),
+            ("1\n10\n5\n", 12),
+            ("1\n18446744073709551615\n2\n", 12),
+            ("x\n", 12),
+            ("1\n-1\n2\n", 12),
+            ("123456789012345678901\n", 12),
+        ] {
+            let err = read_pax_sparse(&encoded(map, b"abcde")[..], size).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{map:?}: {err}");
+        }
+        // 数据比映射描述的少
+        let err = read_pax_sparse(&encoded("1\n0\n8\n", b"abc")[..], 12).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = read_pax_sparse(&b"1\n0\n"[..], 12).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = read_pax_sparse(&b""[..], DEFAULT_MAX_DECOMPRESSED_SIZE + 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    }
+
+    #[test]
+    fn only_the_pax_format_1_0_is_read() {
+        let v1 = [
+            ("GNU.sparse.major", "1"),
+            ("GNU.sparse.minor", "0"),
+            ("GNU.sparse.name", "a.btf"),
+            ("GNU.sparse.realsize", "12"),
+        ];
+        assert_eq!(
+            layout_with_records(&v1, &encoded("0\n", b"")).unwrap(),
+            EntryLayout::PaxSparse {
+                name: Some("a.btf".into()),
+                size: 12
+            }
+        );
+        for (records, message) in [
+            (
+                &[("GNU.sparse.size", "12"), ("GNU.sparse.map", "0,3")][..],
+                "unsupported PAX sparse format 0.x",
+            ),
+            (
+                &[("GNU.sparse.offset", "0"), ("GNU.sparse.numbytes", "3")][..],
+                "unsupported PAX sparse format 0.x",
+            ),
+            (
+                &[
+                    ("GNU.sparse.major", "2"),
+                    ("GNU.sparse.minor", "0"),
+                    ("GNU.sparse.realsize", "12"),
+                ][..],
+                "unsupported PAX sparse format 2.0",
+            ),
+            (
+                &[
+                    ("GNU.sparse.major", "1"),
+                    ("GNU.sparse.minor", "0"),
+                    ("GNU.sparse.realsize", "twelve"),
+                ][..],
+                "invalid GNU.sparse.realsize `twelve`",
+            ),
+        ] {
+            let err = layout_with_records(records, b"abc").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert_eq!(err.to_string(), message);
+        }
+        // 其他 PAX 记录不影响条目的读取
+        assert_eq!(
+            layout_with_records(&[("mtime", "1700000000.5")], b"abc").unwrap(),
+            EntryLayout::Contiguous
+        );
+    }
+
+    #[test]
+    fn gnu_sparse_headers_are_written_back_as_regular_ones() {
+        let tar = FixtureArchive::new()
+            .sparse("gnu.btf", &[(512, b"data")], 1024)
+            .file("plain.btf", b"plain".to_vec())
+            .tar();
+        let mut archive = Archive::new(&tar[..]);
+        let mut entries = archive.entries().unwrap();
+        let sparse = entries.next().unwrap().unwrap();
+        assert!(sparse.header().entry_type().is_gnu_sparse());
+        assert!(is_file_entry(sparse.header().entry_type()));
+        let regular = regular_header(sparse.header());
+        assert_eq!(regular.entry_type(), EntryType::Regular);
+        assert_eq!(regular.mode().unwrap(), sparse.header().mode().unwrap());
+        assert_eq!(regular.mtime().unwrap(), 0);
+        drop(sparse);
+        let plain = entries.next().unwrap().unwrap();
+        assert_eq!(
+            regular_header(plain.header()).as_bytes(),
+            plain.header().as_bytes()
+        );
+    }
+}
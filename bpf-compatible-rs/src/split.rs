@@ -0,0 +1,297 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Merging the split btf of a kernel module into the btf of its kernel, for tools that
+//! can't resolve a split btf against a base.
+//!
+//! The types of a split btf are numbered after those of the base: the first one has the
+//! id of the number of base types plus one. Its string offsets below the size of the
+//! base string section refer to base strings, the others to its own strings, shifted by
+//! that size. Appending the split types to the base types, and the split strings to the
+//! base strings, thus keeps every id and offset valid; what's left is checking that the
+//! split btf fits the base it's merged with.
+use crate::{
+    btf::{
+        extra_size, read_u32, validate_btf_bytes, BtfHeaderInfo, BTF_HEADER_SIZE, BTF_KIND_ARRAY,
+        BTF_KIND_ENUM, BTF_KIND_ENUM64, BTF_KIND_STRUCT, BTF_KIND_UNION, BTF_MAGIC, BTF_TYPE_SIZE,
+        BTF_VERSION,
+    },
+    Error, Result,
+};
+
+/// Merge the split btf `split` into `base`, the btf of the kernel it was generated against
+///
+/// The result is a single btf holding the types of both, with the ids and names of the
+/// split types unchanged, so a type id of the split btf can be looked up in the result as
+/// is. Both blobs must pass [`validate_btf_bytes`], and so does the result.
+///
+/// Fails with [`Error::InvalidBtf`] if a split type refers to a type beyond those of the
+/// base and the split btf, or has a name offset outside of the string sections or in the
+/// middle of a string, or if pointers, modifiers or typedefs of the split btf loop, which
+/// is what a split btf merged with the wrong base looks like. The number of base types
+/// a split btf expects isn't recorded in it, so a wrong base may still go unnoticed.
+pub fn merge_split_btf(base: &[u8], split: &[u8]) -> Result<Vec<u8>> {
+    let base_info = validate_btf_bytes(base)?;
+    let split_info = validate_btf_bytes(split)?;
+    let base_types = section(base, &base_info, base_info.type_off, base_info.type_len);
+    let split_types = section(split, &split_info, split_info.type_off, split_info.type_len);
+    let base_strs = section(base, &base_info, base_info.str_off, base_info.str_len);
+    let split_strs = section(split, &split_info, split_info.str_off, split_info.str_len);
+    let mut refs = type_refs(base_types).map_err(|e| Error::InvalidBtf(format!("base: {}", e)))?;
+    let base_count = refs.len() as u64;
+    let split_refs =
+        type_refs(split_types).map_err(|e| Error::InvalidBtf(format!("split: {}", e)))?;
+    let total_count = base_count + split_refs.len() as u64;
+    let strs = [base_strs, split_strs].concat();
+    for (i, refs) in split_refs.iter().enumerate() {
+        let id = base_count + 1 + i as u64;
+        if let Some(type_id) = refs.types.iter().find(|v| **v as u64 > total_count) {
+            return Err(Error::InvalidBtf(format!(
+                "split type {} refers to type {}, but the base has {} types and the split btf {}",
+                id,
+                type_id,
+                base_count,
+                split_refs.len()
+            )));
+        }
+        for name_off in &refs.names {
+            let name_off = *name_off as usize;
+            if name_off >= strs.len() {
+                return Err(Error::InvalidBtf(format!(
+                    "split type {} has a name at offset {}, beyond the {} bytes of strings",
+                    id,
+                    name_off,
+                    strs.len()
+                )));
+            }
+            // 名字应从某个字符串的开头开始，否则多半是与错误的 base 合并
+            if name_off != 0 && name_off != base_strs.len() && strs[name_off - 1] != 0 {
+                return Err(Error::InvalidBtf(format!(
+                    "split type {} has a name at offset {}, in the middle of a string; \
+                     the base doesn't match",
+                    id, name_off
+                )));
+            }
+        }
+    }
+    refs.extend(split_refs);
+    if let Some(id) = find_loop(&refs).filter(|v| *v > base_count) {
+        return Err(Error::InvalidBtf(format!(
+            "split type {} loops through pointers, modifiers or typedefs; the base doesn't match",
+            id
+        )));
+    }
+    let type_len = base_types.len() + split_types.len();
+    let (Ok(type_len), Ok(str_len)) = (u32::try_from(type_len), u32::try_from(strs.len())) else {
+        return Err(Error::InvalidBtf("the merged btf is too large".into()));
+    };
+    let mut merged = Vec::with_capacity(BTF_HEADER_SIZE as usize + type_len as usize + strs.len());
+    merged.extend_from_slice(&BTF_MAGIC.to_ne_bytes());
+    merged.push(BTF_VERSION);
+    merged.push(0);
+    for field in [BTF_HEADER_SIZE, 0, type_len, type_len, str_len] {
+        merged.extend_from_slice(&field.to_ne_bytes());
+    }
+    merged.extend_from_slice(base_types);
+    merged.extend_from_slice(split_types);
+    merged.extend_from_slice(&strs);
+    validate_btf_bytes(&merged)?;
+    Ok(merged)
+}
+
+/// A section of a validated blob, at `off` from the end of the header
+fn section<'a>(bytes: &'a [u8], info: &BtfHeaderInfo, off: u32, len: u32) -> &'a [u8] {
+    let start = info.hdr_len as usize + off as usize;
+    &bytes[start..start + len as usize]
+}
+
+/// The type ids and name offsets one type refers to
+struct TypeRefs {
+    types: Vec<u32>,
+    names: Vec<u32>,
+    /// The type a pointer, modifier, typedef or array is of, which mustn't lead back to it
+    next: Option<u32>,
+}
+
+/// The id of a type from which the chain of [`TypeRefs::next`] loops, if any
+///
+/// Types are numbered from 1, in the order of `refs`.
+fn find_loop(refs: &[TypeRefs]) -> Option<u64> {
+    // 0：未访问，1：在当前链上，2：已确认不成环
+    let mut state = vec![0u8; refs.len()];
+    for start in 0..refs.len() {
+        let mut chain = vec![];
+        let mut current = start;
+        loop {
+            match state[current] {
+                1 => return Some(start as u64 + 1),
+                2 => break,
+                _ => {}
+            }
+            state[current] = 1;
+            chain.push(current);
+            match refs[current].next {
+                Some(id) if id != 0 && id as usize <= refs.len() => current = id as usize - 1,
+                _ => break,
+            }
+        }
+        chain.into_iter().for_each(|v| state[v] = 2);
+    }
+    None
+}
+
+/// Walk a type section, collecting what each type refers to
+///
+/// Unlike [`crate::btf::raw_types`], every kind is decoded in full, so no reference is
+/// missed.
+fn type_refs(section: &[u8]) -> std::result::Result<Vec<TypeRefs>, String> {
+    let mut types = vec![];
+    let mut offset = 0;
+    while offset < section.len() {
+        let truncated = || format!("truncated type at offset {}", offset);
+        let word = |i: usize| read_u32(section, offset + 4 * i).ok_or_else(truncated);
+        let name_off = word(0)?;
+        let type_info = word(1)?;
+        let size_or_type = word(2)?;
+        let kind = ((type_info >> 24) & 0x1f) as u8;
+        let vlen = (type_info & 0xffff) as usize;
+        let mut refs = TypeRefs {
+            types: vec![],
+            names: vec![name_off],
+            next: None,
+        };
+        let extra = extra_size(kind, vlen as u16)
+            .ok_or_else(|| format!("unknown type kind {} at offset {}", kind, offset))?;
+        if offset + BTF_TYPE_SIZE + extra > section.len() {
+            return Err(truncated());
+        }
+        let data = offset + BTF_TYPE_SIZE;
+        let at = |i: usize| read_u32(section, data + 4 * i).unwrap_or_default();
+        match kind {
+            // PTR, TYPEDEF, VOLATILE, CONST, RESTRICT, TYPE_TAG
+            2 | 8..=11 | 18 => {
+                refs.types.push(size_or_type);
+                refs.next = Some(size_or_type);
+            }
+            // FUNC, VAR, DECL_TAG
+            12 | 14 | 17 => refs.types.push(size_or_type),
+            // ARRAY: 元素类型和下标类型
+            BTF_KIND_ARRAY => {
+                refs.types.extend([at(0), at(1)]);
+                refs.next = Some(at(0));
+            }
+            // STRUCT, UNION: 成员的名字和类型
+            BTF_KIND_STRUCT | BTF_KIND_UNION => (0..vlen).for_each(|i| {
+                refs.names.push(at(3 * i));
+                refs.types.push(at(3 * i + 1));
+            }),
+            // ENUM, ENUM64: 枚举值的名字
+            BTF_KIND_ENUM => (0..vlen).for_each(|i| refs.names.push(at(2 * i))),
+            BTF_KIND_ENUM64 => (0..vlen).for_each(|i| refs.names.push(at(3 * i))),
+            // FUNC_PROTO: 返回值类型，以及参数的名字和类型
+            13 => {
+                refs.types.push(size_or_type);
+                (0..vlen).for_each(|i| {
+                    refs.names.push(at(2 * i));
+                    refs.types.push(at(2 * i + 1));
+                });
+            }
+            // DATASEC: 变量的类型
+            15 => (0..vlen).for_each(|i| refs.types.push(at(3 * i))),
+            _ => {}
+        }
+        types.push(refs);
+        offset += BTF_TYPE_SIZE + extra;
+    }
+    Ok(types)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        btf::{btf_arch, name_at, raw_types, BTF_KIND_INT},
+        fixture::{btf_of, btf_of_arch, minimal_valid_btf},
+    };
+
+    /// Kind 2, a pointer
+    const PTR: u32 = 2 << 24;
+    const STRUCT: u32 = (BTF_KIND_STRUCT as u32) << 24;
+
+    /// `btf_of_arch(8, "r15")`: `long` is type 1, `struct pt_regs` type 2, and its strings,
+    /// `\0long\0pt_regs\0r15\0`, take 18 bytes
+    fn base() -> Vec<u8> {
+        btf_of_arch(8, "r15")
+    }
+
+    /// A split btf of `base()`: a pointer to `struct pt_regs`, as type 3, and
+    /// `struct mod_state { struct pt_regs *regs; long count; }`, as type 4
+    fn split() -> Vec<u8> {
+        // 自身的字符串从 base 字符串的长度 18 开始编号
+        let strings = b"\0mod_state\0regs\0count\0";
+        btf_of(
+            &[0, PTR, 2, 19, STRUCT | 2, 16, 29, 3, 0, 34, 1, 64],
+            strings,
+        )
+    }
+
+    #[test]
+    fn split_types_are_found_by_their_own_ids() {
+        let merged = merge_split_btf(&base(), &split()).unwrap();
+        let info = validate_btf_bytes(&merged).unwrap();
+        let types = raw_types(&merged, &info).unwrap();
+        assert_eq!(types.len(), 4);
+        let name = |off| name_at(&merged, &info, off).unwrap();
+        let state = &types[3];
+        assert_eq!(
+            (state.kind, name(state.name_off), state.size_or_type),
+            (BTF_KIND_STRUCT, "mod_state", 16)
+        );
+        let members = state
+            .members
+            .iter()
+            .map(|v| (name(v.name_off), v.type_id))
+            .collect::<Vec<_>>();
+        assert_eq!(members, [("regs", 3), ("count", 1)]);
+        // 顺着指针回到 base 中的类型
+        let regs = &types[2];
+        assert_eq!((regs.kind, regs.size_or_type), (2, 2));
+        assert_eq!(name(types[1].name_off), "pt_regs");
+        assert_eq!(
+            (types[0].kind, name(types[0].name_off)),
+            (BTF_KIND_INT, "long")
+        );
+        // base 的类型不变，架构仍能识别
+        assert_eq!(btf_arch(&merged).unwrap(), btf_arch(&base()).unwrap());
+    }
+
+    #[test]
+    fn empty_split_btf_gives_the_base() {
+        let merged = merge_split_btf(&base(), &btf_of(&[], b"")).unwrap();
+        assert_eq!(merged, base());
+    }
+
+    #[test]
+    fn split_btf_of_another_base_is_refused() {
+        let invalid = |base: &[u8], split: &[u8]| match merge_split_btf(base, split) {
+            Err(Error::InvalidBtf(message)) => message,
+            v => panic!("{:?}", v.map(|v| v.len())),
+        };
+        // minimal_valid_btf 只有一个类型，5 字节的字符串
+        let message = invalid(&minimal_valid_btf(), &split());
+        assert!(message.contains("in the middle of a string"), "{message}");
+        let message = invalid(&minimal_valid_btf(), &btf_of(&[0, PTR, 5, 0, PTR, 2], b""));
+        assert!(message.contains("refers to type 5"), "{message}");
+        let message = invalid(&base(), &btf_of(&[40, PTR, 1], b"\0x\0"));
+        assert!(message.contains("beyond the 21 bytes"), "{message}");
+        // 与错误的 base 合并后，指针互相指向
+        let message = invalid(&base(), &btf_of(&[0, PTR, 4, 0, PTR, 3], b""));
+        assert!(message.contains("loops"), "{message}");
+        assert!(matches!(
+            merge_split_btf(&base(), b"not a btf"),
+            Err(Error::InvalidBtf(_))
+        ));
+    }
+}
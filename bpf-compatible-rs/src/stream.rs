@@ -0,0 +1,421 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Reading the btf of an entry as it's decompressed, for consumers that parse or hash it
+//! on the fly, without a temporary file or the whole btf in memory.
+//!
+//! The entry is located first: through the `INDEX` of the random-access layout, else by a
+//! pass over the tar headers, so the last of several entries with the same path wins, as
+//! with [`BtfhubArchive::extract`](crate::archive::BtfhubArchive::extract). A plain tar is then read in place; a compressed one is
+//! decompressed again, up to the entry and then along with the reads. Sparse entries and
+//! per-kernel tarballs are the exception, they're reassembled or unpacked in memory first.
+use std::{
+    collections::HashMap,
+    io::{Cursor, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{
+    archive::{normalize_entry_path, BtfEncoding, BtfhubArchive},
+    compression::{
+        tar_archive, tar_entries, tar_reader_with_limit, ArchiveFormat, LimitedReader,
+        DEFAULT_MAX_DECOMPRESSED_SIZE,
+    },
+    index::ArchiveIndex,
+    parsed::MAX_LINK_DEPTH,
+    sparse::{entry_layout, entry_path, is_file_entry, EntryLayout},
+    tarball::untar_btf,
+    Error, Result,
+};
+
+/// The btf of an entry, decompressed as it's read, see [`BtfhubArchive::open_btf`]
+///
+/// The reader ends exactly where the btf does. If the archive ends within the entry,
+/// reading fails with [`ErrorKind::UnexpectedEof`], and a corrupt gzipped btf fails as
+/// its decompression does. Unlike [`crate::TarballBtfArchive::extract`], the btf isn't
+/// validated, since it isn't read in full beforehand; see [`crate::btf::validate_btf_bytes`].
+pub struct BtfReader<'a> {
+    inner: Box<dyn Read + 'a>,
+    size: Option<u64>,
+}
+
+impl std::fmt::Debug for BtfReader<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BtfReader")
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// Where the contents of an entry are
+enum Location<'a> {
+    /// In place, in a plain tar
+    Slice(&'a [u8]),
+    /// At `offset` of the decompressed tar, `size` bytes long
+    At { offset: u64, size: u64 },
+    /// In a sparse entry at the path, which has to be reassembled
+    Sparse(PathBuf),
+}
+
+/// A file or link met while scanning the tar headers
+enum Scanned {
+    Contents { offset: u64, size: u64 },
+    Sparse,
+    Link(PathBuf),
+}
+
+impl<'a> BtfReader<'a> {
+    /// Open the entry at `path` of the archive held by `bytes`, whose btf is stored as `encoding`
+    ///
+    /// Links are followed. Fails with [`Error::EntryNotFound`] if there's no such entry.
+    pub(crate) fn open(
+        bytes: &'a [u8],
+        max_size: u64,
+        path: &Path,
+        encoding: BtfEncoding,
+    ) -> Result<Self> {
+        let (mut contents, size): (Box<dyn Read + 'a>, u64) = match locate(bytes, max_size, path)? {
+            Location::Slice(v) => (Box::new(v), v.len() as u64),
+            Location::At { offset, size } => {
+                let mut reader = tar_reader_with_limit(bytes, max_size)?;
+                // 解压并丢弃条目之前的内容
+                let skipped = std::io::copy(&mut (&mut reader).take(offset), &mut std::io::sink())
+                    .map_err(Error::from_read)?;
+                if skipped < offset {
+                    return Err(Error::TarReadError(ends_within(path)));
+                }
+                let reader = EntryReader {
+                    inner: reader,
+                    remaining: size,
+                    path: path.to_path_buf(),
+                };
+                (Box::new(reader), size)
+            }
+            Location::Sparse(resolved) => {
+                let contents = BtfhubArchive::new(bytes)
+                    .with_max_decompressed_size(max_size)
+                    .extract(resolved)?;
+                let size = contents.len() as u64;
+                (Box::new(Cursor::new(contents)), size)
+            }
+        };
+        Ok(match encoding {
+            BtfEncoding::Plain => Self {
+                inner: contents,
+                size: Some(size),
+            },
+            BtfEncoding::Gzipped => Self {
+                inner: Box::new(LimitedReader::new(
+                    GzDecoder::new(contents),
+                    DEFAULT_MAX_DECOMPRESSED_SIZE,
+                )),
+                size: None,
+            },
+            // 内层 tarball 只能整体解压
+            BtfEncoding::Tarball => {
+                let mut tarball = vec![];
+                contents
+                    .read_to_end(&mut tarball)
+                    .map_err(Error::from_read)?;
+                let btf = untar_btf(&tarball, path)?;
+                Self {
+                    size: Some(btf.len() as u64),
+                    inner: Box::new(Cursor::new(btf)),
+                }
+            }
+        })
+    }
+
+    /// Size of the btf, if known before reading it: it is, unless the btf is gzipped on its own
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+}
+
+impl Read for BtfReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// A reader of the `remaining` bytes of the contents of an entry, failing if the archive ends sooner
+struct EntryReader<R> {
+    inner: R,
+    remaining: u64,
+    path: PathBuf,
+}
+
+impl<R: Read> Read for EntryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.remaining == 0 {
+            return Ok(0);
+        }
+        let len = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(ends_within(&self.path));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+fn ends_within(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::UnexpectedEof,
+        format!("the archive ends within `{}`", path.display()),
+    )
+}
+
+/// Where the contents of the entry at `path` are, following links
+fn locate<'a>(bytes: &'a [u8], max_size: u64, path: &Path) -> Result<Location<'a>> {
+    let plain = ArchiveFormat::detect(bytes)? == ArchiveFormat::Tar;
+    // 随机访问布局中，索引直接给出条目的位置
+    if plain {
+        if let Some(contents) = ArchiveIndex::read(bytes).and_then(|v| v.locate(bytes, path)) {
+            return Ok(Location::Slice(contents));
+        }
+    }
+    let mut entries = HashMap::new();
+    let mut archive = tar_archive(tar_reader_with_limit(bytes, max_size)?);
+    for entry in tar_entries(&mut archive).map_err(Error::from_read)? {
+        let mut entry = entry.map_err(Error::from_read)?;
+        let entry_type = entry.header().entry_type();
+        let entry_path = entry_path(&mut entry).map_err(Error::from_read)?;
+        let scanned = if entry_type.is_hard_link() || entry_type.is_symlink() {
+            let Some(target) = entry.link_name().map_err(Error::from_read)? else {
+                continue;
+            };
+            let target = if entry_type.is_symlink() && target.is_relative() {
+                entry_path.parent().unwrap_or(Path::new("")).join(target)
+            } else {
+                target.into_owned()
+            };
+            Scanned::Link(normalize_entry_path(&target))
+        } else if !is_file_entry(entry_type) {
+            continue;
+        } else if entry_layout(&mut entry).map_err(Error::from_read)? != EntryLayout::Contiguous {
+            Scanned::Sparse
+        } else {
+            Scanned::Contents {
+                offset: entry.raw_file_position(),
+                size: entry.size(),
+            }
+        };
+        // 同一路径出现多次时后出现的条目生效
+        entries.insert(normalize_entry_path(&entry_path), scanned);
+    }
+    let mut path = normalize_entry_path(path);
+    // 限制跟随链接的次数，避免链接成环时无限循环
+    for _ in 0..MAX_LINK_DEPTH {
+        match entries.get(&path) {
+            Some(Scanned::Link(target)) => path = target.clone(),
+            Some(Scanned::Sparse) => return Ok(Location::Sparse(path)),
+            Some(&Scanned::Contents { offset, size }) if plain => {
+                let range = usize::try_from(offset)
+                    .ok()
+                    .zip(usize::try_from(size).ok())
+                    .and_then(|(start, size)| Some(start..start.checked_add(size)?));
+                return match range.and_then(|v| bytes.get(v)) {
+                    Some(v) => Ok(Location::Slice(v)),
+                    None => Err(Error::TarReadError(ends_within(&path))),
+                };
+            }
+            Some(&Scanned::Contents { offset, size }) => return Ok(Location::At { offset, size }),
+            None => {
+                return Err(Error::EntryNotFound {
+                    expected: path.clone(),
+                })
+            }
+        }
+    }
+    Err(Error::TooManyLinks(path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::{
+        archive::{BtfEntry, BtfEntryInfo},
+        fixture::{btf_of_arch, btf_with_hole, FixtureArchive},
+        layout::to_random_access,
+        sha256::{sha256, to_hex},
+        TarballBtfArchive,
+    };
+
+    const DIR: &str = "btfhub-archive/ubuntu/20.04/x86_64";
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// The btf each kernel of [`fixture`] has
+    fn btf_of(release: &str) -> Vec<u8> {
+        match release {
+            "5.4.0-47-generic" => btf_with_hole(),
+            v => btf_of_arch(8, v),
+        }
+    }
+
+    /// An archive with a btf stored each way, a link and a path stored twice
+    fn fixture() -> FixtureArchive {
+        let hole = btf_with_hole();
+        FixtureArchive::new()
+            .file(&format!("{DIR}/5.4.0-40-generic.btf"), b"stale".to_vec())
+            .file(
+                &format!("{DIR}/5.4.0-40-generic.btf"),
+                btf_of("5.4.0-40-generic"),
+            )
+            .symlink(
+                &format!("{DIR}/5.4.0-41-generic.btf"),
+                "5.4.0-40-generic.btf",
+            )
+            .file(
+                &format!("{DIR}/5.4.0-42-generic.btf.gz"),
+                gzip(&btf_of("5.4.0-42-generic")),
+            )
+            .file(
+                &format!("{DIR}/5.4.0-45-generic.btf.tar.gz"),
+                FixtureArchive::new()
+                    .file("5.4.0-45-generic.btf", btf_of("5.4.0-45-generic"))
+                    .gz(),
+            )
+            .sparse(
+                &format!("{DIR}/5.4.0-47-generic.btf"),
+                &[(0, &hole[..512]), (1536, &hole[1536..])],
+                hole.len() as u64,
+            )
+    }
+
+    /// Digest of what `reader` gives, read a few bytes at a time
+    fn streamed_digest(mut reader: impl Read) -> std::io::Result<String> {
+        let mut streamed = vec![];
+        let mut buf = [0; 7];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(to_hex(&sha256(&streamed))),
+                n => streamed.extend(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn streamed_btfs_hash_like_extracted_ones() {
+        let fixture = fixture();
+        let random_access = to_random_access(&fixture.tar(), Compression::fast()).unwrap();
+        for (format, bytes) in [
+            ("gz", fixture.gz()),
+            ("tar", fixture.tar()),
+            ("random access", random_access),
+        ] {
+            let archive = BtfhubArchive::new(&bytes);
+            let extracted =
+                TarballBtfArchive::from_bytes_with_limit(&bytes, DEFAULT_MAX_DECOMPRESSED_SIZE)
+                    .unwrap();
+            let mut kernels = vec![];
+            for entry in archive.entries() {
+                let BtfEntryInfo::Btf(entry) = entry.unwrap() else {
+                    continue;
+                };
+                let release = entry.kernel_release.clone();
+                let expected = btf_of(if release == "5.4.0-41-generic" {
+                    "5.4.0-40-generic"
+                } else {
+                    &release
+                });
+                let reader = archive.open_btf(&entry).unwrap();
+                // 只有单独压缩的 btf 事先不知道大小
+                let gzipped = entry.encoding == BtfEncoding::Gzipped;
+                assert_eq!(
+                    reader.size(),
+                    (!gzipped).then_some(expected.len() as u64),
+                    "{format} {release}"
+                );
+                let digest = streamed_digest(reader).unwrap();
+                assert_eq!(digest, to_hex(&sha256(&expected)), "{format} {release}");
+                assert_eq!(
+                    digest,
+                    to_hex(&sha256(&extracted.extract(&entry).unwrap())),
+                    "{format} {release}"
+                );
+                kernels.push(release);
+            }
+            kernels.sort();
+            kernels.dedup();
+            assert_eq!(kernels.len(), 5, "{format} {kernels:?}");
+        }
+    }
+
+    #[test]
+    fn truncated_archives_never_stream_a_short_btf() {
+        let btf = btf_of_arch(8, "long enough to be cut");
+        let fixture =
+            FixtureArchive::new().file(&format!("{DIR}/5.4.0-40-generic.btf"), btf.clone());
+        let entry =
+            BtfEntry::from_path(format!("{DIR}/5.4.0-40-generic.btf"), "btfhub-archive").unwrap();
+        // 截断在条目的内容中间：512 字节的头部之后
+        let tar = fixture.tar();
+        let cut = 512 + btf.len() / 2;
+        for bytes in [tar[..cut].to_vec(), gzip(&tar[..cut])] {
+            match BtfhubArchive::new(&bytes).open_btf(&entry) {
+                Err(e) => assert!(matches!(e, Error::TarReadError(_)), "{e:?}"),
+                Ok(reader) => {
+                    let err = streamed_digest(reader).unwrap_err();
+                    assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{err}");
+                }
+            }
+        }
+        // 压缩流本身在条目中间结束
+        let gz = fixture.gz();
+        let bytes = &gz[..gz.len() - 30];
+        if let Ok(reader) = BtfhubArchive::new(bytes).open_btf(&entry) {
+            assert!(streamed_digest(reader).is_err());
+        }
+
+        let mut reader = EntryReader {
+            inner: &b"short"[..],
+            remaining: 8,
+            path: PathBuf::from("a.btf"),
+        };
+        let mut buf = [0; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 5);
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.to_string(), "the archive ends within `a.btf`");
+        // 读到条目末尾后不再读取后面的内容
+        let mut reader = EntryReader {
+            inner: &b"exactnext entry"[..],
+            remaining: 5,
+            path: PathBuf::from("a.btf"),
+        };
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"exact");
+    }
+
+    #[test]
+    fn missing_entries_and_link_loops_are_errors() {
+        let tar = FixtureArchive::new()
+            .symlink(&format!("{DIR}/a.btf"), "b.btf")
+            .symlink(&format!("{DIR}/b.btf"), "a.btf")
+            .tar();
+        let archive = BtfhubArchive::new(&tar);
+        let open = |name: &str| {
+            archive
+                .open_btf(&BtfEntry::from_path(format!("{DIR}/{name}"), "btfhub-archive").unwrap())
+        };
+        assert!(matches!(open("a.btf"), Err(Error::TooManyLinks(_))));
+        assert!(matches!(open("c.btf"), Err(Error::EntryNotFound { .. })));
+    }
+}
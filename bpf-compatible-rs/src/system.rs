@@ -0,0 +1,687 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The identity of a system as far as btfhub is concerned: the distro from os-release
+//! (or what older systems have instead), and the machine and kernel from uname.
+#[cfg(feature = "host")]
+use std::process::Command;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{arch::normalize_arch, codename, derivative, distro, join_archive_path, Error, Result};
+
+/// Where os-release is looked for, in order, see os-release(5)
+pub const OS_RELEASE_PATHS: &[&str] = &["/etc/os-release", "/usr/lib/os-release"];
+/// Command printing the distro on systems predating os-release
+pub const LSB_RELEASE: &str = "lsb_release";
+/// Release files of old RHEL-like systems, looked for after `lsb_release`
+pub const REDHAT_RELEASE_PATHS: &[&str] = &["/etc/redhat-release", "/etc/system-release"];
+
+/// `ID` of os-release for the distributor ids printed by `lsb_release -si`, where they differ
+/// from the lowercased id
+const LSB_IDS: &[(&str, &str)] = &[
+    ("redhatenterpriseserver", "rhel"),
+    ("redhatenterpriseworkstation", "rhel"),
+    ("redhatenterprise", "rhel"),
+    ("oracleserver", "ol"),
+    ("centosstream", "centos-stream"),
+    ("suse", "sles"),
+    ("suselinux", "sles"),
+];
+
+/// (name prefix, `ID`, whether `VERSION_ID` is the major version only) of the distros
+/// writing `<name> release <version> (<codename>)` to a redhat-release file
+const REDHAT_RELEASES: &[(&str, &str, bool)] = &[
+    ("CentOS Stream", "centos-stream", true),
+    ("CentOS", "centos", true),
+    ("Red Hat Enterprise Linux", "rhel", false),
+    ("Fedora", "fedora", true),
+    ("Rocky Linux", "rocky", false),
+    ("AlmaLinux", "almalinux", false),
+    ("Oracle Linux", "ol", false),
+];
+
+/// Identity of the system a btf is looked up for
+///
+/// Its `Display` is the path of the btf relative to `btfhub-archive`, like
+/// `ubuntu/20.04/x86_64/5.4.0-40-generic.btf`, with the architecture named as in btfhub,
+/// see [`crate::generate_btf_archive_path_for`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SystemInfo {
+    /// `ID` of os-release, e.g. `ubuntu`
+    pub distro_id: String,
+    /// `VERSION_ID` of os-release, e.g. `20.04`
+    pub version_id: String,
+    /// `VERSION_CODENAME` of os-release, e.g. `focal`; empty if unknown
+    pub version_codename: String,
+    /// Machine as reported by uname, e.g. `x86_64`
+    pub arch: String,
+    /// Kernel release as reported by uname, e.g. `5.4.0-40-generic`
+    pub kernel_release: String,
+    /// Kernel version as reported by uname, e.g. `#44-Ubuntu SMP Tue Jun 23 00:01:04 UTC 2020`
+    pub kernel_version: String,
+}
+
+impl SystemInfo {
+    /// Gather the identity of the running system, from uname(2) and os-release
+    ///
+    /// Where no os-release exists (or it lacks the fields needed), the distro is taken from
+    /// `lsb_release`, then from a redhat-release file. If none of them works, the error
+    /// lists what was tried, or is [`Error::OsReleaseMissing`] if none of them exists.
+    #[cfg(feature = "host")]
+    pub fn detect() -> Result<Self> {
+        Self::detect_with_root("/")
+    }
+
+    /// Same as [`SystemInfo::detect`], with the files read relative to `root`
+    ///
+    /// For an agent in a container with the host's root mounted at e.g. `/host`, whose
+    /// os-release describes the container image rather than the host. uname still reports
+    /// the kernel, which is shared with the host. `lsb_release` is only run if `root` is `/`,
+    /// since it would describe the container.
+    #[cfg(feature = "host")]
+    pub fn detect_with_root(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let uname = uname()?;
+        #[cfg(feature = "fake-system")]
+        if let Some(fields) = crate::fake::fake_os_release() {
+            return Self::from_fields(&fields, uname.machine, uname.release, uname.version);
+        }
+        let mut attempts = vec![];
+        // 是否有来源存在，只是内容无法识别
+        let mut any_found = false;
+        for source in DistroSource::all() {
+            if matches!(source, DistroSource::Lsb) && root != Path::new("/") {
+                continue;
+            }
+            let fields = source.read(root);
+            any_found |= fields.is_ok() || source.exists(root);
+            let info = fields.and_then(|fields| {
+                Self::from_fields(
+                    &fields,
+                    uname.machine.clone(),
+                    uname.release.clone(),
+                    uname.version.clone(),
+                )
+                .map_err(|e| e.to_string())
+            });
+            match info {
+                Ok(v) => return Ok(v),
+                Err(e) => attempts.push(format!("{} ({})", source.name(root), e)),
+            }
+        }
+        if !any_found {
+            return Err(Error::OsReleaseMissing(under_root(
+                root,
+                OS_RELEASE_PATHS[0],
+            )));
+        }
+        Err(Error::DistroNotDetected(attempts.join(", ")))
+    }
+
+    /// Build the identity from the contents of an os-release file and what uname reports
+    ///
+    /// `ID` must be set. If `VERSION_ID` isn't, as on some testing releases, it's looked up
+    /// from `VERSION_CODENAME` with [`codename::version_of`]; if `VERSION_CODENAME` isn't set,
+    /// it's looked up from the version with [`codename::codename_of`]. Rolling distros, see
+    /// [`distro::is_rolling`], may have neither, and get an empty version.
+    ///
+    /// `ID` is mapped to the directory of the distro with [`distro::btfhub_distro_id`], e.g.
+    /// `openeuler` for `openEuler`, and `VERSION_ID` completed with [`distro::btfhub_version`].
+    /// CentOS Stream, whose `ID` is `centos`, is told apart by `Stream` in `NAME`, `VERSION`
+    /// or `PRETTY_NAME`, and identified as [`distro::CENTOS_STREAM`].
+    ///
+    /// A derivative distro without a directory in btfhub-archive is identified as the
+    /// distro of its `ID_LIKE` that has one, see [`derivative::btfhub_distro`]. Its version
+    /// is then the upstream's, from e.g. `UBUNTU_CODENAME`, or [`derivative::upstream_version`].
+    pub fn from_os_release(
+        os_release: &str,
+        arch: String,
+        kernel_release: String,
+        kernel_version: String,
+    ) -> Result<Self> {
+        Self::from_fields(
+            &parse_os_release(os_release),
+            arch,
+            kernel_release,
+            kernel_version,
+        )
+    }
+
+    /// Same as [`SystemInfo::from_os_release`], with the os-release fields already parsed
+    pub fn from_fields(
+        fields: &HashMap<String, String>,
+        arch: String,
+        kernel_release: String,
+        kernel_version: String,
+    ) -> Result<Self> {
+        let field = |key: &str| {
+            fields
+                .get(key)
+                .map(String::as_str)
+                .filter(|v| !v.is_empty())
+        };
+        let id = field("ID").ok_or(Error::MissingOsReleaseField("ID"))?;
+        // 部分发行版的 ID 与目录名不同，如 openEuler
+        let id = distro::btfhub_distro_id(id);
+        // CentOS Stream 的 ID 同样是 centos，只能从名称中区分
+        let is_stream = ["NAME", "VERSION", "PRETTY_NAME"]
+            .iter()
+            .any(|v| field(v).is_some_and(|v| v.contains("Stream")));
+        let id = if id == "centos" && is_stream {
+            distro::CENTOS_STREAM
+        } else {
+            id
+        };
+        let distro_id = derivative::btfhub_distro(id, field("ID_LIKE").unwrap_or_default());
+        let is_derivative = distro_id != id;
+        // 衍生发行版自身的版本号和代号对 btfhub 没有意义，改用其上游的（如 linuxmint 的 UBUNTU_CODENAME）
+        let upstream_codename = is_derivative
+            .then(|| {
+                field(&format!(
+                    "{}_CODENAME",
+                    distro_id.to_uppercase().replace('-', "_")
+                ))
+            })
+            .flatten();
+        let upstream_version = upstream_codename
+            .and_then(|v| codename::version_of(distro_id, v))
+            .or_else(|| derivative::upstream_version(id, field("VERSION_ID")?, distro_id));
+        let own_codename = (!is_derivative)
+            .then(|| field("VERSION_CODENAME"))
+            .flatten();
+        let own_version =
+            field("VERSION_ID").map(|v| distro::btfhub_version(id, v, field("VERSION")));
+        let version_id = upstream_version
+            .or(own_version.as_deref())
+            .or_else(|| codename::version_of(distro_id, own_codename?))
+            // 滚动发行版（如 Arch）没有版本号，按内核版本查找
+            .or_else(|| distro::is_rolling(distro_id).then_some(""))
+            .ok_or(Error::MissingOsReleaseField("VERSION_ID"))?;
+        let version_codename = upstream_codename
+            .or(own_codename)
+            .or_else(|| codename::codename_of(distro_id, version_id))
+            .unwrap_or_default();
+        if is_derivative {
+            log_at!(
+                Warn,
+                "{} has no btfs of its own, looking up {} {} of its ID_LIKE instead",
+                id,
+                distro_id,
+                version_id
+            );
+        }
+        Ok(Self {
+            distro_id: distro_id.to_string(),
+            version_id: version_id.to_string(),
+            version_codename: version_codename.to_string(),
+            arch,
+            kernel_release,
+            kernel_version,
+        })
+    }
+}
+
+/// The fields of uname a lookup uses
+#[cfg(feature = "host")]
+pub(crate) struct Uname {
+    pub release: String,
+    pub version: String,
+    pub machine: String,
+}
+
+/// What uname reports, with the values faked through `crate::fake` if built with the
+/// `fake-system` feature
+#[cfg(all(feature = "host", target_os = "linux"))]
+pub(crate) fn uname() -> Result<Uname> {
+    let uname = uname_rs::Uname::new().map_err(Error::UnameError)?;
+    #[allow(unused_mut)]
+    let mut uname = Uname {
+        release: uname.release,
+        version: uname.version,
+        machine: uname.machine,
+    };
+    #[cfg(feature = "fake-system")]
+    crate::fake::fake_uname(&mut uname);
+    Ok(uname)
+}
+
+/// Fails with [`Error::UnsupportedPlatform`]: there's no Linux kernel to identify, so every
+/// detection of the running system, and the lookups built on it, fail the same way
+#[cfg(all(feature = "host", not(target_os = "linux")))]
+pub(crate) fn uname() -> Result<Uname> {
+    Err(Error::UnsupportedPlatform)
+}
+
+/// Where the distro is read from, see [`SystemInfo::detect`]
+#[cfg(feature = "host")]
+#[derive(Debug, Clone, Copy)]
+enum DistroSource {
+    OsRelease(&'static str),
+    Lsb,
+    RedhatRelease(&'static str),
+}
+
+#[cfg(feature = "host")]
+impl DistroSource {
+    /// Every source, in the order they are tried
+    fn all() -> impl Iterator<Item = Self> {
+        OS_RELEASE_PATHS
+            .iter()
+            .map(|v| DistroSource::OsRelease(v))
+            .chain([DistroSource::Lsb])
+            .chain(
+                REDHAT_RELEASE_PATHS
+                    .iter()
+                    .map(|v| DistroSource::RedhatRelease(v)),
+            )
+    }
+
+    fn name(&self, root: &Path) -> String {
+        match self {
+            DistroSource::OsRelease(v) | DistroSource::RedhatRelease(v) => {
+                under_root(root, v).display().to_string()
+            }
+            DistroSource::Lsb => LSB_RELEASE.to_string(),
+        }
+    }
+
+    /// Whether the file of the source exists, even if it can't be read or recognized
+    fn exists(&self, root: &Path) -> bool {
+        match self {
+            DistroSource::OsRelease(v) | DistroSource::RedhatRelease(v) => {
+                under_root(root, v).exists()
+            }
+            DistroSource::Lsb => false,
+        }
+    }
+
+    /// The os-release fields the source provides, or why it couldn't be read
+    fn read(&self, root: &Path) -> std::result::Result<HashMap<String, String>, String> {
+        match self {
+            DistroSource::OsRelease(path) => std::fs::read_to_string(under_root(root, path))
+                .map(|v| parse_os_release(&v))
+                .map_err(|e| e.to_string()),
+            DistroSource::Lsb => {
+                let [id, release, codename] = ["-si", "-sr", "-sc"].map(run_lsb_release);
+                Ok(parse_lsb_release(
+                    &id?,
+                    &release?,
+                    &codename.unwrap_or_default(),
+                ))
+            }
+            DistroSource::RedhatRelease(path) => {
+                let contents =
+                    std::fs::read_to_string(under_root(root, path)).map_err(|e| e.to_string())?;
+                parse_redhat_release(&contents).ok_or_else(|| "unrecognized contents".to_string())
+            }
+        }
+    }
+}
+
+/// The absolute `path` as seen from the directory `root`
+pub fn under_root(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+#[cfg(feature = "host")]
+fn run_lsb_release(arg: &str) -> std::result::Result<String, String> {
+    let output = Command::new(LSB_RELEASE)
+        .arg(arg)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "`{} {}` failed: {}",
+            LSB_RELEASE, arg, output.status
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Turn the output of `lsb_release -si`, `-sr` and `-sc` into os-release fields
+///
+/// The distributor id is lowercased, with spaces removed, and mapped to its os-release
+/// `ID` where they differ, e.g. `RedHatEnterpriseServer` to `rhel`. `n/a` counts as unset.
+pub fn parse_lsb_release(id: &str, release: &str, codename: &str) -> HashMap<String, String> {
+    fn value(v: &str) -> Option<&str> {
+        Some(v.trim()).filter(|v| !v.is_empty() && *v != "n/a")
+    }
+    let id = value(id).map(|v| {
+        let id = v.to_lowercase().replace(' ', "");
+        LSB_IDS
+            .iter()
+            .find(|(lsb, _)| *lsb == id)
+            .map_or(id, |(_, v)| v.to_string())
+    });
+    [
+        ("ID", id),
+        ("VERSION_ID", value(release).map(str::to_string)),
+        ("VERSION_CODENAME", value(codename).map(str::to_lowercase)),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key.to_string(), value?)))
+    .collect()
+}
+
+/// Turn a redhat-release file like `CentOS Linux release 7.9.2009 (Core)` into os-release fields
+///
+/// `None` for a distro not in the table of known ones. Like os-release, `VERSION_ID` keeps
+/// at most major.minor, or only the major version for CentOS and Fedora.
+pub fn parse_redhat_release(contents: &str) -> Option<HashMap<String, String>> {
+    let line = contents.lines().next()?.trim();
+    let (name, rest) = line.split_once(" release ")?;
+    let (_, id, major_only) = REDHAT_RELEASES
+        .iter()
+        .find(|(prefix, _, _)| name.starts_with(prefix))?;
+    let version = rest.split_whitespace().next()?;
+    let components = if *major_only { 1 } else { 2 };
+    let version_id = version
+        .split('.')
+        .take(components)
+        .collect::<Vec<_>>()
+        .join(".");
+    Some(HashMap::from([
+        ("ID".to_string(), id.to_string()),
+        ("VERSION_ID".to_string(), version_id),
+    ]))
+}
+
+impl Display for SystemInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            join_archive_path(&[
+                &self.distro_id,
+                &self.version_id,
+                normalize_arch(&self.arch),
+                &format!("{}.btf", self.kernel_release),
+            ])
+        )
+    }
+}
+
+/// Parse the `KEY=value` assignments of an os-release file
+///
+/// Values may be unquoted, or quoted with `"` or `'`; inside double quotes `\"`, `\\`,
+/// `\$` and `` \` `` are unescaped. Blank lines, comments and lines that aren't
+/// assignments are skipped, and a later assignment overrides an earlier one.
+pub fn parse_os_release(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && !v.starts_with('#'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            Some((key.trim().to_string(), unquote(value.trim())))
+        })
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return inner.to_string();
+    }
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // 只有这几个字符需要转义，其余的反斜杠原样保留
+            '\\' => match chars.next() {
+                Some(v @ ('"' | '\\' | '$' | '`')) => unquoted.push(v),
+                Some(v) => {
+                    unquoted.push('\\');
+                    unquoted.push(v);
+                }
+                None => unquoted.push('\\'),
+            },
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ubuntu(os_release: &str) -> Result<SystemInfo> {
+        SystemInfo::from_os_release(
+            os_release,
+            "x86_64".into(),
+            "5.4.0-40-generic".into(),
+            "#44-Ubuntu SMP".into(),
+        )
+    }
+
+    #[test]
+    fn quoted_and_unquoted_values_are_parsed() {
+        let fields = parse_os_release(concat!(
+            "# comment\n",
+            "\n",
+            "NAME=\"Ubuntu\"\n",
+            "ID=ubuntu\n",
+            "  VERSION_ID = '20.04'  \n",
+            "PRETTY_NAME=\"Ubuntu \\\"Focal\\\" \\$HOME \\n\"\n",
+            "not an assignment\n",
+            "ID=ubuntu-core\n",
+        ));
+        assert_eq!(fields["NAME"], "Ubuntu");
+        assert_eq!(fields["VERSION_ID"], "20.04");
+        // 只有 \" \\ \$ \` 会被转义
+        assert_eq!(fields["PRETTY_NAME"], "Ubuntu \"Focal\" $HOME \\n");
+        // 后面的赋值覆盖前面的
+        assert_eq!(fields["ID"], "ubuntu-core");
+        assert_eq!(fields.len(), 4);
+    }
+
+    #[test]
+    fn identity_is_built_from_os_release_and_uname() {
+        let info = ubuntu("ID=ubuntu\nVERSION_ID=\"20.04\"\n").unwrap();
+        assert_eq!(
+            info,
+            SystemInfo {
+                distro_id: "ubuntu".into(),
+                version_id: "20.04".into(),
+                version_codename: "focal".into(),
+                arch: "x86_64".into(),
+                kernel_release: "5.4.0-40-generic".into(),
+                kernel_version: "#44-Ubuntu SMP".into(),
+            }
+        );
+        assert_eq!(info.to_string(), "ubuntu/20.04/x86_64/5.4.0-40-generic.btf");
+    }
+
+    #[test]
+    fn suse_products_are_looked_up_under_their_service_pack() {
+        for (os_release, path) in [
+            ("ID=\"sles\"\nVERSION_ID=\"15.4\"\n", "sles/15.4"),
+            ("ID=sles\nVERSION_ID=15-SP4\n", "sles/15.4"),
+            (
+                "ID=\"sles_sap\"\nVERSION_ID=\"15\"\nVERSION=\"15-SP4\"\n",
+                "sles/15.4",
+            ),
+            ("ID=sled\nVERSION_ID=\"12 SP5\"\n", "sles/12.5"),
+            ("ID=sle_hpc\nVERSION_ID=\"15.3\"\n", "sles/15.3"),
+            (
+                "ID=\"opensuse-leap\"\nVERSION_ID=\"15.4\"\n",
+                "opensuse-leap/15.4",
+            ),
+            // Leap 42 之前的 ID
+            ("ID=opensuse\nVERSION_ID=\"42.3\"\n", "opensuse-leap/42.3"),
+        ] {
+            let info = SystemInfo::from_os_release(
+                os_release,
+                "x86_64".into(),
+                "5.14.21-150400.24.63-default".into(),
+                "#1 SMP PREEMPT_DYNAMIC".into(),
+            )
+            .unwrap();
+            assert_eq!(
+                info.to_string(),
+                format!("{}/x86_64/5.14.21-150400.24.63-default.btf", path),
+                "{os_release}"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_fields_are_named_in_the_error() {
+        for os_release in ["VERSION_ID=20.04", "ID=\nVERSION_ID=20.04", ""] {
+            let e = ubuntu(os_release).unwrap_err();
+            assert!(matches!(e, Error::MissingOsReleaseField("ID")), "{e}");
+            assert_eq!(e.to_string(), "os-release has no `ID` field");
+        }
+        assert!(matches!(
+            ubuntu("ID=ubuntu"),
+            Err(Error::MissingOsReleaseField("VERSION_ID"))
+        ));
+        // 只有代号时从代号查出版本号
+        assert_eq!(
+            ubuntu("ID=ubuntu\nVERSION_CODENAME=focal")
+                .unwrap()
+                .version_id,
+            "20.04"
+        );
+    }
+
+    #[test]
+    fn lsb_release_output_is_mapped_to_os_release_fields() {
+        let fields = parse_lsb_release("RedHatEnterpriseServer\n", "7.9\n", "Maipo\n");
+        assert_eq!(fields["ID"], "rhel");
+        assert_eq!(fields["VERSION_ID"], "7.9");
+        assert_eq!(fields["VERSION_CODENAME"], "maipo");
+        let fields = parse_lsb_release("CentOSStream\n", "9\n", "n/a\n");
+        assert_eq!(fields["ID"], "centos-stream");
+        assert_eq!(fields["VERSION_ID"], "9");
+        for id in ["SUSE\n", "SUSELinux"] {
+            let fields = parse_lsb_release(id, "15.4\n", "n/a\n");
+            assert_eq!(fields["ID"], "sles", "{id}");
+            assert_eq!(fields["VERSION_ID"], "15.4");
+        }
+        let fields = parse_lsb_release("Ubuntu\n", "n/a\n", "");
+        assert_eq!(fields["ID"], "ubuntu");
+        assert!(!fields.contains_key("VERSION_ID"));
+        assert!(!fields.contains_key("VERSION_CODENAME"));
+    }
+
+    #[test]
+    fn redhat_release_files_are_parsed() {
+        for (contents, id, version) in [
+            ("CentOS Linux release 7.9.2009 (Core)\n", "centos", "7"),
+            ("CentOS Stream release 9\n", "centos-stream", "9"),
+            (
+                "Red Hat Enterprise Linux Server release 6.10 (Santiago)",
+                "rhel",
+                "6.10",
+            ),
+            ("Fedora release 38 (Thirty Eight)", "fedora", "38"),
+        ] {
+            let fields = parse_redhat_release(contents).unwrap();
+            assert_eq!((&*fields["ID"], &*fields["VERSION_ID"]), (id, version));
+        }
+        assert!(parse_redhat_release("Gentoo Base System release 2.14").is_none());
+        assert!(parse_redhat_release("").is_none());
+    }
+
+    #[cfg(all(feature = "host", target_os = "linux"))]
+    mod sources {
+        use super::*;
+
+        /// A root holding the given files, by their absolute paths
+        fn root_with(files: &[(&str, &str)]) -> tempfile::TempDir {
+            let root = tempfile::tempdir().unwrap();
+            for (path, contents) in files {
+                let path = under_root(root.path(), path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            }
+            root
+        }
+
+        fn detected(files: &[(&str, &str)]) -> (String, String) {
+            let root = root_with(files);
+            let info = SystemInfo::detect_with_root(root.path()).unwrap();
+            (info.distro_id, info.version_id)
+        }
+
+        #[test]
+        fn etc_os_release_comes_first() {
+            assert_eq!(
+                detected(&[
+                    ("/etc/os-release", "ID=ubuntu\nVERSION_ID=22.04"),
+                    ("/usr/lib/os-release", "ID=debian\nVERSION_ID=11"),
+                    ("/etc/redhat-release", "CentOS Linux release 7.9.2009"),
+                ]),
+                ("ubuntu".into(), "22.04".into())
+            );
+        }
+
+        #[test]
+        fn usr_lib_os_release_is_used_without_etc_one() {
+            assert_eq!(
+                detected(&[("/usr/lib/os-release", "ID=debian\nVERSION_ID=11")]),
+                ("debian".into(), "11".into())
+            );
+            // /etc/os-release 缺少 ID 时同样继续尝试下一个来源
+            assert_eq!(
+                detected(&[
+                    ("/etc/os-release", "NAME=broken"),
+                    ("/usr/lib/os-release", "ID=debian\nVERSION_ID=11"),
+                ]),
+                ("debian".into(), "11".into())
+            );
+        }
+
+        #[test]
+        fn redhat_release_is_the_last_resort() {
+            assert_eq!(
+                detected(&[("/etc/redhat-release", "CentOS release 6.10 (Final)")]),
+                ("centos".into(), "6".into())
+            );
+            assert_eq!(
+                detected(&[
+                    ("/etc/redhat-release", "Unknown release 1"),
+                    ("/etc/system-release", "Fedora release 38 (Thirty Eight)"),
+                ]),
+                ("fedora".into(), "38".into())
+            );
+        }
+
+        #[test]
+        fn roots_without_any_distro_file_miss_os_release() {
+            let root = root_with(&[]);
+            match SystemInfo::detect_with_root(root.path()) {
+                Err(Error::OsReleaseMissing(path)) => {
+                    assert_eq!(path, root.path().join("etc/os-release"))
+                }
+                other => panic!("{other:?}"),
+            }
+        }
+
+        #[test]
+        fn every_path_tried_is_listed_on_failure() {
+            let root = root_with(&[("/etc/redhat-release", "Unknown release 1")]);
+            let e = SystemInfo::detect_with_root(root.path()).unwrap_err();
+            assert!(matches!(e, Error::DistroNotDetected(_)), "{e}");
+            let message = e.to_string();
+            for path in OS_RELEASE_PATHS.iter().chain(REDHAT_RELEASE_PATHS) {
+                let path = under_root(root.path(), path);
+                assert!(message.contains(&*path.to_string_lossy()), "{message}");
+            }
+            assert!(message.contains("unrecognized contents"), "{message}");
+            // 非 / 的根目录下不运行 lsb_release，它描述的是当前容器
+            assert!(!message.contains(LSB_RELEASE), "{message}");
+        }
+    }
+}
@@ -0,0 +1,1212 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The lookup `bpf-compatible-sys` offers to C, for Rust users, e.g. of libbpf-rs: find the
+//! btf of a system in an archive, and write it where libbpf can read it.
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+#[cfg(target_os = "linux")]
+use std::{io::ErrorKind, os::unix::fs::PermissionsExt};
+
+use flate2::read::GzDecoder;
+use tar::Archive;
+
+use crate::{
+    archive::{normalize_entry_path, parse_btf_path, BtfEncoding, BtfEntry, BtfEntryInfo},
+    btf::validate_btf_bytes,
+    compression::{tar_entries, tar_reader, LimitedReader, DEFAULT_MAX_DECOMPRESSED_SIZE},
+    flat::parse_flat_btf_path,
+    listing::{ArchiveListing, LISTING_ENTRY_NAME},
+    matcher::BtfMatcher,
+    parsed::{IndexedEntry, ParsedArchive},
+    progress::Progress,
+    release::MatchPolicy,
+    sanitize::prepare_file_within,
+    sparse::{entry_path, is_file_entry, read_entry},
+    Error, Result, SystemInfo,
+};
+
+/// A btfhub archive, decompressed once for any number of lookups and extractions
+#[derive(Debug, Clone)]
+pub struct TarballBtfArchive {
+    parsed: ParsedArchive,
+    prefix: PathBuf,
+    /// The listing at the start of the archive, see [`crate::archive::BtfhubArchive::listing`]
+    listing: Option<ArchiveListing>,
+    any_distro: bool,
+}
+
+impl TarballBtfArchive {
+    /// Decompress an archive like the one `btfgen` generates
+    ///
+    /// Despite the name, any format of [`tar_reader`] is accepted, plain tars included.
+    pub fn from_gzipped_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_bytes_with_limit(bytes, DEFAULT_MAX_DECOMPRESSED_SIZE)
+    }
+
+    /// Same as [`TarballBtfArchive::from_gzipped_bytes`], failing if `bytes` decompresses to
+    /// more than `max_size` bytes, see [`ParsedArchive::parse_with_limit`]
+    pub fn from_bytes_with_limit(bytes: &[u8], max_size: u64) -> Result<Self> {
+        Ok(Self::from_parsed(ParsedArchive::parse_with_limit(
+            bytes, max_size,
+        )?))
+    }
+
+    /// Same as [`TarballBtfArchive::from_bytes_with_limit`], telling `progress` how much was
+    /// decompressed, see [`ParsedArchive::parse_with_progress`]
+    pub fn from_bytes_with_progress(
+        bytes: &[u8],
+        max_size: u64,
+        progress: &Progress,
+    ) -> Result<Self> {
+        Ok(Self::from_parsed(ParsedArchive::parse_with_progress(
+            bytes, max_size, progress,
+        )?))
+    }
+
+    fn from_parsed(parsed: ParsedArchive) -> Self {
+        Self {
+            listing: parsed.archive().listing(),
+            parsed,
+            prefix: PathBuf::from(crate::archive::BTFHUB_ARCHIVE_DIR),
+            any_distro: false,
+        }
+    }
+
+    /// Look for the btfs under `prefix` instead of `btfhub-archive`, see [`crate::archive::BtfhubArchive::with_prefix`]
+    pub fn with_prefix(mut self, prefix: impl AsRef<Path>) -> Self {
+        self.prefix = prefix.as_ref().to_path_buf();
+        self.parsed = self.parsed.with_prefix(&self.prefix);
+        self.listing = self.parsed.archive().listing();
+        self
+    }
+
+    /// Also look for the btf of the kernel release under `generic` and other distros, as
+    /// for rolling distros, see [`BtfMatcher::with_any_distro`]
+    pub fn with_any_distro(mut self, any_distro: bool) -> Self {
+        self.any_distro = any_distro;
+        self
+    }
+
+    /// The entry holding the btf of `info`
+    ///
+    /// The entries are matched as by `bpf-compatible-sys`, see [`BtfMatcher`]: the paths of
+    /// [`crate::generate_btf_archive_paths_for`] are tried in turn, in any encoding, then
+    /// the btfs at the root of a flat archive, skipping btfs of the other byte order than
+    /// the host's. Fails with [`Error::NotBtfhubArchive`] if nothing is under the prefix
+    /// nor a btf at the root, and [`Error::EntryNotFound`] if there is no btf for `info`.
+    pub fn lookup(&self, info: &SystemInfo) -> Result<BtfEntry> {
+        self.lookup_with_policy(info, MatchPolicy::Exact)
+    }
+
+    /// Same as [`TarballBtfArchive::lookup`], falling back as `policy` allows, see [`BtfMatcher::fallback`]
+    ///
+    /// If the archive has no btf for the kernel release itself, the nearest release next
+    /// to a candidate is looked for, see [`crate::release::nearest_release`], and for
+    /// `BestEffort` the release under another version of the distro, or under another
+    /// distro of the Enterprise Linux family.
+    pub fn lookup_with_policy(&self, info: &SystemInfo, policy: MatchPolicy) -> Result<BtfEntry> {
+        let prefix = normalize_entry_path(&self.prefix);
+        if !self.parsed.entries().iter().any(|v| {
+            let path = normalize_entry_path(&v.path);
+            path.starts_with(&prefix) || parse_flat_btf_path(&path).is_some()
+        }) {
+            return Err(Error::NotBtfhubArchive);
+        }
+        let matcher = BtfMatcher::new(info, &prefix, policy).with_any_distro(self.any_distro);
+        let entry = self
+            .parsed
+            .lookup_with(&matcher)
+            .and_then(|v| btf_entry(v, &prefix))
+            .ok_or_else(|| Error::EntryNotFound {
+                expected: crate::generate_btf_archive_path_for(info),
+            })?;
+        log_at!(Info, "Selected the btf {}", entry.path.display());
+        Ok(entry)
+    }
+
+    /// The btf of `entry`, decompressed according to its encoding and validated
+    ///
+    /// Links are followed. A btf that doesn't pass [`validate_btf_bytes`] is rejected. If
+    /// the archive has a listing that disagrees with the entry, a warning is logged, see
+    /// [`ArchiveListing::check`], but the entry is used as it is.
+    pub fn extract(&self, entry: &BtfEntry) -> Result<Vec<u8>> {
+        let contents = self.parsed.extract(&entry.path)?;
+        if let Some(listing) = &self.listing {
+            // 链接本身没有内容，按其指向的条目核对
+            let path = &self.parsed.resolve(&entry.path)?.path;
+            if !listing.check(path, contents) {
+                log_at!(
+                    Warn,
+                    "{} disagrees with the {} of the archive, which is stale",
+                    path.display(),
+                    LISTING_ENTRY_NAME
+                );
+            }
+        }
+        decode_btf(contents, entry.encoding, &entry.path)
+    }
+
+    /// The split btf of the kernel module `module` of `info`, validated
+    ///
+    /// It is looked for under the prefix at the paths of
+    /// [`crate::generate_module_btf_paths_for`], following links. Fails with
+    /// [`Error::EntryNotFound`] if the archive has none.
+    pub fn extract_module(&self, info: &SystemInfo, module: &str) -> Result<Vec<u8>> {
+        crate::pack::check_identity(&[module])?;
+        let entry = self.parsed.lookup_module(info, module).ok_or_else(|| {
+            // 与内核 btf 一样，只报告最优先的路径
+            let paths = crate::generate_module_btf_paths_for(info, module);
+            Error::EntryNotFound {
+                expected: paths.first().cloned().unwrap_or_default().into(),
+            }
+        })?;
+        log_at!(Info, "Selected the btf {}", entry.path.display());
+        let btf = self.parsed.extract(&entry.path)?;
+        validate_btf_bytes(btf)?;
+        Ok(btf.to_vec())
+    }
+
+    /// Write the btf of `entry` to `path`, replacing any file there, see [`TarballBtfArchive::extract_to_with`]
+    pub fn extract_to(&self, entry: &BtfEntry, path: &Path) -> Result<()> {
+        self.extract_to_with(entry, path, &ExtractOptions::default().with_overwrite(true))
+    }
+
+    /// Write the btf of `entry` to `path`, see [`TarballBtfArchive::extract`] and [`write_btf_to`]
+    pub fn extract_to_with(
+        &self,
+        entry: &BtfEntry,
+        path: &Path,
+        opts: &ExtractOptions,
+    ) -> Result<()> {
+        let btf = self.extract(entry)?;
+        write_btf_to(path, &btf, opts)?;
+        log_at!(
+            Info,
+            "Wrote the btf of {} to {}",
+            entry.path.display(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Write the btfs of the entries `selector` picks to `dest_dir`, as
+    /// `<distro>/<version>/<arch>/<kernel_release>.btf`
+    ///
+    /// `selector` is given the entries of [`crate::archive::BtfhubArchive::entries`]. Each
+    /// btf is decoded and validated as by [`TarballBtfArchive::extract`], then written with
+    /// [`write_btf_to`], replacing any file there. `dest_dir` is created if missing, and the
+    /// directories below it by [`prepare_file_within`], so no entry lands outside of it.
+    ///
+    /// A selected entry that isn't a btf, or whose kernel was already written, e.g. from
+    /// another encoding, is skipped. A btf that can't be decoded or written is recorded in
+    /// the report, and the others are still written. Selecting nothing isn't an error; only
+    /// failing to create `dest_dir` or to read the archive is.
+    pub fn extract_many(
+        &self,
+        selector: impl Fn(&BtfEntryInfo) -> bool,
+        dest_dir: &Path,
+    ) -> Result<ExtractReport> {
+        std::fs::create_dir_all(dest_dir)
+            .map_err(|e| Error::FileWriteError(dest_dir.display().to_string(), e))?;
+        let opts = ExtractOptions::default().with_overwrite(true);
+        let mut report = ExtractReport::default();
+        let mut written_kernels = HashSet::new();
+        for info in self.parsed.archive().entries() {
+            let info = info?;
+            if !selector(&info) {
+                continue;
+            }
+            let entry = match info {
+                BtfEntryInfo::Btf(v) if !written_kernels.contains(&v.kernel()) => v,
+                BtfEntryInfo::Btf(BtfEntry { path, .. }) | BtfEntryInfo::Other(path) => {
+                    report.skipped.push(path);
+                    continue;
+                }
+            };
+            let kernel = entry.kernel();
+            let written = self.extract(&entry).and_then(|btf| {
+                let path = prepare_file_within(dest_dir, Path::new(&format!("{}.btf", kernel)))?;
+                write_btf_to(&path, &btf, &opts)?;
+                Ok(path)
+            });
+            match written {
+                Ok(path) => {
+                    log_at!(
+                        Debug,
+                        "Wrote the btf of {} to {}",
+                        entry.path.display(),
+                        path.display()
+                    );
+                    written_kernels.insert(kernel);
+                    report.written.push(path);
+                }
+                Err(e) => {
+                    log_at!(Warn, "Failed to extract {}: {}", entry.path.display(), e);
+                    report.failed.push((entry.path, e));
+                }
+            }
+        }
+        log_at!(
+            Info,
+            "Extracted {} btfs to {}, skipped {}, failed {}",
+            report.written.len(),
+            dest_dir.display(),
+            report.skipped.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+}
+
+/// What [`TarballBtfArchive::extract_many`] did
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    /// The files written, in archive order
+    pub written: Vec<PathBuf>,
+    /// Paths of the selected entries left out, as stored in the archive
+    pub skipped: Vec<PathBuf>,
+    /// Paths of the selected btfs that couldn't be decoded or written, with why
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+/// Options of [`write_btf_to`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtractOptions {
+    overwrite: bool,
+    create_dirs: bool,
+}
+
+impl ExtractOptions {
+    /// Replace the file if it exists, instead of failing
+    pub fn with_overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Create the missing parent directories, with mode 0755
+    pub fn with_create_dirs(mut self, create_dirs: bool) -> Self {
+        self.create_dirs = create_dirs;
+        self
+    }
+}
+
+/// Write `btf` to exactly `path`, so readers never see a partial file
+///
+/// The btf is written to a temporary file in the same directory, which is then renamed to
+/// `path`; on any failure, nothing is left at `path` nor in the directory. The file gets
+/// mode 0644. Unless overwriting is allowed, an existing `path` fails with a
+/// [`Error::FileWriteError`] of kind [`std::io::ErrorKind::AlreadyExists`], even if it
+/// appears while writing.
+pub fn write_btf_to(path: &Path, btf: &[u8], opts: &ExtractOptions) -> Result<()> {
+    let write_error = |e| Error::FileWriteError(path.display().to_string(), e);
+    let dir = match path.parent().filter(|v| !v.as_os_str().is_empty()) {
+        Some(v) => v,
+        None => Path::new("."),
+    };
+    if opts.create_dirs {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::FileWriteError(dir.display().to_string(), e))?;
+    }
+    if !opts.overwrite && path.symlink_metadata().is_ok() {
+        #[cfg(target_os = "linux")]
+        let exists = std::io::Error::from_raw_os_error(libc::EEXIST);
+        #[cfg(not(target_os = "linux"))]
+        let exists = std::io::ErrorKind::AlreadyExists.into();
+        return Err(write_error(exists));
+    }
+    // 先写入同一目录下的临时文件再重命名，读者只会看到完整的文件
+    let mut file = tempfile::Builder::new()
+        .prefix(".bpf-compatible.")
+        .tempfile_in(dir)
+        .map_err(|e| Error::FileWriteError(dir.display().to_string(), unwrap_path_error(e)))?;
+    // 临时文件的权限是 0600，改为普通文件的 0644
+    #[cfg(target_os = "linux")]
+    file.as_file()
+        .set_permissions(std::fs::Permissions::from_mode(0o644))
+        .map_err(write_error)?;
+    file.write_all(btf)
+        .and_then(|_| file.as_file().sync_all())
+        .map_err(|e| write_error(unwrap_path_error(e)))?;
+    if opts.overwrite {
+        file.persist(path).map_err(|e| write_error(e.error))?;
+    } else {
+        // 检查之后才出现的文件也不会被覆盖
+        file.persist_noclobber(path)
+            .map_err(|e| write_error(e.error))?;
+    }
+    Ok(())
+}
+
+/// An error of tempfile, which adds the path to the error of the system call, with an errno again
+///
+/// The path is in the message of the caller's error anyway. tempfile keeps only the kind
+/// of the original error, so the errno C callers get is recovered from it.
+#[cfg(target_os = "linux")]
+fn unwrap_path_error(e: std::io::Error) -> std::io::Error {
+    if e.raw_os_error().is_some() {
+        return e;
+    }
+    let errno = match e.kind() {
+        ErrorKind::NotFound => libc::ENOENT,
+        ErrorKind::PermissionDenied => libc::EACCES,
+        ErrorKind::AlreadyExists => libc::EEXIST,
+        ErrorKind::NotADirectory => libc::ENOTDIR,
+        ErrorKind::StorageFull => libc::ENOSPC,
+        ErrorKind::ReadOnlyFilesystem => libc::EROFS,
+        _ => return e,
+    };
+    std::io::Error::from_raw_os_error(errno)
+}
+
+/// Errors are left as they are on other systems than Linux, where there's no C caller
+#[cfg(not(target_os = "linux"))]
+fn unwrap_path_error(e: std::io::Error) -> std::io::Error {
+    e
+}
+
+/// `entry` as a btf of the archive, if it's at `<prefix>/<distro>/<version>/<arch>/<release>.btf`
+fn btf_entry(entry: &IndexedEntry, prefix: &Path) -> Option<BtfEntry> {
+    let path = normalize_entry_path(&entry.path);
+    // 平坦归档根目录下的 btf 不属于任何发行版、版本和架构的目录
+    let (distro, version, arch, kernel_release, encoding) =
+        parse_btf_path(&path, prefix).or_else(|| {
+            let (kernel_release, encoding) = parse_flat_btf_path(&path)?;
+            Some((
+                String::new(),
+                String::new(),
+                String::new(),
+                kernel_release,
+                encoding,
+            ))
+        })?;
+    Some(BtfEntry {
+        distro,
+        version,
+        arch,
+        kernel_release,
+        path: entry.path.clone(),
+        size: entry.size,
+        encoding,
+        is_link: entry.link_target.is_some(),
+        byte_swapped: false,
+    })
+}
+
+/// The btf stored in `contents` with `encoding`, validated; `path` names it in errors
+pub(crate) fn decode_btf(contents: &[u8], encoding: BtfEncoding, path: &Path) -> Result<Vec<u8>> {
+    let btf = match encoding {
+        BtfEncoding::Plain => contents.to_vec(),
+        BtfEncoding::Gzipped => {
+            let mut btf = vec![];
+            LimitedReader::new(GzDecoder::new(contents), DEFAULT_MAX_DECOMPRESSED_SIZE)
+                .read_to_end(&mut btf)
+                .map_err(|e| Error::FileReadError(path.display().to_string(), e))?;
+            btf
+        }
+        BtfEncoding::Tarball => untar_btf(contents, path)?,
+    };
+    validate_btf_bytes(&btf)?;
+    Ok(btf)
+}
+
+/// The single `.btf` file of a per-kernel tarball, as in btfhub-archive
+pub(crate) fn untar_btf(tarball: &[u8], path: &Path) -> Result<Vec<u8>> {
+    let mut inner = Archive::new(tar_reader(tarball)?);
+    for entry in tar_entries(&mut inner).map_err(Error::from_read)? {
+        let mut entry = entry.map_err(Error::from_read)?;
+        let is_btf = is_file_entry(entry.header().entry_type())
+            && entry_path(&mut entry)
+                .map_err(Error::from_read)?
+                .extension()
+                .is_some_and(|v| v == "btf");
+        if is_btf {
+            return read_entry(&mut entry).map_err(Error::from_read);
+        }
+    }
+    Err(Error::EntryNotFound {
+        expected: path.join("*.btf"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::fixture::{btf_of_arch, btf_with_hole, minimal_valid_btf, FixtureArchive};
+
+    fn ubuntu(kernel_release: &str) -> SystemInfo {
+        SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "20.04".into(),
+            arch: "x86_64".into(),
+            kernel_release: kernel_release.into(),
+            ..Default::default()
+        }
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn archive() -> TarballBtfArchive {
+        let gz = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-26-generic",
+                btf_of_arch(8, "26"),
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "40"),
+            )
+            .file(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf.gz",
+                gzip(&btf_of_arch(8, "42")),
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-45-generic",
+                b"corrupt".to_vec(),
+            )
+            .gz();
+        TarballBtfArchive::from_gzipped_bytes(&gz).unwrap()
+    }
+
+    #[test]
+    fn lookup_returns_the_entry_of_the_system() {
+        let archive = archive();
+        let entry = archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(
+            (entry.kernel().as_str(), entry.encoding, entry.is_link),
+            (
+                "ubuntu/20.04/x86_64/5.4.0-40-generic",
+                BtfEncoding::Plain,
+                false
+            )
+        );
+        assert_eq!(
+            entry.path,
+            Path::new("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf")
+        );
+        assert_eq!(entry.size, btf_of_arch(8, "40").len() as u64);
+        assert!(matches!(
+            archive.lookup(&ubuntu("5.4.0-99-generic")),
+            Err(Error::EntryNotFound { expected })
+                if expected == Path::new("ubuntu/20.04/x86_64/5.4.0-99-generic.btf")
+        ));
+    }
+
+    #[test]
+    fn sles_service_pack_is_found_in_a_tree_laid_out_like_the_mirror() {
+        let mut tree = FixtureArchive::new();
+        for (distro, version, release) in [
+            ("sles", "15.3", "5.3.18-150300.59.106-default"),
+            ("sles", "15.4", "5.14.21-150400.24.60-default"),
+            ("sles", "15.4", "5.14.21-150400.24.63-default"),
+            ("sles", "15.4", "5.14.21-150400.24.63-preempt"),
+            ("sles", "15.5", "5.14.21-150500.55.19-default"),
+            ("opensuse-leap", "15.4", "5.14.21-150400.24.63-default"),
+            ("opensuse-leap", "15.1", "4.12.14-lp151.28.36-default"),
+        ] {
+            let name = format!("{distro} {version} {release}");
+            tree = tree.btf(distro, version, "x86_64", release, btf_of_arch(8, &name));
+        }
+        let archive = TarballBtfArchive::from_gzipped_bytes(&tree.gz()).unwrap();
+        let system = |os_release: &str, release: &str| {
+            SystemInfo::from_os_release(
+                os_release,
+                "x86_64".into(),
+                release.into(),
+                "#1 SMP".into(),
+            )
+            .unwrap()
+        };
+        let found = |info: &SystemInfo, policy| {
+            let entry = archive.lookup_with_policy(info, policy).unwrap();
+            String::from_utf8_lossy(&archive.extract(&entry).unwrap()).into_owned()
+        };
+
+        // SLES 15 SP4 与 SLES for SAP 15 SP4 都在 sles/15.4 下
+        for os_release in [
+            "ID=\"sles\"\nVERSION_ID=\"15.4\"\n",
+            "ID=sles\nVERSION_ID=15-SP4\n",
+            "ID=sles_sap\nVERSION_ID=15\nVERSION=\"15-SP4\"\n",
+        ] {
+            let info = system(os_release, "5.14.21-150400.24.63-default");
+            assert!(
+                found(&info, MatchPolicy::Exact).contains("sles 15.4 5.14.21-150400.24.63-default"),
+                "{os_release}"
+            );
+        }
+        // 没有的修订版取同一 flavor 中最近的较低版本，而不是 preempt
+        let info = system(
+            "ID=sles\nVERSION_ID=15-SP4\n",
+            "5.14.21-150400.24.64-default",
+        );
+        assert!(matches!(
+            archive.lookup_with_policy(&info, MatchPolicy::Exact),
+            Err(Error::EntryNotFound { .. })
+        ));
+        assert!(found(&info, MatchPolicy::SameFlavorNearest)
+            .contains("sles 15.4 5.14.21-150400.24.63-default"));
+        let info = system(
+            "ID=\"opensuse-leap\"\nVERSION_ID=\"15.1\"\n",
+            "4.12.14-lp151.28.40-default",
+        );
+        assert!(found(&info, MatchPolicy::SameFlavorNearest)
+            .contains("opensuse-leap 15.1 4.12.14-lp151.28.36-default"));
+    }
+
+    #[test]
+    fn flat_btfs_are_used_after_the_btfhub_tree() {
+        let flat = FixtureArchive::new()
+            .file("5.4.0-40-generic.btf", btf_of_arch(8, "flat40"))
+            .file("5.4.0-26-generic.btf.gz", gzip(&btf_of_arch(8, "flat26")))
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&flat).unwrap();
+        let entry = archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(entry.path, Path::new("5.4.0-40-generic.btf"));
+        assert_eq!(
+            (entry.distro.as_str(), entry.arch.as_str()),
+            ("", ""),
+            "a flat btf belongs to no directory"
+        );
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "flat40"));
+        // 相近的版本同样在根目录下查找
+        assert!(archive.lookup(&ubuntu("5.4.0-29-generic")).is_err());
+        let entry = archive
+            .lookup_with_policy(&ubuntu("5.4.0-29-generic"), MatchPolicy::SameFlavorNearest)
+            .unwrap();
+        assert_eq!(entry.kernel_release, "5.4.0-26-generic");
+        assert_eq!(entry.encoding, BtfEncoding::Gzipped);
+
+        // 同时含 btfhub 目录时，目录中的条目优先
+        let mixed = FixtureArchive::new()
+            .file("5.4.0-40-generic.btf", btf_of_arch(8, "flat40"))
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "hub40"),
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&mixed).unwrap();
+        let entry = archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(entry.kernel(), "ubuntu/20.04/x86_64/5.4.0-40-generic");
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "hub40"));
+        // 目录中没有的内核仍可用根目录下的 btf
+        let debian = SystemInfo {
+            distro_id: "debian".into(),
+            version_id: "11".into(),
+            ..ubuntu("5.4.0-40-generic")
+        };
+        let entry = archive.lookup(&debian).unwrap();
+        assert_eq!(entry.path, Path::new("5.4.0-40-generic.btf"));
+    }
+
+    #[test]
+    fn arch_marker_restricts_the_flat_btfs() {
+        let tar = |marker: &[u8]| {
+            FixtureArchive::new()
+                .file(crate::flat::ARCH_MARKER_NAME, marker.to_vec())
+                .file("5.15.0-1034-raspi.btf", btf_of_arch(8, "x0"))
+                .tar()
+        };
+        let aarch64 = SystemInfo {
+            arch: "aarch64".into(),
+            ..ubuntu("5.15.0-1034-raspi")
+        };
+        for marker in [&b"aarch64\n"[..], b"arm64"] {
+            let archive = TarballBtfArchive::from_gzipped_bytes(&tar(marker)).unwrap();
+            assert_eq!(
+                archive.lookup(&aarch64).unwrap().path,
+                Path::new("5.15.0-1034-raspi.btf")
+            );
+        }
+        let archive = TarballBtfArchive::from_gzipped_bytes(&tar(b"aarch64\n")).unwrap();
+        let x86_64 = ubuntu("5.15.0-1034-raspi");
+        assert!(matches!(
+            archive.lookup(&x86_64),
+            Err(Error::EntryNotFound { .. })
+        ));
+        assert!(archive
+            .lookup_with_policy(&x86_64, MatchPolicy::BestEffort)
+            .is_err());
+    }
+
+    #[test]
+    fn lookup_matches_like_the_c_api() {
+        let mut swapped = btf_of_arch(8, "swapped");
+        swapped.swap(0, 1);
+        let tree = FixtureArchive::new()
+            // HWE 内核在其所属版本的目录下
+            .btf(
+                "ubuntu",
+                "22.04",
+                "x86_64",
+                "5.15.0-76-generic",
+                btf_of_arch(8, "hwe"),
+            )
+            .file(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz",
+                gzip(&btf_of_arch(8, "gz")),
+            )
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-42-generic", swapped)
+            .btf(
+                "ubuntu",
+                "focal",
+                "x86_64",
+                "5.4.0-42-generic",
+                btf_of_arch(8, "focal"),
+            )
+            .btf(
+                "almalinux",
+                "8",
+                "x86_64",
+                "4.18.0-372.9.1.el8.x86_64",
+                btf_of_arch(8, "almalinux"),
+            )
+            .btf(
+                "fedora",
+                "38",
+                "x86_64",
+                "6.1.0-1-default",
+                btf_of_arch(8, "fedora"),
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&tree).unwrap();
+        let found = |archive: &TarballBtfArchive, info: &SystemInfo, policy| {
+            let entry = archive.lookup_with_policy(info, policy).unwrap();
+            archive.extract(&entry).unwrap()
+        };
+        assert_eq!(
+            found(&archive, &ubuntu("5.4.0-40-generic"), MatchPolicy::Exact),
+            btf_of_arch(8, "gz")
+        );
+        assert_eq!(
+            found(&archive, &ubuntu("5.15.0-76-generic"), MatchPolicy::Exact),
+            btf_of_arch(8, "hwe")
+        );
+        // 字节序不符的 btf 跳过，排在后面的代号目录中的候选胜出
+        assert_eq!(
+            found(&archive, &ubuntu("5.4.0-42-generic"), MatchPolicy::Exact),
+            btf_of_arch(8, "focal")
+        );
+        // 企业版 Linux 系列只在 BestEffort 时互相借用
+        let rocky = SystemInfo {
+            distro_id: "rocky".into(),
+            version_id: "8.6".into(),
+            ..ubuntu("4.18.0-372.9.1.el8.x86_64")
+        };
+        assert!(archive.lookup(&rocky).is_err());
+        assert_eq!(
+            found(&archive, &rocky, MatchPolicy::BestEffort),
+            btf_of_arch(8, "almalinux")
+        );
+        // 其他发行版的同一内核只在要求时使用
+        let debian = SystemInfo {
+            distro_id: "debian".into(),
+            version_id: "12".into(),
+            ..ubuntu("6.1.0-1-default")
+        };
+        assert!(archive.lookup(&debian).is_err());
+        let archive = archive.with_any_distro(true);
+        assert_eq!(
+            found(&archive, &debian, MatchPolicy::Exact),
+            btf_of_arch(8, "fedora")
+        );
+    }
+
+    #[test]
+    fn btfs_of_32_bit_machines_are_found() {
+        // btfhub 的 x86 目录，以及以 uname 名字命名 arm 目录的镜像
+        let gz = FixtureArchive::new()
+            .btf(
+                "debian",
+                "10",
+                "x86",
+                "4.19.0-21-686-pae",
+                btf_of_arch(4, "bx"),
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "armv7l",
+                "5.4.0-1069-raspi",
+                btf_of_arch(4, "uregs"),
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&gz).unwrap();
+        let i686 = SystemInfo {
+            distro_id: "debian".into(),
+            version_id: "10".into(),
+            arch: "i686".into(),
+            kernel_release: "4.19.0-21-686-pae".into(),
+            ..Default::default()
+        };
+        let entry = archive.lookup(&i686).unwrap();
+        assert_eq!(entry.kernel(), "debian/10/x86/4.19.0-21-686-pae");
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(4, "bx"));
+        let armv7l = SystemInfo {
+            arch: "armv7l".into(),
+            ..ubuntu("5.4.0-1069-raspi")
+        };
+        let entry = archive.lookup(&armv7l).unwrap();
+        assert_eq!(entry.kernel(), "ubuntu/20.04/armv7l/5.4.0-1069-raspi");
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(4, "uregs"));
+        // 64 位的 x86 不会匹配 32 位的目录
+        let x86_64 = SystemInfo {
+            arch: "x86_64".into(),
+            ..i686
+        };
+        assert!(matches!(
+            archive.lookup(&x86_64),
+            Err(Error::EntryNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn rolling_distros_are_looked_up_by_kernel_release() {
+        let gz = FixtureArchive::new()
+            .file(
+                "btfhub-archive/generic/x86_64/6.5.3-arch1-1.btf",
+                btf_of_arch(8, "generic"),
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&gz).unwrap();
+        let arch = SystemInfo {
+            distro_id: "arch".into(),
+            version_id: "".into(),
+            arch: "x86_64".into(),
+            kernel_release: "6.5.3-arch1-1".into(),
+            ..Default::default()
+        };
+        let entry = archive.lookup(&arch).unwrap();
+        assert_eq!(entry.kernel(), "generic/x86_64/6.5.3-arch1-1");
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "generic"));
+        // 非滚动发行版不查找 generic 目录
+        let ubuntu = SystemInfo {
+            distro_id: "ubuntu".into(),
+            version_id: "23.10".into(),
+            ..arch
+        };
+        assert!(matches!(
+            archive.lookup(&ubuntu),
+            Err(Error::EntryNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn archive_without_btfs_is_not_a_btfhub_archive() {
+        let gz = FixtureArchive::new()
+            .file("README.md", b"btfs".to_vec())
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&gz).unwrap();
+        assert!(matches!(
+            archive.lookup(&ubuntu("5.4.0-40-generic")),
+            Err(Error::NotBtfhubArchive)
+        ));
+        assert!(TarballBtfArchive::from_gzipped_bytes(b"garbage").is_err());
+    }
+
+    #[test]
+    fn btfs_are_decoded_and_validated() {
+        let archive = archive();
+        let entry = archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "40"));
+        // 单独压缩的条目按其后缀解压
+        let gzipped = BtfEntry::from_path(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf.gz",
+            "btfhub-archive",
+        )
+        .unwrap();
+        assert_eq!(gzipped.encoding, BtfEncoding::Gzipped);
+        assert_eq!(archive.extract(&gzipped).unwrap(), btf_of_arch(8, "42"));
+        let corrupt = archive.lookup(&ubuntu("5.4.0-45-generic")).unwrap();
+        assert!(archive.extract(&corrupt).is_err());
+    }
+
+    #[test]
+    fn extract_to_replaces_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vmlinux.btf");
+        fs::write(&path, b"old").unwrap();
+        let archive = archive();
+        let entry = archive.lookup(&ubuntu("5.4.0-26-generic")).unwrap();
+        archive.extract_to(&entry, &path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "26"));
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o644
+        );
+        // 无效的 btf 不会覆盖已有的文件，也不留下临时文件
+        let corrupt = archive.lookup(&ubuntu("5.4.0-45-generic")).unwrap();
+        assert!(archive.extract_to(&corrupt, &path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "26"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    /// Names of the entries of `dir`, sorted, to see that no temporary file is left
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn existing_files_are_replaced_only_when_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vmlinux.btf");
+        let opts = ExtractOptions::default();
+        write_btf_to(&path, &btf_of_arch(8, "first"), &opts).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "first"));
+        let exists = write_btf_to(&path, &btf_of_arch(8, "again"), &opts).unwrap_err();
+        assert!(
+            matches!(&exists, Error::FileWriteError(p, e)
+                if p == &path.display().to_string() && e.raw_os_error() == Some(libc::EEXIST)),
+            "{exists:?}"
+        );
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "first"));
+        // 悬空的符号链接同样算作已有的文件
+        let dangling = dir.path().join("dangling.btf");
+        std::os::unix::fs::symlink("missing", &dangling).unwrap();
+        assert!(write_btf_to(&dangling, &minimal_valid_btf(), &opts).is_err());
+        assert!(!dir.path().join("missing").exists());
+
+        let overwrite = opts.with_overwrite(true);
+        write_btf_to(&path, &btf_of_arch(8, "again"), &overwrite).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "again"));
+        assert_eq!(names(dir.path()), ["dangling.btf", "vmlinux.btf"]);
+        // 替换的是链接本身，而不是它指向的文件
+        write_btf_to(&dangling, &minimal_valid_btf(), &overwrite).unwrap();
+        assert!(!dangling.symlink_metadata().unwrap().is_symlink());
+        assert!(!dir.path().join("missing").exists());
+    }
+
+    #[test]
+    fn missing_parent_directories_are_created_only_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/myagent/vmlinux.btf");
+        let missing =
+            write_btf_to(&path, &minimal_valid_btf(), &ExtractOptions::default()).unwrap_err();
+        assert!(
+            matches!(&missing, Error::FileWriteError(_, e) if e.raw_os_error() == Some(libc::ENOENT)),
+            "{missing:?}"
+        );
+        assert_eq!(names(dir.path()), Vec::<String>::new());
+        let create_dirs = ExtractOptions::default().with_create_dirs(true);
+        write_btf_to(&path, &minimal_valid_btf(), &create_dirs).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), minimal_valid_btf());
+        assert_eq!(
+            fs::metadata(dir.path().join("run/myagent"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777,
+            0o755 & !current_umask()
+        );
+        // 目录已经存在时没有影响
+        let other = dir.path().join("run/myagent/other.btf");
+        write_btf_to(&other, &minimal_valid_btf(), &create_dirs).unwrap();
+        assert_eq!(
+            names(&dir.path().join("run/myagent")),
+            ["other.btf", "vmlinux.btf"]
+        );
+    }
+
+    /// The umask of the process, which the created directories are subject to
+    fn current_umask() -> u32 {
+        use std::os::unix::fs::DirBuilderExt;
+        let dir = tempfile::tempdir().unwrap();
+        let probe = dir.path().join("probe");
+        std::fs::DirBuilder::new()
+            .mode(0o777)
+            .create(&probe)
+            .unwrap();
+        0o777 & !(fs::metadata(&probe).unwrap().permissions().mode() & 0o777)
+    }
+
+    #[test]
+    fn failed_writes_leave_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        // 目标是目录时重命名失败，临时文件随之删除
+        let taken = dir.path().join("vmlinux.btf");
+        fs::create_dir(&taken).unwrap();
+        fs::write(taken.join("inside"), b"kept").unwrap();
+        let overwrite = ExtractOptions::default().with_overwrite(true);
+        assert!(write_btf_to(&taken, &minimal_valid_btf(), &overwrite).is_err());
+        assert!(taken.is_dir());
+        assert_eq!(fs::read(taken.join("inside")).unwrap(), b"kept");
+        assert_eq!(names(dir.path()), ["vmlinux.btf"]);
+        // 父路径是文件时，创建目录和临时文件都失败
+        let file = dir.path().join("file");
+        fs::write(&file, b"plain").unwrap();
+        for opts in [overwrite, overwrite.with_create_dirs(true)] {
+            let e =
+                write_btf_to(&file.join("vmlinux.btf"), &minimal_valid_btf(), &opts).unwrap_err();
+            assert!(matches!(e, Error::FileWriteError(..)), "{e:?}");
+        }
+        assert_eq!(fs::read(&file).unwrap(), b"plain");
+        assert_eq!(names(dir.path()), ["file", "vmlinux.btf"]);
+    }
+
+    #[test]
+    fn extract_to_with_honors_the_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("btf/vmlinux.btf");
+        let archive = archive();
+        let entry = archive.lookup(&ubuntu("5.4.0-40-generic")).unwrap();
+        let opts = ExtractOptions::default();
+        assert!(archive.extract_to_with(&entry, &path, &opts).is_err());
+        let opts = opts.with_create_dirs(true);
+        archive.extract_to_with(&entry, &path, &opts).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "40"));
+        let other = archive.lookup(&ubuntu("5.4.0-26-generic")).unwrap();
+        assert!(archive.extract_to_with(&other, &path, &opts).is_err());
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "40"));
+        archive
+            .extract_to_with(&other, &path, &opts.with_overwrite(true))
+            .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "26"));
+    }
+
+    #[test]
+    fn ensure_core_btf_writes_the_btf_only_without_native_btf() {
+        let host = SystemInfo::detect().unwrap();
+        let gz = FixtureArchive::new()
+            .file(&format!("btfhub-archive/{host}"), minimal_valid_btf())
+            .gz();
+        let btf = crate::ensure_core_btf(&gz).unwrap();
+        if Path::new(crate::VMLINUX_BTF_PATH).exists() {
+            assert!(btf.is_none());
+            return;
+        }
+        let btf = btf.unwrap();
+        assert_eq!(fs::read(btf.path()).unwrap(), minimal_valid_btf());
+        let path = btf.path().to_path_buf();
+        drop(btf);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn module_btfs_are_extracted() {
+        let gz = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "40"),
+            )
+            .module_btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                "nf_tables",
+                btf_of_arch(8, "nft"),
+            )
+            // 模块的 btf 同样可以是链接
+            .symlink(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic/modules/nf_conntrack.btf",
+                "nf_tables.btf",
+            )
+            .module_btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                "broken",
+                b"not a btf".to_vec(),
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&gz).unwrap();
+        let info = ubuntu("5.4.0-40-generic");
+        assert_eq!(
+            archive.extract_module(&info, "nf_tables").unwrap(),
+            btf_of_arch(8, "nft")
+        );
+        assert_eq!(
+            archive.extract_module(&info, "nf_conntrack").unwrap(),
+            btf_of_arch(8, "nft")
+        );
+        match archive.extract_module(&info, "xfs") {
+            Err(Error::EntryNotFound { expected }) => assert_eq!(
+                expected,
+                Path::new("ubuntu/20.04/x86_64/5.4.0-40-generic/modules/xfs.btf")
+            ),
+            v => panic!("{:?}", v),
+        }
+        // 其他内核的模块不会被使用
+        assert!(matches!(
+            archive.extract_module(&ubuntu("5.4.0-42-generic"), "nf_tables"),
+            Err(Error::EntryNotFound { .. })
+        ));
+        assert!(matches!(
+            archive.extract_module(&info, "broken"),
+            Err(Error::InvalidBtf(_))
+        ));
+        for module in ["", "..", "../5.4.0-40-generic", "modules/nf_tables"] {
+            assert!(
+                matches!(
+                    archive.extract_module(&info, module),
+                    Err(Error::InvalidEntryName(_))
+                ),
+                "{:?}",
+                module
+            );
+        }
+        // 模块的 btf 不是内核的 btf
+        assert_eq!(
+            crate::archive::BtfhubArchive::new(&gz).kernels().unwrap(),
+            ["ubuntu/20.04/x86_64/5.4.0-40-generic"]
+        );
+    }
+
+    #[test]
+    fn module_btfs_of_sysfs_come_first() {
+        let host = SystemInfo::detect().unwrap();
+        let gz = FixtureArchive::new()
+            .module_btf(
+                &host.distro_id,
+                &host.version_id,
+                &host.arch,
+                &host.kernel_release,
+                "nf_tables",
+                btf_of_arch(8, "archived"),
+            )
+            .gz();
+        let sysfs = tempfile::tempdir().unwrap();
+
+        // 内核没有 btf 时从归档中提取
+        let btf = crate::ensure_module_btf_in(&gz, "nf_tables", sysfs.path())
+            .unwrap()
+            .unwrap();
+        assert!(!btf.path().starts_with(sysfs.path()));
+        assert_eq!(fs::read(btf.path()).unwrap(), btf_of_arch(8, "archived"));
+        let extracted = btf.path().to_path_buf();
+        drop(btf);
+        assert!(!extracted.exists());
+        assert!(matches!(
+            crate::ensure_module_btf_in(&gz, "xfs", sysfs.path()),
+            Err(Error::EntryNotFound { .. })
+        ));
+
+        // 内核有 btf、但模块没有时交给 libbpf
+        fs::write(sysfs.path().join("vmlinux"), minimal_valid_btf()).unwrap();
+        assert!(crate::ensure_module_btf_in(&gz, "nf_tables", sysfs.path())
+            .unwrap()
+            .is_none());
+
+        // 内核导出的模块 btf 直接使用，不会被删除
+        let exported = sysfs.path().join("nf_tables");
+        fs::write(&exported, btf_of_arch(8, "sysfs")).unwrap();
+        let btf = crate::ensure_module_btf_in(&gz, "nf_tables", sysfs.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(btf.path(), exported);
+        drop(btf);
+        assert!(exported.exists());
+
+        // 模块名不能指向目录之外
+        assert!(matches!(
+            crate::ensure_module_btf_in(&gz, "../vmlinux", sysfs.path()),
+            Err(Error::InvalidEntryName(_))
+        ));
+    }
+
+    #[test]
+    fn stale_listing_is_only_warned_about() {
+        let mut listing = ArchiveListing::default();
+        listing.push(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            &btf_of_arch(8, "old"),
+        );
+        let gz = FixtureArchive::new()
+            .file("btfhub-archive/manifest.json", listing.to_json())
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "40"),
+            )
+            .symlink(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+                "5.4.0-40-generic.btf",
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-42-generic",
+                btf_of_arch(8, "42"),
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&gz).unwrap();
+        assert!(archive.listing.is_some());
+        // 清单中的条目过时、清单漏列的条目以及链接，都以 tar 中的内容为准
+        for (release, btf) in [
+            ("5.4.0-40-generic", btf_of_arch(8, "40")),
+            ("5.4.0-41-generic", btf_of_arch(8, "40")),
+            ("5.4.0-42-generic", btf_of_arch(8, "42")),
+        ] {
+            let entry = archive.lookup(&ubuntu(release)).unwrap();
+            assert_eq!(archive.extract(&entry).unwrap(), btf, "{release}");
+        }
+    }
+
+    #[test]
+    fn sparse_btfs_are_extracted_byte_for_byte() {
+        let btf = btf_with_hole();
+        let chunks: &[(u64, &[u8])] = &[(0, &btf[..512]), (1536, &btf[1536..])];
+        let gz = FixtureArchive::new()
+            .sparse(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+                chunks,
+                btf.len() as u64,
+            )
+            .pax_sparse(
+                "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf",
+                chunks,
+                btf.len() as u64,
+            )
+            .gz();
+        let archive = TarballBtfArchive::from_gzipped_bytes(&gz).unwrap();
+        for release in ["5.4.0-40-generic", "5.4.0-42-generic"] {
+            let entry = archive.lookup(&ubuntu(release)).unwrap();
+            assert_eq!(entry.size, btf.len() as u64, "{release}");
+            assert_eq!(archive.extract(&entry).unwrap(), btf, "{release}");
+            assert_eq!(
+                crate::archive::BtfhubArchive::new(&gz)
+                    .extract(&entry.path)
+                    .unwrap(),
+                btf,
+                "{release}"
+            );
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `VERSION_ID` of os-release doesn't always match the directory btfhub-archive uses for
+//! the release: RHEL-likes report `8.7` where the tree has `8`, and some Ubuntu images
+//! report the point release `20.04.6`. Kylin reports `V10`, and openEuler's LTS releases
+//! carry a suffix, see [`crate::distro::btfhub_version`], which also turns SUSE's `15-SP4`
+//! into the `15.4` of the tree.
+//!
+//! Ubuntu's HWE kernels are the series of a later release, e.g. `5.15` of `22.04` on
+//! `20.04`, so their btfs may be in the directory of that release. Debian's backports
+//! kernels, like `6.1.0-0.deb11.13-amd64` on `11`, are builds of a later release's kernel,
+//! which btfhub doesn't carry.
+
+use crate::release::KernelRelease;
+
+/// How the version directory of a distro is derived from its `VERSION_ID`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionRule {
+    /// Keep `major.minor`, e.g. `20.04` for `20.04.6`
+    MajorMinor,
+    /// Keep only the major version, e.g. `8` for `8.7`
+    Major,
+    /// Keep the leading number, without a `V` or a suffix, e.g. `10` for `V10` and
+    /// `22.03` for `22.03-LTS-SP1`
+    Numeric,
+    /// Use `VERSION_ID` as is
+    Verbatim,
+}
+
+/// Rule of each distro, by `ID`; distros not listed are `Verbatim`
+const VERSION_RULES: &[(&str, VersionRule)] = &[
+    ("ubuntu", VersionRule::MajorMinor),
+    ("centos", VersionRule::Major),
+    ("centos-stream", VersionRule::Major),
+    ("rhel", VersionRule::Major),
+    ("ol", VersionRule::Major),
+    ("rocky", VersionRule::Major),
+    ("almalinux", VersionRule::Major),
+    ("debian", VersionRule::Major),
+    ("fedora", VersionRule::Verbatim),
+    ("anolis", VersionRule::Major),
+    ("amzn", VersionRule::Major),
+    ("openeuler", VersionRule::Numeric),
+    ("kylin", VersionRule::Numeric),
+    ("opensuse-leap", VersionRule::Verbatim),
+    ("sles", VersionRule::Verbatim),
+];
+
+/// Rule for the distro `id`
+pub fn version_rule(id: &str) -> VersionRule {
+    VERSION_RULES
+        .iter()
+        .find(|(i, _)| *i == id)
+        .map_or(VersionRule::Verbatim, |(_, v)| *v)
+}
+
+/// The btfhub-archive directory name of release `version_id` of the distro `id`
+pub fn normalize_version<'a>(id: &str, version_id: &'a str) -> &'a str {
+    let components = match version_rule(id) {
+        VersionRule::MajorMinor => 2,
+        VersionRule::Major => 1,
+        VersionRule::Numeric => return leading_number(version_id),
+        VersionRule::Verbatim => return version_id,
+    };
+    // 截取到第 components 个 `.` 之前
+    match version_id.match_indices('.').nth(components - 1) {
+        Some((end, _)) => &version_id[..end],
+        None => version_id,
+    }
+}
+
+/// The number at the start of `version_id`, after an optional `V`, e.g. `22.03` for `22.03-LTS`
+///
+/// `version_id` is returned as is if it doesn't start with a number.
+fn leading_number(version_id: &str) -> &str {
+    let number = version_id.strip_prefix(['V', 'v']).unwrap_or(version_id);
+    let end = number
+        .find(|v: char| !v.is_ascii_digit() && v != '.')
+        .unwrap_or(number.len());
+    match number[..end].trim_end_matches('.') {
+        "" => version_id,
+        v => v,
+    }
+}
+
+/// (kernel major.minor, Ubuntu release shipping it as its own kernel) of each Ubuntu series
+const UBUNTU_KERNEL_SERIES: &[((u64, u64), &str)] = &[
+    ((3, 13), "14.04"),
+    ((4, 4), "16.04"),
+    ((4, 15), "18.04"),
+    ((4, 18), "18.10"),
+    ((5, 0), "19.04"),
+    ((5, 3), "19.10"),
+    ((5, 4), "20.04"),
+    ((5, 8), "20.10"),
+    ((5, 11), "21.04"),
+    ((5, 13), "21.10"),
+    ((5, 15), "22.04"),
+    ((5, 19), "22.10"),
+    ((6, 2), "23.04"),
+    ((6, 5), "23.10"),
+    ((6, 8), "24.04"),
+    ((6, 11), "24.10"),
+    ((6, 14), "25.04"),
+];
+
+/// The Ubuntu release whose series the kernel `kernel_release` is, if not `version_id` itself
+///
+/// That's where the btf of an HWE kernel may be, e.g. `22.04` for `5.15.0-76-generic` on
+/// `20.04`. `version_id` is normalized first, and `None` is returned for unknown series.
+pub fn ubuntu_hwe_version(version_id: &str, kernel_release: &str) -> Option<&'static str> {
+    let release = KernelRelease::parse(kernel_release)?;
+    let series = (release.numbers[0], release.numbers[1]);
+    let (_, own) = UBUNTU_KERNEL_SERIES.iter().find(|(v, _)| *v == series)?;
+    (*own != normalize_version("ubuntu", version_id)).then_some(*own)
+}
+
+/// (kernel major.minor, Debian release shipping it as its own kernel) of each Debian series
+const DEBIAN_KERNEL_SERIES: &[((u64, u64), &str)] = &[
+    ((3, 16), "8"),
+    ((4, 9), "9"),
+    ((4, 19), "10"),
+    ((5, 10), "11"),
+    ((6, 1), "12"),
+    ((6, 12), "13"),
+];
+
+/// A Debian backports kernel, and the kernel of the release it's built from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebianBackport {
+    /// The release of the same ABI in the release it's built from, e.g. `6.1.0-13-amd64`
+    /// for `6.1.0-0.deb11.13-amd64`
+    pub release: String,
+    /// The Debian release shipping its series, e.g. `12`; `None` for unknown series
+    pub version: Option<&'static str>,
+}
+
+/// The kernel `kernel_release` comes from, if it's a Debian backports kernel
+///
+/// Backports kernels have an ABI of `0.deb<release>.<abi>`, or `0.bpo.<abi>` before
+/// Debian 11, e.g. `6.1.0-0.deb11.13-amd64` and `5.10.0-0.bpo.15-amd64`.
+pub fn debian_backport(kernel_release: &str) -> Option<DebianBackport> {
+    let (upstream, rest) = kernel_release.split_once('-')?;
+    let rest = rest.strip_prefix("0.")?;
+    let rest = match rest.strip_prefix("bpo.") {
+        Some(v) => v,
+        None => rest
+            .strip_prefix("deb")?
+            .trim_start_matches(|v: char| v.is_ascii_digit())
+            .strip_prefix('.')?,
+    };
+    let (abi, flavor) = rest.split_once('-')?;
+    if abi.is_empty() || !abi.bytes().all(|v| v.is_ascii_digit()) {
+        return None;
+    }
+    let version = KernelRelease::parse(upstream).and_then(|v| {
+        let series = (v.numbers[0], v.numbers[1]);
+        DEBIAN_KERNEL_SERIES
+            .iter()
+            .find(|(v, _)| *v == series)
+            .map(|(_, v)| *v)
+    });
+    Some(DebianBackport {
+        release: format!("{}-{}-{}", upstream, abi, flavor),
+        version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_normalized_per_distro_family() {
+        for (id, version_id, expected) in [
+            ("ubuntu", "20.04", "20.04"),
+            ("ubuntu", "20.04.6", "20.04"),
+            ("centos", "8.7", "8"),
+            ("centos", "7", "7"),
+            ("rhel", "9.2", "9"),
+            ("rocky", "8.7", "8"),
+            ("almalinux", "9.2", "9"),
+            ("ol", "8.8", "8"),
+            ("amzn", "2", "2"),
+            ("amzn", "2023", "2023"),
+            ("debian", "11", "11"),
+            ("debian", "10.13", "10"),
+            ("fedora", "38", "38"),
+            ("openeuler", "22.03-LTS-SP1", "22.03"),
+            ("kylin", "V10", "10"),
+            ("sles", "15.4", "15.4"),
+            // 未知发行版保持原样
+            ("nosuch", "1.2.3", "1.2.3"),
+        ] {
+            assert_eq!(
+                normalize_version(id, version_id),
+                expected,
+                "{id} {version_id}"
+            );
+        }
+    }
+
+    #[test]
+    fn non_numeric_versions_are_kept() {
+        assert_eq!(normalize_version("kylin", "Sword"), "Sword");
+        assert_eq!(normalize_version("openeuler", ""), "");
+        assert_eq!(normalize_version("ubuntu", ""), "");
+        assert_eq!(version_rule("nosuch"), VersionRule::Verbatim);
+    }
+
+    #[test]
+    fn hwe_kernels_belong_to_the_release_of_their_series() {
+        for (version_id, release, expected) in [
+            ("20.04", "5.15.0-76-generic", Some("22.04")),
+            ("20.04.6", "5.15.0-1041-azure", Some("22.04")),
+            ("20.04", "5.13.0-52-generic", Some("21.10")),
+            ("18.04", "5.4.0-150-generic", Some("20.04")),
+            ("18.04", "5.4.0-1109-aws", Some("20.04")),
+            ("22.04", "6.5.0-41-generic", Some("23.10")),
+            ("22.04", "6.8.0-40-lowlatency", Some("24.04")),
+            // 发行版自己的内核
+            ("20.04", "5.4.0-150-generic", None),
+            ("22.04", "5.15.0-1041-azure", None),
+            ("20.04.6", "5.4.0-1109-gcp", None),
+            // 未知的系列和无法解析的版本
+            ("20.04", "5.16.0-1-generic", None),
+            ("20.04", "custom", None),
+        ] {
+            assert_eq!(
+                ubuntu_hwe_version(version_id, release),
+                expected,
+                "{version_id} {release}"
+            );
+        }
+    }
+
+    #[test]
+    fn backports_kernels_come_from_a_later_release() {
+        for (release, expected) in [
+            (
+                "6.1.0-0.deb11.13-amd64",
+                Some(("6.1.0-13-amd64", Some("12"))),
+            ),
+            (
+                "6.1.0-0.deb11.21-cloud-amd64",
+                Some(("6.1.0-21-cloud-amd64", Some("12"))),
+            ),
+            ("6.12.9-1~bpo12+1", None),
+            (
+                "6.12.12-0.deb12.1-arm64",
+                Some(("6.12.12-1-arm64", Some("13"))),
+            ),
+            // Debian 11 之前的 bpo 命名
+            (
+                "5.10.0-0.bpo.15-amd64",
+                Some(("5.10.0-15-amd64", Some("11"))),
+            ),
+            (
+                "4.19.0-0.bpo.19-amd64",
+                Some(("4.19.0-19-amd64", Some("10"))),
+            ),
+            // 未知的系列仍然识别为 backports 内核
+            ("5.16.0-0.bpo.4-amd64", Some(("5.16.0-4-amd64", None))),
+            // 发行版自己的内核
+            ("5.10.0-23-amd64", None),
+            ("6.1.0-13-amd64", None),
+            ("6.1.0-0.deb11.x-amd64", None),
+            ("6.1.0-0.deb11.13", None),
+            ("custom", None),
+        ] {
+            assert_eq!(
+                debian_backport(release),
+                expected.map(|(release, version)| DebianBackport {
+                    release: release.into(),
+                    version,
+                }),
+                "{release}"
+            );
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! A streaming xz decoder over an in-memory blob, on top of the system liblzma.
+//!
+//! As for zstd, see `crate::zstd`, liblzma is bound directly rather than through the `xz2`
+//! crate, whose `lzma-sys` builds a vendored liblzma from C sources; three functions of
+//! its stream API are all a decoder needs.
+use std::{
+    ffi::{c_int, c_void},
+    io::{Error, ErrorKind, Read, Result},
+    marker::PhantomData,
+};
+
+/// `lzma_stream` of lzma/base.h, which must be zero-initialized (`LZMA_STREAM_INIT`)
+#[repr(C)]
+struct LzmaStream {
+    next_in: *const u8,
+    avail_in: usize,
+    total_in: u64,
+    next_out: *mut u8,
+    avail_out: usize,
+    total_out: u64,
+    allocator: *const c_void,
+    internal: *mut c_void,
+    reserved_ptr: [*mut c_void; 4],
+    reserved_int: [u64; 2],
+    reserved_size: [usize; 2],
+    reserved_enum: [c_int; 2],
+}
+
+const LZMA_OK: c_int = 0;
+const LZMA_STREAM_END: c_int = 1;
+const LZMA_MEM_ERROR: c_int = 5;
+const LZMA_MEMLIMIT_ERROR: c_int = 6;
+const LZMA_FORMAT_ERROR: c_int = 7;
+const LZMA_DATA_ERROR: c_int = 9;
+const LZMA_BUF_ERROR: c_int = 10;
+/// The whole input is available up front, so the stream is always finishing
+const LZMA_FINISH: c_int = 3;
+/// Decode concatenated `.xz` streams like `xz -d` does
+const LZMA_CONCATENATED: u32 = 0x08;
+
+#[link(name = "lzma")]
+extern "C" {
+    fn lzma_stream_decoder(strm: *mut LzmaStream, memlimit: u64, flags: u32) -> c_int;
+    fn lzma_code(strm: *mut LzmaStream, action: c_int) -> c_int;
+    fn lzma_end(strm: *mut LzmaStream);
+}
+
+fn check(ret: c_int) -> Result<c_int> {
+    let (kind, message) = match ret {
+        LZMA_OK | LZMA_STREAM_END => return Ok(ret),
+        LZMA_MEM_ERROR | LZMA_MEMLIMIT_ERROR => (ErrorKind::OutOfMemory, "out of memory"),
+        LZMA_FORMAT_ERROR => (ErrorKind::InvalidData, "not in the .xz format"),
+        LZMA_DATA_ERROR => (ErrorKind::InvalidData, "the stream is corrupt"),
+        LZMA_BUF_ERROR => (ErrorKind::UnexpectedEof, "the stream is truncated"),
+        _ => (ErrorKind::Other, "internal error"),
+    };
+    Err(Error::new(kind, format!("xz: {} ({})", message, ret)))
+}
+
+/// Decompress the xz data held by a slice
+pub(crate) struct XzDecoder<'a> {
+    stream: LzmaStream,
+    /// `stream.next_in` points into the input slice
+    input: PhantomData<&'a [u8]>,
+    done: bool,
+}
+
+impl<'a> XzDecoder<'a> {
+    pub(crate) fn new(input: &'a [u8]) -> Result<Self> {
+        let mut stream: LzmaStream = unsafe { std::mem::zeroed() };
+        check(unsafe { lzma_stream_decoder(&mut stream, u64::MAX, LZMA_CONCATENATED) })?;
+        stream.next_in = input.as_ptr();
+        stream.avail_in = input.len();
+        Ok(Self {
+            stream,
+            input: PhantomData,
+            done: false,
+        })
+    }
+}
+
+impl Read for XzDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() || self.done {
+            return Ok(0);
+        }
+        loop {
+            self.stream.next_out = buf.as_mut_ptr();
+            self.stream.avail_out = buf.len();
+            let ret = check(unsafe { lzma_code(&mut self.stream, LZMA_FINISH) })?;
+            let written = buf.len() - self.stream.avail_out;
+            self.done = ret == LZMA_STREAM_END;
+            if written > 0 || self.done {
+                return Ok(written);
+            }
+        }
+    }
+}
+
+impl Drop for XzDecoder<'_> {
+    fn drop(&mut self) {
+        unsafe { lzma_end(&mut self.stream) };
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// `LZMA_CHECK_CRC64`, the default of `xz`
+    const LZMA_CHECK_CRC64: c_int = 4;
+
+    extern "C" {
+        fn lzma_stream_buffer_bound(size: usize) -> usize;
+        fn lzma_easy_buffer_encode(
+            preset: u32,
+            check: c_int,
+            allocator: *const c_void,
+            input: *const u8,
+            input_size: usize,
+            output: *mut u8,
+            output_pos: *mut usize,
+            output_size: usize,
+        ) -> c_int;
+    }
+
+    /// `data` as an xz stream, as `xz -6` writes it
+    pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+        let mut stream = vec![0; unsafe { lzma_stream_buffer_bound(data.len()) }];
+        let mut len = 0;
+        check(unsafe {
+            lzma_easy_buffer_encode(
+                6,
+                LZMA_CHECK_CRC64,
+                std::ptr::null(),
+                data.as_ptr(),
+                data.len(),
+                stream.as_mut_ptr(),
+                &mut len,
+                stream.len(),
+            )
+        })
+        .unwrap();
+        stream.truncate(len);
+        stream
+    }
+
+    fn decompress(stream: &[u8]) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        XzDecoder::new(stream)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..1 << 20).map(|v: u32| (v % 241) as u8).collect();
+        assert_eq!(decompress(&compress(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn concatenated_streams_are_one_stream() {
+        let streams = [compress(b"btfhub-"), compress(b"archive")].concat();
+        assert_eq!(decompress(&streams).unwrap(), b"btfhub-archive");
+    }
+
+    #[test]
+    fn truncated_corrupt_and_garbage_input_fail() {
+        let stream = compress(&[7; 4096]);
+        let truncated = decompress(&stream[..stream.len() / 2]).unwrap_err();
+        assert_eq!(truncated.kind(), ErrorKind::UnexpectedEof);
+        let mut corrupt = stream.clone();
+        let middle = corrupt.len() / 2;
+        corrupt[middle] ^= 0xff;
+        assert_eq!(
+            decompress(&corrupt).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        let garbage = decompress(b"definitely not xz").unwrap_err();
+        assert_eq!(garbage.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn xz_archives_are_detected_and_read() {
+        let tar = crate::fixture::FixtureArchive::new()
+            .file("btfhub-archive/README", b"readme".to_vec())
+            .tar();
+        let mut data = vec![];
+        crate::compression::tar_reader(&compress(&tar))
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, tar);
+    }
+}
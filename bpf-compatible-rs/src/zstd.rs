@@ -0,0 +1,205 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! A streaming zstd decoder over an in-memory blob, on top of the system libzstd.
+//!
+//! Only the six functions of the streaming API are bound, rather than depending on the
+//! `zstd` crate: its `zstd-sys` builds a vendored libzstd from C sources, which offline
+//! and cross builds of the loader can't always do, while the systems btfgen archives are
+//! made for ship libzstd already.
+use std::{
+    ffi::{c_char, c_uint, c_void, CStr},
+    io::{Error, ErrorKind, Read, Result},
+};
+
+#[repr(C)]
+struct ZstdInBuffer {
+    src: *const c_void,
+    size: usize,
+    pos: usize,
+}
+
+#[repr(C)]
+struct ZstdOutBuffer {
+    dst: *mut c_void,
+    size: usize,
+    pos: usize,
+}
+
+#[repr(C)]
+struct ZstdDStream {
+    _private: [u8; 0],
+}
+
+#[link(name = "zstd")]
+extern "C" {
+    fn ZSTD_createDStream() -> *mut ZstdDStream;
+    fn ZSTD_freeDStream(stream: *mut ZstdDStream) -> usize;
+    fn ZSTD_initDStream(stream: *mut ZstdDStream) -> usize;
+    fn ZSTD_decompressStream(
+        stream: *mut ZstdDStream,
+        output: *mut ZstdOutBuffer,
+        input: *mut ZstdInBuffer,
+    ) -> usize;
+    fn ZSTD_isError(code: usize) -> c_uint;
+    fn ZSTD_getErrorName(code: usize) -> *const c_char;
+}
+
+fn check(code: usize) -> Result<usize> {
+    if unsafe { ZSTD_isError(code) } == 0 {
+        return Ok(code);
+    }
+    let name = unsafe { CStr::from_ptr(ZSTD_getErrorName(code)) };
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!("zstd: {}", name.to_string_lossy()),
+    ))
+}
+
+/// Decompress the (possibly multi-frame) zstd data held by a slice
+pub(crate) struct ZstdDecoder<'a> {
+    stream: *mut ZstdDStream,
+    input: &'a [u8],
+    pos: usize,
+    /// Whether the last call finished a frame, i.e. the data may end here
+    frame_done: bool,
+}
+
+impl<'a> ZstdDecoder<'a> {
+    pub(crate) fn new(input: &'a [u8]) -> Result<Self> {
+        let stream = unsafe { ZSTD_createDStream() };
+        if stream.is_null() {
+            return Err(Error::new(
+                ErrorKind::OutOfMemory,
+                "zstd: failed to create a stream",
+            ));
+        }
+        let decoder = Self {
+            stream,
+            input,
+            pos: 0,
+            frame_done: false,
+        };
+        check(unsafe { ZSTD_initDStream(stream) })?;
+        Ok(decoder)
+    }
+}
+
+impl Read for ZstdDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // 返回 0 时帧已完整输出，没有缓存的数据，输入耗尽即为正常结束
+            if self.frame_done && self.pos == self.input.len() {
+                return Ok(0);
+            }
+            let mut input = ZstdInBuffer {
+                src: self.input.as_ptr() as *const c_void,
+                size: self.input.len(),
+                pos: self.pos,
+            };
+            let mut output = ZstdOutBuffer {
+                dst: buf.as_mut_ptr() as *mut c_void,
+                size: buf.len(),
+                pos: 0,
+            };
+            let ret =
+                check(unsafe { ZSTD_decompressStream(self.stream, &mut output, &mut input) })?;
+            self.pos = input.pos;
+            // 返回 0 表示当前帧已结束，之后可能还有下一帧
+            self.frame_done = ret == 0;
+            if output.pos > 0 {
+                return Ok(output.pos);
+            }
+            if self.pos == self.input.len() && !self.frame_done {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "zstd: the stream is truncated",
+                ));
+            }
+        }
+    }
+}
+
+impl Drop for ZstdDecoder<'_> {
+    fn drop(&mut self) {
+        unsafe { ZSTD_freeDStream(self.stream) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn ZSTD_compressBound(size: usize) -> usize;
+        fn ZSTD_compress(
+            dst: *mut c_void,
+            capacity: usize,
+            src: *const c_void,
+            size: usize,
+            level: std::ffi::c_int,
+        ) -> usize;
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; unsafe { ZSTD_compressBound(data.len()) }];
+        let len = check(unsafe {
+            ZSTD_compress(
+                frame.as_mut_ptr() as *mut c_void,
+                frame.len(),
+                data.as_ptr() as *const c_void,
+                data.len(),
+                3,
+            )
+        })
+        .unwrap();
+        frame.truncate(len);
+        frame
+    }
+
+    fn decompress(frames: &[u8]) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        ZstdDecoder::new(frames)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..1 << 20).map(|v: u32| (v % 251) as u8).collect();
+        assert_eq!(decompress(&compress(&data)).unwrap(), data);
+        assert_eq!(decompress(&compress(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn concatenated_frames_are_one_stream() {
+        let frames = [compress(b"btfhub-"), compress(b"archive")].concat();
+        assert_eq!(decompress(&frames).unwrap(), b"btfhub-archive");
+    }
+
+    #[test]
+    fn truncated_and_garbage_input_fail() {
+        let frame = compress(&[7; 4096]);
+        let truncated = decompress(&frame[..frame.len() - 4]).unwrap_err();
+        assert_eq!(truncated.kind(), ErrorKind::UnexpectedEof);
+        let garbage = decompress(b"definitely not zstd").unwrap_err();
+        assert_eq!(garbage.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn zstd_archives_are_detected_and_read() {
+        let tar = crate::fixture::FixtureArchive::new()
+            .file("btfhub-archive/README", b"readme".to_vec())
+            .tar();
+        let mut data = vec![];
+        crate::compression::tar_reader(&compress(&tar))
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, tar);
+    }
+}
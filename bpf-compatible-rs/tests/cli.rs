@@ -0,0 +1,296 @@
+//! The `bpf-compat` binary, run against fixture archives
+#![cfg(feature = "test-util")]
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    tempfile::{tempdir, TempDir},
+};
+
+fn archive() -> Vec<u8> {
+    FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .btf(
+            "centos",
+            "8",
+            "aarch64",
+            "4.18.0-80.el8.aarch64",
+            btf_of_arch(8, "pc"),
+        )
+        .gz()
+}
+
+/// A directory holding `archive.tar.gz`, to run the binary in
+fn workdir(tar: &[u8]) -> TempDir {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("archive.tar.gz"), tar).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bpf-compat"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn list_prints_every_kernel() {
+    let dir = workdir(&archive());
+    let output = run(dir.path(), &["list", "archive.tar.gz"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let mut kernels = stdout(&output)
+        .lines()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    kernels.sort();
+    assert_eq!(
+        kernels,
+        [
+            "centos/8/aarch64/4.18.0-80.el8.aarch64",
+            "ubuntu/20.04/x86_64/5.4.0-40-generic"
+        ]
+    );
+}
+
+#[test]
+fn extract_writes_the_entry_given_by_the_options() {
+    let dir = workdir(&archive());
+    let output = run(
+        dir.path(),
+        &[
+            "extract",
+            "archive.tar.gz",
+            "--kernel",
+            "4.18.0-80.el8.aarch64",
+            "--distro",
+            "centos",
+            "--version",
+            "8",
+            "--arch",
+            "aarch64",
+            "-o",
+            "out.btf",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        fs::read(dir.path().join("out.btf")).unwrap(),
+        btf_of_arch(8, "pc")
+    );
+    assert!(stdout(&output).contains("centos/8/aarch64/4.18.0-80.el8.aarch64.btf -> out.btf"));
+
+    // 不给出 -o 时以内核版本命名
+    let output = run(
+        dir.path(),
+        &[
+            "extract",
+            "archive.tar.gz",
+            "--kernel",
+            "5.4.0-40-generic",
+            "--distro",
+            "ubuntu",
+            "--version",
+            "20.04",
+            "--arch",
+            "x86_64",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        fs::read(dir.path().join("5.4.0-40-generic.btf")).unwrap(),
+        btf_of_arch(8, "rip")
+    );
+}
+
+#[test]
+fn extract_of_a_missing_entry_is_not_covered() {
+    let dir = workdir(&archive());
+    let output = run(
+        dir.path(),
+        &[
+            "extract",
+            "archive.tar.gz",
+            "--kernel",
+            "5.15.0-1-generic",
+            "--distro",
+            "ubuntu",
+            "--version",
+            "22.04",
+            "--arch",
+            "x86_64",
+            "-o",
+            "out.btf",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("has no btf"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!dir.path().join("out.btf").exists());
+}
+
+#[test]
+fn errors_exit_with_2() {
+    let dir = workdir(&archive());
+    fs::write(dir.path().join("garbage.tar.gz"), b"not an archive").unwrap();
+    for args in [
+        &[][..],
+        &["frobnicate", "archive.tar.gz"],
+        &["list"],
+        &["list", "missing.tar.gz"],
+        &["list", "archive.tar.gz", "extra"],
+        &["list", "garbage.tar.gz"],
+        &["extract", "archive.tar.gz", "--bogus", "value"],
+        &["extract", "archive.tar.gz", "-o"],
+    ] {
+        let output = run(dir.path(), args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(stderr(&output).starts_with("bpf-compat: "), "{:?}", args);
+    }
+    let output = run(dir.path(), &["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("Usage:"));
+}
+
+/// `check` on a kernel faked to be `release` of ubuntu 20.04 x86_64
+#[cfg(feature = "fake-system")]
+fn check_faked(release: &str) -> Output {
+    use bpf_compatible_rs::fake::{
+        FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV,
+    };
+
+    let dir = workdir(&archive());
+    Command::new(env!("CARGO_BIN_EXE_bpf-compat"))
+        .current_dir(dir.path())
+        .args(["check", "archive.tar.gz"])
+        .env(FAKE_KERNEL_ENV, release)
+        .env(FAKE_ARCH_ENV, "x86_64")
+        .env(FAKE_DISTRO_ENV, "ubuntu")
+        .env(FAKE_VERSION_ENV, "20.04")
+        .output()
+        .unwrap()
+}
+
+#[cfg(feature = "fake-system")]
+#[test]
+fn check_reports_the_archive_entry() {
+    let output = check_faked("5.4.0-40-generic");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output).lines().collect::<Vec<_>>(),
+        [
+            "path: ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            // 伪造内核时不使用当前内核的 btf
+            "native: none",
+            "archive: btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+        ]
+    );
+}
+
+#[cfg(feature = "fake-system")]
+#[test]
+fn check_of_an_uncovered_kernel_exits_with_1() {
+    let output = check_faked("5.4.0-99-generic");
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stdout(&output).contains("archive: none"));
+}
+
+#[test]
+fn trim_keeps_the_btfs_matching_every_option() {
+    let tar = FixtureArchive::new()
+        .file("SHA256SUMS", b"digests".to_vec())
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .btf(
+            "ubuntu",
+            "18.04",
+            "x86_64",
+            "4.15.0-20-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .btf(
+            "ubuntu",
+            "20.04",
+            "arm64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "pc"),
+        )
+        .btf(
+            "centos",
+            "8",
+            "x86_64",
+            "4.18.0-80.el8.x86_64",
+            btf_of_arch(8, "rip"),
+        )
+        .gz();
+    let dir = workdir(&tar);
+    let output = run(
+        dir.path(),
+        &[
+            "trim",
+            "archive.tar.gz",
+            "-o",
+            "slim.tar.gz",
+            "--distro",
+            "ubuntu",
+            "--arch",
+            "x86_64",
+            "--min-kernel",
+            "5.4.0",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(
+        stdout(&output).starts_with("kept 2, dropped 3"),
+        "{}",
+        stdout(&output)
+    );
+    let output = run(dir.path(), &["list", "slim.tar.gz"]);
+    assert_eq!(stdout(&output), "ubuntu/20.04/x86_64/5.4.0-40-generic\n");
+
+    // 没有剩下任何 btf 时失败，不留下输出文件
+    let output = run(
+        dir.path(),
+        &[
+            "trim",
+            "archive.tar.gz",
+            "-o",
+            "none.tar.gz",
+            "--distro",
+            "debian",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!dir.path().join("none.tar.gz").exists());
+    // 缺少输出时给出用法
+    let output = run(dir.path(), &["trim", "archive.tar.gz"]);
+    assert_eq!(output.status.code(), Some(2));
+}
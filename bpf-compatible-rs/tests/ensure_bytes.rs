@@ -0,0 +1,70 @@
+//! `ensure_core_btf_bytes` and `ensure_btf_for_aya`, on a kernel faked to be one of the archive
+//!
+//! This is the only test of the binary, so the faked identity affects no other.
+#![cfg(all(feature = "fake-system", feature = "test-util"))]
+
+use bpf_compatible_rs::{
+    btf::btf_arch,
+    ensure_core_btf_bytes,
+    fake::{FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV},
+    fixture::{btf_of_arch, FixtureArchive},
+    Error,
+};
+
+#[test]
+fn btf_of_the_faked_kernel() {
+    std::env::set_var(FAKE_ARCH_ENV, "x86_64");
+    std::env::set_var(FAKE_DISTRO_ENV, "ubuntu");
+    std::env::set_var(FAKE_VERSION_ENV, "20.04");
+    std::env::set_var(FAKE_KERNEL_ENV, "5.4.0-40-generic");
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "r15"),
+        )
+        .gz();
+
+    // 伪造的内核不使用当前内核的 btf，返回归档中的条目与 btf 的内容
+    let (entry, btf) = ensure_core_btf_bytes(&tar).unwrap().unwrap();
+    assert_eq!(
+        entry.path.to_str().unwrap(),
+        "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf"
+    );
+    assert_eq!(entry.kernel_release, "5.4.0-40-generic");
+    assert_eq!(btf, btf_of_arch(8, "r15"));
+    // 调用者可以直接解析返回的 btf
+    let arch = btf_arch(&btf).unwrap();
+    assert_eq!(arch.pointer_size, Some(8));
+    assert_eq!(arch.family, Some("x86"));
+    // aya 解析出的 btf 可以按名字找到类型
+    #[cfg(feature = "aya")]
+    {
+        use aya_obj::btf::BtfKind;
+        let btf = bpf_compatible_rs::ensure_btf_for_aya(&tar)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            btf.id_by_type_name_kind("pt_regs", BtfKind::Struct)
+                .unwrap(),
+            2
+        );
+        assert_eq!(btf.id_by_type_name_kind("long", BtfKind::Int).unwrap(), 1);
+        assert!(btf
+            .id_by_type_name_kind("task_struct", BtfKind::Struct)
+            .is_err());
+    }
+
+    std::env::set_var(FAKE_KERNEL_ENV, "5.4.0-99-generic");
+    assert!(matches!(
+        ensure_core_btf_bytes(&tar),
+        Err(Error::EntryNotFound { .. })
+    ));
+    #[cfg(feature = "aya")]
+    assert!(matches!(
+        bpf_compatible_rs::ensure_btf_for_aya(&tar),
+        Err(Error::EntryNotFound { .. })
+    ));
+}
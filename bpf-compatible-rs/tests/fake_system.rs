@@ -0,0 +1,71 @@
+//! The identity of the running system, faked with the `fake-system` feature
+//!
+//! This is the only test of the binary, so the faked identity affects no other.
+#![cfg(all(feature = "fake-system", feature = "test-util"))]
+
+use bpf_compatible_rs::{
+    btf::btf_matches_current,
+    current_kernel_release,
+    fake::{is_kernel_faked, FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV},
+    fixture::btf_of_arch,
+    generate_current_system_btf_archive_paths, Error, SystemInfo,
+};
+
+#[test]
+fn faked_identity_replaces_the_running_one() {
+    let real = SystemInfo::detect();
+    // 空值等同于未设置
+    for name in [FAKE_KERNEL_ENV, FAKE_ARCH_ENV, FAKE_DISTRO_ENV] {
+        std::env::set_var(name, "");
+    }
+    assert!(!is_kernel_faked());
+    if let Ok(real) = &real {
+        assert_eq!(&SystemInfo::detect().unwrap(), real);
+    }
+
+    // 只伪造内核时，发行版仍从系统中读取
+    std::env::set_var(FAKE_KERNEL_ENV, "5.4.0-40-generic");
+    assert!(is_kernel_faked());
+    assert_eq!(current_kernel_release().unwrap(), "5.4.0-40-generic");
+    if let Ok(real) = &real {
+        let info = SystemInfo::detect().unwrap();
+        assert_eq!(info.distro_id, real.distro_id);
+        assert_eq!(info.kernel_release, "5.4.0-40-generic");
+        // uname 的版本字符串描述的是真实的内核
+        assert_eq!(info.kernel_version, "");
+    }
+
+    // 伪造发行版时不读取 os-release
+    std::env::set_var(FAKE_DISTRO_ENV, "centos");
+    std::env::set_var(FAKE_VERSION_ENV, "8.7");
+    std::env::set_var(FAKE_ARCH_ENV, "aarch64");
+    std::env::set_var(FAKE_KERNEL_ENV, "4.18.0-425.3.1.el8.aarch64");
+    let info = SystemInfo::detect().unwrap();
+    assert_eq!(
+        (
+            &*info.distro_id,
+            &*info.version_id,
+            &*info.arch,
+            &*info.kernel_release
+        ),
+        ("centos", "8.7", "aarch64", "4.18.0-425.3.1.el8.aarch64")
+    );
+    assert_eq!(
+        generate_current_system_btf_archive_paths().unwrap()[0],
+        "centos/8/arm64/4.18.0-425.3.1.el8.aarch64.btf"
+    );
+    // btf 的指针大小与伪造的架构比较
+    assert!(btf_matches_current(&btf_of_arch(8, "aarch64")).unwrap());
+    std::env::set_var(FAKE_ARCH_ENV, "armv7l");
+    assert!(!btf_matches_current(&btf_of_arch(8, "aarch64")).unwrap());
+    assert!(btf_matches_current(&btf_of_arch(4, "arm")).unwrap());
+
+    // 非滚动发行版仍然需要版本号
+    std::env::remove_var(FAKE_VERSION_ENV);
+    assert!(matches!(
+        SystemInfo::detect(),
+        Err(Error::MissingOsReleaseField("VERSION_ID"))
+    ));
+    std::env::set_var(FAKE_DISTRO_ENV, "arch");
+    assert_eq!(SystemInfo::detect().unwrap().version_id, "");
+}
@@ -0,0 +1,67 @@
+//! `include_btf_archive!`, used from a crate of its own
+//!
+//! The macro needs a file at build time; `tests/fixtures/min_core_btfs.tar.gz` is the
+//! gzipped `FixtureArchive` holding `btf_of_arch(8, "embedded")` for ubuntu 20.04 x86_64
+//! 5.4.0-40-generic.
+#![cfg(feature = "host")]
+
+use bpf_compatible_rs::archive::BtfhubArchive;
+
+mod embedded {
+    // 给出可见性后，其他模块可以使用生成的条目
+    bpf_compatible_rs::include_btf_archive!(pub, "tests/fixtures/min_core_btfs.tar.gz");
+}
+
+const FIXTURE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/min_core_btfs.tar.gz"
+);
+
+#[test]
+fn archive_is_embedded_from_the_manifest_dir() {
+    assert_eq!(embedded::BTF_ARCHIVE, std::fs::read(FIXTURE).unwrap());
+    let archive = BtfhubArchive::new(embedded::BTF_ARCHIVE);
+    assert_eq!(
+        archive.kernels().unwrap(),
+        ["ubuntu/20.04/x86_64/5.4.0-40-generic"]
+    );
+    let btf = archive
+        .extract("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf")
+        .unwrap();
+    bpf_compatible_rs::btf::validate_btf_bytes(&btf).unwrap();
+}
+
+#[test]
+fn private_items_are_usable_in_place() {
+    bpf_compatible_rs::include_btf_archive!("tests/fixtures/min_core_btfs.tar.gz");
+    // 宏也可以在函数体内展开，生成的函数返回库的结果类型
+    assert_eq!(BTF_ARCHIVE, embedded::BTF_ARCHIVE);
+    let _: fn() -> bpf_compatible_rs::Result<Option<bpf_compatible_rs::EnsuredBtf>> =
+        ensure_core_btf;
+}
+
+/// The generated function, end to end, on a kernel faked to be the one of the fixture
+#[cfg(feature = "fake-system")]
+#[test]
+fn generated_function_extracts_the_btf_of_the_faked_system() {
+    use bpf_compatible_rs::fake::{
+        FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV,
+    };
+
+    // 本文件中只有这个测试设置环境变量
+    std::env::set_var(FAKE_KERNEL_ENV, "5.4.0-40-generic");
+    std::env::set_var(FAKE_ARCH_ENV, "x86_64");
+    std::env::set_var(FAKE_DISTRO_ENV, "ubuntu");
+    std::env::set_var(FAKE_VERSION_ENV, "20.04");
+
+    let btf = embedded::ensure_core_btf()
+        .unwrap()
+        .expect("the faked kernel has no native btf");
+    let expected = BtfhubArchive::new(embedded::BTF_ARCHIVE)
+        .extract("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf")
+        .unwrap();
+    assert_eq!(std::fs::read(btf.path()).unwrap(), expected);
+    let path = btf.path().to_path_buf();
+    drop(btf);
+    assert!(!path.exists());
+}
@@ -0,0 +1,272 @@
+//! The JSON the `serde` feature gives the public data types, whose field names are part of the API
+#![cfg(feature = "serde")]
+
+use std::{fmt::Debug, path::PathBuf};
+
+use bpf_compatible_rs::{
+    archive::{BtfEncoding, BtfEntry, BtfEntryInfo},
+    release::{CandidateReason, MatchPolicy},
+    BtfSource, MatchInfo, NativeBtfStatus, SystemInfo,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+/// Serialize `value`, check it gives `expected`, and that it reads back the same
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: &T, expected: Value) {
+    let serialized = serde_json::to_value(value).unwrap();
+    assert_eq!(serialized, expected);
+    assert_eq!(&serde_json::from_value::<T>(serialized).unwrap(), value);
+}
+
+fn entry() -> BtfEntry {
+    BtfEntry {
+        distro: "ubuntu".into(),
+        version: "20.04".into(),
+        arch: "x86_64".into(),
+        kernel_release: "5.4.0-40-generic".into(),
+        path: "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz".into(),
+        size: 1234,
+        encoding: BtfEncoding::Gzipped,
+        is_link: false,
+        byte_swapped: false,
+    }
+}
+
+#[test]
+fn system_info_is_read_from_an_orchestrator() {
+    let info = SystemInfo {
+        distro_id: "ubuntu".into(),
+        version_id: "20.04".into(),
+        version_codename: "focal".into(),
+        arch: "x86_64".into(),
+        kernel_release: "5.4.0-40-generic".into(),
+        kernel_version: "#44-Ubuntu SMP Tue Jun 23 00:01:04 UTC 2020".into(),
+    };
+    round_trip(
+        &info,
+        json!({
+            "distro_id": "ubuntu",
+            "version_id": "20.04",
+            "version_codename": "focal",
+            "arch": "x86_64",
+            "kernel_release": "5.4.0-40-generic",
+            "kernel_version": "#44-Ubuntu SMP Tue Jun 23 00:01:04 UTC 2020",
+        }),
+    );
+    // 缺少字段的描述不会被悄悄补全
+    let partial = json!({"distro_id": "ubuntu", "arch": "x86_64"});
+    assert!(serde_json::from_value::<SystemInfo>(partial).is_err());
+}
+
+#[test]
+fn entries_are_tagged_by_kind() {
+    let expected = json!({
+        "distro": "ubuntu",
+        "version": "20.04",
+        "arch": "x86_64",
+        "kernel_release": "5.4.0-40-generic",
+        "path": "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf.gz",
+        "size": 1234,
+        "encoding": "gzipped",
+        "is_link": false,
+        "byte_swapped": false,
+    });
+    round_trip(&entry(), expected.clone());
+    round_trip(&BtfEntryInfo::Btf(entry()), json!({ "btf": expected }));
+    round_trip(
+        &BtfEntryInfo::Other(PathBuf::from("btfhub-archive/README.md")),
+        json!({"other": "btfhub-archive/README.md"}),
+    );
+    for (encoding, name) in [
+        (BtfEncoding::Plain, "plain"),
+        (BtfEncoding::Gzipped, "gzipped"),
+        (BtfEncoding::Tarball, "tarball"),
+    ] {
+        round_trip(&encoding, json!(name));
+    }
+}
+
+#[test]
+fn match_results_use_snake_case_names() {
+    let matched = MatchInfo {
+        entry_path: Some("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf".into()),
+        source: BtfSource::Archive,
+        exact: false,
+        kernel_release: "5.4.0-40-generic".into(),
+        local_version_stripped: true,
+        candidate: Some(1),
+        native_btf: NativeBtfStatus::Missing,
+        memoized: false,
+    };
+    round_trip(
+        &matched,
+        json!({
+            "entry_path": "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            "source": "archive",
+            "exact": false,
+            "kernel_release": "5.4.0-40-generic",
+            "local_version_stripped": true,
+            "candidate": 1,
+            "native_btf": "missing",
+            "memoized": false,
+        }),
+    );
+    // 较早的版本没有记录这两个字段
+    let older = json!({
+        "entry_path": null,
+        "source": "native",
+        "exact": true,
+        "kernel_release": "6.1.0-18-amd64",
+        "local_version_stripped": false,
+        "candidate": null,
+    });
+    assert_eq!(
+        serde_json::from_value::<MatchInfo>(older).unwrap(),
+        MatchInfo {
+            native_btf: NativeBtfStatus::Unknown,
+            ..MatchInfo::native("6.1.0-18-amd64")
+        }
+    );
+    for (source, name) in [
+        (BtfSource::Native, "native"),
+        (BtfSource::Installed, "installed"),
+        (BtfSource::Cache, "cache"),
+        (BtfSource::Download, "download"),
+        (BtfSource::Pahole, "pahole"),
+        (BtfSource::Override, "override"),
+    ] {
+        round_trip(&source, json!(name));
+    }
+    for (policy, name) in [
+        (MatchPolicy::Exact, "exact"),
+        (MatchPolicy::SameFlavorNearest, "same_flavor_nearest"),
+        (MatchPolicy::BestEffort, "best_effort"),
+    ] {
+        round_trip(&policy, json!(name));
+    }
+    round_trip(&CandidateReason::LowerRevision, json!("lower_revision"));
+    assert!(serde_json::from_value::<MatchPolicy>(json!("SameFlavorNearest")).is_err());
+}
+
+#[cfg(feature = "host")]
+#[test]
+fn reports_round_trip() {
+    use bpf_compatible_rs::{
+        compat::{CompatReport, MissingTarget},
+        dedup::DedupReport,
+        gc::GcReport,
+        metadata::{ArchiveInfo, ArchiveMetadata},
+        pack::FilterReport,
+    };
+
+    round_trip(
+        &CompatReport {
+            relocations: 3,
+            missing: vec![
+                MissingTarget::Type("task_struct".into()),
+                MissingTarget::Member {
+                    type_name: "task_struct".into(),
+                    member: "__state".into(),
+                },
+                MissingTarget::EnumValue {
+                    type_name: "bpf_func_id".into(),
+                    value: "BPF_FUNC_loop".into(),
+                },
+            ],
+        },
+        json!({
+            "relocations": 3,
+            "missing": [
+                {"type": "task_struct"},
+                {"member": {"type_name": "task_struct", "member": "__state"}},
+                {"enum_value": {"type_name": "bpf_func_id", "value": "BPF_FUNC_loop"}},
+            ],
+        }),
+    );
+    round_trip(
+        &ArchiveInfo {
+            btf_entries: 2,
+            uncompressed_size: 4096,
+            compressed_size: 512,
+            metadata: Some(ArchiveMetadata {
+                build_time: Some(1_700_000_000),
+                build_id: None,
+                fields: vec![("build_time".into(), "1700000000".into())],
+            }),
+        },
+        json!({
+            "btf_entries": 2,
+            "uncompressed_size": 4096,
+            "compressed_size": 512,
+            "metadata": {
+                "build_time": 1_700_000_000,
+                "build_id": null,
+                "fields": [["build_time", "1700000000"]],
+            },
+        }),
+    );
+    round_trip(
+        &DedupReport {
+            scanned: 3,
+            linked: vec!["ubuntu/20.04/x86_64/5.4.0-41-generic.btf".into()],
+            saved_bytes: 100,
+        },
+        json!({
+            "scanned": 3,
+            "linked": ["ubuntu/20.04/x86_64/5.4.0-41-generic.btf"],
+            "saved_bytes": 100,
+        }),
+    );
+    round_trip(
+        &FilterReport {
+            kept: 4,
+            dropped: 3,
+            size: 2048,
+        },
+        json!({"kept": 4, "dropped": 3, "size": 2048}),
+    );
+    round_trip(
+        &GcReport {
+            removed: 1,
+            freed_bytes: 10,
+            recent: 2,
+            failed: 0,
+        },
+        json!({"removed": 1, "freed_bytes": 10, "recent": 2, "failed": 0}),
+    );
+}
+
+#[cfg(feature = "minimize")]
+#[test]
+fn minimize_reports_round_trip() {
+    use bpf_compatible_rs::minimize::{MinimizeFailure, MinimizeReport, MinimizedEntry};
+
+    round_trip(
+        &MinimizeReport {
+            minimized: vec![MinimizedEntry {
+                kernel: "ubuntu/20.04/x86_64/5.4.0-40-generic".into(),
+                original_size: 4096,
+                minimized_size: 128,
+            }],
+            failures: vec![MinimizeFailure {
+                kernel: "ubuntu/20.04/x86_64/5.4.0-42-generic".into(),
+                message: "bpftool exited with 255".into(),
+            }],
+            input_size: 8192,
+            output_size: 1024,
+        },
+        json!({
+            "minimized": [{
+                "kernel": "ubuntu/20.04/x86_64/5.4.0-40-generic",
+                "original_size": 4096,
+                "minimized_size": 128,
+            }],
+            "failures": [{
+                "kernel": "ubuntu/20.04/x86_64/5.4.0-42-generic",
+                "message": "bpftool exited with 255",
+            }],
+            "input_size": 8192,
+            "output_size": 1024,
+        }),
+    );
+}
@@ -1,14 +1,555 @@
 #ifndef _BTF_HELPER_H
 #define _BTF_HELPER_H
 
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
 #include <bpf/libbpf.h>
 
+struct bpf_compat_opts {
+	/* sizeof(struct bpf_compat_opts), for forward/backward compatibility: fields past sz
+	 * are treated as zero, and a larger struct than the library knows fails with -E2BIG
+	 * unless the unknown fields are zero */
+	size_t sz;
+	/* allocator of the returned path, the one of bpf_compatible_set_allocator (malloc) if NULL */
+	void *(*alloc)(size_t size);
+	/* deallocator matching alloc, the one of bpf_compatible_set_allocator (free) if NULL */
+	void (*free)(void *ptr);
+	/* store the btf in a sealed memfd, returned as /proc/self/fd/<fd> */
+	bool use_memfd;
+	/* directory of the temporary file, created with mode 0700 if missing;
+	 * $TMPDIR (or /tmp) if NULL */
+	const char *tmpdir;
+	/* keep the btf in $XDG_CACHE_HOME/bpf-compatible and reuse it on later calls,
+	 * unless BPF_COMPATIBLE_NO_CACHE is set */
+	bool use_cache;
+	/* with use_cache, extract the btf again even if it is cached */
+	bool refresh_cache;
+	/* without a btf for the exact kernel release, use the nearest point release
+	 * with the same major.minor and flavor, e.g. 5.4.0-144-generic for 5.4.0-148-generic;
+	 * same as BPF_COMPAT_MATCH_SAME_FLAVOR_NEAREST */
+	bool nearest_fallback;
+	/* one of BPF_COMPAT_MATCH_*, BPF_COMPAT_MATCH_EXACT if 0 */
+	int match_policy;
+	/* root the distro is detected in and /sys/kernel/btf/vmlinux is looked for,
+	 * e.g. the host's root mounted into a container; / if NULL */
+	const char *sysroot;
+	/* directory of the archive holding the btfs, "btfhub-archive" if NULL;
+	 * "" if the entries start directly with <distro>/ */
+	const char *archive_prefix;
+	/* if the kernel has native btf, still set *path, to /sys/kernel/btf/vmlinux
+	 * (under sysroot); clean_core_btf_rs only frees the string then */
+	bool always_path;
+	/* fail with -ENOKEY if the btf can't be checked against a SHA256SUMS manifest
+	 * of the archive; a digest mismatch fails with -EBADMSG in any case */
+	bool require_verification;
+	/* if the archive has no btf for the kernel, download it from btfhub-archive with curl
+	 * into the persistent cache (build with the download feature); failures keep -ENOENT,
+	 * with the reason in bpf_compatible_last_error. BPF_COMPATIBLE_DOWNLOAD does the same */
+	bool allow_download;
+	/* url of the btfs to download, with {distro}, {version}, {arch} and {kernel}
+	 * placeholders; $BPF_COMPATIBLE_DOWNLOAD_URL, or btfhub-archive on GitHub, if NULL */
+	const char *download_url;
+	/* extract the btf to <tmpdir>/<kernel release>-<hash>.btf (tmpdir defaulting to the
+	 * private bpf-compatible-<uid> directory of $TMPDIR), reusing the file another process
+	 * extracted; clean_core_btf_rs leaves it in place. BPF_COMPATIBLE_SHARED does the same */
+	bool share_extracted;
+	/* if the distro has no btf for the kernel, look it up under generic/<arch>/ and then
+	 * under any distro, preferring the running one; always done for rolling distros */
+	bool match_any_distro;
+	/* size the archive, and a compressed btf within it, may decompress to; past it the
+	 * lookup fails with -EFBIG. 4 GiB if 0 */
+	uint64_t max_decompressed_size;
+	/* if the archive has no btf for the kernel, generate it from the vmlinux of the kernel
+	 * with pahole --btf_encode_detached into the persistent cache (build with the pahole
+	 * feature); tried before allow_download, may take several seconds. Failures keep
+	 * -ENOENT, with the reason in bpf_compatible_last_error. BPF_COMPATIBLE_PAHOLE does the same */
+	bool allow_pahole;
+	/* pahole to run, pahole from PATH if NULL */
+	const char *pahole_path;
+	/* locations of the vmlinux with DWARF, separated by ':', with {release} standing for the
+	 * kernel release, under sysroot; /usr/lib/debug/boot/vmlinux-{release}, ..., /boot/vmlinux-{release}
+	 * and /lib/modules/{release}/build/vmlinux if NULL */
+	const char *vmlinux_locations;
+	/* strategies tried in order, n_strategies of BPF_COMPAT_STRATEGY_*; if NULL, the
+	 * override, native, installed, archive, then pahole and download if allowed. One that
+	 * finds nothing is skipped, any other failure ends the lookup. -EINVAL for an unknown
+	 * value, -ENOTSUP for a strategy this build doesn't have */
+	const int *strategies;
+	size_t n_strategies;
+	/* unpacked btfhub-archive looked up by BPF_COMPAT_STRATEGY_ARCHIVE_DIR */
+	const char *archive_dir;
+	/* called with progress_ctx every MiB of the decompressed tar read while the archive is
+	 * scanned, on the calling thread and never after the call returns; bytes_total_hint is
+	 * the size of the archive as given, exceeded by the bytes processed if it's compressed.
+	 * A non-zero return cancels the lookup with -ECANCELED, removing the partial btf file */
+	int (*progress)(uint64_t bytes_processed, uint64_t bytes_total_hint, void *ctx);
+	void *progress_ctx;
+	/* fail with -ENOPKG before scanning the archive if its manifest.json says it has no
+	 * btfs for the distro, version and arch of the system; archives without one are looked
+	 * up as usual. BPF_COMPATIBLE_STRICT_COVERAGE does the same */
+	bool strict_coverage;
+	/* name the temporary file <tempfile_prefix>XXXXXX<tempfile_suffix> in tmpdir, with 6
+	 * random letters or digits; "eunomia.btf." and no suffix if NULL. -EINVAL if the
+	 * prefix is empty or either has a '/'. Collect the files with
+	 * bpf_compatible_gc_stale_btf_tempfiles_template and the same names */
+	const char *tempfile_prefix;
+	const char *tempfile_suffix;
+	/* don't check that the btf of an archive entry is of the arch of its directory and of
+	 * the system, told from its struct pt_regs and pointer size; a btf of another arch
+	 * fails with -ENOEXEC otherwise */
+	bool skip_arch_check;
+	/* make clean_core_btf_opts overwrite the btf file with zeros, flush and truncate it
+	 * before removing it, so links to it made by others are emptied
+	 * too; the file is only removed if the path still names the inode that was zeroed */
+	bool zero_on_clean;
+};
+
+/* values of bpf_compat_opts.strategies */
+#define BPF_COMPAT_STRATEGY_NATIVE 1 /* the kernel's native btf */
+#define BPF_COMPAT_STRATEGY_OVERRIDE 2 /* the file named by BPF_COMPATIBLE_BTF_PATH */
+#define BPF_COMPAT_STRATEGY_INSTALLED 3 /* a btf installed for the kernel, e.g. /boot/vmlinux-<release> */
+#define BPF_COMPAT_STRATEGY_CACHE 4 /* the btf an earlier call kept in the persistent cache */
+#define BPF_COMPAT_STRATEGY_ARCHIVE 5 /* the archive the function is given */
+#define BPF_COMPAT_STRATEGY_ARCHIVE_DIR 6 /* the unpacked btfhub-archive at archive_dir */
+#define BPF_COMPAT_STRATEGY_DOWNLOAD 7 /* download from btfhub-archive (download feature) */
+#define BPF_COMPAT_STRATEGY_PAHOLE 8 /* generate with pahole (pahole feature) */
+
+/* values of bpf_compat_attempt.outcome */
+#define BPF_COMPAT_OUTCOME_HIT 1 /* the strategy found the btf */
+#define BPF_COMPAT_OUTCOME_MISS 2 /* the strategy has no btf for the kernel */
+#define BPF_COMPAT_OUTCOME_ERROR 3 /* the strategy failed, ending the lookup */
+
+/* a strategy the last lookup of the thread tried */
+struct bpf_compat_attempt {
+	int strategy; /* one of BPF_COMPAT_STRATEGY_* */
+	int outcome; /* one of BPF_COMPAT_OUTCOME_* */
+	int error; /* negative errno of BPF_COMPAT_OUTCOME_ERROR, 0 otherwise */
+};
+
+/* writes up to n of the strategies the last lookup of the thread tried to attempts (which
+ * may be NULL), in order, and returns how many it tried */
+size_t bpf_compatible_last_attempts(struct bpf_compat_attempt *attempts, size_t n);
+
+/* values of bpf_compat_opts.match_policy */
+#define BPF_COMPAT_MATCH_EXACT 0 /* only the btf of the exact kernel release */
+#define BPF_COMPAT_MATCH_SAME_FLAVOR_NEAREST 1 /* else the nearest point release of the same flavor */
+#define BPF_COMPAT_MATCH_BEST_EFFORT 2 /* else other flavors, then other distro versions */
+
+/* returned by the _status functions */
+#define BPF_COMPAT_CUSTOM_BTF 0 /* *path is set to the extracted btf */
+#define BPF_COMPAT_NATIVE_BTF 1 /* the kernel has native btf, *path is set to NULL
+				  * (or the native btf with always_path) */
+
+/* every function may be called from several threads at once. Threads extracting the same
+ * btf wait for the first one and are returned the same path, each to be cleaned on its own;
+ * cleaning one of them never removes the file another thread is about to return. The
+ * allocator is fixed atomically by bpf_compatible_set_allocator or the first allocation */
+
+/* returns 0 both if a btf was extracted to *path or if the kernel has native btf
+ * (setting *path to NULL), a negative errno otherwise. -EMEDIUMTYPE means the archive is
+ * readable but not a btf archive: nothing is under btfhub-archive/ nor at its root, e.g.
+ * another tarball was linked by mistake; -EINVAL is left to bad arguments and corrupt data */
 int ensure_core_btf_with_tar_binary(const char **path, const char *tar_bin, int tar_len);
 
+/* same as ensure_core_btf_with_tar_binary, for archives larger than 2 GiB */
+int ensure_core_btf_with_tar_binary2(const char **path, const unsigned char *tar_bin,
+				     size_t tar_len);
+
+/* like ensure_core_btf_with_tar_binary2, but for the given system; NULL arguments are
+ * detected from the running system, and the native btf is only used if kernel_release
+ * is NULL or that of the running kernel */
+int ensure_core_btf_for_system(const char **path, const unsigned char *tar, size_t len,
+			       const char *distro, const char *version, const char *arch,
+			       const char *kernel_release);
+
+/* returns BPF_COMPAT_CUSTOM_BTF, BPF_COMPAT_NATIVE_BTF or a negative errno */
+int ensure_core_btf_with_tar_binary_status(const char **path, const unsigned char *tar_bin,
+					   size_t tar_len);
+
+int ensure_core_btf_with_linked_tar_status(const char **path);
+
+/* writes a raw btf (no archive) to *path, returns BPF_COMPAT_CUSTOM_BTF,
+ * BPF_COMPAT_NATIVE_BTF or a negative errno, -EILSEQ if the buffer isn't a btf */
+int ensure_core_btf_with_raw_btf(const char **path, const unsigned char *btf, size_t len);
+
+int ensure_core_btf_with_tar_binary_opts(const char **path, const char *tar_bin, size_t tar_len,
+					 const struct bpf_compat_opts *opts);
+
+/* values of bpf_compat_match_info.source */
+#define BPF_COMPAT_SOURCE_NATIVE 1 /* the kernel's native btf */
+#define BPF_COMPAT_SOURCE_INSTALLED 2 /* a btf installed for the kernel, e.g. /boot/vmlinux-<release> */
+#define BPF_COMPAT_SOURCE_ARCHIVE 3 /* an entry of the archive */
+#define BPF_COMPAT_SOURCE_CACHE 4 /* the persistent cache, filled by an earlier call */
+#define BPF_COMPAT_SOURCE_DOWNLOAD 5 /* downloaded from btfhub-archive */
+#define BPF_COMPAT_SOURCE_OVERRIDE 6 /* the file named by BPF_COMPATIBLE_BTF_PATH */
+#define BPF_COMPAT_SOURCE_PAHOLE 7 /* generated from the vmlinux of the kernel with pahole */
+
+/* values of bpf_compat_match_info.native_status, what became of /sys/kernel/btf/vmlinux */
+#define BPF_COMPAT_NATIVE_STATUS_UNKNOWN 0 /* not looked at, e.g. BPF_COMPATIBLE_BTF_PATH was set */
+#define BPF_COMPAT_NATIVE_STATUS_USABLE 1 /* used, the source is BPF_COMPAT_SOURCE_NATIVE */
+#define BPF_COMPAT_NATIVE_STATUS_MISSING 2 /* the kernel exports no btf */
+#define BPF_COMPAT_NATIVE_STATUS_UNUSABLE 3 /* there, but unreadable (e.g. in a container) or invalid */
+#define BPF_COMPAT_NATIVE_STATUS_IGNORED 4 /* not of the kernel looked up, e.g. a faked system */
+
+/* the btf a lookup settled on; set sz to sizeof(struct bpf_compat_match_info), fields past
+ * it aren't written */
+struct bpf_compat_match_info {
+	size_t sz;
+	int source; /* one of BPF_COMPAT_SOURCE_* */
+	bool exact; /* the btf is of the kernel release itself, false if unknown (cache, override) */
+	char entry_path[256]; /* entry of the archive, or file on disk; NUL-terminated, truncated,
+			       * "" for the native btf and the cache */
+	char kernel_release[128]; /* release the btf is of, e.g. the nearest one if not exact */
+	bool local_version_stripped; /* matched only once the local version of a custom build,
+				      * like "-mycorp1" or "+", was stripped from the release */
+	int candidate; /* position of entry_path among the archive paths of the system, 0 for
+			* the most preferred, more for a fallback like the raw VERSION_ID or
+			* another name of the arch; -1 if none, e.g. a nearby release, or not from the archive */
+	int native_status; /* one of BPF_COMPAT_NATIVE_STATUS_* */
+	bool memoized; /* reused the btf an earlier call extracted, described as that call found it */
+};
+
+/* same as ensure_core_btf_with_tar_binary_opts, also describing the btf in *info on success */
+int ensure_core_btf_with_tar_binary_match(const char **path, const unsigned char *tar, size_t len,
+					  const struct bpf_compat_opts *opts,
+					  struct bpf_compat_match_info *info);
+
+/* same as ensure_core_btf_with_tar_binary_opts, also storing in *size (unless NULL) the
+ * number of bytes of the btf written to *path, after decompressing the entry; if the kernel
+ * has native btf, 0, or the size of /sys/kernel/btf/vmlinux with always_path */
+int ensure_core_btf_with_tar_binary_sized(const char **path, const unsigned char *tar, size_t len,
+					  const struct bpf_compat_opts *opts, size_t *size);
+
 int ensure_core_btf_with_linked_tar(const char **path);
 
+int ensure_core_btf_with_linked_tar_opts(const char **path, const struct bpf_compat_opts *opts);
+
+/* name of the section ensure_core_btf_with_self_section reads the archive from by default */
+#define BPF_COMPAT_BTF_SECTION ".bpf_compat_btfs"
+
+/* same as ensure_core_btf_with_linked_tar, reading the archive from the section section_name
+ * (BPF_COMPAT_BTF_SECTION if NULL) of /proc/self/exe, e.g. added with
+ * objcopy --add-section .bpf_compat_btfs=min_core_btfs.tar.gz or bpf-compat embed; the
+ * executable must keep its section headers. Returns -ENOENT if there is no such section */
+int ensure_core_btf_with_self_section(const char **path, const char *section_name);
+
+/* same as ensure_core_btf_with_self_section, with options */
+int ensure_core_btf_with_self_section_opts(const char **path, const char *section_name,
+					   const struct bpf_compat_opts *opts);
+
+/* remove every btf from the cache used with use_cache */
+int bpf_compatible_clear_cache(void);
+
+/* like ensure_core_btf_with_tar_binary, but the temporary file is created under tmpdir */
+int ensure_core_btf_with_tar_binary_tmpdir(const char **path, const unsigned char *tar_bin,
+					   size_t tar_len, const char *tmpdir);
+
+/* like ensure_core_btf_with_tar_binary, but the btf is kept in a sealed memfd */
+int ensure_core_btf_with_tar_binary_memfd(const char **path, const unsigned char *tar_bin,
+					  size_t tar_len);
+
+/* like ensure_core_btf_with_tar_binary, but returns the btf contents in a malloc'd buffer,
+ * or sets *buf to NULL if the kernel has native btf */
+int ensure_core_btf_bytes_with_tar_binary(unsigned char **buf, size_t *len,
+					  const unsigned char *tar_bin, int tar_len);
+
+int ensure_core_btf_bytes_with_linked_tar(unsigned char **buf, size_t *len);
+
+void bpf_compatible_free_buffer(void *buf);
+
+/* same lookup as ensure_core_btf_with_tar_binary, writing the btf to out_fd from its current
+ * offset instead of a file of the library; out_fd is neither closed nor seeked back. Returns
+ * the number of bytes written, 0 if the kernel has native btf, or a negative errno, after
+ * which part of the btf may have been written */
+int ensure_core_btf_write_fd(int out_fd, const unsigned char *tar, size_t len);
+
+/* same as ensure_core_btf_with_tar_binary2, writing the path into buf; returns the size the
+ * path needs (NUL included), which is at most buf_len if it was written, 0 with buf set to ""
+ * if the kernel has native btf, or a negative errno. With buf NULL or too small nothing is
+ * kept, so call again with a buffer of the returned size. Release with clean_core_btf_path */
+int ensure_core_btf_path_buf(char *buf, size_t buf_len, const unsigned char *tar, size_t tar_len);
+
+/* removes the btf written by ensure_core_btf_path_buf if this library created it; returns
+ * BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED if it was left in place, or a negative errno */
+int clean_core_btf_path(const char *path);
+
+/* extracts every btf that may be used for the running kernel, best first, to a malloc'd
+ * NULL-terminated array of paths; returns their number, 0 with *paths set to NULL if
+ * the kernel has native btf, or a negative errno */
+int ensure_core_btf_candidates_with_tar_binary(char ***paths, const unsigned char *tar_bin,
+					       size_t tar_len);
+
+/* removes the btfs returned by ensure_core_btf_candidates_with_tar_binary and frees the array */
+void bpf_compatible_free_candidates(char **paths);
+
+/* same as ensure_core_btf_with_tar_binary, reading the archive from tar_path; returns
+ * -ENOENT if it's missing, -EACCES if it can't be read, -EINVAL if it isn't an archive */
+int ensure_core_btf_with_tar_file(const char **path, const char *tar_path);
+
+/* same as ensure_core_btf_with_tar_binary_opts, for an archive shipped as a file; the file
+ * is mapped rather than read, and -ESTALE is returned if it changed during the lookup */
+int ensure_core_btf_with_archive_file(const char **path, const char *archive_path,
+				      const struct bpf_compat_opts *opts);
+
+/* values of bpf_compat_source.kind */
+#define BPF_COMPAT_SRC_BUFFER 1 /* the archive in buf and len */
+#define BPF_COMPAT_SRC_FILE 2 /* the archive file at path */
+#define BPF_COMPAT_SRC_LINKED 3 /* the archive linked into the executable */
+
+/* one of the archives searched by ensure_core_btf_multi */
+struct bpf_compat_source {
+	int kind; /* one of BPF_COMPAT_SRC_* */
+	const unsigned char *buf;
+	size_t len;
+	const char *path;
+};
+
+/* same as ensure_core_btf_with_tar_binary, taking the btf from the first of the n sources
+ * with one for the kernel; a missing file is skipped like an archive without the btf. If
+ * none has it, returns the first other failure, with its message in
+ * bpf_compatible_last_error, else -ENOENT */
+int ensure_core_btf_multi(const struct bpf_compat_source *sources, size_t n, const char **path);
+
+/* same as ensure_core_btf_multi, with the lookup configured by opts (may be NULL) */
+int ensure_core_btf_multi_opts(const struct bpf_compat_source *sources, size_t n,
+			       const char **path, const struct bpf_compat_opts *opts);
+
+/* same as ensure_core_btf_with_tar_binary, reading the archive from fd, from the start if
+ * it can seek, else until end of file (e.g. a pipe); fd is left open */
+int ensure_core_btf_with_fd(const char **path, int fd);
+
+/* same as ensure_core_btf_with_tar_binary, looking the btf up in the unpacked btfhub-archive
+ * at dir, as <release>.btf or e.g. <release>.btf.tar.xz; a plain btf is returned as it is
+ * and never removed by clean_core_btf_rs, a compressed one is extracted to a temporary file */
+int ensure_core_btf_from_dir(const char **path, const char *dir);
+
+/* an archive decompressed and indexed once, for repeated lookups */
+struct bpf_compat_archive;
+
+/* decompresses and indexes the archive, returns 0 with *archive set or a negative errno;
+ * if a path occurs more than once, the last entry wins */
+int bpf_compat_archive_open(struct bpf_compat_archive **archive, const unsigned char *tar,
+			    size_t len);
+
+/* same as bpf_compat_archive_open, for the archive linked into the executable */
+int bpf_compat_archive_open_linked_tar(struct bpf_compat_archive **archive);
+
+/* same as ensure_core_btf_with_tar_binary_opts, without decompressing the archive again;
+ * opts may be NULL */
+int bpf_compat_archive_lookup(const struct bpf_compat_archive *archive, const char **path,
+			      const struct bpf_compat_opts *opts);
+
+/* releases the archive; paths returned by bpf_compat_archive_lookup stay valid */
+void bpf_compat_archive_close(struct bpf_compat_archive *archive);
+
+/* sets *path to the split btf of the kernel module named module: the kernel's own (e.g.
+ * /sys/kernel/btf/<module>, only freed by clean_core_btf_rs) if it has one, otherwise the
+ * archive's <release>/modules/<module>.btf extracted to a temporary file; returns
+ * BPF_COMPAT_NATIVE_BTF with *path set to NULL if the kernel has native btf but none
+ * for the module. opts may be NULL */
+int ensure_module_btf(const char **path, const unsigned char *tar, size_t len, const char *module,
+		      const struct bpf_compat_opts *opts);
+
+/* flags of extract_core_btf_to */
+#define BPF_COMPAT_EXTRACT_OVERWRITE (1U << 0) /* replace an existing file, else -EEXIST */
+#define BPF_COMPAT_EXTRACT_CREATE_DIRS (1U << 1) /* create missing parent directories */
+#define BPF_COMPAT_EXTRACT_ALWAYS (1U << 2) /* write the btf even if the kernel has native btf */
+
+/* writes the btf of the running kernel to exactly dest_path, through a temporary file in
+ * the same directory renamed into place; returns BPF_COMPAT_CUSTOM_BTF, or
+ * BPF_COMPAT_NATIVE_BTF without writing if the kernel has native btf (unless
+ * BPF_COMPAT_EXTRACT_ALWAYS), or a negative errno */
+int extract_core_btf_to(const unsigned char *tar, size_t len, const char *dest_path,
+			unsigned int flags);
+
+/* the btf of the running kernel, kept for libbpf's open options */
+struct bpf_compat_ctx;
+
+/* looks up the btf as ensure_core_btf_with_tar_binary does; returns NULL with errno set
+ * on failure */
+struct bpf_compat_ctx *bpf_compat_open(const unsigned char *tar, size_t len);
+
+/* sets opts->btf_custom_path to the btf of ctx, NULL if the kernel has native btf;
+ * opts_sz is sizeof(*opts). Returns BPF_COMPAT_NATIVE_BTF or BPF_COMPAT_CUSTOM_BTF,
+ * -EINVAL if opts has no btf_custom_path. Keep ctx open until bpf_object__load is done */
+int bpf_compat_fill_open_opts(struct bpf_compat_ctx *ctx, struct bpf_object_open_opts *opts,
+			      size_t opts_sz);
+
+/* removes the btf of ctx and frees it; NULL is ignored */
+void bpf_compat_close(struct bpf_compat_ctx *ctx);
+
+/* values returned by core_btf_is_available, besides BPF_COMPAT_NATIVE_BTF */
+#define BPF_COMPAT_BTF_UNAVAILABLE 0 /* neither the kernel nor the archive has a btf */
+#define BPF_COMPAT_ARCHIVE_BTF 2 /* the archive has a btf for the kernel */
+
+/* tells whether a btf would be found for the running kernel, without creating any file:
+ * returns BPF_COMPAT_NATIVE_BTF, BPF_COMPAT_ARCHIVE_BTF, BPF_COMPAT_BTF_UNAVAILABLE or a
+ * negative errno if the archive can't be read */
+int core_btf_is_available(const unsigned char *tar, size_t len);
+
+/* same as core_btf_is_available, with the lookup configured by opts */
+int core_btf_is_available_opts(const unsigned char *tar, size_t len,
+			       const struct bpf_compat_opts *opts);
+
+/* same as core_btf_is_available, for the archive linked into the executable */
+int core_btf_is_available_linked_tar(void);
+
+/* flag of diagnose_core_btf: try the download, into memory, to tell whether it would work */
+#define BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD (1U << 0)
+
+/* explains the lookup of ensure_core_btf_with_tar_binary_opts, e.g. after -ENOENT: stores a
+ * malloc'd report of the detected system, the paths searched, the kernel's own btf, the
+ * entries scanned, the nearest kernels of the archive and the fallbacks consulted in
+ * *report, to free with bpf_compatible_free_buffer (or the free of opts). Writes nothing and
+ * doesn't reach the network unless flags has BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD; opts may be
+ * NULL. Returns 0 or a negative errno */
+int diagnose_core_btf(const char **report, const unsigned char *tar, size_t len,
+		      const struct bpf_compat_opts *opts, unsigned int flags);
+
+/* lists the kernels the archive has a btf for, like "ubuntu/20.04/x86_64/5.4.0-40-generic",
+ * in archive order, as a malloc'd NULL-terminated array of *count strings; returns 0 or a
+ * negative errno */
+int list_core_btf_kernels(const unsigned char *tar, size_t len, char ***entries, size_t *count);
+
+/* same as list_core_btf_kernels, for the archive linked into the executable */
+int list_core_btf_kernels_linked_tar(char ***entries, size_t *count);
+
+/* frees the array returned by list_core_btf_kernels */
+void free_core_btf_kernel_list(char **entries);
+
+/* writes the btfs of the kernels matching the fnmatch(3) pattern, like "ubuntu/20.04/x86_64/5.4.*",
+ * under dest_dir as <distro>/<version>/<arch>/<release>.btf; a NULL pattern matches every
+ * kernel. Returns the number of btfs written or a negative errno; btfs that failed to
+ * extract are counted in *failed unless it is NULL */
+int extract_core_btfs_to_dir(const unsigned char *tar, size_t len, const char *pattern,
+			     const char *dest_dir, size_t *failed);
+
+/* summary of an archive; set sz to sizeof(struct bpf_compat_archive_info), fields past it
+ * aren't written */
+struct bpf_compat_archive_info {
+	size_t sz;
+	size_t btf_entries; /* number of btfs, links included */
+	uint64_t uncompressed_size; /* size of the tar */
+	uint64_t compressed_size; /* size of the archive as given */
+	int64_t build_time; /* seconds since the epoch, -1 if unknown */
+	char build_id[64]; /* NUL-terminated, truncated; "" if unknown */
+};
+
+/* describes the archive in *info, with the build time and id btfgen writes to
+ * btfhub-archive/.metadata; returns 0 or a negative errno */
+int get_core_btf_archive_info(const unsigned char *tar, size_t len,
+			      struct bpf_compat_archive_info *info);
+
+/* same as get_core_btf_archive_info, for the archive linked into the executable */
+int get_core_btf_archive_info_linked_tar(struct bpf_compat_archive_info *info);
+
+/* stores the path the btf of the running system has under btfhub-archive in *path, like
+ * "ubuntu/20.04/x86_64/5.4.0-40-generic.btf", without reading any archive; free it with
+ * bpf_compatible_free_buffer. Returns 0, or -ENOENT if the distro can't be told */
+int get_current_system_btf_rel_path(const char **path);
+
+/* identity of the running system; set sz to sizeof(struct bpf_compat_system_info), fields
+ * past it aren't written */
+struct bpf_compat_system_info {
+	size_t sz;
+	char distro[64]; /* ID of os-release, e.g. "ubuntu" */
+	char version[64]; /* VERSION_ID of os-release, e.g. "20.04"; "" for rolling distros */
+	char arch[32]; /* machine reported by uname, e.g. "x86_64" */
+	char kernel_release[128]; /* release reported by uname, e.g. "5.4.0-40-generic" */
+};
+
+/* describes the running system in *info, as a lookup sees it; returns 0, or -ENOENT if the
+ * distro can't be told */
+int get_current_system_info(struct bpf_compat_system_info *info);
+
+/* removes the btf file (or memfd) created by this library and frees the path string; files it
+ * didn't create (cached, shared, native or installed btfs) are left in place */
 void clean_core_btf_rs(const char *path);
 
+#define BPF_COMPAT_BTF_DELETED 1 /* the btf file (or memfd) was removed */
+#define BPF_COMPAT_PATH_FREED 0 /* the file isn't ours, only the string was freed */
+
+/* same as clean_core_btf_rs, returning BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED or a
+ * negative errno if the file couldn't be removed (the string is freed anyway); a path not
+ * returned by this library, or already cleaned, is left alone with -EINVAL. Don't use the
+ * pointer after the first call: the guard can't catch an address the allocator reused */
+int clean_core_btf_rs2(const char *path);
+
+/* same as clean_core_btf_rs2, freeing the string with opts->free. If opts->zero_on_clean is
+ * set, the file is zeroed first; a file swapped in under the path is left alone with
+ * -ESTALE, and a file that couldn't be zeroed (e.g. -EROFS, -EACCES) is still removed if
+ * it's the one created, returning the errno of the zeroing; bpf_compatible_last_error()
+ * tells which step failed and why */
+int clean_core_btf_opts(const char *path, const struct bpf_compat_opts *opts);
+
+/* outcome of bpf_compatible_gc_stale_btf_tempfiles; set sz to
+ * sizeof(struct bpf_compat_gc_report), fields past it aren't written */
+struct bpf_compat_gc_report {
+	size_t sz;
+	size_t removed; /* stale files removed */
+	size_t recent; /* files too recent, or still held by this process, left in place */
+	size_t failed; /* stale files that couldn't be removed */
+	uint64_t freed_bytes; /* size of the removed files */
+};
+
+/* removes the eunomia.btf.XXXXXX files of the user last modified more than max_age_secs ago,
+ * left behind by processes that crashed or didn't clean them, from dir, or if NULL from
+ * $TMPDIR (or /tmp) and the user's private directory under it; report may be NULL. Returns
+ * 0 or a negative errno if dir can't be listed */
+int bpf_compatible_gc_stale_btf_tempfiles(const char *dir, uint64_t max_age_secs,
+					  struct bpf_compat_gc_report *report);
+
+/* same as bpf_compatible_gc_stale_btf_tempfiles, for the files named after prefix and
+ * suffix as with bpf_compat_opts.tempfile_prefix and tempfile_suffix; NULL for the
+ * default. Returns -EINVAL for a template the options would reject */
+int bpf_compatible_gc_stale_btf_tempfiles_template(const char *dir, const char *prefix,
+						   const char *suffix, uint64_t max_age_secs,
+						   struct bpf_compat_gc_report *report);
+
+/* removes the btf files this process was returned and didn't clean when it exits, through
+ * atexit(3); not if it's killed. Idempotent, returns 0 */
+int bpf_compatible_register_cleanup_at_exit(void);
+
+#define BPF_COMPAT_LOG_ERROR 0 /* a failure */
+#define BPF_COMPAT_LOG_INFO 1 /* something that doesn't make the call fail, e.g. a fallback */
+#define BPF_COMPAT_LOG_DEBUG 2 /* a detail of the lookup, e.g. the entry that matched */
+
+/* routes the diagnostics to log_fn instead of stderr, NULL restores the default; msg is only
+ * valid during the call. log_fn may run on any thread using the library and must not call
+ * back into it; once this returns, the previous log_fn is no longer running anywhere */
+void bpf_compatible_set_log_fn(void (*log_fn)(int level, const char *msg, void *ctx), void *ctx);
+
+/* allocates every buffer returned by the library (paths, btf buffers, string arrays) with
+ * alloc, released with dealloc by the library's own release functions; malloc and free by
+ * default. Returns 0, or -EBUSY once a buffer was allocated or another allocator was set */
+int bpf_compatible_set_allocator(void *(*alloc)(size_t size), void (*dealloc)(void *ptr));
+
+/* message of the last failed call on this thread, or NULL if it succeeded; valid until the
+ * next call returning an int status on the same thread */
+const char *bpf_compatible_last_error(void);
+
+/* static description of a status returned by this library (0, a BPF_COMPAT_* status or a
+ * negative errno), in its own terms; never NULL */
+const char *bpf_compatible_strerror(int err);
+
+/* version of the library, the string is static */
+const char *bpf_compatible_version(void);
+
+/* bits of bpf_compatible_features, one per cargo feature */
+#define BPF_COMPAT_FEATURE_AUDIT_LOG (1U << 0) /* resolutions recorded, see BPF_COMPATIBLE_AUDIT_LOG */
+#define BPF_COMPAT_FEATURE_ZSTD (1U << 1) /* zstd compressed archives */
+#define BPF_COMPAT_FEATURE_XZ (1U << 2) /* xz compressed archives */
+#define BPF_COMPAT_FEATURE_DOWNLOAD (1U << 3) /* btfs missing from the archive downloaded, see allow_download */
+#define BPF_COMPAT_FEATURE_FAKE_SYSTEM (1U << 4) /* identity of the system taken from BPF_COMPATIBLE_FAKE_*, for testing only */
+#define BPF_COMPAT_FEATURE_PAHOLE (1U << 5) /* btfs missing from the archive generated with pahole, see allow_pahole */
+
+/* features the library was built with, as BPF_COMPAT_FEATURE_* bits; fixed at build time */
+unsigned int bpf_compatible_features(void);
+
+/* identity of the linked tar archive ("none" if not linked), the string is static */
+const char *bpf_compatible_archive_identity(void);
+
 static int ensure_core_btf(struct bpf_object_open_opts *opts)
 {
 	return ensure_core_btf_with_linked_tar(&opts->btf_custom_path);
@@ -24,8 +565,4 @@ static void cleanup_core_btf(struct bpf_object_open_opts *opts)
 	clean_core_btf_rs(opts->btf_custom_path);
 }
 
-// Allow for not linking the tar binary
-char _binary_min_core_btfs_tar_gz_start __attribute__((weak));
-char _binary_min_core_btfs_tar_gz_end __attribute__((weak));
-
 #endif // _BTF_HELPER_H
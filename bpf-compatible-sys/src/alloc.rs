@@ -0,0 +1,43 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The allocator of every buffer handed to the caller, `malloc` and `free` unless replaced.
+use std::{ffi::c_int, sync::OnceLock};
+
+use libc::{c_void, EBUSY};
+
+use crate::opts::{AllocFn, FreeFn};
+
+/// Fixed on the first allocation, or by `bpf_compatible_set_allocator`, whichever comes first
+static ALLOCATOR: OnceLock<(AllocFn, FreeFn)> = OnceLock::new();
+
+fn allocator() -> &'static (AllocFn, FreeFn) {
+    ALLOCATOR.get_or_init(|| (libc::malloc, libc::free))
+}
+
+/// Use `alloc` and `free` for the buffers returned from now on
+///
+/// Returns `-EBUSY` if a buffer was already allocated (or another allocator set), as it
+/// would be released with the wrong function otherwise. Setting the same pair again is fine.
+pub(crate) fn set_allocator(alloc: AllocFn, free: FreeFn) -> c_int {
+    let current = ALLOCATOR.get_or_init(|| (alloc, free));
+    // 函数指针按地址比较即可判断是否为同一对函数
+    if current.0 as usize == alloc as usize && current.1 as usize == free as usize {
+        0
+    } else {
+        report!("The allocator can't be replaced once a buffer was allocated");
+        -EBUSY
+    }
+}
+
+/// Allocate `size` bytes with the registered allocator
+pub(crate) unsafe extern "C" fn alloc(size: usize) -> *mut c_void {
+    (allocator().0)(size)
+}
+
+/// Release a buffer of `alloc`
+pub(crate) unsafe extern "C" fn free(ptr: *mut c_void) {
+    (allocator().1)(ptr)
+}
@@ -0,0 +1,113 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The strategies of `bpf_compat_opts.strategies`, and `struct bpf_compat_attempt` of the
+//! C API, describing how each strategy the last lookup of the thread tried fared
+use std::{
+    cell::RefCell,
+    ffi::{c_int, OsStr},
+    path::PathBuf,
+};
+
+use bpf_compatible_rs::chain::Strategy;
+
+use crate::{
+    platform::OsStrExt, BPF_COMPAT_OUTCOME_ERROR, BPF_COMPAT_OUTCOME_HIT, BPF_COMPAT_OUTCOME_MISS,
+    BPF_COMPAT_STRATEGY_ARCHIVE, BPF_COMPAT_STRATEGY_ARCHIVE_DIR, BPF_COMPAT_STRATEGY_CACHE,
+    BPF_COMPAT_STRATEGY_DOWNLOAD, BPF_COMPAT_STRATEGY_INSTALLED, BPF_COMPAT_STRATEGY_NATIVE,
+    BPF_COMPAT_STRATEGY_OVERRIDE, BPF_COMPAT_STRATEGY_PAHOLE,
+};
+
+/// A strategy the last lookup tried, see `bpf_compatible_last_attempts`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfCompatAttempt {
+    /// One of `BPF_COMPAT_STRATEGY_*`
+    pub strategy: c_int,
+    /// One of `BPF_COMPAT_OUTCOME_*`
+    pub outcome: c_int,
+    /// The negative errno the strategy failed with, 0 unless `outcome` is `BPF_COMPAT_OUTCOME_ERROR`
+    pub error: c_int,
+}
+
+thread_local! {
+    static LAST_ATTEMPTS: RefCell<Vec<BpfCompatAttempt>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The strategy `raw` stands for, `archive_dir` being the directory of `BPF_COMPAT_STRATEGY_ARCHIVE_DIR`
+///
+/// `None` if `raw` isn't one of `BPF_COMPAT_STRATEGY_*`.
+pub(crate) fn strategy_from_raw(raw: c_int, archive_dir: Option<&[u8]>) -> Option<Strategy> {
+    Some(match raw {
+        BPF_COMPAT_STRATEGY_NATIVE => Strategy::Native,
+        BPF_COMPAT_STRATEGY_OVERRIDE => Strategy::EnvOverride,
+        BPF_COMPAT_STRATEGY_INSTALLED => Strategy::Installed,
+        BPF_COMPAT_STRATEGY_CACHE => Strategy::Cache,
+        BPF_COMPAT_STRATEGY_ARCHIVE => Strategy::EmbeddedArchive,
+        BPF_COMPAT_STRATEGY_ARCHIVE_DIR => {
+            Strategy::ArchiveDir(PathBuf::from(OsStr::from_bytes(archive_dir?)))
+        }
+        BPF_COMPAT_STRATEGY_DOWNLOAD => Strategy::Download,
+        BPF_COMPAT_STRATEGY_PAHOLE => Strategy::Pahole,
+        _ => return None,
+    })
+}
+
+/// The `BPF_COMPAT_STRATEGY_*` of `strategy`
+pub(crate) fn strategy_to_raw(strategy: &Strategy) -> c_int {
+    match strategy {
+        Strategy::Native => BPF_COMPAT_STRATEGY_NATIVE,
+        Strategy::EnvOverride => BPF_COMPAT_STRATEGY_OVERRIDE,
+        Strategy::Installed => BPF_COMPAT_STRATEGY_INSTALLED,
+        Strategy::Cache => BPF_COMPAT_STRATEGY_CACHE,
+        Strategy::EmbeddedArchive => BPF_COMPAT_STRATEGY_ARCHIVE,
+        Strategy::ArchiveDir(_) => BPF_COMPAT_STRATEGY_ARCHIVE_DIR,
+        Strategy::Download => BPF_COMPAT_STRATEGY_DOWNLOAD,
+        Strategy::Pahole => BPF_COMPAT_STRATEGY_PAHOLE,
+    }
+}
+
+/// Forget the attempts of the previous lookup, before a new one
+pub(crate) fn clear() {
+    LAST_ATTEMPTS.with(|v| v.borrow_mut().clear());
+}
+
+/// Record that `strategy` was tried: `ret` is `None` on a miss, else what it returned
+pub(crate) fn record(strategy: &Strategy, ret: Option<c_int>) {
+    let (outcome, error) = match ret {
+        None => (BPF_COMPAT_OUTCOME_MISS, 0),
+        Some(v) if v >= 0 => (BPF_COMPAT_OUTCOME_HIT, 0),
+        Some(v) => (BPF_COMPAT_OUTCOME_ERROR, v),
+    };
+    debug!(
+        "{}: {}",
+        strategy,
+        match outcome {
+            BPF_COMPAT_OUTCOME_HIT => "hit",
+            BPF_COMPAT_OUTCOME_MISS => "miss",
+            _ => "error",
+        }
+    );
+    LAST_ATTEMPTS.with(|v| {
+        v.borrow_mut().push(BpfCompatAttempt {
+            strategy: strategy_to_raw(strategy),
+            outcome,
+            error,
+        })
+    });
+}
+
+/// Copy the attempts recorded on the thread to `out`, of `n` entries, returning how many were recorded
+pub(crate) fn write_attempts(out: *mut BpfCompatAttempt, n: usize) -> usize {
+    LAST_ATTEMPTS.with(|v| {
+        let attempts = v.borrow();
+        if !out.is_null() {
+            for (i, attempt) in attempts.iter().take(n).enumerate() {
+                unsafe { *out.add(i) = *attempt };
+            }
+        }
+        attempts.len()
+    })
+}
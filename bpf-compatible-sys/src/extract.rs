@@ -0,0 +1,2036 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Lookup of the running kernel's btf in a (possibly compressed) btfhub tar
+use std::{
+    collections::HashMap,
+    ffi::{c_int, OsStr},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use bpf_compatible_rs::reexport::flate2::read::GzDecoder;
+use bpf_compatible_rs::{
+    archive::{normalize_entry_path, BtfEncoding, BtfhubArchive},
+    btf::{check_btf_arch, validate_btf_bytes},
+    compression::{tar_archive, tar_entries, tar_reader_with_limit, LimitedReader},
+    flat::{arch_marker_allows, parse_flat_btf_path, ARCH_MARKER_NAME},
+    identity::archive_key,
+    index::ArchiveIndex,
+    layout::is_random_access,
+    listing::{ArchiveListing, LISTING_ENTRY_NAME},
+    manifest::{Manifest, MANIFEST_ENTRY_NAME},
+    match_info::{candidate_of, is_release_of, is_stripped_release_of},
+    matcher::{btf_release, encoded_paths, BtfMatcher},
+    parsed::ParsedArchive,
+    progress::ProgressTracker,
+    release::MatchPolicy,
+    sparse::{self, is_file_entry},
+    tar::{Archive, Entry, EntryType},
+    version::debian_backport,
+    BtfSource, Error, MatchInfo, NativeBtfStatus, SystemInfo,
+};
+use libc::{EBADMSG, ECANCELED, EFBIG, EILSEQ, EINVAL, EIO, ELOOP, ENOENT, ENOEXEC, ENOTSUP};
+
+use crate::{
+    match_info,
+    memo::{self, ArchiveFingerprint},
+    opts::Options,
+    platform::{OsStrExt, EMEDIUMTYPE, ENOKEY, ENOPKG, ESTALE},
+    BpfCompatArchive, STRICT_COVERAGE_ENV,
+};
+
+/// 解析链接时最多跟随的层数
+const MAX_LINK_DEPTH: usize = 8;
+
+/// Destination of the contents of the matching entry
+pub(crate) trait BtfSink {
+    /// Replace whatever the sink holds with everything read from `reader`
+    fn overwrite_from(&mut self, reader: &mut dyn Read) -> Result<(), c_int>;
+}
+
+impl BtfSink for Vec<u8> {
+    fn overwrite_from(&mut self, reader: &mut dyn Read) -> Result<(), c_int> {
+        self.clear();
+        if let Err(e) = reader.read_to_end(self) {
+            report!("Failed to read the btf: {}", e);
+            return Err(stream_errno(&e));
+        }
+        Ok(())
+    }
+}
+
+/// Sink dropping whatever it is given, to check that a btf would be found without keeping it
+pub(crate) struct Discard;
+
+impl BtfSink for Discard {
+    fn overwrite_from(&mut self, reader: &mut dyn Read) -> Result<(), c_int> {
+        if let Err(e) = std::io::copy(reader, &mut std::io::sink()) {
+            report!("Failed to read the btf: {}", e);
+            return Err(stream_errno(&e));
+        }
+        Ok(())
+    }
+}
+
+/// The archive a btf is looked up in
+#[derive(Clone, Copy)]
+pub(crate) enum TarSource<'a> {
+    /// A (possibly compressed) tar, read again from the start on every lookup
+    Bytes(&'a [u8]),
+    /// An archive decompressed and indexed once, see `bpf_compat_archive_open`
+    Parsed(&'a BpfCompatArchive),
+}
+
+impl TarSource<'_> {
+    /// The bytes the archive is read from; the decompressed tar of a parsed archive
+    fn bytes(&self) -> &[u8] {
+        match self {
+            TarSource::Bytes(v) => v,
+            TarSource::Parsed(v) => v.parsed.tar(),
+        }
+    }
+
+    /// Fingerprint of the archive, see `ArchiveFingerprint`
+    pub(crate) fn fingerprint(&self) -> ArchiveFingerprint {
+        ArchiveFingerprint::of(self.bytes())
+    }
+
+    /// Key of the archive in the persistent cache, see `archive_key`
+    pub(crate) fn key(&self) -> String {
+        match self {
+            TarSource::Bytes(v) => archive_key(v),
+            TarSource::Parsed(v) => v.key.clone(),
+        }
+    }
+}
+
+/// Look up the btf of the running kernel (or `opts.system`) in the tar, copying it to a sink created by `new_sink`
+///
+/// `new_sink` is only called once a matching entry is found. Unless `opts.policy` is
+/// `Exact`, a btf of a close release is used if the exact one is missing, see [`BtfMatcher::fallback`].
+/// Fails with `-ECANCELED` if `opts.progress` cancels the decompression, the sink created
+/// being dropped, which removes a temporary file.
+pub(crate) fn lookup_btf<S: BtfSink>(
+    source: TarSource,
+    opts: &Options,
+    new_sink: impl FnMut() -> Result<S, c_int>,
+) -> Result<S, c_int> {
+    let progress = opts
+        .progress
+        .clone()
+        .map(|v| ProgressTracker::new(v, source.bytes().len() as u64));
+    let result = lookup_btf_tracked(source, opts, progress.as_ref(), new_sink);
+    // 取消后读取失败的原因各不相同，统一报告为取消
+    if progress.as_ref().is_some_and(ProgressTracker::is_cancelled) {
+        report!("{}", Error::Cancelled);
+        return Err(-ECANCELED);
+    }
+    result
+}
+
+/// The lookup of [`lookup_btf`], counting the decompressed bytes towards `progress`
+fn lookup_btf_tracked<S: BtfSink>(
+    source: TarSource,
+    opts: &Options,
+    progress: Option<&ProgressTracker>,
+    mut new_sink: impl FnMut() -> Result<S, c_int>,
+) -> Result<S, c_int> {
+    let tar_bytes = source.bytes();
+    let policy = opts.policy;
+    // 捕获当前系统信息，生成与 min_core_btf.tar.o 中 btf 存档路径相同的路径字符串
+    // 最终效果：./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf
+    // 使用 `/` 拼接，而不是平台相关的分隔符，与 tar 条目的路径保持一致
+    // 归档可能以版本号或代号（如 ubuntu/focal）命名发行版目录，两者都尝试，越靠前越优先
+    // 归档目录前缀可配置（默认 btfhub-archive），规范化（去掉 `./`、开头的 `/` 和重复的 `/`）后再拼接，空前缀时条目直接以发行版目录开头
+    let prefix = normalize_entry_path(&opts.archive_prefix);
+    let info = match opts.system_info() {
+        Ok(v) => v,
+        Err(e) => {
+            report!("Failed to generate running kernel btf path: {:?}", e);
+            return Err(detect_errno(&e));
+        }
+    };
+    // 滚动发行版（或设置了 match_any_distro 时）按内核版本查找：先是 generic 目录，再是任意发行版的目录
+    // BestEffort 时，Debian 的 backports 内核退而使用其来源版本中的内核，企业版 Linux 系列最后在该系列的所有目录中查找
+    // 候选路径与回退的选择都由 BtfMatcher 决定，与 TarballBtfArchive 的查找一致
+    let matcher = BtfMatcher::new(&info, &prefix, policy).with_any_distro(opts.any_distro);
+    let any_distro = matcher.any_distro();
+    let el_fallback = matcher.el_fallback();
+    let local_btf_paths = matcher.candidates();
+    debug!(
+        "Looking for {}",
+        local_btf_paths
+            .iter()
+            .map(|v| v.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if tar_bytes.is_empty() {
+        report!("No btf archive is linked into the executable");
+        return Err(-ENOENT);
+    }
+    // 同一份归档中已确认不存在的 btf，直接返回，避免重复解压和扫描整个归档
+    // 记录的只是精确匹配的结果，精确匹配失败时仍可能找到最接近的版本
+    let fingerprint = source.fingerprint();
+    let exact = matcher.is_exact();
+    if exact && !any_distro && memo::is_known_miss(fingerprint, local_btf_paths) {
+        report!(
+            "Failed to find the btf archive matching the running kernel, {} (cached)",
+            local_btf_paths[0].display()
+        );
+        return Err(-ENOENT);
+    }
+    // 归档开头的清单列出了所有 btf，其中没有可用的候选时直接返回，无需解压和扫描整个归档
+    // 按内核版本跨发行版查找时候选目录不固定，仍然扫描
+    let listing = BtfhubArchive::new(tar_bytes)
+        .with_prefix(&prefix)
+        .with_max_decompressed_size(opts.max_decompressed_size)
+        .listing();
+    if let Some(listing) = listing.as_ref().filter(|_| !any_distro && !el_fallback) {
+        // 严格模式下，清单表明归档不是为该系统打包的，给出支持的系统，而不是笼统的“找不到”
+        if opts.strict_coverage
+            || std::env::var_os(STRICT_COVERAGE_ENV).is_some_and(|v| !v.is_empty())
+        {
+            if let Err(e) = listing.check_coverage(&info, &prefix) {
+                report!("{}", e);
+                return Err(archive_errno(&e));
+            }
+        }
+        if !matcher.may_match(listing.entries.iter().map(|v| v.path.as_path())) {
+            report!(
+                "Failed to find the btf archive matching the running kernel, {} (not in its {})",
+                local_btf_paths[0].display(),
+                LISTING_ENTRY_NAME
+            );
+            return Err(-ENOENT);
+        }
+    }
+    // 随机访问布局（未压缩的 tar，以 INDEX 开头，btf 各自压缩）按索引直接定位，只解压匹配的条目
+    if let TarSource::Bytes(tar_bytes) = source {
+        if let Some(index) = is_random_access(tar_bytes)
+            .then(|| ArchiveIndex::read(tar_bytes))
+            .flatten()
+        {
+            // 架构标记不符时不使用根目录下的 btf
+            let candidates = match index_arch_marker(tar_bytes, &index) {
+                Some(v) if !arch_marker_allows(v, &info) => matcher.btfhub_candidates(),
+                _ => local_btf_paths,
+            };
+            if let Some((rank, sink)) =
+                find_btf_random_access(tar_bytes, &index, candidates, opts, &mut new_sink)?
+            {
+                note_candidate_match(&local_btf_paths[rank], &matcher);
+                record_match(&local_btf_paths[rank], &info);
+                return Ok(sink);
+            }
+        }
+    }
+    let mut state = ScanState {
+        other_distros: matcher.wants_other_distros().then(Vec::new),
+        listing,
+        ..Default::default()
+    };
+    // 同一发行版、同一架构下的条目，用于找不到精确匹配时选取最接近的内核版本
+    let mut siblings = vec![];
+    let found = match source {
+        TarSource::Bytes(_) => {
+            // 直接在解码流上逐个读取 tar 条目，不在内存中保存整个解压后的归档
+            // 根据开头的魔数判断归档的压缩格式（gzip、xz、zstd 或未压缩的 tar）
+            let tar_reader = match tracked_tar_reader(tar_bytes, opts, progress) {
+                Ok(v) => v,
+                Err(e) => {
+                    report!("{}", e);
+                    return Err(archive_errno(&e));
+                }
+            };
+            // 创建一个新的存档，并将底层对象作为读取器；拼接而成的归档中间的结束标记会被跳过
+            let mut tar = tar_archive(tar_reader);
+            find_btf_in_tar(
+                &mut tar,
+                &matcher,
+                &mut state,
+                (!exact).then_some(&mut siblings),
+                opts,
+                &mut new_sink,
+            )?
+        }
+        // 已解析的归档按索引直接定位候选条目，无需再次解压
+        TarSource::Parsed(archive) => find_btf_indexed(
+            &archive.parsed,
+            &matcher,
+            &mut state,
+            (!exact).then_some(&mut siblings),
+            opts,
+            &mut new_sink,
+        )?,
+    };
+    // 流式扫描时架构标记可能出现在匹配的条目之后，扫描结束后才能确定是否可用
+    let refuses_flat = state
+        .arch_marker
+        .as_deref()
+        .is_some_and(|v| !arch_marker_allows(v, &info));
+    let found = match found {
+        Some(_) if refuses_flat && state.matched_rank.is_some_and(|v| matcher.is_flat(v)) => {
+            state.matched_rank = None;
+            state.refused_flat = true;
+            None
+        }
+        v => v,
+    };
+    if refuses_flat {
+        siblings.retain(|v| parse_flat_btf_path(v).is_none());
+    }
+    if let Some(rank) = state.matched_rank {
+        note_candidate_match(&local_btf_paths[rank], &matcher);
+        record_match(&local_btf_paths[rank], &info);
+    }
+    // 最接近的版本、其他发行版或企业版 Linux 系列中的同一内核，由匹配策略决定
+    let found = match found {
+        None => matcher
+            .fallback(
+                &siblings,
+                state.other_distros.as_deref().unwrap_or_default(),
+            )
+            .map(|v| {
+                note!("{}", v);
+                note_backport_match(&v.path, &matcher);
+                record_match(&v.path, &info);
+                Found::Link(v.path, v.encoding)
+            }),
+        v => v,
+    };
+
+    match found {
+        Some(Found::Contents(v)) => Ok(v),
+        Some(Found::Link(target, encoding)) => resolve_link(
+            source,
+            target,
+            encoding,
+            state.manifest.as_ref(),
+            opts,
+            progress,
+            &mut new_sink,
+        ),
+        None if state.seen_foreign_endian => {
+            report!("The only matching btf is of the other byte order than the host's");
+            Err(-ENOEXEC)
+        }
+        None if state.refused_flat => {
+            report!(
+                "The btfs at the root of the archive are for the architecture {}, not {}",
+                String::from_utf8_lossy(state.arch_marker.as_deref().unwrap_or_default()).trim(),
+                info.arch
+            );
+            Err(-ENOENT)
+        }
+        None if state.seen_non_regular => {
+            report!("The entry matching the running kernel is not a regular file");
+            Err(-ENOENT)
+        }
+        None if !state.seen_btfhub_entry => {
+            report!("{}", Error::NotBtfhubArchive);
+            Err(-EMEDIUMTYPE)
+        }
+        None => {
+            report!(
+                "Failed to find the btf archive matching the running kernel, {}",
+                local_btf_paths[0].display()
+            );
+            report_backport(&info.distro_id, &info.kernel_release, policy);
+            if exact && !any_distro {
+                memo::record_miss(fingerprint, local_btf_paths);
+            }
+            Err(-ENOENT)
+        }
+    }
+}
+
+/// The decompressed tar of `tar_bytes`, limited to `opts.max_decompressed_size` and counted towards `progress`
+fn tracked_tar_reader<'a>(
+    tar_bytes: &'a [u8],
+    opts: &Options,
+    progress: Option<&'a ProgressTracker>,
+) -> bpf_compatible_rs::Result<Box<dyn Read + 'a>> {
+    let reader = tar_reader_with_limit(tar_bytes, opts.max_decompressed_size)?;
+    Ok(match progress {
+        Some(v) => Box::new(v.reader(reader)),
+        None => reader,
+    })
+}
+
+/// What a scan of the archive came across, besides the matching entry
+#[derive(Default)]
+struct ScanState {
+    /// 是否存在任何一个位于归档目录（默认 btfhub-archive）下的条目，用于区分“归档文件不对”和“内核未被覆盖”
+    seen_btfhub_entry: bool,
+    /// 是否跳过了字节序与本机不符的匹配条目，找不到其他 btf 时据此返回专门的错误
+    seen_foreign_endian: bool,
+    /// 是否跳过了路径匹配但并非普通文件（目录、设备文件等）的条目
+    seen_non_regular: bool,
+    /// The `SHA256SUMS` entry, if it came before the matching entry
+    manifest: Option<Manifest>,
+    /// The listing at the start of the archive, which the matching entry is checked against
+    listing: Option<ArchiveListing>,
+    /// Entries of the release and architecture of a candidate under any distro, collected
+    /// if set, see [`BtfMatcher::is_other_distro`]
+    other_distros: Option<Vec<PathBuf>>,
+    /// Rank of the candidate the matching entry was found at
+    matched_rank: Option<usize>,
+    /// Contents of the architecture marker at the root of a flat archive, see `bpf_compatible_rs::flat`
+    arch_marker: Option<Vec<u8>>,
+    /// 根目录下的 btf 匹配，但架构标记表明其属于其他架构
+    refused_flat: bool,
+}
+
+/// The best matching entry of the archive
+enum Found<S> {
+    /// A regular entry, whose contents were copied to the sink
+    Contents(S),
+    /// A hardlink or symlink, pointing to this path of the archive
+    Link(PathBuf, BtfEncoding),
+}
+
+/// Look up the best match among the candidates of `matcher` in `tar`, copying it to a sink
+///
+/// Entries are read one by one from the stream, so only the matching entry's contents
+/// are held in memory, to be validated before they are copied. A matching link is only recorded, since its
+/// target may have been streamed past already, see [`resolve_link`].
+///
+/// Entry paths are compared component-wise, so `./btfhub-archive/x`, `btfhub-archive//x` and
+/// `/btfhub-archive/x` all match `btfhub-archive/x`, see [`normalize_entry_path`], and
+/// ranked by `matcher`. If `siblings` is given, the paths of entries under `<distro>/*/<arch>`
+/// of the candidates are collected into it, see [`BtfMatcher::is_sibling`].
+fn find_btf_in_tar<R: Read, S: BtfSink>(
+    tar: &mut Archive<R>,
+    matcher: &BtfMatcher,
+    state: &mut ScanState,
+    mut siblings: Option<&mut Vec<PathBuf>>,
+    opts: &Options,
+    new_sink: &mut impl FnMut() -> Result<S, c_int>,
+) -> Result<Option<Found<S>>, c_int> {
+    // 针对 Archive 存档的条目，构建一个迭代器
+    // 迭代器中的每一个条目必须按照顺序处理，否则读取的每个条目的内容可能被破坏
+    let entries = tar_entries(tar).map_err(|e| {
+        report!("Failed to read entries in the tar: {}", e);
+        -EINVAL
+    })?;
+    // 命中的候选路径的序号，以及保存其内容的 sink（或链接的目标）
+    let mut best_match: Option<(usize, Found<S>)> = None;
+    // 如果归档的第一个条目是 INDEX，记录索引中最优候选的位置，扫描到该位置即可停止，无需读完整个归档
+    let mut indexed = None;
+    for (i, entry) in entries.enumerate() {
+        let mut entry = entry.map_err(|e| {
+            report!("Failed to read entry: {}", e);
+            stream_errno(&e)
+        })?;
+        if i == 0 {
+            if let Some(index) = ArchiveIndex::from_entry(&mut entry) {
+                // 架构标记可能在根目录下的 btf 之后，不能在读到标记之前停止
+                let has_arch_marker = index
+                    .paths()
+                    .any(|v| normalize_entry_path(v) == Path::new(ARCH_MARKER_NAME));
+                indexed = matcher
+                    .candidates()
+                    .iter()
+                    .enumerate()
+                    .find_map(|(rank, v)| {
+                        if has_arch_marker && parse_flat_btf_path(v).is_some() {
+                            return None;
+                        }
+                        let path = index
+                            .paths()
+                            .find(|path| normalize_entry_path(path) == *v)?;
+                        Some((rank, index.lookup(path)?))
+                    });
+                continue;
+            }
+        }
+        // GNU 长文件名和 PAX 扩展头只是描述下一个条目的元数据，并不是文件
+        let entry_type = entry.header().entry_type();
+        if is_metadata_entry(entry_type) {
+            continue;
+        }
+        // 只有普通文件和链接可以提取，目录、设备文件等条目即使路径匹配，内容也不是 btf
+        let extractable = is_file_entry(entry_type) || is_link_entry(entry_type);
+        let path_and_rank = {
+            // path of a entry looks like `./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf`
+            // 取决于打包时 tar 的调用方式，也可能没有 `./`，或是 `/btfhub-archive/...`
+            // entry.path() 返回条目的完整路径，超过 100 字节的路径保存在 GNU longname（@LongLink）或 PAX 扩展头中，
+            // 而 entry.header().path() 只能得到头部中被截断的名字。该方法将会转 \ 字符为目录分割符。
+            // PAX 格式的稀疏条目以 GNU.sparse.name 记录的名字为准
+            let path = match sparse::entry_path(&mut entry) {
+                Ok(v) => normalize_entry_path(&v),
+                Err(e) => {
+                    // 路径按字节比较，不要求是 UTF-8；无法解读的条目只有命中候选路径时才算失败
+                    let raw =
+                        normalize_entry_path(Path::new(OsStr::from_bytes(&entry.path_bytes())));
+                    if matcher.rank(&raw).is_some() {
+                        report!("Failed to read path name of {}: {}", raw.display(), e);
+                        return Err(-EILSEQ);
+                    }
+                    note!("Skipped the entry {}: {}", raw.display(), e);
+                    continue;
+                }
+            };
+            if path.starts_with(matcher.prefix()) || parse_flat_btf_path(&path).is_some() {
+                state.seen_btfhub_entry = true;
+            }
+            if path == Path::new(ARCH_MARKER_NAME) && entry.header().entry_type().is_file() {
+                let mut contents = vec![];
+                entry.read_to_end(&mut contents).map_err(|e| {
+                    report!("Failed to read the architecture marker: {}", e);
+                    stream_errno(&e)
+                })?;
+                state.arch_marker = Some(contents);
+                continue;
+            }
+            // 摘要清单需出现在 btf 之前（归档开头，或紧跟 INDEX 之后），流式读取时才能在写出前校验
+            if path == Path::new(MANIFEST_ENTRY_NAME) && entry.header().entry_type().is_file() {
+                let mut contents = vec![];
+                entry.read_to_end(&mut contents).map_err(|e| {
+                    report!("Failed to read the manifest: {}", e);
+                    stream_errno(&e)
+                })?;
+                state.manifest = Some(Manifest::parse(&contents));
+                continue;
+            }
+            // 根据当前系统生成的 BTF 存档路径信息 同 btfhub-archive 存档的 btf 文件地址比对，检索出使用与当前系统的 btf 文件
+            if let Some(siblings) = siblings.as_deref_mut().filter(|_| extractable) {
+                if matcher.is_sibling(&path) {
+                    siblings.push(path.to_path_buf());
+                }
+            }
+            if let Some(other_distros) = state.other_distros.as_mut().filter(|_| extractable) {
+                if matcher.is_other_distro(&path) {
+                    other_distros.push(path.to_path_buf());
+                }
+            }
+            matcher.rank(&path).map(|v| (path, v))
+        };
+        let Some((path, (rank, encoding))) = path_and_rank else {
+            continue;
+        };
+        if !extractable {
+            debug!(
+                "Skipped {}, a {:?} entry rather than a regular file",
+                path.display(),
+                entry_type
+            );
+            state.seen_non_regular = true;
+            continue;
+        }
+        // 同一路径出现多次时后出现的条目生效，与 tar 解包的行为一致
+        if best_match.as_ref().is_some_and(|(best, _)| *best < rank) {
+            continue;
+        }
+        // 链接条目本身没有内容，只记录其目标，等扫描结束后再解析
+        if let Some(target) = link_target(&entry, &path)? {
+            best_match = Some((rank, Found::Link(target, encoding)));
+            continue;
+        }
+        // 字节序不符的条目跳过，归档中之后的正确条目仍可胜出
+        let verifier =
+            Verifier::new(state.manifest.as_ref(), opts).with_listing(state.listing.as_ref());
+        let decoded = {
+            // 稀疏条目的内容需重组，不能原样读取
+            let mut contents = sparse::entry_contents(&mut entry).map_err(|e| {
+                report!("Failed to read {}: {}", path.display(), e);
+                stream_errno(&e)
+            })?;
+            decode_btf(&mut contents, &path, encoding, verifier)?
+        };
+        let Some(btf) = decoded else {
+            state.seen_foreign_endian = true;
+            continue;
+        };
+        let mut sink = match best_match.take() {
+            Some((_, Found::Contents(v))) => v,
+            _ => new_sink()?,
+        };
+        sink.overwrite_from(&mut &btf[..])?;
+        best_match = Some((rank, Found::Contents(sink)));
+        if indexed == Some((rank, (entry.raw_file_position(), entry.size()))) {
+            break;
+        }
+    }
+    state.matched_rank = best_match.as_ref().map(|(rank, _)| *rank);
+    Ok(best_match.map(|(_, v)| v))
+}
+
+/// Contents of the architecture marker of a flat archive in the random-access layout, if it has one
+fn index_arch_marker<'a>(tar_bytes: &'a [u8], index: &ArchiveIndex) -> Option<&'a [u8]> {
+    let path = index
+        .paths()
+        .find(|v| normalize_entry_path(v) == Path::new(ARCH_MARKER_NAME))?;
+    index.locate(tar_bytes, path)
+}
+
+/// Look up the candidates in the `INDEX` of an archive in the random-access layout, see `bpf_compatible_rs::layout`
+///
+/// Only the matching entry is read and decompressed, best candidate first, and returned
+/// with the rank of its candidate. Returns `None`
+/// if the index holds no usable candidate, e.g. because the btf is a link, which isn't
+/// indexed, or the index is stale; the archive should be scanned as usual then.
+fn find_btf_random_access<S: BtfSink>(
+    tar_bytes: &[u8],
+    index: &ArchiveIndex,
+    candidates: &[PathBuf],
+    opts: &Options,
+    new_sink: &mut impl FnMut() -> Result<S, c_int>,
+) -> Result<Option<(usize, S)>, c_int> {
+    let paths = index
+        .paths()
+        .map(|v| (normalize_entry_path(v), v))
+        .collect::<HashMap<_, _>>();
+    let manifest = paths
+        .get(Path::new(MANIFEST_ENTRY_NAME))
+        .and_then(|v| Some((index.lookup(v)?.0, index.locate(tar_bytes, v)?)))
+        .map(|(offset, contents)| (offset, Manifest::parse(contents)));
+    for (rank, candidate) in candidates.iter().enumerate() {
+        // 同一候选的多种存储方式都存在时，与顺序扫描一致，归档中靠后的生效
+        let found = encoded_paths(candidate)
+            .filter_map(|(path, encoding)| {
+                let raw_path = *paths.get(&path)?;
+                let (offset, _) = index.lookup(raw_path)?;
+                Some((path, raw_path, offset, encoding))
+            })
+            .max_by_key(|(_, _, offset, _)| *offset);
+        let Some((path, raw_path, offset, encoding)) = found else {
+            continue;
+        };
+        // 索引与 tar 头部不符（索引已过期）时交给顺序扫描
+        let Some(contents) = index.locate(tar_bytes, raw_path) else {
+            return Ok(None);
+        };
+        let verifier = Verifier::new(
+            manifest
+                .as_ref()
+                .filter(|(v, _)| *v < offset)
+                .map(|(_, v)| v),
+            opts,
+        );
+        let Some(btf) = decode_btf(&mut &contents[..], &path, encoding, verifier)? else {
+            continue;
+        };
+        let mut sink = new_sink()?;
+        sink.overwrite_from(&mut &btf[..])?;
+        return Ok(Some((rank, sink)));
+    }
+    Ok(None)
+}
+
+/// Same as [`find_btf_in_tar`], but looks the candidates up in the index of a parsed archive
+///
+/// Only the entries of the candidates are read, best first. As when streaming, the
+/// manifest is only honored for entries it comes before, and the last of the entries
+/// sharing a path wins, as does the last of the encodings of a candidate.
+fn find_btf_indexed<S: BtfSink>(
+    archive: &ParsedArchive,
+    matcher: &BtfMatcher,
+    state: &mut ScanState,
+    siblings: Option<&mut Vec<PathBuf>>,
+    opts: &Options,
+    new_sink: &mut impl FnMut() -> Result<S, c_int>,
+) -> Result<Option<Found<S>>, c_int> {
+    let entries = archive.entries();
+    state.seen_btfhub_entry = entries.iter().any(|v| {
+        let path = normalize_entry_path(&v.path);
+        path.starts_with(matcher.prefix()) || parse_flat_btf_path(&path).is_some()
+    });
+    state.arch_marker = archive
+        .entry(ARCH_MARKER_NAME)
+        .filter(|v| v.link_target.is_none())
+        .and_then(|_| archive.extract(ARCH_MARKER_NAME).ok())
+        .map(<[u8]>::to_vec);
+    let manifest_offset = archive
+        .entry(MANIFEST_ENTRY_NAME)
+        .filter(|v| v.link_target.is_none())
+        .map(|v| v.offset);
+    state.manifest = manifest_offset
+        .and_then(|_| archive.extract(MANIFEST_ENTRY_NAME).ok())
+        .map(Manifest::parse);
+    if let Some(other_distros) = state.other_distros.as_mut() {
+        other_distros.extend(
+            entries
+                .iter()
+                .map(|v| normalize_entry_path(&v.path))
+                .filter(|path| matcher.is_other_distro(path)),
+        );
+    }
+    if let Some(siblings) = siblings {
+        siblings.extend(
+            entries
+                .iter()
+                .map(|v| normalize_entry_path(&v.path))
+                .filter(|path| matcher.is_sibling(path)),
+        );
+    }
+    for (rank, candidate) in matcher.candidates().iter().enumerate() {
+        let found = encoded_paths(candidate)
+            .filter_map(|(path, encoding)| Some((archive.entry(path)?, encoding)))
+            .max_by_key(|(entry, _)| entry.offset);
+        let Some((entry, encoding)) = found else {
+            continue;
+        };
+        if let Some(target) = &entry.link_target {
+            state.matched_rank = Some(rank);
+            return Ok(Some(Found::Link(target.clone(), encoding)));
+        }
+        let path = normalize_entry_path(&entry.path);
+        let verifier = Verifier::new(
+            state
+                .manifest
+                .as_ref()
+                .filter(|_| manifest_offset.is_some_and(|v| v < entry.offset)),
+            opts,
+        )
+        .with_listing(state.listing.as_ref());
+        let contents = indexed_contents(archive, &path)?;
+        // 字节序不符的条目跳过，排在后面的候选仍可胜出
+        let Some(btf) = decode_btf(&mut &contents[..], &path, encoding, verifier)? else {
+            state.seen_foreign_endian = true;
+            continue;
+        };
+        let mut sink = new_sink()?;
+        sink.overwrite_from(&mut &btf[..])?;
+        state.matched_rank = Some(rank);
+        return Ok(Some(Found::Contents(sink)));
+    }
+    Ok(None)
+}
+
+/// Contents of the regular entry at `path` of a parsed archive
+fn indexed_contents<'a>(archive: &'a ParsedArchive, path: &Path) -> Result<&'a [u8], c_int> {
+    archive.extract(path).map_err(|e| {
+        report!("Failed to read {}: {}", path.display(), e);
+        archive_errno(&e)
+    })
+}
+
+/// Note that the btf of `candidate` was taken from the directory of another Ubuntu release, or of the
+/// release a Debian backports kernel is built from, see [`BtfMatcher::is_hwe`]
+fn note_candidate_match(candidate: &Path, matcher: &BtfMatcher) {
+    if matcher.is_hwe(candidate) {
+        note!(
+            "No btf for the HWE kernel under its own release, using {} of the release it comes from",
+            candidate.display()
+        );
+    }
+    note_backport_match(candidate, matcher);
+}
+
+/// Record `entry` as the btf the lookup for `info` settled on, see `ensure_core_btf_with_tar_binary_match`
+fn record_match(entry: &Path, info: &SystemInfo) {
+    let release = btf_release(entry).unwrap_or_default();
+    let local_version_stripped = is_stripped_release_of(release, info);
+    if local_version_stripped {
+        note!(
+            "No btf of {} in the archive, using {} of the release without its local version",
+            info.kernel_release,
+            entry.display()
+        );
+    }
+    match_info::record(MatchInfo {
+        entry_path: Some(entry.to_path_buf()),
+        source: BtfSource::Archive,
+        exact: is_release_of(release, info),
+        kernel_release: release.to_string(),
+        local_version_stripped,
+        candidate: candidate_of(entry, info),
+        native_btf: NativeBtfStatus::Unknown,
+        memoized: false,
+    });
+}
+
+/// Note that the btf of the backports kernel was taken from the release it's built from, see [`BtfMatcher::is_backport`]
+fn note_backport_match(path: &Path, matcher: &BtfMatcher) {
+    if matcher.is_backport(path) {
+        note!(
+            "{} is a backports kernel, using {} of the release it's built from",
+            matcher.info().kernel_release,
+            path.display()
+        );
+    }
+}
+
+/// Explain why no btf was found for `kernel_release` if it's a Debian backports kernel
+fn report_backport(distro_id: &str, kernel_release: &str, policy: MatchPolicy) {
+    if distro_id != "debian" {
+        return;
+    }
+    let Some(backport) = debian_backport(kernel_release) else {
+        return;
+    };
+    match (backport.version, policy) {
+        (Some(version), MatchPolicy::BestEffort) => report!(
+            "{} is a backports kernel, and debian {} has no btf close to {} either",
+            kernel_release,
+            version,
+            backport.release
+        ),
+        (Some(version), _) => report!(
+            "{} is a backports kernel, which btfhub has no btfs of; BPF_COMPAT_MATCH_BEST_EFFORT falls back to {} of debian {}",
+            kernel_release,
+            backport.release,
+            version
+        ),
+        (None, _) => report!(
+            "{} is a backports kernel of an unknown series, which btfhub has no btfs of",
+            kernel_release
+        ),
+    }
+}
+
+/// Path within the archive a hardlink or symlink entry at `path` points to, `None` for other entries
+///
+/// Hardlink targets are relative to the root of the archive, relative symlink targets to
+/// the directory holding the link. Absolute symlinks are taken relative to the root too.
+fn link_target<R: Read>(entry: &Entry<R>, path: &Path) -> Result<Option<PathBuf>, c_int> {
+    let entry_type = entry.header().entry_type();
+    if !is_link_entry(entry_type) {
+        return Ok(None);
+    }
+    let target = match entry.link_name() {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            report!("The link {} has no target", path.display());
+            return Err(-ENOENT);
+        }
+        Err(e) => {
+            report!("Failed to read the target of {}: {}", path.display(), e);
+            return Err(-EILSEQ);
+        }
+    };
+    let target = if entry_type.is_symlink() && target.is_relative() {
+        path.parent().unwrap_or(Path::new("")).join(target)
+    } else {
+        target.into_owned()
+    };
+    Ok(Some(normalize_entry_path(&target)))
+}
+
+/// Copy the contents of the entry a matching link points to, following chains of links
+///
+/// The target may come before or after the link, so the archive is read again from the
+/// start, unless it was parsed, see [`resolve_link_indexed`]
+fn resolve_link<S: BtfSink>(
+    source: TarSource,
+    mut target: PathBuf,
+    encoding: BtfEncoding,
+    manifest: Option<&Manifest>,
+    opts: &Options,
+    progress: Option<&ProgressTracker>,
+    new_sink: &mut impl FnMut() -> Result<S, c_int>,
+) -> Result<S, c_int> {
+    let tar_bytes = match source {
+        TarSource::Bytes(v) => v,
+        TarSource::Parsed(archive) => {
+            return resolve_link_indexed(
+                &archive.parsed,
+                target,
+                encoding,
+                manifest,
+                opts,
+                new_sink,
+            )
+        }
+    };
+    // 限制跟随链接的次数，避免链接成环时无限循环
+    for _ in 0..MAX_LINK_DEPTH {
+        debug!("Following the link to {}", target.display());
+        let reader = tracked_tar_reader(tar_bytes, opts, progress).map_err(|e| {
+            report!("{}", e);
+            -EINVAL
+        })?;
+        let mut tar = tar_archive(reader);
+        let entries = tar_entries(&mut tar).map_err(|e| {
+            report!("Failed to read entries in the tar: {}", e);
+            -EINVAL
+        })?;
+        let mut next = None;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| {
+                report!("Failed to read entry: {}", e);
+                stream_errno(&e)
+            })?;
+            if is_metadata_entry(entry.header().entry_type()) {
+                continue;
+            }
+            let path = match sparse::entry_path(&mut entry) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if normalize_entry_path(&path) != target {
+                continue;
+            }
+            // 与查找时一致，同一路径出现多次时以最后一个条目为准
+            next = match link_target(&entry, &path)? {
+                Some(v) => Some(Found::Link(v, encoding)),
+                None if is_file_entry(entry.header().entry_type()) => {
+                    let verifier = Verifier::new(manifest, opts);
+                    let mut contents = sparse::entry_contents(&mut entry).map_err(|e| {
+                        report!("Failed to read {}: {}", path.display(), e);
+                        stream_errno(&e)
+                    })?;
+                    let Some(btf) = decode_btf(&mut contents, &path, encoding, verifier)? else {
+                        return Err(-ENOEXEC);
+                    };
+                    // 目标重复出现时覆盖之前写入的 sink，不再创建新的临时文件
+                    let mut sink = match next.take() {
+                        Some(Found::Contents(v)) => v,
+                        _ => new_sink()?,
+                    };
+                    sink.overwrite_from(&mut &btf[..])?;
+                    Some(Found::Contents(sink))
+                }
+                None => None,
+            };
+        }
+        match next {
+            Some(Found::Contents(sink)) => return Ok(sink),
+            Some(Found::Link(v, _)) => target = v,
+            None => {
+                report!(
+                    "The btf is a link to {}, which is not in the archive",
+                    target.display()
+                );
+                return Err(-ENOENT);
+            }
+        }
+    }
+    report!("Too many levels of links resolving the btf");
+    Err(-ELOOP)
+}
+
+/// Same as [`resolve_link`], following the links through the index of a parsed archive
+fn resolve_link_indexed<S: BtfSink>(
+    archive: &ParsedArchive,
+    mut target: PathBuf,
+    encoding: BtfEncoding,
+    manifest: Option<&Manifest>,
+    opts: &Options,
+    new_sink: &mut impl FnMut() -> Result<S, c_int>,
+) -> Result<S, c_int> {
+    for _ in 0..MAX_LINK_DEPTH {
+        debug!("Following the link to {}", target.display());
+        let Some(entry) = archive.entry(&target) else {
+            report!(
+                "The btf is a link to {}, which is not in the archive",
+                target.display()
+            );
+            return Err(-ENOENT);
+        };
+        if let Some(next) = &entry.link_target {
+            target = next.clone();
+            continue;
+        }
+        let verifier = Verifier::new(manifest, opts);
+        let contents = indexed_contents(archive, &target)?;
+        let Some(btf) = decode_btf(&mut &contents[..], &entry.path, encoding, verifier)? else {
+            return Err(-ENOEXEC);
+        };
+        let mut sink = new_sink()?;
+        sink.overwrite_from(&mut &btf[..])?;
+        return Ok(sink);
+    }
+    report!("Too many levels of links resolving the btf");
+    Err(-ELOOP)
+}
+
+/// Whether an entry of this type only carries metadata, like `@LongLink` or `pax_global_header`
+fn is_metadata_entry(entry_type: EntryType) -> bool {
+    matches!(
+        entry_type,
+        EntryType::GNULongName
+            | EntryType::GNULongLink
+            | EntryType::XHeader
+            | EntryType::XGlobalHeader
+    )
+}
+
+/// Whether an entry is a hard or symbolic link, whose btf is that of its target
+fn is_link_entry(entry_type: EntryType) -> bool {
+    entry_type.is_hard_link() || entry_type.is_symlink()
+}
+
+/// How the bytes of the matching entry are checked against the manifest
+#[derive(Clone, Copy)]
+struct Verifier<'a> {
+    manifest: Option<&'a Manifest>,
+    /// Fail if the entry can't be verified, because there is no manifest or it isn't listed
+    required: bool,
+    /// Size a compressed entry may decompress to, see `Options::max_decompressed_size`
+    max_size: u64,
+    /// The listing of the archive, only warned about if it disagrees with the entry
+    listing: Option<&'a ArchiveListing>,
+    /// The options the system looked up is detected with, to check the btf is of its
+    /// architecture; `None` if it isn't checked, see `Options::check_arch`
+    system: Option<&'a Options>,
+}
+
+impl<'a> Verifier<'a> {
+    /// Check entries against `manifest`, as strictly as `opts` asks
+    fn new(manifest: Option<&'a Manifest>, opts: &'a Options) -> Self {
+        Self {
+            manifest,
+            required: opts.require_verification,
+            max_size: opts.max_decompressed_size,
+            listing: None,
+            system: opts.check_arch.then_some(opts),
+        }
+    }
+
+    /// Also check entries against the listing of the archive, if it has one
+    fn with_listing(mut self, listing: Option<&'a ArchiveListing>) -> Self {
+        self.listing = listing;
+        self
+    }
+
+    /// Check the bytes of the entry at `path`, as stored in the archive
+    ///
+    /// A digest mismatch fails with `-EBADMSG`; an entry that can't be verified only fails,
+    /// with `-ENOKEY`, if verification is required.
+    fn verify(&self, path: &Path, contents: &[u8]) -> Result<(), c_int> {
+        // 清单只是提示，与实际条目不符时以 tar 为准
+        if self.listing.is_some_and(|v| !v.check(path, contents)) {
+            note!(
+                "{} disagrees with the {} of the archive, which is stale",
+                path.display(),
+                LISTING_ENTRY_NAME
+            );
+        }
+        let Some(manifest) = self.manifest else {
+            if self.required {
+                report!(
+                    "Verification is required, but the archive has no {} before {}",
+                    MANIFEST_ENTRY_NAME,
+                    path.display()
+                );
+                return Err(-ENOKEY);
+            }
+            return Ok(());
+        };
+        match manifest.verify(path, contents) {
+            Ok(()) => Ok(()),
+            Err(e @ Error::NotInManifest(_)) if self.required => {
+                report!("Verification is required: {}", e);
+                Err(-ENOKEY)
+            }
+            Err(Error::NotInManifest(_)) => Ok(()),
+            Err(e) => {
+                report!("{}", e);
+                Err(-EBADMSG)
+            }
+        }
+    }
+}
+
+impl Verifier<'_> {
+    /// Check that the btf of the entry at `path` is of the architecture of its directory, and
+    /// of the one looked up, unless that's turned off
+    ///
+    /// An entry at the root of a flat archive has no architecture directory; an
+    /// architecture that can't be told from the btf isn't compared.
+    fn check_arch(&self, path: &Path, btf: &[u8]) -> Result<(), c_int> {
+        let Some(opts) = self.system else {
+            return Ok(());
+        };
+        let directory = path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+        let arch = opts.system_info().map(|v| v.arch).unwrap_or_default();
+        // 条目放错了架构目录时，CO-RE 重定位的结果在运行时才会出错
+        check_btf_arch(btf, &[directory, &arch]).map_err(|e| {
+            report!(
+                "The entry {} is of another architecture: {}",
+                path.display(),
+                e
+            );
+            archive_errno(&e)
+        })
+    }
+}
+
+/// Decode the btf stored in the entry at `path` with `encoding`
+///
+/// The entry is checked against the manifest by `verifier`, and the btf with
+/// `validate_btf_bytes`, before anything is written, so a corrupt entry fails here instead
+/// of confusing libbpf later on. A btf of the other byte order than the host's gives
+/// `None`, so the caller may look further. A btf of another architecture than the one
+/// of its directory, or the one looked up, fails with `-ENOEXEC`, see `check_arch`.
+fn decode_btf(
+    entry: &mut dyn Read,
+    path: &Path,
+    encoding: BtfEncoding,
+    verifier: Verifier,
+) -> Result<Option<Vec<u8>>, c_int> {
+    let contents = read_entry(entry)?;
+    verifier.verify(path, &contents)?;
+    let btf = match encoding {
+        BtfEncoding::Plain => contents,
+        BtfEncoding::Gzipped => gunzip_btf(&mut &contents[..], verifier.max_size)?,
+        BtfEncoding::Tarball => untar_btf(&mut &contents[..], verifier.max_size)?,
+    };
+    // libbpf 无法识别的内容不应作为成功结果返回
+    match validate_btf_bytes(&btf) {
+        Ok(_) => {
+            verifier.check_arch(path, &btf)?;
+            debug!("Using the btf of entry {}", path.display());
+            Ok(Some(btf))
+        }
+        Err(Error::BtfEndiannessMismatch(endianness)) => {
+            note!(
+                "BTF endianness mismatch: entry {} appears to be {}-endian",
+                path.display(),
+                endianness
+            );
+            Ok(None)
+        }
+        Err(e) => {
+            report!(
+                "The entry {} doesn't hold a valid btf: {}",
+                path.display(),
+                e
+            );
+            Err(-EILSEQ)
+        }
+    }
+}
+
+/// Read the whole contents of an entry
+fn read_entry(reader: &mut dyn Read) -> Result<Vec<u8>, c_int> {
+    let mut btf = vec![];
+    if let Err(e) = reader.read_to_end(&mut btf) {
+        report!("Failed to read the btf: {}", e);
+        return Err(stream_errno(&e));
+    }
+    Ok(btf)
+}
+
+/// Decompress an individually gzipped btf, of at most `max_size` bytes
+fn gunzip_btf(reader: &mut dyn Read, max_size: u64) -> Result<Vec<u8>, c_int> {
+    let mut btf = vec![];
+    if let Err(e) = LimitedReader::new(GzDecoder::new(reader), max_size).read_to_end(&mut btf) {
+        report!("Failed to decompress the gzipped btf: {}", e);
+        return Err(stream_errno(&e));
+    }
+    Ok(btf)
+}
+
+/// Extract the single `.btf` member of a per-kernel tarball
+///
+/// Only this one level of nesting is looked into; the member is taken as is. The tarball
+/// may decompress to at most `max_size` bytes.
+fn untar_btf(reader: &mut dyn Read, max_size: u64) -> Result<Vec<u8>, c_int> {
+    let mut tarball = vec![];
+    if let Err(e) = reader.read_to_end(&mut tarball) {
+        report!("Failed to read the per-kernel tarball: {}", e);
+        return Err(stream_errno(&e));
+    }
+    let inner_reader = match tar_reader_with_limit(&tarball, max_size) {
+        Ok(v) => v,
+        Err(e) => {
+            report!("Failed to open the per-kernel tarball: {}", e);
+            return Err(match e {
+                Error::UnsupportedCompression(_) | Error::StrategyUnavailable(..) => -ENOTSUP,
+                _ => -EILSEQ,
+            });
+        }
+    };
+    // 内层归档损坏（解压或解析失败）时返回 -EILSEQ，超出解压大小上限返回 -EFBIG，其余返回 -EIO
+    let corrupt = |e: std::io::Error| {
+        report!("The per-kernel tarball is corrupt: {}", e);
+        match stream_errno(&e) {
+            v if v == -EINVAL => -EILSEQ,
+            v => v,
+        }
+    };
+    let mut inner = Archive::new(inner_reader);
+    let mut btf = None;
+    for entry in tar_entries(&mut inner).map_err(corrupt)? {
+        let mut entry = entry.map_err(corrupt)?;
+        let is_btf = is_file_entry(entry.header().entry_type())
+            && sparse::entry_path(&mut entry)
+                .map_err(corrupt)?
+                .extension()
+                .is_some_and(|v| v == "btf");
+        if !is_btf {
+            continue;
+        }
+        if btf.is_some() {
+            report!("The per-kernel tarball holds more than one btf");
+            return Err(-EILSEQ);
+        }
+        btf = Some(sparse::read_entry(&mut entry).map_err(corrupt)?);
+    }
+    btf.ok_or_else(|| {
+        report!("The per-kernel tarball holds no btf");
+        -EILSEQ
+    })
+}
+
+/// Errno for an error of `bpf_compatible_rs`
+///
+/// Every variant is matched, so the errno a C caller sees for a given failure stays the
+/// same, and a new variant has to be given one here.
+pub(crate) fn archive_errno(e: &Error) -> c_int {
+    let os_errno = |e: &std::io::Error| -e.raw_os_error().unwrap_or(EIO);
+    match e {
+        Error::OsReleaseError(e) => -e.raw_os_error().unwrap_or(ENOENT),
+        Error::OsReleaseMissing(_)
+        | Error::MissingOsReleaseField(_)
+        | Error::DistroNotDetected(_) => -ENOENT,
+        Error::UnameError(e)
+        | Error::Io(e)
+        | Error::TempDirError(e)
+        | Error::TarUnpackError(e)
+        | Error::FileReadError(_, e)
+        | Error::FileWriteError(_, e)
+        | Error::BpftoolUnavailable(_, e)
+        | Error::PaholeUnavailable(_, e) => os_errno(e),
+        Error::TarReadError(e) | Error::Decompress(e) => stream_errno(e),
+        Error::InvalidBtf(_) | Error::BtfEndiannessMismatch(_) => -EILSEQ,
+        // 与参数错误或归档损坏区分开，调用者可提示换用正确的归档
+        Error::NotBtfhubArchive => -EMEDIUMTYPE,
+        Error::UnknownArchiveFormat(_)
+        | Error::InvalidGzipHeader
+        | Error::UnsupportedTarget(_)
+        | Error::InvalidObject(_)
+        | Error::DuplicateEntry(_)
+        | Error::InvalidEntryName(_)
+        | Error::InvalidTempfileTemplate(_)
+        | Error::UnsafePath(_) => -EINVAL,
+        // 格式可以识别但编译时未启用对应的解压支持，与数据损坏区分开
+        Error::UnsupportedCompression(_)
+        | Error::StrategyUnavailable(..)
+        | Error::UnsupportedArch(_) => -ENOTSUP,
+        // 非 Linux 系统上没有需要查找的 btf
+        Error::UnsupportedPlatform => -ENOTSUP,
+        Error::EntryNotFound { .. }
+        | Error::NoNativeBtf
+        | Error::DownloadFailed(..)
+        | Error::VmlinuxNotFound(..)
+        | Error::PaholeFailed(..)
+        | Error::SectionNotFound(..)
+        | Error::NoSectionHeaders(_) => -ENOENT,
+        Error::InvalidElf(_) | Error::BtfArchMismatch(..) => -ENOEXEC,
+        Error::ArchiveChanged(_) => -ESTALE,
+        Error::TooManyLinks(_) => -ELOOP,
+        Error::NotInManifest(_) => -ENOKEY,
+        Error::DigestMismatch(..) => -EBADMSG,
+        Error::Cancelled => -ECANCELED,
+        Error::SystemNotCovered(..) => -ENOPKG,
+    }
+}
+
+/// Errno for a failure to detect the running system: `-ENOENT`, or `-ENOTSUP` on other
+/// systems than Linux
+pub(crate) fn detect_errno(e: &Error) -> c_int {
+    match e {
+        Error::UnsupportedPlatform => -ENOTSUP,
+        _ => -ENOENT,
+    }
+}
+
+/// Errno for a failure while reading the tar stream
+pub(crate) fn stream_errno(e: &std::io::Error) -> c_int {
+    use std::io::ErrorKind;
+    match e.kind() {
+        // 解压失败（数据损坏或被截断）
+        ErrorKind::InvalidInput | ErrorKind::InvalidData | ErrorKind::UnexpectedEof => -EINVAL,
+        // 解压后的大小超出上限，见 Options::max_decompressed_size
+        ErrorKind::FileTooLarge => -EFBIG,
+        // 非 Linux 系统上无法创建临时文件等
+        ErrorKind::Unsupported => -ENOTSUP,
+        _ => -EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpf_compatible_rs::{
+        fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+        tar::Header,
+        SystemInfo,
+    };
+
+    use super::*;
+
+    /// Options looking up `release` of ubuntu 20.04 x86_64 with `policy`
+    fn opts_for(release: &str, policy: MatchPolicy) -> Options {
+        Options {
+            system: Some(SystemInfo {
+                distro_id: "ubuntu".into(),
+                version_id: "20.04".into(),
+                arch: "x86_64".into(),
+                kernel_release: release.into(),
+                ..Default::default()
+            }),
+            policy,
+            ..Options::default()
+        }
+    }
+
+    /// The btf found in `tar` with `opts`, and the entry the lookup recorded
+    fn find(tar: &[u8], opts: &Options) -> Result<(Vec<u8>, MatchInfo), c_int> {
+        let btf = lookup_btf(TarSource::Bytes(tar), opts, || Ok(Vec::new()))?;
+        Ok((btf, crate::match_info::last().unwrap()))
+    }
+
+    fn ubuntu_archive() -> Vec<u8> {
+        FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-140-generic",
+                minimal_valid_btf(),
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-144-generic",
+                btf_of_arch(8, "r15"),
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-150-generic",
+                minimal_valid_btf(),
+            )
+            .btf(
+                "ubuntu",
+                "18.04",
+                "x86_64",
+                "5.4.0-148-generic",
+                minimal_valid_btf(),
+            )
+            .gz()
+    }
+
+    #[test]
+    fn exact_match_is_the_default() {
+        let tar = ubuntu_archive();
+        assert_eq!(
+            find(&tar, &opts_for("5.4.0-148-generic", MatchPolicy::default())).err(),
+            Some(-ENOENT)
+        );
+    }
+
+    #[test]
+    fn nearest_lower_point_release_is_used_if_asked() {
+        let tar = ubuntu_archive();
+        let (btf, matched) = find(
+            &tar,
+            &opts_for("5.4.0-148-generic", MatchPolicy::SameFlavorNearest),
+        )
+        .unwrap();
+        assert_eq!(btf, btf_of_arch(8, "r15"));
+        // 回退的结果不是精确匹配，调用者可以通过匹配信息看到
+        assert!(!matched.exact);
+        assert_eq!(matched.kernel_release, "5.4.0-144-generic");
+        assert_eq!(
+            matched.entry_path.unwrap(),
+            Path::new("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-144-generic.btf")
+        );
+    }
+
+    #[test]
+    fn exact_release_is_still_preferred_with_the_fallback() {
+        let tar = ubuntu_archive();
+        let (_, matched) = find(
+            &tar,
+            &opts_for("5.4.0-150-generic", MatchPolicy::SameFlavorNearest),
+        )
+        .unwrap();
+        assert!(matched.exact);
+        assert_eq!(matched.kernel_release, "5.4.0-150-generic");
+    }
+
+    #[test]
+    fn no_release_of_the_same_series_is_a_miss() {
+        let tar = ubuntu_archive();
+        for release in ["5.8.0-63-generic", "5.4.0-148-azure"] {
+            assert_eq!(
+                find(&tar, &opts_for(release, MatchPolicy::SameFlavorNearest)).err(),
+                Some(-ENOENT)
+            );
+        }
+    }
+
+    #[test]
+    fn each_policy_selects_its_entry() {
+        let tar = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-140-azure",
+                minimal_valid_btf(),
+            )
+            .btf(
+                "ubuntu",
+                "18.04",
+                "x86_64",
+                "5.8.0-63-generic",
+                minimal_valid_btf(),
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-150-generic",
+                minimal_valid_btf(),
+            )
+            .gz();
+        let entry = |release: &str, policy| {
+            find(&tar, &opts_for(release, policy))
+                .map(|(_, v)| v.entry_path.unwrap().display().to_string())
+        };
+        let dir = "btfhub-archive/ubuntu";
+        // 同一 flavor 中只有较新的版本
+        assert_eq!(entry("5.4.0-148-generic", MatchPolicy::Exact), Err(-ENOENT));
+        assert_eq!(
+            entry("5.4.0-148-generic", MatchPolicy::SameFlavorNearest),
+            Ok(format!("{}/20.04/x86_64/5.4.0-150-generic.btf", dir))
+        );
+        // 其他 flavor 只有 BestEffort 才会使用
+        assert_eq!(
+            entry("5.4.0-148-aws", MatchPolicy::SameFlavorNearest),
+            Err(-ENOENT)
+        );
+        assert_eq!(
+            entry("5.4.0-148-aws", MatchPolicy::BestEffort),
+            Ok(format!("{}/20.04/x86_64/5.4.0-140-azure.btf", dir))
+        );
+        // 最后的手段：同一内核位于发行版的其他版本目录下
+        assert_eq!(
+            entry("5.8.0-63-generic", MatchPolicy::SameFlavorNearest),
+            Err(-ENOENT)
+        );
+        assert_eq!(
+            entry("5.8.0-63-generic", MatchPolicy::BestEffort),
+            Ok(format!("{}/18.04/x86_64/5.8.0-63-generic.btf", dir))
+        );
+    }
+
+    /// The entry found for `release` on ubuntu 20.04 with `policy` in an archive of the
+    /// `(version, release)` btfs `entries`, as its path under `btfhub-archive/ubuntu`
+    fn found_in(
+        entries: &[(&str, &str)],
+        release: &str,
+        policy: MatchPolicy,
+    ) -> Result<String, c_int> {
+        let mut fixture = FixtureArchive::new();
+        for (version, entry) in entries {
+            fixture = fixture.btf("ubuntu", version, "x86_64", entry, minimal_valid_btf());
+        }
+        let (_, matched) = find(&fixture.gz(), &opts_for(release, policy))?;
+        let path = matched.entry_path.unwrap();
+        let path = path.strip_prefix("btfhub-archive/ubuntu").unwrap();
+        Ok(path.display().to_string())
+    }
+
+    #[test]
+    fn hwe_kernels_are_found_under_the_release_of_their_series() {
+        let exact = MatchPolicy::Exact;
+        // 20.04 上的 HWE 内核属于 22.04 的系列
+        for release in [
+            "5.15.0-76-generic",
+            "5.15.0-1041-azure",
+            "5.15.0-1039-aws",
+            "5.15.0-1036-gcp",
+            "5.15.0-76-lowlatency",
+        ] {
+            assert_eq!(
+                found_in(&[("22.04", release)], release, exact),
+                Ok(format!("22.04/x86_64/{release}.btf"))
+            );
+        }
+        // 发行版自己的目录优先
+        let both = [
+            ("22.04", "5.15.0-76-generic"),
+            ("20.04", "5.15.0-76-generic"),
+        ];
+        assert_eq!(
+            found_in(&both, "5.15.0-76-generic", exact),
+            Ok("20.04/x86_64/5.15.0-76-generic.btf".into())
+        );
+        // 以代号命名的目录同样查找
+        assert_eq!(
+            found_in(
+                &[("jammy", "5.15.0-76-generic")],
+                "5.15.0-76-generic",
+                exact
+            ),
+            Ok("jammy/x86_64/5.15.0-76-generic.btf".into())
+        );
+        // 原生内核不会到其他版本的目录下查找
+        assert_eq!(
+            found_in(
+                &[("22.04", "5.4.0-150-generic")],
+                "5.4.0-150-generic",
+                exact
+            ),
+            Err(-ENOENT)
+        );
+        // 未知系列的内核没有 HWE 目录
+        assert_eq!(
+            found_in(&[("22.04", "5.16.0-1-generic")], "5.16.0-1-generic", exact),
+            Err(-ENOENT)
+        );
+        let (_, matched) = find(
+            &FixtureArchive::new()
+                .btf(
+                    "ubuntu",
+                    "22.04",
+                    "x86_64",
+                    "5.15.0-76-generic",
+                    minimal_valid_btf(),
+                )
+                .gz(),
+            &opts_for("5.15.0-76-generic", exact),
+        )
+        .unwrap();
+        // 是同一内核的 btf，只是位于其他版本的目录下
+        assert!(matched.exact);
+        assert!(matched.candidate.is_some_and(|v| v > 0), "{matched:?}");
+    }
+
+    #[test]
+    fn flavors_are_crossed_last() {
+        let release = "5.15.0-1041-azure";
+        let nearest = MatchPolicy::SameFlavorNearest;
+        let best_effort = MatchPolicy::BestEffort;
+        // 同一 flavor 最接近的 ABI，即使位于 HWE 的目录下
+        let entries = [
+            ("20.04", "5.15.0-1042-generic"),
+            ("22.04", "5.15.0-1040-azure"),
+            ("22.04", "5.15.0-1037-azure"),
+        ];
+        for policy in [nearest, best_effort] {
+            assert_eq!(
+                found_in(&entries, release, policy),
+                Ok("22.04/x86_64/5.15.0-1040-azure.btf".into())
+            );
+        }
+        // 只有 generic 时，只有 BestEffort 才跨越 flavor
+        let generic = [("22.04", "5.15.0-76-generic")];
+        assert_eq!(
+            found_in(&generic, release, MatchPolicy::Exact),
+            Err(-ENOENT)
+        );
+        assert_eq!(found_in(&generic, release, nearest), Err(-ENOENT));
+        assert_eq!(
+            found_in(&generic, release, best_effort),
+            Ok("22.04/x86_64/5.15.0-76-generic.btf".into())
+        );
+        // 其他系列的同一 flavor 不算
+        let other_series = [("22.04", "5.19.0-1041-azure")];
+        assert_eq!(found_in(&other_series, release, best_effort), Err(-ENOENT));
+    }
+
+    /// Options looking up `release` of debian `version` x86_64 with `policy`
+    fn debian_opts(version: &str, release: &str, policy: MatchPolicy) -> Options {
+        let mut opts = opts_for(release, policy);
+        let system = opts.system.as_mut().unwrap();
+        system.distro_id = "debian".into();
+        system.version_id = version.into();
+        opts
+    }
+
+    fn debian_archive() -> Vec<u8> {
+        FixtureArchive::new()
+            .btf(
+                "debian",
+                "11",
+                "x86_64",
+                "5.10.0-23-amd64",
+                minimal_valid_btf(),
+            )
+            .btf(
+                "debian",
+                "12",
+                "x86_64",
+                "6.1.0-13-amd64",
+                btf_of_arch(8, "r13"),
+            )
+            .btf(
+                "debian",
+                "12",
+                "x86_64",
+                "6.1.0-18-amd64",
+                btf_of_arch(8, "r18"),
+            )
+            .gz()
+    }
+
+    #[test]
+    fn debian_kernels_are_found_under_their_major_release() {
+        let tar = debian_archive();
+        for (version, release, entry) in [
+            (
+                "11",
+                "5.10.0-23-amd64",
+                "debian/11/x86_64/5.10.0-23-amd64.btf",
+            ),
+            (
+                "11.7",
+                "5.10.0-23-amd64",
+                "debian/11/x86_64/5.10.0-23-amd64.btf",
+            ),
+            (
+                "12",
+                "6.1.0-18-amd64",
+                "debian/12/x86_64/6.1.0-18-amd64.btf",
+            ),
+            (
+                "12.5",
+                "6.1.0-18-amd64",
+                "debian/12/x86_64/6.1.0-18-amd64.btf",
+            ),
+        ] {
+            let (_, matched) = find(&tar, &debian_opts(version, release, MatchPolicy::Exact))
+                .unwrap_or_else(|e| panic!("{version} {release}: {e}"));
+            assert_eq!(
+                matched.entry_path.unwrap(),
+                Path::new("btfhub-archive").join(entry)
+            );
+            assert!(matched.exact);
+        }
+    }
+
+    #[test]
+    fn backports_kernels_are_found_only_with_best_effort() {
+        let tar = debian_archive();
+        let release = "6.1.0-0.deb11.13-amd64";
+        for policy in [MatchPolicy::Exact, MatchPolicy::SameFlavorNearest] {
+            assert_eq!(
+                find(&tar, &debian_opts("11", release, policy)),
+                Err(-ENOENT)
+            );
+            // 错误说明这是 backports 内核，以及如何退而使用其来源版本的 btf
+            let error = crate::last_error::get().unwrap();
+            assert!(
+                error.contains("backports kernel")
+                    && error.contains("6.1.0-13-amd64")
+                    && error.contains("BPF_COMPAT_MATCH_BEST_EFFORT"),
+                "{error}"
+            );
+        }
+        // 来源版本中同一 ABI 的内核
+        let (btf, matched) =
+            find(&tar, &debian_opts("11", release, MatchPolicy::BestEffort)).unwrap();
+        assert_eq!(btf, btf_of_arch(8, "r13"));
+        assert_eq!(
+            matched.entry_path.unwrap(),
+            Path::new("btfhub-archive/debian/12/x86_64/6.1.0-13-amd64.btf")
+        );
+        // 同一 ABI 不存在时，取来源版本中最接近的较低 ABI，没有时取较高的
+        for (release, expected) in [
+            ("6.1.0-0.deb11.17-amd64", "r13"),
+            ("6.1.0-0.deb11.9-amd64", "r13"),
+            ("6.1.0-0.deb11.21-amd64", "r18"),
+        ] {
+            let (btf, _) =
+                find(&tar, &debian_opts("11", release, MatchPolicy::BestEffort)).unwrap();
+            assert_eq!(btf, btf_of_arch(8, expected), "{release}");
+        }
+        // 来源版本中也没有时，错误同样提到 backports
+        assert_eq!(
+            find(
+                &tar,
+                &debian_opts("11", "6.12.12-0.deb12.1-amd64", MatchPolicy::BestEffort)
+            ),
+            Err(-ENOENT)
+        );
+        let error = crate::last_error::get().unwrap();
+        assert!(
+            error.contains("backports kernel") && error.contains("debian 13"),
+            "{error}"
+        );
+        // 未知系列的 backports 内核
+        assert_eq!(
+            find(
+                &tar,
+                &debian_opts("11", "5.16.0-0.bpo.4-amd64", MatchPolicy::BestEffort)
+            ),
+            Err(-ENOENT)
+        );
+        assert!(crate::last_error::get().unwrap().contains("unknown series"));
+    }
+
+    /// The entry the btf of `release` on `distro` 8.8 is found at in an archive of `entries`, relative to the archive root
+    fn el_found_in(
+        entries: &[(&str, &str)],
+        distro: &str,
+        release: &str,
+        policy: MatchPolicy,
+    ) -> Result<String, c_int> {
+        let mut fixture = FixtureArchive::new();
+        for (dir, entry) in entries {
+            let (distro, version) = dir.split_once('/').unwrap();
+            fixture = fixture.btf(distro, version, "x86_64", entry, minimal_valid_btf());
+        }
+        let mut opts = opts_for(release, policy);
+        let system = opts.system.as_mut().unwrap();
+        system.distro_id = distro.into();
+        system.version_id = "8.8".into();
+        let (_, matched) = find(&fixture.gz(), &opts)?;
+        let path = matched.entry_path.unwrap();
+        let path = path.strip_prefix("btfhub-archive").unwrap();
+        Ok(path.display().to_string())
+    }
+
+    #[test]
+    fn enterprise_linux_kernels_are_found_across_the_family() {
+        let release = "4.18.0-477.10.1.el8_8.x86_64";
+        let exact = MatchPolicy::Exact;
+        // rhel 和 centos 的目录属于精确匹配，按家族中的顺序
+        let both = [("centos/8", release), ("rhel/8", release)];
+        assert_eq!(
+            el_found_in(&both, "rocky", release, exact),
+            Ok(format!("rhel/8/x86_64/{release}.btf"))
+        );
+        assert_eq!(
+            el_found_in(&both, "centos", release, exact),
+            Ok(format!("centos/8/x86_64/{release}.btf"))
+        );
+        assert_eq!(
+            el_found_in(&[("centos/8", release)], "almalinux", release, exact),
+            Ok(format!("centos/8/x86_64/{release}.btf"))
+        );
+        // 自己的目录优先
+        let own = [("rhel/8", release), ("rocky/8", release)];
+        assert_eq!(
+            el_found_in(&own, "rocky", release, exact),
+            Ok(format!("rocky/8/x86_64/{release}.btf"))
+        );
+        // 其他目录（如 ol，或另一个主版本）中的同一内核只在 BestEffort 时使用
+        for dir in ["ol/8", "rhel/9"] {
+            let other = [(dir, release)];
+            for policy in [exact, MatchPolicy::SameFlavorNearest] {
+                assert_eq!(el_found_in(&other, "rocky", release, policy), Err(-ENOENT));
+            }
+            assert_eq!(
+                el_found_in(&other, "rocky", release, MatchPolicy::BestEffort),
+                Ok(format!("{dir}/x86_64/{release}.btf"))
+            );
+        }
+        // 家族之外的目录不算
+        let outside = [("anolis/8", release)];
+        assert_eq!(
+            el_found_in(&outside, "rocky", release, MatchPolicy::BestEffort),
+            Err(-ENOENT)
+        );
+        // 家族之外的发行版不会查找家族的目录
+        assert_eq!(
+            el_found_in(
+                &[("ol/8", release)],
+                "anolis",
+                release,
+                MatchPolicy::BestEffort
+            ),
+            Err(-ENOENT)
+        );
+    }
+
+    #[test]
+    fn non_regular_entries_at_the_path_are_skipped() {
+        let entry = "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-144-generic.btf";
+        let opts = opts_for("5.4.0-144-generic", MatchPolicy::default());
+        // 只有目录时查找失败，并说明原因
+        let tar = FixtureArchive::new().dir(entry).gz();
+        assert_eq!(find(&tar, &opts).err(), Some(-ENOENT));
+        let error = crate::last_error::get().unwrap();
+        assert!(error.contains("not a regular file"), "{error}");
+        // 目录在文件之前或之后都不影响找到的 btf
+        for tar in [
+            FixtureArchive::new()
+                .dir(entry)
+                .file(entry, btf_of_arch(8, "r15"))
+                .gz(),
+            FixtureArchive::new()
+                .file(entry, btf_of_arch(8, "r15"))
+                .dir(entry)
+                .gz(),
+        ] {
+            let (btf, matched) = find(&tar, &opts).unwrap();
+            assert_eq!(btf, btf_of_arch(8, "r15"));
+            assert!(matched.exact);
+        }
+    }
+
+    #[test]
+    fn non_regular_entries_are_not_nearest_releases() {
+        // 更接近的点版本是目录时，回退到较远的文件
+        let tar = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-140-generic",
+                btf_of_arch(8, "r15"),
+            )
+            .dir("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-146-generic.btf")
+            .gz();
+        let (btf, matched) = find(
+            &tar,
+            &opts_for("5.4.0-148-generic", MatchPolicy::SameFlavorNearest),
+        )
+        .unwrap();
+        assert_eq!(btf, btf_of_arch(8, "r15"));
+        assert_eq!(matched.kernel_release, "5.4.0-140-generic");
+    }
+
+    #[test]
+    fn fifo_at_the_path_is_not_read() {
+        let entry = "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-144-generic.btf";
+        let mut tar = FixtureArchive::new()
+            .file(entry, btf_of_arch(8, "r15"))
+            .tar();
+        // 把条目改成 FIFO，并重新计算校验和
+        let mut header = Header::new_old();
+        header.as_mut_bytes().copy_from_slice(&tar[..512]);
+        header.set_entry_type(EntryType::Fifo);
+        header.set_cksum();
+        tar[..512].copy_from_slice(header.as_bytes());
+        assert_eq!(
+            find(&tar, &opts_for("5.4.0-144-generic", MatchPolicy::default())).err(),
+            Some(-ENOENT)
+        );
+        let error = crate::last_error::get().unwrap();
+        assert!(error.contains("not a regular file"), "{error}");
+    }
+
+    #[test]
+    fn entries_of_concatenated_archives_are_found() {
+        let first = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-140-generic",
+                btf_of_arch(8, "r14"),
+            )
+            .gz();
+        let second = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-144-generic",
+                btf_of_arch(8, "r15"),
+            )
+            .gz();
+        // 追加了新内核的归档，且被补零到段大小的边界
+        let mut tar = [first, second].concat();
+        tar.resize(tar.len() + 3000, 0);
+        for (release, register) in [("5.4.0-140-generic", "r14"), ("5.4.0-144-generic", "r15")] {
+            let (btf, _) = find(&tar, &opts_for(release, MatchPolicy::default())).unwrap();
+            assert_eq!(btf, btf_of_arch(8, register));
+        }
+    }
+
+    #[test]
+    fn custom_builds_are_found_without_their_local_version() {
+        let tar = ubuntu_archive();
+        let (btf, matched) = find(
+            &tar,
+            &opts_for("5.4.0-144-generic-mycorp1+", MatchPolicy::default()),
+        )
+        .unwrap();
+        assert_eq!(btf, btf_of_arch(8, "r15"));
+        assert!(matched.exact);
+        assert!(matched.local_version_stripped);
+        assert_eq!(matched.kernel_release, "5.4.0-144-generic");
+        // 原样的版本在归档中时优先使用
+        let tar = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-144-generic",
+                minimal_valid_btf(),
+            )
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-144-generic-mycorp1",
+                btf_of_arch(8, "r15"),
+            )
+            .gz();
+        let (btf, matched) = find(
+            &tar,
+            &opts_for("5.4.0-144-generic-mycorp1", MatchPolicy::default()),
+        )
+        .unwrap();
+        assert_eq!(btf, btf_of_arch(8, "r15"));
+        assert!(!matched.local_version_stripped);
+        // 单个词的 flavor 不会被当作本地版本去掉
+        assert_eq!(
+            find(&tar, &opts_for("5.4.0-144-custom", MatchPolicy::default())).err(),
+            Some(-ENOENT)
+        );
+    }
+
+    #[test]
+    fn kernels_missing_from_the_listing_are_not_looked_for() {
+        let mut listing = ArchiveListing::default();
+        // 清单中 140 的内容已过时，150 未列出
+        listing.push(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-140-generic.btf",
+            b"stale",
+        );
+        let listed = |listing: &ArchiveListing| {
+            FixtureArchive::new()
+                .file("btfhub-archive/manifest.json", listing.to_json())
+                .btf(
+                    "ubuntu",
+                    "20.04",
+                    "x86_64",
+                    "5.4.0-140-generic",
+                    btf_of_arch(8, "140"),
+                )
+                .btf(
+                    "ubuntu",
+                    "20.04",
+                    "x86_64",
+                    "5.4.0-150-generic",
+                    btf_of_arch(8, "150"),
+                )
+                .gz()
+        };
+        let tar = listed(&listing);
+        let exact = |release| opts_for(release, MatchPolicy::Exact);
+        assert_eq!(find(&tar, &exact("5.4.0-150-generic")).err(), Some(-ENOENT));
+        let error = crate::last_error::get().unwrap();
+        assert!(error.contains("not in its manifest.json"), "{error}");
+        assert_eq!(
+            find(&tar, &exact("5.4.0-140-generic")).unwrap().0,
+            btf_of_arch(8, "140")
+        );
+        // 不要求精确匹配时，清单中同一目录下的任何 btf 都可能是最近的版本，仍然扫描
+        let (btf, matched) = find(
+            &tar,
+            &opts_for("5.4.0-160-generic", MatchPolicy::SameFlavorNearest),
+        )
+        .unwrap();
+        assert_eq!(btf, btf_of_arch(8, "150"));
+        assert_eq!(matched.kernel_release, "5.4.0-150-generic");
+
+        let mut other = ArchiveListing::default();
+        other.push(
+            "btfhub-archive/centos/8/x86_64/4.18.0-305.el8.x86_64.btf",
+            b"",
+        );
+        assert_eq!(
+            find(
+                &listed(&other),
+                &opts_for("5.4.0-160-generic", MatchPolicy::SameFlavorNearest)
+            )
+            .err(),
+            Some(-ENOENT)
+        );
+        // 没有清单时照常扫描
+        let unlisted = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-150-generic",
+                btf_of_arch(8, "150"),
+            )
+            .gz();
+        assert_eq!(
+            find(&unlisted, &exact("5.4.0-150-generic")).unwrap().0,
+            btf_of_arch(8, "150")
+        );
+    }
+
+    #[test]
+    fn every_error_has_a_fixed_errno() {
+        use bpf_compatible_rs::compression::ArchiveFormat;
+        use std::io::{Error as IoError, ErrorKind};
+
+        let io = |errno| IoError::from_raw_os_error(errno);
+        let s = String::new;
+        let table = [
+            (Error::OsReleaseError(io(libc::EACCES)), -libc::EACCES),
+            (Error::OsReleaseError(ErrorKind::Other.into()), -ENOENT),
+            (Error::OsReleaseMissing(s().into()), -ENOENT),
+            (Error::MissingOsReleaseField("ID"), -ENOENT),
+            (Error::DistroNotDetected(s()), -ENOENT),
+            (Error::UnameError(io(libc::EFAULT)), -libc::EFAULT),
+            (Error::TempDirError(io(libc::ENOSPC)), -libc::ENOSPC),
+            (Error::TarUnpackError(ErrorKind::Other.into()), -EIO),
+            (Error::FileReadError(s(), io(libc::EACCES)), -libc::EACCES),
+            (Error::FileWriteError(s(), io(libc::EROFS)), -libc::EROFS),
+            (Error::BpftoolUnavailable(s(), io(ENOENT)), -ENOENT),
+            (
+                Error::PaholeUnavailable(s(), io(libc::EACCES)),
+                -libc::EACCES,
+            ),
+            // 读取 tar 流的错误按类型区分损坏、超限和其他 I/O 错误
+            (
+                Error::TarReadError(ErrorKind::UnexpectedEof.into()),
+                -EINVAL,
+            ),
+            (Error::TarReadError(ErrorKind::InvalidData.into()), -EINVAL),
+            (Error::TarReadError(ErrorKind::FileTooLarge.into()), -EFBIG),
+            (Error::TarReadError(ErrorKind::Unsupported.into()), -ENOTSUP),
+            (Error::TarReadError(io(libc::EACCES)), -EIO),
+            (Error::Decompress(ErrorKind::InvalidInput.into()), -EINVAL),
+            (Error::Decompress(ErrorKind::FileTooLarge.into()), -EFBIG),
+            (Error::Io(io(libc::ENOSPC)), -libc::ENOSPC),
+            (Error::InvalidBtf(s()), -EILSEQ),
+            (Error::BtfEndiannessMismatch("big"), -EILSEQ),
+            (Error::NotBtfhubArchive, -crate::platform::EMEDIUMTYPE),
+            (Error::UnknownArchiveFormat(s()), -EINVAL),
+            (Error::InvalidGzipHeader, -EINVAL),
+            (Error::UnsupportedTarget(s()), -EINVAL),
+            (Error::InvalidObject(s()), -EINVAL),
+            (Error::DuplicateEntry(s()), -EINVAL),
+            (Error::InvalidEntryName(s()), -EINVAL),
+            (Error::InvalidTempfileTemplate(s()), -EINVAL),
+            (Error::UnsafePath(s()), -EINVAL),
+            (Error::UnsupportedCompression(ArchiveFormat::Zstd), -ENOTSUP),
+            (Error::StrategyUnavailable(s(), "download"), -ENOTSUP),
+            (Error::UnsupportedArch(s()), -ENOTSUP),
+            (Error::UnsupportedPlatform, -ENOTSUP),
+            (
+                Error::EntryNotFound {
+                    expected: s().into(),
+                },
+                -ENOENT,
+            ),
+            (Error::NoNativeBtf, -ENOENT),
+            (Error::DownloadFailed(s(), s()), -ENOENT),
+            (Error::VmlinuxNotFound(s(), s()), -ENOENT),
+            (Error::PaholeFailed(s(), s()), -ENOENT),
+            (Error::SectionNotFound(s(), s()), -ENOENT),
+            (Error::NoSectionHeaders(s()), -ENOENT),
+            (Error::InvalidElf(s()), -ENOEXEC),
+            (Error::BtfArchMismatch(s(), s()), -ENOEXEC),
+            (Error::ArchiveChanged(s()), -crate::platform::ESTALE),
+            (Error::TooManyLinks(s()), -ELOOP),
+            (Error::NotInManifest(s()), -crate::platform::ENOKEY),
+            (Error::DigestMismatch(s(), s(), s()), -EBADMSG),
+            (Error::Cancelled, -ECANCELED),
+            (Error::SystemNotCovered(s(), s()), -crate::platform::ENOPKG),
+        ];
+        for (error, errno) in table {
+            assert_eq!(archive_errno(&error), errno, "{error:?}");
+        }
+        assert_eq!(detect_errno(&Error::UnsupportedPlatform), -ENOTSUP);
+        assert_eq!(detect_errno(&Error::DistroNotDetected(s())), -ENOENT);
+    }
+}
@@ -0,0 +1,57 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `struct bpf_compat_gc_report` of the C API
+use std::{ffi::c_int, mem::size_of};
+
+use bpf_compatible_rs::gc::GcReport;
+
+use crate::sized::{declared_size, write_sized, SizedStruct};
+
+/// What `bpf_compatible_gc_stale_btf_tempfiles` did
+///
+/// Like `struct bpf_compat_archive_info`, `sz` must be set to
+/// `sizeof(struct bpf_compat_gc_report)` by the caller, and only that many bytes are written.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfCompatGcReport {
+    pub sz: usize,
+    /// Stale files removed
+    pub removed: usize,
+    /// Files left in place as they're too recent, or of this process
+    pub recent: usize,
+    /// Stale files that couldn't be removed
+    pub failed: usize,
+    /// Bytes the removed files held
+    pub freed_bytes: u64,
+}
+
+unsafe impl SizedStruct for BpfCompatGcReport {
+    const NAME: &'static str = "bpf_compat_gc_report";
+}
+
+impl From<&GcReport> for BpfCompatGcReport {
+    fn from(report: &GcReport) -> Self {
+        Self {
+            sz: size_of::<Self>(),
+            removed: report.removed,
+            recent: report.recent,
+            failed: report.failed,
+            freed_bytes: report.freed_bytes,
+        }
+    }
+}
+
+/// Check the size the caller declares, before anything is removed
+pub(crate) fn check_report(out: *mut BpfCompatGcReport) -> c_int {
+    declared_size(out).err().unwrap_or(0)
+}
+
+/// Copy `report` to the caller's struct, up to the size it declares
+pub(crate) fn write_report(out: *mut BpfCompatGcReport, report: &GcReport) {
+    if let Ok(sz) = declared_size(out) {
+        write_sized(out, &report.into(), sz);
+    }
+}
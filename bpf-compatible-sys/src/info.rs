@@ -0,0 +1,202 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `struct bpf_compat_archive_info` of the C API
+use std::{
+    ffi::{c_char, c_int},
+    mem::size_of,
+};
+
+use bpf_compatible_rs::metadata::ArchiveInfo;
+
+use crate::sized::{copy_c_string, declared_size, write_sized, SizedStruct};
+
+/// Capacity of `build_id`, NUL included; longer identifiers are truncated
+const BUILD_ID_SIZE: usize = 64;
+
+/// Summary of an archive, see `get_core_btf_archive_info`
+///
+/// Like `struct bpf_compat_opts`, `sz` must be set to `sizeof(struct bpf_compat_archive_info)`
+/// by the caller, and only that many bytes are written, so the struct can grow.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfCompatArchiveInfo {
+    pub sz: usize,
+    /// Number of btf entries, links included
+    pub btf_entries: usize,
+    /// Size of the tar, once decompressed
+    pub uncompressed_size: u64,
+    /// Size of the archive as given
+    pub compressed_size: u64,
+    /// `build_time` of the metadata entry, in seconds since the epoch; -1 if unknown
+    pub build_time: i64,
+    /// `build_id` of the metadata entry, NUL-terminated; empty if unknown
+    pub build_id: [c_char; BUILD_ID_SIZE],
+}
+
+unsafe impl SizedStruct for BpfCompatArchiveInfo {
+    const NAME: &'static str = "bpf_compat_archive_info";
+}
+
+impl From<&ArchiveInfo> for BpfCompatArchiveInfo {
+    fn from(info: &ArchiveInfo) -> Self {
+        let metadata = info.metadata.as_ref();
+        let mut raw = Self {
+            sz: size_of::<Self>(),
+            btf_entries: info.btf_entries,
+            uncompressed_size: info.uncompressed_size,
+            compressed_size: info.compressed_size,
+            build_time: metadata
+                .and_then(|v| v.build_time)
+                .and_then(|v| i64::try_from(v).ok())
+                .unwrap_or(-1),
+            build_id: [0; BUILD_ID_SIZE],
+        };
+        let build_id = metadata
+            .and_then(|v| v.build_id.as_deref())
+            .unwrap_or_default();
+        // 标识中的 NUL 会截断字符串，与 C 的语义一致
+        copy_c_string(&mut raw.build_id, build_id.as_bytes());
+        raw
+    }
+}
+
+/// Copy `info` to the caller's struct, up to the size it declares
+pub(crate) fn write_info(out: *mut BpfCompatArchiveInfo, info: &ArchiveInfo) -> c_int {
+    match declared_size(out) {
+        Ok(sz) => {
+            write_sized(out, &info.into(), sz);
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CStr, mem::offset_of};
+
+    use bpf_compatible_rs::fixture::{minimal_valid_btf, FixtureArchive};
+    use libc::EINVAL;
+
+    use super::*;
+    use crate::{get_core_btf_archive_info, get_core_btf_archive_info_linked_tar};
+
+    /// A struct as a C caller would pass it, everything but `sz` set to a marker
+    fn marked(sz: usize) -> BpfCompatArchiveInfo {
+        BpfCompatArchiveInfo {
+            sz,
+            btf_entries: usize::MAX,
+            uncompressed_size: u64::MAX,
+            compressed_size: u64::MAX,
+            build_time: i64::MIN,
+            build_id: [b'x' as c_char; BUILD_ID_SIZE],
+        }
+    }
+
+    fn fixture() -> FixtureArchive {
+        FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                minimal_valid_btf(),
+            )
+            .btf(
+                "debian",
+                "11",
+                "arm64",
+                "5.10.0-20-arm64",
+                minimal_valid_btf(),
+            )
+    }
+
+    fn info_of(tar: &[u8], info: &mut BpfCompatArchiveInfo) -> c_int {
+        get_core_btf_archive_info(tar.as_ptr(), tar.len(), info)
+    }
+
+    #[test]
+    fn archive_with_metadata_is_described() {
+        let tar = fixture()
+            .file(
+                "btfhub-archive/.metadata",
+                b"build_time=1700000000\nbuild_id=ci-42\n".to_vec(),
+            )
+            .gz();
+        let mut info = marked(size_of::<BpfCompatArchiveInfo>());
+        assert_eq!(info_of(&tar, &mut info), 0);
+        assert_eq!(info.btf_entries, 2);
+        assert_eq!(info.compressed_size, tar.len() as u64);
+        assert!(info.uncompressed_size > info.compressed_size);
+        assert_eq!(info.build_time, 1700000000);
+        let build_id = unsafe { CStr::from_ptr(info.build_id.as_ptr()) };
+        assert_eq!(build_id.to_str().unwrap(), "ci-42");
+    }
+
+    #[test]
+    fn archive_without_metadata_has_no_build_info() {
+        let tar = fixture().gz();
+        let mut info = marked(size_of::<BpfCompatArchiveInfo>());
+        assert_eq!(info_of(&tar, &mut info), 0);
+        assert_eq!((info.btf_entries, info.build_time), (2, -1));
+        assert_eq!(info.build_id[0], 0);
+    }
+
+    #[test]
+    fn long_build_id_is_truncated_with_its_nul() {
+        let id = "b".repeat(100);
+        let tar = fixture()
+            .file(
+                "btfhub-archive/.metadata",
+                format!("build_id={}\n", id).into_bytes(),
+            )
+            .gz();
+        let mut info = marked(size_of::<BpfCompatArchiveInfo>());
+        assert_eq!(info_of(&tar, &mut info), 0);
+        let build_id = unsafe { CStr::from_ptr(info.build_id.as_ptr()) };
+        assert_eq!(build_id.to_str().unwrap(), &id[..BUILD_ID_SIZE - 1]);
+    }
+
+    #[test]
+    fn only_the_declared_size_is_written() {
+        let tar = fixture()
+            .file("btfhub-archive/.metadata", b"build_time=1\n".to_vec())
+            .gz();
+        // 较旧的调用者的结构体到 build_time 为止
+        let mut info = marked(offset_of!(BpfCompatArchiveInfo, build_time));
+        assert_eq!(info_of(&tar, &mut info), 0);
+        assert_eq!(info.btf_entries, 2);
+        assert_eq!(info.compressed_size, tar.len() as u64);
+        assert_eq!(info.build_time, i64::MIN);
+        assert_eq!(info.build_id[0], b'x' as c_char);
+        // 连 sz 都放不下的大小无效
+        let mut info = marked(0);
+        assert_eq!(info_of(&tar, &mut info), -EINVAL);
+        assert_eq!(info.btf_entries, usize::MAX);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let tar = fixture().gz();
+        assert_eq!(
+            get_core_btf_archive_info(tar.as_ptr(), tar.len(), std::ptr::null_mut()),
+            -EINVAL
+        );
+        let mut info = marked(size_of::<BpfCompatArchiveInfo>());
+        assert_eq!(
+            get_core_btf_archive_info(std::ptr::null(), 0, &mut info),
+            -EINVAL
+        );
+        assert!(info_of(&[0xa5; 4096], &mut info) < 0);
+        assert_eq!(info.btf_entries, usize::MAX);
+        // 测试程序中没有链接归档
+        assert_eq!(get_core_btf_archive_info_linked_tar(&mut info), -EINVAL);
+        assert_eq!(
+            get_core_btf_archive_info_linked_tar(std::ptr::null_mut()),
+            -EINVAL
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! The message of the last failed call on each thread, for callers that don't see stderr.
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{c_char, c_int, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+use libc::ENOTRECOVERABLE;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Log a failure, and keep its message as the last error of the thread
+macro_rules! report {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        $crate::log::log($crate::BPF_COMPAT_LOG_ERROR, &message);
+        $crate::last_error::set(message);
+    }};
+}
+
+/// Keep `message` as the last error of the thread
+pub(crate) fn set(message: String) {
+    // 消息中的 NUL 会截断 C 字符串，替换掉
+    let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|v| *v.borrow_mut() = Some(message));
+}
+
+/// The last error of the thread, to set it again once later failures reported theirs
+pub(crate) fn get() -> Option<String> {
+    LAST_ERROR.with(|v| {
+        v.borrow()
+            .as_ref()
+            .map(|v| v.to_string_lossy().into_owned())
+    })
+}
+
+fn clear() {
+    LAST_ERROR.with(|v| *v.borrow_mut() = None);
+}
+
+/// Run the body of an entry point returning 0, a positive status or a negative errno
+///
+/// The last error is cleared before, and again if `f` succeeds. A failure that reported
+/// nothing gets the description of its errno, so every failed call leaves a message.
+/// A panic of `f`, which would abort the process at the FFI boundary, is caught and
+/// returned as `-ENOTRECOVERABLE`.
+pub(crate) fn track(f: impl FnOnce() -> c_int) -> c_int {
+    clear();
+    let ret = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        report!(
+            "Internal error of bpf-compatible, please report it: {}",
+            panic_message(payload.as_ref())
+        );
+        -ENOTRECOVERABLE
+    });
+    if ret >= 0 {
+        clear();
+    } else if LAST_ERROR.with(|v| v.borrow().is_none()) {
+        set(std::io::Error::from_raw_os_error(-ret).to_string());
+    }
+    ret
+}
+
+/// The message a panic was started with, if it's a string as with `panic!`
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// The last error of the calling thread, see `bpf_compatible_last_error`
+pub(crate) fn as_ptr() -> *const c_char {
+    LAST_ERROR.with(|v| v.borrow().as_ref().map_or(std::ptr::null(), |v| v.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_always_leave_a_message() {
+        assert_eq!(
+            track(|| {
+                report!("Failed to read `a\0b`");
+                -libc::EIO
+            }),
+            -libc::EIO
+        );
+        // NUL 被转义，消息不会被截断
+        assert_eq!(get().as_deref(), Some("Failed to read `a\\0b`"));
+        // 没有报告消息的失败使用 errno 的描述
+        assert_eq!(track(|| -libc::ENOENT), -libc::ENOENT);
+        assert_eq!(
+            get(),
+            Some(std::io::Error::from_raw_os_error(libc::ENOENT).to_string())
+        );
+        // 成功时即使报告过消息也会清除
+        assert_eq!(
+            track(|| {
+                report!("Ignored failure");
+                1
+            }),
+            1
+        );
+        assert!(get().is_none());
+        assert!(as_ptr().is_null());
+    }
+
+    #[test]
+    fn panics_are_caught_at_the_boundary() {
+        assert_eq!(
+            track(|| panic!("Unexpected entry {}", 42)),
+            -ENOTRECOVERABLE
+        );
+        let message = get().unwrap();
+        assert!(message.ends_with("Unexpected entry 42"), "{message}");
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&7), "unknown panic");
+    }
+}
@@ -5,180 +5,3810 @@
 //!
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 use std::{
-    ffi::{c_char, c_int, CStr},
-    io::{Read, Write},
-    path::PathBuf,
+    ffi::{c_char, c_int, c_uint, CStr, CString, OsStr},
+    path::{Path, PathBuf},
     slice,
+    sync::OnceLock,
 };
+#[cfg(target_os = "linux")]
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    mem::ManuallyDrop,
+    os::unix::io::FromRawFd,
+};
+
+use bpf_compatible_rs::{
+    archive::{BtfEncoding, BtfEntryInfo, BtfhubArchive},
+    btf::{check_btf_file, validate_btf_bytes},
+    cache::BtfCache,
+    chain::{Strategy, DEFAULT_CHAIN},
+    compression::ArchiveFormat,
+    container::detect_container,
+    current_kernel_release,
+    diagnose::{diagnose_with, DiagnoseOptions},
+    directory::BtfDirectory,
+    identity::{archive_identity, archive_key},
+    kconfig::kernel_btf_config_of,
+    mapped::ArchiveFile,
+    parsed::ParsedArchive,
+    section::{read_self_section, BTF_SECTION_NAME},
+    shared::store_shared,
+    tarball::{write_btf_to, ExtractOptions},
+    BtfSource, MatchInfo, NativeBtfStatus, SystemInfo, TarballBtfArchive,
+};
+use extract::{BtfSink, TarSource};
+#[cfg(target_os = "linux")]
+use libc::ESPIPE;
+use libc::{
+    c_void, E2BIG, EACCES, EBADF, EBADMSG, EBUSY, ECANCELED, EEXIST, EFBIG, EILSEQ, EINVAL, EIO,
+    ELOOP, ENOENT, ENOEXEC, ENOMEM, ENOTRECOVERABLE, ENOTSUP, EOVERFLOW, EROFS,
+};
+use memfd::BtfMemfd;
+use opts::{BpfCompatOpts, Options};
+use platform::{OsStrExt, EMEDIUMTYPE, ENOKEY, ENOPKG, ESTALE};
+use temp::BtfTempfile;
+
+#[macro_use]
+mod log;
+#[macro_use]
+mod last_error;
+mod alloc;
+mod extract;
+mod info;
+mod memfd;
+mod memo;
+mod open_opts;
+mod platform;
+mod sized;
+mod temp;
+
+/// Options struct of the C API
+pub mod opts;
+
+/// `struct bpf_compat_match_info` of the C API
+pub mod match_info;
 
-use bpf_compatible_rs::{generate_current_system_btf_archive_path, tar::Archive};
-/// flate2::read 在读数据流上进行操作，包括各种格式的编码器和解码器
-/// GzDecoder 针对 gzip文件中单个成员的解码器
-/// 此结构对外暴露了一个读的接口，可以通过底层的读取器消费压缩的数据，也可以获取解压的数据
-use flate2::read::GzDecoder;
-use libc::{c_void, malloc, EILSEQ, EINVAL, EIO, ENOENT, ENOMEM};
+/// `struct bpf_compat_system_info` of the C API
+pub mod system_info;
 
-/// 包含 btf 信息的 vmlinux 地址
-const VMLINUX_BTF_PATH: &str = "/sys/kernel/btf/vmlinux";
+/// `struct bpf_compat_source` of the C API
+pub mod source;
 
+/// `struct bpf_compat_attempt` of the C API, and the strategies of `bpf_compat_opts`
+pub mod chain;
+
+/// `struct bpf_compat_gc_report` of the C API
+pub mod gc;
+
+/// 设置该环境变量（非空）后忽略 opts 中的 use_cache，不读写持久化缓存
+const NO_CACHE_ENV: &str = "BPF_COMPATIBLE_NO_CACHE";
+/// 设置该环境变量（非空）后直接使用其指向的 btf 文件，不再查找归档
+const BTF_PATH_ENV: &str = bpf_compatible_rs::diagnose::BTF_PATH_ENV;
+/// 设置该环境变量（非空）后，归档中没有对应的 btf 时从 btfhub-archive 下载，同 opts 中的 allow_download
+const DOWNLOAD_ENV: &str = "BPF_COMPATIBLE_DOWNLOAD";
+/// 设置该环境变量（非空）后，归档中没有对应的 btf 时用 pahole 从 vmlinux 生成，同 opts 中的 allow_pahole
+const PAHOLE_ENV: &str = "BPF_COMPATIBLE_PAHOLE";
+/// 设置该环境变量（非空）后，同 opts 中的 share_extracted，解压到以内核版本和内容命名的共享文件
+const SHARED_ENV: &str = "BPF_COMPATIBLE_SHARED";
+/// 设置该环境变量（非空）后，同 opts 中的 strict_coverage，归档的清单表明不支持该系统时直接失败
+const STRICT_COVERAGE_ENV: &str = "BPF_COMPATIBLE_STRICT_COVERAGE";
+/// 下载 btf 的 url 模板，opts 中的 download_url 优先
+#[cfg(feature = "download")]
+const DOWNLOAD_URL_ENV: &str = "BPF_COMPATIBLE_DOWNLOAD_URL";
+/// 内核导出 btf 的 sysfs 目录
+const SYS_KERNEL_BTF_DIR: &str = "/sys/kernel/btf";
+/// 最小的 gzip 文件大小：10 字节头部加 8 字节尾部
+const MIN_GZIP_SIZE: usize = 18;
+
+/// Extract the btf of the running kernel from a tar archive, if the kernel has no native btf
+///
+/// On success `*path` is set to a malloc'd string holding the path of the extracted btf;
+/// it should be released with `clean_core_btf_rs`
 #[no_mangle]
 pub extern "C" fn ensure_core_btf_with_tar_binary(
     path: *mut *const c_char,
     tar_bin: *const u8,
     tar_len: c_int,
 ) -> c_int {
-    // 判断当系统是否具备 btf 文件生成的条件
-    if PathBuf::from(VMLINUX_BTF_PATH).exists() {
-        return 0;
-    }
+    last_error::track(|| match c_int_len(tar_len) {
+        Ok(v) => ensure_core_btf_with_tar_binary2(path, tar_bin, v),
+        Err(e) => e,
+    })
+}
 
-    // 创建指向原始内存的切片，在原始内存上进行安全有效的操作（slice）
-    let tar_bytes = unsafe { slice::from_raw_parts(tar_bin, tar_len as usize) };
-    let decompressed_bytes = {
-        let mut val = vec![];
-        // 从给定的读取器创建一个新的解码器，立即解析gzip 的 header 信息
-        let mut gzip_reader = GzDecoder::new(tar_bytes);
-        // read_to_end 方法读取所有的字节，直到 EOF 标识，并将他们放入缓冲区
-        if let Err(e) = gzip_reader.read_to_end(&mut val) {
-            eprintln!("Failed to decompress: {}", e);
-            return -EINVAL;
-        }
-        val
-    };
+/// Same as `ensure_core_btf_with_tar_binary`, but takes the length as a `size_t`, so archives
+/// larger than 2 GiB can be passed
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_binary2(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+) -> c_int {
+    last_error::track(|| {
+        // 在任何 unsafe 操作之前检查参数，再创建指向原始内存的切片，在原始内存上进行安全有效的操作（slice）
+        let tar_bytes = match check_args(path.is_null(), tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        without_status(ensure_core_btf(
+            path,
+            TarSource::Bytes(tar_bytes),
+            &Options::default(),
+        ))
+    })
+}
 
-    // new() 创建一个新的存档，并将底层对象作为读取器
-    let mut tar = Archive::new(&decompressed_bytes[..]);
-    // 捕获当前系统信息，生成与 min_core_btf.tar.o 中 btf 存档路径相同的路径字符串
-    // 最终效果：./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf
-    let local_btf_path =
-        PathBuf::from("./btfhub-archive").join(match generate_current_system_btf_archive_path() {
+/// Same as `ensure_core_btf_with_tar_binary2`, but for the given system instead of the running one
+///
+/// Each of `distro`, `version`, `arch` and `kernel_release` (`ID` and `VERSION_ID` of
+/// os-release, and the machine and release reported by uname) may be NULL to use the
+/// value of the running system. Useful where os-release describes a container image
+/// rather than the host, or to check an archive against other machines. The kernel's
+/// native btf is only used if `kernel_release` is NULL or that of the running kernel.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_for_system(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+    distro: *const c_char,
+    version: *const c_char,
+    arch: *const c_char,
+    kernel_release: *const c_char,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null(), tar_bin, tar_len) {
             Ok(v) => v,
-            Err(e) => {
-                eprintln!("Failed to generate running kernel btf path: {:?}", e);
-                return -ENOENT;
+            Err(e) => return e,
+        };
+        let system = match system_info_with_overrides([distro, version, arch, kernel_release]) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let opts = Options {
+            system,
+            ..Options::default()
+        };
+        without_status(ensure_core_btf(path, TarSource::Bytes(tar_bytes), &opts))
+    })
+}
+
+/// The running system with `distro`, `version`, `arch` and `kernel_release` replaced by the non-NULL ones
+///
+/// `None` if all of them are NULL, so the lookup is exactly the one of the running system
+fn system_info_with_overrides(overrides: [*const c_char; 4]) -> Result<Option<SystemInfo>, c_int> {
+    if overrides.iter().all(|v| v.is_null()) {
+        return Ok(None);
+    }
+    let mut values = [None; 4];
+    for (value, ptr) in values.iter_mut().zip(overrides) {
+        if ptr.is_null() {
+            continue;
+        }
+        match unsafe { CStr::from_ptr(ptr) }.to_str() {
+            Ok(v) => *value = Some(v),
+            Err(_) => {
+                report!("The system identity must be UTF-8");
+                return Err(-EINVAL);
             }
-        });
-    // 针对 Archive 存档的条目，构建一个迭代器
-    // 迭代器中的每一个条目必须按照顺序处理，否则读取的每个条目的内容可能被破坏
-    let entries = match tar.entries() {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("Failed to read entries in the tar: {}", e);
-            return -EINVAL;
         }
+    }
+    // 全部指定时无需读取当前系统信息，便于在任意机器上检查归档
+    let mut info = if values.iter().all(Option::is_some) {
+        SystemInfo::default()
+    } else {
+        SystemInfo::detect().map_err(|e| {
+            report!("Failed to gather the running system: {}", e);
+            extract::detect_errno(&e)
+        })?
     };
-    let mut btf_path = None;
-    for entry in entries {
-        let entry = match entry {
+    // 发行版或版本被替换后，当前系统的代号不再适用
+    if values[0].is_some() || values[1].is_some() {
+        info.version_codename.clear();
+    }
+    let fields = [
+        &mut info.distro_id,
+        &mut info.version_id,
+        &mut info.arch,
+        &mut info.kernel_release,
+    ];
+    for (field, value) in fields.into_iter().zip(values) {
+        if let Some(value) = value {
+            *field = value.to_string();
+        }
+    }
+    Ok(Some(info))
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, with the behavior tuned by `opts` (NULL for defaults)
+///
+/// If `opts` sets an allocator, the returned path must be released with `clean_core_btf_opts`
+/// and the same `opts`
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_binary_opts(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null(), tar_bin, tar_len) {
             Ok(v) => v,
-            Err(e) => {
-                eprintln!("Failed to read entry: {}", e);
-                return -EIO;
-            }
+            Err(e) => return e,
+        };
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        without_status(ensure_core_btf(path, TarSource::Bytes(tar_bytes), &opts))
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary_opts`, also describing the btf it settled on in `*info`
+///
+/// `info->sz` must be set by the caller; `*info` is only written on success, with the
+/// entry of the archive, whether it's of the exact kernel release and where the btf came
+/// from, `BPF_COMPAT_SOURCE_NATIVE` if the kernel has native btf.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_binary_match(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+    opts: *const BpfCompatOpts,
+    info: *mut match_info::BpfCompatMatchInfo,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null() || info.is_null(), tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        // 先检查结构体大小，避免解压之后才失败
+        let sz = match sized::declared_size(info) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let ret = without_status(ensure_core_btf(path, TarSource::Bytes(tar_bytes), &opts));
+        if ret == 0 {
+            match_info::write_match(info, sz);
+        }
+        ret
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary_opts`, also storing the size of the btf in `*size` unless it's NULL
+///
+/// The size is that of the file `*path` names, i.e. of the btf as written, after
+/// decompressing a `.btf.gz` or `.btf.tar.xz` entry, not the size of the entry in the
+/// archive. If the kernel has native btf, it's 0 as `*path` is NULL, or with `always_path`
+/// the size of `/sys/kernel/btf/vmlinux`. `*size` is only written on success.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_binary_sized(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+    opts: *const BpfCompatOpts,
+    size: *mut usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null(), tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
         };
-        // path of a entry looks like `./btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf`
-        // entry.header() 返回归档条目的头部信息，提供了对归档条目元数据的访问
-        // entry.header().path() 返回存储在头部信息中原始的路径名，如果路径名不是 unicode 编码或者在 windows 平台上，将不可用。该方法将会转 \ 字符为目录分割符
-        let path = match entry.header().path() {
+        let opts = match Options::from_raw(opts) {
             Ok(v) => v,
+            Err(e) => return e,
+        };
+        let ret = without_status(ensure_core_btf(path, TarSource::Bytes(tar_bytes), &opts));
+        if ret != 0 || size.is_null() {
+            return ret;
+        }
+        let written = unsafe { *path };
+        if written.is_null() {
+            unsafe { *size = 0 };
+            return 0;
+        }
+        // memfd 的 /proc/self/fd/<fd> 路径同样指向其内容
+        let file = OsStr::from_bytes(unsafe { CStr::from_ptr(written) }.to_bytes());
+        match std::fs::metadata(file) {
+            Ok(v) => {
+                unsafe { *size = v.len() as usize };
+                0
+            }
             Err(e) => {
-                eprintln!("Failed to read path name: {}", e);
-                return -EILSEQ;
+                report!(
+                    "Failed to get the size of {}: {}",
+                    Path::new(file).display(),
+                    e
+                );
+                clean_core_btf(written as *mut c_char, &opts);
+                unsafe { *path = std::ptr::null() };
+                -e.raw_os_error().unwrap_or(EIO)
             }
+        }
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, but stores the btf in a sealed memfd instead of a temporary file
+///
+/// `*path` is set to `/proc/self/fd/<fd>`, which stays valid until `clean_core_btf_rs`
+/// closes the memfd. Nothing is left on disk even if the process crashes.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_binary_memfd(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null(), tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let opts = Options {
+            use_memfd: true,
+            ..Default::default()
         };
+        without_status(ensure_core_btf(path, TarSource::Bytes(tar_bytes), &opts))
+    })
+}
 
-        // 根据当前系统生成的 BTF 存档路径信息 同 btfhub-archive 存档的 btf 文件地址比对，检索出使用与当前系统的 btf 文件
-        if path == local_btf_path {
-            let mut temp_file = match mkstemp::TempFile::new("/tmp/eunomia.btf.XXXXXX", false) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("Failed to create a tempfile to store the btf: {}", e);
-                    return -EIO;
-                }
-            };
-            // 返回归档条目文件开始的位置，以字节为单位
-            // 如果条目文件是连续的，且底层读写器实现了 Seek，则从 header_pos 到 header_pos + 512 的字节包含头部信息
-            // 此处是将该条目拷贝到 file_bytes 缓冲区
-            let file_bytes = &decompressed_bytes[entry.raw_file_position() as usize
-                ..(entry.raw_file_position() + entry.size()) as usize];
-            // 将 btf 文件保存到临时文件
-            if let Err(e) = temp_file.write_all(file_bytes) {
-                eprintln!("Failed to write btf things to the tempfile: {}", e);
-                return -EIO;
-            }
-            btf_path = Some(temp_file.path().to_string());
+/// Same as `ensure_core_btf_with_tar_binary`, but creates the temporary file under `tmpdir`
+///
+/// `tmpdir` is created with mode 0700 if it doesn't exist; NULL falls back to `$TMPDIR`
+/// (or `/tmp`). Failing to create the file is reported with its errno, e.g. `-EACCES`.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_binary_tmpdir(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+    tmpdir: *const c_char,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null(), tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let opts = Options {
+            tmpdir: (!tmpdir.is_null())
+                .then(|| OsStr::from_bytes(unsafe { CStr::from_ptr(tmpdir) }.to_bytes()).into()),
+            ..Default::default()
+        };
+        without_status(ensure_core_btf(path, TarSource::Bytes(tar_bytes), &opts))
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, but tells whether a custom btf is needed through the return value
+///
+/// Returns `BPF_COMPAT_NATIVE_BTF` (1) with `*path` set to NULL if the kernel has native
+/// btf, `BPF_COMPAT_CUSTOM_BTF` (0) with `*path` set to the extracted btf, or a negative
+/// errno on failure, in which case `*path` is NULL too
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_binary_status(
+    path: *mut *const c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null(), tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        ensure_core_btf(path, TarSource::Bytes(tar_bytes), &Options::default())
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary_status`, but uses the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_linked_tar_status(path: *mut *const c_char) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
         }
-    }
+        // 未链接归档时不检查归档参数，内核自带 btf 的情况下仍应成功
+        ensure_core_btf(
+            path,
+            TarSource::Bytes(linked_archive_bytes().unwrap_or_default()),
+            &Options::default(),
+        )
+    })
+}
 
-    // 获取btf文件的地址
-    let btf_path = match btf_path {
-        Some(v) => v,
-        None => {
-            eprintln!("Failed to find the btf archive matching the running kernel");
-            return -ENOENT;
+/// Write a raw btf blob to a temporary file, for deployments built for a single known kernel
+///
+/// No archive is involved. Returns `BPF_COMPAT_NATIVE_BTF` with `*path` set to NULL if the
+/// kernel has native btf, `BPF_COMPAT_CUSTOM_BTF` with `*path` set to the written btf, or
+/// a negative errno; a buffer that isn't a btf is rejected with `-EILSEQ`
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_raw_btf(
+    path: *mut *const c_char,
+    btf: *const u8,
+    len: usize,
+) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        if btf.is_null() || isize::try_from(len).is_err() {
+            report!("Invalid btf buffer");
+            return -EINVAL;
         }
+        let btf = unsafe { slice::from_raw_parts(btf, len) };
+        ensure_raw_btf(path, btf, &Options::default())
+    })
+}
+
+/// `ensure_core_btf_with_raw_btf` once the arguments are checked
+fn ensure_raw_btf(path: *mut *const c_char, btf: &[u8], opts: &Options) -> c_int {
+    unsafe { *path = std::ptr::null() };
+    if has_native_btf(opts) {
+        record_resolution("native", None, 0);
+        return BPF_COMPAT_NATIVE_BTF;
+    }
+    if let Err(e) = validate_btf_bytes(btf) {
+        report!("The buffer doesn't hold a btf: {}", e);
+        return -EILSEQ;
+    }
+    let ret = write_raw_btf(path, btf, opts);
+    record_resolution(
+        "raw",
+        (ret == 0).then(|| unsafe { CStr::from_ptr(*path) }.to_string_lossy()),
+        ret,
+    );
+    ret
+}
+
+fn write_raw_btf(path: *mut *const c_char, btf: &[u8], opts: &Options) -> c_int {
+    let mut btf_file = match BtfTempfile::create(opts.tmpdir.as_deref(), &opts.tempfile_template) {
+        Ok(v) => v,
+        Err(e) => return e,
     };
-    let btf_path_bytes = btf_path.as_bytes();
-    // The buffer will be passed to C program, so allocate it with malloc
-    // 缓冲区将传递个C程序，所有用 malloc 初始化了一个内存空间。
-    let holder = unsafe { malloc(btf_path_bytes.len() + 1) } as *mut u8;
-    if holder.is_null() {
-        eprintln!("Unable to allocate a buffer for c string");
-        return -ENOMEM;
+    if let Err(e) = btf_file.overwrite_from(&mut &btf[..]) {
+        return e;
     }
-    // 将 holder 封装成一个安全的内存切片
-    let holder_slice = unsafe { slice::from_raw_parts_mut(holder, btf_path_bytes.len() + 1) };
-    // 将 btf 文件的路径信息以切片的方式拷贝到 holder_slice 中
-    holder_slice[..btf_path_bytes.len()].copy_from_slice(btf_path_bytes);
-    // C-Strings require a trailing zero
-    // C 字符创的最后一个字符是以 0 结尾的
-    holder_slice[btf_path_bytes.len()] = 0;
-    // 完成了 btf 文件信息赋值给 path 指针
-    *unsafe { &mut *path } = holder as *const c_char;
-    0
+    let ret = return_path(path, btf_file.path().to_bytes(), opts);
+    if ret == 0 {
+        btf_file.keep();
+    }
+    ret
 }
 
-extern "C" {
-    static _binary_min_core_btfs_tar_gz_start: c_char;
-    static _binary_min_core_btfs_tar_gz_end: c_char;
+/// The message of the last failed call on the calling thread, or NULL if that call succeeded
+///
+/// Every function returning an `int` status records the message it prints to stderr when
+/// it fails, e.g. with the path or entry involved, for callers that don't see stderr.
+/// The string belongs to the library and stays valid until the next such call on the same
+/// thread; copy it to keep it longer.
+#[no_mangle]
+pub extern "C" fn bpf_compatible_last_error() -> *const c_char {
+    last_error::as_ptr()
 }
 
+/// Level of the messages of failures, see `bpf_compatible_set_log_fn`
+pub const BPF_COMPAT_LOG_ERROR: c_int = 0;
+/// Level of the messages that don't make a call fail, e.g. a fallback to another btf
+pub const BPF_COMPAT_LOG_INFO: c_int = 1;
+/// Level of the details of a lookup, e.g. the entry that matched
+pub const BPF_COMPAT_LOG_DEBUG: c_int = 2;
+
+/// Route the diagnostics of the library to `log_fn` instead of stderr
 ///
+/// `log_fn` gets the level (`BPF_COMPAT_LOG_*`), the message, only valid during the call,
+/// and `ctx` as given here. It may be called from any thread the library is used on, and
+/// must not call back into the library. It can be replaced at any time: once this returns,
+/// the previous callback is no longer running on any thread. Passing NULL restores the
+/// default, which prints errors and infos to stderr and drops debug messages.
 #[no_mangle]
-pub extern "C" fn ensure_core_btf_with_linked_tar(path: *mut *const c_char) -> c_int {
-    /*
-        通过 bpftool gen min_core_btf 命令，根据 epbf 生成的.o 目标文件，生成 btfhub-archive
-        归档的所有厂商 btf 的精简 btf，将所有的 btf 文件打包成 min_core_btfs.tar.gz
+pub extern "C" fn bpf_compatible_set_log_fn(log_fn: Option<log::LogFn>, ctx: *mut c_void) {
+    log::set_logger(log_fn, ctx);
+}
 
-        ld -r -b binary min_core_btfs.tar.gz -o min_core_btfs_tar.o 生成的静态链接文件 .o
+/// Allocate every buffer returned to the caller with `alloc`, to be released with `dealloc`
+///
+/// Applies to path strings, btf buffers and string arrays, and is what the library's own
+/// release functions (`clean_core_btf_rs`, `bpf_compatible_free_buffer`, ...) call. The
+/// allocator is fixed by the first allocation: returns 0, or `-EBUSY` if a buffer was
+/// already allocated or another allocator set, and `-EINVAL` if either function is NULL.
+/// `alloc` and `dealloc` in `struct bpf_compat_opts` still take precedence for that call.
+#[no_mangle]
+pub extern "C" fn bpf_compatible_set_allocator(
+    alloc: Option<opts::AllocFn>,
+    dealloc: Option<opts::FreeFn>,
+) -> c_int {
+    last_error::track(|| match (alloc, dealloc) {
+        (Some(alloc), Some(dealloc)) => alloc::set_allocator(alloc, dealloc),
+        _ => {
+            report!("Both the allocation and deallocation functions must be given");
+            -EINVAL
+        }
+    })
+}
 
-        最终通过 clang <your_program> libbpf_compatible.a min_core_btf.tar.o 生成可执行的
-        二进制文件，其中 min_core_btf.tar.o 链接中定义了 _binary_min_core_btfs_tar_gz_end
-        和 _binary_min_core_btfs_tar_gz_start 为嵌入的 tar.gz 文件的范围。
-    */
-    let len = unsafe {
-        &_binary_min_core_btfs_tar_gz_end as *const c_char as usize
-            - &_binary_min_core_btfs_tar_gz_start as *const c_char as usize
-    };
-    ensure_core_btf_with_tar_binary(
-        path,
-        unsafe { &_binary_min_core_btfs_tar_gz_start as *const c_char } as *const u8,
-        len as c_int,
-    )
+/// What each status returned by this library means, NUL-terminated for `bpf_compatible_strerror`
+const STATUS_DESCRIPTIONS: &[(c_int, &str)] = &[
+    (
+        0,
+        "success: the btf was extracted (for core_btf_is_available: no btf is available; for clean_core_btf_rs2: only the path string was freed)\0",
+    ),
+    (
+        BPF_COMPAT_NATIVE_BTF,
+        "success: the kernel has native btf, no file is needed (for clean_core_btf_rs2: the btf file was removed)\0",
+    ),
+    (
+        BPF_COMPAT_ARCHIVE_BTF,
+        "success: the archive has a btf for the kernel\0",
+    ),
+    (
+        -EINVAL,
+        "invalid argument, or the archive is corrupt\0",
+    ),
+    (
+        -ENOENT,
+        "no btf for the running kernel in the archive, or a file was not found\0",
+    ),
+    (-EIO, "failed to read the archive or write the btf\0"),
+    (
+        -EILSEQ,
+        "the matching entry of the archive has an unreadable path, or holds no valid btf\0",
+    ),
+    (-ENOMEM, "out of memory\0"),
+    (
+        -EACCES,
+        "permission denied reading the archive, or creating or removing the btf file\0",
+    ),
+    (-EBADF, "the file descriptor is not open\0"),
+    (
+        -E2BIG,
+        "struct bpf_compat_opts sets fields this version of the library doesn't know\0",
+    ),
+    (
+        -EEXIST,
+        "the destination file exists and overwriting it wasn't asked for\0",
+    ),
+    (
+        -EROFS,
+        "the btf file can't be created or removed on a read-only file system\0",
+    ),
+    (
+        -EOVERFLOW,
+        "the btf is too large for its size to be returned\0",
+    ),
+    (
+        -ENOEXEC,
+        "the only matching btf is of the other byte order, or another architecture, than the system's\0",
+    ),
+    (
+        -ENOTSUP,
+        "the archive is compressed in a format this build can't decompress, or btfs aren't looked up on this platform\0",
+    ),
+    (
+        -ESTALE,
+        "the archive file changed while it was being read (for clean_core_btf_opts: the btf file was replaced by another)\0",
+    ),
+    (-ELOOP, "too many levels of links in the archive\0"),
+    (
+        -EBUSY,
+        "the allocator can't be replaced once a buffer was allocated\0",
+    ),
+    (
+        -ENOKEY,
+        "verification is required, but no manifest of the archive covers the btf\0",
+    ),
+    (
+        -EBADMSG,
+        "the btf doesn't match its digest in the manifest\0",
+    ),
+    (
+        -EFBIG,
+        "the archive or the btf decompresses to more than max_decompressed_size\0",
+    ),
+    (
+        -ECANCELED,
+        "the lookup was cancelled by the progress callback\0",
+    ),
+    (
+        -ENOPKG,
+        "the archive has no btfs for the distro, version or architecture of the system (strict_coverage)\0",
+    ),
+    (
+        -EMEDIUMTYPE,
+        "the archive holds no btfhub-archive directory nor btfs at its root: it isn't a btf archive\0",
+    ),
+    (
+        -ENOTRECOVERABLE,
+        "an internal error of this library, which is a bug to report\0",
+    ),
+];
+
+/// A static description of a status returned by this library, in its own terms
+///
+/// E.g. for `-EILSEQ`, that the matching entry of the archive has an unreadable path or holds no
+/// valid btf, rather than the generic text of `strerror`. Values this library doesn't
+/// return give `unknown bpf-compatible error`; the result is never NULL and must not be freed.
+#[no_mangle]
+pub extern "C" fn bpf_compatible_strerror(err: c_int) -> *const c_char {
+    STATUS_DESCRIPTIONS
+        .iter()
+        .find(|(code, _)| *code == err)
+        .map_or("unknown bpf-compatible error\0", |(_, v)| v)
+        .as_ptr() as *const c_char
 }
 
+/// Returned by the `_status` functions when the kernel has native btf
+pub const BPF_COMPAT_NATIVE_BTF: c_int = 1;
+/// Returned by the `_status` functions when a custom btf was extracted
+pub const BPF_COMPAT_CUSTOM_BTF: c_int = 0;
+
+/// `source` of `struct bpf_compat_match_info`: the kernel's native btf
+pub const BPF_COMPAT_SOURCE_NATIVE: c_int = 1;
+/// `source` of `struct bpf_compat_match_info`: a btf installed for the kernel, e.g. `/boot/vmlinux-<release>`
+pub const BPF_COMPAT_SOURCE_INSTALLED: c_int = 2;
+/// `source` of `struct bpf_compat_match_info`: an entry of the archive
+pub const BPF_COMPAT_SOURCE_ARCHIVE: c_int = 3;
+/// `source` of `struct bpf_compat_match_info`: a btf extracted or downloaded by an earlier call, from the persistent cache
+pub const BPF_COMPAT_SOURCE_CACHE: c_int = 4;
+/// `source` of `struct bpf_compat_match_info`: a btf downloaded from btfhub-archive
+pub const BPF_COMPAT_SOURCE_DOWNLOAD: c_int = 5;
+/// `source` of `struct bpf_compat_match_info`: the file named by `BPF_COMPATIBLE_BTF_PATH`
+pub const BPF_COMPAT_SOURCE_OVERRIDE: c_int = 6;
+/// `source` of `struct bpf_compat_match_info`: a btf generated from the vmlinux of the kernel with pahole
+pub const BPF_COMPAT_SOURCE_PAHOLE: c_int = 7;
+
+/// `native_status` of `struct bpf_compat_match_info`: the native btf wasn't looked at
+pub const BPF_COMPAT_NATIVE_STATUS_UNKNOWN: c_int = 0;
+/// `native_status` of `struct bpf_compat_match_info`: the native btf was used
+pub const BPF_COMPAT_NATIVE_STATUS_USABLE: c_int = 1;
+/// `native_status` of `struct bpf_compat_match_info`: the kernel exports no btf
+pub const BPF_COMPAT_NATIVE_STATUS_MISSING: c_int = 2;
+/// `native_status` of `struct bpf_compat_match_info`: the native btf is there but unreadable or invalid
+pub const BPF_COMPAT_NATIVE_STATUS_UNUSABLE: c_int = 3;
+/// `native_status` of `struct bpf_compat_match_info`: the native btf isn't of the kernel looked up
+pub const BPF_COMPAT_NATIVE_STATUS_IGNORED: c_int = 4;
+
+/// Strategy of `bpf_compat_opts.strategies`: the kernel's native btf
+pub const BPF_COMPAT_STRATEGY_NATIVE: c_int = 1;
+/// Strategy of `bpf_compat_opts.strategies`: the file named by `BPF_COMPATIBLE_BTF_PATH`
+pub const BPF_COMPAT_STRATEGY_OVERRIDE: c_int = 2;
+/// Strategy of `bpf_compat_opts.strategies`: a btf installed for the kernel, e.g. `/boot/vmlinux-<release>`
+pub const BPF_COMPAT_STRATEGY_INSTALLED: c_int = 3;
+/// Strategy of `bpf_compat_opts.strategies`: the btf an earlier call kept in the persistent cache for the archive
+pub const BPF_COMPAT_STRATEGY_CACHE: c_int = 4;
+/// Strategy of `bpf_compat_opts.strategies`: the archive the function is given
+pub const BPF_COMPAT_STRATEGY_ARCHIVE: c_int = 5;
+/// Strategy of `bpf_compat_opts.strategies`: the unpacked btfhub-archive at `archive_dir`
+pub const BPF_COMPAT_STRATEGY_ARCHIVE_DIR: c_int = 6;
+/// Strategy of `bpf_compat_opts.strategies`: downloading the btf, needs the `download` feature
+pub const BPF_COMPAT_STRATEGY_DOWNLOAD: c_int = 7;
+/// Strategy of `bpf_compat_opts.strategies`: generating the btf with pahole, needs the `pahole` feature
+pub const BPF_COMPAT_STRATEGY_PAHOLE: c_int = 8;
+
+/// `outcome` of `struct bpf_compat_attempt`: the strategy found the btf
+pub const BPF_COMPAT_OUTCOME_HIT: c_int = 1;
+/// `outcome` of `struct bpf_compat_attempt`: the strategy has no btf for the kernel
+pub const BPF_COMPAT_OUTCOME_MISS: c_int = 2;
+/// `outcome` of `struct bpf_compat_attempt`: the strategy failed, ending the lookup
+pub const BPF_COMPAT_OUTCOME_ERROR: c_int = 3;
+
+/// Describe in `attempts`, of `n` entries, the strategies the last lookup of the thread tried
+///
+/// Returns how many strategies were tried, which may be more than `n`, in which case only
+/// the first `n` are written; `attempts` may be NULL to get the count. The strategies after
+/// the one ending the lookup aren't tried, so they're missing.
 #[no_mangle]
-pub extern "C" fn clean_core_btf_rs(path: *mut c_char) {
-    if path.is_null() {
-        return;
+pub extern "C" fn bpf_compatible_last_attempts(
+    attempts: *mut chain::BpfCompatAttempt,
+    n: usize,
+) -> usize {
+    chain::write_attempts(attempts, n)
+}
+
+/// Convert an archive length passed as `int`, rejecting negative values
+fn c_int_len(tar_len: c_int) -> Result<usize, c_int> {
+    usize::try_from(tar_len).map_err(|_| {
+        report!("Invalid length of the tar archive: {}", tar_len);
+        -EINVAL
+    })
+}
+
+/// Validate the arguments of an entry point, before any of them is dereferenced
+///
+/// `out_is_null` tells whether any of the output pointers is NULL. The archive must be
+/// non-NULL and at least as long as an empty gzip file.
+fn check_args<'a>(
+    out_is_null: bool,
+    tar_bin: *const u8,
+    tar_len: usize,
+) -> Result<&'a [u8], c_int> {
+    if out_is_null {
+        report!("The output pointer is NULL");
+        return Err(-EINVAL);
+    }
+    if tar_bin.is_null() {
+        report!("The tar archive is NULL");
+        return Err(-EINVAL);
     }
-    let path_buf = PathBuf::from(
-        unsafe { CStr::from_ptr(path) }
-            .to_string_lossy()
-            .to_string(),
+    if tar_len < MIN_GZIP_SIZE {
+        report!(
+            "The tar archive is truncated: {} bytes is smaller than a gzip header",
+            tar_len
+        );
+        return Err(-EINVAL);
+    }
+    // 切片长度不能超过 isize::MAX，更长的长度只可能是把负数当作 size_t 传入
+    if isize::try_from(tar_len).is_err() {
+        report!("Invalid length of the tar archive: {}", tar_len);
+        return Err(-EINVAL);
+    }
+    Ok(unsafe { slice::from_raw_parts(tar_bin, tar_len) })
+}
+
+/// Map the status of `ensure_core_btf` to the older convention, where 0 covers both success cases
+fn without_status(ret: c_int) -> c_int {
+    if ret == BPF_COMPAT_NATIVE_BTF {
+        BPF_COMPAT_CUSTOM_BTF
+    } else {
+        ret
+    }
+}
+
+/// Returns `BPF_COMPAT_NATIVE_BTF`, `BPF_COMPAT_CUSTOM_BTF` or a negative errno
+fn ensure_core_btf(path: *mut *const c_char, source: TarSource, opts: &Options) -> c_int {
+    resolve_core_btf(path, opts, "archive", Some(source), |path| {
+        extract_btf_from_archive(path, source, opts)
+    })
+}
+
+/// The resolution of `ensure_core_btf`, trying the strategies of `opts.chain` in order
+///
+/// `lookup` is run for `BPF_COMPAT_STRATEGY_ARCHIVE`, and `source` names where it finds the
+/// btf, in the audit log; `archive`, if it's a single one, is that of the cache strategy.
+/// A strategy that finds nothing, returning `-ENOENT`, is a miss and the next one is
+/// tried; any other failure ends the chain. `-ENOENT` if all miss.
+fn resolve_core_btf(
+    path: *mut *const c_char,
+    opts: &Options,
+    source: &str,
+    archive: Option<TarSource>,
+    mut lookup: impl FnMut(*mut *const c_char) -> c_int,
+) -> c_int {
+    // 无论结果如何，先将 *path 置空，避免调用者未初始化指针时把垃圾值传给 libbpf
+    unsafe { *path = std::ptr::null() };
+    match_info::clear();
+    chain::clear();
+    let strategies = opts.chain.clone().unwrap_or_else(|| default_chain(opts));
+    for strategy in &strategies {
+        let ret = match strategy {
+            Strategy::EnvOverride => override_strategy(path, opts),
+            Strategy::Native => native_strategy(path, opts),
+            Strategy::Installed => installed_strategy(path, opts),
+            Strategy::Cache => archive.and_then(|v| cached_btf(path, v, opts)),
+            Strategy::EmbeddedArchive => {
+                note_container_without_sysfs();
+                Some(lookup(path)).filter(|v| *v != -ENOENT)
+            }
+            Strategy::ArchiveDir(dir) => {
+                Some(btf_from_dir(path, dir, opts)).filter(|v| *v != -ENOENT)
+            }
+            Strategy::Pahole => pahole_core_btf(path, opts),
+            Strategy::Download => download_core_btf(path, opts),
+        };
+        chain::record(strategy, ret);
+        let Some(ret) = ret else {
+            continue;
+        };
+        let name = match strategy {
+            Strategy::EmbeddedArchive => source,
+            v => v.name(),
+        };
+        record_resolution(
+            name,
+            (ret == 0).then(|| unsafe { CStr::from_ptr(*path) }.to_string_lossy()),
+            ret,
+        );
+        return ret;
+    }
+    // 未命中的策略通常已说明原因（如归档中找不到该内核），不覆盖它
+    if last_error::get().is_none() {
+        report!(
+            "None of the strategies {} has a btf for the kernel",
+            strategies
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    record_resolution(source, None, -ENOENT);
+    -ENOENT
+}
+
+/// The chain tried without `bpf_compat_opts.strategies`: `DEFAULT_CHAIN`, generating and
+/// downloading the btf only if allowed
+fn default_chain(opts: &Options) -> Vec<Strategy> {
+    DEFAULT_CHAIN
+        .iter()
+        .filter(|v| match v {
+            Strategy::Pahole => pahole_allowed(opts),
+            Strategy::Download => download_allowed(opts),
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// The btf file named by `BPF_COMPATIBLE_BTF_PATH`, `None` if it isn't set
+fn override_strategy(path: *mut *const c_char, opts: &Options) -> Option<c_int> {
+    let btf_path = std::env::var_os(BTF_PATH_ENV).filter(|v| !v.is_empty())?;
+    match_info::record(MatchInfo::of_source(
+        BtfSource::Override,
+        Some(PathBuf::from(&btf_path)),
+        false,
+        kernel_release_of(opts),
+    ));
+    Some(override_btf(path, &PathBuf::from(btf_path), opts))
+}
+
+/// `BPF_COMPAT_NATIVE_BTF` if the kernel has native btf, `None` otherwise
+fn native_strategy(path: *mut *const c_char, opts: &Options) -> Option<c_int> {
+    let status = native_btf_status(opts);
+    match_info::record_native_btf(status);
+    if status != NativeBtfStatus::Usable {
+        return None;
+    }
+    // 调用者希望总能拿到一个路径时，返回内核自带 btf 的路径，clean_core_btf_rs 不会删除它
+    if opts.always_path {
+        let ret = return_cached_path(path, &opts.vmlinux_path, opts);
+        if ret != 0 {
+            return Some(ret);
+        }
+    }
+    debug!(
+        "The kernel has native btf at {}",
+        opts.vmlinux_path.display()
     );
-    if let Err(e) = std::fs::remove_file(path_buf) {
-        eprintln!("Failed to perform clean: {}", e);
+    match_info::record(MatchInfo::native(kernel_release_of(opts)));
+    Some(BPF_COMPAT_NATIVE_BTF)
+}
+
+/// The btf installed for the kernel, `None` if there's none
+fn installed_strategy(path: *mut *const c_char, opts: &Options) -> Option<c_int> {
+    // 内核未导出 btf，但发行版可能已安装了该内核的 btf（如 /boot/vmlinux-<release>），无需解压归档
+    let installed = installed_btf(opts)?;
+    debug!("Using the installed btf {}", installed.display());
+    match_info::record(MatchInfo::of_source(
+        BtfSource::Installed,
+        Some(installed.clone()),
+        true,
+        kernel_release_of(opts),
+    ));
+    Some(return_cached_path(path, &installed, opts))
+}
+
+/// Release of the kernel the btf is looked up for, empty if it can't be detected
+fn kernel_release_of(opts: &Options) -> String {
+    opts.system_info()
+        .map(|v| v.kernel_release)
+        .unwrap_or_default()
+}
+
+/// Same lookup as `ensure_core_btf_with_tar_binary`, but returns the contents of the btf instead of writing a file
+///
+/// On success `*buf` is set to a malloc'd buffer of `*len` bytes holding the btf; it should
+/// be released with `bpf_compatible_free_buffer`. If the kernel has native btf, 0 is
+/// returned with `*buf` set to NULL.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_bytes_with_tar_binary(
+    buf: *mut *mut u8,
+    len: *mut usize,
+    tar_bin: *const u8,
+    tar_len: c_int,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match c_int_len(tar_len)
+            .and_then(|v| check_args(buf.is_null() || len.is_null(), tar_bin, v))
+        {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        ensure_core_btf_bytes(buf, len, tar_bytes, &Options::default())
+    })
+}
+
+/// Same as `ensure_core_btf_bytes_with_tar_binary`, but uses the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_bytes_with_linked_tar(
+    buf: *mut *mut u8,
+    len: *mut usize,
+) -> c_int {
+    last_error::track(|| {
+        if buf.is_null() || len.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        ensure_core_btf_bytes(
+            buf,
+            len,
+            linked_archive_bytes().unwrap_or_default(),
+            &Options::default(),
+        )
+    })
+}
+
+fn ensure_core_btf_bytes(
+    buf: *mut *mut u8,
+    len: *mut usize,
+    tar_bytes: &[u8],
+    opts: &Options,
+) -> c_int {
+    unsafe {
+        *buf = std::ptr::null_mut();
+        *len = 0;
+    }
+    if has_native_btf(opts) {
+        record_resolution("native", None, 0);
+        return 0;
+    }
+    note_container_without_sysfs();
+    let ret = match extract::lookup_btf(TarSource::Bytes(tar_bytes), opts, || Ok(Vec::new())) {
+        Ok(btf) => {
+            // 至少分配 1 字节，避免 malloc(0) 返回 NULL 被误认为分配失败
+            let holder = unsafe { alloc::alloc(btf.len().max(1)) } as *mut u8;
+            if holder.is_null() {
+                report!("Unable to allocate a buffer for the btf");
+                -ENOMEM
+            } else {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(btf.as_ptr(), holder, btf.len());
+                    *buf = holder;
+                    *len = btf.len();
+                }
+                0
+            }
+        }
+        Err(e) => e,
+    };
+    record_resolution("archive", None, ret);
+    ret
+}
+
+/// Release a buffer returned by `ensure_core_btf_bytes_with_tar_binary`
+#[no_mangle]
+pub extern "C" fn bpf_compatible_free_buffer(buf: *mut u8) {
+    unsafe { alloc::free(buf as *mut c_void) };
+}
+
+/// Same lookup as `ensure_core_btf_with_tar_binary`, but writes the btf to the caller's `out_fd` instead of a file of its own
+///
+/// For a privileged helper handing over e.g. an `O_TMPFILE` descriptor, so the other side
+/// never deals with paths. The btf is written in full from the current offset of `out_fd`,
+/// which is neither closed nor seeked back. Returns the number of bytes written, 0 without
+/// writing if the kernel has native btf, or a negative errno, e.g. `-EPIPE` for a pipe
+/// whose reader is gone, once the process ignores `SIGPIPE`. Part of the btf may have been
+/// written on failure.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_write_fd(out_fd: c_int, tar: *const u8, len: usize) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(false, tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        if out_fd < 0 {
+            report!("Invalid file descriptor {}", out_fd);
+            return -EBADF;
+        }
+        let opts = Options::default();
+        if has_native_btf(&opts) {
+            record_resolution("native", None, 0);
+            return 0;
+        }
+        note_container_without_sysfs();
+        let ret = match extract::lookup_btf(TarSource::Bytes(tar_bytes), &opts, || Ok(Vec::new())) {
+            Ok(btf) => match c_int::try_from(btf.len()) {
+                Ok(size) => match write_fd(out_fd, &btf) {
+                    Ok(()) => size,
+                    Err(e) => {
+                        report!("Failed to write the btf to fd {}: {}", out_fd, e);
+                        e.raw_os_error()
+                            .map_or_else(|| extract::stream_errno(&e), |v| -v)
+                    }
+                },
+                Err(_) => {
+                    report!(
+                        "The btf is too large to return its size: {} bytes",
+                        btf.len()
+                    );
+                    -EOVERFLOW
+                }
+            },
+            Err(e) => e,
+        };
+        record_resolution("archive", None, ret.min(0));
+        ret
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary2`, writing the path into the caller's buffer instead of a malloc'd string
+///
+/// Returns the size the path needs, NUL included, like `snprintf`: the path was written
+/// if it is at most `buf_len`. If `buf` is NULL or too small, nothing is kept and the
+/// size is returned, so a second call with a large enough buffer gets the path; the
+/// lookup is done in full both times, so a buffer of `PATH_MAX` bytes saves the first
+/// call. If the kernel has native btf, 0 is returned with `buf` set to the empty string,
+/// as `ensure_core_btf_with_tar_binary2` returns 0 with `*path` set to NULL. The btf is
+/// released with `clean_core_btf_path`.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_path_buf(
+    buf: *mut c_char,
+    buf_len: usize,
+    tar_bin: *const u8,
+    tar_len: usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(false, tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let buf = (!buf.is_null() && buf_len > 0)
+            .then(|| unsafe { slice::from_raw_parts_mut(buf as *mut u8, buf_len) });
+        ensure_core_btf_path_into(buf, TarSource::Bytes(tar_bytes))
+    })
+}
+
+fn ensure_core_btf_path_into(buf: Option<&mut [u8]>, source: TarSource) -> c_int {
+    let opts = Options::default();
+    let mut btf_path: *const c_char = std::ptr::null();
+    let ret = ensure_core_btf(&mut btf_path, source, &opts);
+    if ret < 0 {
+        return ret;
+    }
+    if btf_path.is_null() {
+        if let Some(buf) = buf {
+            buf[0] = 0;
+        }
+        return 0;
+    }
+    let path_bytes = unsafe { CStr::from_ptr(btf_path) }.to_bytes_with_nul();
+    let Ok(needed) = c_int::try_from(path_bytes.len()) else {
+        clean_core_btf(btf_path as *mut c_char, &opts);
+        report!("The btf path is too long");
+        return -EINVAL;
+    };
+    match buf {
+        Some(buf) if path_bytes.len() <= buf.len() => {
+            buf[..path_bytes.len()].copy_from_slice(path_bytes);
+            // 文件留给调用者，之后由 clean_core_btf_path 清理；只释放字符串
+            memo::take_handed_out(btf_path);
+            unsafe { (opts.free)(btf_path as *mut c_void) };
+        }
+        buf => {
+            // 缓冲区不够大时不保留任何东西，避免调用者不再调用时留下临时文件
+            if let Some(buf) = buf {
+                buf[0] = 0;
+            }
+            clean_core_btf(btf_path as *mut c_char, &opts);
+        }
+    }
+    needed
+}
+
+/// Remove the btf at `path` written by `ensure_core_btf_path_buf`, leaving the string to the caller
+///
+/// As with `clean_core_btf_rs2`, only files this library created are removed. Returns
+/// `BPF_COMPAT_BTF_DELETED`, `BPF_COMPAT_PATH_FREED` if the file was left in place (there
+/// is nothing to free here), or a negative errno if it couldn't be removed.
+#[no_mangle]
+pub extern "C" fn clean_core_btf_path(path: *const c_char) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The btf path is NULL");
+            return -EINVAL;
+        }
+        remove_owned_btf(unsafe { CStr::from_ptr(path) }.to_bytes(), false)
+    })
+}
+
+/// Extract every btf of the archive that may be used for the running kernel, best first
+///
+/// For loaders that try the next btf when CO-RE relocation fails with one. On success
+/// `*paths` is set to a malloc'd NULL-terminated array of malloc'd paths, one per
+/// candidate, and the number of candidates is returned; the array should be released with
+/// `bpf_compatible_free_candidates`. The order is the one of
+/// `bpf_compatible_rs::archive::BtfhubArchive::lookup_candidates`. If the kernel has native
+/// btf, 0 is returned with `*paths` set to NULL; if there is no candidate, `-ENOENT`.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_candidates_with_tar_binary(
+    paths: *mut *mut *mut c_char,
+    tar_bin: *const u8,
+    tar_len: usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(paths.is_null(), tar_bin, tar_len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        unsafe { *paths = std::ptr::null_mut() };
+        let opts = Options::default();
+        if has_native_btf(&opts) {
+            record_resolution("native", None, 0);
+            return 0;
+        }
+        note_container_without_sysfs();
+        let ret = match extract_btf_candidates(tar_bytes, &opts) {
+            Ok(files) => return_candidate_paths(paths, files),
+            Err(e) => e,
+        };
+        record_resolution("candidates", None, ret.min(0));
+        ret
+    })
+}
+
+/// Write every candidate btf of the archive to a temporary file, best first
+fn extract_btf_candidates(tar_bytes: &[u8], opts: &Options) -> Result<Vec<BtfTempfile>, c_int> {
+    let info = opts.system_info().map_err(|e| {
+        report!("Failed to gather the running system: {}", e);
+        extract::detect_errno(&e)
+    })?;
+    let candidates = BtfhubArchive::new(tar_bytes)
+        .with_prefix(&opts.archive_prefix)
+        .with_max_decompressed_size(opts.max_decompressed_size)
+        .lookup_candidates(&info)
+        .map_err(|e| {
+            report!("{}", e);
+            extract::archive_errno(&e)
+        })?;
+    if candidates.is_empty() {
+        report!("Failed to find any btf that may be used for the running kernel");
+        return Err(-ENOENT);
+    }
+    // 先写出全部候选，任何一个失败时已写出的临时文件随 drop 删除
+    let mut files = vec![];
+    for candidate in candidates {
+        let btf = candidate.extract().map_err(|e| {
+            report!("Failed to extract `{}`: {}", candidate.path.display(), e);
+            extract::archive_errno(&e)
+        })?;
+        let mut btf_file = BtfTempfile::create(opts.tmpdir.as_deref(), &opts.tempfile_template)?;
+        btf_file.overwrite_from(&mut &btf[..])?;
+        files.push(btf_file);
+    }
+    Ok(files)
+}
+
+/// Hand the paths of `files` out to the C caller as a NULL-terminated array, returning their number
+fn return_candidate_paths(paths: *mut *mut *mut c_char, files: Vec<BtfTempfile>) -> c_int {
+    let holder = match string_array(files.iter().map(|v| v.path().to_bytes())) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let count = c_int::try_from(files.len()).unwrap_or(c_int::MAX);
+    files.into_iter().for_each(BtfTempfile::keep);
+    unsafe { *paths = holder };
+    count
+}
+
+/// A malloc'd NULL-terminated array of malloc'd copies of `strings`
+fn string_array<'a>(
+    strings: impl ExactSizeIterator<Item = &'a [u8]>,
+) -> Result<*mut *mut c_char, c_int> {
+    let holder = (strings.len() + 1)
+        .checked_mul(std::mem::size_of::<*mut c_char>())
+        .map_or(std::ptr::null_mut(), |v| unsafe { alloc::alloc(v) })
+        as *mut *mut c_char;
+    if holder.is_null() {
+        report!("Unable to allocate the array of strings");
+        return Err(-ENOMEM);
+    }
+    // 先全部置为 NULL，失败时未填充的位置不会被释放
+    unsafe { std::ptr::write_bytes(holder, 0, strings.len() + 1) };
+    let default_opts = Options::default();
+    for (i, string) in strings.enumerate() {
+        let slot = unsafe { holder.add(i) } as *mut *const c_char;
+        if return_path(slot, string, &default_opts) != 0 {
+            // 未填充的位置为 NULL，可直接按数组释放
+            free_candidate_paths(holder, false);
+            return Err(-ENOMEM);
+        }
+    }
+    Ok(holder)
+}
+
+/// Returned by `core_btf_is_available` when a btf for the kernel was found in the archive
+pub const BPF_COMPAT_ARCHIVE_BTF: c_int = 2;
+/// Returned by `core_btf_is_available` when neither the kernel nor the archive has a btf
+pub const BPF_COMPAT_BTF_UNAVAILABLE: c_int = 0;
+
+/// Tell whether a btf would be found for the running kernel, without extracting anything
+///
+/// Returns `BPF_COMPAT_NATIVE_BTF` (1) if the kernel has native btf (or an installed one,
+/// see `NativeBtfProbe`), `BPF_COMPAT_ARCHIVE_BTF` (2) if the archive has a matching entry,
+/// `BPF_COMPAT_BTF_UNAVAILABLE` (0) if neither, or a negative errno if the archive can't be
+/// read. The lookup is the one of `ensure_core_btf_with_tar_binary`, but the matching entry
+/// is only decoded and validated, no file is created and nothing is allocated for the caller.
+#[no_mangle]
+pub extern "C" fn core_btf_is_available(tar: *const u8, len: usize) -> c_int {
+    last_error::track(|| core_btf_is_available_opts(tar, len, std::ptr::null()))
+}
+
+/// Same as `core_btf_is_available`, with the lookup configured by `opts`, e.g. `sysroot` or `match_policy`
+#[no_mangle]
+pub extern "C" fn core_btf_is_available_opts(
+    tar: *const u8,
+    len: usize,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(false, tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        btf_availability(tar_bytes, &opts)
+    })
+}
+
+/// Same as `core_btf_is_available`, but checks the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn core_btf_is_available_linked_tar() -> c_int {
+    last_error::track(|| {
+        // 未链接归档时不检查归档参数，内核自带 btf 的情况下仍应返回 1
+        btf_availability(
+            linked_archive_bytes().unwrap_or_default(),
+            &Options::default(),
+        )
+    })
+}
+
+fn btf_availability(tar_bytes: &[u8], opts: &Options) -> c_int {
+    // 与 ensure_core_btf 的判断顺序一致，保证两者的结论不会不同
+    if has_native_btf(opts) || installed_btf(opts).is_some() {
+        return BPF_COMPAT_NATIVE_BTF;
+    }
+    match extract::lookup_btf(TarSource::Bytes(tar_bytes), opts, || Ok(extract::Discard)) {
+        Ok(extract::Discard) => BPF_COMPAT_ARCHIVE_BTF,
+        // 没有匹配的条目，或唯一匹配的条目字节序与本机不符
+        Err(e) if e == -ENOENT || e == -ENOEXEC => BPF_COMPAT_BTF_UNAVAILABLE,
+        Err(e) => e,
+    }
+}
+
+/// Flag of `diagnose_core_btf`: try to download the btf, into memory, to tell whether the download would work
+pub const BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD: c_uint = 1 << 0;
+
+/// Explain the lookup of `ensure_core_btf_with_tar_binary_opts`, e.g. after it failed with `-ENOENT`
+///
+/// On success `*report` is set to a malloc'd string of a few lines, see
+/// `bpf_compatible_rs::diagnose::Diagnosis`: the system as detected, the paths searched in
+/// the archive, the state of the kernel's own btf, the number of entries scanned, the
+/// kernels of the same distro and arch nearest to the running one, and the fallbacks
+/// (`BPF_COMPATIBLE_BTF_PATH`, the cache, the download) consulted. It should be released
+/// with the `free` of `opts`, `bpf_compatible_free_buffer` by default. Nothing is written
+/// and the network isn't reached, unless `flags` has `BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD`.
+/// `opts` may be NULL. Fails only on invalid arguments or if the report can't be allocated.
+#[no_mangle]
+pub extern "C" fn diagnose_core_btf(
+    report: *mut *const c_char,
+    tar: *const u8,
+    len: usize,
+    opts: *const BpfCompatOpts,
+    flags: c_uint,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(report.is_null(), tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        unsafe { *report = std::ptr::null() };
+        if flags & !BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD != 0 {
+            report!(
+                "Unknown flags {:#x}",
+                flags & !BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD
+            );
+            return -EINVAL;
+        }
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let mut diagnose_opts = DiagnoseOptions::new()
+            .with_root(&opts.sysroot)
+            .with_prefix(&opts.archive_prefix)
+            .with_vmlinux_path(&opts.vmlinux_path)
+            .with_native_probe(opts.native_probe.clone())
+            .with_max_decompressed_size(opts.max_decompressed_size)
+            .with_download(download_allowed(&opts))
+            .with_download_probe(flags & BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD != 0)
+            .with_pahole(pahole_allowed(&opts));
+        #[cfg(feature = "pahole")]
+        {
+            diagnose_opts = diagnose_opts.with_pahole_options(opts.pahole.clone());
+        }
+        if let Some(system) = &opts.system {
+            diagnose_opts = diagnose_opts.with_system(system.clone());
+        }
+        if opts.use_cache && std::env::var_os(NO_CACHE_ENV).is_none_or(|v| v.is_empty()) {
+            if let Some(cache) = BtfCache::from_default() {
+                diagnose_opts = diagnose_opts.with_cache(cache, archive_key(tar_bytes));
+            }
+        }
+        #[cfg(feature = "download")]
+        if let Some(url) = opts.download_url.clone().or_else(|| {
+            std::env::var(DOWNLOAD_URL_ENV)
+                .ok()
+                .filter(|v| !v.is_empty())
+        }) {
+            diagnose_opts = diagnose_opts.with_download_url(url);
+        }
+        let diagnosis = diagnose_with(tar_bytes, &diagnose_opts).to_string();
+        let holder = unsafe { (opts.alloc)(diagnosis.len() + 1) } as *mut u8;
+        if holder.is_null() {
+            report!("Unable to allocate a buffer for c string");
+            return -ENOMEM;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(diagnosis.as_ptr(), holder, diagnosis.len());
+            *holder.add(diagnosis.len()) = 0;
+            *report = holder as *const c_char;
+        }
+        0
+    })
+}
+
+/// List the kernels the archive has a btf for, as `<distro>/<version>/<arch>/<release>`
+///
+/// On success `*entries` is set to a malloc'd NULL-terminated array of malloc'd strings,
+/// in archive order, and `*count` to their number; the array should be released with
+/// `free_core_btf_kernel_list`. Entries that aren't btfs, like directories or the
+/// manifest, are skipped, see `bpf_compatible_rs::archive::BtfhubArchive::kernels`.
+#[no_mangle]
+pub extern "C" fn list_core_btf_kernels(
+    tar: *const u8,
+    len: usize,
+    entries: *mut *mut *mut c_char,
+    count: *mut usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(entries.is_null() || count.is_null(), tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        list_kernels(tar_bytes, entries, count)
+    })
+}
+
+/// Same as `list_core_btf_kernels`, but lists the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn list_core_btf_kernels_linked_tar(
+    entries: *mut *mut *mut c_char,
+    count: *mut usize,
+) -> c_int {
+    last_error::track(|| {
+        if entries.is_null() || count.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        let tar_bytes = linked_archive_bytes().unwrap_or_default();
+        if tar_bytes.is_empty() {
+            report!("No btf archive is linked into the executable");
+            return -EINVAL;
+        }
+        list_kernels(tar_bytes, entries, count)
+    })
+}
+
+/// Describe the archive `tar` in `*out`: its number of btfs, its sizes and the build time and id packed into it
+///
+/// `out->sz` must be set to `sizeof(struct bpf_compat_archive_info)`; fields beyond it
+/// aren't written. The build time and id come from the optional metadata entry
+/// (`btfhub-archive/.metadata`, written by `btfgen`), and are -1 and empty without it.
+/// Returns 0 or a negative errno, e.g. `-EINVAL` for something that isn't an archive.
+#[no_mangle]
+pub extern "C" fn get_core_btf_archive_info(
+    tar: *const u8,
+    len: usize,
+    out: *mut info::BpfCompatArchiveInfo,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(out.is_null(), tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        archive_info(tar_bytes, out)
+    })
+}
+
+/// Same as `get_core_btf_archive_info`, but describes the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn get_core_btf_archive_info_linked_tar(
+    out: *mut info::BpfCompatArchiveInfo,
+) -> c_int {
+    last_error::track(|| {
+        if out.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        let tar_bytes = linked_archive_bytes().unwrap_or_default();
+        if tar_bytes.is_empty() {
+            report!("No btf archive is linked into the executable");
+            return -EINVAL;
+        }
+        archive_info(tar_bytes, out)
+    })
+}
+
+fn archive_info(tar_bytes: &[u8], out: *mut info::BpfCompatArchiveInfo) -> c_int {
+    let opts = Options::default();
+    match BtfhubArchive::new(tar_bytes)
+        .with_prefix(&opts.archive_prefix)
+        .info()
+    {
+        Ok(v) => info::write_info(out, &v),
+        Err(e) => {
+            report!("Failed to read the archive: {}", e);
+            extract::archive_errno(&e)
+        }
+    }
+}
+
+/// Store the path the btf of the running system has in btfhub-archive in `*path`, e.g. `ubuntu/20.04/x86_64/5.4.0-40-generic.btf`
+///
+/// The path is relative to the `btfhub-archive` directory, and is the first one a lookup
+/// tries; no archive is read. `*path` is allocated with the allocator of
+/// `bpf_compatible_set_allocator` and released with `bpf_compatible_free_buffer`. Fails
+/// with `-ENOENT` if the distro can't be told, see `bpf_compatible_last_error`.
+#[no_mangle]
+pub extern "C" fn get_current_system_btf_rel_path(path: *mut *const c_char) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        unsafe { *path = std::ptr::null() };
+        let info = match current_system_info() {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let rel_path = info.to_string();
+        let holder = unsafe { alloc::alloc(rel_path.len() + 1) } as *mut u8;
+        if holder.is_null() {
+            report!("Unable to allocate a buffer for c string");
+            return -ENOMEM;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(rel_path.as_ptr(), holder, rel_path.len());
+            *holder.add(rel_path.len()) = 0;
+            *path = holder as *const c_char;
+        }
+        0
+    })
+}
+
+/// Describe the running system in `*out`: its distro, version, architecture and kernel release
+///
+/// `out->sz` must be set to `sizeof(struct bpf_compat_system_info)`; fields beyond it
+/// aren't written. The values are those a lookup uses, faked ones included, see
+/// `ensure_core_btf_for_system` to look up another system. Fails with `-ENOENT` if the
+/// distro can't be told, see `bpf_compatible_last_error`.
+#[no_mangle]
+pub extern "C" fn get_current_system_info(out: *mut system_info::BpfCompatSystemInfo) -> c_int {
+    last_error::track(|| {
+        if out.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        match current_system_info() {
+            Ok(v) => system_info::write_system_info(out, &v),
+            Err(e) => e,
+        }
+    })
+}
+
+/// The identity of the running system, as a lookup with the default options sees it
+fn current_system_info() -> Result<SystemInfo, c_int> {
+    Options::default().system_info().map_err(|e| {
+        report!("Failed to gather the running system: {}", e);
+        extract::detect_errno(&e)
+    })
+}
+
+fn list_kernels(tar_bytes: &[u8], entries: *mut *mut *mut c_char, count: *mut usize) -> c_int {
+    unsafe {
+        *entries = std::ptr::null_mut();
+        *count = 0;
+    }
+    let opts = Options::default();
+    let kernels = match BtfhubArchive::new(tar_bytes)
+        .with_prefix(&opts.archive_prefix)
+        .kernels()
+    {
+        Ok(v) => v,
+        Err(e) => {
+            report!("Failed to list the kernels of the archive: {}", e);
+            return extract::archive_errno(&e);
+        }
+    };
+    match string_array(kernels.iter().map(|v| v.as_bytes())) {
+        Ok(holder) => {
+            unsafe {
+                *entries = holder;
+                *count = kernels.len();
+            }
+            0
+        }
+        Err(e) => e,
+    }
+}
+
+/// Free the array returned by `list_core_btf_kernels`
+#[no_mangle]
+pub extern "C" fn free_core_btf_kernel_list(entries: *mut *mut c_char) {
+    free_candidate_paths(entries, false)
+}
+
+/// Write the btfs of the kernels matching `pattern` under `dest_dir`, as `<distro>/<version>/<arch>/<release>.btf`
+///
+/// `pattern` is a shell wildcard, see `fnmatch(3)`, matched against
+/// `<distro>/<version>/<arch>/<release>` as listed by `list_core_btf_kernels`, e.g.
+/// `ubuntu/20.04/*` or `*/x86_64/5.4.0-*`; `*` also matches `/`, and NULL matches every
+/// kernel. Returns the number of btfs written, 0 if nothing matched, or a negative errno if
+/// `dest_dir` can't be created or the archive can't be read. A btf that fails to extract is
+/// reported and left out, and counted in `*failed` unless it is NULL. See
+/// `bpf_compatible_rs::TarballBtfArchive::extract_many`.
+#[no_mangle]
+pub extern "C" fn extract_core_btfs_to_dir(
+    tar: *const u8,
+    len: usize,
+    pattern: *const c_char,
+    dest_dir: *const c_char,
+    failed: *mut usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(dest_dir.is_null(), tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        if !failed.is_null() {
+            unsafe { *failed = 0 };
+        }
+        let dest_dir = Path::new(OsStr::from_bytes(
+            unsafe { CStr::from_ptr(dest_dir) }.to_bytes(),
+        ));
+        let opts = Options::default();
+        let archive = match TarballBtfArchive::from_gzipped_bytes(tar_bytes) {
+            Ok(v) => v.with_prefix(&opts.archive_prefix),
+            Err(e) => {
+                report!("Failed to read the archive: {}", e);
+                return extract::archive_errno(&e);
+            }
+        };
+        let selector = |info: &BtfEntryInfo| match info {
+            BtfEntryInfo::Btf(entry) => {
+                pattern.is_null()
+                    || CString::new(entry.kernel())
+                        .is_ok_and(|kernel| unsafe { fnmatch(pattern, kernel.as_ptr(), 0) == 0 })
+            }
+            BtfEntryInfo::Other(_) => false,
+        };
+        let extracted = match archive.extract_many(selector, dest_dir) {
+            Ok(v) => v,
+            Err(e) => {
+                report!(
+                    "Failed to extract the btfs to {}: {}",
+                    dest_dir.display(),
+                    e
+                );
+                return extract::archive_errno(&e);
+            }
+        };
+        for (path, e) in &extracted.failed {
+            report!("Failed to extract {}: {}", path.display(), e);
+        }
+        if !failed.is_null() {
+            unsafe { *failed = extracted.failed.len() };
+        }
+        c_int::try_from(extracted.written.len()).unwrap_or(c_int::MAX)
+    })
+}
+
+// 依赖的 libc 版本尚未声明 fnmatch
+extern "C" {
+    fn fnmatch(pattern: *const c_char, string: *const c_char, flags: c_int) -> c_int;
+}
+
+/// Remove the btfs returned by `ensure_core_btf_candidates_with_tar_binary`, and free the array
+#[no_mangle]
+pub extern "C" fn bpf_compatible_free_candidates(paths: *mut *mut c_char) {
+    free_candidate_paths(paths, true)
+}
+
+fn free_candidate_paths(paths: *mut *mut c_char, remove_files: bool) {
+    if paths.is_null() {
+        return;
+    }
+    let mut slot = paths;
+    loop {
+        let path = unsafe { *slot };
+        if path.is_null() {
+            break;
+        }
+        memo::take_handed_out(path);
+        let path_bytes = unsafe { CStr::from_ptr(path) }.to_bytes();
+        if remove_files && memo::take_created_path(path_bytes) {
+            if let Err(e) = std::fs::remove_file(OsStr::from_bytes(path_bytes)) {
+                error!("Failed to perform clean: {}", e);
+            }
+        }
+        unsafe {
+            alloc::free(path as *mut c_void);
+            slot = slot.add(1);
+        }
+    }
+    unsafe { alloc::free(paths as *mut c_void) };
+}
+
+/// `path` resolved against the current directory if it's relative
+///
+/// Paths are handed out as absolute ones, so they stay valid if the caller changes
+/// directory before libbpf opens them, e.g. with a relative `$XDG_CACHE_HOME`, `sysroot`
+/// or `BPF_COMPATIBLE_BTF_PATH`. Symlinks are left as they are.
+fn absolute_path(path: &std::path::Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|e| {
+        debug!("Unable to make {} absolute: {}", path.display(), e);
+        path.to_path_buf()
+    })
+}
+
+/// Hand out the btf file named by the operator, after checking it's a btf
+fn override_btf(path: *mut *const c_char, btf_path: &std::path::Path, opts: &Options) -> c_int {
+    let bytes = match std::fs::read(btf_path) {
+        Ok(v) => v,
+        Err(e) => {
+            report!(
+                "Unable to read {} set by {}: {}",
+                btf_path.display(),
+                BTF_PATH_ENV,
+                e
+            );
+            return -e.raw_os_error().unwrap_or(ENOENT);
+        }
+    };
+    if let Err(e) = validate_btf_bytes(&bytes) {
+        report!(
+            "{} set by {} is not a valid btf: {}",
+            btf_path.display(),
+            BTF_PATH_ENV,
+            e
+        );
+        return -EILSEQ;
+    }
+    let btf_path = absolute_path(btf_path);
+    let btf_path = btf_path.as_os_str().as_bytes();
+    let ret = return_path(path, btf_path, opts);
+    if ret == 0 {
+        // 该文件由使用者提供，clean_core_btf_rs 时不应被删除
+        memo::record_cached_path(btf_path);
+    }
+    ret
+}
+
+/// Whether the running kernel exposes its own btf at `opts.vmlinux_path`
+///
+/// The file must be readable and start with the btf magic, not merely exist.
+fn has_native_btf(opts: &Options) -> bool {
+    native_btf_status(opts) == NativeBtfStatus::Usable
+}
+
+/// What the btf at `opts.vmlinux_path` is like, see `has_native_btf`
+fn native_btf_status(opts: &Options) -> NativeBtfStatus {
+    // 伪造的内核版本并非真实运行的内核，其自带的 btf 与之无关
+    #[cfg(feature = "fake-system")]
+    if bpf_compatible_rs::fake::is_kernel_faked() {
+        return NativeBtfStatus::Ignored;
+    }
+    // 查找的是另一个内核版本的 btf 时，运行中内核自带的 btf 与之无关
+    if let Some(info) = &opts.system {
+        if current_kernel_release().is_ok_and(|v| v != info.kernel_release) {
+            return NativeBtfStatus::Ignored;
+        }
+    }
+    // 判断当系统是否具备 btf 文件生成的条件；无法判断是否存在时按存在但不可用处理
+    if opts.vmlinux_path.try_exists().is_ok_and(|v| !v) {
+        return NativeBtfStatus::Missing;
+    }
+    // 受限的容器中文件存在但无法打开（缺少 CAP_SYS_ADMIN 或 LSM 策略限制），此时交给 libbpf 只会在之后更难排查的地方失败
+    match check_btf_file(&opts.vmlinux_path) {
+        Ok(()) => NativeBtfStatus::Usable,
+        Err(e) => {
+            note!(
+                "{} exists but is not usable, ignoring it: {}",
+                opts.vmlinux_path.display(),
+                e
+            );
+            NativeBtfStatus::Unusable
+        }
+    }
+}
+
+/// An installed btf of the kernel the btf is looked up for, see `NativeBtfProbe::probe_with_config`
+fn installed_btf(opts: &Options) -> Option<PathBuf> {
+    let release = match &opts.system {
+        Some(v) => v.kernel_release.clone(),
+        None => current_kernel_release().ok()?,
+    };
+    // 内核配置了 CONFIG_DEBUG_INFO_BTF 时，磁盘上的内核镜像带有 .BTF 节，libbpf 可以直接使用
+    let btf_config = kernel_btf_config_of(&release, &opts.sysroot);
+    if btf_config == Some(true) && !opts.vmlinux_path.exists() {
+        note!(
+            "The kernel was built with CONFIG_DEBUG_INFO_BTF, but {} is missing: \
+             it predates 5.4, or sysfs is hidden",
+            opts.vmlinux_path.display()
+        );
+    }
+    opts.native_probe.probe_with_config(&release, btf_config)
+}
+
+/// Explain why the archive is used inside a container that doesn't see the host's sysfs
+fn note_container_without_sysfs() {
+    // 容器中未挂载 /sys 时无法得知宿主机是否具备 btf，但 uname 返回的仍是宿主机的内核版本，可以据此在归档中查找
+    if !PathBuf::from(SYS_KERNEL_BTF_DIR).exists() {
+        if let Some(runtime) = detect_container() {
+            note!(
+                "Running in a {} container without {} mounted, looking up the archive with the host kernel release {}",
+                runtime,
+                SYS_KERNEL_BTF_DIR,
+                current_kernel_release().unwrap_or_else(|_| "unknown".to_string())
+            );
+        }
+    }
+}
+
+/// Record the resolution in the audit log, if one was configured through `BPF_COMPATIBLE_AUDIT_LOG`
+#[cfg(feature = "audit-log")]
+fn record_resolution(source: &str, matched_path: Option<std::borrow::Cow<str>>, ret: c_int) {
+    use bpf_compatible_rs::audit::{AuditLog, AuditRecord};
+    let Some(log) = AuditLog::from_env() else {
+        return;
+    };
+    let release = current_kernel_release().unwrap_or_else(|_| "unknown".to_string());
+    let result = if ret == 0 {
+        "ok".to_string()
+    } else {
+        format!("errno={}", -ret)
+    };
+    if let Err(e) = log.append(&AuditRecord {
+        release: &release,
+        source,
+        matched_path: matched_path.as_deref(),
+        result: &result,
+    }) {
+        note!("Failed to write audit log: {}", e);
+    }
+}
+
+#[cfg(not(feature = "audit-log"))]
+fn record_resolution(_source: &str, _matched_path: Option<std::borrow::Cow<str>>, _ret: c_int) {}
+
+/// Look up the btf of the running kernel in the tar, and extract it to a temporary file (or a memfd)
+fn extract_btf_from_archive(path: *mut *const c_char, source: TarSource, opts: &Options) -> c_int {
+    // 多个线程同时查找时只有一个解压，其余的等待后复用同一个文件
+    let _extraction = memo::lock_extraction();
+    if opts.use_cache && std::env::var_os(NO_CACHE_ENV).is_none_or(|v| v.is_empty()) {
+        if let Some(ret) = extract_btf_cached(path, source, opts) {
+            return ret;
+        }
+    }
+    if opts.use_memfd {
+        let memfd = match extract::lookup_btf(source, opts, BtfMemfd::create) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let memfd_path = match memfd.seal() {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let ret = return_path(path, memfd_path.as_bytes(), opts);
+        if ret == 0 {
+            // memfd 保持打开，由调用者通过 clean_core_btf_rs 关闭
+            memfd.keep();
+        }
+        return ret;
+    }
+    if opts.share_extracted || std::env::var_os(SHARED_ENV).is_some_and(|v| !v.is_empty()) {
+        if let Some(ret) = extract_btf_shared(path, source, opts) {
+            return ret;
+        }
+    }
+    // 同一进程中内核不会改变，之前解压出的文件仍完好时直接复用，无需再次解压归档
+    let key = extraction_key(&source, opts);
+    if let Some((btf_path, matched)) = key.as_ref().and_then(memo::memoized_btf) {
+        debug!(
+            "Reusing {}, extracted by an earlier call",
+            OsStr::from_bytes(&btf_path).to_string_lossy()
+        );
+        if let Some(matched) = matched {
+            match_info::record(MatchInfo {
+                memoized: true,
+                ..matched
+            });
+        }
+        let ret = return_path(path, &btf_path, opts);
+        if ret == 0 {
+            memo::record_extra_handout(&btf_path);
+        }
+        return ret;
+    }
+    let btf_file = match extract::lookup_btf(source, opts, || {
+        BtfTempfile::create(opts.tmpdir.as_deref(), &opts.tempfile_template)
+    }) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    // 路径以字节的形式原样返回，不经过 UTF-8 转换，保证与磁盘上的文件名完全一致
+    let btf_path = btf_file.path().to_bytes().to_vec();
+    let ret = return_path(path, &btf_path, opts);
+    if ret == 0 {
+        // 此后临时文件由调用者通过 clean_core_btf_rs 清理
+        btf_file.keep();
+        if let Some(key) = key {
+            memo::memoize(key, &btf_path, match_info::last());
+        }
+    }
+    ret
+}
+
+/// What the btf a lookup extracts depends on, to reuse it in later lookups, see `memo::memoized_btf`
+///
+/// `None` if the system can't be detected, in which case the lookup fails anyway.
+fn extraction_key(source: &TarSource, opts: &Options) -> Option<memo::ExtractionKey> {
+    Some(memo::ExtractionKey {
+        archive: source.fingerprint(),
+        system: opts.system_info().ok()?,
+        lookup: format!(
+            "{:?} {} {} {} {} {:?} {} {}",
+            opts.policy,
+            opts.any_distro,
+            opts.require_verification,
+            opts.max_decompressed_size,
+            opts.archive_prefix.display(),
+            opts.tmpdir,
+            opts.tempfile_template,
+            opts.check_arch
+        ),
+    })
+}
+
+/// Look up the btf in the persistent cache, extracting it to the cache on a miss
+///
+/// Returns `None` if the cache can't be used at all (e.g. no cache directory can be
+/// determined), in which case the btf should be extracted as usual
+fn extract_btf_cached(
+    path: *mut *const c_char,
+    source: TarSource,
+    opts: &Options,
+) -> Option<c_int> {
+    // 缓存命中时无需解压归档
+    if let Some(ret) = cached_btf(path, source, opts) {
+        return Some(ret);
+    }
+    let cache = BtfCache::from_default()?;
+    let archive_path = opts.system_info().ok()?.to_string();
+    let key = source.key();
+    let btf = match extract::lookup_btf(source, opts, || Ok(Vec::new())) {
+        Ok(v) => v,
+        Err(e) => return Some(e),
+    };
+    match cache.store(&key, &archive_path, &btf) {
+        Ok(cached) => Some(return_cached_path(path, &cached, opts)),
+        Err(e) => {
+            // 缓存目录不可写时退回到临时文件
+            note!(
+                "Failed to cache the btf, using a temporary file instead: {}",
+                e
+            );
+            Some(return_btf_tempfile(path, &btf, opts))
+        }
+    }
+}
+
+/// The btf the persistent cache holds for the archive of `source`, `None` on a miss
+///
+/// Also `None` with `refresh_cache`, or if the cache is disabled with `BPF_COMPATIBLE_NO_CACHE`.
+fn cached_btf(path: *mut *const c_char, source: TarSource, opts: &Options) -> Option<c_int> {
+    if opts.refresh_cache || std::env::var_os(NO_CACHE_ENV).is_some_and(|v| !v.is_empty()) {
+        return None;
+    }
+    let cache = BtfCache::from_default()?;
+    let archive_path = opts.system_info().ok()?.to_string();
+    let cached = cache.lookup(&source.key(), &archive_path)?;
+    record_cache_match(opts);
+    Some(return_cached_path(path, &cached, opts))
+}
+
+/// Extract the btf to a file shared with the other processes of the user, see `bpf_compatible_rs::shared`
+///
+/// The file is returned as a cached one, so `clean_core_btf_rs` leaves it for the others.
+/// Returns `None` if there is no private directory to share it in, in which case the btf
+/// should be extracted as usual
+fn extract_btf_shared(
+    path: *mut *const c_char,
+    source: TarSource,
+    opts: &Options,
+) -> Option<c_int> {
+    let dir = temp::shared_dir(opts.tmpdir.as_deref())?;
+    let kernel_release = opts.system_info().ok()?.kernel_release;
+    let btf = match extract::lookup_btf(source, opts, || Ok(Vec::new())) {
+        Ok(v) => v,
+        Err(e) => return Some(e),
+    };
+    match store_shared(&dir, &kernel_release, &btf) {
+        Ok(shared) => Some(return_cached_path(path, &shared, opts)),
+        Err(e) => {
+            note!(
+                "Failed to share the btf, using a temporary file instead: {}",
+                e
+            );
+            Some(return_btf_tempfile(path, &btf, opts))
+        }
+    }
+}
+
+/// Record that the btf is taken from the persistent cache, whose entry isn't known
+fn record_cache_match(opts: &Options) {
+    match_info::record(MatchInfo::of_source(
+        BtfSource::Cache,
+        None,
+        false,
+        kernel_release_of(opts),
+    ));
+}
+
+/// Write `btf` to a temporary file and return its path
+fn return_btf_tempfile(path: *mut *const c_char, btf: &[u8], opts: &Options) -> c_int {
+    let mut btf_file = match BtfTempfile::create(opts.tmpdir.as_deref(), &opts.tempfile_template) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    if let Err(e) = btf_file.overwrite_from(&mut &btf[..]) {
+        return e;
+    }
+    let ret = return_path(path, btf_file.path().to_bytes(), opts);
+    if ret == 0 {
+        btf_file.keep();
+    }
+    ret
+}
+
+/// Whether the caller allowed downloading btfs missing from the archive, through opts or `BPF_COMPATIBLE_DOWNLOAD`
+fn download_allowed(opts: &Options) -> bool {
+    opts.allow_download || std::env::var_os(DOWNLOAD_ENV).is_some_and(|v| !v.is_empty())
+}
+
+/// Download the btf of the kernel from btfhub-archive into the persistent cache
+///
+/// A btf downloaded before is taken from the cache without reaching the network. Returns
+/// `None` if the btf can't be downloaded, with the reason kept as the last error, so the
+/// caller returns the `-ENOENT` of the archive lookup.
+#[cfg(feature = "download")]
+fn download_core_btf(path: *mut *const c_char, opts: &Options) -> Option<c_int> {
+    use bpf_compatible_rs::{
+        download::{
+            btfhub_url, check_btfhub_arch, download_btf_with, DownloadConfig, DEFAULT_URL_TEMPLATE,
+        },
+        sha256::{sha256, to_hex},
+    };
+    let info = opts.system_info().ok()?;
+    let template = opts
+        .download_url
+        .clone()
+        .or_else(|| {
+            std::env::var(DOWNLOAD_URL_ENV)
+                .ok()
+                .filter(|v| !v.is_empty())
+        })
+        .unwrap_or_else(|| DEFAULT_URL_TEMPLATE.to_string());
+    let url = btfhub_url(&template, &info);
+    // 不同来源下载的 btf 分开缓存
+    let key = format!("download-{}", &to_hex(&sha256(template.as_bytes()))[..16]);
+    let archive_path = info.to_string();
+    let cache = BtfCache::from_default();
+    if let Some(cached) = cache.as_ref().and_then(|v| v.lookup(&key, &archive_path)) {
+        debug!("Using the btf downloaded before to {}", cached.display());
+        record_cache_match(opts);
+        return Some(return_cached_path(path, &cached, opts));
+    }
+    let btf = match check_btfhub_arch(&template, &info)
+        .and_then(|_| download_btf_with(&url, &DownloadConfig::from_env()))
+    {
+        Ok(v) => v,
+        Err(e) => {
+            report!("The archive has no btf for {}: {}", archive_path, e);
+            return None;
+        }
+    };
+    note!("Downloaded the btf from {}", url);
+    match_info::record(MatchInfo::of_source(
+        BtfSource::Download,
+        Some(PathBuf::from(&url)),
+        true,
+        info.kernel_release.clone(),
+    ));
+    if let Some(cache) = &cache {
+        match cache.store(&key, &archive_path, &btf) {
+            Ok(cached) => return Some(return_cached_path(path, &cached, opts)),
+            Err(e) => note!(
+                "Failed to cache the downloaded btf, using a temporary file instead: {}",
+                e
+            ),
+        }
+    }
+    Some(return_btf_tempfile(path, &btf, opts))
+}
+
+#[cfg(not(feature = "download"))]
+fn download_core_btf(_path: *mut *const c_char, _opts: &Options) -> Option<c_int> {
+    note!("Downloading btfs needs the download feature, which this build doesn't have");
+    None
+}
+
+/// Whether the caller allowed generating btfs missing from the archive with pahole, through opts or `BPF_COMPATIBLE_PAHOLE`
+fn pahole_allowed(opts: &Options) -> bool {
+    opts.allow_pahole || std::env::var_os(PAHOLE_ENV).is_some_and(|v| !v.is_empty())
+}
+
+/// Generate the btf of the kernel from its vmlinux with pahole, into the persistent cache
+///
+/// A btf generated before from the same vmlinux is taken from the cache without running
+/// pahole again. Returns `None` if the btf can't be generated, with the reason kept as the
+/// last error, so the caller moves on, or returns the `-ENOENT` of the archive lookup.
+#[cfg(feature = "pahole")]
+fn pahole_core_btf(path: *mut *const c_char, opts: &Options) -> Option<c_int> {
+    use bpf_compatible_rs::{
+        pahole::generate_btf,
+        sha256::{sha256, to_hex},
+    };
+    let info = opts.system_info().ok()?;
+    let archive_path = info.to_string();
+    let vmlinux = match opts.pahole.find_vmlinux(&info.kernel_release) {
+        Ok(v) => v,
+        Err(e) => {
+            report!("The archive has no btf for {}: {}", archive_path, e);
+            return None;
+        }
+    };
+    // 以 vmlinux 的路径、大小和修改时间区分缓存，重新编译的同版本内核不会用到旧的 btf
+    let stamp = std::fs::metadata(&vmlinux)
+        .map(|v| format!("{}:{}:{:?}", vmlinux.display(), v.len(), v.modified().ok()))
+        .unwrap_or_default();
+    let key = format!("pahole-{}", &to_hex(&sha256(stamp.as_bytes()))[..16]);
+    let cache = BtfCache::from_default();
+    if let Some(cached) = cache.as_ref().and_then(|v| v.lookup(&key, &archive_path)) {
+        debug!("Using the btf generated before to {}", cached.display());
+        record_cache_match(opts);
+        return Some(return_cached_path(path, &cached, opts));
+    }
+    let btf = match generate_btf(&info.kernel_release, &opts.pahole) {
+        Ok(v) => v,
+        Err(e) => {
+            report!("The archive has no btf for {}: {}", archive_path, e);
+            return None;
+        }
+    };
+    note!("Generated the btf from {} with pahole", vmlinux.display());
+    match_info::record(MatchInfo::of_source(
+        BtfSource::Pahole,
+        Some(vmlinux),
+        true,
+        info.kernel_release.clone(),
+    ));
+    if let Some(cache) = &cache {
+        match cache.store(&key, &archive_path, &btf) {
+            Ok(cached) => return Some(return_cached_path(path, &cached, opts)),
+            Err(e) => note!(
+                "Failed to cache the generated btf, using a temporary file instead: {}",
+                e
+            ),
+        }
+    }
+    Some(return_btf_tempfile(path, &btf, opts))
+}
+
+#[cfg(not(feature = "pahole"))]
+fn pahole_core_btf(_path: *mut *const c_char, _opts: &Options) -> Option<c_int> {
+    note!("Generating btfs with pahole needs the pahole feature, which this build doesn't have");
+    None
+}
+
+fn return_cached_path(path: *mut *const c_char, cached: &std::path::Path, opts: &Options) -> c_int {
+    let cached = absolute_path(cached);
+    let cached = cached.as_os_str().as_bytes();
+    let ret = return_path(path, cached, opts);
+    if ret == 0 {
+        // 缓存文件在 clean_core_btf_rs 时不应被删除
+        memo::record_cached_path(cached);
+    }
+    ret
+}
+
+/// Hand `btf_path_bytes` out to the C caller through `path`, as a string allocated by `opts`
+fn return_path(path: *mut *const c_char, btf_path_bytes: &[u8], opts: &Options) -> c_int {
+    // The buffer will be passed to C program, so allocate it with malloc (or the allocator in opts)
+    // 缓冲区将传递个C程序，所有用 malloc（或 opts 中指定的分配函数）初始化了一个内存空间。
+    let holder = unsafe { (opts.alloc)(btf_path_bytes.len() + 1) } as *mut u8;
+    if holder.is_null() {
+        report!("Unable to allocate a buffer for c string");
+        return -ENOMEM;
+    }
+    // 将 holder 封装成一个安全的内存切片
+    let holder_slice = unsafe { slice::from_raw_parts_mut(holder, btf_path_bytes.len() + 1) };
+    // 将 btf 文件的路径信息以切片的方式拷贝到 holder_slice 中
+    holder_slice[..btf_path_bytes.len()].copy_from_slice(btf_path_bytes);
+    // C-Strings require a trailing zero
+    // C 字符创的最后一个字符是以 0 结尾的
+    holder_slice[btf_path_bytes.len()] = 0;
+    // 完成了 btf 文件信息赋值给 path 指针
+    *unsafe { &mut *path } = holder as *const c_char;
+    // 只有登记过的字符串才会被 clean_core_btf_rs 释放
+    memo::record_handed_out(holder as *const c_char);
+    0
+}
+
+/// Emit `bpf_compat_linked_tar_range`, the addresses of the symbols of the linked archive
+///
+/// The symbols are weak references, resolved to 0 if the archive isn't linked, so programs
+/// only using the other entry points link without it. Stable Rust has no weak linkage, so
+/// the references are made from assembly, with `$align` and `$word` sized for a pointer.
+macro_rules! linked_tar_range {
+    ($align:literal, $word:literal) => {
+        std::arch::global_asm!(concat!(
+            ".weak _binary_min_core_btfs_tar_gz_start\n",
+            ".weak _binary_min_core_btfs_tar_gz_end\n",
+            ".pushsection .data.rel.ro.bpf_compat_linked_tar, \"aw\"\n",
+            ".p2align ",
+            $align,
+            "\n",
+            ".globl bpf_compat_linked_tar_range\n",
+            ".hidden bpf_compat_linked_tar_range\n",
+            "bpf_compat_linked_tar_range:\n",
+            $word,
+            " _binary_min_core_btfs_tar_gz_start\n",
+            $word,
+            " _binary_min_core_btfs_tar_gz_end\n",
+            ".popsection\n",
+        ));
+    };
+}
+
+#[cfg(target_pointer_width = "64")]
+linked_tar_range!("3", ".quad");
+#[cfg(target_pointer_width = "32")]
+linked_tar_range!("2", ".long");
+
+extern "C" {
+    static bpf_compat_linked_tar_range: [*const u8; 2];
+}
+
+/// Largest range of the linked archive taken as one; farther apart, the symbols are rather unrelated
+const MAX_LINKED_ARCHIVE_SIZE: usize = 1 << 30;
+
+/// The archive linked into the executable with `min_core_btfs_tar.o`, as `ensure_core_btf_with_linked_tar` reads it
+///
+/// For Rust programs linking the object as C programs do, so they don't declare the
+/// `_binary_min_core_btfs_tar_gz_start` and `_end` symbols themselves; the bytes can be
+/// passed to `bpf_compatible_rs::ensure_core_btf`. Returns `None` if no archive is linked,
+/// or, logging why, if the symbols don't delimit one: a range over 1 GiB, or bytes that
+/// aren't in any format of `ArchiveFormat::detect`.
+pub fn linked_archive_bytes() -> Option<&'static [u8]> {
+    /*
+        通过 bpftool gen min_core_btf 命令，根据 epbf 生成的.o 目标文件，生成 btfhub-archive
+        归档的所有厂商 btf 的精简 btf，将所有的 btf 文件打包成 min_core_btfs.tar.gz
+
+        ld -r -b binary min_core_btfs.tar.gz -o min_core_btfs_tar.o 生成的静态链接文件 .o
+
+        最终通过 clang <your_program> libbpf_compatible.a min_core_btf.tar.o 生成可执行的
+        二进制文件，其中 min_core_btf.tar.o 链接中定义了 _binary_min_core_btfs_tar_gz_end
+        和 _binary_min_core_btfs_tar_gz_start 为嵌入的 tar.gz 文件的范围。
+    */
+    let [start, end] = unsafe { bpf_compat_linked_tar_range };
+    // 未链接归档时两个弱引用都是 NULL；旧版 btf_helpers.h 中的弱定义则是两个互不相关的占位字节，
+    // 其间的距离不可能容纳一个完整的 gzip 文件（头部与尾部共 18 字节）
+    let len = (end as usize).saturating_sub(start as usize);
+    if start.is_null() || len < MIN_GZIP_SIZE {
+        debug!("No btf archive is linked into the executable");
+        return None;
+    }
+    if len > MAX_LINKED_ARCHIVE_SIZE {
+        note!(
+            "Ignored the linked btf archive, whose symbols are {} bytes apart",
+            len
+        );
+        return None;
+    }
+    let bytes = unsafe { slice::from_raw_parts(start, len) };
+    if let Err(e) = ArchiveFormat::detect(bytes) {
+        note!("Ignored the linked btf archive: {}", e);
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, but uses the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_linked_tar(path: *mut *const c_char) -> c_int {
+    last_error::track(|| without_status(ensure_core_btf_with_linked_tar_status(path)))
+}
+
+/// Same as `ensure_core_btf_with_tar_binary_opts`, but uses the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_linked_tar_opts(
+    path: *mut *const c_char,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        without_status(ensure_core_btf(
+            path,
+            TarSource::Bytes(linked_archive_bytes().unwrap_or_default()),
+            &opts,
+        ))
+    })
+}
+
+/// Same as `ensure_core_btf_with_linked_tar`, but reads the archive from the section `section_name` of the executable
+///
+/// See `ensure_core_btf_with_self_section_opts`, which this calls with the default options.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_self_section(
+    path: *mut *const c_char,
+    section_name: *const c_char,
+) -> c_int {
+    last_error::track(|| {
+        ensure_core_btf_with_self_section_opts(path, section_name, std::ptr::null())
+    })
+}
+
+/// Same as `ensure_core_btf_with_linked_tar_opts`, but reads the archive from the section `section_name` of the executable
+///
+/// The section, `.bpf_compat_btfs` if `section_name` is NULL, is found through the section
+/// headers of `/proc/self/exe`, see `bpf_compatible_rs::section`; it's only read if the
+/// kernel has no btf of its own. Fails with `-ENOENT` if the executable has no such
+/// section, or no section headers at all, e.g. after `sstrip`, and with `-ENOEXEC` if it
+/// can't be parsed. `opts` may be NULL.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_self_section_opts(
+    path: *mut *const c_char,
+    section_name: *const c_char,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        let section_name = if section_name.is_null() {
+            BTF_SECTION_NAME.to_string()
+        } else {
+            unsafe { CStr::from_ptr(section_name) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        without_status(resolve_core_btf(path, &opts, "section", None, |path| {
+            let archive = match read_self_section(&section_name) {
+                Ok(v) => v,
+                Err(e) => {
+                    report!("Failed to read the archive from the executable: {}", e);
+                    return extract::archive_errno(&e);
+                }
+            };
+            extract_btf_from_archive(path, TarSource::Bytes(&archive), &opts)
+        }))
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, but reads the tar archive from the file at `tar_path`
+///
+/// A missing file fails with `-ENOENT`, an unreadable one with `-EACCES`, and one that
+/// isn't an archive with `-EINVAL`. See `ensure_core_btf_with_archive_file`, which this
+/// calls with the default options.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_tar_file(
+    path: *mut *const c_char,
+    tar_path: *const c_char,
+) -> c_int {
+    last_error::track(|| ensure_core_btf_with_archive_file(path, tar_path, std::ptr::null()))
+}
+
+/// Same as `ensure_core_btf_with_tar_binary_opts`, but looks the btf up in the archive file at `archive_path`
+///
+/// The file is mapped into memory rather than read, so only the pages the lookup touches
+/// become resident; it is read into memory if it can't be mapped. The file must exist even
+/// if the kernel has native btf. Fails with `-ESTALE` if the file changed during the
+/// lookup, see `bpf_compatible_rs::mapped`. `opts` may be NULL.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_archive_file(
+    path: *mut *const c_char,
+    archive_path: *const c_char,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        unsafe { *path = std::ptr::null() };
+        if archive_path.is_null() {
+            report!("The archive path is NULL");
+            return -EINVAL;
+        }
+        let archive_path = OsStr::from_bytes(unsafe { CStr::from_ptr(archive_path) }.to_bytes());
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let file = match ArchiveFile::open(archive_path) {
+            Ok(v) => v,
+            Err(e) => {
+                report!("Failed to open the archive: {}", e);
+                return extract::archive_errno(&e);
+            }
+        };
+        let ret = without_status(ensure_core_btf(path, TarSource::Bytes(file.bytes()), &opts));
+        check_file_unchanged(path, &file, &opts).unwrap_or(ret)
+    })
+}
+
+/// `-ESTALE` if `file` changed during the lookup, after releasing the btf it set `*path` to
+fn check_file_unchanged(
+    path: *mut *const c_char,
+    file: &ArchiveFile,
+    opts: &Options,
+) -> Option<c_int> {
+    // 查找期间文件被原地修改时，结果可能来自新旧内容的混合，不能交给调用者
+    let e = file.check_unchanged().err()?;
+    report!("{}", e);
+    let btf_path = unsafe { *path } as *mut c_char;
+    if !btf_path.is_null() {
+        clean_core_btf(btf_path, opts);
+        unsafe { *path = std::ptr::null() };
+    }
+    Some(-ESTALE)
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, searching the archives of `sources` in order
+///
+/// See `ensure_core_btf_multi_opts`, which this calls with the default options.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_multi(
+    sources: *const source::BpfCompatSource,
+    n: usize,
+    path: *mut *const c_char,
+) -> c_int {
+    last_error::track(|| ensure_core_btf_multi_opts(sources, n, path, std::ptr::null()))
+}
+
+/// Same as `ensure_core_btf_with_tar_binary_opts`, searching the archives of `sources` in order
+///
+/// The btf is taken from the first archive that has one for the kernel, e.g. a base archive
+/// linked into the executable, then a supplement for newly released kernels next to it. A
+/// file that doesn't exist is skipped, as is an archive without the btf. Other failures are
+/// reported and the search goes on; if no archive has the btf, the first of them is
+/// returned, with its message as the last error, so it isn't masked by the failures of
+/// later sources. Otherwise `-ENOENT`, or the btf is downloaded if that's allowed. Every
+/// source is checked before any is searched, an invalid one fails with `-EINVAL`.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_multi_opts(
+    sources: *const source::BpfCompatSource,
+    n: usize,
+    path: *mut *const c_char,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        unsafe { *path = std::ptr::null() };
+        if sources.is_null() || n == 0 {
+            report!("No archive source is given");
+            return -EINVAL;
+        }
+        let sources = unsafe { slice::from_raw_parts(sources, n) };
+        if let Err(e) = sources.iter().try_for_each(|v| v.check()) {
+            return e;
+        }
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        without_status(resolve_core_btf(path, &opts, "archives", None, |path| {
+            extract_btf_from_sources(path, sources, &opts)
+        }))
+    })
+}
+
+/// Search the archives of `sources` in order, see `ensure_core_btf_multi_opts`
+fn extract_btf_from_sources(
+    path: *mut *const c_char,
+    sources: &[source::BpfCompatSource],
+    opts: &Options,
+) -> c_int {
+    // 第一个并非“找不到”的错误及其消息，全部未命中时返回它，不被之后的来源覆盖
+    let mut first_error: Option<(c_int, Option<String>)> = None;
+    for source in sources {
+        debug!("Looking the btf up in {}", source);
+        let ret = match source.open() {
+            Ok(archive) => {
+                let ret = extract_btf_from_archive(path, TarSource::Bytes(archive.bytes()), opts);
+                match archive {
+                    source::OpenedSource::File(file) if ret >= 0 => {
+                        check_file_unchanged(path, &file, opts).map_or(ret, |e| e)
+                    }
+                    _ => ret,
+                }
+            }
+            Err(e) => e,
+        };
+        match ret {
+            v if v >= 0 => return v,
+            // 调用者取消了查找，不再尝试之后的归档
+            v if v == -ECANCELED => return v,
+            v if v == -ENOENT => debug!("No btf in {}", source),
+            v => {
+                note!(
+                    "Failed to look the btf up in {}, trying the next archive",
+                    source
+                );
+                first_error.get_or_insert((v, last_error::get()));
+            }
+        }
+    }
+    if let Some((ret, message)) = first_error {
+        if let Some(message) = message {
+            last_error::set(message);
+        }
+        return ret;
+    }
+    report!(
+        "None of the {} archives has a btf for the running kernel",
+        sources.len()
+    );
+    -ENOENT
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, but reads the archive from the open descriptor `fd`
+///
+/// For processes that get the archive from another one and can't open files themselves.
+/// The descriptor is read from the start if it can seek; otherwise, e.g. for a pipe, it is
+/// read from its current position until end of file. It is left open, at the position the
+/// read ends at. Returns `-EBADF` if `fd` isn't an open descriptor and `-EINVAL` if what's
+/// read isn't an archive.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_with_fd(path: *mut *const c_char, fd: c_int) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        unsafe { *path = std::ptr::null() };
+        if fd < 0 {
+            report!("Invalid file descriptor {}", fd);
+            return -EBADF;
+        }
+        let tar = match read_fd(fd) {
+            Ok(v) => v,
+            Err(e) => {
+                report!("Failed to read the archive from fd {}: {}", fd, e);
+                return e
+                    .raw_os_error()
+                    .map_or_else(|| extract::stream_errno(&e), |v| -v);
+            }
+        };
+        without_status(ensure_core_btf(
+            path,
+            TarSource::Bytes(&tar),
+            &Options::default(),
+        ))
+    })
+}
+
+/// Same as `ensure_core_btf_with_tar_binary`, but looks the btf up in the unpacked btfhub-archive at `dir`
+///
+/// The path of the running system, as `generate_current_system_btf_archive_path` gives
+/// it, is looked up under `dir` as `<release>.btf`, then in the compressed forms, e.g.
+/// btfhub's `<release>.btf.tar.xz`. A plain btf is returned as it is, and
+/// `clean_core_btf_rs` only frees the string, never removing a file of `dir`; a compressed
+/// one is extracted to a temporary file, removed by `clean_core_btf_rs`. Returns
+/// `-ENOENT` if `dir` has no btf for the kernel.
+#[no_mangle]
+pub extern "C" fn ensure_core_btf_from_dir(path: *mut *const c_char, dir: *const c_char) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        unsafe { *path = std::ptr::null() };
+        if dir.is_null() {
+            report!("The directory is NULL");
+            return -EINVAL;
+        }
+        let dir = Path::new(OsStr::from_bytes(unsafe { CStr::from_ptr(dir) }.to_bytes()));
+        let opts = Options::default();
+        without_status(resolve_core_btf(path, &opts, "dir", None, |path| {
+            btf_from_dir(path, dir, &opts)
+        }))
+    })
+}
+
+fn btf_from_dir(path: *mut *const c_char, dir: &Path, opts: &Options) -> c_int {
+    let info = match opts.system_info() {
+        Ok(v) => v,
+        Err(e) => {
+            report!("Failed to detect the system: {}", e);
+            return extract::archive_errno(&e);
+        }
+    };
+    let directory = BtfDirectory::new(dir);
+    let entry = match directory.lookup(&info) {
+        Ok(v) => v,
+        Err(e) => {
+            report!("Failed to find the btf in {}: {}", dir.display(), e);
+            return extract::archive_errno(&e);
+        }
+    };
+    match_info::record(MatchInfo::of_entry(&entry, &info, BtfSource::Archive));
+    if entry.encoding == BtfEncoding::Plain {
+        if let Err(e) = check_btf_file(&entry.path) {
+            report!("{}", e);
+            return extract::archive_errno(&e);
+        }
+        // 目录中的文件属于调用者，按缓存文件登记，clean_core_btf_rs 不会删除它
+        return return_cached_path(path, &entry.path, opts);
+    }
+    match directory.extract(&entry) {
+        Ok(btf) => return_btf_tempfile(path, &btf, opts),
+        Err(e) => {
+            report!("Failed to extract {}: {}", entry.path.display(), e);
+            extract::archive_errno(&e)
+        }
+    }
+}
+
+/// Get the split btf of the kernel module `module`, from the tar archive if the kernel has none
+///
+/// If the kernel exposes the module's btf, e.g. `/sys/kernel/btf/nf_tables` (under the
+/// sysroot of `opts`), its path is returned, which `clean_core_btf_rs` only frees. If the
+/// kernel has native btf but not for the module, e.g. because it isn't loaded,
+/// `BPF_COMPAT_NATIVE_BTF` is returned with `*path` set to NULL. Otherwise the btf is
+/// looked up in the archive at `<release>/modules/<module>.btf`, next to the btf of the
+/// kernel, and extracted to a temporary file; `-ENOENT` if the archive has none. The
+/// module btf is split, so the kernel's btf is needed too. `opts` may be NULL.
+#[no_mangle]
+pub extern "C" fn ensure_module_btf(
+    path: *mut *const c_char,
+    tar: *const u8,
+    len: usize,
+    module: *const c_char,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(path.is_null(), tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        unsafe { *path = std::ptr::null() };
+        if module.is_null() {
+            report!("The module name is NULL");
+            return -EINVAL;
+        }
+        let module = unsafe { CStr::from_ptr(module) }.to_string_lossy();
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        module_btf(path, tar_bytes, &module, &opts)
+    })
+}
+
+fn module_btf(path: *mut *const c_char, tar_bytes: &[u8], module: &str, opts: &Options) -> c_int {
+    // 模块名用作文件名，不能含有 `/` 或是 `..`
+    if Path::new(module).file_name() != Some(OsStr::new(module)) {
+        report!("Invalid module name `{}`", module);
+        return -EINVAL;
+    }
+    // 内核自带的 btf 与 vmlinux 位于同一目录，以模块名命名
+    let sysfs = opts
+        .vmlinux_path
+        .parent()
+        .unwrap_or(Path::new("/"))
+        .join(module);
+    if check_btf_file(&sysfs).is_ok() {
+        debug!(
+            "The kernel has native btf for {} at {}",
+            module,
+            sysfs.display()
+        );
+        return return_cached_path(path, &sysfs, opts);
+    }
+    if has_native_btf(opts) {
+        debug!("The kernel has native btf, but none for {}", module);
+        return BPF_COMPAT_NATIVE_BTF;
+    }
+    let info = match opts.system_info() {
+        Ok(v) => v,
+        Err(e) => {
+            report!("Failed to detect the system: {}", e);
+            return extract::archive_errno(&e);
+        }
+    };
+    let btf = TarballBtfArchive::from_gzipped_bytes(tar_bytes).and_then(|v| {
+        v.with_prefix(&opts.archive_prefix)
+            .extract_module(&info, module)
+    });
+    match btf {
+        Ok(v) => return_btf_tempfile(path, &v, opts),
+        Err(e) => {
+            report!("Failed to find the btf of {}: {}", module, e);
+            extract::archive_errno(&e)
+        }
+    }
+}
+
+/// Flag of `extract_core_btf_to`: replace the file at the destination if there is one
+pub const BPF_COMPAT_EXTRACT_OVERWRITE: c_uint = 1 << 0;
+/// Flag of `extract_core_btf_to`: create the missing parent directories of the destination
+pub const BPF_COMPAT_EXTRACT_CREATE_DIRS: c_uint = 1 << 1;
+/// Flag of `extract_core_btf_to`: write the btf from the archive even if the kernel has native btf
+pub const BPF_COMPAT_EXTRACT_ALWAYS: c_uint = 1 << 2;
+
+/// Write the btf of the running kernel from the tar archive to exactly `dest_path`
+///
+/// For a btf at a stable location, e.g. referenced by a systemd unit, instead of a
+/// temporary file. The btf is written to a temporary file in the same directory and
+/// renamed into place, so readers never see a partial file. `flags` is a set of
+/// `BPF_COMPAT_EXTRACT_*` bits. Returns `BPF_COMPAT_CUSTOM_BTF` once written, or
+/// `BPF_COMPAT_NATIVE_BTF` without writing anything if the kernel has native btf and
+/// `BPF_COMPAT_EXTRACT_ALWAYS` isn't set. Fails with `-EEXIST` if the file exists and
+/// `BPF_COMPAT_EXTRACT_OVERWRITE` isn't set, and `-ENOENT` if the parent directory is
+/// missing and `BPF_COMPAT_EXTRACT_CREATE_DIRS` isn't set, or the archive has no btf.
+#[no_mangle]
+pub extern "C" fn extract_core_btf_to(
+    tar: *const u8,
+    len: usize,
+    dest_path: *const c_char,
+    flags: c_uint,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(dest_path.is_null(), tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let known = BPF_COMPAT_EXTRACT_OVERWRITE
+            | BPF_COMPAT_EXTRACT_CREATE_DIRS
+            | BPF_COMPAT_EXTRACT_ALWAYS;
+        if flags & !known != 0 {
+            report!("Unknown flags {:#x}", flags & !known);
+            return -EINVAL;
+        }
+        let dest = Path::new(OsStr::from_bytes(
+            unsafe { CStr::from_ptr(dest_path) }.to_bytes(),
+        ));
+        let opts = Options::default();
+        // 调用者要的是文件本身时，即使内核自带 btf 也照常写入
+        if flags & BPF_COMPAT_EXTRACT_ALWAYS == 0 && has_native_btf(&opts) {
+            debug!(
+                "The kernel has native btf at {}, not writing {}",
+                opts.vmlinux_path.display(),
+                dest.display()
+            );
+            return BPF_COMPAT_NATIVE_BTF;
+        }
+        let btf = match extract::lookup_btf(TarSource::Bytes(tar_bytes), &opts, || Ok(Vec::new())) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let write_opts = ExtractOptions::default()
+            .with_overwrite(flags & BPF_COMPAT_EXTRACT_OVERWRITE != 0)
+            .with_create_dirs(flags & BPF_COMPAT_EXTRACT_CREATE_DIRS != 0);
+        match write_btf_to(dest, &btf, &write_opts) {
+            Ok(()) => {
+                debug!("Wrote the btf to {}", dest.display());
+                BPF_COMPAT_CUSTOM_BTF
+            }
+            Err(e) => {
+                report!("{}", e);
+                extract::archive_errno(&e)
+            }
+        }
+    })
+}
+
+/// Read everything from `fd`, from the start if it can seek, without closing it
+#[cfg(target_os = "linux")]
+fn read_fd(fd: c_int) -> std::io::Result<Vec<u8>> {
+    // 描述符属于调用者，ManuallyDrop 保证不会被关闭
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    match file.seek(SeekFrom::Start(0)) {
+        Ok(_) => {}
+        // 管道等不可定位的描述符从当前位置读到结束
+        Err(e) if e.raw_os_error() == Some(ESPIPE) => {}
+        Err(e) => return Err(e),
+    }
+    let mut tar = vec![];
+    // read_to_end 会重试被信号中断的读取，并一直读到文件结束
+    file.read_to_end(&mut tar)?;
+    Ok(tar)
+}
+
+/// Descriptors are only read on Linux, this fails with `ErrorKind::Unsupported`
+#[cfg(not(target_os = "linux"))]
+fn read_fd(_fd: c_int) -> std::io::Result<Vec<u8>> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Write all of `bytes` to `fd` from its current offset, without closing it
+#[cfg(target_os = "linux")]
+fn write_fd(fd: c_int, bytes: &[u8]) -> std::io::Result<()> {
+    // 描述符属于调用者，ManuallyDrop 保证不会被关闭
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    // write_all 会重试部分写入和被信号中断的写入
+    file.write_all(bytes)
+}
+
+/// Descriptors aren't written to elsewhere either, failing with `ErrorKind::Unsupported`
+#[cfg(not(target_os = "linux"))]
+fn write_fd(_fd: c_int, _bytes: &[u8]) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// An archive decompressed and indexed once, see `bpf_compat_archive_open`
+///
+/// Opaque to C. Lookups only read it, so a handle may be shared between threads.
+pub struct BpfCompatArchive {
+    pub(crate) parsed: ParsedArchive,
+    /// Key of the compressed archive in the persistent cache
+    pub(crate) key: String,
+}
+
+/// Decompress and index a tar archive once, for repeated lookups with `bpf_compat_archive_lookup`
+///
+/// The archive may be compressed in any format `ensure_core_btf_with_tar_binary` accepts;
+/// the decompressed tar is kept in memory until `bpf_compat_archive_close`. If a path
+/// occurs more than once, the last entry wins, as when unpacking the tar. Returns 0 with
+/// `*archive` set, or a negative errno with `*archive` set to NULL.
+#[no_mangle]
+pub extern "C" fn bpf_compat_archive_open(
+    archive: *mut *mut BpfCompatArchive,
+    tar: *const u8,
+    len: usize,
+) -> c_int {
+    last_error::track(|| {
+        let tar_bytes = match check_args(archive.is_null(), tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        open_archive(archive, tar_bytes)
+    })
+}
+
+/// Same as `bpf_compat_archive_open`, but opens the tar archive linked into the executable
+#[no_mangle]
+pub extern "C" fn bpf_compat_archive_open_linked_tar(archive: *mut *mut BpfCompatArchive) -> c_int {
+    last_error::track(|| {
+        if archive.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        let tar_bytes = linked_archive_bytes().unwrap_or_default();
+        if tar_bytes.is_empty() {
+            unsafe { *archive = std::ptr::null_mut() };
+            report!("No btf archive is linked into the executable");
+            return -EINVAL;
+        }
+        open_archive(archive, tar_bytes)
+    })
+}
+
+fn open_archive(archive: *mut *mut BpfCompatArchive, tar_bytes: &[u8]) -> c_int {
+    unsafe { *archive = std::ptr::null_mut() };
+    let parsed = match ParsedArchive::parse(tar_bytes) {
+        Ok(v) => v,
+        Err(e) => {
+            report!("Failed to open the archive: {}", e);
+            return extract::archive_errno(&e);
+        }
+    };
+    let handle = Box::new(BpfCompatArchive {
+        parsed,
+        key: archive_key(tar_bytes),
+    });
+    unsafe { *archive = Box::into_raw(handle) };
+    0
+}
+
+/// Same as `ensure_core_btf_with_tar_binary_opts`, but looks the btf up in an archive opened with `bpf_compat_archive_open`
+///
+/// The candidate entries are found through the index, so nothing is decompressed again;
+/// the result is otherwise the same as looking up the archive the handle was opened
+/// from. `opts` may be NULL.
+#[no_mangle]
+pub extern "C" fn bpf_compat_archive_lookup(
+    archive: *const BpfCompatArchive,
+    path: *mut *const c_char,
+    opts: *const BpfCompatOpts,
+) -> c_int {
+    last_error::track(|| {
+        if path.is_null() {
+            report!("The output pointer is NULL");
+            return -EINVAL;
+        }
+        let Some(archive) = (unsafe { archive.as_ref() }) else {
+            unsafe { *path = std::ptr::null() };
+            report!("The archive handle is NULL");
+            return -EINVAL;
+        };
+        let opts = match Options::from_raw(opts) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        without_status(ensure_core_btf(path, TarSource::Parsed(archive), &opts))
+    })
+}
+
+/// Release an archive opened with `bpf_compat_archive_open`
+///
+/// Paths returned by `bpf_compat_archive_lookup` stay valid, and are still released with
+/// `clean_core_btf_rs`. NULL is ignored.
+#[no_mangle]
+pub extern "C" fn bpf_compat_archive_close(archive: *mut BpfCompatArchive) {
+    if !archive.is_null() {
+        drop(unsafe { Box::from_raw(archive) });
+    }
+}
+
+/// The btf of the running kernel, held for `bpf_compat_fill_open_opts` until `bpf_compat_close`
+///
+/// Opaque to C.
+pub struct BpfCompatCtx {
+    /// The path handed out by the lookup, NULL if the kernel has native btf
+    path: *mut c_char,
+}
+
+/// Look up the btf of the running kernel in the tar archive, and keep it until `bpf_compat_close`
+///
+/// The lookup is the one of `ensure_core_btf_with_tar_binary`. Returns NULL on failure,
+/// with errno set to the error and the message in `bpf_compatible_last_error`.
+#[no_mangle]
+pub extern "C" fn bpf_compat_open(tar: *const u8, len: usize) -> *mut BpfCompatCtx {
+    let mut ctx = std::ptr::null_mut();
+    let ret = last_error::track(|| {
+        let tar_bytes = match check_args(false, tar, len) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let mut path = std::ptr::null();
+        let ret = ensure_core_btf(&mut path, TarSource::Bytes(tar_bytes), &Options::default());
+        if ret < 0 {
+            return ret;
+        }
+        ctx = Box::into_raw(Box::new(BpfCompatCtx {
+            path: path as *mut c_char,
+        }));
+        ret
+    });
+    if ret < 0 {
+        // 与 libbpf 返回指针的函数一致，失败时通过 errno 报告原因
+        #[cfg(target_os = "linux")]
+        unsafe {
+            *libc::__errno_location() = -ret
+        };
+    }
+    ctx
+}
+
+/// Set `btf_custom_path` of the libbpf open options at `opts`, of `opts_sz` bytes, to the btf of `ctx`
+///
+/// `opts` is a `struct bpf_object_open_opts`, written by offset so that no libbpf is
+/// needed to build this library; nothing but `btf_custom_path` is touched. It is set to
+/// NULL if the kernel has native btf. The path stays valid until `bpf_compat_close`, so
+/// close the context only after `bpf_object__load`, which is when libbpf reads the file.
+/// Returns `BPF_COMPAT_NATIVE_BTF` or `BPF_COMPAT_CUSTOM_BTF`, or `-EINVAL` if `opts_sz`
+/// is too small to have the field, i.e. for a libbpf older than 0.6.
+#[no_mangle]
+pub extern "C" fn bpf_compat_fill_open_opts(
+    ctx: *const BpfCompatCtx,
+    opts: *mut c_void,
+    opts_sz: usize,
+) -> c_int {
+    last_error::track(|| {
+        let Some(ctx) = (unsafe { ctx.as_ref() }) else {
+            report!("The context is NULL");
+            return -EINVAL;
+        };
+        if opts.is_null() {
+            report!("The open options are NULL");
+            return -EINVAL;
+        }
+        let ret = open_opts::fill_btf_custom_path(opts as *mut u8, opts_sz, ctx.path);
+        if ret < 0 {
+            ret
+        } else if ctx.path.is_null() {
+            BPF_COMPAT_NATIVE_BTF
+        } else {
+            BPF_COMPAT_CUSTOM_BTF
+        }
+    })
+}
+
+/// Remove the btf of `ctx`, as `clean_core_btf_rs` does, and free the context; NULL is ignored
+#[no_mangle]
+pub extern "C" fn bpf_compat_close(ctx: *mut BpfCompatCtx) {
+    if ctx.is_null() {
+        return;
+    }
+    let ctx = unsafe { Box::from_raw(ctx) };
+    clean_core_btf(ctx.path, &Options::default());
+}
+
+/// Remove every btf from the persistent cache used with `use_cache`
+#[no_mangle]
+pub extern "C" fn bpf_compatible_clear_cache() -> c_int {
+    last_error::track(|| {
+        let Some(cache) = BtfCache::from_default() else {
+            return 0;
+        };
+        match cache.invalidate() {
+            Ok(()) => 0,
+            Err(e) => {
+                report!("Failed to clear the btf cache: {}", e);
+                -libc::EIO
+            }
+        }
+    })
+}
+
+/// Version of this library, e.g. `0.1.0`
+///
+/// The string is static and must not be freed
+#[no_mangle]
+pub extern "C" fn bpf_compatible_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Bit of `bpf_compatible_features`: built with the `audit-log` feature
+pub const BPF_COMPAT_FEATURE_AUDIT_LOG: c_uint = 1 << 0;
+/// Bit of `bpf_compatible_features`: built with the `zstd` feature
+pub const BPF_COMPAT_FEATURE_ZSTD: c_uint = 1 << 1;
+/// Bit of `bpf_compatible_features`: built with the `xz` feature
+pub const BPF_COMPAT_FEATURE_XZ: c_uint = 1 << 2;
+/// Bit of `bpf_compatible_features`: built with the `download` feature
+pub const BPF_COMPAT_FEATURE_DOWNLOAD: c_uint = 1 << 3;
+/// Bit of `bpf_compatible_features`: built with the `fake-system` feature
+pub const BPF_COMPAT_FEATURE_FAKE_SYSTEM: c_uint = 1 << 4;
+/// Bit of `bpf_compatible_features`: built with the `pahole` feature
+pub const BPF_COMPAT_FEATURE_PAHOLE: c_uint = 1 << 5;
+
+/// Cargo features this library was built with, as `BPF_COMPAT_FEATURE_*` bits
+///
+/// Decided at build time, so it tells what a statically linked copy supports.
+#[no_mangle]
+pub extern "C" fn bpf_compatible_features() -> c_uint {
+    let mut features = 0;
+    if cfg!(feature = "audit-log") {
+        features |= BPF_COMPAT_FEATURE_AUDIT_LOG;
+    }
+    if cfg!(feature = "zstd") {
+        features |= BPF_COMPAT_FEATURE_ZSTD;
+    }
+    if cfg!(feature = "xz") {
+        features |= BPF_COMPAT_FEATURE_XZ;
+    }
+    if cfg!(feature = "download") {
+        features |= BPF_COMPAT_FEATURE_DOWNLOAD;
+    }
+    if cfg!(feature = "fake-system") {
+        features |= BPF_COMPAT_FEATURE_FAKE_SYSTEM;
+    }
+    if cfg!(feature = "pahole") {
+        features |= BPF_COMPAT_FEATURE_PAHOLE;
+    }
+    features
+}
+
+/// Identity of the tar archive linked into the executable, `none` if there is none
+///
+/// See `bpf_compatible_rs::identity::archive_identity` for the format. The string is
+/// static and must not be freed
+#[no_mangle]
+pub extern "C" fn bpf_compatible_archive_identity() -> *const c_char {
+    static IDENTITY: OnceLock<CString> = OnceLock::new();
+    IDENTITY
+        .get_or_init(|| {
+            let tar_bytes = linked_archive_bytes().unwrap_or_default();
+            let identity = if tar_bytes.is_empty() {
+                "none".to_string()
+            } else {
+                // 此函数不经过 last_error::track，同样不能让 panic 展开到 C 中
+                std::panic::catch_unwind(|| archive_identity(tar_bytes)).unwrap_or_else(|payload| {
+                    note!(
+                        "Failed to identify the archive: {}",
+                        last_error::panic_message(payload.as_ref())
+                    );
+                    format!("size={}", tar_bytes.len())
+                })
+            };
+            // 归档元数据中可能包含 NUL，截断到第一个 NUL 为止
+            let identity = identity.split('\0').next().unwrap_or_default().to_string();
+            CString::new(identity).unwrap_or_default()
+        })
+        .as_ptr()
+}
+
+/// Returned by `clean_core_btf_rs2` when the btf file (or memfd) created by this library was removed
+pub const BPF_COMPAT_BTF_DELETED: c_int = 1;
+/// Returned by `clean_core_btf_rs2` when only the string was freed, the file not being ours
+pub const BPF_COMPAT_PATH_FREED: c_int = 0;
+
+/// Remove the btf extracted by `ensure_core_btf_with_tar_binary` (or close its memfd), and free the path string
+///
+/// See `clean_core_btf_rs2`, which reports the outcome.
+#[no_mangle]
+pub extern "C" fn clean_core_btf_rs(path: *mut c_char) {
+    last_error::track(|| clean_core_btf(path, &Options::default()));
+}
+
+/// Same as `clean_core_btf_rs`, returning what was done
+///
+/// Only files this library created are removed: cached and shared btfs, installed or native btfs and
+/// the operator's btf are left in place, and the string is merely freed. Returns
+/// `BPF_COMPAT_BTF_DELETED` or `BPF_COMPAT_PATH_FREED`, or a negative errno if the file
+/// couldn't be removed (`-ENOENT` if it was already gone); the string is freed anyway.
+/// NULL returns `BPF_COMPAT_PATH_FREED`. A string that wasn't returned by this library, or
+/// was already cleaned, is left alone with `-EINVAL`: this guards against most double
+/// frees, but the pointer must not be used after the first call all the same, as the
+/// allocator may hand the same address out again.
+#[no_mangle]
+pub extern "C" fn clean_core_btf_rs2(path: *mut c_char) -> c_int {
+    last_error::track(|| clean_core_btf(path, &Options::default()))
+}
+
+/// Same as `clean_core_btf_rs2`, freeing the path string with the deallocator in `opts`
+///
+/// With `zero_on_clean` set in `opts`, the file is overwritten with zeros and truncated
+/// before it's removed. A file swapped in under the path since it was created is then left
+/// alone with `-ESTALE`, and a file that couldn't be zeroed, e.g. on a file system
+/// remounted read-only, is still removed if it's the one created, returning the errno of
+/// the zeroing; `bpf_compatible_last_error()` tells which step failed and why. Invalid
+/// `opts` fail with `-EINVAL`, leaking the string rather than freeing it with the wrong
+/// function.
+#[no_mangle]
+pub extern "C" fn clean_core_btf_opts(path: *mut c_char, opts: *const BpfCompatOpts) -> c_int {
+    last_error::track(|| match Options::from_raw(opts) {
+        Ok(opts) => clean_core_btf(path, &opts),
+        // 无法确定应使用哪个释放函数时，宁可泄漏也不要用错误的函数释放
+        Err(e) => e,
+    })
+}
+
+fn clean_core_btf(path: *mut c_char, opts: &Options) -> c_int {
+    if path.is_null() {
+        return BPF_COMPAT_PATH_FREED;
+    }
+    // 未登记的指针可能已被释放，不能解引用
+    if !memo::take_handed_out(path) {
+        report!("The btf path was not returned by bpf-compatible, or was already cleaned");
+        return -EINVAL;
+    }
+    let ret = remove_owned_btf(
+        unsafe { CStr::from_ptr(path) }.to_bytes(),
+        opts.zero_on_clean,
+    );
+    unsafe { (opts.free)(path as *mut c_void) };
+    ret
+}
+
+/// Remove the btf at `path_bytes` if this library created it, see `clean_core_btf_rs2`,
+/// zeroing it first if `zero` is set, see `temp::zero_and_remove`
+fn remove_owned_btf(path_bytes: &[u8], zero: bool) -> c_int {
+    // 其他线程可能刚从 memo 中取得同一文件、尚未登记，等它返回后再决定是否删除
+    let _extraction = memo::lock_extraction();
+    // 清理后不再复用该文件；同一文件还交给了其他调用者时留在原处
+    memo::forget_memoized(path_bytes);
+    if memo::take_extra_handout(path_bytes) {
+        return BPF_COMPAT_PATH_FREED;
+    }
+    // 缓存中的文件留给之后的调用使用；memfd 没有对应的文件，关闭 fd 即可释放
+    if memo::take_cached_path(path_bytes) {
+        BPF_COMPAT_PATH_FREED
+    } else if memfd::close_memfd_path(path_bytes) {
+        BPF_COMPAT_BTF_DELETED
+    } else if let Some(identity) = memo::take_created_file(path_bytes) {
+        let path_buf = PathBuf::from(OsStr::from_bytes(path_bytes));
+        if zero {
+            return match temp::zero_and_remove(&path_buf, identity) {
+                Ok(()) => BPF_COMPAT_BTF_DELETED,
+                Err(e) => e,
+            };
+        }
+        match std::fs::remove_file(&path_buf) {
+            Ok(()) => BPF_COMPAT_BTF_DELETED,
+            Err(e) => {
+                report!("Failed to remove {}: {}", path_buf.display(), e);
+                -e.raw_os_error().unwrap_or(EIO)
+            }
+        }
+    } else {
+        // 其他文件（原生或已安装的 btf）不是本库创建的，留在原处
+        debug!(
+            "Leaving {} in place",
+            OsStr::from_bytes(path_bytes).to_string_lossy()
+        );
+        BPF_COMPAT_PATH_FREED
+    }
+}
+
+/// Remove the btf temporary files left behind by processes that crashed or didn't clean them, last modified more than `max_age_secs` ago
+///
+/// Only regular files named like `eunomia.btf.XXXXXX` and owned by the effective user are
+/// removed, from `dir`, or if it's NULL from `$TMPDIR` (or `/tmp`) and the private
+/// directory of the user under it; the files this process still holds are left alone.
+/// The outcome is written to `*report` unless it's NULL, see `struct bpf_compat_gc_report`.
+/// Returns 0, or a negative errno if `dir` can't be listed; files that couldn't be
+/// removed are counted as failed instead.
+#[no_mangle]
+pub extern "C" fn bpf_compatible_gc_stale_btf_tempfiles(
+    dir: *const c_char,
+    max_age_secs: u64,
+    report: *mut gc::BpfCompatGcReport,
+) -> c_int {
+    bpf_compatible_gc_stale_btf_tempfiles_template(
+        dir,
+        std::ptr::null(),
+        std::ptr::null(),
+        max_age_secs,
+        report,
+    )
+}
+
+/// Same as `bpf_compatible_gc_stale_btf_tempfiles`, for the files named after `tempfile_prefix` and `tempfile_suffix` of `struct bpf_compat_opts`
+///
+/// NULL stands for the default as in `struct bpf_compat_opts`, so both NULL collect the
+/// `eunomia.btf.XXXXXX` files. Returns `-EINVAL` for a template the options would reject.
+#[no_mangle]
+pub extern "C" fn bpf_compatible_gc_stale_btf_tempfiles_template(
+    dir: *const c_char,
+    prefix: *const c_char,
+    suffix: *const c_char,
+    max_age_secs: u64,
+    report: *mut gc::BpfCompatGcReport,
+) -> c_int {
+    last_error::track(|| {
+        if !report.is_null() {
+            let ret = gc::check_report(report);
+            if ret < 0 {
+                return ret;
+            }
+        }
+        let template = match opts::read_tempfile_template(prefix, suffix) {
+            Ok(v) => v,
+            Err(e) => return e,
+        };
+        let max_age = std::time::Duration::from_secs(max_age_secs);
+        let result = match dir.is_null() {
+            true => bpf_compatible_rs::gc::gc_stale_btf_tempfiles_with(max_age, &template),
+            false => {
+                let dir = Path::new(OsStr::from_bytes(unsafe { CStr::from_ptr(dir) }.to_bytes()));
+                bpf_compatible_rs::gc::gc_stale_btf_tempfiles_in_with(dir, max_age, &template)
+            }
+        };
+        match result {
+            Ok(v) => {
+                if !report.is_null() {
+                    gc::write_report(report, &v);
+                }
+                0
+            }
+            Err(e) => {
+                report!("Failed to collect the stale btfs: {}", e);
+                extract::archive_errno(&e)
+            }
+        }
+    })
+}
+
+/// Remove the btf temporary files this process created and didn't clean when it exits, through `atexit`
+///
+/// Files returned by this library and not passed to `clean_core_btf_rs` are removed by
+/// `exit` or a return from `main`, not if the process is killed. Cached, shared and
+/// native btfs are left alone. Calling it again has no effect; returns 0.
+#[no_mangle]
+pub extern "C" fn bpf_compatible_register_cleanup_at_exit() -> c_int {
+    last_error::track(|| {
+        bpf_compatible_rs::gc::register_cleanup_at_exit();
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use bpf_compatible_rs::fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive};
+
+    /// The value of an errno named in the documentation
+    fn errno_named(name: &str) -> c_int {
+        match name {
+            "E2BIG" => E2BIG,
+            "EACCES" => EACCES,
+            "EBADF" => EBADF,
+            "EBADMSG" => EBADMSG,
+            "EBUSY" => EBUSY,
+            "ECANCELED" => ECANCELED,
+            "EEXIST" => EEXIST,
+            "EFBIG" => EFBIG,
+            "EILSEQ" => EILSEQ,
+            "EINVAL" => EINVAL,
+            "EIO" => EIO,
+            "ELOOP" => ELOOP,
+            "EMEDIUMTYPE" => EMEDIUMTYPE,
+            "ENOENT" => ENOENT,
+            "ENOEXEC" => ENOEXEC,
+            "ENOKEY" => ENOKEY,
+            "ENOMEM" => ENOMEM,
+            "ENOPKG" => ENOPKG,
+            "ENOTRECOVERABLE" => ENOTRECOVERABLE,
+            "ENOTSUP" => ENOTSUP,
+            "EOVERFLOW" => EOVERFLOW,
+            "EROFS" => EROFS,
+            "ESTALE" => ESTALE,
+            _ => panic!("{name} is documented, add it to errno_named and STATUS_DESCRIPTIONS"),
+        }
+    }
+
+    fn strerror(err: c_int) -> &'static str {
+        unsafe { CStr::from_ptr(bpf_compatible_strerror(err)) }
+            .to_str()
+            .unwrap()
+    }
+
+    #[test]
+    fn every_documented_code_is_described() {
+        let docs = [
+            include_str!("../../README.md"),
+            include_str!("../../README_zh.md"),
+            include_str!("../btf_helpers.h"),
+        ];
+        let mut codes = BTreeSet::new();
+        for doc in docs {
+            for (i, _) in doc.match_indices("-E") {
+                let name = doc[i + 1..]
+                    .split(|v: char| !v.is_ascii_alphanumeric())
+                    .next()
+                    .unwrap();
+                if name.len() > 2 {
+                    codes.insert(-errno_named(name));
+                }
+            }
+        }
+        codes.extend([
+            0,
+            BPF_COMPAT_NATIVE_BTF,
+            BPF_COMPAT_ARCHIVE_BTF,
+            BPF_COMPAT_BTF_UNAVAILABLE,
+            -ENOMEM,
+            -EIO,
+            -ELOOP,
+        ]);
+        for code in codes {
+            let description = strerror(code);
+            assert_ne!(description, "unknown bpf-compatible error", "{code}");
+            assert!(!description.is_empty(), "{code}");
+        }
+    }
+
+    #[test]
+    fn descriptions_are_specific_and_unknown_codes_have_a_fixed_one() {
+        // 描述的是本库中的含义，而不是 strerror 的通用文本
+        assert!(strerror(-EILSEQ).contains("valid btf"));
+        assert!(strerror(-ENOENT).contains("running kernel"));
+        assert!(strerror(BPF_COMPAT_NATIVE_BTF).contains("native btf"));
+        for unknown in [-9999, 9999, c_int::MIN, c_int::MAX, -libc::EPERM] {
+            assert_eq!(strerror(unknown), "unknown bpf-compatible error");
+        }
+        // 每个代码只有一条描述，且各自以 NUL 结尾
+        let mut seen = BTreeSet::new();
+        for (code, description) in STATUS_DESCRIPTIONS {
+            assert!(seen.insert(*code), "{code} is described twice");
+            assert_eq!(description.find('\0'), Some(description.len() - 1));
+        }
+    }
+
+    /// Options looking up the running kernel as ubuntu 20.04 x86_64, with the native btf at `vmlinux`
+    fn native_opts(vmlinux: &Path) -> Options {
+        Options {
+            system: Some(SystemInfo {
+                distro_id: "ubuntu".into(),
+                version_id: "20.04".into(),
+                arch: "x86_64".into(),
+                kernel_release: current_kernel_release().unwrap(),
+                ..Default::default()
+            }),
+            vmlinux_path: vmlinux.to_path_buf(),
+            ..Options::default()
+        }
+    }
+
+    /// Resolve the btf with `opts` in an archive holding the one of the running kernel
+    fn resolve(opts: &Options) -> (c_int, Option<PathBuf>) {
+        let release = current_kernel_release().unwrap();
+        let tar = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", &release, minimal_valid_btf())
+            .gz();
+        let mut path = std::ptr::null();
+        let ret = ensure_core_btf(&mut path, TarSource::Bytes(&tar), opts);
+        let resolved = (!path.is_null()).then(|| {
+            PathBuf::from(OsStr::from_bytes(
+                unsafe { CStr::from_ptr(path) }.to_bytes(),
+            ))
+        });
+        (ret, resolved)
+    }
+
+    #[test]
+    fn usable_native_btf_is_preferred_with_a_null_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let vmlinux = dir.path().join("vmlinux");
+        std::fs::write(&vmlinux, minimal_valid_btf()).unwrap();
+        assert_eq!(
+            resolve(&native_opts(&vmlinux)),
+            (BPF_COMPAT_NATIVE_BTF, None)
+        );
+        let opts = Options {
+            always_path: true,
+            ..native_opts(&vmlinux)
+        };
+        assert_eq!(resolve(&opts), (BPF_COMPAT_NATIVE_BTF, Some(vmlinux)));
+    }
+
+    #[test]
+    fn missing_or_unusable_native_btf_falls_back_to_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let garbage = dir.path().join("garbage");
+        std::fs::write(&garbage, b"not a btf").unwrap();
+        for vmlinux in [dir.path().join("missing"), garbage] {
+            let (ret, path) = resolve(&native_opts(&vmlinux));
+            assert_eq!(ret, 0);
+            let path = path.unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), minimal_valid_btf());
+            assert_eq!(
+                remove_owned_btf(path.as_os_str().as_bytes(), false),
+                BPF_COMPAT_BTF_DELETED
+            );
+        }
+    }
+
+    #[test]
+    fn sized_structs_are_written_up_to_the_declared_size() {
+        let report = bpf_compatible_rs::gc::GcReport {
+            removed: 1,
+            freed_bytes: 2,
+            recent: 3,
+            failed: 4,
+        };
+        let mut out = gc::BpfCompatGcReport {
+            sz: size_of::<usize>() * 2,
+            removed: 0,
+            recent: 0,
+            failed: 0,
+            freed_bytes: 0,
+        };
+        gc::write_report(&mut out, &report);
+        // 旧调用者的结构体只有 removed，之后的字段不会被写入，sz 保留调用者的值
+        assert_eq!(
+            (out.sz, out.removed, out.recent, out.failed, out.freed_bytes),
+            (size_of::<usize>() * 2, 1, 0, 0, 0)
+        );
+        out.sz = size_of::<gc::BpfCompatGcReport>() + 8;
+        gc::write_report(&mut out, &report);
+        assert_eq!((out.recent, out.failed, out.freed_bytes), (3, 4, 2));
+    }
+
+    #[test]
+    fn sized_structs_too_small_for_sz_are_rejected() {
+        let mut out = gc::BpfCompatGcReport {
+            sz: size_of::<usize>() - 1,
+            removed: 0,
+            recent: 0,
+            failed: 0,
+            freed_bytes: 0,
+        };
+        assert_eq!(gc::check_report(&mut out), -EINVAL);
+        gc::write_report(&mut out, &Default::default());
+        assert_eq!(out.sz, size_of::<usize>() - 1);
+    }
+
+    /// Extract the btf of the archive to a file of its own under `tmpdir`
+    fn extracted(tmpdir: &Path) -> PathBuf {
+        let opts = Options {
+            tmpdir: Some(tmpdir.into()),
+            ..native_opts(&tmpdir.join("missing"))
+        };
+        let (ret, path) = resolve(&opts);
+        assert_eq!(ret, 0);
+        path.unwrap()
+    }
+
+    /// Run a copy of `sleep` written over the file at `path`, which then can't be opened for writing
+    #[cfg(target_os = "linux")]
+    fn run_as_sleep(path: &Path) -> std::process::Child {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(path, std::fs::read("/bin/sleep").unwrap()).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700)).unwrap();
+        // 其他测试线程 fork 时可能短暂继承写入的 fd，此时执行会失败，重试即可
+        for _ in 0..50 {
+            match std::process::Command::new(path).arg("60").spawn() {
+                Err(e) if e.raw_os_error() == Some(libc::ETXTBSY) => {
+                    std::thread::sleep(std::time::Duration::from_millis(20))
+                }
+                v => return v.unwrap(),
+            }
+        }
+        panic!("{} stayed busy", path.display());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn zeroing_empties_the_hardlinks_of_the_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = extracted(dir.path());
+        let link = dir.path().join("link");
+        std::fs::hard_link(&path, &link).unwrap();
+        assert_eq!(
+            remove_owned_btf(path.as_os_str().as_bytes(), true),
+            BPF_COMPAT_BTF_DELETED
+        );
+        assert!(!path.exists());
+        assert_eq!(std::fs::read(&link).unwrap(), b"");
+    }
+
+    #[test]
+    fn zeroing_a_btf_already_removed_fails_with_enoent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = extracted(dir.path());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            remove_owned_btf(path.as_os_str().as_bytes(), true),
+            -libc::ENOENT
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn zeroing_leaves_a_file_swapped_in_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = extracted(dir.path());
+        let other = dir.path().join("other");
+        std::fs::write(&other, b"not ours").unwrap();
+        std::fs::rename(&other, &path).unwrap();
+        assert_eq!(
+            remove_owned_btf(path.as_os_str().as_bytes(), true),
+            -libc::ESTALE
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"not ours");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn unwritable_btf_is_removed_only_if_it_is_the_one_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = extracted(dir.path());
+        let mut child = run_as_sleep(&path);
+        let ret = remove_owned_btf(path.as_os_str().as_bytes(), true);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(ret, -libc::ETXTBSY);
+        assert!(!path.exists());
+
+        // 无法写入、路径又被换成其他文件时，不能删除
+        let path = extracted(dir.path());
+        let other = dir.path().join("other");
+        let mut child = run_as_sleep(&other);
+        std::fs::rename(&other, &path).unwrap();
+        let ret = remove_owned_btf(path.as_os_str().as_bytes(), true);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(ret, -libc::ESTALE);
+        assert!(path.exists());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn permission_denied_removal_is_reported() {
+        use std::os::unix::fs::PermissionsExt;
+        // root 不受文件权限限制
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = extracted(dir.path());
+        let parent = path.parent().unwrap().to_path_buf();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o400)).unwrap();
+        std::fs::set_permissions(&parent, std::fs::Permissions::from_mode(0o500)).unwrap();
+        let ret = remove_owned_btf(path.as_os_str().as_bytes(), true);
+        std::fs::set_permissions(&parent, std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert_eq!(ret, -libc::EACCES);
+        assert!(path.exists());
+    }
+
+    /// Look the btf of the running kernel up in `tar` as `ensure_core_btf_bytes_with_tar_binary` does
+    /// Write `btf` with `ensure_raw_btf`, returning the status and what the file held
+    fn raw_btf(btf: &[u8], opts: &Options) -> (c_int, Option<Vec<u8>>) {
+        let mut path = std::ptr::null();
+        let ret = ensure_raw_btf(&mut path, btf, opts);
+        if path.is_null() {
+            return (ret, None);
+        }
+        let written = std::fs::read(OsStr::from_bytes(
+            unsafe { CStr::from_ptr(path) }.to_bytes(),
+        ))
+        .unwrap();
+        assert_eq!(
+            clean_core_btf_rs2(path as *mut c_char),
+            BPF_COMPAT_BTF_DELETED
+        );
+        (ret, Some(written))
+    }
+
+    #[test]
+    fn raw_btf_is_written_without_native_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = Options {
+            tmpdir: Some(dir.path().into()),
+            ..native_opts(&dir.path().join("missing"))
+        };
+        let btf = btf_of_arch(8, "r15");
+        assert_eq!(raw_btf(&btf, &opts), (BPF_COMPAT_CUSTOM_BTF, Some(btf)));
+    }
+
+    #[test]
+    fn raw_buffers_that_arent_btf_fail_with_eilseq() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = Options {
+            tmpdir: Some(dir.path().into()),
+            ..native_opts(&dir.path().join("missing"))
+        };
+        let truncated = minimal_valid_btf();
+        let truncated = &truncated[..truncated.len() - 1];
+        // 看似归档的缓冲区同样不是 btf
+        let tar = FixtureArchive::new().gz();
+        for buf in [&b""[..], b"garbage", truncated, &tar] {
+            assert_eq!(raw_btf(buf, &opts), (-EILSEQ, None));
+        }
+        // 失败时不留下任何文件
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn raw_btf_is_unused_with_native_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let vmlinux = dir.path().join("vmlinux");
+        std::fs::write(&vmlinux, minimal_valid_btf()).unwrap();
+        // 内核自带 btf 时不检查缓冲区
+        assert_eq!(
+            raw_btf(b"garbage", &native_opts(&vmlinux)),
+            (BPF_COMPAT_NATIVE_BTF, None)
+        );
+    }
+
+    #[test]
+    fn raw_btf_arguments_are_checked() {
+        let btf = minimal_valid_btf();
+        let mut path = std::ptr::null();
+        assert_eq!(
+            ensure_core_btf_with_raw_btf(std::ptr::null_mut(), btf.as_ptr(), btf.len()),
+            -EINVAL
+        );
+        assert_eq!(
+            ensure_core_btf_with_raw_btf(&mut path, std::ptr::null(), btf.len()),
+            -EINVAL
+        );
+        assert_eq!(
+            ensure_core_btf_with_raw_btf(&mut path, btf.as_ptr(), usize::MAX),
+            -EINVAL
+        );
+        assert!(path.is_null());
+    }
+
+    fn btf_bytes(tar: &[u8], opts: &Options) -> (c_int, Option<Vec<u8>>) {
+        let mut buf = std::ptr::null_mut();
+        let mut len = usize::MAX;
+        let ret = ensure_core_btf_bytes(&mut buf, &mut len, tar, opts);
+        if buf.is_null() {
+            assert_eq!(len, 0);
+            return (ret, None);
+        }
+        let btf = unsafe { slice::from_raw_parts(buf, len) }.to_vec();
+        bpf_compatible_free_buffer(buf);
+        (ret, Some(btf))
+    }
+
+    #[test]
+    fn btf_bytes_are_returned_from_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = native_opts(&dir.path().join("missing"));
+        let release = current_kernel_release().unwrap();
+        let btf = btf_of_arch(8, "r15");
+        let tar = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                minimal_valid_btf(),
+            )
+            .btf("ubuntu", "20.04", "x86_64", &release, btf.clone())
+            .gz();
+        assert_eq!(btf_bytes(&tar, &opts), (0, Some(btf)));
+    }
+
+    #[test]
+    fn btf_bytes_of_a_kernel_missing_from_the_archive_fail_with_enoent() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = native_opts(&dir.path().join("missing"));
+        let tar = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "0.0.0-missing",
+                minimal_valid_btf(),
+            )
+            .gz();
+        assert_eq!(btf_bytes(&tar, &opts), (-ENOENT, None));
+    }
+
+    #[test]
+    fn no_btf_bytes_are_returned_with_native_btf() {
+        let dir = tempfile::tempdir().unwrap();
+        let vmlinux = dir.path().join("vmlinux");
+        std::fs::write(&vmlinux, minimal_valid_btf()).unwrap();
+        let release = current_kernel_release().unwrap();
+        let tar = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", &release, minimal_valid_btf())
+            .gz();
+        assert_eq!(btf_bytes(&tar, &native_opts(&vmlinux)), (0, None));
+    }
+
+    /// The C strings of a NULL-terminated array handed out to the caller
+    fn paths_of(paths: *mut *mut c_char) -> Vec<PathBuf> {
+        let mut result = vec![];
+        let mut slot = paths;
+        while !unsafe { *slot }.is_null() {
+            let path = unsafe { CStr::from_ptr(*slot) }.to_bytes();
+            result.push(PathBuf::from(OsStr::from_bytes(path)));
+            slot = unsafe { slot.add(1) };
+        }
+        result
+    }
+
+    #[test]
+    fn candidates_are_handed_out_best_first_and_freed_with_their_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut fixture = FixtureArchive::new();
+        for release in ["5.4.0-150", "5.4.0-144", "5.4.0-146", "5.4.0-140"] {
+            let name = format!("{}-generic", release);
+            fixture = fixture.btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                &name,
+                name.clone().into_bytes(),
+            );
+        }
+        let tar = fixture.gz();
+        let mut opts = Options {
+            tmpdir: Some(dir.path().into()),
+            ..native_opts(&dir.path().join("missing"))
+        };
+        opts.system.as_mut().unwrap().kernel_release = "5.4.0-146-generic".into();
+        let files = extract_btf_candidates(&tar, &opts).unwrap();
+        let mut paths = std::ptr::null_mut();
+        assert_eq!(return_candidate_paths(&mut paths, files), 4);
+        let handed_out = paths_of(paths);
+        let contents = handed_out
+            .iter()
+            .map(|v| String::from_utf8(std::fs::read(v).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            [
+                "5.4.0-146-generic",
+                "5.4.0-144-generic",
+                "5.4.0-140-generic",
+                "5.4.0-150-generic"
+            ]
+        );
+        assert!(handed_out.iter().all(|v| v.starts_with(dir.path())));
+        bpf_compatible_free_candidates(paths);
+        assert!(handed_out.iter().all(|v| !v.exists()));
+    }
+
+    #[test]
+    fn no_candidate_is_enoent_and_leaves_no_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", "5.8.0-63-generic", vec![])
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-146-azure", vec![])
+            .gz();
+        let mut opts = Options {
+            tmpdir: Some(dir.path().into()),
+            ..native_opts(&dir.path().join("missing"))
+        };
+        opts.system.as_mut().unwrap().kernel_release = "5.4.0-146-generic".into();
+        assert_eq!(extract_btf_candidates(&tar, &opts).err(), Some(-ENOENT));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        // NULL 数组可以直接释放
+        bpf_compatible_free_candidates(std::ptr::null_mut());
+    }
+
+    #[test]
+    fn native_btf_is_only_usable_if_its_magic_can_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = |vmlinux: &Path| native_btf_status(&native_opts(vmlinux));
+        let valid = dir.path().join("valid");
+        std::fs::write(&valid, minimal_valid_btf()).unwrap();
+        assert_eq!(status(&valid), NativeBtfStatus::Usable);
+        assert_eq!(
+            status(&dir.path().join("missing")),
+            NativeBtfStatus::Missing
+        );
+        let empty = dir.path().join("empty");
+        std::fs::write(&empty, b"").unwrap();
+        // 存在但无法读出 btf 的按不可用处理，而不是交给 libbpf
+        for unusable in [empty.as_path(), dir.path()] {
+            assert_eq!(status(unusable), NativeBtfStatus::Unusable);
+            let (ret, path) = resolve(&native_opts(unusable));
+            assert_eq!(ret, 0);
+            let path = path.unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), minimal_valid_btf());
+            remove_owned_btf(path.as_os_str().as_bytes(), false);
+        }
+    }
+
+    #[test]
+    fn unreadable_native_btf_falls_back_to_the_archive() {
+        use std::os::unix::fs::PermissionsExt;
+        // root 不受文件权限限制
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let vmlinux = dir.path().join("vmlinux");
+        std::fs::write(&vmlinux, minimal_valid_btf()).unwrap();
+        std::fs::set_permissions(&vmlinux, std::fs::Permissions::from_mode(0o000)).unwrap();
+        let opts = Options {
+            tmpdir: Some(dir.path().into()),
+            ..native_opts(&vmlinux)
+        };
+        assert_eq!(native_btf_status(&opts), NativeBtfStatus::Unusable);
+        let (ret, path) = resolve(&opts);
+        assert_eq!(ret, 0);
+        assert!(path.unwrap().starts_with(dir.path()));
     }
-    unsafe { libc::free(path as *mut c_void) };
 }
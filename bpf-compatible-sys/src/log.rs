@@ -0,0 +1,88 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Where diagnostics go: a callback registered by the caller, or stderr by default.
+use std::{
+    ffi::{c_char, c_int, CString},
+    sync::{Arc, RwLock},
+};
+
+use bpf_compatible_rs::log::Level;
+use libc::c_void;
+
+use crate::{BPF_COMPAT_LOG_DEBUG, BPF_COMPAT_LOG_ERROR, BPF_COMPAT_LOG_INFO};
+
+/// Callback receiving the diagnostics, see `bpf_compatible_set_log_fn`
+pub type LogFn = unsafe extern "C" fn(level: c_int, msg: *const c_char, ctx: *mut c_void);
+
+struct Logger {
+    log_fn: LogFn,
+    /// 调用者的上下文指针，库本身不会解引用
+    ctx: usize,
+}
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Log a failure, see `report!` for failures the caller should see through `bpf_compatible_last_error`
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::BPF_COMPAT_LOG_ERROR, &format!($($arg)*))
+    };
+}
+
+/// Log something worth knowing that doesn't make the call fail, e.g. a fallback
+macro_rules! note {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::BPF_COMPAT_LOG_INFO, &format!($($arg)*))
+    };
+}
+
+/// Log a detail of the lookup, e.g. the entry that matched; dropped unless a callback is set
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::BPF_COMPAT_LOG_DEBUG, &format!($($arg)*))
+    };
+}
+
+/// Replace the callback, or restore the default with `None`
+///
+/// While a callback is set, the messages of `bpf_compatible_rs` are forwarded to it too;
+/// by default they are dropped, as they were before callbacks existed. Returns once no
+/// thread runs the previous callback anymore, so its context may be released.
+pub(crate) fn set_logger(log_fn: Option<LogFn>, ctx: *mut c_void) {
+    let logger = log_fn.map(|log_fn| Logger {
+        log_fn,
+        ctx: ctx as usize,
+    });
+    let forward = logger.is_some();
+    // 持有写锁期间同时替换两处，并发的设置不会交错；写锁会等待正在执行的回调返回
+    let mut current = LOGGER.write().unwrap_or_else(|e| e.into_inner());
+    *current = logger;
+    bpf_compatible_rs::log::set_logger(forward.then(|| Arc::new(forward_rs_message) as _));
+}
+
+/// Pass a message of `bpf_compatible_rs` on at the matching level
+fn forward_rs_message(level: Level, message: &str) {
+    let level = match level {
+        Level::Error => BPF_COMPAT_LOG_ERROR,
+        Level::Warn => BPF_COMPAT_LOG_INFO,
+        Level::Info | Level::Debug => BPF_COMPAT_LOG_DEBUG,
+    };
+    log(level, message);
+}
+
+/// Hand `message` to the callback, or print it to stderr unless it's a debug message
+pub(crate) fn log(level: c_int, message: &str) {
+    let logger = LOGGER.read().unwrap_or_else(|e| e.into_inner());
+    match logger.as_ref() {
+        Some(logger) => {
+            // 消息中的 NUL 会截断 C 字符串，替换掉
+            let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+            unsafe { (logger.log_fn)(level, message.as_ptr(), logger.ctx as *mut c_void) };
+        }
+        None if level != BPF_COMPAT_LOG_DEBUG => eprintln!("{}", message),
+        None => {}
+    }
+}
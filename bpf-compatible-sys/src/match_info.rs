@@ -0,0 +1,141 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `struct bpf_compat_match_info` of the C API, describing the btf the last lookup of the
+//! thread settled on
+use std::{
+    cell::{Cell, RefCell},
+    ffi::{c_char, c_int},
+    mem::size_of,
+};
+
+use crate::{
+    platform::OsStrExt,
+    sized::{copy_c_string, write_sized, SizedStruct},
+    BPF_COMPAT_NATIVE_STATUS_IGNORED, BPF_COMPAT_NATIVE_STATUS_MISSING,
+    BPF_COMPAT_NATIVE_STATUS_UNKNOWN, BPF_COMPAT_NATIVE_STATUS_UNUSABLE,
+    BPF_COMPAT_NATIVE_STATUS_USABLE, BPF_COMPAT_SOURCE_ARCHIVE, BPF_COMPAT_SOURCE_CACHE,
+    BPF_COMPAT_SOURCE_DOWNLOAD, BPF_COMPAT_SOURCE_INSTALLED, BPF_COMPAT_SOURCE_NATIVE,
+    BPF_COMPAT_SOURCE_OVERRIDE, BPF_COMPAT_SOURCE_PAHOLE,
+};
+use bpf_compatible_rs::{BtfSource, MatchInfo, NativeBtfStatus};
+
+/// Capacity of `entry_path`, NUL included; longer paths are truncated
+const ENTRY_PATH_SIZE: usize = 256;
+/// Capacity of `kernel_release`, NUL included
+const KERNEL_RELEASE_SIZE: usize = 128;
+
+/// The btf a lookup settled on, see `ensure_core_btf_with_tar_binary_match`
+///
+/// Like `struct bpf_compat_archive_info`, `sz` must be set to
+/// `sizeof(struct bpf_compat_match_info)` by the caller, and only that many bytes are
+/// written, so the struct can grow.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfCompatMatchInfo {
+    pub sz: usize,
+    /// One of `BPF_COMPAT_SOURCE_*`
+    pub source: c_int,
+    /// Whether the btf is that of the kernel release itself
+    pub exact: bool,
+    /// Path of the entry in the archive, or of the file on disk, NUL-terminated; empty if unknown
+    pub entry_path: [c_char; ENTRY_PATH_SIZE],
+    /// Release of the kernel the btf is of, NUL-terminated
+    pub kernel_release: [c_char; KERNEL_RELEASE_SIZE],
+    /// Whether the release only matched without the local version of the running kernel
+    pub local_version_stripped: bool,
+    /// Position of `entry_path` among the archive paths of the system, most preferred
+    /// first; -1 if it's none of them
+    pub candidate: c_int,
+    /// One of `BPF_COMPAT_NATIVE_STATUS_*`, what the lookup made of the kernel's own btf
+    pub native_status: c_int,
+    /// Whether the btf was extracted by an earlier call and reused, the rest describing it
+    /// as that call found it
+    pub memoized: bool,
+}
+
+thread_local! {
+    static LAST_MATCH: RefCell<Option<MatchInfo>> = const { RefCell::new(None) };
+    static NATIVE_BTF: Cell<NativeBtfStatus> = const { Cell::new(NativeBtfStatus::Unknown) };
+}
+
+/// Forget the match of the previous lookup, before a new one
+pub(crate) fn clear() {
+    LAST_MATCH.with(|v| *v.borrow_mut() = None);
+    NATIVE_BTF.with(|v| v.set(NativeBtfStatus::Unknown));
+}
+
+/// Keep what the lookup of the thread made of the kernel's own btf, for the match recorded after
+pub(crate) fn record_native_btf(status: NativeBtfStatus) {
+    NATIVE_BTF.with(|v| v.set(status));
+}
+
+/// Keep `matched` as the btf the lookup of the thread settled on, replacing what an earlier stage recorded
+pub(crate) fn record(matched: MatchInfo) {
+    let matched = MatchInfo {
+        native_btf: NATIVE_BTF.with(Cell::get),
+        ..matched
+    };
+    LAST_MATCH.with(|v| *v.borrow_mut() = Some(matched));
+}
+
+/// The match recorded on the thread, if any
+pub(crate) fn last() -> Option<MatchInfo> {
+    LAST_MATCH.with(|v| v.borrow().clone())
+}
+
+/// Copy the match recorded on the thread to the caller's struct of `sz` bytes
+///
+/// Nothing is written if no match was recorded.
+pub(crate) fn write_match(out: *mut BpfCompatMatchInfo, sz: usize) {
+    if let Some(matched) = last() {
+        write_sized(out, &(&matched).into(), sz);
+    }
+}
+
+unsafe impl SizedStruct for BpfCompatMatchInfo {
+    const NAME: &'static str = "bpf_compat_match_info";
+}
+
+impl From<&MatchInfo> for BpfCompatMatchInfo {
+    fn from(matched: &MatchInfo) -> Self {
+        let mut raw = Self {
+            sz: size_of::<Self>(),
+            source: match matched.source {
+                BtfSource::Native => BPF_COMPAT_SOURCE_NATIVE,
+                BtfSource::Installed => BPF_COMPAT_SOURCE_INSTALLED,
+                BtfSource::Archive => BPF_COMPAT_SOURCE_ARCHIVE,
+                BtfSource::Cache => BPF_COMPAT_SOURCE_CACHE,
+                BtfSource::Download => BPF_COMPAT_SOURCE_DOWNLOAD,
+                BtfSource::Override => BPF_COMPAT_SOURCE_OVERRIDE,
+                BtfSource::Pahole => BPF_COMPAT_SOURCE_PAHOLE,
+            },
+            exact: matched.exact,
+            entry_path: [0; ENTRY_PATH_SIZE],
+            kernel_release: [0; KERNEL_RELEASE_SIZE],
+            local_version_stripped: matched.local_version_stripped,
+            candidate: matched
+                .candidate
+                .and_then(|v| c_int::try_from(v).ok())
+                .unwrap_or(-1),
+            native_status: match matched.native_btf {
+                NativeBtfStatus::Unknown => BPF_COMPAT_NATIVE_STATUS_UNKNOWN,
+                NativeBtfStatus::Usable => BPF_COMPAT_NATIVE_STATUS_USABLE,
+                NativeBtfStatus::Missing => BPF_COMPAT_NATIVE_STATUS_MISSING,
+                NativeBtfStatus::Unusable => BPF_COMPAT_NATIVE_STATUS_UNUSABLE,
+                NativeBtfStatus::Ignored => BPF_COMPAT_NATIVE_STATUS_IGNORED,
+            },
+            memoized: matched.memoized,
+        };
+        let entry_path = matched
+            .entry_path
+            .as_ref()
+            .map(|v| v.as_os_str().as_bytes())
+            .unwrap_or_default();
+        copy_c_string(&mut raw.entry_path, entry_path);
+        copy_c_string(&mut raw.kernel_release, matched.kernel_release.as_bytes());
+        raw
+    }
+}
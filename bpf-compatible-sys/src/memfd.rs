@@ -0,0 +1,128 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Anonymous memory files holding extracted btfs, for systems where no temporary
+//! file may be created. The btf is handed out as `/proc/self/fd/<fd>`, which libbpf
+//! can open like any other path. Other systems than Linux have no memfds.
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::{
+    ffi::{c_int, CString},
+    fs::File,
+    io::{Read, Seek},
+};
+
+#[cfg(target_os = "linux")]
+use libc::EIO;
+#[cfg(not(target_os = "linux"))]
+use libc::ENOTSUP;
+
+use crate::extract::{stream_errno, BtfSink};
+
+/// Name of the memfds, as shown by `readlink /proc/self/fd/<fd>`
+#[cfg(target_os = "linux")]
+const MEMFD_NAME: &str = "eunomia.btf";
+/// Prefix of the paths handed out for memfds
+#[cfg(target_os = "linux")]
+const PROC_SELF_FD: &str = "/proc/self/fd/";
+
+/// A memfd holding an extracted btf, closed on drop unless kept with `keep`
+pub(crate) struct BtfMemfd {
+    file: File,
+}
+
+#[cfg(target_os = "linux")]
+impl BtfMemfd {
+    pub(crate) fn create() -> Result<Self, c_int> {
+        let name = CString::new(MEMFD_NAME).unwrap_or_default();
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if fd < 0 {
+            let e = std::io::Error::last_os_error();
+            report!("Failed to create a memfd to store the btf: {}", e);
+            return Err(-e.raw_os_error().unwrap_or(EIO));
+        }
+        Ok(Self {
+            file: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Seal the memfd against any further modification, returning the path to open it with
+    pub(crate) fn seal(&self) -> Result<CString, c_int> {
+        let seals =
+            libc::F_SEAL_WRITE | libc::F_SEAL_GROW | libc::F_SEAL_SHRINK | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+            let e = std::io::Error::last_os_error();
+            report!("Failed to seal the memfd: {}", e);
+            return Err(-e.raw_os_error().unwrap_or(EIO));
+        }
+        Ok(CString::new(format!("{}{}", PROC_SELF_FD, self.file.as_raw_fd())).unwrap_or_default())
+    }
+
+    /// Keep the memfd open after the handle is dropped, until `close_memfd_path` is called
+    pub(crate) fn keep(self) {
+        let _ = self.file.into_raw_fd();
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl BtfMemfd {
+    /// Fails with `-ENOTSUP`, there are no memfds on other systems than Linux
+    pub(crate) fn create() -> Result<Self, c_int> {
+        Err(-ENOTSUP)
+    }
+
+    pub(crate) fn seal(&self) -> Result<CString, c_int> {
+        Err(-ENOTSUP)
+    }
+
+    pub(crate) fn keep(self) {}
+}
+
+impl BtfSink for BtfMemfd {
+    fn overwrite_from(&mut self, reader: &mut dyn Read) -> Result<(), c_int> {
+        let result = self
+            .file
+            .set_len(0)
+            .and_then(|_| self.file.rewind())
+            .and_then(|_| std::io::copy(reader, &mut self.file));
+        if let Err(e) = result {
+            report!("Failed to write btf things to the memfd: {}", e);
+            return Err(stream_errno(&e));
+        }
+        Ok(())
+    }
+}
+
+/// If `path` was handed out by `BtfMemfd::seal`, close the memfd and return true
+///
+/// The fd is only closed if it still refers to one of our memfds, so a path that
+/// merely looks alike never closes an unrelated fd
+#[cfg(target_os = "linux")]
+pub(crate) fn close_memfd_path(path: &[u8]) -> bool {
+    let Some(fd) = std::str::from_utf8(path)
+        .ok()
+        .and_then(|v| v.strip_prefix(PROC_SELF_FD))
+        .and_then(|v| v.parse::<c_int>().ok())
+    else {
+        return false;
+    };
+    let expected = format!("/memfd:{}", MEMFD_NAME);
+    // memfd 的链接目标形如 `/memfd:eunomia.btf (deleted)`
+    match std::fs::read_link(format!("{}{}", PROC_SELF_FD, fd)) {
+        Ok(target) if target.to_string_lossy().starts_with(&expected) => {
+            unsafe { libc::close(fd) };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Always false on other systems than Linux, which have no memfds
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn close_memfd_path(_path: &[u8]) -> bool {
+    false
+}
@@ -0,0 +1,370 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! In-process memory of earlier lookups
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    ffi::{c_char, OsStr},
+    hash::Hasher,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use bpf_compatible_rs::{
+    gc::{self, TempfileTemplate},
+    MatchInfo, SystemInfo,
+};
+
+use crate::platform::OsStrExt;
+
+/// Upper bound of remembered misses, the set is simply cleared when reaching it
+const MAX_NEGATIVE_ENTRIES: usize = 64;
+
+/// Paths of cached (or operator provided) btfs handed out to the caller, which must survive `clean_core_btf_rs`
+static CACHED_PATHS: Mutex<Option<HashSet<Vec<u8>>>> = Mutex::new(None);
+
+/// Temporary files this library created and handed out, the only ones `clean_core_btf_rs` removes
+static CREATED_PATHS: Mutex<Option<HashMap<Vec<u8>, Option<FileIdentity>>>> = Mutex::new(None);
+
+/// Device and inode of a file, to tell it from another file put under its path later
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileIdentity {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+/// Addresses of the path strings handed out and not cleaned yet
+static HANDED_OUT: Mutex<Option<HashSet<usize>>> = Mutex::new(None);
+
+/// Held while a btf is extracted and handed out, or cleaned, see `lock_extraction`
+static EXTRACTION: Mutex<()> = Mutex::new(());
+
+/// Serialize the extractions and cleanups of the process
+///
+/// Threads looking the btf up at the same time wait for the first one to extract it, and
+/// are then handed the same file by `memoized_btf` (or the cache). Cleaning a file while
+/// holding it can't remove one another thread found in the memo and is about to return.
+pub(crate) fn lock_extraction() -> MutexGuard<'static, ()> {
+    // 提取中的 panic 已由 last_error::track 捕获，锁本身不保护任何数据，忽略中毒
+    EXTRACTION.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Identity of an archive, a hash of all of its bytes
+///
+/// Only a gzip trailer covers the whole of the data, while plain tars, zstd frames without
+/// a checksum and the entries an `INDEX` points at may change in the middle, keeping the
+/// size and both ends; hashing all of it is still far cheaper than decompressing it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ArchiveFingerprint {
+    len: usize,
+    hash: u64,
+}
+
+impl ArchiveFingerprint {
+    pub(crate) fn of(archive: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(archive);
+        Self {
+            len: archive.len(),
+            hash: hasher.finish(),
+        }
+    }
+}
+
+/// The expected entry paths of a lookup within a given archive
+type NegativeKey = (ArchiveFingerprint, Vec<Vec<u8>>);
+
+/// Lookups known to find none of their expected entry paths in an archive
+static NEGATIVE_CACHE: Mutex<Option<HashSet<NegativeKey>>> = Mutex::new(None);
+
+fn negative_key(archive: ArchiveFingerprint, entries: &[PathBuf]) -> NegativeKey {
+    (
+        archive,
+        entries
+            .iter()
+            .map(|v| v.as_os_str().as_bytes().to_vec())
+            .collect(),
+    )
+}
+
+/// Whether an earlier lookup of `entries` in this archive found nothing
+pub(crate) fn is_known_miss(archive: ArchiveFingerprint, entries: &[PathBuf]) -> bool {
+    NEGATIVE_CACHE
+        .lock()
+        .map(|cache| {
+            cache
+                .as_ref()
+                .is_some_and(|v| v.contains(&negative_key(archive, entries)))
+        })
+        .unwrap_or(false)
+}
+
+/// Remember that none of `entries` are in this archive
+pub(crate) fn record_miss(archive: ArchiveFingerprint, entries: &[PathBuf]) {
+    if let Ok(mut cache) = NEGATIVE_CACHE.lock() {
+        let cache = cache.get_or_insert_with(HashSet::new);
+        if cache.len() >= MAX_NEGATIVE_ENTRIES {
+            cache.clear();
+        }
+        cache.insert(negative_key(archive, entries));
+    }
+}
+
+/// Remember that `path` was handed out from the persistent cache, or is the operator's btf
+pub(crate) fn record_cached_path(path: &[u8]) {
+    if let Ok(mut paths) = CACHED_PATHS.lock() {
+        paths.get_or_insert_with(HashSet::new).insert(path.to_vec());
+    }
+}
+
+/// Forget a path that `record_cached_path` remembered, returning whether it was one
+pub(crate) fn take_cached_path(path: &[u8]) -> bool {
+    CACHED_PATHS
+        .lock()
+        .map(|mut paths| paths.as_mut().is_some_and(|v| v.remove(path)))
+        .unwrap_or(false)
+}
+
+/// Remember that `path` is a temporary file created by this library, named after `template`,
+/// whose inode is `identity` if known
+///
+/// It's also recorded for `bpf_compatible_register_cleanup_at_exit`, and left alone by
+/// `bpf_compatible_gc_stale_btf_tempfiles` until cleaned.
+pub(crate) fn record_created_path(
+    path: &[u8],
+    template: &TempfileTemplate,
+    identity: Option<FileIdentity>,
+) {
+    if let Ok(mut paths) = CREATED_PATHS.lock() {
+        paths
+            .get_or_insert_with(HashMap::new)
+            .insert(path.to_vec(), identity);
+    }
+    gc::record_created_with(Path::new(OsStr::from_bytes(path)), template);
+}
+
+/// Forget a path that `record_created_path` remembered, returning whether it was one
+pub(crate) fn take_created_path(path: &[u8]) -> bool {
+    take_created_file(path).is_some()
+}
+
+/// Same as `take_created_path`, returning the inode recorded with the path if it was one
+pub(crate) fn take_created_file(path: &[u8]) -> Option<Option<FileIdentity>> {
+    gc::forget_created(Path::new(OsStr::from_bytes(path)));
+    CREATED_PATHS
+        .lock()
+        .ok()?
+        .as_mut()
+        .and_then(|v| v.remove(path))
+}
+
+/// Remember the address of a path string handed out to the caller
+pub(crate) fn record_handed_out(ptr: *const c_char) {
+    if let Ok(mut ptrs) = HANDED_OUT.lock() {
+        ptrs.get_or_insert_with(HashSet::new).insert(ptr as usize);
+    }
+}
+
+/// Forget a string that `record_handed_out` remembered, returning whether it was one
+///
+/// A string is only cleaned once: a second call with the same pointer finds nothing.
+pub(crate) fn take_handed_out(ptr: *const c_char) -> bool {
+    HANDED_OUT
+        .lock()
+        .map(|mut ptrs| ptrs.as_mut().is_some_and(|v| v.remove(&(ptr as usize))))
+        .unwrap_or(false)
+}
+
+/// What the btf extracted by a lookup depends on
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct ExtractionKey {
+    pub archive: ArchiveFingerprint,
+    pub system: SystemInfo,
+    /// The options the lookup depends on, e.g. the match policy, formatted
+    pub lookup: String,
+}
+
+/// The temporary file the last lookup extracted the btf to, handed out again by later ones
+struct MemoizedBtf {
+    key: ExtractionKey,
+    path: Vec<u8>,
+    size: u64,
+    matched: Option<MatchInfo>,
+}
+
+static MEMOIZED: Mutex<Option<MemoizedBtf>> = Mutex::new(None);
+
+/// Temporary files handed out again by `memoized_btf`, with the number of copies not cleaned yet
+static EXTRA_HANDOUTS: Mutex<Option<HashMap<Vec<u8>, usize>>> = Mutex::new(None);
+
+/// Remember that the lookup `key` extracted the btf to `path`, matching `matched`
+pub(crate) fn memoize(key: ExtractionKey, path: &[u8], matched: Option<MatchInfo>) {
+    let Ok(metadata) = std::fs::metadata(OsStr::from_bytes(path)) else {
+        return;
+    };
+    if let Ok(mut memoized) = MEMOIZED.lock() {
+        *memoized = Some(MemoizedBtf {
+            key,
+            path: path.to_vec(),
+            size: metadata.len(),
+            matched,
+        });
+    }
+}
+
+/// The file an earlier lookup `key` extracted the btf to, if it's still there with the same size
+///
+/// A file that vanished or changed is forgotten, so the btf is extracted again.
+pub(crate) fn memoized_btf(key: &ExtractionKey) -> Option<(Vec<u8>, Option<MatchInfo>)> {
+    let mut memoized = MEMOIZED.lock().ok()?;
+    let btf = memoized.as_ref().filter(|v| v.key == *key)?;
+    let intact = std::fs::metadata(OsStr::from_bytes(&btf.path))
+        .is_ok_and(|v| v.is_file() && v.len() == btf.size);
+    if !intact {
+        debug!(
+            "{} was removed or changed since it was extracted, extracting the btf again",
+            OsStr::from_bytes(&btf.path).to_string_lossy()
+        );
+        *memoized = None;
+        return None;
+    }
+    Some((btf.path.clone(), btf.matched.clone()))
+}
+
+/// Forget the memoized btf if it's at `path`, since it's being cleaned
+pub(crate) fn forget_memoized(path: &[u8]) {
+    if let Ok(mut memoized) = MEMOIZED.lock() {
+        if memoized.as_ref().is_some_and(|v| v.path == path) {
+            *memoized = None;
+        }
+    }
+}
+
+/// Remember that `path` was handed out once more, see `memoized_btf`
+pub(crate) fn record_extra_handout(path: &[u8]) {
+    if let Ok(mut handouts) = EXTRA_HANDOUTS.lock() {
+        *handouts
+            .get_or_insert_with(HashMap::new)
+            .entry(path.to_vec())
+            .or_default() += 1;
+    }
+}
+
+/// Count one copy of `path` as cleaned, returning whether another one is still handed out
+///
+/// The file must then be left in place for the other holders.
+pub(crate) fn take_extra_handout(path: &[u8]) -> bool {
+    let Ok(mut handouts) = EXTRA_HANDOUTS.lock() else {
+        return false;
+    };
+    let Some(handouts) = handouts.as_mut() else {
+        return false;
+    };
+    match handouts.get_mut(path) {
+        Some(1) => {
+            handouts.remove(path);
+            true
+        }
+        Some(count) => {
+            *count -= 1;
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_change_with_any_byte_of_the_archive() {
+        let archive = (0..3 << 16).map(|v| v as u8).collect::<Vec<_>>();
+        let fingerprint = ArchiveFingerprint::of(&archive);
+        assert!(fingerprint == ArchiveFingerprint::of(&archive.clone()));
+        let mut shorter = archive.clone();
+        shorter.pop();
+        assert!(ArchiveFingerprint::of(&shorter) != fingerprint);
+        // 开头、中间和结尾的修改都能发现，大小不变也一样
+        for at in [0, archive.len() / 2, archive.len() - 1] {
+            let mut changed = archive.clone();
+            changed[at] ^= 1;
+            assert!(ArchiveFingerprint::of(&changed) != fingerprint, "{at}");
+        }
+        assert!(ArchiveFingerprint::of(b"a") != ArchiveFingerprint::of(b"b"));
+        assert!(ArchiveFingerprint::of(b"") == ArchiveFingerprint::of(b""));
+    }
+
+    #[test]
+    fn misses_are_remembered_per_archive_and_entries() {
+        let archive = ArchiveFingerprint::of(b"misses_are_remembered");
+        let entries = [PathBuf::from(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0.btf",
+        )];
+        assert!(!is_known_miss(archive, &entries));
+        record_miss(archive, &entries);
+        assert!(is_known_miss(archive, &entries));
+        // 另一份归档，或另一组路径，都不算
+        let other = ArchiveFingerprint::of(b"another archive");
+        assert!(!is_known_miss(other, &entries));
+        assert!(!is_known_miss(archive, &[PathBuf::from("other.btf")]));
+    }
+
+    #[test]
+    fn extra_handouts_are_counted_down() {
+        let path = b"/tmp/extra_handouts_are_counted_down.btf";
+        assert!(!take_extra_handout(path));
+        record_extra_handout(path);
+        record_extra_handout(path);
+        assert!(take_extra_handout(path));
+        assert!(take_extra_handout(path));
+        // 最后一份由创建它的查找持有
+        assert!(!take_extra_handout(path));
+    }
+
+    #[test]
+    fn paths_are_taken_once() {
+        let cached = b"/tmp/paths_are_taken_once.cached";
+        record_cached_path(cached);
+        assert!(take_cached_path(cached));
+        assert!(!take_cached_path(cached));
+
+        let mut value = 0u8;
+        let ptr = &mut value as *mut u8 as *const c_char;
+        assert!(!take_handed_out(ptr));
+        record_handed_out(ptr);
+        assert!(take_handed_out(ptr));
+        assert!(!take_handed_out(ptr));
+    }
+
+    #[test]
+    fn memoized_btfs_must_be_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memoized.btf");
+        std::fs::write(&path, b"btf").unwrap();
+        let path = path.as_os_str().as_bytes();
+        let key = ExtractionKey {
+            archive: ArchiveFingerprint::of(b"memoized_btfs_must_be_intact"),
+            system: SystemInfo::default(),
+            lookup: String::new(),
+        };
+        // 记忆只有一份，其他测试可能替换它，因此只检查与本测试的键对应的结果
+        memoize(key.clone(), path, None);
+        if let Some((memoized, _)) = memoized_btf(&key) {
+            assert_eq!(memoized, path);
+        }
+        let other = ExtractionKey {
+            lookup: "other".into(),
+            ..key.clone()
+        };
+        assert!(memoized_btf(&other).is_none());
+        // 大小改变的文件被遗忘
+        std::fs::write(OsStr::from_bytes(path), b"changed").unwrap();
+        assert!(memoized_btf(&key).is_none());
+        memoize(key.clone(), path, None);
+        forget_memoized(path);
+        assert!(memoized_btf(&key).is_none());
+    }
+}
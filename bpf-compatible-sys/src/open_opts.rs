@@ -0,0 +1,155 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `btf_custom_path` of libbpf's `struct bpf_object_open_opts`, set without linking libbpf
+use std::{
+    ffi::{c_char, c_int},
+    mem::{offset_of, size_of},
+};
+
+use libc::EINVAL;
+
+/// The start of `struct bpf_object_open_opts`, up to `btf_custom_path`, as of libbpf 0.6
+///
+/// The fields before it haven't moved since, and libbpf only ever appends, so the offset
+/// holds for every later version.
+#[repr(C)]
+struct OpenOptsPrefix {
+    sz: usize,
+    object_name: *const c_char,
+    relaxed_maps: bool,
+    pin_root_path: *const c_char,
+    /// The removed `attach_prog_fd`, kept as padding by libbpf
+    _unused: u32,
+    kconfig: *const c_char,
+    btf_custom_path: *const c_char,
+}
+
+/// Offset of `btf_custom_path` in `struct bpf_object_open_opts`
+pub(crate) const BTF_CUSTOM_PATH_OFFSET: usize = offset_of!(OpenOptsPrefix, btf_custom_path);
+
+/// Set `btf_custom_path` of the open options at `opts`, of `opts_sz` bytes, to `btf_path`
+///
+/// Fails with `-EINVAL` if the struct is too small to have the field, i.e. from a libbpf
+/// older than 0.6. Nothing else is written, `sz` included.
+pub(crate) fn fill_btf_custom_path(
+    opts: *mut u8,
+    opts_sz: usize,
+    btf_path: *const c_char,
+) -> c_int {
+    if opts_sz < BTF_CUSTOM_PATH_OFFSET + size_of::<*const c_char>() {
+        report!(
+            "struct bpf_object_open_opts of {} bytes has no btf_custom_path",
+            opts_sz
+        );
+        return -EINVAL;
+    }
+    // 调用者的结构体不一定按指针对齐（如嵌在打包的结构体中），按非对齐方式写入
+    unsafe { (opts.add(BTF_CUSTOM_PATH_OFFSET) as *mut *const c_char).write_unaligned(btf_path) };
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    /// `struct bpf_object_open_opts` as of libbpf 1.2, the layout this is tested against
+    #[repr(C)]
+    struct BpfObjectOpenOpts {
+        sz: usize,
+        object_name: *const c_char,
+        relaxed_maps: bool,
+        pin_root_path: *const c_char,
+        _unused: u32,
+        kconfig: *const c_char,
+        btf_custom_path: *const c_char,
+        kernel_log_buf: *mut c_char,
+        kernel_log_size: usize,
+        kernel_log_level: u32,
+    }
+
+    fn opts() -> BpfObjectOpenOpts {
+        BpfObjectOpenOpts {
+            sz: size_of::<BpfObjectOpenOpts>(),
+            object_name: c"prog".as_ptr(),
+            relaxed_maps: true,
+            pin_root_path: c"/sys/fs/bpf".as_ptr(),
+            _unused: u32::MAX,
+            kconfig: c"CONFIG_X=y".as_ptr(),
+            btf_custom_path: ptr::null(),
+            kernel_log_buf: ptr::null_mut(),
+            kernel_log_size: 4096,
+            kernel_log_level: 1,
+        }
+    }
+
+    #[test]
+    fn offset_is_that_of_libbpf() {
+        assert_eq!(
+            BTF_CUSTOM_PATH_OFFSET,
+            offset_of!(BpfObjectOpenOpts, btf_custom_path)
+        );
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(BTF_CUSTOM_PATH_OFFSET, 48);
+    }
+
+    #[test]
+    fn only_btf_custom_path_is_written() {
+        let path = c"/tmp/eunomia.btf.abc123";
+        let mut opts = opts();
+        let sz = opts.sz;
+        assert_eq!(
+            fill_btf_custom_path(&mut opts as *mut _ as *mut u8, sz, path.as_ptr()),
+            0
+        );
+        assert_eq!(opts.btf_custom_path, path.as_ptr());
+        let untouched = self::opts();
+        assert_eq!(opts.sz, untouched.sz);
+        assert_eq!(opts.object_name, untouched.object_name);
+        assert!(opts.relaxed_maps);
+        assert_eq!(opts.pin_root_path, untouched.pin_root_path);
+        assert_eq!(opts._unused, u32::MAX);
+        assert_eq!(opts.kconfig, untouched.kconfig);
+        assert_eq!(opts.kernel_log_size, 4096);
+        assert_eq!(opts.kernel_log_level, 1);
+
+        // 内核自带 btf 时置空
+        fill_btf_custom_path(&mut opts as *mut _ as *mut u8, sz, ptr::null());
+        assert!(opts.btf_custom_path.is_null());
+    }
+
+    #[test]
+    fn options_without_the_field_are_rejected() {
+        let mut bytes = [0xaau8; BTF_CUSTOM_PATH_OFFSET + size_of::<*const c_char>()];
+        assert_eq!(
+            fill_btf_custom_path(bytes.as_mut_ptr(), bytes.len() - 1, c"x".as_ptr()),
+            -EINVAL
+        );
+        assert!(bytes.iter().all(|v| *v == 0xaa));
+        // 恰好容纳该字段即可
+        assert_eq!(
+            fill_btf_custom_path(bytes.as_mut_ptr(), bytes.len(), ptr::null()),
+            0
+        );
+        assert!(bytes[BTF_CUSTOM_PATH_OFFSET..].iter().all(|v| *v == 0));
+        assert!(bytes[..BTF_CUSTOM_PATH_OFFSET].iter().all(|v| *v == 0xaa));
+    }
+
+    #[test]
+    fn unaligned_options_are_written() {
+        let path = c"/tmp/unaligned.btf";
+        let mut bytes = vec![0u8; 1 + size_of::<BpfObjectOpenOpts>()];
+        let opts = unsafe { bytes.as_mut_ptr().add(1) };
+        assert_eq!(
+            fill_btf_custom_path(opts, size_of::<BpfObjectOpenOpts>(), path.as_ptr()),
+            0
+        );
+        let written =
+            unsafe { (opts.add(BTF_CUSTOM_PATH_OFFSET) as *const *const c_char).read_unaligned() };
+        assert_eq!(written, path.as_ptr());
+    }
+}
@@ -0,0 +1,597 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+use std::{
+    ffi::{c_char, c_int, CStr, OsStr, OsString},
+    mem::size_of,
+    ops::ControlFlow,
+    path::PathBuf,
+};
+
+use bpf_compatible_rs::{
+    archive::BTFHUB_ARCHIVE_DIR,
+    chain::{check_chain, Strategy},
+    compression::DEFAULT_MAX_DECOMPRESSED_SIZE,
+    gc::TempfileTemplate,
+    native::NativeBtfProbe,
+    progress::Progress,
+    release::MatchPolicy,
+    system::under_root,
+    SystemInfo, VMLINUX_BTF_PATH,
+};
+use libc::{c_void, E2BIG, EINVAL, ENOTSUP};
+
+use crate::{chain::strategy_from_raw, platform::OsStrExt};
+
+/// Allocation function handed out through `struct bpf_compat_opts`
+pub type AllocFn = unsafe extern "C" fn(usize) -> *mut c_void;
+/// Deallocation function handed out through `struct bpf_compat_opts`
+pub type FreeFn = unsafe extern "C" fn(*mut c_void);
+/// Progress callback of `struct bpf_compat_opts`, returning non-zero to cancel the lookup
+pub type ProgressFn =
+    unsafe extern "C" fn(bytes_processed: u64, bytes_total_hint: u64, ctx: *mut c_void) -> c_int;
+
+/// `struct bpf_compat_opts` of the C API
+///
+/// Like libbpf's opts structs, `sz` must be set to `sizeof(struct bpf_compat_opts)`
+/// by the caller, so the struct can grow without breaking older callers: fields beyond
+/// `sz` are treated as zero. A caller built against a later version may pass a larger
+/// struct, as long as the fields this library doesn't know about are zero.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfCompatOpts {
+    pub sz: usize,
+    /// Allocator for the buffers returned to the caller, the one of `bpf_compatible_set_allocator` (`malloc`) if NULL
+    pub alloc: Option<AllocFn>,
+    /// Deallocator matching `alloc`, `free` if NULL
+    pub free: Option<FreeFn>,
+    /// Store the btf in a sealed memfd and return its `/proc/self/fd/<fd>` path,
+    /// instead of creating a temporary file
+    pub use_memfd: bool,
+    /// Directory for the temporary file, created with mode 0700 if missing;
+    /// `$TMPDIR` (or `/tmp`) if NULL
+    pub tmpdir: *const c_char,
+    /// Keep the btf in the persistent cache (`$XDG_CACHE_HOME/bpf-compatible`) and
+    /// return the cached file directly on later calls
+    pub use_cache: bool,
+    /// With `use_cache`, extract the btf again even if it is already cached
+    pub refresh_cache: bool,
+    /// If the archive has no btf for the exact kernel release, use the one of the nearest
+    /// point release with the same major.minor and flavor in the same directory; same as
+    /// `match_policy` 1
+    pub nearest_fallback: bool,
+    /// How strictly the btf must match the running kernel: 0 for exact matches only,
+    /// 1 for the nearest point release of the same flavor, 2 for best effort, see
+    /// `bpf_compatible_rs::release::MatchPolicy`
+    pub match_policy: c_int,
+    /// Root of the filesystem the distro is detected in and `/sys/kernel/btf/vmlinux` is
+    /// looked for, e.g. the host's root mounted into a container; `/` if NULL
+    pub sysroot: *const c_char,
+    /// Directory of the archive holding the btfs, `btfhub-archive` if NULL; an empty
+    /// string means the entries start directly with `<distro>/`
+    pub archive_prefix: *const c_char,
+    /// If the kernel has native btf, still set the path, to `/sys/kernel/btf/vmlinux`
+    /// (under `sysroot`), so it can be used as `btf_custom_path` unconditionally
+    pub always_path: bool,
+    /// Fail if the btf can't be checked against a `SHA256SUMS` manifest of the archive,
+    /// instead of only checking it when there is one
+    pub require_verification: bool,
+    /// If the archive has no btf for the kernel, download it from btfhub-archive into the
+    /// persistent cache; needs the `download` feature, see `BPF_COMPATIBLE_DOWNLOAD`
+    pub allow_download: bool,
+    /// Url of the btfs to download, with `{distro}`, `{version}`, `{arch}` and `{kernel}`
+    /// placeholders; `BPF_COMPATIBLE_DOWNLOAD_URL`, or btfhub-archive on GitHub, if NULL
+    pub download_url: *const c_char,
+    /// Extract the btf to `<tmpdir>/<kernel release>-<hash>.btf`, in the private
+    /// subdirectory of `$TMPDIR` if `tmpdir` is NULL, reusing the file another process
+    /// extracted; `clean_core_btf_rs` leaves it in place. See `BPF_COMPATIBLE_SHARED`
+    pub share_extracted: bool,
+    /// Look the kernel release up under `generic/<arch>` and any distro directory if the
+    /// distro has no btf for it, as for rolling distros like Arch
+    pub match_any_distro: bool,
+    /// Size the archive, and a compressed btf within it, may decompress to; fails with
+    /// `-EFBIG` past it. `bpf_compatible_rs::compression::DEFAULT_MAX_DECOMPRESSED_SIZE`
+    /// (4 GiB) if 0
+    pub max_decompressed_size: u64,
+    /// If the archive has no btf for the kernel, generate it from the vmlinux of the kernel
+    /// with pahole into the persistent cache; needs the `pahole` feature, see `BPF_COMPATIBLE_PAHOLE`
+    pub allow_pahole: bool,
+    /// pahole to run, `pahole` from `PATH` if NULL
+    pub pahole_path: *const c_char,
+    /// Locations of the vmlinux with DWARF, separated by `:`, with `{release}` standing for
+    /// the kernel release; `bpf_compatible_rs::pahole::DEBUG_VMLINUX_LOCATIONS` if NULL
+    pub vmlinux_locations: *const c_char,
+    /// Strategies tried in order, `BPF_COMPAT_STRATEGY_*`; the default chain, see
+    /// `bpf_compatible_rs::chain::DEFAULT_CHAIN`, if NULL or `n_strategies` is 0
+    pub strategies: *const c_int,
+    /// Number of strategies of `strategies`
+    pub n_strategies: usize,
+    /// Unpacked btfhub-archive looked up by `BPF_COMPAT_STRATEGY_ARCHIVE_DIR`
+    pub archive_dir: *const c_char,
+    /// Told how many bytes of the decompressed tar were read, every
+    /// `bpf_compatible_rs::progress::PROGRESS_INTERVAL` bytes; a non-zero return cancels
+    /// the lookup with `-ECANCELED`
+    pub progress: Option<ProgressFn>,
+    /// Passed to `progress` as it is
+    pub progress_ctx: *mut c_void,
+    /// Fail with `-ENOPKG` before scanning the archive if its listing says it has no btfs
+    /// for the distro, version and architecture of the system, see
+    /// `bpf_compatible_rs::listing::ArchiveListing::check_coverage`
+    pub strict_coverage: bool,
+    /// Start of the name of the temporary file, followed by 6 random letters or digits;
+    /// `eunomia.btf.` if NULL. Fails with `-EINVAL` if empty or if it has a `/`
+    pub tempfile_prefix: *const c_char,
+    /// End of the name of the temporary file, e.g. `.btf`; none if NULL. Fails with
+    /// `-EINVAL` if it has a `/`
+    pub tempfile_suffix: *const c_char,
+    /// Don't check that the btf of an archive entry is of the architecture of its
+    /// directory and of the system, see `bpf_compatible_rs::btf::check_btf_arch`; a btf
+    /// of another one fails with `-ENOEXEC` otherwise
+    pub skip_arch_check: bool,
+    /// Make `clean_core_btf_opts` overwrite the btf file with zeros and truncate it before
+    /// removing it, see `temp::zero_and_remove`
+    pub zero_on_clean: bool,
+}
+
+/// Resolved options, with the defaults filled in
+pub(crate) struct Options {
+    pub alloc: AllocFn,
+    pub free: FreeFn,
+    pub use_memfd: bool,
+    pub tmpdir: Option<OsString>,
+    pub use_cache: bool,
+    pub refresh_cache: bool,
+    pub policy: MatchPolicy,
+    /// Identity of the system to look up the btf for, the running one if `None`
+    pub system: Option<SystemInfo>,
+    /// Root the system is detected in, see `SystemInfo::detect_with_root`
+    pub sysroot: PathBuf,
+    /// Native btf of the running kernel, only replaced to drive the native branch in tests
+    pub vmlinux_path: PathBuf,
+    /// Directory of the archive holding the btfs, see `BtfhubArchive::with_prefix`
+    pub archive_prefix: PathBuf,
+    /// Installed btfs tried before the archive, under `sysroot`
+    pub native_probe: NativeBtfProbe,
+    pub always_path: bool,
+    pub require_verification: bool,
+    pub allow_download: bool,
+    #[cfg_attr(not(feature = "download"), allow(dead_code))]
+    pub download_url: Option<String>,
+    pub share_extracted: bool,
+    /// Look the kernel release up by itself too, see `BpfCompatOpts::match_any_distro`
+    pub any_distro: bool,
+    /// Size the archive and its entries may decompress to, see `tar_reader_with_limit`
+    pub max_decompressed_size: u64,
+    pub allow_pahole: bool,
+    /// Where pahole finds the vmlinux, under `sysroot`
+    #[cfg(feature = "pahole")]
+    pub pahole: bpf_compatible_rs::pahole::PaholeOptions,
+    /// Strategies tried in order, the default chain if `None`, see `resolve_core_btf`
+    pub chain: Option<Vec<Strategy>>,
+    /// Told how far the decompression of the archive got, see `extract::lookup_btf`
+    pub progress: Option<Progress>,
+    /// See `BpfCompatOpts::strict_coverage` and `BPF_COMPATIBLE_STRICT_COVERAGE`
+    pub strict_coverage: bool,
+    /// Names of the temporary files, see `bpf_compatible_rs::gc::TempfileTemplate`
+    pub tempfile_template: TempfileTemplate,
+    /// See `BpfCompatOpts::skip_arch_check`
+    pub check_arch: bool,
+    /// See `BpfCompatOpts::zero_on_clean`
+    pub zero_on_clean: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            alloc: crate::alloc::alloc,
+            free: crate::alloc::free,
+            use_memfd: false,
+            tmpdir: None,
+            use_cache: false,
+            refresh_cache: false,
+            policy: MatchPolicy::Exact,
+            system: None,
+            sysroot: PathBuf::from("/"),
+            vmlinux_path: PathBuf::from(VMLINUX_BTF_PATH),
+            archive_prefix: PathBuf::from(BTFHUB_ARCHIVE_DIR),
+            native_probe: NativeBtfProbe::default(),
+            always_path: false,
+            require_verification: false,
+            allow_download: false,
+            download_url: None,
+            share_extracted: false,
+            any_distro: false,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            allow_pahole: false,
+            #[cfg(feature = "pahole")]
+            pahole: Default::default(),
+            chain: None,
+            progress: None,
+            strict_coverage: false,
+            tempfile_template: TempfileTemplate::default(),
+            check_arch: true,
+            zero_on_clean: false,
+        }
+    }
+}
+
+impl Options {
+    /// Read the options passed by a C caller; NULL means defaults
+    ///
+    /// Returns `-EINVAL` if `sz` is too small to hold even the `sz` field, and `-E2BIG` if
+    /// it is larger than the struct this library knows and a byte past it isn't zero, as
+    /// libbpf does: the caller asked for an option that isn't supported.
+    pub(crate) fn from_raw(opts: *const BpfCompatOpts) -> Result<Self, c_int> {
+        if opts.is_null() {
+            return Ok(Self::default());
+        }
+        let sz = unsafe { *(opts as *const usize) };
+        if sz < size_of::<usize>() {
+            report!("Invalid size of struct bpf_compat_opts: {}", sz);
+            return Err(-EINVAL);
+        }
+        if sz > size_of::<BpfCompatOpts>() {
+            let tail = unsafe {
+                std::slice::from_raw_parts(
+                    (opts as *const u8).add(size_of::<BpfCompatOpts>()),
+                    sz - size_of::<BpfCompatOpts>(),
+                )
+            };
+            if let Some(offset) = tail.iter().position(|v| *v != 0) {
+                report!(
+                    "struct bpf_compat_opts has a non-zero byte at offset {}, past the {} bytes this library supports",
+                    size_of::<BpfCompatOpts>() + offset,
+                    size_of::<BpfCompatOpts>()
+                );
+                return Err(-E2BIG);
+            }
+        }
+        // 只拷贝调用者声明的大小，其余字段视为 0，兼容较旧的调用者
+        let mut raw = BpfCompatOpts {
+            sz: 0,
+            alloc: None,
+            free: None,
+            use_memfd: false,
+            tmpdir: std::ptr::null(),
+            use_cache: false,
+            refresh_cache: false,
+            nearest_fallback: false,
+            match_policy: 0,
+            sysroot: std::ptr::null(),
+            archive_prefix: std::ptr::null(),
+            always_path: false,
+            require_verification: false,
+            allow_download: false,
+            download_url: std::ptr::null(),
+            share_extracted: false,
+            match_any_distro: false,
+            max_decompressed_size: 0,
+            allow_pahole: false,
+            pahole_path: std::ptr::null(),
+            vmlinux_locations: std::ptr::null(),
+            strategies: std::ptr::null(),
+            n_strategies: 0,
+            archive_dir: std::ptr::null(),
+            progress: None,
+            progress_ctx: std::ptr::null_mut(),
+            strict_coverage: false,
+            tempfile_prefix: std::ptr::null(),
+            tempfile_suffix: std::ptr::null(),
+            skip_arch_check: false,
+            zero_on_clean: false,
+        };
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                opts as *const u8,
+                &mut raw as *mut BpfCompatOpts as *mut u8,
+                sz.min(size_of::<BpfCompatOpts>()),
+            )
+        };
+        let policy = match MatchPolicy::from_raw(raw.match_policy) {
+            Some(MatchPolicy::Exact) if raw.nearest_fallback => MatchPolicy::SameFlavorNearest,
+            Some(v) => v,
+            None => {
+                report!("Invalid match policy: {}", raw.match_policy);
+                return Err(-EINVAL);
+            }
+        };
+        let default = Self::default();
+        let sysroot = (!raw.sysroot.is_null())
+            .then(|| {
+                PathBuf::from(OsStr::from_bytes(
+                    unsafe { CStr::from_ptr(raw.sysroot) }.to_bytes(),
+                ))
+            })
+            .filter(|v| !v.as_os_str().is_empty())
+            .unwrap_or(default.sysroot);
+        #[cfg(feature = "pahole")]
+        let pahole = {
+            let mut pahole = default.pahole.clone().with_root(&sysroot);
+            if !raw.pahole_path.is_null() {
+                pahole = pahole.with_pahole(OsStr::from_bytes(
+                    unsafe { CStr::from_ptr(raw.pahole_path) }.to_bytes(),
+                ));
+            }
+            if !raw.vmlinux_locations.is_null() {
+                let locations = unsafe { CStr::from_ptr(raw.vmlinux_locations) }.to_string_lossy();
+                pahole = pahole.with_locations(locations.split(':').filter(|v| !v.is_empty()));
+            }
+            pahole
+        };
+        let chain = match (raw.strategies.is_null(), raw.n_strategies) {
+            (true, _) | (_, 0) => None,
+            (false, n) => Some(read_chain(
+                unsafe { std::slice::from_raw_parts(raw.strategies, n) },
+                raw.archive_dir,
+            )?),
+        };
+        let tempfile_template = read_tempfile_template(raw.tempfile_prefix, raw.tempfile_suffix)?;
+        Ok(Self {
+            alloc: raw.alloc.unwrap_or(default.alloc),
+            free: raw.free.unwrap_or(default.free),
+            use_memfd: raw.use_memfd,
+            tmpdir: (!raw.tmpdir.is_null()).then(|| {
+                OsStr::from_bytes(unsafe { CStr::from_ptr(raw.tmpdir) }.to_bytes()).into()
+            }),
+            use_cache: raw.use_cache,
+            refresh_cache: raw.refresh_cache,
+            policy,
+            system: None,
+            vmlinux_path: under_root(&sysroot, VMLINUX_BTF_PATH),
+            native_probe: default.native_probe.with_root(&sysroot),
+            sysroot,
+            always_path: raw.always_path,
+            require_verification: raw.require_verification,
+            allow_download: raw.allow_download,
+            download_url: (!raw.download_url.is_null()).then(|| {
+                unsafe { CStr::from_ptr(raw.download_url) }
+                    .to_string_lossy()
+                    .into_owned()
+            }),
+            share_extracted: raw.share_extracted,
+            any_distro: raw.match_any_distro,
+            max_decompressed_size: match raw.max_decompressed_size {
+                0 => default.max_decompressed_size,
+                v => v,
+            },
+            // 与 sysroot 不同，空字符串有意义：条目直接以发行版目录开头
+            archive_prefix: if raw.archive_prefix.is_null() {
+                default.archive_prefix
+            } else {
+                PathBuf::from(OsStr::from_bytes(
+                    unsafe { CStr::from_ptr(raw.archive_prefix) }.to_bytes(),
+                ))
+            },
+            allow_pahole: raw.allow_pahole,
+            #[cfg(feature = "pahole")]
+            pahole,
+            chain,
+            progress: raw.progress.map(|progress| {
+                // 上下文指针只原样传回给回调，库本身不会解引用
+                let ctx = raw.progress_ctx as usize;
+                Progress::new(move |processed, total| {
+                    match unsafe { progress(processed, total, ctx as *mut c_void) } {
+                        0 => ControlFlow::Continue(()),
+                        _ => ControlFlow::Break(()),
+                    }
+                })
+            }),
+            strict_coverage: raw.strict_coverage,
+            tempfile_template,
+            check_arch: !raw.skip_arch_check,
+            zero_on_clean: raw.zero_on_clean,
+        })
+    }
+
+    /// Identity of the system the btf is looked up for: `system` if set, else the one
+    /// detected in `sysroot`
+    pub(crate) fn system_info(&self) -> bpf_compatible_rs::Result<SystemInfo> {
+        match &self.system {
+            Some(v) => Ok(v.clone()),
+            None => SystemInfo::detect_with_root(&self.sysroot),
+        }
+    }
+}
+
+/// The template of `bpf_compat_opts.tempfile_prefix` and `tempfile_suffix`
+///
+/// Returns `-EINVAL` if it isn't UTF-8 or isn't valid, see `TempfileTemplate::new`.
+pub(crate) fn read_tempfile_template(
+    prefix: *const c_char,
+    suffix: *const c_char,
+) -> Result<TempfileTemplate, c_int> {
+    if prefix.is_null() && suffix.is_null() {
+        return Ok(TempfileTemplate::default());
+    }
+    let read = |v: *const c_char, default: &str| match v.is_null() {
+        true => Ok(default.to_string()),
+        false => unsafe { CStr::from_ptr(v) }
+            .to_str()
+            .map(str::to_string)
+            .map_err(|_| {
+                report!("The name of the temporary files isn't UTF-8");
+                -EINVAL
+            }),
+    };
+    let default = TempfileTemplate::default();
+    TempfileTemplate::new(
+        read(prefix, default.prefix())?,
+        read(suffix, default.suffix())?,
+    )
+    .map_err(|e| {
+        report!("{}", e);
+        -EINVAL
+    })
+}
+
+/// The chain of `bpf_compat_opts.strategies`
+///
+/// Returns `-EINVAL` for a value that isn't a `BPF_COMPAT_STRATEGY_*`, or
+/// `BPF_COMPAT_STRATEGY_ARCHIVE_DIR` without `archive_dir`, and `-ENOTSUP` for a strategy
+/// this build doesn't have, rather than leaving it out silently.
+fn read_chain(raw: &[c_int], archive_dir: *const c_char) -> Result<Vec<Strategy>, c_int> {
+    let archive_dir = (!archive_dir.is_null()).then(|| unsafe { CStr::from_ptr(archive_dir) });
+    let chain = raw
+        .iter()
+        .map(|v| {
+            strategy_from_raw(*v, archive_dir.map(CStr::to_bytes)).ok_or_else(|| {
+                report!("Invalid strategy {}, or no archive_dir for it", v);
+                -EINVAL
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Err(e) = check_chain(&chain) {
+        report!("{}", e);
+        return Err(-ENOTSUP);
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// `struct bpf_compat_opts` with every field zero but `sz`, as a C caller would set it up
+    fn zeroed() -> BpfCompatOpts {
+        BpfCompatOpts {
+            sz: size_of::<BpfCompatOpts>(),
+            ..unsafe { std::mem::zeroed() }
+        }
+    }
+
+    #[test]
+    fn nearest_fallback_selects_the_same_flavor_policy() {
+        assert_eq!(
+            Options::from_raw(&zeroed()).unwrap().policy,
+            MatchPolicy::Exact
+        );
+        let raw = BpfCompatOpts {
+            nearest_fallback: true,
+            ..zeroed()
+        };
+        assert_eq!(
+            Options::from_raw(&raw).unwrap().policy,
+            MatchPolicy::SameFlavorNearest
+        );
+    }
+
+    #[test]
+    fn match_policy_is_read_from_its_number() {
+        for (value, policy) in [
+            (0, MatchPolicy::Exact),
+            (1, MatchPolicy::SameFlavorNearest),
+            (2, MatchPolicy::BestEffort),
+        ] {
+            let raw = BpfCompatOpts {
+                match_policy: value,
+                ..zeroed()
+            };
+            assert_eq!(Options::from_raw(&raw).unwrap().policy, policy);
+        }
+        // nearest_fallback 不会降低更宽松的策略
+        let raw = BpfCompatOpts {
+            match_policy: 2,
+            nearest_fallback: true,
+            ..zeroed()
+        };
+        assert_eq!(
+            Options::from_raw(&raw).unwrap().policy,
+            MatchPolicy::BestEffort
+        );
+    }
+
+    #[test]
+    fn unknown_match_policy_is_rejected() {
+        for value in [-1, 3] {
+            let raw = BpfCompatOpts {
+                match_policy: value,
+                ..zeroed()
+            };
+            assert_eq!(Options::from_raw(&raw).err(), Some(-EINVAL));
+        }
+    }
+
+    #[test]
+    fn vmlinux_is_looked_for_under_the_sysroot() {
+        let default = Options::from_raw(&zeroed()).unwrap();
+        assert_eq!(default.sysroot, Path::new("/"));
+        assert_eq!(default.vmlinux_path, Path::new(VMLINUX_BTF_PATH));
+        for (sysroot, expected) in [(&b"\0"[..], "/"), (b"/host\0", "/host")] {
+            let raw = BpfCompatOpts {
+                sysroot: sysroot.as_ptr() as *const c_char,
+                ..zeroed()
+            };
+            let opts = Options::from_raw(&raw).unwrap();
+            assert_eq!(opts.sysroot, Path::new(expected));
+            assert_eq!(
+                opts.vmlinux_path,
+                Path::new(expected).join("sys/kernel/btf/vmlinux")
+            );
+        }
+    }
+
+    #[test]
+    fn fields_past_sz_are_ignored() {
+        let sysroot = b"/host\0";
+        let raw = BpfCompatOpts {
+            match_policy: 2,
+            sysroot: sysroot.as_ptr() as *const c_char,
+            ..zeroed()
+        };
+        // 较旧的调用者不知道 sysroot，之后的字段即使不是 0 也不读取
+        let older = BpfCompatOpts {
+            sz: std::mem::offset_of!(BpfCompatOpts, sysroot),
+            ..raw
+        };
+        let opts = Options::from_raw(&older).unwrap();
+        assert_eq!(opts.policy, MatchPolicy::BestEffort);
+        assert_eq!(opts.sysroot, Path::new("/"));
+        let oldest = BpfCompatOpts {
+            sz: std::mem::offset_of!(BpfCompatOpts, match_policy),
+            ..raw
+        };
+        assert_eq!(
+            Options::from_raw(&oldest).unwrap().policy,
+            MatchPolicy::Exact
+        );
+        assert_eq!(Options::from_raw(&raw).unwrap().sysroot, Path::new("/host"));
+        for sz in [0, size_of::<usize>() - 1] {
+            let raw = BpfCompatOpts { sz, ..raw };
+            assert_eq!(Options::from_raw(&raw).err(), Some(-EINVAL));
+        }
+    }
+
+    #[test]
+    fn larger_struct_may_only_add_zeros() {
+        /// The struct of a later version of the library, with an option this one lacks
+        #[repr(C)]
+        struct Later {
+            known: BpfCompatOpts,
+            unknown: [u8; 16],
+        }
+        let mut later = Later {
+            known: BpfCompatOpts {
+                sz: size_of::<Later>(),
+                match_policy: 1,
+                ..zeroed()
+            },
+            unknown: [0; 16],
+        };
+        let read = |later: &Later| Options::from_raw(&later.known as *const BpfCompatOpts);
+        assert_eq!(read(&later).unwrap().policy, MatchPolicy::SameFlavorNearest);
+        later.unknown[5] = 1;
+        assert_eq!(read(&later).err(), Some(-E2BIG));
+        let error = crate::last_error::get().unwrap();
+        assert!(
+            error.contains(&format!("offset {}", size_of::<BpfCompatOpts>() + 5)),
+            "{error}"
+        );
+        // sz 之外的字节，即使不是 0，也不属于调用者的结构体
+        later.known.sz = size_of::<BpfCompatOpts>() + 5;
+        assert!(read(&later).is_ok());
+    }
+}
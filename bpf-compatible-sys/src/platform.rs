@@ -0,0 +1,49 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! What differs between Linux and the other systems the library builds on
+//!
+//! Paths cross the C API as bytes, which on Linux are the path itself. Elsewhere they're
+//! taken as UTF-8, which is all a path of a btfhub archive ever is. No btf is looked up on
+//! other systems, see `bpf_compatible_rs::Error::UnsupportedPlatform`.
+#[cfg(target_os = "linux")]
+pub(crate) use libc::{EMEDIUMTYPE, ENOKEY, ENOPKG, ESTALE};
+#[cfg(target_os = "linux")]
+pub(crate) use std::os::unix::ffi::OsStrExt;
+
+#[cfg(not(target_os = "linux"))]
+use std::ffi::{c_int, OsStr};
+
+/// The Linux value, not every system has one; lookups fail before returning it there
+#[cfg(not(target_os = "linux"))]
+pub(crate) const ENOKEY: c_int = 126;
+/// The Linux value, not every system has one; lookups fail before returning it there
+#[cfg(not(target_os = "linux"))]
+pub(crate) const ESTALE: c_int = 116;
+/// The Linux value, not every system has one; lookups fail before returning it there
+#[cfg(not(target_os = "linux"))]
+pub(crate) const ENOPKG: c_int = 65;
+/// The Linux value, not every system has one; lookups fail before returning it there
+#[cfg(not(target_os = "linux"))]
+pub(crate) const EMEDIUMTYPE: c_int = 124;
+
+/// The part of `std::os::unix::ffi::OsStrExt` used here
+#[cfg(not(target_os = "linux"))]
+pub(crate) trait OsStrExt {
+    fn from_bytes(slice: &[u8]) -> &Self;
+    fn as_bytes(&self) -> &[u8];
+}
+
+#[cfg(not(target_os = "linux"))]
+impl OsStrExt for OsStr {
+    /// The bytes as UTF-8, or a path matching nothing if they aren't
+    fn from_bytes(slice: &[u8]) -> &Self {
+        OsStr::new(std::str::from_utf8(slice).unwrap_or("\u{fffd}"))
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        self.as_encoded_bytes()
+    }
+}
@@ -0,0 +1,61 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Structs of the C API the library fills in, like `struct bpf_compat_match_info`
+//!
+//! They start with `sz`, set by the caller to the size of the struct it was built
+//! with, and only that many bytes are written, so a struct can grow without breaking
+//! older callers.
+use std::{
+    ffi::{c_char, c_int},
+    mem::size_of,
+};
+
+use libc::EINVAL;
+
+/// A `#[repr(C)]` struct of the C API whose first field is `sz: usize`
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` and start with the `sz` field.
+pub(crate) unsafe trait SizedStruct: Copy {
+    /// Name of the struct in btf_helpers.h, for the errors
+    const NAME: &'static str;
+}
+
+/// The size the caller declared in `out.sz`, if it's large enough to hold `sz` itself
+pub(crate) fn declared_size<T: SizedStruct>(out: *const T) -> Result<usize, c_int> {
+    let sz = unsafe { *(out as *const usize) };
+    if sz < size_of::<usize>() {
+        report!("Invalid size of struct {}: {}", T::NAME, sz);
+        return Err(-EINVAL);
+    }
+    Ok(sz)
+}
+
+/// Copy `raw` to the caller's struct, past `sz` and up to the `sz` bytes it declares
+pub(crate) fn write_sized<T: SizedStruct>(out: *mut T, raw: &T, sz: usize) {
+    // 只写入调用者声明的大小，较旧的调用者的结构体不会被越界写入；sz 字段保留调用者的值
+    let start = size_of::<usize>();
+    let end = sz.min(size_of::<T>());
+    if end <= start {
+        return;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            (raw as *const T as *const u8).add(start),
+            (out as *mut u8).add(start),
+            end - start,
+        )
+    };
+}
+
+/// Copy `src` into `dst`, truncated so the NUL at the end is kept
+pub(crate) fn copy_c_string(dst: &mut [c_char], src: &[u8]) {
+    let capacity = dst.len() - 1;
+    for (dst, src) in dst[..capacity].iter_mut().zip(src) {
+        *dst = *src as c_char;
+    }
+}
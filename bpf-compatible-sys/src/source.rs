@@ -0,0 +1,112 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `struct bpf_compat_source` of the C API, one of the archives `ensure_core_btf_multi` searches in order
+use std::{
+    ffi::{c_char, c_int, CStr, OsStr},
+    fmt::Display,
+    path::Path,
+};
+
+use bpf_compatible_rs::mapped::ArchiveFile;
+use libc::{EINVAL, ENOENT};
+
+use crate::platform::OsStrExt;
+
+/// `kind` of `struct bpf_compat_source`: an archive in memory, `buf` and `len`
+pub const BPF_COMPAT_SRC_BUFFER: c_int = 1;
+/// `kind` of `struct bpf_compat_source`: the archive file at `path`
+pub const BPF_COMPAT_SRC_FILE: c_int = 2;
+/// `kind` of `struct bpf_compat_source`: the archive linked into the executable
+pub const BPF_COMPAT_SRC_LINKED: c_int = 3;
+
+/// An archive to look the btf up in, see `ensure_core_btf_multi`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfCompatSource {
+    /// One of `BPF_COMPAT_SRC_*`
+    pub kind: c_int,
+    /// The archive, with `BPF_COMPAT_SRC_BUFFER`
+    pub buf: *const u8,
+    /// Size of `buf`
+    pub len: usize,
+    /// Path of the archive, with `BPF_COMPAT_SRC_FILE`
+    pub path: *const c_char,
+}
+
+/// A source whose archive is ready to be read
+pub(crate) enum OpenedSource<'a> {
+    Bytes(&'a [u8]),
+    File(ArchiveFile),
+}
+
+impl OpenedSource<'_> {
+    pub(crate) fn bytes(&self) -> &[u8] {
+        match self {
+            OpenedSource::Bytes(v) => v,
+            OpenedSource::File(v) => v.bytes(),
+        }
+    }
+}
+
+impl Display for BpfCompatSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            BPF_COMPAT_SRC_BUFFER => write!(f, "the archive of {} bytes", self.len),
+            BPF_COMPAT_SRC_FILE => write!(f, "the archive {}", self.file_path().display()),
+            _ => write!(f, "the linked archive"),
+        }
+    }
+}
+
+impl BpfCompatSource {
+    /// Check the fields `kind` needs, before any archive is searched
+    pub(crate) fn check(&self) -> Result<(), c_int> {
+        match self.kind {
+            BPF_COMPAT_SRC_BUFFER => crate::check_args(false, self.buf, self.len).map(|_| ()),
+            BPF_COMPAT_SRC_FILE if self.path.is_null() => {
+                report!("The path of the archive file is NULL");
+                Err(-EINVAL)
+            }
+            BPF_COMPAT_SRC_FILE | BPF_COMPAT_SRC_LINKED => Ok(()),
+            kind => {
+                report!("Invalid kind of archive source: {}", kind);
+                Err(-EINVAL)
+            }
+        }
+    }
+
+    fn file_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(
+            unsafe { CStr::from_ptr(self.path) }.to_bytes(),
+        ))
+    }
+
+    /// The archive of the source, which must have passed `check`
+    ///
+    /// A file that doesn't exist, like the linked archive of an executable without one,
+    /// fails with `-ENOENT`, as an archive without the btf does.
+    pub(crate) fn open(&self) -> Result<OpenedSource<'_>, c_int> {
+        match self.kind {
+            BPF_COMPAT_SRC_BUFFER => {
+                crate::check_args(false, self.buf, self.len).map(OpenedSource::Bytes)
+            }
+            BPF_COMPAT_SRC_FILE => match ArchiveFile::open(self.file_path()) {
+                Ok(v) => Ok(OpenedSource::File(v)),
+                Err(e) => {
+                    report!("Failed to open the archive: {}", e);
+                    Err(crate::extract::archive_errno(&e))
+                }
+            },
+            _ => match crate::linked_archive_bytes() {
+                Some(v) => Ok(OpenedSource::Bytes(v)),
+                None => {
+                    report!("No btf archive is linked into the executable");
+                    Err(-ENOENT)
+                }
+            },
+        }
+    }
+}
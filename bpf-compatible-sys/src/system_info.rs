@@ -0,0 +1,72 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! `struct bpf_compat_system_info` of the C API, describing the system the btf is looked up for
+use std::{
+    ffi::{c_char, c_int},
+    mem::size_of,
+};
+
+use bpf_compatible_rs::SystemInfo;
+
+use crate::sized::{copy_c_string, declared_size, write_sized, SizedStruct};
+
+/// Capacity of `distro` and `version`, NUL included; longer values are truncated
+const DISTRO_FIELD_SIZE: usize = 64;
+/// Capacity of `arch`, NUL included
+const ARCH_SIZE: usize = 32;
+/// Capacity of `kernel_release`, NUL included
+const KERNEL_RELEASE_SIZE: usize = 128;
+
+/// Identity of the running system, see `get_current_system_info`
+///
+/// Like `struct bpf_compat_archive_info`, `sz` must be set to
+/// `sizeof(struct bpf_compat_system_info)` by the caller, and only that many bytes are
+/// written, so the struct can grow.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BpfCompatSystemInfo {
+    pub sz: usize,
+    /// `ID` of os-release, e.g. `ubuntu`, NUL-terminated
+    pub distro: [c_char; DISTRO_FIELD_SIZE],
+    /// `VERSION_ID` of os-release, e.g. `20.04`, NUL-terminated; empty for rolling distros
+    pub version: [c_char; DISTRO_FIELD_SIZE],
+    /// Machine as reported by uname, e.g. `x86_64`, NUL-terminated
+    pub arch: [c_char; ARCH_SIZE],
+    /// Kernel release as reported by uname, e.g. `5.4.0-40-generic`, NUL-terminated
+    pub kernel_release: [c_char; KERNEL_RELEASE_SIZE],
+}
+
+unsafe impl SizedStruct for BpfCompatSystemInfo {
+    const NAME: &'static str = "bpf_compat_system_info";
+}
+
+impl From<&SystemInfo> for BpfCompatSystemInfo {
+    fn from(info: &SystemInfo) -> Self {
+        let mut raw = Self {
+            sz: size_of::<Self>(),
+            distro: [0; DISTRO_FIELD_SIZE],
+            version: [0; DISTRO_FIELD_SIZE],
+            arch: [0; ARCH_SIZE],
+            kernel_release: [0; KERNEL_RELEASE_SIZE],
+        };
+        copy_c_string(&mut raw.distro, info.distro_id.as_bytes());
+        copy_c_string(&mut raw.version, info.version_id.as_bytes());
+        copy_c_string(&mut raw.arch, info.arch.as_bytes());
+        copy_c_string(&mut raw.kernel_release, info.kernel_release.as_bytes());
+        raw
+    }
+}
+
+/// Copy `info` to the caller's struct, up to the size it declares
+pub(crate) fn write_system_info(out: *mut BpfCompatSystemInfo, info: &SystemInfo) -> c_int {
+    match declared_size(out) {
+        Ok(sz) => {
+            write_sized(out, &info.into(), sz);
+            0
+        }
+        Err(e) => e,
+    }
+}
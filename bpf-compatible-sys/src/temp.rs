@@ -0,0 +1,536 @@
+//!  SPDX-License-Identifier: MIT
+//!
+//! Copyright (c) 2023, eunomia-bpf
+//! All rights reserved.
+//!
+//! Temporary files holding extracted btfs, only created on Linux
+use std::{
+    ffi::{c_int, CStr, CString, OsStr},
+    fs::{DirBuilder, File},
+    io::{ErrorKind, Read, Seek},
+    path::{Path, PathBuf},
+};
+#[cfg(target_os = "linux")]
+use std::{
+    fs::{OpenOptions, Permissions},
+    os::unix::{
+        ffi::OsStringExt,
+        fs::{DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bpf_compatible_rs::gc::TempfileTemplate;
+#[cfg(target_os = "linux")]
+use bpf_compatible_rs::gc::{PRIVATE_DIR_PREFIX, TEMPFILE_SUFFIX_LEN};
+#[cfg(target_os = "linux")]
+use libc::c_void;
+
+use crate::{
+    extract::{stream_errno, BtfSink},
+    memo::FileIdentity,
+    platform::OsStrExt,
+};
+
+/// A temporary file holding an extracted btf, removed on drop unless kept with `keep`
+pub(crate) struct BtfTempfile {
+    file: File,
+    path: Option<CString>,
+    template: TempfileTemplate,
+}
+
+impl BtfTempfile {
+    /// Create the file under `dir`, or the default temporary directory if `None`, named
+    /// after `template`
+    pub(crate) fn create(dir: Option<&OsStr>, template: &TempfileTemplate) -> Result<Self, c_int> {
+        match create_btf_tempfile(dir, template) {
+            Ok((file, path)) => Ok(Self {
+                file,
+                path: Some(path),
+                template: template.clone(),
+            }),
+            Err(e) => {
+                report!("Failed to create a tempfile to store the btf: {}", e);
+                // 返回具体的错误码（如 -ENOENT、-EACCES），便于调用者判断原因
+                Err(e.raw_os_error().map_or_else(|| stream_errno(&e), |v| -v))
+            }
+        }
+    }
+
+    pub(crate) fn path(&self) -> &CStr {
+        self.path.as_deref().unwrap_or_default()
+    }
+
+    /// Keep the file after the handle is dropped, for `clean_core_btf_rs` to remove
+    pub(crate) fn keep(mut self) {
+        if let Some(path) = self.path.take() {
+            #[cfg(target_os = "linux")]
+            let identity = self.file.metadata().ok().map(|v| FileIdentity {
+                dev: v.dev(),
+                ino: v.ino(),
+            });
+            #[cfg(not(target_os = "linux"))]
+            let identity = None;
+            crate::memo::record_created_path(path.as_bytes(), &self.template, identity);
+        }
+    }
+}
+
+impl BtfSink for BtfTempfile {
+    fn overwrite_from(&mut self, reader: &mut dyn Read) -> Result<(), c_int> {
+        // 将 btf 文件保存到临时文件
+        let result = self
+            .file
+            .set_len(0)
+            .and_then(|_| self.file.rewind())
+            .and_then(|_| std::io::copy(reader, &mut self.file));
+        if let Err(e) = result {
+            report!("Failed to write btf things to the tempfile: {}", e);
+            return Err(stream_errno(&e));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BtfTempfile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = std::fs::remove_file(OsStr::from_bytes(path.as_bytes()));
+        }
+    }
+}
+
+/// Attempts at finding an unused name before giving up, as `mkstemp` does
+#[cfg(target_os = "linux")]
+const MAX_NAME_ATTEMPTS: usize = 100;
+/// Characters of the random suffix of the file name
+#[cfg(target_os = "linux")]
+const NAME_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Directory holding the temporary files: `dir` if given, else a private subdirectory of
+/// `$TMPDIR` (or `/tmp`)
+///
+/// A relative directory is resolved against the current directory, so the returned
+/// path stays valid if the caller changes directory later. `dir` is created with
+/// mode 0700 if it doesn't exist.
+fn tempfile_dir(dir: Option<&OsStr>) -> std::io::Result<PathBuf> {
+    let dir = match dir.filter(|v| !v.is_empty()) {
+        Some(dir) => {
+            let mut builder = DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(target_os = "linux")]
+            builder.mode(0o700);
+            builder.create(dir)?;
+            PathBuf::from(dir)
+        }
+        None => {
+            let base = default_tempdir();
+            private_subdir(&base).unwrap_or(base)
+        }
+    };
+    if dir.is_relative() {
+        return Ok(std::env::current_dir()?.join(dir));
+    }
+    Ok(dir)
+}
+
+/// `$TMPDIR`, or `/tmp` if unset
+fn default_tempdir() -> PathBuf {
+    std::env::var_os("TMPDIR")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+}
+
+/// Directory of the btfs shared by the processes of the user: `dir` if given, else the
+/// private subdirectory of `$TMPDIR` (or `/tmp`) also holding the temporary files
+///
+/// Unlike [`tempfile_dir`], there's no falling back to `$TMPDIR` itself, where another
+/// user could have planted a file under the name of a shared btf: `None` if the private
+/// subdirectory can't be used.
+pub(crate) fn shared_dir(dir: Option<&OsStr>) -> Option<PathBuf> {
+    match dir.filter(|v| !v.is_empty()) {
+        Some(_) => tempfile_dir(dir)
+            .inspect_err(|e| debug!("Unable to create {:?}: {}", dir, e))
+            .ok(),
+        None => {
+            let dir = private_subdir(&default_tempdir())?;
+            // 与临时文件一样，相对的 $TMPDIR 按当前目录解析
+            match dir.is_relative() {
+                true => std::env::current_dir().ok().map(|v| v.join(dir)),
+                false => Some(dir),
+            }
+        }
+    }
+}
+
+/// `base/bpf-compatible-<uid>`, created with mode 0700 on first use
+///
+/// `None` if it can't be created, or if it exists but isn't a directory of the user that
+/// only they can access, e.g. one another user planted in a shared `/tmp`; the file is
+/// then created in `base` itself, which is still safe as the file is created exclusively.
+#[cfg(target_os = "linux")]
+fn private_subdir(base: &Path) -> Option<PathBuf> {
+    let uid = unsafe { libc::geteuid() };
+    let dir = base.join(format!("{}{}", PRIVATE_DIR_PREFIX, uid));
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        // 与文件一样，显式设置权限，不受 umask 影响
+        Ok(()) => {
+            let _ = std::fs::set_permissions(&dir, Permissions::from_mode(0o700));
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            debug!("Unable to create {}: {}", dir.display(), e);
+            return None;
+        }
+    }
+    // 用 symlink_metadata 检查目录本身，不跟随符号链接
+    match std::fs::symlink_metadata(&dir) {
+        Ok(meta) if meta.is_dir() && meta.uid() == uid && meta.mode() & 0o777 == 0o700 => Some(dir),
+        _ => {
+            note!(
+                "Not using {}, which isn't a private directory of the user",
+                dir.display()
+            );
+            None
+        }
+    }
+}
+
+/// Always `None` on other systems than Linux, where there's no such directory
+#[cfg(not(target_os = "linux"))]
+fn private_subdir(_base: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Random characters for a file name, from `getrandom`, or the clock if it fails
+#[cfg(target_os = "linux")]
+fn random_suffix() -> [u8; TEMPFILE_SUFFIX_LEN] {
+    let mut bytes = [0u8; TEMPFILE_SUFFIX_LEN];
+    let ret = unsafe { libc::getrandom(bytes.as_mut_ptr() as *mut c_void, bytes.len(), 0) };
+    if ret != bytes.len() as isize {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |v| v.as_nanos() as u64);
+        let seed = nanos ^ ((std::process::id() as u64) << 32);
+        bytes.copy_from_slice(&seed.to_ne_bytes()[..TEMPFILE_SUFFIX_LEN]);
+    }
+    bytes.map(|v| NAME_CHARS[v as usize % NAME_CHARS.len()])
+}
+
+/// Create a temporary file for the btf under the directory chosen by `tempfile_dir`,
+/// named after `template`
+///
+/// The file is created exclusively (`O_CREAT | O_EXCL`, without following a symlink)
+/// with mode 0600, whatever the umask, so it can neither be read by other users nor be
+/// a file they prepared. The path is kept as raw bytes, so a non-UTF-8 directory is
+/// preserved exactly.
+#[cfg(target_os = "linux")]
+fn create_btf_tempfile(
+    dir: Option<&OsStr>,
+    template: &TempfileTemplate,
+) -> std::io::Result<(File, CString)> {
+    let dir = tempfile_dir(dir)?.into_os_string().into_vec();
+    for _ in 0..MAX_NAME_ATTEMPTS {
+        let mut path = dir.clone();
+        path.push(b'/');
+        path.extend_from_slice(template.prefix().as_bytes());
+        path.extend_from_slice(&random_suffix());
+        path.extend_from_slice(template.suffix().as_bytes());
+        let result = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
+            .open(OsStr::from_bytes(&path));
+        match result {
+            Ok(file) => {
+                // umask 只会去掉权限位，这里显式设为 0600，保证 libbpf 能按路径读取
+                file.set_permissions(Permissions::from_mode(0o600))?;
+                return Ok((file, CString::new(path)?));
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::from_raw_os_error(libc::EEXIST))
+}
+
+/// Btfs are only written to files on Linux, this fails with `ErrorKind::Unsupported`
+#[cfg(not(target_os = "linux"))]
+fn create_btf_tempfile(
+    _dir: Option<&OsStr>,
+    _template: &TempfileTemplate,
+) -> std::io::Result<(File, CString)> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// Bytes of zeros written at once by [`zero_and_remove`]
+#[cfg(target_os = "linux")]
+const ZERO_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Overwrite the btf file at `path` with zeros and truncate it, then remove it, see
+/// `BpfCompatOpts::zero_on_clean`
+///
+/// The file is opened without following a symlink, and `fstat` of what was opened must be
+/// a regular file, and the inode of `identity` if it's known, i.e. the one created; before
+/// the unlink, the path must still name that inode, so a file swapped in under the path
+/// is left alone with `-ESTALE`. As the zeros go
+/// through the inode, links to the file made by others are emptied too. A file that can't
+/// be opened for writing, e.g. on a file system remounted read-only, is still removed if
+/// it can be, once `check_unwritable` found it's the one created; the first failure is
+/// returned either way, with its reason reported.
+#[cfg(target_os = "linux")]
+pub(crate) fn zero_and_remove(path: &Path, identity: Option<FileIdentity>) -> Result<(), c_int> {
+    let errno = |e: &std::io::Error| -e.raw_os_error().unwrap_or(libc::EIO);
+    let opened = OpenOptions::new()
+        .write(true)
+        // 路径被换成 FIFO 时不阻塞，换成符号链接时不跟随
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC)
+        .open(path);
+    let file = match opened {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            report!("{} was already removed", path.display());
+            return Err(-libc::ENOENT);
+        }
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ELOOP | libc::ENXIO)) => {
+            report!(
+                "{} was replaced by another file, leaving it",
+                path.display()
+            );
+            return Err(-libc::ESTALE);
+        }
+        Err(e) => {
+            report!("Failed to open {} to zero it: {}", path.display(), e);
+            // 无法写入时也要先确认路径仍指向创建的 inode，不能盲目删除
+            if let Some(stale) = check_unwritable(path, identity, errno(&e)) {
+                return Err(stale);
+            }
+            return match std::fs::remove_file(path) {
+                Ok(()) => Err(errno(&e)),
+                Err(removal) => {
+                    report!("Failed to remove {}: {}", path.display(), removal);
+                    Err(errno(&removal))
+                }
+            };
+        }
+    };
+    let meta = file.metadata().map_err(|e| {
+        report!("Failed to stat {}: {}", path.display(), e);
+        errno(&e)
+    })?;
+    let opened = FileIdentity {
+        dev: meta.dev(),
+        ino: meta.ino(),
+    };
+    if !meta.is_file() || identity.is_some_and(|v| v != opened) {
+        report!(
+            "{} was replaced by another file, leaving it",
+            path.display()
+        );
+        return Err(-libc::ESTALE);
+    }
+    if meta.nlink() > 1 {
+        note!(
+            "{} has {} other links, which are emptied too",
+            path.display(),
+            meta.nlink() - 1
+        );
+    }
+    let zeroed = zero_file(&file, meta.len()).map_err(|e| {
+        report!("Failed to zero {}: {}", path.display(), e);
+        errno(&e)
+    });
+    // 写入期间路径可能被换成其他文件，删除前确认仍是同一 inode
+    match std::fs::symlink_metadata(path) {
+        Ok(v) if v.dev() == opened.dev && v.ino() == opened.ino => {}
+        Ok(_) => {
+            report!(
+                "{} was replaced by another file, leaving it",
+                path.display()
+            );
+            return Err(-libc::ESTALE);
+        }
+        Err(e) => {
+            report!("{} was removed meanwhile: {}", path.display(), e);
+            return Err(errno(&e));
+        }
+    }
+    std::fs::remove_file(path).map_err(|e| {
+        report!("Failed to remove {}: {}", path.display(), e);
+        errno(&e)
+    })?;
+    zeroed
+}
+
+/// Why the file at `path`, which couldn't be opened for writing, must be left in place, if it must
+///
+/// It's opened with `O_PATH`, which needs no permission on the file itself, and must be the
+/// regular file of `identity`; `-ESTALE` is returned otherwise. Without `identity` the
+/// file can't be told from another, and `open_errno`, why it couldn't be opened, is kept.
+#[cfg(target_os = "linux")]
+fn check_unwritable(
+    path: &Path,
+    identity: Option<FileIdentity>,
+    open_errno: c_int,
+) -> Option<c_int> {
+    let Some(identity) = identity else {
+        report!(
+            "{} can't be told from a file swapped in under its path, leaving it",
+            path.display()
+        );
+        return Some(open_errno);
+    };
+    let meta = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        .open(path)
+        .and_then(|v| v.metadata());
+    match meta {
+        Ok(v) if v.is_file() && v.dev() == identity.dev && v.ino() == identity.ino => None,
+        Ok(_) => {
+            report!(
+                "{} was replaced by another file, leaving it",
+                path.display()
+            );
+            Some(-libc::ESTALE)
+        }
+        Err(e) => {
+            report!(
+                "Failed to check {} before removing it: {}",
+                path.display(),
+                e
+            );
+            Some(-libc::ESTALE)
+        }
+    }
+}
+
+/// Write `len` zeros from the start of `file`, flush them to the disk and truncate it
+#[cfg(target_os = "linux")]
+fn zero_file(file: &File, len: u64) -> std::io::Result<()> {
+    let zeros = [0u8; ZERO_CHUNK_SIZE];
+    let mut offset = 0;
+    while offset < len {
+        let n = (len - offset).min(ZERO_CHUNK_SIZE as u64) as usize;
+        file.write_all_at(&zeros[..n], offset)?;
+        offset += n as u64;
+    }
+    file.sync_data()?;
+    file.set_len(0)
+}
+
+/// Removes the file without zeroing it on other systems than Linux, where no btf file is
+/// ever created
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn zero_and_remove(path: &Path, _identity: Option<FileIdentity>) -> Result<(), c_int> {
+    std::fs::remove_file(path).map_err(|e| {
+        report!("Failed to remove {}: {}", path.display(), e);
+        -e.raw_os_error().unwrap_or(libc::EIO)
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::*;
+
+    #[test]
+    fn missing_tmpdir_is_created_private() {
+        let base = tempfile::tempdir().unwrap();
+        let dir = base.path().join("run/agent");
+        let file = BtfTempfile::create(Some(dir.as_os_str()), &Default::default()).unwrap();
+        let path = Path::new(OsStr::from_bytes(file.path().to_bytes()));
+        assert_eq!(path.parent(), Some(dir.as_path()));
+        let mode = |v: &Path| fs::metadata(v).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(path), 0o600);
+        // 未保留的文件在释放时删除
+        let path = path.to_path_buf();
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn private_subdir_is_created_for_the_user() {
+        let base = tempfile::tempdir().unwrap();
+        let dir = private_subdir(base.path()).unwrap();
+        let uid = unsafe { libc::geteuid() };
+        assert_eq!(
+            dir,
+            base.path().join(format!("{}{}", PRIVATE_DIR_PREFIX, uid))
+        );
+        let meta = fs::symlink_metadata(&dir).unwrap();
+        assert_eq!((meta.mode() & 0o777, meta.uid()), (0o700, uid));
+        // 再次使用时沿用已有的目录
+        assert_eq!(private_subdir(base.path()), Some(dir.clone()));
+        // 其中的文件都是新建的，只有所有者可读写
+        let [first, second] = [(); 2].map(|_| {
+            let file = BtfTempfile::create(Some(dir.as_os_str()), &Default::default()).unwrap();
+            let path = PathBuf::from(OsStr::from_bytes(file.path().to_bytes()));
+            assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+            file.keep();
+            path
+        });
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn planted_subdirs_are_not_used() {
+        let uid = unsafe { libc::geteuid() };
+        let name = format!("{}{}", PRIVATE_DIR_PREFIX, uid);
+        // 其他人可以访问的目录
+        let base = tempfile::tempdir().unwrap();
+        DirBuilder::new()
+            .mode(0o777)
+            .create(base.path().join(&name))
+            .unwrap();
+        fs::set_permissions(base.path().join(&name), Permissions::from_mode(0o777)).unwrap();
+        assert_eq!(private_subdir(base.path()), None);
+        // 指向私有目录的符号链接
+        let base = tempfile::tempdir().unwrap();
+        let target = base.path().join("elsewhere");
+        DirBuilder::new().mode(0o700).create(&target).unwrap();
+        std::os::unix::fs::symlink(&target, base.path().join(&name)).unwrap();
+        assert_eq!(private_subdir(base.path()), None);
+        // 同名的普通文件
+        let base = tempfile::tempdir().unwrap();
+        fs::write(base.path().join(&name), b"").unwrap();
+        assert_eq!(private_subdir(base.path()), None);
+    }
+
+    #[test]
+    fn relative_tmpdir_is_resolved_against_the_current_directory() {
+        // 使用已存在的目录，测试不在当前目录下创建任何东西
+        let dir = tempfile_dir(Some(OsStr::new("src"))).unwrap();
+        assert_eq!(dir, std::env::current_dir().unwrap().join("src"));
+    }
+
+    #[test]
+    fn tmpdir_failures_are_reported_with_their_errno() {
+        let base = tempfile::tempdir().unwrap();
+        let file = base.path().join("file");
+        fs::write(&file, b"").unwrap();
+        let create = |dir: &Path| BtfTempfile::create(Some(dir.as_os_str()), &Default::default());
+        assert_eq!(create(&file.join("tmp")).err(), Some(-libc::ENOTDIR));
+        assert_eq!(
+            create(Path::new("/proc/self/no-such-dir")).err(),
+            Some(-libc::ENOENT)
+        );
+    }
+
+    #[test]
+    fn unwritable_tmpdir_fails_with_eacces() {
+        // root 不受目录权限限制
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let base = tempfile::tempdir().unwrap();
+        fs::set_permissions(base.path(), Permissions::from_mode(0o500)).unwrap();
+        let err = BtfTempfile::create(Some(base.path().as_os_str()), &Default::default()).err();
+        assert_eq!(err, Some(-libc::EACCES));
+    }
+}
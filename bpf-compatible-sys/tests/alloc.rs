@@ -0,0 +1,51 @@
+//! Buffers returned through the allocator of the caller
+use std::{cell::Cell, ffi::c_void, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    clean_core_btf_opts, ensure_core_btf_with_tar_binary_opts, opts::BpfCompatOpts,
+    BPF_COMPAT_BTF_DELETED,
+};
+use bpf_compatible_rs::fixture::minimal_valid_btf;
+use common::{path_of, FakeRoot};
+
+mod common;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static FREED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe extern "C" fn counting_alloc(size: usize) -> *mut c_void {
+    ALLOCATED.with(|v| v.set(v.get() + 1));
+    libc::malloc(size)
+}
+
+unsafe extern "C" fn counting_free(ptr: *mut c_void) {
+    FREED.with(|v| v.set(v.get() + 1));
+    libc::free(ptr)
+}
+
+#[test]
+fn path_is_allocated_and_freed_by_the_functions_of_the_call() {
+    let root = FakeRoot::new();
+    let tar = root.archive(minimal_valid_btf()).gz();
+    let opts = BpfCompatOpts {
+        alloc: Some(counting_alloc),
+        free: Some(counting_free),
+        ..root.opts()
+    };
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_binary_opts(&mut path, tar.as_ptr(), tar.len(), &opts),
+        0
+    );
+    assert_eq!((ALLOCATED.get(), FREED.get()), (1, 0));
+    let extracted = path_of(path);
+    assert!(extracted.starts_with(root.path()));
+    assert_eq!(
+        clean_core_btf_opts(path as *mut c_char, &opts),
+        BPF_COMPAT_BTF_DELETED
+    );
+    assert_eq!((ALLOCATED.get(), FREED.get()), (1, 1));
+    assert!(!extracted.exists());
+}
@@ -0,0 +1,48 @@
+//! The path of the native btf handed out with `always_path`
+mod common;
+
+use std::{fs, os::raw::c_char};
+
+use bpf_compatible::{clean_core_btf_rs2, opts::BpfCompatOpts, BPF_COMPAT_PATH_FREED};
+use bpf_compatible_rs::fixture::{btf_of_arch, minimal_valid_btf};
+use common::{ensure_opts, lookup_opts, path_of, FakeRoot};
+
+/// A root whose kernel exports a native btf
+fn root_with_native_btf() -> FakeRoot {
+    let root = FakeRoot::new();
+    fs::create_dir_all(root.path().join("sys/kernel/btf")).unwrap();
+    fs::write(
+        root.path().join("sys/kernel/btf/vmlinux"),
+        minimal_valid_btf(),
+    )
+    .unwrap();
+    root
+}
+
+#[test]
+fn native_btf_is_null_by_default() {
+    let root = root_with_native_btf();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    assert!(ensure_opts(&tar, &root.opts()).unwrap().is_null());
+}
+
+#[test]
+fn native_btf_path_is_returned_and_never_removed() {
+    let root = root_with_native_btf();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let opts = BpfCompatOpts {
+        always_path: true,
+        ..root.opts()
+    };
+    let path = ensure_opts(&tar, &opts).unwrap();
+    let vmlinux = root.path().join("sys/kernel/btf/vmlinux");
+    assert_eq!(path_of(path), vmlinux);
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_PATH_FREED
+    );
+    assert_eq!(fs::read(&vmlinux).unwrap(), minimal_valid_btf());
+    // 没有内核自带的 btf 时照常从归档中提取
+    fs::remove_file(&vmlinux).unwrap();
+    assert_eq!(lookup_opts(&tar, &opts), Ok(btf_of_arch(8, "archived")));
+}
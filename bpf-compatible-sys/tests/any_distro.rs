@@ -0,0 +1,153 @@
+//! Lookups by kernel release alone, for rolling distros and with `match_any_distro`
+mod common;
+
+use std::{
+    ffi::{c_void, CStr},
+    os::raw::{c_char, c_int},
+    ptr,
+    sync::Mutex,
+};
+
+use bpf_compatible::{bpf_compatible_set_log_fn, opts::BpfCompatOpts};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    layout::to_random_access,
+    reexport::Compression,
+};
+use common::{lookup_opts, FakeRoot};
+
+const ARCH_OS_RELEASE: &str = "NAME=\"Arch Linux\"\nID=arch\nBUILD_ID=rolling\n";
+
+/// An archive with `btf` of the kernel of `root` under `distro/version`
+fn under(
+    fixture: FixtureArchive,
+    root: &FakeRoot,
+    distro: &str,
+    version: &str,
+    btf: Vec<u8>,
+) -> FixtureArchive {
+    let info = &root.info;
+    fixture.btf(distro, version, &info.arch, &info.kernel_release, btf)
+}
+
+/// Same as [`under`], in the `generic` tree keyed by kernel release alone
+fn generic(fixture: FixtureArchive, root: &FakeRoot, btf: Vec<u8>) -> FixtureArchive {
+    let info = &root.info;
+    fixture.file(
+        &format!(
+            "btfhub-archive/generic/{}/{}.btf",
+            info.arch, info.kernel_release
+        ),
+        btf,
+    )
+}
+
+type Notes = Mutex<Vec<String>>;
+
+unsafe extern "C" fn capture(_level: c_int, msg: *const c_char, ctx: *mut c_void) {
+    let notes = &*(ctx as *const Notes);
+    let msg = CStr::from_ptr(msg).to_string_lossy().into_owned();
+    notes.lock().unwrap().push(msg);
+}
+
+#[test]
+fn rolling_distro_uses_the_generic_tree() {
+    let root = FakeRoot::with_os_release(ARCH_OS_RELEASE);
+    assert_eq!(root.info.distro_id, "arch");
+    assert_eq!(root.info.version_id, "");
+    let fixture = generic(FixtureArchive::new(), &root, btf_of_arch(8, "generic"));
+    let fixture = under(fixture, &root, "ubuntu", "20.04", btf_of_arch(8, "ubuntu"));
+    // 两种布局的归档结果相同
+    let tar = fixture.tar();
+    let random_access = to_random_access(&tar, Compression::default()).unwrap();
+    for tar in [fixture.gz(), random_access] {
+        assert_eq!(
+            lookup_opts(&tar, &root.opts()),
+            Ok(btf_of_arch(8, "generic"))
+        );
+    }
+    // 其他内核的 btf 不会被选中
+    let other = FixtureArchive::new()
+        .file(
+            &format!(
+                "btfhub-archive/generic/{}/0.0.1-arch1-1.btf",
+                root.info.arch
+            ),
+            btf_of_arch(8, "other"),
+        )
+        .gz();
+    assert_eq!(lookup_opts(&other, &root.opts()), Err(-libc::ENOENT));
+}
+
+#[test]
+fn rolling_distro_picks_among_other_distros_deterministically() {
+    let root = FakeRoot::with_os_release(ARCH_OS_RELEASE);
+    let notes = Notes::default();
+    // 无论条目的顺序如何，都选择路径最小的那个
+    let forward = under(
+        FixtureArchive::new(),
+        &root,
+        "fedora",
+        "38",
+        btf_of_arch(8, "fedora"),
+    );
+    let forward = under(forward, &root, "ubuntu", "22.04", btf_of_arch(8, "ubuntu"));
+    let backward = under(
+        FixtureArchive::new(),
+        &root,
+        "ubuntu",
+        "22.04",
+        btf_of_arch(8, "ubuntu"),
+    );
+    let backward = under(backward, &root, "fedora", "38", btf_of_arch(8, "fedora"));
+    bpf_compatible_set_log_fn(Some(capture), &notes as *const _ as *mut c_void);
+    for fixture in [forward, backward] {
+        assert_eq!(
+            lookup_opts(&fixture.gz(), &root.opts()),
+            Ok(btf_of_arch(8, "fedora"))
+        );
+    }
+    bpf_compatible_set_log_fn(None, ptr::null_mut());
+    let notes = notes.into_inner().unwrap();
+    assert!(
+        notes.iter().any(|v| v.contains("under 2 distros")),
+        "{notes:?}"
+    );
+}
+
+#[test]
+fn other_distros_are_tried_only_when_asked() {
+    let root = FakeRoot::new();
+    let fixture = under(
+        FixtureArchive::new(),
+        &root,
+        "debian",
+        "11",
+        btf_of_arch(8, "debian"),
+    );
+    let tar = generic(fixture.clone(), &root, btf_of_arch(8, "generic")).gz();
+    let any = BpfCompatOpts {
+        match_any_distro: true,
+        ..root.opts()
+    };
+    assert_eq!(lookup_opts(&tar, &root.opts()), Err(-libc::ENOENT));
+    // generic 目录优先于其他发行版的目录
+    assert_eq!(lookup_opts(&tar, &any), Ok(btf_of_arch(8, "generic")));
+    assert_eq!(
+        lookup_opts(&fixture.gz(), &any),
+        Ok(btf_of_arch(8, "debian"))
+    );
+    // 同一发行版的其他版本优先，即使其路径更大
+    let tar = under(fixture, &root, "ubuntu", "22.04", btf_of_arch(8, "ubuntu")).gz();
+    assert_eq!(lookup_opts(&tar, &any), Ok(btf_of_arch(8, "ubuntu")));
+    // 系统自己的目录仍然最先
+    let tar = under(
+        FixtureArchive::new(),
+        &root,
+        "ubuntu",
+        "22.04",
+        btf_of_arch(8, "jammy"),
+    );
+    let tar = under(tar, &root, "ubuntu", "20.04", btf_of_arch(8, "focal")).gz();
+    assert_eq!(lookup_opts(&tar, &any), Ok(btf_of_arch(8, "focal")));
+}
@@ -0,0 +1,124 @@
+//! Btfs of another architecture than that of their directory, or of the system looked up
+mod common;
+
+use std::{ffi::CString, fs, os::raw::c_char, ptr};
+
+use bpf_compatible::{ensure_core_btf_for_system, opts::BpfCompatOpts, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::{
+    btf::arch_family,
+    fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+    index::prepend_index,
+};
+use common::{ensure_opts, last_error, lookup, lookup_opts, take_btf, FakeRoot};
+use libc::ENOEXEC;
+
+/// An ubuntu 20.04 archive holding `btf` under the directory of `arch`
+fn filed_under(arch: &str, btf: Vec<u8>) -> FixtureArchive {
+    FixtureArchive::new().btf("ubuntu", "20.04", arch, "5.4.0-40-generic", btf)
+}
+
+/// Look up the btf of ubuntu 20.04 `arch` in `tar`, returning its contents
+fn lookup_arch(tar: &[u8], arch: &str) -> Result<Vec<u8>, i32> {
+    let [distro, version, arch, release] =
+        ["ubuntu", "20.04", arch, "5.4.0-40-generic"].map(|v| CString::new(v).unwrap());
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_for_system(
+        &mut path,
+        tar.as_ptr(),
+        tar.len(),
+        distro.as_ptr(),
+        version.as_ptr(),
+        arch.as_ptr(),
+        release.as_ptr(),
+    );
+    if err != 0 {
+        return Err(err);
+    }
+    Ok(take_btf(path, BPF_COMPAT_BTF_DELETED))
+}
+
+#[test]
+fn btf_of_another_family_under_x86_64_is_refused() {
+    let archive = filed_under("x86_64", btf_of_arch(8, "orig_x0"));
+    // 通过索引定位与逐个扫描条目都要检查
+    for (form, tar) in [
+        ("gz", archive.gz()),
+        ("indexed", prepend_index(&archive.tar()).unwrap()),
+    ] {
+        assert_eq!(lookup(&tar), Err(-ENOEXEC), "{form}");
+        let error = last_error();
+        assert!(error.contains("is of another architecture"), "{error}");
+        assert!(
+            error.contains("arm64 with 8-byte pointers") && error.contains("x86_64"),
+            "{error}"
+        );
+        assert!(
+            error.contains("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf"),
+            "{error}"
+        );
+    }
+}
+
+#[test]
+fn pointer_size_alone_tells_the_architecture_apart() {
+    // i686 与 x86_64 同族，只有 long 的大小不同
+    let tar = filed_under("x86_64", btf_of_arch(4, "bx")).gz();
+    assert_eq!(lookup(&tar), Err(-ENOEXEC));
+    assert!(last_error().contains("x86 with 4-byte pointers"));
+    // 不含 pt_regs 的 btf 只比较指针大小
+    let tar = filed_under("x86_64", btf_of_arch(4, "unknown")).gz();
+    assert_eq!(lookup(&tar), Err(-ENOEXEC));
+    assert!(last_error().contains("an architecture with 4-byte pointers"));
+}
+
+#[test]
+fn btfs_of_their_architecture_are_used() {
+    for (arch, btf) in [
+        ("x86_64", btf_of_arch(8, "r15")),
+        ("arm64", btf_of_arch(8, "orig_x0")),
+        ("s390x", btf_of_arch(8, "orig_gpr2")),
+        // 无法判断架构的 btf 不比较
+        ("x86_64", minimal_valid_btf()),
+    ] {
+        let tar = filed_under(arch, btf.clone()).gz();
+        assert_eq!(lookup_arch(&tar, arch), Ok(btf), "{arch}");
+    }
+}
+
+#[test]
+fn system_of_another_architecture_refuses_the_btf() {
+    // 根目录下的 btf 没有架构目录，只与查找的系统比较
+    let tar = FixtureArchive::new()
+        .file("5.4.0-40-generic.btf", btf_of_arch(8, "r15"))
+        .gz();
+    assert_eq!(lookup_arch(&tar, "x86_64"), Ok(btf_of_arch(8, "r15")));
+    assert_eq!(lookup_arch(&tar, "aarch64"), Err(-ENOEXEC));
+    let error = last_error();
+    assert!(
+        error.contains("x86 with 8-byte pointers") && error.contains("aarch64"),
+        "{error}"
+    );
+}
+
+#[test]
+fn skip_arch_check_accepts_the_btf() {
+    let root = FakeRoot::new();
+    let foreign = match arch_family(&root.info.arch) {
+        Some("arm64") => btf_of_arch(8, "r15"),
+        _ => btf_of_arch(8, "orig_x0"),
+    };
+    let tar = root.archive(foreign.clone()).gz();
+    let checked = root.opts();
+    let skipped = BpfCompatOpts {
+        skip_arch_check: true,
+        ..root.opts()
+    };
+    assert_eq!(lookup_opts(&tar, &checked), Err(-ENOEXEC));
+    assert_eq!(lookup_opts(&tar, &skipped), Ok(foreign.clone()));
+    // 跳过检查时提取的 btf 不会被要求检查的查找复用
+    let path = ensure_opts(&tar, &skipped).unwrap();
+    assert_eq!(lookup_opts(&tar, &checked), Err(-ENOEXEC));
+    assert_eq!(take_btf(path, BPF_COMPAT_BTF_DELETED), foreign);
+    // 失败的查找不留下临时文件
+    assert_eq!(fs::read_dir(root.path().join("tmp")).unwrap().count(), 0);
+}
@@ -0,0 +1,79 @@
+//! Lookups in an archive shipped as a file next to the binary
+mod common;
+
+use std::{
+    ffi::CString,
+    fs,
+    os::{raw::c_char, unix::ffi::OsStrExt},
+    path::Path,
+    ptr,
+};
+
+use bpf_compatible::{ensure_core_btf_with_archive_file, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{last_error, lookup_opts, take_btf, FakeRoot};
+
+/// Look up the btf of `root` in the archive file at `archive`, returning its contents
+fn lookup_file(root: &FakeRoot, archive: &Path) -> Result<Vec<u8>, i32> {
+    let archive = CString::new(archive.as_os_str().as_bytes()).unwrap();
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_with_archive_file(&mut path, archive.as_ptr(), &root.opts());
+    if err != 0 {
+        assert!(path.is_null());
+        return Err(err);
+    }
+    let contents = take_btf(path, BPF_COMPAT_BTF_DELETED);
+    Ok(contents)
+}
+
+#[test]
+fn file_gives_the_btf_of_the_same_archive_in_memory() {
+    let root = FakeRoot::new();
+    for tar in [
+        root.archive(btf_of_arch(8, "rip")).gz(),
+        root.archive(btf_of_arch(8, "rip")).tar(),
+    ] {
+        let file = root.path().join("min_core_btfs.tar.gz");
+        fs::write(&file, &tar).unwrap();
+        let in_memory = lookup_opts(&tar, &root.opts());
+        assert!(in_memory.is_ok(), "{}", last_error());
+        assert_eq!(lookup_file(&root, &file), in_memory);
+    }
+}
+
+#[test]
+fn unusable_files_fail_with_their_errno() {
+    let root = FakeRoot::new();
+    let missing = root.path().join("missing.tar.gz");
+    assert_eq!(lookup_file(&root, &missing), Err(-libc::ENOENT));
+    assert!(last_error().contains("missing.tar.gz"), "{}", last_error());
+
+    let garbage = root.path().join("garbage.tar.gz");
+    fs::write(&garbage, [0xa5; 4096]).unwrap();
+    assert_eq!(lookup_file(&root, &garbage), Err(-libc::EINVAL));
+
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_archive_file(&mut path, ptr::null(), ptr::null()),
+        -libc::EINVAL
+    );
+    let archive = CString::new(missing.as_os_str().as_bytes()).unwrap();
+    assert_eq!(
+        ensure_core_btf_with_archive_file(ptr::null_mut(), archive.as_ptr(), ptr::null()),
+        -libc::EINVAL
+    );
+}
+
+#[test]
+fn unreadable_file_fails_with_eacces() {
+    // root 无视文件权限
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+    use std::os::unix::fs::PermissionsExt;
+    let root = FakeRoot::new();
+    let file = root.path().join("min_core_btfs.tar.gz");
+    fs::write(&file, root.archive(btf_of_arch(8, "rip")).gz()).unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o000)).unwrap();
+    assert_eq!(lookup_file(&root, &file), Err(-libc::EACCES));
+}
@@ -0,0 +1,142 @@
+//! Preflight checks of whether a btf would be found, which never extract anything
+mod common;
+
+use std::{fs, path::Path, ptr};
+
+use bpf_compatible::{
+    core_btf_is_available, core_btf_is_available_linked_tar, core_btf_is_available_opts,
+    opts::BpfCompatOpts, BPF_COMPAT_ARCHIVE_BTF, BPF_COMPAT_BTF_UNAVAILABLE, BPF_COMPAT_NATIVE_BTF,
+};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+    release::MatchPolicy,
+    TarballBtfArchive,
+};
+use common::{last_error, lookup_opts, FakeRoot};
+
+/// Check `tar` for the system of `root`, asserting nothing was written to its tmp dir
+fn available(root: &FakeRoot, tar: &[u8]) -> i32 {
+    let opts = root.opts();
+    let result = core_btf_is_available_opts(tar.as_ptr(), tar.len(), &opts);
+    assert!(!root.path().join("tmp").exists());
+    result
+}
+
+fn with_native_btf(root: &FakeRoot, contents: &[u8]) {
+    fs::create_dir_all(root.path().join("sys/kernel/btf")).unwrap();
+    fs::write(root.path().join("sys/kernel/btf/vmlinux"), contents).unwrap();
+}
+
+#[test]
+fn native_btf_comes_before_the_archive() {
+    let root = FakeRoot::new();
+    with_native_btf(&root, &minimal_valid_btf());
+    assert_eq!(
+        available(&root, &root.archive(btf_of_arch(8, "rip")).gz()),
+        BPF_COMPAT_NATIVE_BTF
+    );
+    // 内核自带 btf 时不读取归档，即使它是坏的
+    assert_eq!(available(&root, &[0xff; 64]), BPF_COMPAT_NATIVE_BTF);
+}
+
+#[test]
+fn matching_entry_of_the_archive_is_reported() {
+    let root = FakeRoot::new();
+    assert_eq!(
+        available(&root, &root.archive(btf_of_arch(8, "rip")).gz()),
+        BPF_COMPAT_ARCHIVE_BTF
+    );
+    // 不可用的内核自带 btf 和 ensure_core_btf 一样被跳过
+    with_native_btf(&root, b"garbage");
+    assert_eq!(
+        available(&root, &root.archive(btf_of_arch(8, "rip")).tar()),
+        BPF_COMPAT_ARCHIVE_BTF
+    );
+}
+
+#[test]
+fn neither_is_reported_as_unavailable() {
+    let root = FakeRoot::new();
+    let tar = FixtureArchive::new()
+        .btf(
+            "debian",
+            "11",
+            &root.info.arch,
+            &root.info.kernel_release,
+            btf_of_arch(8, "rip"),
+        )
+        .gz();
+    assert_eq!(available(&root, &tar), BPF_COMPAT_BTF_UNAVAILABLE);
+}
+
+#[test]
+fn fallbacks_are_reported_as_the_lookups_take_them() {
+    let root = FakeRoot::new();
+    let under = |distro: &str, version: &str| {
+        FixtureArchive::new()
+            .btf(
+                distro,
+                version,
+                &root.info.arch,
+                &root.info.kernel_release,
+                btf_of_arch(8, "rip"),
+            )
+            .gz()
+    };
+    // 其他版本目录下的同一内核只在 BestEffort 时使用，其他发行版的只在 match_any_distro 时使用
+    for (tar, policy, any_distro, found) in [
+        (under("ubuntu", "18.04"), MatchPolicy::Exact, false, false),
+        (
+            under("ubuntu", "18.04"),
+            MatchPolicy::BestEffort,
+            false,
+            true,
+        ),
+        (under("fedora", "38"), MatchPolicy::BestEffort, false, false),
+        (under("fedora", "38"), MatchPolicy::Exact, true, true),
+    ] {
+        let opts = BpfCompatOpts {
+            match_policy: policy as i32,
+            match_any_distro: any_distro,
+            ..root.opts()
+        };
+        let expected = match found {
+            true => BPF_COMPAT_ARCHIVE_BTF,
+            false => BPF_COMPAT_BTF_UNAVAILABLE,
+        };
+        assert_eq!(
+            core_btf_is_available_opts(tar.as_ptr(), tar.len(), &opts),
+            expected,
+            "{policy:?} {any_distro}"
+        );
+        assert_eq!(lookup_opts(&tar, &opts).is_ok(), found);
+        let archive = TarballBtfArchive::from_gzipped_bytes(&tar)
+            .unwrap()
+            .with_any_distro(any_distro);
+        assert_eq!(
+            archive.lookup_with_policy(&root.info, policy).is_ok(),
+            found
+        );
+    }
+}
+
+#[test]
+fn unreadable_archive_is_a_negative_errno() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "rip")).gz();
+    let result = available(&root, &tar[..tar.len() / 2]);
+    assert!(result < 0, "{result}");
+    assert!(!last_error().is_empty());
+    assert_eq!(core_btf_is_available(ptr::null(), 1), -libc::EINVAL);
+}
+
+#[test]
+fn linked_check_only_finds_native_btf_without_a_linked_archive() {
+    // 测试程序没有链接归档，只有内核自带 btf 时才可用
+    let result = core_btf_is_available_linked_tar();
+    if Path::new("/sys/kernel/btf/vmlinux").exists() {
+        assert_eq!(result, BPF_COMPAT_NATIVE_BTF);
+    } else {
+        assert!(result <= BPF_COMPAT_BTF_UNAVAILABLE, "{result}");
+    }
+}
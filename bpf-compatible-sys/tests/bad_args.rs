@@ -0,0 +1,156 @@
+//! The C entry points called with NULL pointers, bad lengths and truncated archives
+//!
+//! Every call fails with `-EINVAL` before dereferencing anything, so none of these crash.
+mod common;
+
+use std::{
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+use bpf_compatible::{
+    ensure_core_btf_bytes_with_tar_binary, ensure_core_btf_candidates_with_tar_binary,
+    ensure_core_btf_for_system, ensure_core_btf_with_tar_binary, ensure_core_btf_with_tar_binary2,
+    ensure_core_btf_with_tar_binary_match, ensure_core_btf_with_tar_binary_memfd,
+    ensure_core_btf_with_tar_binary_opts, ensure_core_btf_with_tar_binary_status,
+    ensure_core_btf_with_tar_binary_tmpdir,
+};
+use bpf_compatible_rs::fixture::{minimal_valid_btf, FixtureArchive};
+use common::last_error;
+use libc::EINVAL;
+
+/// A valid archive, so only the argument under test is wrong
+fn archive() -> Vec<u8> {
+    FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        )
+        .gz()
+}
+
+/// Assert that `call` fails with `-EINVAL` and `message` as the last error, leaving `*path` NULL
+fn assert_rejected(message: &str, call: impl FnOnce(*mut *const c_char) -> c_int) {
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(call(&mut path), -EINVAL);
+    assert!(path.is_null());
+    assert!(last_error().contains(message), "{}", last_error());
+}
+
+#[test]
+fn null_out_pointer_is_rejected() {
+    let tar = archive();
+    let len = tar.len() as c_int;
+    for ret in [
+        ensure_core_btf_with_tar_binary(ptr::null_mut(), tar.as_ptr(), len),
+        ensure_core_btf_with_tar_binary2(ptr::null_mut(), tar.as_ptr(), tar.len()),
+        ensure_core_btf_with_tar_binary_opts(ptr::null_mut(), tar.as_ptr(), tar.len(), ptr::null()),
+        ensure_core_btf_with_tar_binary_memfd(ptr::null_mut(), tar.as_ptr(), tar.len()),
+        ensure_core_btf_with_tar_binary_tmpdir(
+            ptr::null_mut(),
+            tar.as_ptr(),
+            tar.len(),
+            ptr::null(),
+        ),
+        ensure_core_btf_with_tar_binary_status(ptr::null_mut(), tar.as_ptr(), tar.len()),
+        ensure_core_btf_candidates_with_tar_binary(ptr::null_mut(), tar.as_ptr(), tar.len()),
+    ] {
+        assert_eq!(ret, -EINVAL);
+        assert!(last_error().contains("output pointer is NULL"));
+    }
+}
+
+#[test]
+fn null_match_info_and_buffer_length_are_rejected() {
+    let tar = archive();
+    assert_rejected("output pointer is NULL", |path| {
+        ensure_core_btf_with_tar_binary_match(
+            path,
+            tar.as_ptr(),
+            tar.len(),
+            ptr::null(),
+            ptr::null_mut(),
+        )
+    });
+    let mut buf: *mut u8 = ptr::null_mut();
+    let ret = ensure_core_btf_bytes_with_tar_binary(
+        &mut buf,
+        ptr::null_mut(),
+        tar.as_ptr(),
+        tar.len() as c_int,
+    );
+    assert_eq!(ret, -EINVAL);
+    assert!(buf.is_null());
+}
+
+#[test]
+fn null_archive_is_rejected() {
+    let len = archive().len();
+    assert_rejected("archive is NULL", |path| {
+        ensure_core_btf_with_tar_binary(path, ptr::null(), len as c_int)
+    });
+    assert_rejected("archive is NULL", |path| {
+        ensure_core_btf_with_tar_binary2(path, ptr::null(), len)
+    });
+    assert_rejected("archive is NULL", |path| {
+        ensure_core_btf_with_tar_binary_opts(path, ptr::null(), len, ptr::null())
+    });
+}
+
+#[test]
+fn negative_lengths_are_rejected() {
+    let tar = archive();
+    for len in [-1, c_int::MIN] {
+        assert_rejected("Invalid length", |path| {
+            ensure_core_btf_with_tar_binary(path, tar.as_ptr(), len)
+        });
+    }
+    // 负数被当作 size_t 传入时超过 isize::MAX，同样被拒绝
+    assert_rejected("Invalid length", |path| {
+        ensure_core_btf_with_tar_binary2(path, tar.as_ptr(), -1isize as usize)
+    });
+    assert_rejected("Invalid length", |path| {
+        ensure_core_btf_with_tar_binary_status(path, tar.as_ptr(), -1isize as usize)
+    });
+    assert_rejected("Invalid length", |path| {
+        ensure_core_btf_with_tar_binary_memfd(path, tar.as_ptr(), -1isize as usize)
+    });
+    assert_rejected("Invalid length", |path| {
+        ensure_core_btf_with_tar_binary_tmpdir(path, tar.as_ptr(), -1isize as usize, ptr::null())
+    });
+}
+
+#[test]
+fn empty_and_truncated_archives_are_rejected() {
+    let tar = archive();
+    // 空的 gzip 文件也至少有 18 字节
+    for len in [0, 1, 10, 17] {
+        assert_rejected("truncated", |path| {
+            ensure_core_btf_with_tar_binary(path, tar.as_ptr(), len)
+        });
+        assert_rejected("truncated", |path| {
+            ensure_core_btf_with_tar_binary2(path, tar.as_ptr(), len as usize)
+        });
+    }
+}
+
+#[test]
+fn non_utf8_system_fields_are_rejected() {
+    let tar = archive();
+    let name = c"ubuntu";
+    // NULL 表示使用当前系统的值，不是错误；非 UTF-8 的值才是
+    assert_rejected("must be UTF-8", |path| {
+        ensure_core_btf_for_system(
+            path,
+            tar.as_ptr(),
+            tar.len(),
+            name.as_ptr(),
+            c"20.04\xff".as_ptr(),
+            c"x86_64".as_ptr(),
+            c"5.4.0-40-generic".as_ptr(),
+        )
+    });
+}
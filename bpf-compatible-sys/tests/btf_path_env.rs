@@ -0,0 +1,73 @@
+//! Btf files forced by `BPF_COMPATIBLE_BTF_PATH`
+//!
+//! This is the only test of the binary, so setting the variable affects no other.
+mod common;
+
+use std::{ffi::CStr, fs, mem::size_of, os::raw::c_char, os::unix::ffi::OsStrExt, ptr};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, ensure_core_btf_with_tar_binary_match, match_info::BpfCompatMatchInfo,
+    BPF_COMPAT_BTF_DELETED, BPF_COMPAT_NATIVE_STATUS_UNKNOWN, BPF_COMPAT_PATH_FREED,
+    BPF_COMPAT_SOURCE_OVERRIDE,
+};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{ensure_opts, last_error, path_of, take_btf, FakeRoot};
+
+const BTF_PATH_ENV: &str = "BPF_COMPATIBLE_BTF_PATH";
+
+#[test]
+fn forced_btf_replaces_the_archive_and_is_never_removed() {
+    let root = FakeRoot::new();
+    let archived = btf_of_arch(8, "archived");
+    let tar = root.archive(archived.clone()).gz();
+    let opts = root.opts();
+    let lookup = || ensure_opts(&tar, &opts);
+
+    // 未设置或为空时照常查找归档
+    for value in [None, Some("")] {
+        match value {
+            Some(v) => std::env::set_var(BTF_PATH_ENV, v),
+            None => std::env::remove_var(BTF_PATH_ENV),
+        }
+        let path = lookup().unwrap();
+        assert_eq!(take_btf(path, BPF_COMPAT_BTF_DELETED), archived);
+    }
+
+    let forced = root.path().join("forced.btf");
+    fs::write(&forced, btf_of_arch(8, "forced")).unwrap();
+    std::env::set_var(BTF_PATH_ENV, &forced);
+    let path = lookup().unwrap();
+    assert_eq!(path_of(path), forced);
+    // 使用者提供的文件只释放路径，不删除
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_PATH_FREED
+    );
+    assert_eq!(fs::read(&forced).unwrap(), btf_of_arch(8, "forced"));
+    // 来源为该环境变量，内核自带的 btf 没有检查
+    let mut info: BpfCompatMatchInfo = unsafe { std::mem::zeroed() };
+    info.sz = size_of::<BpfCompatMatchInfo>();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_binary_match(&mut path, tar.as_ptr(), tar.len(), &opts, &mut info),
+        0
+    );
+    assert_eq!(info.source, BPF_COMPAT_SOURCE_OVERRIDE);
+    assert_eq!(info.native_status, BPF_COMPAT_NATIVE_STATUS_UNKNOWN);
+    assert_eq!(
+        unsafe { CStr::from_ptr(info.entry_path.as_ptr()) }.to_bytes(),
+        forced.as_os_str().as_bytes()
+    );
+    assert!(!info.memoized);
+    clean_core_btf_rs2(path as *mut c_char);
+
+    // 指向的文件不可用时报错，而不是退回到归档
+    std::env::set_var(BTF_PATH_ENV, root.path().join("missing.btf"));
+    assert_eq!(lookup(), Err(-libc::ENOENT));
+    assert!(last_error().contains(BTF_PATH_ENV), "{}", last_error());
+    fs::write(&forced, b"not a btf").unwrap();
+    std::env::set_var(BTF_PATH_ENV, &forced);
+    assert_eq!(lookup(), Err(-libc::EILSEQ));
+    assert!(last_error().contains("not a valid btf"), "{}", last_error());
+    assert!(forced.exists());
+}
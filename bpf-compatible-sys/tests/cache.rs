@@ -0,0 +1,94 @@
+//! The persistent btf cache under `XDG_CACHE_HOME`
+//!
+//! This is the only test of the binary, so setting `XDG_CACHE_HOME` and
+//! `BPF_COMPATIBLE_NO_CACHE` affects no other.
+mod common;
+
+use std::{fs, os::raw::c_char, path::PathBuf, ptr};
+
+use bpf_compatible::{
+    bpf_compatible_clear_cache, clean_core_btf_rs2, ensure_core_btf_with_tar_binary_opts,
+    opts::BpfCompatOpts, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED,
+};
+use bpf_compatible_rs::{fixture::minimal_valid_btf, identity::archive_key};
+use common::{ensure_opts, last_error, path_of, FakeRoot};
+
+/// The archive with its deflate stream overwritten, but the gzip header and trailer kept
+///
+/// The cache is keyed by the trailer, so a hit returns the btf cached for `tar`, while
+/// anything decoding the archive fails.
+fn undecodable(tar: &[u8]) -> Vec<u8> {
+    let mut corrupt = tar.to_vec();
+    let len = corrupt.len();
+    // 0xff 的块类型为保留值 3，解码第一个块即失败
+    corrupt[10..len - 8].fill(0xff);
+    corrupt
+}
+
+fn ensure(tar: &[u8], opts: &BpfCompatOpts) -> Result<PathBuf, i32> {
+    let path = ensure_opts(tar, opts)?;
+    let btf = path_of(path);
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_PATH_FREED,
+        "cached btfs are left in place"
+    );
+    Ok(btf)
+}
+
+#[test]
+fn second_lookup_is_answered_from_the_cache_without_decoding() {
+    let cache_home = tempfile::tempdir().unwrap();
+    std::env::set_var("XDG_CACHE_HOME", cache_home.path());
+    let root = FakeRoot::new();
+    let tar = root.archive(minimal_valid_btf()).gz();
+    let opts = BpfCompatOpts {
+        use_cache: true,
+        ..root.opts()
+    };
+
+    let cached = ensure(&tar, &opts).unwrap();
+    assert_eq!(
+        cached,
+        cache_home
+            .path()
+            .join("bpf-compatible")
+            .join(archive_key(&tar))
+            .join(root.info.to_string())
+    );
+    assert_eq!(fs::read(&cached).unwrap(), minimal_valid_btf());
+
+    // 命中缓存时不解压归档，即使其内容无法解码
+    let corrupt = undecodable(&tar);
+    assert_eq!(archive_key(&corrupt), archive_key(&tar));
+    assert_eq!(ensure(&corrupt, &opts), Ok(cached.clone()));
+
+    // refresh_cache 和 BPF_COMPATIBLE_NO_CACHE 都绕过缓存，必须解压归档
+    let refresh = BpfCompatOpts {
+        refresh_cache: true,
+        ..opts
+    };
+    assert!(ensure(&corrupt, &refresh).is_err(), "{}", last_error());
+    std::env::set_var("BPF_COMPATIBLE_NO_CACHE", "1");
+    let mut path: *const c_char = ptr::null();
+    assert!(
+        ensure_core_btf_with_tar_binary_opts(&mut path, corrupt.as_ptr(), corrupt.len(), &opts) < 0
+    );
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_binary_opts(&mut path, tar.as_ptr(), tar.len(), &opts),
+        0
+    );
+    assert_ne!(path_of(path), cached);
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    std::env::remove_var("BPF_COMPATIBLE_NO_CACHE");
+
+    // 清空缓存之后需要重新解压
+    assert_eq!(bpf_compatible_clear_cache(), 0);
+    assert!(!cached.exists());
+    assert!(ensure(&corrupt, &opts).is_err());
+    assert_eq!(ensure(&tar, &opts), Ok(cached));
+}
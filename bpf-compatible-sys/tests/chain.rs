@@ -0,0 +1,180 @@
+//! `bpf_compat_opts.strategies`, and the attempts `bpf_compatible_last_attempts` reports
+mod common;
+
+use std::{ffi::CString, fs, os::raw::c_char, os::raw::c_int, ptr};
+
+use bpf_compatible::{
+    bpf_compatible_last_attempts, chain::BpfCompatAttempt, opts::BpfCompatOpts,
+    BPF_COMPAT_BTF_DELETED, BPF_COMPAT_OUTCOME_ERROR, BPF_COMPAT_OUTCOME_HIT,
+    BPF_COMPAT_OUTCOME_MISS, BPF_COMPAT_PATH_FREED, BPF_COMPAT_STRATEGY_ARCHIVE,
+    BPF_COMPAT_STRATEGY_ARCHIVE_DIR, BPF_COMPAT_STRATEGY_DOWNLOAD, BPF_COMPAT_STRATEGY_INSTALLED,
+    BPF_COMPAT_STRATEGY_NATIVE, BPF_COMPAT_STRATEGY_PAHOLE,
+};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{ensure_opts, last_error, take_btf, FakeRoot};
+use libc::{EINVAL, ENOTSUP};
+
+/// `strategy`, `outcome` and `error` of an attempt
+type Attempt = (c_int, c_int, c_int);
+
+/// The path handed out by the lookup of `tar` trying `strategies`, and the attempts it made
+fn ensure(
+    tar: &[u8],
+    opts: &BpfCompatOpts,
+    strategies: &[c_int],
+) -> (Result<*const c_char, i32>, Vec<Attempt>) {
+    let opts = BpfCompatOpts {
+        strategies: strategies.as_ptr(),
+        n_strategies: strategies.len(),
+        ..*opts
+    };
+    let result = ensure_opts(tar, &opts);
+    let n = bpf_compatible_last_attempts(ptr::null_mut(), 0);
+    let mut attempts = vec![
+        BpfCompatAttempt {
+            strategy: 0,
+            outcome: 0,
+            error: 0
+        };
+        n
+    ];
+    assert_eq!(bpf_compatible_last_attempts(attempts.as_mut_ptr(), n), n);
+    let attempts = attempts
+        .iter()
+        .map(|v| (v.strategy, v.outcome, v.error))
+        .collect();
+    (result, attempts)
+}
+
+#[test]
+fn chains_pick_the_first_source_holding_the_btf() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let unpacked = root.path().join("unpacked");
+    let entry = unpacked.join(root.info.to_string());
+    fs::create_dir_all(entry.parent().unwrap()).unwrap();
+    fs::write(&entry, btf_of_arch(8, "unpacked")).unwrap();
+    fs::create_dir_all(root.path().join("sys/kernel/btf")).unwrap();
+    fs::write(
+        root.path().join("sys/kernel/btf/vmlinux"),
+        btf_of_arch(8, "native"),
+    )
+    .unwrap();
+    let archive_dir = CString::new(unpacked.to_str().unwrap()).unwrap();
+    let opts = BpfCompatOpts {
+        archive_dir: archive_dir.as_ptr(),
+        always_path: true,
+        ..root.opts()
+    };
+
+    let (path, attempts) = ensure(
+        &tar,
+        &opts,
+        &[BPF_COMPAT_STRATEGY_ARCHIVE_DIR, BPF_COMPAT_STRATEGY_ARCHIVE],
+    );
+    // 目录中的 btf 与内核自带的 btf 都不属于本库，只释放路径
+    assert_eq!(
+        take_btf(path.unwrap(), BPF_COMPAT_PATH_FREED),
+        btf_of_arch(8, "unpacked")
+    );
+    assert_eq!(
+        attempts,
+        [(BPF_COMPAT_STRATEGY_ARCHIVE_DIR, BPF_COMPAT_OUTCOME_HIT, 0)]
+    );
+
+    let (path, attempts) = ensure(
+        &tar,
+        &opts,
+        &[BPF_COMPAT_STRATEGY_NATIVE, BPF_COMPAT_STRATEGY_ARCHIVE_DIR],
+    );
+    assert_eq!(
+        take_btf(path.unwrap(), BPF_COMPAT_PATH_FREED),
+        btf_of_arch(8, "native")
+    );
+    assert_eq!(
+        attempts,
+        [(BPF_COMPAT_STRATEGY_NATIVE, BPF_COMPAT_OUTCOME_HIT, 0)]
+    );
+
+    // 没有为内核安装的 btf，改用归档
+    let (path, attempts) = ensure(
+        &tar,
+        &opts,
+        &[BPF_COMPAT_STRATEGY_INSTALLED, BPF_COMPAT_STRATEGY_ARCHIVE],
+    );
+    assert_eq!(
+        take_btf(path.unwrap(), BPF_COMPAT_BTF_DELETED),
+        btf_of_arch(8, "archived")
+    );
+    assert_eq!(
+        attempts,
+        [
+            (BPF_COMPAT_STRATEGY_INSTALLED, BPF_COMPAT_OUTCOME_MISS, 0),
+            (BPF_COMPAT_STRATEGY_ARCHIVE, BPF_COMPAT_OUTCOME_HIT, 0),
+        ]
+    );
+}
+
+#[test]
+fn failing_strategy_ends_the_lookup() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let (path, attempts) = ensure(
+        &tar[..tar.len() / 2],
+        &root.opts(),
+        &[BPF_COMPAT_STRATEGY_ARCHIVE, BPF_COMPAT_STRATEGY_INSTALLED],
+    );
+    let err = path.unwrap_err();
+    assert!(err < 0);
+    assert_eq!(
+        attempts,
+        [(BPF_COMPAT_STRATEGY_ARCHIVE, BPF_COMPAT_OUTCOME_ERROR, err)]
+    );
+    // 只取部分尝试时返回的仍是总数
+    let mut first = [BpfCompatAttempt {
+        strategy: 0,
+        outcome: 0,
+        error: 0,
+    }; 1];
+    assert_eq!(bpf_compatible_last_attempts(first.as_mut_ptr(), 0), 1);
+    assert_eq!(first[0].strategy, 0);
+}
+
+#[test]
+fn invalid_chains_are_refused() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let (path, _) = ensure(&tar, &root.opts(), &[BPF_COMPAT_STRATEGY_ARCHIVE, 99]);
+    assert_eq!(path, Err(-EINVAL));
+    assert!(
+        last_error().contains("Invalid strategy 99"),
+        "{}",
+        last_error()
+    );
+    // 目录策略需要 archive_dir
+    let (path, _) = ensure(&tar, &root.opts(), &[BPF_COMPAT_STRATEGY_ARCHIVE_DIR]);
+    assert_eq!(path, Err(-EINVAL));
+    // 编译时未启用的策略被拒绝，而不是被悄悄跳过；启用的不在这里尝试，以免访问网络
+    for (strategy, enabled) in [
+        (BPF_COMPAT_STRATEGY_DOWNLOAD, cfg!(feature = "download")),
+        (BPF_COMPAT_STRATEGY_PAHOLE, cfg!(feature = "pahole")),
+    ] {
+        if enabled {
+            continue;
+        }
+        let (path, attempts) = ensure(&tar, &root.opts(), &[BPF_COMPAT_STRATEGY_ARCHIVE, strategy]);
+        assert_eq!(path, Err(-ENOTSUP));
+        assert!(last_error().contains("feature"), "{}", last_error());
+        assert!(attempts.is_empty());
+    }
+    // strategies 为空时使用默认的策略链
+    let (path, attempts) = ensure(&tar, &root.opts(), &[]);
+    assert_eq!(
+        take_btf(path.unwrap(), BPF_COMPAT_BTF_DELETED),
+        btf_of_arch(8, "archived")
+    );
+    assert_eq!(
+        attempts.last(),
+        Some(&(BPF_COMPAT_STRATEGY_ARCHIVE, BPF_COMPAT_OUTCOME_HIT, 0))
+    );
+}
@@ -0,0 +1,95 @@
+//! `clean_core_btf_rs2`, removing only the btf files the library created
+mod common;
+
+use std::{ffi::CString, fs, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    clean_core_btf_rs, clean_core_btf_rs2, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED,
+};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{ensure_opts, last_error, path_of, FakeRoot};
+
+fn extract(root: &FakeRoot, tar: &[u8]) -> *mut c_char {
+    let path = ensure_opts(tar, &root.opts()).unwrap();
+    assert!(!path.is_null());
+    path as *mut c_char
+}
+
+#[test]
+fn owned_btf_is_deleted() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "owned")).gz();
+    let path = extract(&root, &tar);
+    let file = path_of(path);
+    assert_eq!(fs::read(&file).unwrap(), btf_of_arch(8, "owned"));
+    assert_eq!(clean_core_btf_rs2(path), BPF_COMPAT_BTF_DELETED);
+    assert!(!file.exists());
+}
+
+#[test]
+fn already_deleted_btf_is_reported_and_the_string_freed() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "gone")).gz();
+    let path = extract(&root, &tar);
+    let file = path_of(path);
+    fs::remove_file(&file).unwrap();
+    assert_eq!(clean_core_btf_rs2(path), -libc::ENOENT);
+    assert!(last_error().contains(&file.display().to_string()));
+    // 字符串已经释放，再次清理被拒绝而不是再次释放
+    assert_eq!(clean_core_btf_rs2(path), -libc::EINVAL);
+}
+
+#[test]
+fn cleaning_twice_is_refused() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "twice")).gz();
+    let path = extract(&root, &tar);
+    let file = path_of(path);
+    assert_eq!(clean_core_btf_rs2(path), BPF_COMPAT_BTF_DELETED);
+    // 同名文件再次出现时也不会被删除
+    fs::write(&file, b"foreign").unwrap();
+    assert_eq!(clean_core_btf_rs2(path), -libc::EINVAL);
+    assert!(last_error().contains("already cleaned"), "{}", last_error());
+    assert_eq!(fs::read(&file).unwrap(), b"foreign");
+}
+
+#[test]
+fn foreign_paths_are_left_alone() {
+    let root = FakeRoot::new();
+    let victim = root.path().join("vmlinux");
+    fs::write(&victim, b"not ours").unwrap();
+    let foreign = CString::new(victim.to_str().unwrap()).unwrap().into_raw();
+    assert_eq!(clean_core_btf_rs2(foreign), -libc::EINVAL);
+    // 旧接口同样不删除
+    clean_core_btf_rs(foreign);
+    assert_eq!(fs::read(&victim).unwrap(), b"not ours");
+    // 字符串没有被释放，仍归调用者所有
+    assert_eq!(
+        unsafe { CString::from_raw(foreign) }.to_str().unwrap(),
+        victim.to_str().unwrap()
+    );
+    let system = CString::new("/sys/kernel/btf/vmlinux").unwrap().into_raw();
+    assert_eq!(clean_core_btf_rs2(system), -libc::EINVAL);
+    drop(unsafe { CString::from_raw(system) });
+}
+
+#[test]
+fn null_is_freed_only() {
+    assert_eq!(clean_core_btf_rs2(ptr::null_mut()), BPF_COMPAT_PATH_FREED);
+    clean_core_btf_rs(ptr::null_mut());
+}
+
+#[test]
+fn file_handed_out_twice_is_removed_by_the_last_clean() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "shared")).gz();
+    let first = extract(&root, &tar);
+    let second = extract(&root, &tar);
+    // 同一归档的第二次查找复用了第一次提取的文件
+    let file = path_of(first);
+    assert_eq!(path_of(second), file);
+    assert_eq!(clean_core_btf_rs2(first), BPF_COMPAT_PATH_FREED);
+    assert!(file.exists());
+    assert_eq!(clean_core_btf_rs2(second), BPF_COMPAT_BTF_DELETED);
+    assert!(!file.exists());
+}
@@ -0,0 +1,150 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs,
+    os::{raw::c_char, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use bpf_compatible::{
+    bpf_compatible_last_error, clean_core_btf_rs2, ensure_core_btf_for_system,
+    ensure_core_btf_with_tar_binary_opts, opts::BpfCompatOpts, BPF_COMPAT_BTF_DELETED,
+};
+use bpf_compatible_rs::{fixture::FixtureArchive, SystemInfo};
+use tempfile::TempDir;
+
+/// A root filesystem of ubuntu 20.04 running the kernel of the host, without native btf
+///
+/// Passed as `sysroot`, lookups go to the archive for the btf of the running kernel. The
+/// btfs are extracted to the `tmp` directory of the root, so the lookups of concurrent
+/// tests are never memoized for one another.
+pub struct FakeRoot {
+    dir: TempDir,
+    sysroot: CString,
+    tmpdir: CString,
+    /// The system detected under the root
+    pub info: SystemInfo,
+}
+
+impl FakeRoot {
+    pub fn new() -> Self {
+        Self::with_os_release("ID=ubuntu\nVERSION_ID=\"20.04\"\n")
+    }
+
+    /// A root filesystem of the distro of `os_release` instead
+    pub fn with_os_release(os_release: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("etc")).unwrap();
+        fs::write(dir.path().join("etc/os-release"), os_release).unwrap();
+        let info = SystemInfo::detect_with_root(dir.path()).unwrap();
+        let c_path = |v: &Path| CString::new(v.as_os_str().as_bytes()).unwrap();
+        let sysroot = c_path(dir.path());
+        let tmpdir = c_path(&dir.path().join("tmp"));
+        Self {
+            dir,
+            sysroot,
+            tmpdir,
+            info,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// An archive holding `btf` as the btf of the system
+    pub fn archive(&self, btf: Vec<u8>) -> FixtureArchive {
+        FixtureArchive::new().file(&format!("btfhub-archive/{}", self.info), btf)
+    }
+
+    /// Options with everything else zero, as a C caller would `memset` them
+    pub fn opts(&self) -> BpfCompatOpts {
+        BpfCompatOpts {
+            sz: std::mem::size_of::<BpfCompatOpts>(),
+            sysroot: self.sysroot.as_ptr(),
+            tmpdir: self.tmpdir.as_ptr(),
+            ..zeroed_opts()
+        }
+    }
+}
+
+/// `struct bpf_compat_opts` with every field zero
+pub fn zeroed_opts() -> BpfCompatOpts {
+    unsafe { std::mem::zeroed() }
+}
+
+/// The path a C string returned by the library names
+pub fn path_of(path: *const c_char) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(
+        unsafe { CStr::from_ptr(path) }.to_bytes(),
+    ))
+}
+
+/// Look up the btf of ubuntu 20.04 x86_64 `5.4.0-40-generic` in `tar`, never the native one
+///
+/// Returns the contents of the extracted btf, which is removed, or the error
+pub fn lookup(tar: &[u8]) -> Result<Vec<u8>, i32> {
+    lookup_release(tar, "5.4.0-40-generic")
+}
+
+/// Same as [`lookup`], for the kernel `release` of ubuntu 20.04 x86_64
+pub fn lookup_release(tar: &[u8], release: &str) -> Result<Vec<u8>, i32> {
+    let [distro, version, arch, release] =
+        ["ubuntu", "20.04", "x86_64", release].map(|v| CString::new(v).unwrap());
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_for_system(
+        &mut path,
+        tar.as_ptr(),
+        tar.len(),
+        distro.as_ptr(),
+        version.as_ptr(),
+        arch.as_ptr(),
+        release.as_ptr(),
+    );
+    if err != 0 {
+        return Err(err);
+    }
+    Ok(take_btf(path, BPF_COMPAT_BTF_DELETED))
+}
+
+/// The path `ensure_core_btf_with_tar_binary_opts` hands out for `tar` and `opts`, NULL if
+/// the native btf is used, or the error
+pub fn ensure_opts(tar: &[u8], opts: &BpfCompatOpts) -> Result<*const c_char, i32> {
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_with_tar_binary_opts(&mut path, tar.as_ptr(), tar.len(), opts);
+    if err != 0 {
+        assert!(path.is_null());
+        return Err(err);
+    }
+    Ok(path)
+}
+
+/// Same as [`lookup`], for the system and with the options of `opts`
+///
+/// Fails the test if no btf was extracted, as when the native one is used.
+pub fn lookup_opts(tar: &[u8], opts: &BpfCompatOpts) -> Result<Vec<u8>, i32> {
+    let path = ensure_opts(tar, opts)?;
+    assert!(!path.is_null(), "the native btf was used");
+    Ok(take_btf(path, BPF_COMPAT_BTF_DELETED))
+}
+
+/// The contents of the btf at the handed out `path`, which is then released by
+/// `clean_core_btf_rs2`, returning `released`
+pub fn take_btf(path: *const c_char, released: i32) -> Vec<u8> {
+    let contents = fs::read(path_of(path)).unwrap();
+    assert_eq!(clean_core_btf_rs2(path as *mut c_char), released);
+    contents
+}
+
+/// The message of the last failed call on the thread, empty if it succeeded
+pub fn last_error() -> String {
+    let message = bpf_compatible_last_error();
+    if message.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(message) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,119 @@
+//! Lookups and cleanups of many threads at once, sharing the btf one of them extracted
+//!
+//! The process remembers a single extraction, so this is the only test of the binary.
+mod common;
+
+use std::{
+    collections::HashSet,
+    fs,
+    os::raw::c_char,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Barrier,
+    },
+    thread,
+};
+
+use bpf_compatible::{clean_core_btf_rs2, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{ensure_opts, last_error, path_of, FakeRoot};
+
+const THREADS: usize = 16;
+
+const ROUNDS: usize = 25;
+
+fn ensure(root: &FakeRoot, tar: &[u8]) -> *mut c_char {
+    // 选项中的指针不能跨线程共享，各自生成
+    let opts = root.opts();
+    let path = ensure_opts(tar, &opts).unwrap_or_else(|e| panic!("{e}: {}", last_error()));
+    path as *mut c_char
+}
+
+#[test]
+fn threads_share_one_extraction() {
+    let root = FakeRoot::new();
+    let btf = btf_of_arch(8, "concurrent");
+    let tar = root
+        .archive(btf.clone())
+        .file("padding", vec![0x5a; 1 << 20])
+        .gz();
+    let barrier = Barrier::new(THREADS);
+
+    // 同时开始的查找只解压一次，都得到同一个文件
+    let paths = thread::scope(|s| {
+        let threads = (0..THREADS)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    ensure(&root, &tar) as usize
+                })
+            })
+            .collect::<Vec<_>>();
+        threads
+            .into_iter()
+            .map(|v| v.join().unwrap() as *mut c_char)
+            .collect::<Vec<_>>()
+    });
+    let files = paths.iter().map(|v| path_of(*v)).collect::<HashSet<_>>();
+    assert_eq!(files.len(), 1, "{files:?}");
+    let file = files.into_iter().next().unwrap();
+    assert_eq!(fs::read(&file).unwrap(), btf);
+    // 同时清理时，文件在最后一份路径清理时删除一次
+    let results = thread::scope(|s| {
+        let threads = paths
+            .iter()
+            .map(|v| *v as usize)
+            .map(|v| {
+                let barrier = &barrier;
+                s.spawn(move || {
+                    barrier.wait();
+                    clean_core_btf_rs2(v as *mut c_char)
+                })
+            })
+            .collect::<Vec<_>>();
+        threads
+            .into_iter()
+            .map(|v| v.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(
+        results
+            .iter()
+            .filter(|v| **v == BPF_COMPAT_BTF_DELETED)
+            .count(),
+        1,
+        "{results:?}"
+    );
+    assert!(results
+        .iter()
+        .all(|v| [BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED].contains(v)));
+    assert!(!file.exists());
+
+    // 反复查找并清理时，返回的文件在调用者清理之前一直可读
+    let deleted = AtomicUsize::new(0);
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                barrier.wait();
+                for _ in 0..ROUNDS {
+                    let path = ensure(&root, &tar);
+                    assert_eq!(fs::read(path_of(path)).unwrap(), btf);
+                    match clean_core_btf_rs2(path) {
+                        BPF_COMPAT_BTF_DELETED => deleted.fetch_add(1, Ordering::SeqCst),
+                        v => {
+                            assert_eq!(v, BPF_COMPAT_PATH_FREED);
+                            0
+                        }
+                    };
+                }
+            });
+        }
+    });
+    assert!(deleted.into_inner() >= 1);
+    // 全部清理后不留下任何文件
+    let left = fs::read_dir(root.path().join("tmp"))
+        .unwrap()
+        .map(|v| v.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert!(left.is_empty(), "{left:?}");
+}
@@ -0,0 +1,110 @@
+//! Truncated and corrupt archives, which fail with an errno rather than a panic
+mod common;
+
+use std::{collections::BTreeSet, os::raw::c_char, ptr};
+
+use bpf_compatible::{free_core_btf_kernel_list, list_core_btf_kernels};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    reexport::tar::{Archive, Header},
+};
+use common::{last_error, lookup};
+use libc::{EILSEQ, EINVAL, ENOENT, ENOEXEC};
+
+const README: &str = "btfhub-archive/README";
+
+fn fixture() -> FixtureArchive {
+    FixtureArchive::new().file(README, b"btfs".to_vec()).btf(
+        "ubuntu",
+        "20.04",
+        "x86_64",
+        "5.4.0-40-generic",
+        btf_of_arch(8, "rip"),
+    )
+}
+
+/// The errno of listing `tar`, or 0
+fn list(tar: &[u8]) -> i32 {
+    let mut entries: *mut *mut c_char = ptr::null_mut();
+    let mut count = 0;
+    let err = list_core_btf_kernels(tar.as_ptr(), tar.len(), &mut entries, &mut count);
+    if err == 0 {
+        free_core_btf_kernel_list(entries);
+    }
+    err
+}
+
+/// `tar` with the size of the entry at `at` set to `size`
+fn with_size(tar: &[u8], at: usize, size: u64) -> Vec<u8> {
+    let mut header = Header::from_byte_slice(&tar[at..at + 512]).clone();
+    header.set_size(size);
+    header.set_cksum();
+    let mut corrupt = tar.to_vec();
+    corrupt[at..at + 512].copy_from_slice(header.as_bytes());
+    corrupt
+}
+
+#[test]
+fn truncated_and_flipped_archives_fail_with_an_errno() {
+    let btf = btf_of_arch(8, "rip");
+    // 损坏可能使路径不再匹配，或使 btf 本身无效
+    let expected = [0, -EINVAL, -EILSEQ, -ENOENT, -ENOEXEC];
+    let mut seen = BTreeSet::new();
+    let mut seed = 7u64;
+    for tar in [fixture().tar(), fixture().gz()] {
+        let mut check = |bytes: &[u8]| {
+            let err = match lookup(bytes) {
+                // 未检出的损坏只可能落在 btf 之外，例如 README 的内容
+                Ok(v) => {
+                    assert_eq!(v, btf);
+                    0
+                }
+                Err(e) => e,
+            };
+            assert!(expected.contains(&err), "{err}: {}", last_error());
+            assert!(expected.contains(&list(bytes)), "{}", last_error());
+            seen.insert(err);
+        };
+        for len in (0..tar.len()).step_by(61) {
+            check(&tar[..len]);
+        }
+        for _ in 0..200 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let mut flipped = tar.clone();
+            let at = (seed >> 33) as usize % flipped.len();
+            flipped[at] ^= 1 << ((seed >> 29) % 8);
+            check(&flipped);
+        }
+    }
+    // 损坏的头部和损坏的 btf 都被检出
+    assert!(
+        seen.contains(&-EINVAL) && seen.contains(&-EILSEQ),
+        "{seen:?}"
+    );
+}
+
+#[test]
+fn oversized_entries_are_named() {
+    let tar = fixture().tar();
+    let headers = Archive::new(&tar[..])
+        .entries()
+        .unwrap()
+        .map(|v| v.unwrap().raw_header_position() as usize)
+        .collect::<Vec<_>>();
+    for size in [tar.len() as u64, 1 << 40, u64::MAX >> 4] {
+        // README 越过了归档的结尾，在读取下一个头部时失败
+        let corrupt = with_size(&tar, headers[0], size);
+        assert_eq!(lookup(&corrupt), Err(-EINVAL));
+        assert!(
+            last_error().contains(&format!("the entry after `{README}`")),
+            "{}",
+            last_error()
+        );
+        assert_eq!(list(&corrupt), -EINVAL);
+        // btf 本身越界时同样
+        let corrupt = with_size(&tar, headers[1], size);
+        assert_eq!(lookup(&corrupt), Err(-EINVAL), "{}", last_error());
+    }
+}
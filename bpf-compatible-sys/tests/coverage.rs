@@ -0,0 +1,124 @@
+//! `strict_coverage`, refusing systems the listing of the archive has no btfs for
+//!
+//! Only built on Linux, whose libc is the one naming the `ENOPKG` of uncovered systems.
+#![cfg(target_os = "linux")]
+mod common;
+
+use std::{
+    ffi::c_void,
+    os::raw::c_int,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bpf_compatible::opts::BpfCompatOpts;
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    listing::{ArchiveListing, LISTING_ENTRY_NAME},
+    progress::PROGRESS_INTERVAL,
+};
+use common::{last_error, lookup_opts, FakeRoot};
+use libc::{ENOENT, ENOPKG};
+
+unsafe extern "C" fn count(_: u64, _: u64, ctx: *mut c_void) -> c_int {
+    (*(ctx as *const AtomicU64)).fetch_add(1, Ordering::SeqCst);
+    0
+}
+
+/// An archive of the btf of `root` under `version` instead of its own, preceded by a
+/// listing of `coverage` if there is one, and followed by padding read in several intervals
+fn archive(root: &FakeRoot, version: &str, coverage: Option<&[&str]>) -> Vec<u8> {
+    let info = &root.info;
+    let path = format!(
+        "btfhub-archive/{}/{}/{}/{}.btf",
+        info.distro_id, version, info.arch, info.kernel_release
+    );
+    let btf = btf_of_arch(8, "covered");
+    let mut fixture = FixtureArchive::new();
+    if let Some(coverage) = coverage {
+        let mut listing = ArchiveListing {
+            coverage: coverage.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        };
+        listing.push(&path, &btf);
+        fixture = fixture.file(
+            &format!("btfhub-archive/{}", LISTING_ENTRY_NAME),
+            listing.to_json(),
+        );
+    }
+    fixture
+        .file(&path, btf)
+        .file("padding", vec![0x5a; 3 * PROGRESS_INTERVAL as usize])
+        .gz()
+}
+
+/// The lookup of `tar`, with `strict_coverage` as given, and how often the progress was told
+fn lookup(root: &FakeRoot, tar: &[u8], strict_coverage: bool) -> (c_int, u64) {
+    let calls = AtomicU64::new(0);
+    let opts = BpfCompatOpts {
+        strict_coverage,
+        progress: Some(count),
+        progress_ctx: &calls as *const _ as *mut c_void,
+        ..root.opts()
+    };
+    let ret = lookup_opts(tar, &opts).err().unwrap_or(0);
+    (ret, calls.into_inner())
+}
+
+#[test]
+fn covered_system_is_looked_up() {
+    let root = FakeRoot::new();
+    let tree = format!("ubuntu/20.04/{}", root.info.arch);
+    let tar = archive(&root, "20.04", Some(&["debian/12/x86_64", &tree]));
+    let (ret, calls) = lookup(&root, &tar, true);
+    assert_eq!(ret, 0, "{}", last_error());
+    assert!(calls > 0);
+    // 以代号命名的目录同样覆盖
+    let tar = archive(
+        &root,
+        "focal",
+        Some(&[&format!("ubuntu/focal/{}", root.info.arch)]),
+    );
+    assert_eq!(lookup(&root, &tar, true).0, 0, "{}", last_error());
+}
+
+#[test]
+fn uncovered_system_fails_before_decompressing() {
+    let root = FakeRoot::new();
+    let tar = archive(
+        &root,
+        "22.04",
+        Some(&["ubuntu/22.04/x86_64", "debian/12/x86_64"]),
+    );
+    assert_eq!(lookup(&root, &tar, true), (-ENOPKG, 0));
+    assert_eq!(
+        last_error(),
+        format!(
+            "This build does not support ubuntu/20.04/{}; supported: ubuntu/22.04/x86_64, debian/12/x86_64",
+            root.info.arch
+        )
+    );
+    // 未设置时照常按清单查找，只给出笼统的“找不到”
+    assert_eq!(lookup(&root, &tar, false).0, -ENOENT);
+    assert!(
+        last_error().contains("not in its manifest.json"),
+        "{}",
+        last_error()
+    );
+    // 旧的清单没有 coverage 时，按列出的 btf 判断
+    let tar = archive(&root, "22.04", Some(&[]));
+    assert_eq!(lookup(&root, &tar, true), (-ENOPKG, 0));
+    assert!(
+        last_error().ends_with(&format!("supported: ubuntu/22.04/{}", root.info.arch)),
+        "{}",
+        last_error()
+    );
+}
+
+#[test]
+fn archive_without_listing_is_looked_up_as_usual() {
+    let root = FakeRoot::new();
+    let (ret, calls) = lookup(&root, &archive(&root, "22.04", None), true);
+    assert_eq!(ret, -ENOENT);
+    assert!(calls > 0);
+    assert_eq!(lookup(&root, &archive(&root, "20.04", None), true).0, 0);
+}
@@ -0,0 +1,78 @@
+//! `max_decompressed_size`, bounding what the archive and its btfs decompress to
+mod common;
+
+use std::{ffi::CStr, io::Write};
+
+use bpf_compatible::{bpf_compatible_strerror, opts::BpfCompatOpts};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    reexport::flate2::{write::GzEncoder, Compression},
+};
+use common::{last_error, lookup_opts, FakeRoot};
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(vec![], Compression::best());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Look up the btf in `tar` with the decompressed size bounded by `max_size`
+fn ensure(root: &FakeRoot, tar: &[u8], max_size: u64) -> i32 {
+    let opts = BpfCompatOpts {
+        max_decompressed_size: max_size,
+        ..root.opts()
+    };
+    lookup_opts(tar, &opts).err().unwrap_or(0)
+}
+
+#[test]
+fn bomb_is_refused_without_running_out_of_memory() {
+    let root = FakeRoot::new();
+    // 64 MiB 的零压缩后不到上限的 1 MiB；放在 btf 之前，找到 btf 前必须读过它
+    let tar = FixtureArchive::new()
+        .file("padding", vec![0; 64 << 20])
+        .file(
+            &format!("btfhub-archive/{}", root.info),
+            btf_of_arch(8, "bomb"),
+        )
+        .gz();
+    assert!(tar.len() < 1 << 20, "{}", tar.len());
+    assert_eq!(ensure(&root, &tar, 1 << 20), -libc::EFBIG);
+    assert!(last_error().contains("limit"), "{}", last_error());
+    let description = unsafe { CStr::from_ptr(bpf_compatible_strerror(-libc::EFBIG)) };
+    assert!(
+        description
+            .to_str()
+            .unwrap()
+            .contains("max_decompressed_size"),
+        "{description:?}"
+    );
+    // 上限足够大时照常找到
+    assert_eq!(ensure(&root, &tar, 128 << 20), 0, "{}", last_error());
+}
+
+#[test]
+fn archive_of_exactly_the_limit_is_read() {
+    let root = FakeRoot::new();
+    let fixture = root.archive(btf_of_arch(8, "edge"));
+    let size = fixture.tar().len() as u64;
+    let tar = fixture.gz();
+    assert_eq!(ensure(&root, &tar, size), 0, "{}", last_error());
+    assert_eq!(ensure(&root, &tar, size - 1), -libc::EFBIG);
+    // 0 表示默认的上限
+    assert_eq!(ensure(&root, &tar, 0), 0, "{}", last_error());
+}
+
+#[test]
+fn gzipped_btfs_are_bounded_too() {
+    let root = FakeRoot::new();
+    let mut btf = btf_of_arch(8, "inner");
+    btf.resize(4 << 20, 0);
+    let entry = format!("btfhub-archive/{}.gz", root.info);
+    let tar = FixtureArchive::new().file(&entry, gzip(&btf)).gz();
+    // 外层归档很小，单独压缩的 btf 解压后超出上限
+    let outer = FixtureArchive::new().file(&entry, gzip(&btf)).tar().len() as u64;
+    assert!(outer < 1 << 20);
+    assert_eq!(ensure(&root, &tar, 1 << 20), -libc::EFBIG);
+    assert!(last_error().contains("gzipped btf"), "{}", last_error());
+}
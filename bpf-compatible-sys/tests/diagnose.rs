@@ -0,0 +1,111 @@
+//! Reports explaining why a lookup provides no btf, which write nothing
+mod common;
+
+use std::{ffi::CStr, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    bpf_compatible_free_buffer, diagnose_core_btf, opts::BpfCompatOpts,
+    BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{last_error, FakeRoot};
+use libc::EINVAL;
+
+/// The report of `tar` with `opts` and `flags`, freed once copied
+fn diagnose(tar: &[u8], opts: *const BpfCompatOpts, flags: u32) -> Result<String, i32> {
+    let mut report: *const c_char = ptr::null();
+    let err = diagnose_core_btf(&mut report, tar.as_ptr(), tar.len(), opts, flags);
+    if err != 0 {
+        assert!(report.is_null());
+        return Err(err);
+    }
+    let text = unsafe { CStr::from_ptr(report) }
+        .to_str()
+        .unwrap()
+        .to_owned();
+    bpf_compatible_free_buffer(report as *mut u8);
+    Ok(text)
+}
+
+#[test]
+fn miss_is_explained_without_writing_anything() {
+    let root = FakeRoot::new();
+    let info = &root.info;
+    let neighbour = format!("ubuntu/20.04/{}/5.4.0-40-generic", info.arch);
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            &info.arch,
+            "5.4.0-40-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .btf(
+            "debian",
+            "11",
+            &info.arch,
+            &info.kernel_release,
+            btf_of_arch(8, "rip"),
+        )
+        .gz();
+    let opts = root.opts();
+    let report = diagnose(&tar, &opts, 0).unwrap();
+    for line in [
+        format!(
+            "system: distro ubuntu, version 20.04 (focal), arch {}, kernel {}\n",
+            info.arch, info.kernel_release
+        ),
+        "native btf: missing\n".to_string(),
+        format!("searched under btfhub-archive/:\n  {}\n", info),
+        "archive: 2 entries scanned, none of the searched paths\n".to_string(),
+        format!("  {}\n", neighbour),
+        "  cache (not consulted): not used\n".to_string(),
+    ] {
+        assert!(report.contains(&line), "{line}\n{report}");
+    }
+    // 其他发行版的同一内核不算相近的内核
+    assert!(!report.contains("debian/11"), "{report}");
+    assert!(!root.path().join("tmp").exists());
+}
+
+#[test]
+fn hit_names_the_entry() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "rip")).gz();
+    let report = diagnose(&tar, &root.opts(), 0).unwrap();
+    assert!(
+        report.contains(&format!(
+            "archive: 1 entries scanned, found btfhub-archive/{}\n",
+            root.info
+        )),
+        "{report}"
+    );
+    assert!(!root.path().join("tmp").exists());
+}
+
+#[test]
+fn invalid_arguments_are_refused() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "rip")).gz();
+    let opts = root.opts();
+    assert_eq!(
+        diagnose_core_btf(ptr::null_mut(), tar.as_ptr(), tar.len(), &opts, 0),
+        -EINVAL
+    );
+    assert_eq!(diagnose(&tar[..8], &opts, 0), Err(-EINVAL));
+    assert_eq!(
+        diagnose(&tar, &opts, BPF_COMPAT_DIAGNOSE_PROBE_DOWNLOAD << 1),
+        Err(-EINVAL)
+    );
+    assert!(
+        last_error().contains("Unknown flags 0x2"),
+        "{}",
+        last_error()
+    );
+    // opts 可以为 NULL，诊断正在运行的系统
+    let report = diagnose(&tar, ptr::null(), 0).unwrap();
+    assert!(
+        report.contains("searched under btfhub-archive/:"),
+        "{report}"
+    );
+}
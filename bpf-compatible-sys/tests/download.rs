@@ -0,0 +1,110 @@
+//! Downloading the btfs missing from the archive, from a local server
+//!
+//! This is the only test of the binary, so setting `XDG_CACHE_HOME` affects no other.
+#![cfg(feature = "download")]
+mod common;
+
+use std::{
+    ffi::CString,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use bpf_compatible::{opts::BpfCompatOpts, BPF_COMPAT_PATH_FREED};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{ensure_opts, last_error, take_btf, FakeRoot};
+
+/// Serve `btf` for every path ending with `.btf` and 404 otherwise, on a local port;
+/// returns the base url and the paths requested so far
+fn serve(btf: Vec<u8>) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(vec![]));
+    let seen = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let path = request_line
+                .split(' ')
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+            let (status, body) = if path.ends_with(".btf") {
+                (200, &btf[..])
+            } else {
+                (404, &b"not found"[..])
+            };
+            seen.lock().unwrap().push(path);
+            let head = format!(
+                "HTTP/1.1 {} Fixture\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(body);
+        }
+    });
+    (base, requests)
+}
+
+fn ensure(tar: &[u8], opts: &BpfCompatOpts) -> Result<Vec<u8>, i32> {
+    let path = ensure_opts(tar, opts)?;
+    // 下载的 btf 保存在缓存中，不会被删除
+    Ok(take_btf(path, BPF_COMPAT_PATH_FREED))
+}
+
+#[test]
+fn missing_btfs_are_downloaded_only_when_allowed() {
+    let root = FakeRoot::new();
+    std::env::set_var("XDG_CACHE_HOME", root.path().join("cache"));
+    // 归档中只有其他内核的 btf
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            &root.info.arch,
+            "0.0.0-other",
+            btf_of_arch(8, "other"),
+        )
+        .gz();
+    let (base, requests) = serve(btf_of_arch(8, "served"));
+    let template = CString::new(format!(
+        "{}/{{distro}}/{{version}}/{{arch}}/{{kernel}}.btf",
+        base
+    ))
+    .unwrap();
+
+    // 不允许时不会访问网络
+    let mut opts = root.opts();
+    opts.download_url = template.as_ptr();
+    assert_eq!(ensure(&tar, &opts), Err(-libc::ENOENT));
+    assert!(requests.lock().unwrap().is_empty());
+
+    opts.allow_download = true;
+    assert_eq!(ensure(&tar, &opts).unwrap(), btf_of_arch(8, "served"));
+    assert_eq!(
+        *requests.lock().unwrap(),
+        [format!(
+            "/ubuntu/20.04/{}/{}.btf",
+            root.info.arch, root.info.kernel_release
+        )]
+    );
+    // 再次查找使用缓存，不再下载
+    assert_eq!(ensure(&tar, &opts).unwrap(), btf_of_arch(8, "served"));
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    // 下载失败时仍返回 -ENOENT，原因保存在最后的错误中
+    let missing = CString::new(format!("{}/{{kernel}}.missing", base)).unwrap();
+    opts.download_url = missing.as_ptr();
+    assert_eq!(ensure(&tar, &opts), Err(-libc::ENOENT));
+    assert!(last_error().contains("404"), "{}", last_error());
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
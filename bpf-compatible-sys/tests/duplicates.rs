@@ -0,0 +1,67 @@
+//! Archives holding an entry more than once, e.g. after appending to them with `tar -r`
+mod common;
+
+use std::fs;
+
+use bpf_compatible::BPF_COMPAT_BTF_DELETED;
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{ensure_opts, last_error, take_btf, FakeRoot};
+
+/// The btf found in `tar` and the files of the temporary directory then, or the error and the files
+fn ensure(root: &FakeRoot, tar: &[u8]) -> (Result<Vec<u8>, i32>, Vec<String>) {
+    let path = ensure_opts(tar, &root.opts());
+    let files = fs::read_dir(root.path().join("tmp"))
+        .map(|v| {
+            v.map(|v| v.unwrap().file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    (path.map(|v| take_btf(v, BPF_COMPAT_BTF_DELETED)), files)
+}
+
+#[test]
+fn last_copy_wins_with_a_single_file() {
+    let root = FakeRoot::new();
+    let entry = format!("btfhub-archive/{}", root.info);
+    let tar = FixtureArchive::new()
+        .file(&entry, btf_of_arch(8, "first"))
+        .file(&entry, btf_of_arch(8, "second"))
+        .file(&entry, btf_of_arch(8, "third"))
+        .gz();
+    let (btf, files) = ensure(&root, &tar);
+    // 与 tar -x 一样取最后一份
+    assert_eq!(btf, Ok(btf_of_arch(8, "third")));
+    assert_eq!(files.len(), 1, "{files:?}");
+}
+
+#[test]
+fn duplicate_link_targets_share_a_file() {
+    let root = FakeRoot::new();
+    let entry = format!("btfhub-archive/{}", root.info);
+    let target = format!("{}.orig", entry);
+    let name = target.rsplit('/').next().unwrap().to_string();
+    let tar = FixtureArchive::new()
+        .symlink(&entry, &name)
+        .file(&target, btf_of_arch(8, "first"))
+        .file(&target, btf_of_arch(8, "second"))
+        .gz();
+    let (btf, files) = ensure(&root, &tar);
+    assert_eq!(btf, Ok(btf_of_arch(8, "second")));
+    assert_eq!(files.len(), 1, "{files:?}");
+}
+
+#[test]
+fn failed_copy_leaves_nothing_behind() {
+    let root = FakeRoot::new();
+    let entry = format!("btfhub-archive/{}", root.info);
+    // 最后一份不是 btf 时失败，之前写入的临时文件被删除
+    let tar = FixtureArchive::new()
+        .file(&entry, btf_of_arch(8, "valid"))
+        .file(&entry, b"not a btf".to_vec())
+        .gz();
+    let (btf, files) = ensure(&root, &tar);
+    assert!(btf.is_err());
+    assert!(!last_error().is_empty());
+    assert!(root.path().join("tmp").is_dir());
+    assert!(files.is_empty(), "{files:?}");
+}
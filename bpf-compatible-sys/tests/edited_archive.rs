@@ -0,0 +1,50 @@
+//! Archives edited in the middle, keeping their size and both of their ends
+mod common;
+
+use bpf_compatible::BPF_COMPAT_BTF_DELETED;
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{ensure_opts, lookup, lookup_opts, take_btf, FakeRoot};
+use libc::ENOENT;
+
+/// Padding before and after the edited entry, past what any window of the ends would cover
+const PADDING: usize = 128 << 10;
+
+/// An uncompressed tar holding `path` between two large paddings
+fn padded(path: &str, contents: Vec<u8>) -> Vec<u8> {
+    FixtureArchive::new()
+        .file("head", vec![0; PADDING])
+        .file(path, contents)
+        .file("tail", vec![0; PADDING])
+        .tar()
+}
+
+/// Both the negative cache and the memo are global, so the cases run in one test
+#[test]
+fn archives_edited_in_the_middle_are_looked_up_again() {
+    // 只改了中间条目名的归档不能复用上一次的未命中
+    let btf = btf_of_arch(8, "r15");
+    let missing = padded(
+        "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-41-generic.btf",
+        btf.clone(),
+    );
+    let present = padded(
+        "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+        btf.clone(),
+    );
+    assert_eq!(missing.len(), present.len());
+    assert_eq!(lookup(&missing), Err(-ENOENT));
+    assert_eq!(lookup(&present), Ok(btf));
+
+    // 只改了中间 btf 内容的归档不能复用上一次提取的 btf
+    let root = FakeRoot::new();
+    let entry = format!("btfhub-archive/{}", root.info);
+    let old = padded(&entry, btf_of_arch(8, "aaaa"));
+    let new = padded(&entry, btf_of_arch(8, "bbbb"));
+    assert_eq!(old.len(), new.len());
+    let held = ensure_opts(&old, &root.opts()).unwrap();
+    assert_eq!(lookup_opts(&new, &root.opts()), Ok(btf_of_arch(8, "bbbb")));
+    assert_eq!(
+        take_btf(held, BPF_COMPAT_BTF_DELETED),
+        btf_of_arch(8, "aaaa")
+    );
+}
@@ -0,0 +1,50 @@
+//! Btfs of the other byte order than the host's, e.g. from an s390x box, packed by mistake
+mod common;
+
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{last_error, lookup};
+
+/// `btf` as a host of the other byte order would have written it: the magic and the
+/// header fields swapped, the types left alone as only the header is looked at
+fn byte_swapped(mut btf: Vec<u8>) -> Vec<u8> {
+    btf.swap(0, 1);
+    for field in btf[4..24].chunks_mut(4) {
+        field.reverse();
+    }
+    btf
+}
+
+const ENTRY: &str = "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf";
+const CODENAME_ENTRY: &str = "btfhub-archive/ubuntu/focal/x86_64/5.4.0-40-generic.btf";
+
+#[test]
+fn only_a_byte_swapped_btf_fails_with_enoexec() {
+    let tar = FixtureArchive::new()
+        .file(ENTRY, byte_swapped(btf_of_arch(8, "r15")))
+        .gz();
+    assert_eq!(lookup(&tar), Err(-libc::ENOEXEC));
+    assert!(
+        last_error().contains("other byte order"),
+        "{}",
+        last_error()
+    );
+}
+
+#[test]
+fn byte_swapped_btf_is_skipped_for_another_candidate() {
+    let swapped = byte_swapped(btf_of_arch(8, "r15"));
+    let valid = btf_of_arch(8, "r15");
+    // 无论正确的条目位于字节序不符的条目之前还是之后
+    for tar in [
+        FixtureArchive::new()
+            .file(ENTRY, swapped.clone())
+            .file(CODENAME_ENTRY, valid.clone())
+            .gz(),
+        FixtureArchive::new()
+            .file(CODENAME_ENTRY, valid.clone())
+            .file(ENTRY, swapped.clone())
+            .gz(),
+    ] {
+        assert_eq!(lookup(&tar), Ok(valid.clone()));
+    }
+}
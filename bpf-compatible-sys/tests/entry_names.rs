@@ -0,0 +1,78 @@
+//! Entry names that aren't UTF-8, or can't be read at all
+mod common;
+
+use bpf_compatible_rs::{
+    fixture::btf_of_arch,
+    reexport::tar::{Builder, EntryType, Header},
+};
+use common::{last_error, lookup};
+use libc::EILSEQ;
+
+const BTF_PATH: &[u8] = b"btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf";
+
+/// Append a regular entry named `name` verbatim, preceded by a PAX header holding `pax` if any
+fn append(builder: &mut Builder<Vec<u8>>, name: &[u8], contents: &[u8], pax: Option<&[u8]>) {
+    if let Some(pax) = pax {
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_size(pax.len() as u64);
+        header.set_path("PaxHeaders/entry").unwrap();
+        header.set_cksum();
+        builder.append(&header, pax).unwrap();
+    }
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.as_old_mut().name[..name.len()].copy_from_slice(name);
+    header.set_cksum();
+    builder.append(&header, contents).unwrap();
+}
+
+/// Name, contents and PAX header of an entry
+type Entry<'a> = (&'a [u8], &'a [u8], Option<&'a [u8]>);
+
+fn archive(entries: &[Entry]) -> Vec<u8> {
+    let mut builder = Builder::new(vec![]);
+    for (name, contents, pax) in entries {
+        append(&mut builder, name, contents, *pax);
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn latin1_names_are_passed_over() {
+    let btf = btf_of_arch(8, "rip");
+    for junk in [
+        &b"btfhub-archive/caf\xe9.btf"[..],
+        b"btfhub-archive/\xff\xfe/readme",
+    ] {
+        let before = archive(&[(junk, b"junk", None), (BTF_PATH, &btf, None)]);
+        assert_eq!(lookup(&before), Ok(btf.clone()));
+        let after = archive(&[(BTF_PATH, &btf, None), (junk, b"junk", None)]);
+        assert_eq!(lookup(&after), Ok(btf.clone()));
+    }
+}
+
+#[test]
+fn unreadable_names_fail_only_the_matching_entry() {
+    let btf = btf_of_arch(8, "rip");
+    // 记录的长度超出了扩展头的内容
+    let malformed: &[u8] = b"99 path=btfhub-archive/other.btf\n";
+    let tar = archive(&[
+        (b"btfhub-archive/other.btf", b"junk", Some(malformed)),
+        (BTF_PATH, &btf, None),
+    ]);
+    assert_eq!(lookup(&tar), Ok(btf.clone()));
+
+    let tar = archive(&[(BTF_PATH, &btf, Some(malformed))]);
+    assert_eq!(lookup(&tar), Err(-EILSEQ));
+    assert!(
+        last_error().contains(&format!(
+            "Failed to read path name of {}",
+            String::from_utf8_lossy(BTF_PATH)
+        )),
+        "{}",
+        last_error()
+    );
+}
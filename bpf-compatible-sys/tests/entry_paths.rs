@@ -0,0 +1,74 @@
+//! Entry paths written differently by the tools creating the archive
+//!
+//! GNU tar keeps the `./` of `tar czf btfs.tar.gz ./btfhub-archive` and writes GNU headers,
+//! bsdtar writes ustar headers without it, the `tar` crate writes what it's given, and
+//! `tar -P` or other tools may keep a leading `/` or duplicate slashes.
+mod common;
+
+use bpf_compatible_rs::{
+    fixture::btf_of_arch,
+    reexport::{
+        flate2::{write::GzEncoder, Compression},
+        tar::{Builder, EntryType, Header},
+    },
+};
+use common::lookup;
+
+const RELEASE_PATH: &str = "ubuntu/20.04/x86_64/5.4.0-40-generic.btf";
+
+/// A gzipped tar of the directories leading to `RELEASE_PATH`, then the btf, all named
+/// `<lead><component>...` and written verbatim into headers made by `new_header`
+fn archive(new_header: fn() -> Header, lead: &str, separator: &str) -> Vec<u8> {
+    let mut builder = Builder::new(GzEncoder::new(vec![], Compression::fast()));
+    let mut append = |name: &str, entry_type: EntryType, contents: &[u8]| {
+        let mut header = new_header();
+        header.set_entry_type(entry_type);
+        header.set_size(contents.len() as u64);
+        header.set_mode(if entry_type.is_dir() { 0o755 } else { 0o644 });
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_cksum();
+        builder.append(&header, contents).unwrap();
+    };
+    let components = ["btfhub-archive"]
+        .into_iter()
+        .chain(RELEASE_PATH.split('/'))
+        .collect::<Vec<_>>();
+    if lead == "./" {
+        append("./", EntryType::Directory, &[]);
+    }
+    for end in 1..components.len() {
+        let dir = format!("{}{}/", lead, components[..end].join(separator));
+        append(&dir, EntryType::Directory, &[]);
+    }
+    let file = format!("{}{}", lead, components.join(separator));
+    append(&file, EntryType::Regular, &btf_of_arch(8, "r15"));
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn gnu_tar_entries_with_a_leading_dot_are_found() {
+    let tar = archive(Header::new_gnu, "./", "/");
+    assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "r15")));
+}
+
+#[test]
+fn bsdtar_entries_without_a_leading_dot_are_found() {
+    let tar = archive(Header::new_ustar, "", "/");
+    assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "r15")));
+}
+
+#[test]
+fn absolute_entries_are_found() {
+    for new_header in [Header::new_gnu, Header::new_ustar] {
+        let tar = archive(new_header, "/", "/");
+        assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "r15")));
+    }
+}
+
+#[test]
+fn duplicate_slashes_are_collapsed() {
+    for lead in ["", "./", "//"] {
+        let tar = archive(Header::new_gnu, lead, "//");
+        assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "r15")), "{lead:?}");
+    }
+}
@@ -0,0 +1,147 @@
+//! `extract_core_btf_to`, writing the btf of the running kernel to a path of the caller
+mod common;
+
+use std::{ffi::CString, fs, os::unix::ffi::OsStrExt, path::Path, ptr};
+
+use bpf_compatible::{
+    extract_core_btf_to, BPF_COMPAT_CUSTOM_BTF, BPF_COMPAT_EXTRACT_ALWAYS,
+    BPF_COMPAT_EXTRACT_CREATE_DIRS, BPF_COMPAT_EXTRACT_OVERWRITE, BPF_COMPAT_NATIVE_BTF,
+};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    SystemInfo,
+};
+use common::last_error;
+
+/// An archive holding `btf` as the btf of the host
+fn host_archive(btf: Vec<u8>) -> Vec<u8> {
+    let host = SystemInfo::detect().unwrap();
+    FixtureArchive::new()
+        .file(&format!("btfhub-archive/{host}"), btf)
+        .gz()
+}
+
+fn extract_to(tar: &[u8], dest: &Path, flags: u32) -> i32 {
+    let dest = CString::new(dest.as_os_str().as_bytes()).unwrap();
+    extract_core_btf_to(tar.as_ptr(), tar.len(), dest.as_ptr(), flags)
+}
+
+#[test]
+fn bad_arguments_are_rejected() {
+    let tar = host_archive(btf_of_arch(8, "args"));
+    let dir = tempfile::tempdir().unwrap();
+    let dest = CString::new(dir.path().join("vmlinux.btf").to_str().unwrap()).unwrap();
+    assert_eq!(
+        extract_core_btf_to(tar.as_ptr(), tar.len(), ptr::null(), 0),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        extract_core_btf_to(ptr::null(), 0, dest.as_ptr(), 0),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        extract_core_btf_to(tar.as_ptr(), tar.len(), dest.as_ptr(), 1 << 7),
+        -libc::EINVAL
+    );
+    assert!(last_error().contains("0x80"), "{}", last_error());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn native_btf_is_not_written_unless_asked() {
+    if !Path::new("/sys/kernel/btf/vmlinux").exists() {
+        return;
+    }
+    let tar = host_archive(btf_of_arch(8, "native"));
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("vmlinux.btf");
+    assert_eq!(extract_to(&tar, &dest, 0), BPF_COMPAT_NATIVE_BTF);
+    assert!(!dest.exists());
+    // 调用者明确要文件时照常写入
+    assert_eq!(
+        extract_to(&tar, &dest, BPF_COMPAT_EXTRACT_ALWAYS),
+        BPF_COMPAT_CUSTOM_BTF
+    );
+    assert_eq!(fs::read(&dest).unwrap(), btf_of_arch(8, "native"));
+}
+
+#[test]
+fn existing_files_are_kept_without_overwrite() {
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("vmlinux.btf");
+    let first = host_archive(btf_of_arch(8, "first"));
+    let second = host_archive(btf_of_arch(8, "second"));
+    assert_eq!(
+        extract_to(&first, &dest, BPF_COMPAT_EXTRACT_ALWAYS),
+        BPF_COMPAT_CUSTOM_BTF
+    );
+    assert_eq!(
+        extract_to(&second, &dest, BPF_COMPAT_EXTRACT_ALWAYS),
+        -libc::EEXIST
+    );
+    assert!(
+        last_error().contains(dest.to_str().unwrap()),
+        "{}",
+        last_error()
+    );
+    assert_eq!(fs::read(&dest).unwrap(), btf_of_arch(8, "first"));
+    assert_eq!(
+        extract_to(
+            &second,
+            &dest,
+            BPF_COMPAT_EXTRACT_ALWAYS | BPF_COMPAT_EXTRACT_OVERWRITE
+        ),
+        BPF_COMPAT_CUSTOM_BTF
+    );
+    assert_eq!(fs::read(&dest).unwrap(), btf_of_arch(8, "second"));
+    // 没有留下临时文件
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn missing_parent_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("run/myagent/vmlinux.btf");
+    let tar = host_archive(btf_of_arch(8, "dirs"));
+    assert_eq!(
+        extract_to(&tar, &dest, BPF_COMPAT_EXTRACT_ALWAYS),
+        -libc::ENOENT
+    );
+    assert!(!dir.path().join("run").exists());
+    assert_eq!(
+        extract_to(
+            &tar,
+            &dest,
+            BPF_COMPAT_EXTRACT_ALWAYS | BPF_COMPAT_EXTRACT_CREATE_DIRS
+        ),
+        BPF_COMPAT_CUSTOM_BTF
+    );
+    assert_eq!(fs::read(&dest).unwrap(), btf_of_arch(8, "dirs"));
+}
+
+#[test]
+fn failed_lookups_write_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let dest = dir.path().join("vmlinux.btf");
+    fs::write(&dest, b"previous").unwrap();
+    // 归档中没有本机的 btf
+    let other = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "0.0.0-not-the-host",
+            btf_of_arch(8, "other"),
+        )
+        .gz();
+    let flags = BPF_COMPAT_EXTRACT_ALWAYS | BPF_COMPAT_EXTRACT_OVERWRITE;
+    assert_eq!(extract_to(&other, &dest, flags), -libc::ENOENT);
+    // 目标是目录时重命名失败，不留下临时文件
+    let taken = dir.path().join("taken");
+    fs::create_dir(&taken).unwrap();
+    let tar = host_archive(btf_of_arch(8, "taken"));
+    assert!(extract_to(&tar, &taken, flags) < 0);
+    assert!(taken.is_dir());
+    assert_eq!(fs::read(&dest).unwrap(), b"previous");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
@@ -0,0 +1,126 @@
+//! `ensure_core_btf_with_tar_binary` end to end, for systems faked with the `fake-system` feature
+//!
+//! The variables are those of the whole process, so this is the only test of the binary.
+mod common;
+
+use bpf_compatible::{bpf_compatible_features, BPF_COMPAT_FEATURE_FAKE_SYSTEM};
+
+#[test]
+fn lookups_of_faked_systems() {
+    assert_eq!(
+        bpf_compatible_features() & BPF_COMPAT_FEATURE_FAKE_SYSTEM != 0,
+        cfg!(feature = "fake-system")
+    );
+    #[cfg(feature = "fake-system")]
+    faked::lookups();
+}
+
+#[cfg(feature = "fake-system")]
+mod faked {
+    use std::{os::raw::c_char, ptr};
+
+    use bpf_compatible::{ensure_core_btf_with_tar_binary, BPF_COMPAT_BTF_DELETED};
+    use bpf_compatible_rs::{
+        fake::{FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV},
+        fixture::{btf_of_arch, FixtureArchive},
+    };
+
+    use crate::common::{last_error, take_btf};
+
+    /// (distro, version, uname machine, kernel release) of the faked systems
+    const SYSTEMS: &[(&str, &str, &str, &str)] = &[
+        ("ubuntu", "20.04", "x86_64", "5.4.0-40-generic"),
+        ("centos", "7", "x86_64", "3.10.0-1160.el7.x86_64"),
+        ("debian", "11", "aarch64", "5.10.0-23-arm64"),
+        ("fedora", "38", "x86_64", "6.2.9-300.fc38.x86_64"),
+        ("amzn", "2", "x86_64", "4.14.320-243.544.amzn2.x86_64"),
+        ("debian", "10", "i686", "4.19.0-21-686-pae"),
+    ];
+
+    /// Fake the system `(distro, version, machine, release)`, an empty version leaving it unset
+    fn fake((distro, version, machine, release): (&str, &str, &str, &str)) {
+        std::env::set_var(FAKE_DISTRO_ENV, distro);
+        std::env::set_var(FAKE_VERSION_ENV, version);
+        std::env::set_var(FAKE_ARCH_ENV, machine);
+        std::env::set_var(FAKE_KERNEL_ENV, release);
+    }
+
+    /// The btf of the faked system in `tar`, which is removed, or the error
+    fn ensure(tar: &[u8]) -> Result<Vec<u8>, i32> {
+        let mut path: *const c_char = ptr::null();
+        match ensure_core_btf_with_tar_binary(&mut path, tar.as_ptr(), tar.len() as i32) {
+            0 => {}
+            err => return Err(err),
+        }
+        // 伪造内核版本时不使用真实内核自带的 btf
+        assert!(!path.is_null());
+        Ok(take_btf(path, BPF_COMPAT_BTF_DELETED))
+    }
+
+    /// The btf of `system` in the fixtures, of the pointer size of its machine
+    fn btf_of(system: &(&str, &str, &str, &str)) -> Vec<u8> {
+        let long_size = if system.2 == "i686" { 4 } else { 8 };
+        btf_of_arch(long_size, system.3)
+    }
+
+    /// Directory of btfhub for `machine`, e.g. `arm64` for `aarch64` and `x86` for `i686`
+    fn directory(machine: &str) -> &str {
+        match machine {
+            "aarch64" => "arm64",
+            "i686" => "x86",
+            v => v,
+        }
+    }
+
+    pub fn lookups() {
+        let mut fixture = FixtureArchive::new();
+        for system in SYSTEMS {
+            let (distro, version, machine, release) = *system;
+            fixture = fixture.btf(distro, version, directory(machine), release, btf_of(system));
+        }
+        let tar = fixture.gz();
+
+        for system in SYSTEMS {
+            fake(*system);
+            assert_eq!(
+                ensure(&tar).unwrap_or_else(|e| panic!("{system:?}: {e} {}", last_error())),
+                btf_of(system),
+                "{system:?}"
+            );
+        }
+
+        // 滚动发行版没有版本号，按内核版本在 generic 目录下查找
+        let tar = FixtureArchive::new()
+            .file(
+                "btfhub-archive/generic/x86_64/6.5.3-arch1-1.btf",
+                btf_of_arch(8, "arch"),
+            )
+            .gz();
+        fake(("arch", "", "x86_64", "6.5.3-arch1-1"));
+        assert_eq!(ensure(&tar).unwrap(), btf_of_arch(8, "arch"));
+
+        // 其他系统的 btf 不会被用于伪造的系统，除非内核属于另一版本的系列
+        let tar = FixtureArchive::new()
+            .btf(
+                "ubuntu",
+                "20.04",
+                "x86_64",
+                "5.4.0-40-generic",
+                btf_of_arch(8, "ubuntu"),
+            )
+            .gz();
+        // 18.04 的 HWE 内核属于 20.04 的系列
+        fake(("ubuntu", "18.04", "x86_64", "5.4.0-40-generic"));
+        assert_eq!(ensure(&tar).unwrap(), btf_of_arch(8, "ubuntu"));
+        for system in [
+            ("ubuntu", "22.04", "x86_64", "5.4.0-42-generic"),
+            ("ubuntu", "20.04", "aarch64", "5.4.0-40-generic"),
+            ("ubuntu", "20.04", "x86_64", "5.4.0-42-generic"),
+            ("debian", "20.04", "x86_64", "5.4.0-40-generic"),
+        ] {
+            fake(system);
+            assert_eq!(ensure(&tar), Err(-libc::ENOENT), "{system:?}");
+            assert!(last_error().contains(system.3), "{}", last_error());
+        }
+    }
+}
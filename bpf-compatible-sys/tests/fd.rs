@@ -0,0 +1,98 @@
+//! `ensure_core_btf_with_fd`, reading the archive from a descriptor of the caller
+#![cfg(target_os = "linux")]
+mod common;
+
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    os::{
+        raw::c_char,
+        unix::io::{AsRawFd, FromRawFd},
+    },
+    path::Path,
+    ptr, thread,
+};
+
+use bpf_compatible::{ensure_core_btf_with_fd, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    SystemInfo,
+};
+use common::take_btf;
+
+/// An archive with a btf for the running system
+fn host_archive() -> Vec<u8> {
+    let host = SystemInfo::detect().unwrap();
+    FixtureArchive::new()
+        .file(&format!("btfhub-archive/{host}"), btf_of_arch(8, "rip"))
+        .gz()
+}
+
+/// What a lookup of [`host_archive`] returns: nothing if the kernel has native btf
+fn expected() -> Option<Vec<u8>> {
+    (!Path::new("/sys/kernel/btf/vmlinux").exists()).then(|| btf_of_arch(8, "rip"))
+}
+
+fn lookup_fd(fd: i32) -> Result<Option<Vec<u8>>, i32> {
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_with_fd(&mut path, fd);
+    if err != 0 {
+        assert!(path.is_null());
+        return Err(err);
+    }
+    if path.is_null() {
+        return Ok(None);
+    }
+    let contents = take_btf(path, BPF_COMPAT_BTF_DELETED);
+    Ok(Some(contents))
+}
+
+fn is_open(fd: i32) -> bool {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    flags != -1
+}
+
+#[test]
+fn regular_file_is_read_from_its_start() {
+    let archive = host_archive();
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&archive).unwrap();
+    // 描述符不在开头时回到开头读取
+    file.seek(SeekFrom::Start(100)).unwrap();
+    assert_eq!(lookup_fd(file.as_raw_fd()), Ok(expected()));
+    assert!(is_open(file.as_raw_fd()));
+    assert_eq!(file.stream_position().unwrap(), archive.len() as u64);
+}
+
+#[test]
+fn pipe_is_streamed_until_closed() {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let [reader, writer] = fds;
+    let archive = host_archive();
+    // 分成小块写入，读取方会遇到不完整的读取
+    let feeder = thread::spawn(move || {
+        let mut writer = unsafe { File::from_raw_fd(writer) };
+        for chunk in archive.chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+    });
+    assert_eq!(lookup_fd(reader), Ok(expected()));
+    feeder.join().unwrap();
+    // 读到了管道结束，描述符仍然打开
+    assert!(is_open(reader));
+    let mut rest = [0u8; 1];
+    assert_eq!(
+        unsafe { libc::read(reader, rest.as_mut_ptr().cast(), 1) },
+        0
+    );
+    unsafe { libc::close(reader) };
+}
+
+#[test]
+fn invalid_descriptors_fail_with_their_errno() {
+    assert_eq!(lookup_fd(-1), Err(-libc::EBADF));
+    let dir = File::open(std::env::temp_dir()).unwrap();
+    assert_eq!(lookup_fd(dir.as_raw_fd()), Err(-libc::EISDIR));
+    assert_eq!(ensure_core_btf_with_fd(ptr::null_mut(), 0), -libc::EINVAL);
+}
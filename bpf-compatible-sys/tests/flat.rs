@@ -0,0 +1,108 @@
+//! Archives of btfs at their root, named after the kernel release alone
+mod common;
+
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    flat::ARCH_MARKER_NAME,
+    index::prepend_index,
+};
+use common::{last_error, lookup};
+use libc::ENOENT;
+
+const FLAT_BTF: &str = "5.4.0-40-generic.btf";
+
+/// The forms of `archive` a lookup reads, streamed or sliced through its index
+fn forms(archive: &FixtureArchive) -> [(&'static str, Vec<u8>); 3] {
+    [
+        ("gz", archive.gz()),
+        ("tar", archive.tar()),
+        ("indexed", prepend_index(&archive.tar()).unwrap()),
+    ]
+}
+
+#[test]
+fn flat_btf_is_found_by_release() {
+    let archive = FixtureArchive::new()
+        .file("5.4.0-26-generic.btf", btf_of_arch(8, "26"))
+        .file(FLAT_BTF, btf_of_arch(8, "40"));
+    for (form, tar) in forms(&archive) {
+        assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "40")), "{form}");
+    }
+}
+
+#[test]
+fn btfhub_tree_comes_before_the_flat_btfs() {
+    let archive = FixtureArchive::new()
+        .file(FLAT_BTF, btf_of_arch(8, "flat"))
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "hub"),
+        );
+    for (form, tar) in forms(&archive) {
+        assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "hub")), "{form}");
+    }
+    // 目录中只有其他内核时仍使用根目录下的 btf
+    let archive = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-26-generic",
+            btf_of_arch(8, "hub"),
+        )
+        .file(FLAT_BTF, btf_of_arch(8, "flat"));
+    for (form, tar) in forms(&archive) {
+        assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "flat")), "{form}");
+    }
+}
+
+#[test]
+fn arch_marker_of_another_architecture_refuses_the_flat_btfs() {
+    // 流式扫描时标记可能在匹配的条目之前或之后
+    let before = FixtureArchive::new()
+        .file(ARCH_MARKER_NAME, b"aarch64\n".to_vec())
+        .file(FLAT_BTF, btf_of_arch(8, "x0"));
+    let after = FixtureArchive::new()
+        .file(FLAT_BTF, btf_of_arch(8, "x0"))
+        .file(ARCH_MARKER_NAME, b"arm64".to_vec());
+    for (order, archive) in [("before", before), ("after", after)] {
+        for (form, tar) in forms(&archive) {
+            assert_eq!(lookup(&tar), Err(-ENOENT), "{order} {form}");
+            let error = last_error();
+            assert!(
+                error.contains("for the architecture aarch64, not x86_64")
+                    || error.contains("for the architecture arm64, not x86_64"),
+                "{order} {form}: {error}"
+            );
+        }
+    }
+    // 标记不影响 btfhub 目录中的条目
+    let archive = FixtureArchive::new()
+        .file(ARCH_MARKER_NAME, b"aarch64".to_vec())
+        .file(FLAT_BTF, btf_of_arch(8, "flat"))
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "hub"),
+        );
+    for (form, tar) in forms(&archive) {
+        assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "hub")), "{form}");
+    }
+}
+
+#[test]
+fn arch_marker_naming_the_architecture_is_accepted() {
+    for marker in ["x86_64", "amd64\n", " x86_64\r\n"] {
+        let archive = FixtureArchive::new()
+            .file(ARCH_MARKER_NAME, marker.as_bytes().to_vec())
+            .file(FLAT_BTF, btf_of_arch(8, "x0"));
+        for (form, tar) in forms(&archive) {
+            assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "x0")), "{marker:?} {form}");
+        }
+    }
+}
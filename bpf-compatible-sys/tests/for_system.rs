@@ -0,0 +1,156 @@
+//! Lookups for a system given by the caller instead of the running one
+mod common;
+
+use std::{ffi::CString, os::raw::c_char, ptr};
+
+use bpf_compatible::{ensure_core_btf_for_system, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::{
+    current_kernel_release,
+    fixture::{btf_of_arch, FixtureArchive},
+    SystemInfo,
+};
+use common::take_btf;
+
+/// A btf told from the others by the name of its register
+fn tagged(tag: &str) -> Vec<u8> {
+    btf_of_arch(8, tag)
+}
+
+/// A gzipped archive with the btfs at the given paths under `btfhub-archive`
+fn archive_of(btfs: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    btfs.iter()
+        .fold(FixtureArchive::new(), |archive, (path, btf)| {
+            archive.file(&format!("btfhub-archive/{}", path), btf.clone())
+        })
+        .gz()
+}
+
+/// Look up the btf in `tar` for the system, NULL for each `None`, returning its contents
+/// or NULL if the native btf is used
+fn lookup(tar: &[u8], identity: [Option<&str>; 4]) -> Result<Option<Vec<u8>>, i32> {
+    let owned = identity.map(|v| v.map(|v| CString::new(v).unwrap()));
+    let [distro, version, arch, release] = owned
+        .each_ref()
+        .map(|v| v.as_ref().map_or(ptr::null(), |v| v.as_ptr()));
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_for_system(
+        &mut path,
+        tar.as_ptr(),
+        tar.len(),
+        distro,
+        version,
+        arch,
+        release,
+    );
+    if err != 0 {
+        return Err(err);
+    }
+    if path.is_null() {
+        return Ok(None);
+    }
+    let contents = take_btf(path, BPF_COMPAT_BTF_DELETED);
+    Ok(Some(contents))
+}
+
+#[test]
+fn each_identity_finds_its_own_btf() {
+    let tar = archive_of(&[
+        ("ubuntu/20.04/x86_64/5.4.0-40-generic.btf", tagged("ubuntu")),
+        ("ubuntu/focal/x86_64/5.4.0-42-generic.btf", tagged("focal")),
+        (
+            "centos/8/arm64/4.18.0-348.el8.aarch64.btf",
+            tagged("centos"),
+        ),
+        ("debian/11/x86_64/5.10.0-23-amd64.btf", tagged("debian")),
+    ]);
+    for (identity, tag) in [
+        (["ubuntu", "20.04", "x86_64", "5.4.0-40-generic"], "ubuntu"),
+        // 没有版本目录时使用代号目录
+        (["ubuntu", "20.04", "x86_64", "5.4.0-42-generic"], "focal"),
+        // 版本和架构按 btfhub 的目录名归一化
+        (
+            ["centos", "8.5", "aarch64", "4.18.0-348.el8.aarch64"],
+            "centos",
+        ),
+        (["debian", "11", "x86_64", "5.10.0-23-amd64"], "debian"),
+    ] {
+        assert_eq!(
+            lookup(&tar, identity.map(Some)),
+            Ok(Some(tagged(tag))),
+            "{identity:?}"
+        );
+    }
+    assert_eq!(
+        lookup(
+            &tar,
+            ["debian", "12", "x86_64", "5.10.0-23-amd64"].map(Some)
+        ),
+        Err(-libc::ENOENT)
+    );
+}
+
+#[test]
+fn null_fields_are_those_of_the_running_system() {
+    let host = SystemInfo::detect().unwrap();
+    let release = "5.4.0-9999-overridden";
+    let path = format!(
+        "{}/{}/{}/{}.btf",
+        host.distro_id, host.version_id, host.arch, release
+    );
+    let tar = archive_of(&[(path.as_str(), tagged("host"))]);
+    assert_eq!(
+        lookup(&tar, [None, None, None, Some(release)]),
+        Ok(Some(tagged("host")))
+    );
+    assert_eq!(
+        lookup(
+            &tar,
+            [Some(&host.distro_id), None, Some(&host.arch), Some(release)]
+        ),
+        Ok(Some(tagged("host")))
+    );
+    // 只替换发行版时，运行中系统的版本目录不再匹配
+    assert_eq!(
+        lookup(&tar, [Some("nosuchdistro"), None, None, Some(release)]),
+        Err(-libc::ENOENT)
+    );
+}
+
+#[test]
+fn native_btf_is_only_bypassed_for_another_kernel() {
+    if !std::path::Path::new("/sys/kernel/btf/vmlinux").exists() {
+        return;
+    }
+    let running = current_kernel_release().unwrap();
+    let tar = archive_of(&[
+        (
+            format!("ubuntu/20.04/x86_64/{running}.btf").as_str(),
+            tagged("running"),
+        ),
+        ("ubuntu/20.04/x86_64/5.4.0-40-generic.btf", tagged("other")),
+    ]);
+    // 运行中的内核自带 btf，无需从归档中提取
+    assert_eq!(
+        lookup(&tar, [Some("ubuntu"), Some("20.04"), Some("x86_64"), None]),
+        Ok(None)
+    );
+    assert_eq!(
+        lookup(
+            &tar,
+            [
+                Some("ubuntu"),
+                Some("20.04"),
+                Some("x86_64"),
+                Some(&running)
+            ]
+        ),
+        Ok(None)
+    );
+    assert_eq!(
+        lookup(
+            &tar,
+            ["ubuntu", "20.04", "x86_64", "5.4.0-40-generic"].map(Some)
+        ),
+        Ok(Some(tagged("other")))
+    );
+}
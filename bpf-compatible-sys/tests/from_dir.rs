@@ -0,0 +1,109 @@
+//! `ensure_core_btf_from_dir`, looking the btf up in an unpacked btfhub-archive
+//!
+//! The lookup is for the running system, faked with the `fake-system` feature; this is
+//! the only test of the binary, so the variables affect no other.
+mod common;
+
+use std::{ffi::CString, os::raw::c_char, path::Path, ptr};
+
+use bpf_compatible::ensure_core_btf_from_dir;
+use common::last_error;
+
+#[test]
+fn btfs_of_the_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let c_dir = CString::new(dir.path().to_str().unwrap()).unwrap();
+    let mut path = ptr::dangling::<c_char>();
+    assert_eq!(
+        ensure_core_btf_from_dir(ptr::null_mut(), c_dir.as_ptr()),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        ensure_core_btf_from_dir(&mut path, ptr::null()),
+        -libc::EINVAL
+    );
+    assert!(path.is_null());
+    assert!(last_error().contains("NULL"), "{}", last_error());
+
+    // 内核自带 btf 时不读取目录
+    if Path::new("/sys/kernel/btf/vmlinux").exists() {
+        let missing = CString::new("/nonexistent/btfhub-archive").unwrap();
+        assert_eq!(ensure_core_btf_from_dir(&mut path, missing.as_ptr()), 0);
+        assert!(path.is_null());
+    }
+
+    #[cfg(feature = "fake-system")]
+    faked::lookups(dir.path(), &c_dir);
+}
+
+#[cfg(feature = "fake-system")]
+mod faked {
+    use std::{fs, io::Write};
+
+    use bpf_compatible::{clean_core_btf_rs2, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED};
+    use bpf_compatible_rs::{
+        fake::{FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV},
+        fixture::{btf_of_arch, FixtureArchive},
+        reexport::{flate2::write::GzEncoder, Compression},
+    };
+
+    use super::*;
+    use crate::common::path_of;
+
+    fn ensure(dir: &CString, release: &str) -> Result<*const c_char, i32> {
+        std::env::set_var(FAKE_KERNEL_ENV, release);
+        let mut path: *const c_char = ptr::null();
+        match ensure_core_btf_from_dir(&mut path, dir.as_ptr()) {
+            0 => Ok(path),
+            err => Err(err),
+        }
+    }
+
+    pub fn lookups(dir: &Path, c_dir: &CString) {
+        std::env::set_var(FAKE_ARCH_ENV, "x86_64");
+        std::env::set_var(FAKE_DISTRO_ENV, "ubuntu");
+        std::env::set_var(FAKE_VERSION_ENV, "20.04");
+        let arch_dir = dir.join("ubuntu/20.04/x86_64");
+        fs::create_dir_all(&arch_dir).unwrap();
+        let plain = arch_dir.join("5.4.0-40-generic.btf");
+        fs::write(&plain, btf_of_arch(8, "plain")).unwrap();
+        let tarball = FixtureArchive::new()
+            .file("5.4.0-42-generic.btf", btf_of_arch(8, "packed"))
+            .tar();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&tarball).unwrap();
+        fs::write(
+            arch_dir.join("5.4.0-42-generic.btf.tar.gz"),
+            encoder.finish().unwrap(),
+        )
+        .unwrap();
+
+        // 未压缩的 btf 直接返回目录中的文件，清理时不删除
+        let path = ensure(c_dir, "5.4.0-40-generic").unwrap();
+        assert_eq!(path_of(path), plain);
+        assert_eq!(
+            clean_core_btf_rs2(path as *mut c_char),
+            BPF_COMPAT_PATH_FREED
+        );
+        assert_eq!(fs::read(&plain).unwrap(), btf_of_arch(8, "plain"));
+
+        // 压缩的 btf 解压到临时文件，清理时删除
+        let path = ensure(c_dir, "5.4.0-42-generic").unwrap();
+        let extracted = path_of(path);
+        assert!(!extracted.starts_with(dir));
+        assert_eq!(fs::read(&extracted).unwrap(), btf_of_arch(8, "packed"));
+        assert_eq!(
+            clean_core_btf_rs2(path as *mut c_char),
+            BPF_COMPAT_BTF_DELETED
+        );
+        assert!(!extracted.exists());
+        assert!(arch_dir.join("5.4.0-42-generic.btf.tar.gz").exists());
+
+        assert_eq!(ensure(c_dir, "5.4.0-99-generic"), Err(-libc::ENOENT));
+        assert!(
+            last_error().contains(&dir.display().to_string()),
+            "{}",
+            last_error()
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Collection of the btf temporary files left behind, and their removal at exit
+mod common;
+
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    mem::size_of,
+    os::{raw::c_char, unix::ffi::OsStrExt},
+    path::Path,
+    process::Command,
+    ptr,
+    time::{Duration, SystemTime},
+};
+
+use bpf_compatible::{
+    bpf_compatible_gc_stale_btf_tempfiles, bpf_compatible_gc_stale_btf_tempfiles_template,
+    bpf_compatible_register_cleanup_at_exit, clean_core_btf_rs2, gc::BpfCompatGcReport,
+    opts::BpfCompatOpts, BPF_COMPAT_BTF_DELETED,
+};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{ensure_opts, last_error, path_of, FakeRoot};
+use libc::{EINVAL, ENOENT};
+
+/// Set by the test running itself as a child process
+const CHILD_TMPDIR: &str = "BPF_COMPAT_GC_TEST_TMPDIR";
+
+fn report() -> BpfCompatGcReport {
+    BpfCompatGcReport {
+        sz: size_of::<BpfCompatGcReport>(),
+        removed: usize::MAX,
+        recent: usize::MAX,
+        failed: usize::MAX,
+        freed_bytes: u64::MAX,
+    }
+}
+
+fn c_path(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+/// Write `len` bytes to `dir/name`, last modified two hours ago
+fn stale(dir: &Path, name: &str, len: usize) {
+    let path = dir.join(name);
+    fs::write(&path, vec![0; len]).unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() - Duration::from_secs(7200))
+        .unwrap();
+}
+
+/// Extract the btf of `root` with `opts`, returning its path
+fn extract(root: &FakeRoot, opts: &BpfCompatOpts) -> *const c_char {
+    let tar = root.archive(btf_of_arch(8, "gc")).gz();
+    ensure_opts(&tar, opts).unwrap_or_else(|e| panic!("{e}: {}", last_error()))
+}
+
+#[test]
+fn stale_files_are_collected_but_not_those_held() {
+    let root = FakeRoot::new();
+    let held = extract(&root, &root.opts());
+    let tmp = path_of(held).parent().unwrap().to_path_buf();
+    let name = path_of(held).file_name().unwrap().to_owned();
+    // 本进程持有的文件即使已过期也不会被删除
+    stale(&tmp, name.to_str().unwrap(), 3);
+    stale(&tmp, "eunomia.btf.Crash0", 100);
+    stale(&tmp, "eunomia.btf.Crash1", 20);
+    stale(&tmp, "unrelated.btf", 20);
+
+    let dir = c_path(&tmp);
+    let mut out = report();
+    assert_eq!(
+        bpf_compatible_gc_stale_btf_tempfiles(dir.as_ptr(), 3600, &mut out),
+        0
+    );
+    assert_eq!(
+        (out.sz, out.removed, out.recent, out.failed, out.freed_bytes),
+        (size_of::<BpfCompatGcReport>(), 2, 1, 0, 120)
+    );
+    assert!(path_of(held).exists());
+    assert!(tmp.join("unrelated.btf").exists());
+
+    // 清理后不再属于本进程，report 可以为 NULL
+    assert_eq!(
+        clean_core_btf_rs2(held as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    stale(&tmp, name.to_str().unwrap(), 3);
+    assert_eq!(
+        bpf_compatible_gc_stale_btf_tempfiles(dir.as_ptr(), 3600, ptr::null_mut()),
+        0
+    );
+    assert!(!tmp.join(&name).exists());
+}
+
+#[test]
+fn templates_and_errors() {
+    let root = FakeRoot::new();
+    let tmp = root.path().join("tmp");
+    fs::create_dir(&tmp).unwrap();
+    stale(&tmp, "svc.Ab12Cd.btf", 8);
+    stale(&tmp, "eunomia.btf.Ab12Cd", 8);
+    let dir = c_path(&tmp);
+    let (prefix, suffix) = (CString::new("svc.").unwrap(), CString::new(".btf").unwrap());
+    let mut out = report();
+    assert_eq!(
+        bpf_compatible_gc_stale_btf_tempfiles_template(
+            dir.as_ptr(),
+            prefix.as_ptr(),
+            suffix.as_ptr(),
+            3600,
+            &mut out
+        ),
+        0
+    );
+    assert_eq!((out.removed, out.freed_bytes), (1, 8));
+    assert!(tmp.join("eunomia.btf.Ab12Cd").exists());
+
+    // 空前缀会匹配其他程序的文件
+    let empty = CString::new("").unwrap();
+    assert_eq!(
+        bpf_compatible_gc_stale_btf_tempfiles_template(
+            dir.as_ptr(),
+            empty.as_ptr(),
+            ptr::null(),
+            0,
+            ptr::null_mut()
+        ),
+        -EINVAL
+    );
+    // 过小的 sz 在删除任何文件前被拒绝
+    let mut small = BpfCompatGcReport { sz: 1, ..report() };
+    assert_eq!(
+        bpf_compatible_gc_stale_btf_tempfiles(dir.as_ptr(), 0, &mut small),
+        -EINVAL
+    );
+    assert!(tmp.join("eunomia.btf.Ab12Cd").exists());
+    assert_eq!(small.removed, usize::MAX);
+
+    let missing = c_path(&root.path().join("missing"));
+    assert_eq!(
+        bpf_compatible_gc_stale_btf_tempfiles(missing.as_ptr(), 0, &mut out),
+        -ENOENT
+    );
+    assert!(
+        last_error().contains("Failed to collect the stale btfs"),
+        "{}",
+        last_error()
+    );
+}
+
+#[test]
+fn btfs_not_cleaned_are_removed_at_exit() {
+    if let Some(tmpdir) = std::env::var_os(CHILD_TMPDIR) {
+        let root = FakeRoot::new();
+        let tmpdir = c_path(Path::new(&tmpdir));
+        let opts = BpfCompatOpts {
+            tmpdir: tmpdir.as_ptr(),
+            ..root.opts()
+        };
+        assert_eq!(bpf_compatible_register_cleanup_at_exit(), 0);
+        assert_eq!(bpf_compatible_register_cleanup_at_exit(), 0);
+        let kept = extract(&root, &opts);
+        assert!(path_of(kept).exists());
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let tmp = dir.path().join("tmp");
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "btfs_not_cleaned_are_removed_at_exit"])
+        .env(CHILD_TMPDIR, &tmp)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    // 子进程提取了 btf，退出时将其删除
+    assert!(tmp.exists());
+    assert_eq!(fs::read_dir(&tmp).unwrap().count(), 0);
+}
@@ -0,0 +1,57 @@
+//! Btfs installed on disk for the kernel, used before the archive
+mod common;
+
+use std::{fs, os::raw::c_char};
+
+use bpf_compatible::{clean_core_btf_rs2, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{ensure_opts, path_of, FakeRoot};
+
+fn install(root: &FakeRoot, location: &str, contents: &[u8]) {
+    let path = root
+        .path()
+        .join(location.replace("{release}", &root.info.kernel_release));
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+/// The path handed out for the btf of `root`, with what freeing it returned
+fn lookup(root: &FakeRoot, tar: &[u8]) -> (std::path::PathBuf, i32) {
+    let path = ensure_opts(tar, &root.opts()).unwrap();
+    let returned = path_of(path);
+    (returned, clean_core_btf_rs2(path as *mut c_char))
+}
+
+#[test]
+fn installed_btf_is_used_before_the_archive_and_kept() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    install(
+        &root,
+        "boot/vmlinux-{release}",
+        &btf_of_arch(8, "installed"),
+    );
+    let (path, freed) = lookup(&root, &tar);
+    assert_eq!(
+        path,
+        root.path()
+            .join(format!("boot/vmlinux-{}", root.info.kernel_release))
+    );
+    // 安装的 btf 不属于本库，只释放路径
+    assert_eq!(freed, BPF_COMPAT_PATH_FREED);
+    assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "installed"));
+}
+
+#[test]
+fn installed_file_that_isnt_a_btf_is_ignored() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    install(&root, "boot/vmlinux-{release}", b"\x7fELF");
+    let (path, freed) = lookup(&root, &tar);
+    assert!(
+        path.starts_with(root.path().join("tmp")),
+        "{}",
+        path.display()
+    );
+    assert_eq!(freed, BPF_COMPAT_BTF_DELETED);
+}
@@ -0,0 +1,110 @@
+//! Archives longer than `i32::MAX` bytes, passed with a `size_t` length
+//!
+//! The archive is a plain tar in an anonymous mapping: a filler entry declaring 3 GiB of
+//! zeros, which are never written so take no memory, then the btf of the system.
+#![cfg(all(target_os = "linux", target_pointer_width = "64"))]
+mod common;
+
+use std::{os::raw::c_char, ptr, slice};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, ensure_core_btf_for_system, ensure_core_btf_with_tar_binary,
+    BPF_COMPAT_BTF_DELETED,
+};
+use bpf_compatible_rs::{
+    fixture::{minimal_valid_btf, FixtureArchive},
+    reexport::tar::{EntryType, Header},
+};
+use libc::EINVAL;
+
+/// Size of the filler entry, so the btf starts past `i32::MAX`
+const FILLER_SIZE: u64 = 3 << 30;
+
+/// An anonymous mapping, unmapped on drop
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(len: usize) -> Self {
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+        Self {
+            addr: addr as *mut u8,
+            len,
+        }
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.addr, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, self.len) };
+    }
+}
+
+#[test]
+fn btf_past_2_gib_is_found() {
+    let mut filler = Header::new_ustar();
+    filler.set_path("btfhub-archive/filler").unwrap();
+    filler.set_size(FILLER_SIZE);
+    filler.set_mode(0o644);
+    filler.set_entry_type(EntryType::Regular);
+    filler.set_cksum();
+    let rest = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        )
+        .tar();
+    let rest_offset = 512 + FILLER_SIZE as usize;
+    let mut tar = Mapping::new(rest_offset + rest.len());
+    let bytes = tar.bytes();
+    bytes[..512].copy_from_slice(filler.as_bytes());
+    bytes[rest_offset..].copy_from_slice(&rest);
+    assert!(bytes.len() > i32::MAX as usize);
+
+    // 长度按 size_t 传入，不会被截断为 int
+    let [distro, version, arch, release] = [c"ubuntu", c"20.04", c"x86_64", c"5.4.0-40-generic"];
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_for_system(
+        &mut path,
+        bytes.as_ptr(),
+        bytes.len(),
+        distro.as_ptr(),
+        version.as_ptr(),
+        arch.as_ptr(),
+        release.as_ptr(),
+    );
+    assert_eq!(err, 0, "{}", common::last_error());
+    let extracted = common::path_of(path);
+    assert_eq!(std::fs::read(&extracted).unwrap(), minimal_valid_btf());
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+
+    // int 长度的入口无法表示这样的长度，截断后的负数被拒绝而不是当作长度使用
+    let truncated = bytes.len() as i32;
+    assert!(truncated < 0);
+    assert_eq!(
+        ensure_core_btf_with_tar_binary(&mut path, bytes.as_ptr(), truncated),
+        -EINVAL
+    );
+}
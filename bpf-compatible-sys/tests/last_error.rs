@@ -0,0 +1,80 @@
+//! `bpf_compatible_last_error`, the message of the last failed call of the thread
+mod common;
+
+use std::{ffi::CString, os::raw::c_char, ptr, thread};
+
+use bpf_compatible::{
+    bpf_compatible_last_error, ensure_core_btf_with_tar_binary, ensure_core_btf_with_tar_file,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{last_error, lookup, lookup_release};
+
+fn archive() -> Vec<u8> {
+    FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .gz()
+}
+
+#[test]
+fn failures_name_what_they_failed_on() {
+    let tar = archive();
+    // 归档中没有的内核
+    assert_eq!(lookup_release(&tar, "5.4.0-99-generic"), Err(-libc::ENOENT));
+    assert!(
+        last_error().contains("5.4.0-99-generic"),
+        "{}",
+        last_error()
+    );
+    // 不存在的归档文件
+    let missing = CString::new("/nonexistent/min_core_btfs.tar.gz").unwrap();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_file(&mut path, missing.as_ptr()),
+        -libc::ENOENT
+    );
+    assert!(
+        last_error().contains("/nonexistent/min_core_btfs.tar.gz"),
+        "{}",
+        last_error()
+    );
+    // 参数检查同样留下消息
+    assert_eq!(
+        ensure_core_btf_with_tar_binary(ptr::null_mut(), tar.as_ptr(), tar.len() as _),
+        -libc::EINVAL
+    );
+    assert!(last_error().contains("output pointer"), "{}", last_error());
+    // 截断的归档
+    assert!(lookup(&tar[..tar.len() / 2]).is_err());
+    assert!(!last_error().is_empty());
+}
+
+#[test]
+fn success_clears_the_message() {
+    let tar = archive();
+    assert!(lookup_release(&tar, "5.4.0-99-generic").is_err());
+    assert!(!bpf_compatible_last_error().is_null());
+    // 同一消息在下一次调用之前保持不变
+    assert_eq!(bpf_compatible_last_error(), bpf_compatible_last_error());
+    assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "rip")));
+    assert!(bpf_compatible_last_error().is_null());
+}
+
+#[test]
+fn messages_are_per_thread() {
+    let tar = archive();
+    assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "rip")));
+    let other = thread::spawn(move || {
+        assert!(lookup_release(&tar, "5.4.0-99-generic").is_err());
+        last_error()
+    })
+    .join()
+    .unwrap();
+    assert!(other.contains("5.4.0-99-generic"), "{other}");
+    assert!(bpf_compatible_last_error().is_null());
+}
@@ -0,0 +1,68 @@
+//! The linked archive entry points of a program whose archive symbols delimit no archive
+//!
+//! As in `linked_tar.rs`, the symbols are defined here, around bytes in none of the
+//! formats of `ArchiveFormat::detect`. The check is done on each call, and the log
+//! callback is that of the whole process, so this is the only test of the binary.
+mod common;
+
+use std::{
+    ffi::{c_void, CStr},
+    os::raw::{c_char, c_int},
+    ptr,
+    sync::Mutex,
+};
+
+use bpf_compatible::{
+    bpf_compatible_set_log_fn, ensure_core_btf_with_linked_tar_opts, linked_archive_bytes,
+    BPF_COMPAT_LOG_INFO,
+};
+use common::{last_error, FakeRoot};
+
+std::arch::global_asm!(
+    ".pushsection .data.min_core_btfs_tar_gz, \"aw\"",
+    ".globl _binary_min_core_btfs_tar_gz_start",
+    ".globl _binary_min_core_btfs_tar_gz_end",
+    "_binary_min_core_btfs_tar_gz_start:",
+    ".fill 4096, 1, 0x5a",
+    "_binary_min_core_btfs_tar_gz_end:",
+    ".popsection",
+);
+
+type Messages = Mutex<Vec<(c_int, String)>>;
+
+unsafe extern "C" fn capture(level: c_int, msg: *const c_char, ctx: *mut c_void) {
+    let messages = &*(ctx as *const Messages);
+    let msg = CStr::from_ptr(msg).to_string_lossy().into_owned();
+    messages.lock().unwrap().push((level, msg));
+}
+
+#[test]
+fn bytes_of_no_archive_are_ignored() {
+    let messages = Messages::default();
+    bpf_compatible_set_log_fn(Some(capture), &messages as *const _ as *mut c_void);
+    assert!(linked_archive_bytes().is_none());
+    bpf_compatible_set_log_fn(None, ptr::null_mut());
+    let messages = messages.into_inner().unwrap();
+    assert!(
+        messages
+            .iter()
+            .any(|(level, msg)| *level == BPF_COMPAT_LOG_INFO
+                && msg.starts_with("Ignored the linked btf archive")
+                && msg.contains("5a 5a 5a")),
+        "{messages:?}"
+    );
+
+    // C 程序的查找与未链接归档时相同
+    let root = FakeRoot::new();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_linked_tar_opts(&mut path, &root.opts()),
+        -libc::ENOENT
+    );
+    assert!(path.is_null());
+    assert!(
+        last_error().contains("No btf archive is linked"),
+        "{}",
+        last_error()
+    );
+}
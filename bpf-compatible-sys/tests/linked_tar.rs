@@ -0,0 +1,65 @@
+//! The linked archive entry points of a program linking the archive
+//!
+//! The symbols `ld -r -b binary` defines for `min_core_btfs.tar.gz` are defined here
+//! around a zeroed buffer, which the test fills with the archive before any lookup; the
+//! gzip decoder skips the zeros after it. The archive is that of the whole process, so
+//! this is the only test of the binary.
+mod common;
+
+use std::{os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    ensure_core_btf_with_linked_tar_opts, linked_archive_bytes, BPF_COMPAT_BTF_DELETED,
+};
+use bpf_compatible_rs::{fixture::btf_of_arch, TarballBtfArchive};
+use common::{last_error, take_btf, FakeRoot};
+
+/// Size of the buffer between the symbols
+const LINKED_SIZE: usize = 64 << 10;
+
+std::arch::global_asm!(
+    ".pushsection .bss.min_core_btfs_tar_gz, \"aw\", @nobits",
+    ".globl _binary_min_core_btfs_tar_gz_start",
+    ".globl _binary_min_core_btfs_tar_gz_end",
+    "_binary_min_core_btfs_tar_gz_start:",
+    ".zero {size}",
+    "_binary_min_core_btfs_tar_gz_end:",
+    ".popsection",
+    size = const LINKED_SIZE,
+);
+
+extern "C" {
+    static mut _binary_min_core_btfs_tar_gz_start: [u8; LINKED_SIZE];
+}
+
+#[test]
+fn linked_archive_is_looked_up() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "linked")).gz();
+    assert!(tar.len() < LINKED_SIZE);
+    // 此前没有任何查找，写入后的内容就是链接的归档
+    unsafe {
+        ptr::copy_nonoverlapping(
+            tar.as_ptr(),
+            ptr::addr_of_mut!(_binary_min_core_btfs_tar_gz_start).cast(),
+            tar.len(),
+        )
+    };
+    assert_eq!(linked_archive_bytes().unwrap().len(), LINKED_SIZE);
+    // Rust 程序可以把链接的归档交给 bpf_compatible_rs
+    let archive = TarballBtfArchive::from_gzipped_bytes(linked_archive_bytes().unwrap()).unwrap();
+    let entry = archive.lookup(&root.info).unwrap();
+    assert_eq!(archive.extract(&entry).unwrap(), btf_of_arch(8, "linked"));
+
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_linked_tar_opts(&mut path, &root.opts()),
+        0,
+        "{}",
+        last_error()
+    );
+    assert_eq!(
+        take_btf(path, BPF_COMPAT_BTF_DELETED),
+        btf_of_arch(8, "linked")
+    );
+}
@@ -0,0 +1,91 @@
+//! Btf entries that are hardlinks or symlinks to another entry of the archive, as
+//! archives deduplicating identical btfs across kernel flavors have them
+mod common;
+
+use bpf_compatible_rs::fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive};
+use common::{last_error, lookup};
+
+const DIR: &str = "btfhub-archive/ubuntu/20.04/x86_64";
+
+fn at(file: &str) -> String {
+    format!("{}/{}", DIR, file)
+}
+
+#[test]
+fn hardlink_to_an_earlier_entry_is_followed() {
+    let tar = FixtureArchive::new()
+        .file(&at("5.4.0-42-generic.btf"), btf_of_arch(8, "r15"))
+        .hardlink(&at("5.4.0-40-generic.btf"), &at("5.4.0-42-generic.btf"))
+        .gz();
+    assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "r15")));
+}
+
+#[test]
+fn hardlink_to_a_later_entry_is_followed() {
+    // 目标位于链接之后时需要再次读取归档
+    let tar = FixtureArchive::new()
+        .hardlink(&at("5.4.0-40-generic.btf"), &at("5.4.0-42-lowlatency.btf"))
+        .file(&at("5.4.0-42-lowlatency.btf"), minimal_valid_btf())
+        .gz();
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+}
+
+#[test]
+fn relative_symlink_is_resolved_from_its_directory() {
+    let tar = FixtureArchive::new()
+        .file(
+            "btfhub-archive/ubuntu/shared/5.4.0-40.btf",
+            btf_of_arch(8, "r15"),
+        )
+        .symlink(&at("5.4.0-40-generic.btf"), "../../shared/5.4.0-40.btf")
+        .gz();
+    assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "r15")));
+}
+
+#[test]
+fn absolute_symlink_is_resolved_from_the_root_of_the_archive() {
+    let tar = FixtureArchive::new()
+        .file("btfhub-archive/shared/a.btf", minimal_valid_btf())
+        .symlink(&at("5.4.0-40-generic.btf"), "/btfhub-archive/shared/a.btf")
+        .gz();
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+}
+
+#[test]
+fn chain_of_links_is_followed() {
+    let tar = FixtureArchive::new()
+        .symlink(&at("5.4.0-40-generic.btf"), "5.4.0-41-generic.btf")
+        .hardlink(&at("5.4.0-41-generic.btf"), &at("5.4.0-42-generic.btf"))
+        .file(&at("5.4.0-42-generic.btf"), btf_of_arch(8, "r15"))
+        .gz();
+    assert_eq!(lookup(&tar), Ok(btf_of_arch(8, "r15")));
+}
+
+#[test]
+fn dangling_link_fails_with_enoent_naming_the_target() {
+    for tar in [
+        FixtureArchive::new()
+            .hardlink(&at("5.4.0-40-generic.btf"), &at("5.4.0-43-generic.btf"))
+            .gz(),
+        FixtureArchive::new()
+            .file(&at("5.4.0-44-generic.btf"), minimal_valid_btf())
+            .symlink(&at("5.4.0-40-generic.btf"), "5.4.0-43-generic.btf")
+            .gz(),
+    ] {
+        assert_eq!(lookup(&tar), Err(-libc::ENOENT));
+        assert!(
+            last_error().contains(&at("5.4.0-43-generic.btf")),
+            "{}",
+            last_error()
+        );
+    }
+}
+
+#[test]
+fn link_loop_fails_with_eloop() {
+    let tar = FixtureArchive::new()
+        .symlink(&at("5.4.0-40-generic.btf"), "5.4.0-41-generic.btf")
+        .symlink(&at("5.4.0-41-generic.btf"), "5.4.0-40-generic.btf")
+        .gz();
+    assert_eq!(lookup(&tar), Err(-libc::ELOOP));
+}
@@ -0,0 +1,130 @@
+//! Listing the kernels an archive has a btf for
+mod common;
+
+use std::{ffi::CStr, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    free_core_btf_kernel_list, list_core_btf_kernels, list_core_btf_kernels_linked_tar,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::last_error;
+
+/// List the kernels of `tar`, checking the array is NULL-terminated after `count` strings
+fn list(tar: &[u8]) -> Result<Vec<String>, i32> {
+    let mut entries: *mut *mut c_char = ptr::null_mut();
+    let mut count = usize::MAX;
+    let err = list_core_btf_kernels(tar.as_ptr(), tar.len(), &mut entries, &mut count);
+    if err != 0 {
+        assert!(entries.is_null());
+        assert_eq!(count, 0);
+        return Err(err);
+    }
+    let kernels = (0..count)
+        .map(|i| {
+            let entry = unsafe { *entries.add(i) };
+            unsafe { CStr::from_ptr(entry) }
+                .to_str()
+                .unwrap()
+                .to_owned()
+        })
+        .collect();
+    assert!(unsafe { *entries.add(count) }.is_null());
+    free_core_btf_kernel_list(entries);
+    Ok(kernels)
+}
+
+#[test]
+fn only_btf_entries_are_listed_in_archive_order() {
+    let btf = btf_of_arch(8, "rip");
+    let fixture = FixtureArchive::new()
+        .file("SHA256SUMS", b"".to_vec())
+        .dir("btfhub-archive/ubuntu/20.04/x86_64")
+        .file(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            btf.clone(),
+        )
+        .file("btfhub-archive/ubuntu/20.04/x86_64/README", b"".to_vec())
+        .file(
+            "./btfhub-archive/debian/11/arm64/5.10.0-23-arm64.btf.tar.xz",
+            b"".to_vec(),
+        )
+        .symlink(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf",
+            "5.4.0-40-generic.btf",
+        )
+        .hardlink(
+            "btfhub-archive/centos/8/x86_64/4.18.0-348.el8.x86_64.btf.gz",
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+        )
+        // 不在 btfhub-archive 下或层级不对的 btf 不算
+        .file(
+            "other/ubuntu/20.04/x86_64/5.4.0-99-generic.btf",
+            btf.clone(),
+        )
+        .file("btfhub-archive/ubuntu/5.4.0-99-generic.btf", btf.clone())
+        // 重复的内核只列出一次
+        .file(
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            btf,
+        );
+    let expected = [
+        "ubuntu/20.04/x86_64/5.4.0-40-generic",
+        "debian/11/arm64/5.10.0-23-arm64",
+        "ubuntu/20.04/x86_64/5.4.0-42-generic",
+        "centos/8/x86_64/4.18.0-348.el8.x86_64",
+    ];
+    assert_eq!(
+        list(&fixture.tar()),
+        Ok(expected.map(String::from).to_vec())
+    );
+    assert_eq!(list(&fixture.gz()), Ok(expected.map(String::from).to_vec()));
+}
+
+#[test]
+fn archive_without_btfs_lists_nothing() {
+    let tar = FixtureArchive::new()
+        .dir("btfhub-archive")
+        .file("README.md", b"btfs".to_vec())
+        .gz();
+    assert_eq!(list(&tar), Ok(vec![]));
+}
+
+#[test]
+fn invalid_arguments_and_archives_are_errors() {
+    let tar = FixtureArchive::new().gz();
+    let mut entries: *mut *mut c_char = ptr::null_mut();
+    let mut count = 0;
+    assert_eq!(
+        list_core_btf_kernels(tar.as_ptr(), tar.len(), ptr::null_mut(), &mut count),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        list_core_btf_kernels(tar.as_ptr(), tar.len(), &mut entries, ptr::null_mut()),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        list_core_btf_kernels(ptr::null(), 1, &mut entries, &mut count),
+        -libc::EINVAL
+    );
+    // 截断的 gzip 无法读取
+    assert!(list(&tar[..tar.len() / 2]).unwrap_err() < 0);
+    assert!(!last_error().is_empty());
+    // 释放 NULL 什么也不做
+    free_core_btf_kernel_list(ptr::null_mut());
+}
+
+#[test]
+fn linked_list_needs_a_linked_archive() {
+    let mut entries: *mut *mut c_char = ptr::null_mut();
+    let mut count = 0;
+    assert_eq!(
+        list_core_btf_kernels_linked_tar(&mut entries, ptr::null_mut()),
+        -libc::EINVAL
+    );
+    // 测试程序没有链接 min_core_btfs_tar.o
+    assert_eq!(
+        list_core_btf_kernels_linked_tar(&mut entries, &mut count),
+        -libc::EINVAL
+    );
+    assert!(last_error().contains("linked"), "{}", last_error());
+}
@@ -0,0 +1,226 @@
+//! `bpf_compatible_set_log_fn`, routing the diagnostics to a callback
+mod common;
+
+use std::{
+    ffi::{c_void, CStr},
+    os::raw::{c_char, c_int},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use bpf_compatible::{
+    bpf_compatible_set_log_fn, BPF_COMPAT_LOG_DEBUG, BPF_COMPAT_LOG_ERROR, BPF_COMPAT_LOG_INFO,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{lookup, lookup_release};
+
+// 回调是全局的，本文件的测试逐个运行
+static SERIAL: Mutex<()> = Mutex::new(());
+
+type Messages = Mutex<Vec<(c_int, String)>>;
+
+unsafe extern "C" fn capture(level: c_int, msg: *const c_char, ctx: *mut c_void) {
+    let messages = &*(ctx as *const Messages);
+    let msg = CStr::from_ptr(msg).to_string_lossy().into_owned();
+    messages.lock().unwrap().push((level, msg));
+}
+
+/// Run `f` with [`capture`] set, returning what it captured
+fn captured(f: impl FnOnce()) -> Vec<(c_int, String)> {
+    let messages = Messages::default();
+    bpf_compatible_set_log_fn(Some(capture), &messages as *const _ as *mut c_void);
+    f();
+    bpf_compatible_set_log_fn(None, ptr::null_mut());
+    messages.into_inner().unwrap()
+}
+
+fn archive() -> Vec<u8> {
+    FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .gz()
+}
+
+#[test]
+fn failing_lookup_is_reported_as_an_error() {
+    let _serial = SERIAL.lock().unwrap();
+    let tar = archive();
+    let messages = captured(|| {
+        assert_eq!(lookup_release(&tar, "5.4.0-99-generic"), Err(-libc::ENOENT));
+    });
+    let errors = messages
+        .iter()
+        .filter(|(level, _)| *level == BPF_COMPAT_LOG_ERROR)
+        .map(|(_, msg)| msg)
+        .collect::<Vec<_>>();
+    assert!(
+        errors.iter().any(|v| v.contains("5.4.0-99-generic")),
+        "{messages:?}"
+    );
+    // 查找过程的细节以 debug 级别给出
+    assert!(
+        messages
+            .iter()
+            .any(|(level, msg)| *level == BPF_COMPAT_LOG_DEBUG && msg.starts_with("Looking for")),
+        "{messages:?}"
+    );
+    for (level, _) in &messages {
+        assert!(
+            [
+                BPF_COMPAT_LOG_ERROR,
+                BPF_COMPAT_LOG_INFO,
+                BPF_COMPAT_LOG_DEBUG
+            ]
+            .contains(level),
+            "{messages:?}"
+        );
+    }
+}
+
+#[test]
+fn successful_lookup_names_the_matched_entry() {
+    let _serial = SERIAL.lock().unwrap();
+    let tar = archive();
+    let messages = captured(|| {
+        assert_eq!(lookup(&tar).unwrap(), btf_of_arch(8, "rip"));
+    });
+    assert!(
+        messages
+            .iter()
+            .any(|(level, msg)| *level == BPF_COMPAT_LOG_DEBUG
+                && msg.contains("ubuntu/20.04/x86_64/5.4.0-40-generic.btf")),
+        "{messages:?}"
+    );
+    assert!(
+        messages
+            .iter()
+            .all(|(level, _)| *level != BPF_COMPAT_LOG_ERROR),
+        "{messages:?}"
+    );
+}
+
+#[test]
+fn null_restores_the_default() {
+    let _serial = SERIAL.lock().unwrap();
+    let tar = archive();
+    let messages = Messages::default();
+    bpf_compatible_set_log_fn(Some(capture), &messages as *const _ as *mut c_void);
+    assert!(lookup_release(&tar, "5.4.0-98-generic").is_err());
+    let before = messages.lock().unwrap().len();
+    assert!(before > 0);
+    bpf_compatible_set_log_fn(None, ptr::null_mut());
+    assert!(lookup_release(&tar, "5.4.0-97-generic").is_err());
+    assert_eq!(messages.lock().unwrap().len(), before);
+}
+
+#[test]
+fn replacing_the_callback_switches_the_context() {
+    let _serial = SERIAL.lock().unwrap();
+    let tar = archive();
+    let first = Messages::default();
+    let second = Messages::default();
+    bpf_compatible_set_log_fn(Some(capture), &first as *const _ as *mut c_void);
+    assert!(lookup_release(&tar, "5.4.0-96-generic").is_err());
+    bpf_compatible_set_log_fn(Some(capture), &second as *const _ as *mut c_void);
+    assert!(lookup_release(&tar, "5.4.0-95-generic").is_err());
+    bpf_compatible_set_log_fn(None, ptr::null_mut());
+    let joined = |v: &Messages| {
+        v.lock()
+            .unwrap()
+            .iter()
+            .map(|(_, m)| m.clone())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    assert!(joined(&first).contains("5.4.0-96-generic"));
+    assert!(!joined(&first).contains("5.4.0-95-generic"));
+    assert!(joined(&second).contains("5.4.0-95-generic"));
+    assert!(!joined(&second).contains("5.4.0-96-generic"));
+}
+
+/// Calls made with a context its caller already took back
+static LATE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts in [`LATE_CALLS`] the calls after the context was flagged released, lingering
+/// in the callback so replacements race with it
+unsafe extern "C" fn check_released(_: c_int, _: *const c_char, ctx: *mut c_void) {
+    let released = &*(ctx as *const AtomicBool);
+    thread::sleep(Duration::from_micros(50));
+    if released.load(Ordering::SeqCst) {
+        LATE_CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn replaced_callback_is_no_longer_running() {
+    let _serial = SERIAL.lock().unwrap();
+    let tar = archive();
+    let done = AtomicBool::new(false);
+    // 上下文在替换后标记为已释放，不再被任何线程的回调使用
+    let contexts = (0..200).map(|_| AtomicBool::new(false)).collect::<Vec<_>>();
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                while !done.load(Ordering::SeqCst) {
+                    assert!(lookup_release(&tar, "5.4.0-94-generic").is_err());
+                }
+            });
+        }
+        for context in &contexts {
+            bpf_compatible_set_log_fn(Some(check_released), context as *const _ as *mut c_void);
+            thread::sleep(Duration::from_micros(200));
+            bpf_compatible_set_log_fn(None, ptr::null_mut());
+            context.store(true, Ordering::SeqCst);
+        }
+        done.store(true, Ordering::SeqCst);
+    });
+    assert_eq!(LATE_CALLS.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn btfs_of_another_release_are_noted() {
+    let _serial = SERIAL.lock().unwrap();
+    // 20.04 上的 HWE 内核，btf 位于其系列所属的 22.04 目录下
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "22.04",
+            "x86_64",
+            "5.15.0-76-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .gz();
+    let messages = captured(|| {
+        assert_eq!(
+            lookup_release(&tar, "5.15.0-76-generic").unwrap(),
+            btf_of_arch(8, "rip")
+        );
+    });
+    assert!(
+        messages
+            .iter()
+            .any(|(level, msg)| *level == BPF_COMPAT_LOG_INFO
+                && msg.contains("HWE")
+                && msg.contains("ubuntu/22.04/x86_64/5.15.0-76-generic.btf")),
+        "{messages:?}"
+    );
+    // 在自己的目录下找到时没有提示
+    let tar = archive();
+    let messages = captured(|| {
+        lookup(&tar).unwrap();
+    });
+    assert!(
+        messages.iter().all(|(_, msg)| !msg.contains("HWE")),
+        "{messages:?}"
+    );
+}
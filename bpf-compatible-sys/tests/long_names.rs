@@ -0,0 +1,122 @@
+//! Entries whose path doesn't fit in a tar header, written with a GNU long name or a PAX
+//! extended header
+mod common;
+
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+    reexport::{
+        flate2::{write::GzEncoder, Compression},
+        tar::{Builder, EntryType, Header},
+    },
+};
+use common::lookup_release;
+
+/// A kernel release long enough for the path of its btf to exceed the 100 bytes of a header
+fn long_release(flavor: &str) -> String {
+    format!(
+        "4.18.0-348.7.1.el8_5.x86_64+debug-{}-{}",
+        flavor,
+        "x".repeat(60)
+    )
+}
+
+fn entry_path(release: &str) -> String {
+    format!("./btfhub-archive/ubuntu/20.04/x86_64/{}.btf", release)
+}
+
+fn header(entry_type: EntryType, size: usize) -> Header {
+    let mut header = Header::new_ustar();
+    header.set_entry_type(entry_type);
+    header.set_size(size as u64);
+    header.set_mode(0o644);
+    header
+}
+
+/// A PAX record `<len> <key>=<value>\n`, whose length counts its own digits
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let rest = format!(" {}={}\n", key, value);
+    let mut len = rest.len() + 1;
+    while (len.to_string().len() + rest.len()) != len {
+        len += 1;
+    }
+    format!("{}{}", len, rest).into_bytes()
+}
+
+/// A gzipped tar of `entries`, each led by a PAX header giving its full path
+///
+/// The name in the header of the entry itself is cut at 100 bytes, as tar does.
+fn pax_archive(global: Option<&[u8]>, entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut builder = Builder::new(GzEncoder::new(vec![], Compression::fast()));
+    if let Some(records) = global {
+        // git archive 写在最前面的全局扩展头
+        let mut global = header(EntryType::XGlobalHeader, records.len());
+        global.set_path("pax_global_header").unwrap();
+        global.set_cksum();
+        builder.append(&global, records).unwrap();
+    }
+    for (path, contents) in entries {
+        let records = pax_record("path", path);
+        let mut pax = header(EntryType::XHeader, records.len());
+        pax.set_path("PaxHeaders.0/btf").unwrap();
+        pax.set_cksum();
+        builder.append(&pax, &records[..]).unwrap();
+        let mut entry = header(EntryType::Regular, contents.len());
+        let truncated = &path.as_bytes()[..path.len().min(100)];
+        entry.as_old_mut().name[..truncated.len()].copy_from_slice(truncated);
+        entry.set_cksum();
+        builder.append(&entry, &contents[..]).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+#[test]
+fn btf_with_a_gnu_long_name_is_found() {
+    let release = long_release("gnu");
+    assert!(entry_path(&release).len() > 100);
+    let tar = FixtureArchive::new()
+        .file(&entry_path("5.4.0-40-generic"), minimal_valid_btf())
+        .longname_entry(&entry_path(&release), btf_of_arch(8, "r15"))
+        .gz();
+    assert_eq!(lookup_release(&tar, &release), Ok(btf_of_arch(8, "r15")));
+}
+
+#[test]
+fn btf_with_a_pax_path_is_found() {
+    let release = long_release("pax");
+    let tar = pax_archive(None, &[(&entry_path(&release), btf_of_arch(8, "r15"))]);
+    assert_eq!(lookup_release(&tar, &release), Ok(btf_of_arch(8, "r15")));
+}
+
+#[test]
+fn truncated_name_in_the_header_isnt_matched() {
+    // 头部中被截断的名字恰好是另一个内核的 btf 路径，但只按完整路径匹配
+    let prefix = entry_path("").len() - ".btf".len();
+    let release = format!("5.4.0-40-{}", "x".repeat(100 - prefix - 13));
+    assert_eq!(entry_path(&release).len(), 100);
+    let tar = pax_archive(
+        None,
+        &[(
+            &format!("{}.orig", entry_path(&release)),
+            minimal_valid_btf(),
+        )],
+    );
+    assert_eq!(lookup_release(&tar, &release), Err(-libc::ENOENT));
+}
+
+#[test]
+fn metadata_entries_are_skipped() {
+    let release = long_release("global");
+    let records = pax_record("comment", "0123456789abcdef");
+    let tar = pax_archive(
+        Some(&records),
+        &[
+            (&entry_path("5.4.0-40-generic"), minimal_valid_btf()),
+            (&entry_path(&release), btf_of_arch(8, "r15")),
+        ],
+    );
+    assert_eq!(lookup_release(&tar, &release), Ok(btf_of_arch(8, "r15")));
+    assert_eq!(
+        lookup_release(&tar, "5.4.0-40-generic"),
+        Ok(minimal_valid_btf())
+    );
+}
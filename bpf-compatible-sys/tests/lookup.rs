@@ -0,0 +1,135 @@
+//! Lookups through the C API in archives built in memory
+use std::fs;
+
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+    index::prepend_index,
+};
+use common::{ensure_opts, last_error, lookup, FakeRoot};
+
+mod common;
+
+fn archive_of(release: &str) -> FixtureArchive {
+    FixtureArchive::new().btf("ubuntu", "20.04", "x86_64", release, minimal_valid_btf())
+}
+
+// libc 只在 Linux 上定义 EMEDIUMTYPE
+#[cfg(target_os = "linux")]
+#[test]
+fn tarball_without_btfs_is_not_a_btf_archive() {
+    let tar = FixtureArchive::new()
+        .file("docs/README.md", b"# not btfs\n".to_vec())
+        .file("src/main.c", b"int main() {}\n".to_vec())
+        .gz();
+    assert_eq!(lookup(&tar), Err(-libc::EMEDIUMTYPE));
+}
+
+#[test]
+fn btfhub_archive_without_the_kernel_is_a_miss() {
+    assert_eq!(
+        lookup(&archive_of("5.4.0-99-generic").gz()),
+        Err(-libc::ENOENT)
+    );
+}
+
+#[test]
+fn repeated_miss_is_answered_from_the_negative_cache() {
+    let tar = archive_of("5.4.0-98-generic").gz();
+    assert_eq!(lookup(&tar), Err(-libc::ENOENT));
+    assert!(!last_error().contains("(cached)"), "{}", last_error());
+    assert_eq!(lookup(&tar), Err(-libc::ENOENT));
+    assert!(last_error().contains("(cached)"), "{}", last_error());
+    // 归档变了，之前的结果不再适用
+    let tar = archive_of("5.4.0-98-generic")
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        )
+        .gz();
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+}
+
+#[test]
+fn codename_layout_is_found_and_the_version_one_preferred() {
+    let by_codename = btf_of_arch(8, "r15");
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "focal",
+            "x86_64",
+            "5.4.0-40-generic",
+            by_codename.clone(),
+        )
+        .gz();
+    assert_eq!(lookup(&tar), Ok(by_codename.clone()));
+    let tar = FixtureArchive::new()
+        .btf("ubuntu", "focal", "x86_64", "5.4.0-40-generic", by_codename)
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        )
+        .gz();
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+}
+
+#[test]
+fn indexed_archive_finds_the_btf() {
+    let tar = prepend_index(&archive_of("5.4.0-40-generic").tar()).unwrap();
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+}
+
+#[test]
+fn dotted_paths_and_link_targets_are_resolved() {
+    let tar = FixtureArchive::new()
+        .file(
+            "./btfhub-archive/ubuntu/bionic/../20.04/x86_64/5.4.0-39-generic.btf",
+            minimal_valid_btf(),
+        )
+        .symlink(
+            "/btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            "../../20.04/./x86_64/5.4.0-39-generic.btf",
+        )
+        .gz();
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+}
+
+#[test]
+fn plain_tar_is_looked_up_like_a_gzipped_one() {
+    let archive = archive_of("5.4.0-40-generic");
+    assert_eq!(lookup(&archive.tar()), Ok(minimal_valid_btf()));
+}
+
+#[test]
+fn unknown_format_is_reported_with_its_magic() {
+    let mut zip = b"PK\x03\x04".to_vec();
+    zip.resize(64, 0xaa);
+    assert_eq!(lookup(&zip), Err(-libc::EINVAL));
+    assert!(
+        last_error().contains("50 4b 03 04 aa aa"),
+        "{}",
+        last_error()
+    );
+}
+
+#[test]
+fn corrupt_btf_fails_with_eilseq_and_leaves_no_file() {
+    let root = FakeRoot::new();
+    let btf = minimal_valid_btf();
+    for corrupt in [btf[..btf.len() - 1].to_vec(), b"\0\0garbage".to_vec()] {
+        let tar = root.archive(corrupt).gz();
+        let opts = root.opts();
+        assert_eq!(ensure_opts(&tar, &opts), Err(-libc::EILSEQ));
+        let message = last_error();
+        assert!(message.contains(&root.info.to_string()), "{message}");
+        // 校验在写出之前进行，临时目录中没有留下文件
+        assert!(fs::read_dir(root.path().join("tmp"))
+            .map(|v| v.count() == 0)
+            .unwrap_or(true));
+    }
+}
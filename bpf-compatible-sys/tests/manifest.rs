@@ -0,0 +1,86 @@
+//! Btfs checked against a `SHA256SUMS` manifest of the archive
+//!
+//! Only built on Linux, whose libc is the one naming the `ENOKEY` of unverifiable btfs.
+#![cfg(target_os = "linux")]
+mod common;
+
+use std::fs;
+
+use bpf_compatible::opts::BpfCompatOpts;
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    manifest::{build_manifest, prepend_manifest, MANIFEST_ENTRY_NAME},
+};
+use common::{last_error, lookup_opts, FakeRoot};
+
+/// Look up the btf of a new root in `tar`, requiring verification if `strict`
+fn lookup(make_tar: impl Fn(&FakeRoot) -> Vec<u8>, strict: bool) -> Result<Vec<u8>, i32> {
+    let root = FakeRoot::new();
+    let tar = make_tar(&root);
+    let opts = BpfCompatOpts {
+        require_verification: strict,
+        ..root.opts()
+    };
+    let result = lookup_opts(&tar, &opts);
+    if result.is_err() {
+        // 校验失败时没有写出任何文件
+        assert!(fs::read_dir(root.path().join("tmp"))
+            .map(|v| v.count() == 0)
+            .unwrap_or(true));
+    }
+    result
+}
+
+fn packaged(root: &FakeRoot) -> Vec<u8> {
+    root.archive(btf_of_arch(8, "packaged")).tar()
+}
+
+/// An archive of the root with `manifest` as its first entry, whatever `btf` is
+fn manifest_first(root: &FakeRoot, manifest: &[u8], btf: Vec<u8>) -> Vec<u8> {
+    FixtureArchive::new()
+        .file(MANIFEST_ENTRY_NAME, manifest.to_vec())
+        .file(&format!("btfhub-archive/{}", root.info), btf)
+        .tar()
+}
+
+#[test]
+fn matching_digest_is_accepted() {
+    for strict in [false, true] {
+        assert_eq!(
+            lookup(|root| prepend_manifest(&packaged(root)).unwrap(), strict),
+            Ok(btf_of_arch(8, "packaged"))
+        );
+    }
+}
+
+#[test]
+fn mismatching_digest_fails_with_ebadmsg() {
+    // 清单描述的是打包时的 btf，条目却被替换了
+    let tampered = |root: &FakeRoot| {
+        let manifest = build_manifest(&packaged(root)).unwrap();
+        manifest_first(root, &manifest, btf_of_arch(8, "tampered"))
+    };
+    for strict in [false, true] {
+        assert_eq!(lookup(tampered, strict), Err(-libc::EBADMSG));
+        assert!(
+            last_error().starts_with("Digest mismatch"),
+            "{}",
+            last_error()
+        );
+    }
+}
+
+#[test]
+fn unverifiable_btf_only_fails_in_strict_mode() {
+    assert_eq!(lookup(packaged, false), Ok(btf_of_arch(8, "packaged")));
+    assert_eq!(lookup(packaged, true), Err(-libc::ENOKEY));
+    assert!(
+        last_error().contains(MANIFEST_ENTRY_NAME),
+        "{}",
+        last_error()
+    );
+    // 清单存在但没有覆盖该条目
+    let uncovered = |root: &FakeRoot| manifest_first(root, b"", btf_of_arch(8, "packaged"));
+    assert_eq!(lookup(uncovered, false), Ok(btf_of_arch(8, "packaged")));
+    assert_eq!(lookup(uncovered, true), Err(-libc::ENOKEY));
+}
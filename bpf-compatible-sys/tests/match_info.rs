@@ -0,0 +1,268 @@
+//! `ensure_core_btf_with_tar_binary_match`, describing the btf a lookup settled on
+mod common;
+
+use std::{
+    ffi::CStr,
+    mem::size_of,
+    os::raw::{c_char, c_int},
+    path::Path,
+    ptr,
+};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, ensure_core_btf_with_tar_binary_match, match_info::BpfCompatMatchInfo,
+    opts::BpfCompatOpts, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_NATIVE_STATUS_MISSING,
+    BPF_COMPAT_NATIVE_STATUS_UNUSABLE, BPF_COMPAT_NATIVE_STATUS_USABLE, BPF_COMPAT_SOURCE_ARCHIVE,
+    BPF_COMPAT_SOURCE_NATIVE,
+};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    generate_btf_archive_paths_for,
+};
+use common::{last_error, path_of, take_btf, zeroed_opts, FakeRoot};
+
+/// A match info of `sz` bytes the caller filled with 0xff, to tell the fields written
+fn unwritten(sz: usize) -> BpfCompatMatchInfo {
+    let mut info: BpfCompatMatchInfo = unsafe { std::mem::zeroed() };
+    unsafe {
+        ptr::write_bytes(
+            &mut info as *mut _ as *mut u8,
+            0xff,
+            size_of::<BpfCompatMatchInfo>(),
+        )
+    };
+    info.sz = sz;
+    info
+}
+
+/// Look up the btf in `tar` with `opts`, returning the result, the match and the contents of the btf
+fn matched(
+    tar: &[u8],
+    opts: &BpfCompatOpts,
+    sz: usize,
+) -> (c_int, BpfCompatMatchInfo, Option<Vec<u8>>) {
+    let mut info = unwritten(sz);
+    let mut path: *const c_char = ptr::null();
+    let ret =
+        ensure_core_btf_with_tar_binary_match(&mut path, tar.as_ptr(), tar.len(), opts, &mut info);
+    let btf = (!path.is_null()).then(|| take_btf(path, BPF_COMPAT_BTF_DELETED));
+    (ret, info, btf)
+}
+
+fn text(v: &[c_char]) -> &str {
+    unsafe { CStr::from_ptr(v.as_ptr()) }.to_str().unwrap()
+}
+
+#[test]
+fn exact_match_names_the_entry() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "exact")).gz();
+    let (ret, info, btf) = matched(&tar, &root.opts(), size_of::<BpfCompatMatchInfo>());
+    assert_eq!(ret, 0, "{}", last_error());
+    assert_eq!(btf.unwrap(), btf_of_arch(8, "exact"));
+    assert_eq!(info.sz, size_of::<BpfCompatMatchInfo>());
+    assert_eq!(info.source, BPF_COMPAT_SOURCE_ARCHIVE);
+    assert!(info.exact);
+    assert_eq!(
+        text(&info.entry_path),
+        format!("btfhub-archive/{}", root.info)
+    );
+    assert_eq!(text(&info.kernel_release), root.info.kernel_release);
+    assert!(!info.local_version_stripped);
+    assert_eq!(info.candidate, 0);
+    // sysroot 下没有内核自带的 btf
+    assert_eq!(info.native_status, BPF_COMPAT_NATIVE_STATUS_MISSING);
+    assert!(!info.memoized);
+}
+
+/// A release of the same major.minor and flavor as `release`, older by one point release
+fn older_point_release(release: &str) -> Option<String> {
+    let (version, rest) =
+        release.split_at(release.find(|v: char| v != '.' && !v.is_ascii_digit())?);
+    let mut numbers = version.split('.').map(|v| v.parse::<u64>().ok());
+    let (major, minor, patch) = (numbers.next()??, numbers.next()??, numbers.next()??);
+    Some(format!(
+        "{}.{}.{}{}",
+        major,
+        minor,
+        patch.checked_sub(1)?,
+        rest
+    ))
+}
+
+#[test]
+fn nearest_release_is_not_exact() {
+    let root = FakeRoot::new();
+    let info = &root.info;
+    // 运行的内核（主机的内核）无法得出较旧的点版本时跳过
+    let Some(nearest) = older_point_release(&info.kernel_release) else {
+        return;
+    };
+    let tar = FixtureArchive::new()
+        .btf(
+            &info.distro_id,
+            &info.version_id,
+            &info.arch,
+            &nearest,
+            btf_of_arch(8, "nearest"),
+        )
+        .gz();
+    // 精确匹配时找不到，结构体保持原样
+    let (ret, untouched, _) = matched(&tar, &root.opts(), size_of::<BpfCompatMatchInfo>());
+    assert_eq!(ret, -libc::ENOENT);
+    assert_eq!(untouched.source, -1);
+    assert!(untouched.entry_path.iter().all(|v| *v == -1));
+    // 允许最接近的点版本时，结构体给出实际使用的内核
+    let opts = BpfCompatOpts {
+        match_policy: 1,
+        ..root.opts()
+    };
+    let (ret, matched, btf) = matched(&tar, &opts, size_of::<BpfCompatMatchInfo>());
+    assert_eq!(ret, 0, "{}", last_error());
+    assert_eq!(btf.unwrap(), btf_of_arch(8, "nearest"));
+    assert_eq!(matched.source, BPF_COMPAT_SOURCE_ARCHIVE);
+    assert!(!matched.exact);
+    assert_eq!(text(&matched.kernel_release), nearest);
+    assert_eq!(
+        text(&matched.entry_path),
+        format!(
+            "btfhub-archive/{}/{}/{}/{}.btf",
+            info.distro_id, info.version_id, info.arch, nearest
+        )
+    );
+    // 不是系统的任何候选路径
+    assert_eq!(matched.candidate, -1);
+}
+
+#[test]
+fn native_btf_has_no_entry() {
+    if !Path::new("/sys/kernel/btf/vmlinux").exists() {
+        return;
+    }
+    let tar = FixtureArchive::new().gz();
+    let opts = BpfCompatOpts {
+        sz: size_of::<BpfCompatOpts>(),
+        ..zeroed_opts()
+    };
+    let (ret, info, btf) = matched(&tar, &opts, size_of::<BpfCompatMatchInfo>());
+    assert_eq!(ret, 0, "{}", last_error());
+    assert!(btf.is_none());
+    assert_eq!(info.source, BPF_COMPAT_SOURCE_NATIVE);
+    assert!(info.exact);
+    assert_eq!(text(&info.entry_path), "");
+    assert_eq!(info.candidate, -1);
+    assert_eq!(info.native_status, BPF_COMPAT_NATIVE_STATUS_USABLE);
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap();
+    assert_eq!(text(&info.kernel_release), release.trim());
+}
+
+#[test]
+fn native_btf_of_the_sysroot_is_told_apart() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let vmlinux = root.path().join("sys/kernel/btf/vmlinux");
+    std::fs::create_dir_all(vmlinux.parent().unwrap()).unwrap();
+    let always_path = BpfCompatOpts {
+        always_path: true,
+        ..root.opts()
+    };
+
+    // 存在但不是有效的 btf，或无法读取，与不存在区分开
+    std::fs::write(&vmlinux, b"garbage").unwrap();
+    let (ret, info, btf) = matched(&tar, &root.opts(), size_of::<BpfCompatMatchInfo>());
+    assert_eq!(ret, 0, "{}", last_error());
+    assert_eq!(btf.unwrap(), btf_of_arch(8, "archived"));
+    assert_eq!(info.source, BPF_COMPAT_SOURCE_ARCHIVE);
+    assert_eq!(info.native_status, BPF_COMPAT_NATIVE_STATUS_UNUSABLE);
+    std::fs::remove_file(&vmlinux).unwrap();
+    std::fs::create_dir(&vmlinux).unwrap();
+    let (_, info, _) = matched(&tar, &root.opts(), size_of::<BpfCompatMatchInfo>());
+    assert_eq!(info.native_status, BPF_COMPAT_NATIVE_STATUS_UNUSABLE);
+    std::fs::remove_dir(&vmlinux).unwrap();
+
+    std::fs::write(&vmlinux, btf_of_arch(8, "native")).unwrap();
+    for opts in [root.opts(), always_path] {
+        let mut info = unwritten(size_of::<BpfCompatMatchInfo>());
+        let mut path: *const c_char = ptr::null();
+        assert_eq!(
+            ensure_core_btf_with_tar_binary_match(
+                &mut path,
+                tar.as_ptr(),
+                tar.len(),
+                &opts,
+                &mut info
+            ),
+            0
+        );
+        assert_eq!(path.is_null(), !opts.always_path);
+        assert_eq!(info.source, BPF_COMPAT_SOURCE_NATIVE);
+        assert_eq!(info.native_status, BPF_COMPAT_NATIVE_STATUS_USABLE);
+        assert_eq!(text(&info.kernel_release), root.info.kernel_release);
+        if !path.is_null() {
+            assert_eq!(path_of(path), vmlinux);
+            clean_core_btf_rs2(path as *mut c_char);
+        }
+    }
+}
+
+#[test]
+fn only_the_declared_size_is_written() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "sized")).gz();
+    // 较旧的调用者只有 source 和 exact 字段
+    let old = size_of::<usize>() + size_of::<c_int>() + 1;
+    let (ret, info, _) = matched(&tar, &root.opts(), old);
+    assert_eq!(ret, 0, "{}", last_error());
+    assert_eq!(info.sz, old);
+    assert_eq!(info.source, BPF_COMPAT_SOURCE_ARCHIVE);
+    assert!(info.exact);
+    assert!(info.entry_path.iter().all(|v| *v == -1));
+    assert_eq!(info.candidate, -1);
+    // 比 sz 字段本身还小的大小被拒绝，此时不解压归档
+    let (ret, info, btf) = matched(&tar, &root.opts(), 4);
+    assert_eq!(ret, -libc::EINVAL);
+    assert!(btf.is_none());
+    assert!(
+        last_error().contains("bpf_compat_match_info"),
+        "{}",
+        last_error()
+    );
+    assert_eq!(info.source, -1);
+}
+
+#[test]
+fn earliest_candidate_present_wins_whatever_the_archive_order() {
+    // 点版本号使原始的 VERSION_ID 与规范化后的版本成为不同的候选路径
+    let root = FakeRoot::with_os_release("ID=ubuntu\nVERSION_ID=\"20.04.6\"\n");
+    // 依次为规范化的版本、原始的版本、代号，再是架构的其他名称
+    let paths = generate_btf_archive_paths_for(&root.info);
+    assert!(
+        paths.iter().any(|v| v.starts_with("ubuntu/20.04.6/")),
+        "{paths:?}"
+    );
+    assert!(
+        paths
+            .iter()
+            .any(|v| !v.contains(&format!("/{}/", root.info.arch))),
+        "{paths:?}"
+    );
+    for first in 0..paths.len() {
+        // 后面的候选路径排在归档的前面
+        let mut archive = FixtureArchive::new();
+        for (i, path) in paths.iter().enumerate().skip(first).rev() {
+            archive = archive.file(
+                &format!("btfhub-archive/{path}"),
+                btf_of_arch(8, &format!("c{i}")),
+            );
+        }
+        let (ret, info, btf) =
+            matched(&archive.gz(), &root.opts(), size_of::<BpfCompatMatchInfo>());
+        assert_eq!(ret, 0, "{}", last_error());
+        assert_eq!(btf.unwrap(), btf_of_arch(8, &format!("c{first}")));
+        assert_eq!(info.candidate, first as c_int);
+        assert_eq!(
+            text(&info.entry_path),
+            format!("btfhub-archive/{}", paths[first])
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Btfs handed out as sealed memfds
+#![cfg(target_os = "linux")]
+mod common;
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::{raw::c_char, unix::io::AsRawFd},
+};
+
+use bpf_compatible::{clean_core_btf_rs2, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::{btf::validate_btf_bytes, fixture::btf_of_arch};
+use common::{ensure_opts, path_of, FakeRoot};
+
+#[test]
+fn memfd_btf_is_readable_while_the_fd_is_open() {
+    let root = FakeRoot::new();
+    let btf = btf_of_arch(8, "rip");
+    let tar = root.archive(btf.clone()).gz();
+    let opts = bpf_compatible::opts::BpfCompatOpts {
+        use_memfd: true,
+        ..root.opts()
+    };
+    let path = ensure_opts(&tar, &opts).unwrap();
+    let memfd = path_of(path);
+    assert!(memfd.starts_with("/proc/self/fd/"), "{}", memfd.display());
+    // 和 libbpf 一样按路径打开并解析
+    let contents = fs::read(&memfd).unwrap();
+    assert_eq!(contents, btf);
+    validate_btf_bytes(&contents).unwrap();
+    // 没有在临时目录中留下文件
+    assert!(fs::read_dir(root.path().join("tmp"))
+        .map(|v| v.count() == 0)
+        .unwrap_or(true));
+
+    // 密封之后内容不能再被修改
+    let mut writer = OpenOptions::new().write(true).open(&memfd).unwrap();
+    assert_eq!(
+        writer.write_all(b"\0").unwrap_err().kind(),
+        ErrorKind::PermissionDenied
+    );
+    drop(writer);
+    assert_eq!(fs::read(&memfd).unwrap(), btf);
+
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    assert!(fs::read(&memfd).is_err());
+}
+
+#[test]
+fn unrelated_fd_path_is_not_closed() {
+    let file = tempfile::tempfile().unwrap();
+    let fd_path = format!("/proc/self/fd/{}", file.as_raw_fd());
+    let path = format!("{}\0", fd_path);
+    // 不是本库创建的 memfd，不关闭也不删除
+    assert_ne!(
+        clean_core_btf_rs2(path.as_ptr() as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    assert!(fs::metadata(&fd_path).is_ok());
+}
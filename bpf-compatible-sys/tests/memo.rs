@@ -0,0 +1,123 @@
+//! Reuse of the btf extracted by an earlier lookup of the process
+//!
+//! The process remembers a single extraction, so this is the only test of the binary.
+mod common;
+
+use std::{
+    ffi::{c_void, CStr},
+    fs,
+    mem::size_of,
+    os::raw::{c_char, c_int},
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, ensure_core_btf_with_tar_binary_match, match_info::BpfCompatMatchInfo,
+    opts::BpfCompatOpts, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_NATIVE_STATUS_MISSING,
+    BPF_COMPAT_PATH_FREED, BPF_COMPAT_SOURCE_ARCHIVE,
+};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{last_error, path_of, FakeRoot};
+
+/// Counts the calls of the progress callback, made as the decompressed tar is read
+unsafe extern "C" fn count_progress(_: u64, _: u64, ctx: *mut c_void) -> c_int {
+    (*(ctx as *const AtomicU64)).fetch_add(1, Ordering::SeqCst);
+    0
+}
+
+/// Look up the btf in `tar`, returning the path, the match, and how often the archive was read
+fn ensure(tar: &[u8], opts: &BpfCompatOpts) -> (*mut c_char, BpfCompatMatchInfo, u64) {
+    let reads = AtomicU64::new(0);
+    let opts = BpfCompatOpts {
+        progress: Some(count_progress),
+        progress_ctx: &reads as *const _ as *mut c_void,
+        ..*opts
+    };
+    let mut info: BpfCompatMatchInfo = unsafe { std::mem::zeroed() };
+    info.sz = size_of::<BpfCompatMatchInfo>();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_binary_match(&mut path, tar.as_ptr(), tar.len(), &opts, &mut info),
+        0,
+        "{}",
+        last_error()
+    );
+    (path as *mut c_char, info, reads.into_inner())
+}
+
+#[test]
+fn later_lookups_reuse_the_extracted_btf() {
+    let root = FakeRoot::new();
+    // 大于进度回调的间隔，读取归档时至少回调一次
+    let tar = root
+        .archive(btf_of_arch(8, "memo"))
+        .file("padding", vec![0x5a; 3 << 20])
+        .gz();
+    let opts = root.opts();
+
+    let (first, info, reads) = ensure(&tar, &opts);
+    assert!(!info.memoized);
+    assert!(reads > 0);
+    let file = path_of(first);
+
+    // 之后的查找不再解压归档，返回同一个文件
+    let (second, info, reads) = ensure(&tar, &opts);
+    assert!(info.memoized);
+    assert_eq!(reads, 0);
+    // 复用的 btf 仍按首次查找时的来源描述
+    assert_eq!(info.source, BPF_COMPAT_SOURCE_ARCHIVE);
+    assert!(info.exact);
+    assert_eq!(
+        unsafe { CStr::from_ptr(info.entry_path.as_ptr()) }.to_str(),
+        Ok(format!("btfhub-archive/{}", root.info).as_str())
+    );
+    assert_eq!(info.native_status, BPF_COMPAT_NATIVE_STATUS_MISSING);
+    assert_eq!(path_of(second), file);
+    // 清理任何一份路径都使记忆失效；文件在最后一份路径清理时才删除
+    assert_eq!(clean_core_btf_rs2(second), BPF_COMPAT_PATH_FREED);
+    assert!(file.exists());
+    let (third, info, reads) = ensure(&tar, &opts);
+    assert!(!info.memoized && reads > 0);
+    assert_ne!(path_of(third), file);
+    assert_eq!(clean_core_btf_rs2(first), BPF_COMPAT_BTF_DELETED);
+    assert!(!file.exists());
+
+    // 文件被删除后，透明地重新解压
+    let file = path_of(third);
+    fs::remove_file(&file).unwrap();
+    let (fourth, info, reads) = ensure(&tar, &opts);
+    assert!(!info.memoized && reads > 0);
+    let refreshed = path_of(fourth);
+    assert_eq!(fs::read(&refreshed).unwrap(), btf_of_arch(8, "memo"));
+    assert_eq!(clean_core_btf_rs2(third), -libc::ENOENT);
+
+    // 文件大小改变时同样重新解压
+    fs::write(&refreshed, b"truncated").unwrap();
+    let (fifth, info, _) = ensure(&tar, &opts);
+    assert!(!info.memoized);
+    assert_ne!(path_of(fifth), refreshed);
+    assert_eq!(fs::read(path_of(fifth)).unwrap(), btf_of_arch(8, "memo"));
+    assert_eq!(clean_core_btf_rs2(fourth), BPF_COMPAT_BTF_DELETED);
+    let (sixth, info, _) = ensure(&tar, &opts);
+    assert!(info.memoized);
+    assert_eq!(path_of(sixth), path_of(fifth));
+    assert_eq!(clean_core_btf_rs2(fifth), BPF_COMPAT_PATH_FREED);
+    assert_eq!(clean_core_btf_rs2(sixth), BPF_COMPAT_BTF_DELETED);
+
+    // 归档或选项不同时不复用
+    let other = root.archive(btf_of_arch(8, "other")).gz();
+    let (seventh, info, _) = ensure(&other, &opts);
+    assert!(!info.memoized);
+    assert_eq!(fs::read(path_of(seventh)).unwrap(), btf_of_arch(8, "other"));
+    let nearest = BpfCompatOpts {
+        match_policy: 1,
+        ..opts
+    };
+    let (eighth, info, _) = ensure(&other, &nearest);
+    assert!(!info.memoized);
+    assert_ne!(path_of(eighth), path_of(seventh));
+    for path in [seventh, eighth] {
+        assert_eq!(clean_core_btf_rs2(path), BPF_COMPAT_BTF_DELETED);
+    }
+}
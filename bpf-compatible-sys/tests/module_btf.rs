@@ -0,0 +1,100 @@
+//! `ensure_module_btf`, the split btf of a kernel module
+mod common;
+
+use std::{ffi::CString, fs, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, ensure_module_btf, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_NATIVE_BTF,
+    BPF_COMPAT_PATH_FREED,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive};
+use common::{last_error, path_of, FakeRoot};
+
+fn archive(root: &FakeRoot) -> Vec<u8> {
+    let info = &root.info;
+    FixtureArchive::new()
+        .module_btf(
+            &info.distro_id,
+            &info.version_id,
+            &info.arch,
+            &info.kernel_release,
+            "nf_tables",
+            btf_of_arch(8, "archived"),
+        )
+        .gz()
+}
+
+fn ensure(root: &FakeRoot, tar: &[u8], module: &str) -> (i32, *const c_char) {
+    let module = CString::new(module).unwrap();
+    let mut path: *const c_char = ptr::null();
+    let ret = ensure_module_btf(
+        &mut path,
+        tar.as_ptr(),
+        tar.len(),
+        module.as_ptr(),
+        &root.opts(),
+    );
+    (ret, path)
+}
+
+#[test]
+fn module_btf_is_extracted_without_native_btf() {
+    let root = FakeRoot::new();
+    let tar = archive(&root);
+    let (ret, path) = ensure(&root, &tar, "nf_tables");
+    assert_eq!(ret, 0, "{}", last_error());
+    let extracted = path_of(path);
+    assert_eq!(fs::read(&extracted).unwrap(), btf_of_arch(8, "archived"));
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    assert!(!extracted.exists());
+
+    let (ret, path) = ensure(&root, &tar, "xfs");
+    assert_eq!(ret, -libc::ENOENT);
+    assert!(path.is_null());
+    assert!(last_error().contains("xfs"), "{}", last_error());
+}
+
+#[test]
+fn module_btf_of_the_kernel_comes_first() {
+    let root = FakeRoot::new();
+    let tar = archive(&root);
+    let sysfs = root.path().join("sys/kernel/btf");
+    fs::create_dir_all(&sysfs).unwrap();
+    fs::write(sysfs.join("vmlinux"), minimal_valid_btf()).unwrap();
+
+    // 内核有 btf、但模块没有加载时交给 libbpf
+    let (ret, path) = ensure(&root, &tar, "nf_tables");
+    assert_eq!(ret, BPF_COMPAT_NATIVE_BTF);
+    assert!(path.is_null());
+
+    // 内核导出的模块 btf 直接返回，清理时不删除
+    fs::write(sysfs.join("nf_tables"), btf_of_arch(8, "sysfs")).unwrap();
+    let (ret, path) = ensure(&root, &tar, "nf_tables");
+    assert_eq!(ret, 0, "{}", last_error());
+    assert_eq!(path_of(path), sysfs.join("nf_tables"));
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_PATH_FREED
+    );
+    assert!(sysfs.join("nf_tables").exists());
+}
+
+#[test]
+fn invalid_module_names_are_rejected() {
+    let root = FakeRoot::new();
+    let tar = archive(&root);
+    for module in ["", "..", "../vmlinux", "a/b"] {
+        let (ret, path) = ensure(&root, &tar, module);
+        assert_eq!(ret, -libc::EINVAL, "{:?}", module);
+        assert!(path.is_null());
+    }
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_module_btf(&mut path, tar.as_ptr(), tar.len(), ptr::null(), ptr::null()),
+        -libc::EINVAL
+    );
+    assert!(last_error().contains("NULL"), "{}", last_error());
+}
@@ -0,0 +1,162 @@
+//! `ensure_core_btf_multi_opts`, searching several archives in order
+mod common;
+
+use std::{ffi::CString, fs, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    ensure_core_btf_multi, ensure_core_btf_multi_opts,
+    source::{BpfCompatSource, BPF_COMPAT_SRC_BUFFER, BPF_COMPAT_SRC_FILE, BPF_COMPAT_SRC_LINKED},
+    BPF_COMPAT_BTF_DELETED,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{last_error, take_btf, FakeRoot};
+
+fn buffer(tar: &[u8]) -> BpfCompatSource {
+    BpfCompatSource {
+        kind: BPF_COMPAT_SRC_BUFFER,
+        buf: tar.as_ptr(),
+        len: tar.len(),
+        path: ptr::null(),
+    }
+}
+
+fn file(path: &CString) -> BpfCompatSource {
+    BpfCompatSource {
+        kind: BPF_COMPAT_SRC_FILE,
+        buf: ptr::null(),
+        len: 0,
+        path: path.as_ptr(),
+    }
+}
+
+/// The btf found in the first of `sources` having one, which is removed, or the error
+fn search(root: &FakeRoot, sources: &[BpfCompatSource]) -> Result<Vec<u8>, i32> {
+    let mut path: *const c_char = ptr::null();
+    let ret = ensure_core_btf_multi_opts(sources.as_ptr(), sources.len(), &mut path, &root.opts());
+    if ret != 0 {
+        assert!(path.is_null());
+        return Err(ret);
+    }
+    let btf = take_btf(path, BPF_COMPAT_BTF_DELETED);
+    Ok(btf)
+}
+
+#[test]
+fn first_archive_with_the_btf_wins() {
+    let root = FakeRoot::new();
+    let base = root.archive(btf_of_arch(8, "base")).gz();
+    let supplement = root.archive(btf_of_arch(8, "supplement")).gz();
+    let other = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "other"),
+        )
+        .gz();
+    assert_eq!(
+        search(&root, &[buffer(&base), buffer(&supplement)]),
+        Ok(btf_of_arch(8, "base"))
+    );
+    assert_eq!(
+        search(&root, &[buffer(&other), buffer(&supplement)]),
+        Ok(btf_of_arch(8, "supplement"))
+    );
+    // 文件与内存中的归档可以混用
+    let supplement_file = root.path().join("supplement.tar.gz");
+    fs::write(&supplement_file, &supplement).unwrap();
+    let supplement_file = CString::new(supplement_file.to_str().unwrap()).unwrap();
+    assert_eq!(
+        search(&root, &[buffer(&other), file(&supplement_file)]),
+        Ok(btf_of_arch(8, "supplement"))
+    );
+}
+
+#[test]
+fn missing_sources_are_skipped() {
+    let root = FakeRoot::new();
+    let supplement = root.archive(btf_of_arch(8, "supplement")).gz();
+    let missing = CString::new(root.path().join("missing.tar.gz").to_str().unwrap()).unwrap();
+    let linked = BpfCompatSource {
+        kind: BPF_COMPAT_SRC_LINKED,
+        buf: ptr::null(),
+        len: 0,
+        path: ptr::null(),
+    };
+    // 测试程序没有链接归档，与不存在的文件一样跳过
+    assert_eq!(
+        search(&root, &[linked, file(&missing), buffer(&supplement)]),
+        Ok(btf_of_arch(8, "supplement"))
+    );
+}
+
+#[test]
+fn miss_in_every_archive_is_not_found() {
+    let root = FakeRoot::new();
+    let other = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "other"),
+        )
+        .gz();
+    let missing = CString::new(root.path().join("missing.tar.gz").to_str().unwrap()).unwrap();
+    assert_eq!(
+        search(&root, &[buffer(&other), file(&missing), buffer(&other)]),
+        Err(-libc::ENOENT)
+    );
+    assert!(
+        last_error().contains("None of the 3 archives"),
+        "{}",
+        last_error()
+    );
+}
+
+#[test]
+fn earlier_failure_is_not_masked_by_later_misses() {
+    let root = FakeRoot::new();
+    let supplement = root.archive(btf_of_arch(8, "supplement")).gz();
+    let corrupt = b"PK\x03\x04 not an archive".to_vec();
+    let other = FixtureArchive::new().gz();
+    let err = search(&root, &[buffer(&corrupt), buffer(&other)]).unwrap_err();
+    assert_ne!(err, -libc::ENOENT);
+    assert!(err < 0);
+    assert!(last_error().contains("50 4b 03 04"), "{}", last_error());
+    // 之后的归档有 btf 时照常使用
+    assert_eq!(
+        search(&root, &[buffer(&corrupt), buffer(&supplement)]),
+        Ok(btf_of_arch(8, "supplement"))
+    );
+}
+
+#[test]
+fn invalid_sources_fail_before_any_search() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "base")).gz();
+    let null_file = BpfCompatSource {
+        kind: BPF_COMPAT_SRC_FILE,
+        buf: ptr::null(),
+        len: 0,
+        path: ptr::null(),
+    };
+    let unknown = BpfCompatSource {
+        kind: 42,
+        ..buffer(&tar)
+    };
+    for sources in [[buffer(&tar), null_file], [buffer(&tar), unknown]] {
+        assert_eq!(search(&root, &sources), Err(-libc::EINVAL));
+    }
+    assert!(last_error().contains("42"), "{}", last_error());
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_multi(ptr::null(), 0, &mut path),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        ensure_core_btf_multi([buffer(&tar)].as_ptr(), 1, ptr::null_mut()),
+        -libc::EINVAL
+    );
+}
@@ -0,0 +1,133 @@
+//! Per-kernel tarballs, as btfhub-archive ships them: `<release>.btf.tar.xz` holding
+//! `<release>.btf`
+mod common;
+
+use bpf_compatible_rs::fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive};
+use common::{last_error, lookup};
+use libc::EILSEQ;
+
+const RELEASE: &str = "5.4.0-40-generic";
+
+/// The btfhub-archive layout, with the per-kernel tarball compressed as `suffix`
+fn btfhub_archive(suffix: &str, tarball: Vec<u8>) -> Vec<u8> {
+    FixtureArchive::new()
+        .file(
+            &format!(
+                "btfhub-archive/ubuntu/20.04/x86_64/{}.btf.tar.{}",
+                RELEASE, suffix
+            ),
+            tarball,
+        )
+        .gz()
+}
+
+/// A per-kernel tarball holding the btf under its file name only, as in btfhub-archive
+fn per_kernel(btf: Vec<u8>) -> FixtureArchive {
+    FixtureArchive::new().file(&format!("{}.btf", RELEASE), btf)
+}
+
+#[test]
+fn btf_is_extracted_from_a_gzipped_per_kernel_tarball() {
+    let btf = btf_of_arch(8, "r15");
+    let tar = btfhub_archive("gz", per_kernel(btf.clone()).gz());
+    assert_eq!(lookup(&tar), Ok(btf));
+}
+
+#[test]
+fn non_btf_members_of_the_tarball_are_skipped() {
+    let tarball = per_kernel(minimal_valid_btf())
+        .file("README", b"not a btf".to_vec())
+        .dir("extra")
+        .gz();
+    assert_eq!(
+        lookup(&btfhub_archive("gz", tarball)),
+        Ok(minimal_valid_btf())
+    );
+}
+
+#[test]
+fn only_one_level_of_nesting_is_looked_into() {
+    // 内层归档中的 .btf.tar.gz 不会再被解开
+    let twice = FixtureArchive::new()
+        .file(
+            &format!("{}.btf.tar.gz", RELEASE),
+            per_kernel(minimal_valid_btf()).gz(),
+        )
+        .gz();
+    assert_eq!(lookup(&btfhub_archive("gz", twice)), Err(-EILSEQ));
+    assert!(last_error().contains("holds no btf"), "{}", last_error());
+}
+
+#[test]
+fn tarball_with_several_btfs_is_rejected() {
+    let tarball = per_kernel(minimal_valid_btf())
+        .file("5.4.0-41-generic.btf", btf_of_arch(8, "r15"))
+        .gz();
+    assert_eq!(lookup(&btfhub_archive("gz", tarball)), Err(-EILSEQ));
+    assert!(
+        last_error().contains("more than one btf"),
+        "{}",
+        last_error()
+    );
+}
+
+#[test]
+fn corrupt_tarballs_fail_with_eilseq() {
+    let mut truncated = per_kernel(btf_of_arch(4, "bx")).gz();
+    truncated.truncate(truncated.len() / 2);
+    let mut flipped = per_kernel(btf_of_arch(8, "orig_x0")).gz();
+    let middle = flipped.len() / 2;
+    flipped[middle] ^= 0xff;
+    for tarball in [truncated, flipped, b"garbage, not a tarball".to_vec()] {
+        assert_eq!(lookup(&btfhub_archive("gz", tarball)), Err(-EILSEQ));
+        assert!(
+            last_error().contains("per-kernel tarball"),
+            "{}",
+            last_error()
+        );
+    }
+}
+
+#[cfg(not(feature = "xz"))]
+#[test]
+fn xz_tarball_without_the_feature_is_unsupported() {
+    let tarball = [&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00][..], &[0; 32]].concat();
+    assert_eq!(lookup(&btfhub_archive("xz", tarball)), Err(-libc::ENOTSUP));
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn btf_is_extracted_from_an_xz_per_kernel_tarball() {
+    extern "C" {
+        fn lzma_stream_buffer_bound(size: usize) -> usize;
+        fn lzma_easy_buffer_encode(
+            preset: u32,
+            check: libc::c_int,
+            allocator: *const libc::c_void,
+            input: *const u8,
+            input_size: usize,
+            output: *mut u8,
+            output_pos: *mut usize,
+            output_size: usize,
+        ) -> libc::c_int;
+    }
+    let tar = per_kernel(btf_of_arch(8, "r15")).tar();
+    let mut xz = vec![0; unsafe { lzma_stream_buffer_bound(tar.len()) }];
+    let mut len = 0;
+    // 预设 6 与 CRC64 校验（4）是 xz 命令的默认值
+    let ret = unsafe {
+        lzma_easy_buffer_encode(
+            6,
+            4,
+            std::ptr::null(),
+            tar.as_ptr(),
+            tar.len(),
+            xz.as_mut_ptr(),
+            &mut len,
+            xz.len(),
+        )
+    };
+    assert_eq!(ret, 0);
+    xz.truncate(len);
+    assert_eq!(lookup(&btfhub_archive("xz", xz)), Ok(btf_of_arch(8, "r15")));
+}
@@ -0,0 +1,28 @@
+//! The linked archive entry points of a program not linking `min_core_btfs_tar.o`
+//!
+//! The library references the symbols of the archive weakly, so this binary links without
+//! them; see `linked_tar.rs` for one defining them.
+mod common;
+
+use std::{os::raw::c_char, ptr};
+
+use bpf_compatible::{ensure_core_btf_with_linked_tar_opts, linked_archive_bytes};
+use common::{last_error, FakeRoot};
+
+#[test]
+fn missing_archive_is_not_found() {
+    assert!(linked_archive_bytes().is_none());
+    // sysroot 下没有内核自带的 btf，只能查找归档
+    let root = FakeRoot::new();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_linked_tar_opts(&mut path, &root.opts()),
+        -libc::ENOENT
+    );
+    assert!(path.is_null());
+    assert!(
+        last_error().contains("No btf archive is linked"),
+        "{}",
+        last_error()
+    );
+}
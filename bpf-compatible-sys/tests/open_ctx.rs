@@ -0,0 +1,143 @@
+//! `bpf_compat_open`, `bpf_compat_fill_open_opts` and `bpf_compat_close`
+//!
+//! The lookups go to the running system, so the btf is forced with
+//! `BPF_COMPATIBLE_BTF_PATH`, or the kernel faked with the `fake-system` feature; this is
+//! the only test of the binary setting them.
+mod common;
+
+use std::{ffi::CStr, fs, os::raw::c_char, path::Path, ptr};
+
+use bpf_compatible::{
+    bpf_compat_close, bpf_compat_fill_open_opts, bpf_compat_open, BPF_COMPAT_CUSTOM_BTF,
+    BPF_COMPAT_NATIVE_BTF,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::last_error;
+
+/// The start of `struct bpf_object_open_opts`, as C callers declare it, up to `btf_custom_path`
+#[repr(C)]
+struct OpenOpts {
+    sz: usize,
+    object_name: *const c_char,
+    relaxed_maps: bool,
+    pin_root_path: *const c_char,
+    _unused: u32,
+    kconfig: *const c_char,
+    btf_custom_path: *const c_char,
+    kernel_log_buf: *mut c_char,
+}
+
+fn unfilled_opts() -> OpenOpts {
+    OpenOpts {
+        sz: std::mem::size_of::<OpenOpts>(),
+        object_name: ptr::null(),
+        relaxed_maps: false,
+        pin_root_path: ptr::null(),
+        _unused: 0,
+        kconfig: ptr::null(),
+        // 填充前的值，确认函数确实写入了该字段
+        btf_custom_path: ptr::dangling(),
+        kernel_log_buf: ptr::null_mut(),
+    }
+}
+
+fn errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap()
+}
+
+fn fill(ctx: *const bpf_compatible::BpfCompatCtx, opts: &mut OpenOpts) -> i32 {
+    bpf_compat_fill_open_opts(ctx, opts as *mut _ as *mut _, opts.sz)
+}
+
+#[test]
+fn open_fill_close() {
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "ctx"),
+        )
+        .gz();
+
+    // 参数错误时返回 NULL 并设置 errno
+    assert!(bpf_compat_open(ptr::null(), 0).is_null());
+    assert_eq!(errno(), libc::EINVAL);
+    let mut opts = unfilled_opts();
+    assert_eq!(fill(ptr::null(), &mut opts), -libc::EINVAL);
+    bpf_compat_close(ptr::null_mut());
+
+    if Path::new("/sys/kernel/btf/vmlinux").exists() {
+        // 内核自带 btf 时置空，由 libbpf 自行查找
+        let ctx = bpf_compat_open(tar.as_ptr(), tar.len());
+        assert!(!ctx.is_null(), "{}", last_error());
+        let mut opts = unfilled_opts();
+        assert_eq!(fill(ctx, &mut opts), BPF_COMPAT_NATIVE_BTF);
+        assert!(opts.btf_custom_path.is_null());
+        assert_eq!(opts.sz, std::mem::size_of::<OpenOpts>());
+        // 结构体太小、没有该字段时拒绝
+        assert_eq!(
+            bpf_compat_fill_open_opts(ctx, &mut opts as *mut _ as *mut _, 16),
+            -libc::EINVAL
+        );
+        // 出错后上下文仍可使用
+        assert_eq!(fill(ctx, &mut opts), BPF_COMPAT_NATIVE_BTF);
+        bpf_compat_close(ctx);
+    }
+
+    // 使用者指定的 btf 不会被删除
+    let dir = tempfile::tempdir().unwrap();
+    let forced = dir.path().join("forced.btf");
+    fs::write(&forced, btf_of_arch(8, "forced")).unwrap();
+    std::env::set_var("BPF_COMPATIBLE_BTF_PATH", &forced);
+    let ctx = bpf_compat_open(tar.as_ptr(), tar.len());
+    assert!(!ctx.is_null(), "{}", last_error());
+    let mut opts = unfilled_opts();
+    assert_eq!(fill(ctx, &mut opts), BPF_COMPAT_CUSTOM_BTF);
+    assert_eq!(
+        unsafe { CStr::from_ptr(opts.btf_custom_path) }
+            .to_str()
+            .unwrap(),
+        forced.to_str().unwrap()
+    );
+    bpf_compat_close(ctx);
+    assert!(forced.exists());
+
+    std::env::set_var("BPF_COMPATIBLE_BTF_PATH", dir.path().join("missing.btf"));
+    assert!(bpf_compat_open(tar.as_ptr(), tar.len()).is_null());
+    assert_eq!(errno(), libc::ENOENT);
+    std::env::remove_var("BPF_COMPATIBLE_BTF_PATH");
+
+    #[cfg(feature = "fake-system")]
+    faked(&tar);
+}
+
+/// The btf extracted from the archive lives until the context is closed
+#[cfg(feature = "fake-system")]
+fn faked(tar: &[u8]) {
+    use bpf_compatible_rs::fake::{
+        FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV,
+    };
+
+    std::env::set_var(FAKE_ARCH_ENV, "x86_64");
+    std::env::set_var(FAKE_DISTRO_ENV, "ubuntu");
+    std::env::set_var(FAKE_VERSION_ENV, "20.04");
+    std::env::set_var(FAKE_KERNEL_ENV, "5.4.0-40-generic");
+    let ctx = bpf_compat_open(tar.as_ptr(), tar.len());
+    assert!(!ctx.is_null(), "{}", last_error());
+    let mut opts = unfilled_opts();
+    assert_eq!(fill(ctx, &mut opts), BPF_COMPAT_CUSTOM_BTF);
+    let path = common::path_of(opts.btf_custom_path);
+    // 在 bpf_object__load 读取之前文件一直存在
+    assert_eq!(fs::read(&path).unwrap(), btf_of_arch(8, "ctx"));
+    let mut again = unfilled_opts();
+    assert_eq!(fill(ctx, &mut again), BPF_COMPAT_CUSTOM_BTF);
+    assert_eq!(again.btf_custom_path, opts.btf_custom_path);
+    bpf_compat_close(ctx);
+    assert!(!path.exists());
+
+    std::env::set_var(FAKE_KERNEL_ENV, "5.4.0-99-generic");
+    assert!(bpf_compat_open(tar.as_ptr(), tar.len()).is_null());
+    assert_eq!(errno(), libc::ENOENT);
+}
@@ -0,0 +1,83 @@
+//! Lookups on other systems than Linux, which fail with `-ENOTSUP` and write nothing
+//!
+//! Only built there; on Linux the other tests cover the same functions.
+#![cfg(not(target_os = "linux"))]
+mod common;
+
+use std::{ffi::CString, mem::size_of, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    bpf_compatible_gc_stale_btf_tempfiles, ensure_core_btf_for_system, free_core_btf_kernel_list,
+    get_current_system_info, list_core_btf_kernels, opts::BpfCompatOpts,
+    system_info::BpfCompatSystemInfo,
+};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{ensure_opts, last_error, zeroed_opts};
+use libc::ENOTSUP;
+
+fn archive() -> Vec<u8> {
+    FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            btf_of_arch(8, "rip"),
+        )
+        .gz()
+}
+
+#[test]
+fn running_system_is_unsupported() {
+    let tar = archive();
+    let opts = BpfCompatOpts {
+        sz: size_of::<BpfCompatOpts>(),
+        ..zeroed_opts()
+    };
+    assert_eq!(ensure_opts(&tar, &opts), Err(-ENOTSUP));
+    assert!(
+        last_error().contains("Not supported on this platform"),
+        "{}",
+        last_error()
+    );
+
+    let mut info: BpfCompatSystemInfo = unsafe { std::mem::zeroed() };
+    info.sz = size_of::<BpfCompatSystemInfo>();
+    assert_eq!(get_current_system_info(&mut info), -ENOTSUP);
+    assert_eq!(
+        bpf_compatible_gc_stale_btf_tempfiles(ptr::null(), 0, ptr::null_mut()),
+        -ENOTSUP
+    );
+}
+
+#[test]
+fn explicit_systems_are_found_but_not_written() {
+    let tar = archive();
+    // 归档可以照常读取
+    let mut entries: *mut *mut c_char = ptr::null_mut();
+    let mut count = 0;
+    assert_eq!(
+        list_core_btf_kernels(tar.as_ptr(), tar.len(), &mut entries, &mut count),
+        0
+    );
+    assert_eq!(count, 1);
+    free_core_btf_kernel_list(entries);
+
+    // 找到了 btf，但没有可以写入的临时文件
+    let [distro, version, arch, release] =
+        ["ubuntu", "20.04", "x86_64", "5.4.0-40-generic"].map(|v| CString::new(v).unwrap());
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_for_system(
+            &mut path,
+            tar.as_ptr(),
+            tar.len(),
+            distro.as_ptr(),
+            version.as_ptr(),
+            arch.as_ptr(),
+            release.as_ptr(),
+        ),
+        -ENOTSUP
+    );
+    assert!(path.is_null());
+}
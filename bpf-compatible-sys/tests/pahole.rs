@@ -0,0 +1,89 @@
+//! Generating the btfs missing from the archive with a stub of pahole
+//!
+//! This is the only test of the binary, so setting `XDG_CACHE_HOME` affects no other.
+#![cfg(feature = "pahole")]
+mod common;
+
+use std::{ffi::CString, fs, os::unix::fs::PermissionsExt};
+
+use bpf_compatible::{opts::BpfCompatOpts, BPF_COMPAT_PATH_FREED};
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{ensure_opts, last_error, lookup_opts, take_btf, FakeRoot};
+
+fn ensure(tar: &[u8], opts: &BpfCompatOpts) -> Result<Vec<u8>, i32> {
+    let path = ensure_opts(tar, opts)?;
+    // 生成的 btf 保存在缓存中，不会被删除
+    Ok(take_btf(path, BPF_COMPAT_PATH_FREED))
+}
+
+#[test]
+fn missing_btfs_are_generated_only_when_allowed() {
+    let root = FakeRoot::new();
+    std::env::set_var("XDG_CACHE_HOME", root.path().join("cache"));
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            &root.info.arch,
+            "0.0.0-other",
+            btf_of_arch(8, "other"),
+        )
+        .gz();
+    // pahole 的桩程序记录每次运行，并把固定的 btf 写到输出位置
+    let fixture = root.path().join("fixture.btf");
+    fs::write(&fixture, btf_of_arch(8, "pahole")).unwrap();
+    let runs = root.path().join("runs");
+    let stub = root.path().join("pahole");
+    fs::write(
+        &stub,
+        format!(
+            "#!/bin/sh\necho \"$3\" >> {}\ncp {} \"$2\"\n",
+            runs.display(),
+            fixture.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+    let vmlinux = root
+        .path()
+        .join(format!("srv/vmlinux-{}", root.info.kernel_release));
+    let pahole = CString::new(stub.to_str().unwrap()).unwrap();
+    let locations = CString::new("/nonexistent/{release}:/srv/vmlinux-{release}").unwrap();
+    let mut opts = BpfCompatOpts {
+        pahole_path: pahole.as_ptr(),
+        vmlinux_locations: locations.as_ptr(),
+        ..root.opts()
+    };
+
+    // 不允许时不运行 pahole
+    assert_eq!(ensure(&tar, &opts), Err(-libc::ENOENT));
+    assert!(!runs.exists());
+
+    // 没有 vmlinux 时同样返回 -ENOENT，原因保存在最后的错误中
+    opts.allow_pahole = true;
+    assert_eq!(ensure(&tar, &opts), Err(-libc::ENOENT));
+    assert!(last_error().contains("No vmlinux"), "{}", last_error());
+    assert!(!runs.exists());
+
+    fs::create_dir(vmlinux.parent().unwrap()).unwrap();
+    fs::write(&vmlinux, b"\x7fELF\x02\x01\x01\0with dwarf").unwrap();
+    assert_eq!(ensure(&tar, &opts).unwrap(), btf_of_arch(8, "pahole"));
+    assert_eq!(
+        fs::read_to_string(&runs).unwrap(),
+        format!("{}\n", vmlinux.display())
+    );
+    // 再次查找使用缓存，不再运行 pahole
+    assert_eq!(ensure(&tar, &opts).unwrap(), btf_of_arch(8, "pahole"));
+    assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+
+    // 归档中有对应的 btf 时不会用到 pahole
+    let covered = root.archive(btf_of_arch(8, "archived")).gz();
+    assert_eq!(lookup_opts(&covered, &opts), Ok(btf_of_arch(8, "archived")));
+    assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+
+    // pahole 失败时仍返回 -ENOENT
+    fs::write(&stub, "#!/bin/sh\necho 'no DWARF' >&2\nexit 1\n").unwrap();
+    fs::write(&vmlinux, b"\x7fELF\x02\x01\x01\0rebuilt").unwrap();
+    assert_eq!(ensure(&tar, &opts), Err(-libc::ENOENT));
+    assert!(last_error().contains("no DWARF"), "{}", last_error());
+}
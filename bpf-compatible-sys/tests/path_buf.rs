@@ -0,0 +1,79 @@
+//! `ensure_core_btf_path_buf`, writing the path into a buffer of the caller
+//!
+//! The lookups go to the running system, so the btf is forced with
+//! `BPF_COMPATIBLE_BTF_PATH`; this is the only test of the binary setting it.
+mod common;
+
+use std::{ffi::CStr, fs, os::raw::c_char, path::Path, ptr};
+
+use bpf_compatible::{clean_core_btf_path, ensure_core_btf_path_buf, BPF_COMPAT_PATH_FREED};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::FakeRoot;
+
+const BTF_PATH_ENV: &str = "BPF_COMPATIBLE_BTF_PATH";
+
+fn path_buf(buf: Option<&mut [u8]>, tar: &[u8]) -> i32 {
+    let (ptr, len) = match buf {
+        Some(v) => (v.as_mut_ptr() as *mut c_char, v.len()),
+        None => (ptr::null_mut(), 0),
+    };
+    ensure_core_btf_path_buf(ptr, len, tar.as_ptr(), tar.len())
+}
+
+#[test]
+fn two_call_pattern() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let forced = root.path().join("forced.btf");
+    fs::write(&forced, btf_of_arch(8, "forced")).unwrap();
+    std::env::set_var(BTF_PATH_ENV, &forced);
+    let needed = forced.as_os_str().len() as i32 + 1;
+
+    // 探测所需的大小
+    assert_eq!(path_buf(None, &tar), needed);
+    assert_eq!(
+        ensure_core_btf_path_buf(ptr::null_mut(), 4096, tar.as_ptr(), tar.len()),
+        needed
+    );
+
+    // 差一个字节放不下 NUL，只写入空字符串
+    let mut small = vec![0xff; needed as usize - 1];
+    assert_eq!(path_buf(Some(&mut small), &tar), needed);
+    assert_eq!(small[0], 0);
+    assert!(small[1..].iter().all(|v| *v == 0xff));
+
+    // 恰好放下
+    let mut exact = vec![0xff; needed as usize];
+    assert_eq!(path_buf(Some(&mut exact), &tar), needed);
+    let path = CStr::from_bytes_with_nul(&exact).unwrap();
+    assert_eq!(path.to_str().unwrap(), forced.to_str().unwrap());
+
+    // 更大的缓冲区只用到所需的部分
+    let mut large = vec![0xff; 4096];
+    assert_eq!(path_buf(Some(&mut large), &tar), needed);
+    assert_eq!(&large[..needed as usize], &exact[..]);
+    assert_eq!(large[needed as usize], 0xff);
+
+    // 使用者提供的文件不会被删除
+    assert_eq!(clean_core_btf_path(path.as_ptr()), BPF_COMPAT_PATH_FREED);
+    assert_eq!(fs::read(&forced).unwrap(), btf_of_arch(8, "forced"));
+
+    // 查找失败时返回错误，缓冲区不变
+    std::env::set_var(BTF_PATH_ENV, root.path().join("missing.btf"));
+    let mut untouched = vec![0xff; 4096];
+    assert_eq!(path_buf(Some(&mut untouched), &tar), -libc::ENOENT);
+    assert!(untouched.iter().all(|v| *v == 0xff));
+    assert_eq!(
+        ensure_core_btf_path_buf(untouched.as_mut_ptr() as _, untouched.len(), ptr::null(), 0),
+        -libc::EINVAL
+    );
+
+    // 内核自带 btf 时返回 0 和空字符串，与指针版本返回 NULL 对应
+    std::env::remove_var(BTF_PATH_ENV);
+    if Path::new("/sys/kernel/btf/vmlinux").exists() {
+        let mut buf = vec![0xff; 16];
+        assert_eq!(path_buf(Some(&mut buf), &tar), 0);
+        assert_eq!(buf[0], 0);
+        assert_eq!(path_buf(None, &tar), 0);
+    }
+}
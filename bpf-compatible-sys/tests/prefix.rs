@@ -0,0 +1,64 @@
+//! Archives holding the btfs under a directory other than `btfhub-archive`
+mod common;
+
+use std::{ffi::CString, ptr};
+
+use bpf_compatible::opts::BpfCompatOpts;
+use bpf_compatible_rs::fixture::{btf_of_arch, FixtureArchive};
+use common::{lookup_opts, FakeRoot};
+
+/// Look up the btf in `tar` with `archive_prefix`, NULL for the default
+fn lookup(root: &FakeRoot, tar: &[u8], prefix: Option<&str>) -> Result<Vec<u8>, i32> {
+    let prefix = prefix.map(|v| CString::new(v).unwrap());
+    let opts = BpfCompatOpts {
+        archive_prefix: prefix.as_ref().map_or(ptr::null(), |v| v.as_ptr()),
+        ..root.opts()
+    };
+    lookup_opts(tar, &opts)
+}
+
+/// An archive with the btf of `root` under `dir`, and a decoy under `btfhub-archive`
+fn archive_under(root: &FakeRoot, dir: &str) -> Vec<u8> {
+    let entry = if dir.is_empty() {
+        root.info.to_string()
+    } else {
+        format!("{}/{}", dir, root.info)
+    };
+    FixtureArchive::new()
+        .file(
+            &format!("btfhub-archive/{}", root.info),
+            btf_of_arch(8, "decoy"),
+        )
+        .file(&entry, btf_of_arch(8, "wanted"))
+        .gz()
+}
+
+#[test]
+fn btfs_are_found_under_each_prefix() {
+    let root = FakeRoot::new();
+    for (dir, prefix) in [
+        ("btfs", "btfs"),
+        ("./custom-btf", "custom-btf"),
+        ("custom-btf", "./custom-btf"),
+        ("nested/btfs", "nested/btfs"),
+        // 空前缀：条目直接以发行版开头
+        ("", ""),
+        (".", ""),
+    ] {
+        let tar = archive_under(&root, dir);
+        assert_eq!(
+            lookup(&root, &tar, Some(prefix)),
+            Ok(btf_of_arch(8, "wanted")),
+            "{dir:?} {prefix:?}"
+        );
+    }
+}
+
+#[test]
+fn default_prefix_is_btfhub_archive() {
+    let root = FakeRoot::new();
+    let tar = archive_under(&root, "btfs");
+    assert_eq!(lookup(&root, &tar, None), Ok(btf_of_arch(8, "decoy")));
+    // 指定的前缀下没有任何条目时不会退回到 btfhub-archive
+    assert!(lookup(&root, &tar, Some("other")).is_err());
+}
@@ -0,0 +1,128 @@
+//! The progress callback of `struct bpf_compat_opts`, which can cancel the lookup
+mod common;
+
+use std::{
+    ffi::c_void,
+    fs,
+    os::raw::{c_char, c_int},
+    ptr,
+    sync::Mutex,
+};
+
+use bpf_compatible::{
+    ensure_core_btf_multi_opts,
+    opts::BpfCompatOpts,
+    source::{BpfCompatSource, BPF_COMPAT_SRC_BUFFER},
+};
+use bpf_compatible_rs::{fixture::btf_of_arch, progress::PROGRESS_INTERVAL};
+use common::{ensure_opts, last_error, lookup_opts, FakeRoot};
+use libc::ECANCELED;
+
+/// The calls of [`record`], and the one to cancel at, if any
+#[derive(Default)]
+struct Recorder {
+    calls: Mutex<Vec<(u64, u64)>>,
+    cancel_at: Option<usize>,
+}
+
+unsafe extern "C" fn record(processed: u64, total: u64, ctx: *mut c_void) -> c_int {
+    let recorder = &*(ctx as *const Recorder);
+    let mut calls = recorder.calls.lock().unwrap();
+    calls.push((processed, total));
+    c_int::from(recorder.cancel_at == Some(calls.len()))
+}
+
+fn with_progress(root: &FakeRoot, recorder: &Recorder) -> BpfCompatOpts {
+    BpfCompatOpts {
+        progress: Some(record),
+        progress_ctx: recorder as *const _ as *mut c_void,
+        ..root.opts()
+    }
+}
+
+/// An archive of the btf of `root`, followed by several intervals of padding
+fn large_archive(root: &FakeRoot) -> Vec<u8> {
+    root.archive(btf_of_arch(8, "progress"))
+        .file("padding", vec![0x5a; 5 * PROGRESS_INTERVAL as usize])
+        .gz()
+}
+
+fn files_in_tmp(root: &FakeRoot) -> usize {
+    fs::read_dir(root.path().join("tmp"))
+        .map(|v| v.count())
+        .unwrap_or_default()
+}
+
+#[test]
+fn progress_is_reported_while_decompressing() {
+    let root = FakeRoot::new();
+    let tar = large_archive(&root);
+    let recorder = Recorder::default();
+    assert_eq!(
+        lookup_opts(&tar, &with_progress(&root, &recorder)),
+        Ok(btf_of_arch(8, "progress")),
+        "{}",
+        last_error()
+    );
+    let calls = recorder.calls.into_inner().unwrap();
+    assert!(calls.len() >= 5, "{calls:?}");
+    // 已处理的字节数递增，总量的提示为传入的归档大小，压缩时会被超过
+    assert!(calls.windows(2).all(|v| v[0].0 < v[1].0), "{calls:?}");
+    assert!(calls.iter().all(|(_, total)| *total == tar.len() as u64));
+    assert!(calls.last().unwrap().0 > tar.len() as u64);
+}
+
+#[test]
+fn non_zero_return_cancels_without_leaving_files() {
+    let root = FakeRoot::new();
+    let tar = large_archive(&root);
+    for opts in [
+        |v: BpfCompatOpts| v,
+        |v: BpfCompatOpts| BpfCompatOpts {
+            use_memfd: true,
+            ..v
+        },
+    ] {
+        let recorder = Recorder {
+            cancel_at: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            ensure_opts(&tar, &opts(with_progress(&root, &recorder))),
+            Err(-ECANCELED)
+        );
+        assert!(last_error().contains("cancelled"), "{}", last_error());
+        // 匹配的 btf 在取消之前已部分写入，取消后删除；回调不在返回后调用
+        assert_eq!(files_in_tmp(&root), 0);
+        assert_eq!(recorder.calls.lock().unwrap().len(), 2);
+    }
+}
+
+#[test]
+fn cancelled_multi_lookup_tries_no_later_archive() {
+    let root = FakeRoot::new();
+    let tar = large_archive(&root);
+    let fallback = root.archive(btf_of_arch(8, "fallback")).gz();
+    let sources = [&tar, &fallback].map(|v| BpfCompatSource {
+        kind: BPF_COMPAT_SRC_BUFFER,
+        buf: v.as_ptr(),
+        len: v.len(),
+        path: ptr::null(),
+    });
+    let recorder = Recorder {
+        cancel_at: Some(1),
+        ..Default::default()
+    };
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_multi_opts(
+            sources.as_ptr(),
+            sources.len(),
+            &mut path,
+            &with_progress(&root, &recorder)
+        ),
+        -ECANCELED
+    );
+    assert!(path.is_null());
+    assert_eq!(files_in_tmp(&root), 0);
+}
@@ -0,0 +1,71 @@
+//! Lookups in archives of the random-access layout, see `bpf_compatible_rs::layout`
+mod common;
+
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    index::{build_index, ArchiveIndex, INDEX_ENTRY_NAME},
+    layout::{is_random_access, to_random_access},
+    reexport::{tar, Compression},
+};
+use common::lookup;
+
+/// A classic archive with the btf of the looked up kernel between others
+fn classic() -> FixtureArchive {
+    let mut fixture = FixtureArchive::new();
+    for release in ["5.4.0-26", "5.4.0-40", "5.4.0-42", "5.4.0-45"] {
+        let release = format!("{release}-generic");
+        fixture = fixture.btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            &release,
+            btf_of_arch(8, &release),
+        );
+    }
+    fixture
+}
+
+#[test]
+fn both_layouts_extract_the_same_bytes() {
+    let expected = btf_of_arch(8, "5.4.0-40-generic");
+    let gz = classic().gz();
+    let random_access = to_random_access(&gz, Compression::default()).unwrap();
+    assert!(is_random_access(&random_access));
+    assert_eq!(lookup(&gz), Ok(expected.clone()));
+    assert_eq!(lookup(&random_access), Ok(expected));
+}
+
+#[test]
+fn only_the_index_and_the_matching_entry_are_read() {
+    let random_access = to_random_access(&classic().tar(), Compression::default()).unwrap();
+    let index = ArchiveIndex::read(&random_access).unwrap();
+    let (offset, _) = index
+        .lookup("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-42-generic.btf.gz")
+        .unwrap();
+    // 截断在后面的条目中间，顺序扫描会失败
+    let truncated = &random_access[..offset as usize + 16];
+    assert_eq!(lookup(truncated), Ok(btf_of_arch(8, "5.4.0-40-generic")));
+}
+
+#[test]
+fn stale_index_falls_back_to_scanning() {
+    // 索引描述的是另一个归档，定位到的 tar 头部与之不符
+    let other = to_random_access(
+        &FixtureArchive::new().file("padding", vec![0; 4096]).tar(),
+        Compression::default(),
+    )
+    .unwrap();
+    let converted = to_random_access(&classic().tar(), Compression::default()).unwrap();
+    let mut stale = FixtureArchive::new().file(INDEX_ENTRY_NAME, build_index(&other).unwrap());
+    let mut archive = tar::Archive::new(&converted[..]);
+    for entry in archive.entries().unwrap().skip(1) {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().display().to_string();
+        let mut contents = vec![];
+        std::io::Read::read_to_end(&mut entry, &mut contents).unwrap();
+        stale = stale.file(&path, contents);
+    }
+    let stale = stale.tar();
+    assert!(is_random_access(&stale));
+    assert_eq!(lookup(&stale), Ok(btf_of_arch(8, "5.4.0-40-generic")));
+}
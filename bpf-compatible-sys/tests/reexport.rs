@@ -0,0 +1,49 @@
+//! Archives built with the tar and flate2 types bpf-compatible-rs re-exports, as this
+//! crate has no dependency of its own on them
+use bpf_compatible_rs::{
+    fixture::minimal_valid_btf,
+    pack::BtfArchiveBuilder,
+    reexport::{flate2::write::GzEncoder, tar::Builder, Compression, EntryType, Header},
+};
+use common::lookup;
+
+mod common;
+
+#[test]
+fn archive_written_with_the_reexported_types_is_found() {
+    let btf = minimal_valid_btf();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(btf.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    let mut builder = Builder::new(GzEncoder::new(vec![], Compression::fast()));
+    builder
+        .append_data(
+            &mut header,
+            "btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf",
+            &btf[..],
+        )
+        .unwrap();
+    let tar = builder.into_inner().unwrap().finish().unwrap();
+    assert_eq!(lookup(&tar), Ok(btf));
+}
+
+#[test]
+fn builder_takes_the_reexported_compression() {
+    let mut builder = BtfArchiveBuilder::new();
+    builder
+        .add_bytes(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        )
+        .unwrap();
+    let mut tar = vec![];
+    builder.write_gz(&mut tar, Compression::none()).unwrap();
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+    let plain = builder.write_tar(vec![]).unwrap();
+    assert_eq!(lookup(&plain), Ok(minimal_valid_btf()));
+}
@@ -0,0 +1,42 @@
+//! Btfs extracted under a relative `TMPDIR`
+//!
+//! This is the only test of the binary, so changing the current directory and `TMPDIR`
+//! affects no other.
+mod common;
+
+use std::{fs, os::raw::c_char, ptr};
+
+use bpf_compatible::{clean_core_btf_rs2, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::fixture::minimal_valid_btf;
+use common::{ensure_opts, path_of, FakeRoot};
+
+#[test]
+fn relative_tmpdir_is_resolved_once_and_kept_absolute() {
+    let root = FakeRoot::new();
+    let cwd = tempfile::tempdir().unwrap();
+    fs::create_dir(cwd.path().join("rel")).unwrap();
+    std::env::set_current_dir(cwd.path()).unwrap();
+    std::env::set_var("TMPDIR", "rel");
+
+    let tar = root.archive(minimal_valid_btf()).gz();
+    let opts = bpf_compatible::opts::BpfCompatOpts {
+        tmpdir: ptr::null(),
+        ..root.opts()
+    };
+    let path = ensure_opts(&tar, &opts).unwrap();
+    let extracted = path_of(path);
+    let uid = unsafe { libc::geteuid() };
+    assert_eq!(
+        extracted.parent().unwrap(),
+        cwd.path().join(format!("rel/bpf-compatible-{}", uid))
+    );
+
+    // 之后切换当前目录，返回的路径仍然有效
+    std::env::set_current_dir("/").unwrap();
+    assert_eq!(fs::read(&extracted).unwrap(), minimal_valid_btf());
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    assert!(!extracted.exists());
+}
@@ -0,0 +1,105 @@
+//! `bpf_compatible_set_allocator`, the allocator of every buffer returned
+//!
+//! The allocator is fixed for the process, so this is the only test of the binary.
+mod common;
+
+use std::{
+    ffi::c_void,
+    os::raw::c_char,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bpf_compatible::{
+    bpf_compatible_set_allocator, clean_core_btf_rs2, ensure_core_btf_with_tar_binary_opts,
+    free_core_btf_kernel_list, list_core_btf_kernels, BPF_COMPAT_BTF_DELETED,
+};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{last_error, path_of, FakeRoot};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" fn counting_alloc(size: usize) -> *mut c_void {
+    ALLOCATED.fetch_add(1, Ordering::SeqCst);
+    libc::malloc(size)
+}
+
+unsafe extern "C" fn counting_free(ptr: *mut c_void) {
+    FREED.fetch_add(1, Ordering::SeqCst);
+    libc::free(ptr)
+}
+
+unsafe extern "C" fn other_alloc(size: usize) -> *mut c_void {
+    libc::malloc(size)
+}
+
+fn counts() -> (usize, usize) {
+    (
+        ALLOCATED.load(Ordering::SeqCst),
+        FREED.load(Ordering::SeqCst),
+    )
+}
+
+#[test]
+fn every_returned_buffer_goes_through_the_hooks() {
+    // 两个函数必须同时给出
+    assert_eq!(
+        bpf_compatible_set_allocator(Some(counting_alloc), None),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        bpf_compatible_set_allocator(None, Some(counting_free)),
+        -libc::EINVAL
+    );
+    assert_eq!(
+        bpf_compatible_set_allocator(Some(counting_alloc), Some(counting_free)),
+        0
+    );
+    // 重复设置同一对函数没有问题，换成别的则被拒绝
+    assert_eq!(
+        bpf_compatible_set_allocator(Some(counting_alloc), Some(counting_free)),
+        0
+    );
+    assert_eq!(
+        bpf_compatible_set_allocator(Some(other_alloc), Some(counting_free)),
+        -libc::EBUSY
+    );
+    assert!(
+        last_error().contains("can't be replaced"),
+        "{}",
+        last_error()
+    );
+    assert_eq!(counts(), (0, 0));
+
+    // 提取的路径由钩子分配，清理时由钩子释放
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "hooked")).gz();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_binary_opts(&mut path, tar.as_ptr(), tar.len(), &root.opts()),
+        0
+    );
+    assert_eq!(counts(), (1, 0));
+    let extracted = path_of(path);
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    assert_eq!(counts(), (1, 1));
+    assert!(!extracted.exists());
+
+    // 字符串数组及其中的每个字符串同样如此
+    let mut kernels: *mut *mut c_char = ptr::null_mut();
+    let mut count = 0;
+    assert_eq!(
+        list_core_btf_kernels(tar.as_ptr(), tar.len(), &mut kernels, &mut count),
+        0
+    );
+    assert_eq!(count, 1);
+    let (allocated, freed) = counts();
+    assert!(allocated > 1 && freed == 1, "{:?}", counts());
+    free_core_btf_kernel_list(kernels);
+    let (allocated, freed) = counts();
+    assert_eq!(allocated, freed);
+}
@@ -0,0 +1,88 @@
+//! Btfs extracted to files shared between processes, under content-derived names
+//!
+//! `BPF_COMPATIBLE_SHARED` is set at the end; this is the only test of the binary, so it
+//! affects no other.
+mod common;
+
+use std::{fs, os::raw::c_char, sync::Barrier};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, opts::BpfCompatOpts, BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED,
+};
+use bpf_compatible_rs::{fixture::btf_of_arch, shared::shared_btf_name};
+use common::{ensure_opts, path_of, FakeRoot};
+
+fn extract(tar: &[u8], opts: &BpfCompatOpts) -> *mut c_char {
+    let path = ensure_opts(tar, opts).unwrap();
+    assert!(!path.is_null());
+    path as *mut c_char
+}
+
+fn shared_opts(root: &FakeRoot) -> BpfCompatOpts {
+    BpfCompatOpts {
+        share_extracted: true,
+        ..root.opts()
+    }
+}
+
+#[test]
+fn shared_btfs() {
+    let root = FakeRoot::new();
+    let btf = btf_of_arch(8, "shared");
+    let tar = root.archive(btf.clone()).gz();
+    let opts = shared_opts(&root);
+    let expected = root
+        .path()
+        .join("tmp")
+        .join(shared_btf_name(&root.info.kernel_release, &btf));
+
+    // 并发的两次提取都成功，并得到同一个文件
+    let barrier = Barrier::new(2);
+    let paths = std::thread::scope(|s| {
+        let racers = (0..2)
+            .map(|_| {
+                s.spawn(|| {
+                    // 选项中的指针不能跨线程共享，各自生成
+                    let opts = shared_opts(&root);
+                    barrier.wait();
+                    path_of(extract(&tar, &opts))
+                })
+            })
+            .collect::<Vec<_>>();
+        racers
+            .into_iter()
+            .map(|v| v.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(paths, [expected.clone(), expected.clone()]);
+    assert_eq!(fs::read(&expected).unwrap(), btf);
+    assert_eq!(fs::read_dir(root.path().join("tmp")).unwrap().count(), 1);
+
+    // 共享的文件属于所有进程，清理时留在原处
+    let path = extract(&tar, &opts);
+    assert_eq!(path_of(path), expected);
+    assert_eq!(clean_core_btf_rs2(path), BPF_COMPAT_PATH_FREED);
+    assert_eq!(fs::read(&expected).unwrap(), btf);
+
+    // 不共享时照常提取到各自的临时文件，清理时删除
+    let own = extract(&tar, &root.opts());
+    let own_path = path_of(own);
+    assert_ne!(own_path, expected);
+    assert_eq!(clean_core_btf_rs2(own), BPF_COMPAT_BTF_DELETED);
+    assert!(!own_path.exists());
+
+    // 另一个 btf 有另一个名字
+    let other = root.archive(btf_of_arch(8, "others")).gz();
+    let other_path = path_of(extract(&other, &opts));
+    assert_ne!(other_path, expected);
+    assert!(other_path
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with(&format!("{}-", root.info.kernel_release)));
+
+    // 环境变量与选项效果相同
+    std::env::set_var("BPF_COMPATIBLE_SHARED", "1");
+    assert_eq!(path_of(extract(&tar, &root.opts())), expected);
+}
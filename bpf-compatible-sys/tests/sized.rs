@@ -0,0 +1,119 @@
+//! The size of the btf stored along with its path
+mod common;
+
+use std::{fs, io::Write, os::raw::c_char, ptr};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, ensure_core_btf_with_tar_binary_sized, opts::BpfCompatOpts,
+    BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED,
+};
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive},
+    reexport::flate2::{write::GzEncoder, Compression},
+};
+use common::{path_of, FakeRoot};
+use libc::ENOENT;
+
+/// Never the size of a btf
+const UNSET: usize = usize::MAX;
+
+/// The path and size stored by the lookup of `tar` with `opts`
+fn lookup(tar: &[u8], opts: &BpfCompatOpts) -> Result<(*const c_char, usize), (i32, usize)> {
+    let mut path: *const c_char = ptr::null();
+    let mut size = UNSET;
+    match ensure_core_btf_with_tar_binary_sized(&mut path, tar.as_ptr(), tar.len(), opts, &mut size)
+    {
+        0 => Ok((path, size)),
+        err => Err((err, size)),
+    }
+}
+
+#[test]
+fn size_is_that_of_the_btf_written() {
+    let root = FakeRoot::new();
+    let btf = btf_of_arch(8, "sized");
+    let (path, size) = lookup(&root.archive(btf.clone()).gz(), &root.opts()).unwrap();
+    assert_eq!(size, btf.len());
+    assert_eq!(fs::metadata(path_of(path)).unwrap().len() as usize, size);
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+
+    // 压缩的条目按解压后的大小计，而不是条目在归档中的大小
+    let mut encoder = GzEncoder::new(vec![], Compression::best());
+    encoder.write_all(&btf).unwrap();
+    let gzipped = encoder.finish().unwrap();
+    assert_ne!(gzipped.len(), btf.len());
+    let tar = FixtureArchive::new()
+        .file(&format!("btfhub-archive/{}.gz", root.info), gzipped)
+        .gz();
+    let (path, size) = lookup(&tar, &root.opts()).unwrap();
+    assert_eq!(size, btf.len());
+    assert_eq!(fs::read(path_of(path)).unwrap(), btf);
+    clean_core_btf_rs2(path as *mut c_char);
+
+    // 写入 memfd 时同样
+    let opts = BpfCompatOpts {
+        use_memfd: true,
+        ..root.opts()
+    };
+    let (path, size) = lookup(&root.archive(btf.clone()).gz(), &opts).unwrap();
+    assert_eq!(size, btf.len());
+    clean_core_btf_rs2(path as *mut c_char);
+}
+
+#[test]
+fn size_of_the_native_btf_depends_on_always_path() {
+    let root = FakeRoot::new();
+    fs::create_dir_all(root.path().join("sys/kernel/btf")).unwrap();
+    fs::write(
+        root.path().join("sys/kernel/btf/vmlinux"),
+        minimal_valid_btf(),
+    )
+    .unwrap();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let (path, size) = lookup(&tar, &root.opts()).unwrap();
+    assert!(path.is_null());
+    assert_eq!(size, 0);
+    let opts = BpfCompatOpts {
+        always_path: true,
+        ..root.opts()
+    };
+    let (path, size) = lookup(&tar, &opts).unwrap();
+    assert_eq!(size, minimal_valid_btf().len());
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_PATH_FREED
+    );
+}
+
+#[test]
+fn size_is_optional_and_only_written_on_success() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "sized")).gz();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_binary_sized(
+            &mut path,
+            tar.as_ptr(),
+            tar.len(),
+            &root.opts(),
+            ptr::null_mut()
+        ),
+        0
+    );
+    assert_eq!(fs::read(path_of(path)).unwrap(), btf_of_arch(8, "sized"));
+    clean_core_btf_rs2(path as *mut c_char);
+
+    let other = FixtureArchive::new()
+        .btf(
+            "debian",
+            "11",
+            "x86_64",
+            "5.10.0-26-amd64",
+            minimal_valid_btf(),
+        )
+        .gz();
+    assert_eq!(lookup(&other, &root.opts()).err(), Some((-ENOENT, UNSET)));
+}
@@ -0,0 +1,52 @@
+//! Sparse entries, whose holes of zeros must be filled back in rather than the encoded
+//! contents sliced out of the tar
+mod common;
+
+use bpf_compatible_rs::fixture::{btf_with_hole, FixtureArchive};
+use common::{last_error, lookup_opts, FakeRoot};
+
+#[test]
+fn sparse_btfs_are_extracted_byte_for_byte() {
+    let root = FakeRoot::new();
+    let path = format!("btfhub-archive/{}", root.info);
+    let btf = btf_with_hole();
+    let chunks: &[(u64, &[u8])] = &[(0, &btf[..512]), (1536, &btf[1536..])];
+    let size = btf.len() as u64;
+    for archive in [
+        FixtureArchive::new().sparse(&path, chunks, size),
+        FixtureArchive::new().pax_sparse(&path, chunks, size),
+        // 指向稀疏条目的链接
+        FixtureArchive::new()
+            .sparse("btfhub-archive/shared.btf", chunks, size)
+            .hardlink(&path, "btfhub-archive/shared.btf"),
+        // 每个内核单独打包时，内层的 tar 中的稀疏条目
+        FixtureArchive::new().file(
+            &format!("{}.tar.gz", path),
+            FixtureArchive::new()
+                .pax_sparse("vmlinux.btf", chunks, size)
+                .gz(),
+        ),
+    ] {
+        assert_eq!(lookup_opts(&archive.gz(), &root.opts()), Ok(btf.clone()));
+        assert_eq!(lookup_opts(&archive.tar(), &root.opts()), Ok(btf.clone()));
+    }
+}
+
+#[test]
+fn malformed_sparse_maps_fail_the_lookup() {
+    let root = FakeRoot::new();
+    let path = format!("btfhub-archive/{}", root.info);
+    let btf = btf_with_hole();
+    // 片段超出文件的大小，或顺序颠倒
+    for chunks in [
+        &[(0, &btf[..512]), (btf.len() as u64, &btf[1536..])][..],
+        &[(1536, &btf[1536..]), (0, &btf[..512])][..],
+    ] {
+        let tar = FixtureArchive::new()
+            .pax_sparse(&path, chunks, btf.len() as u64)
+            .gz();
+        let err = lookup_opts(&tar, &root.opts()).unwrap_err();
+        assert!(err < 0);
+        assert!(last_error().contains("sparse"), "{}", last_error());
+    }
+}
@@ -0,0 +1,89 @@
+//! The archive is decompressed as a stream, never held in memory as a whole
+//!
+//! The allocator of this binary tracks the peak of the bytes allocated, so this file
+//! holds a single test, which nothing else allocates alongside.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bpf_compatible_rs::fixture::{minimal_valid_btf, FixtureArchive};
+use common::lookup;
+
+mod common;
+
+struct PeakTracking;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(size: usize) {
+    let current = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(current, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for PeakTracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakTracking = PeakTracking;
+
+/// Size of each of the other btfs, which the lookup reads past
+const FILLER_SIZE: usize = 4 << 20;
+/// Number of the other btfs, before the one looked up
+const FILLERS: usize = 16;
+
+#[test]
+fn lookup_allocates_far_less_than_the_decompressed_archive() {
+    let mut archive = FixtureArchive::new();
+    for i in 0..FILLERS {
+        archive = archive.btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            &format!("5.4.0-{}-generic", 100 + i),
+            vec![0; FILLER_SIZE],
+        );
+    }
+    let tar = archive
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        )
+        .gz();
+
+    PEAK.store(CURRENT.load(Ordering::Relaxed), Ordering::Relaxed);
+    let before = CURRENT.load(Ordering::Relaxed);
+    assert_eq!(lookup(&tar), Ok(minimal_valid_btf()));
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+    // 解压后的归档有 64 MiB，查找时分配的内存只与几个缓冲区相当
+    assert!(
+        peak < 1 << 20,
+        "{peak} bytes allocated for a {} bytes archive",
+        FILLERS * FILLER_SIZE
+    );
+}
@@ -0,0 +1,60 @@
+//! Lookups for the host's identity, from a root mounted into a container
+mod common;
+
+use std::fs;
+
+use bpf_compatible_rs::fixture::{btf_of_arch, minimal_valid_btf, FixtureArchive};
+use common::{ensure_opts, last_error, lookup_opts, FakeRoot};
+
+#[test]
+fn os_release_of_the_sysroot_is_used() {
+    let root = FakeRoot::new();
+    let ubuntu = btf_of_arch(8, "ubuntu");
+    // 容器自身的 os-release 描述的是镜像，不应被使用
+    let tar = root
+        .archive(ubuntu.clone())
+        .file(
+            &format!(
+                "btfhub-archive/alpine/3.18/{}/{}.btf",
+                root.info.arch, root.info.kernel_release
+            ),
+            minimal_valid_btf(),
+        )
+        .gz();
+    assert_eq!(root.info.distro_id, "ubuntu");
+    assert_eq!(root.info.version_id, "20.04");
+    assert_eq!(lookup_opts(&tar, &root.opts()), Ok(ubuntu));
+}
+
+#[test]
+fn native_btf_is_looked_for_under_the_sysroot() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    fs::create_dir_all(root.path().join("sys/kernel/btf")).unwrap();
+    fs::write(
+        root.path().join("sys/kernel/btf/vmlinux"),
+        minimal_valid_btf(),
+    )
+    .unwrap();
+    assert!(ensure_opts(&tar, &root.opts()).unwrap().is_null());
+    // 根目录下的 btf 不可用时从归档中提取
+    fs::write(root.path().join("sys/kernel/btf/vmlinux"), b"garbage").unwrap();
+    assert_eq!(
+        lookup_opts(&tar, &root.opts()),
+        Ok(btf_of_arch(8, "archived"))
+    );
+}
+
+#[test]
+fn sysroot_without_a_distro_fails_naming_what_was_tried() {
+    let root = FakeRoot::new();
+    fs::remove_file(root.path().join("etc/os-release")).unwrap();
+    let tar = FixtureArchive::new()
+        .file("btfhub-archive/ubuntu/20.04/x86_64/x.btf", vec![])
+        .gz();
+    assert_eq!(lookup_opts(&tar, &root.opts()), Err(-libc::ENOENT));
+    let message = last_error();
+    let tried = root.path().join("etc/os-release");
+    assert!(message.contains(&*tried.to_string_lossy()), "{message}");
+    assert!(message.contains("OsReleaseMissing"), "{message}");
+}
@@ -0,0 +1,130 @@
+//! `get_current_system_info` and `get_current_system_btf_rel_path`, describing the running system
+//!
+//! With the `fake-system` feature, the identity is faked with the variables of the whole
+//! process, so this is the only test of the binary.
+mod common;
+
+use std::{
+    ffi::CStr,
+    mem::size_of,
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+use bpf_compatible::{
+    bpf_compatible_free_buffer, get_current_system_btf_rel_path, get_current_system_info,
+    system_info::BpfCompatSystemInfo,
+};
+use common::last_error;
+
+fn text(v: &[c_char]) -> &str {
+    unsafe { CStr::from_ptr(v.as_ptr()) }.to_str().unwrap()
+}
+
+/// The running system through the C API, or the error
+fn system_info() -> Result<BpfCompatSystemInfo, c_int> {
+    let mut info: BpfCompatSystemInfo = unsafe { std::mem::zeroed() };
+    info.sz = size_of::<BpfCompatSystemInfo>();
+    match get_current_system_info(&mut info) {
+        0 => Ok(info),
+        err => Err(err),
+    }
+}
+
+/// The relative path of the btf of the running system through the C API, or the error
+fn rel_path() -> Result<String, c_int> {
+    let mut path: *const c_char = ptr::null();
+    match get_current_system_btf_rel_path(&mut path) {
+        0 => {}
+        err => {
+            assert!(path.is_null());
+            return Err(err);
+        }
+    }
+    let owned = unsafe { CStr::from_ptr(path) }
+        .to_str()
+        .unwrap()
+        .to_string();
+    bpf_compatible_free_buffer(path as *mut u8);
+    Ok(owned)
+}
+
+#[test]
+fn running_system_is_described() {
+    assert_eq!(get_current_system_info(ptr::null_mut()), -libc::EINVAL);
+    assert_eq!(
+        get_current_system_btf_rel_path(ptr::null_mut()),
+        -libc::EINVAL
+    );
+    #[cfg(feature = "fake-system")]
+    faked::described();
+    #[cfg(not(feature = "fake-system"))]
+    {
+        // 与 Rust 接口看到的系统一致；无法识别发行版时两者都失败
+        let Ok(expected) = bpf_compatible_rs::SystemInfo::detect() else {
+            assert_eq!(system_info().err(), Some(-libc::ENOENT));
+            assert_eq!(rel_path(), Err(-libc::ENOENT));
+            assert!(!last_error().is_empty());
+            return;
+        };
+        let info = system_info().unwrap();
+        assert_eq!(text(&info.distro), expected.distro_id);
+        assert_eq!(text(&info.version), expected.version_id);
+        assert_eq!(text(&info.arch), expected.arch);
+        assert_eq!(text(&info.kernel_release), expected.kernel_release);
+        assert_eq!(rel_path().unwrap(), expected.to_string());
+    }
+}
+
+#[cfg(feature = "fake-system")]
+mod faked {
+    use bpf_compatible_rs::fake::{
+        FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV,
+    };
+
+    use super::*;
+
+    fn fake(distro: &str, version: &str, arch: &str, release: &str) {
+        std::env::set_var(FAKE_DISTRO_ENV, distro);
+        std::env::set_var(FAKE_VERSION_ENV, version);
+        std::env::set_var(FAKE_ARCH_ENV, arch);
+        std::env::set_var(FAKE_KERNEL_ENV, release);
+    }
+
+    pub fn described() {
+        fake("ubuntu", "20.04", "x86_64", "5.4.0-40-generic");
+        let info = system_info().unwrap();
+        assert_eq!(info.sz, size_of::<BpfCompatSystemInfo>());
+        assert_eq!(text(&info.distro), "ubuntu");
+        assert_eq!(text(&info.version), "20.04");
+        assert_eq!(text(&info.arch), "x86_64");
+        assert_eq!(text(&info.kernel_release), "5.4.0-40-generic");
+        assert_eq!(
+            rel_path().unwrap(),
+            "ubuntu/20.04/x86_64/5.4.0-40-generic.btf"
+        );
+
+        // btfhub 的目录名与 uname 的机器名不同
+        fake("debian", "11", "aarch64", "5.10.0-23-arm64");
+        assert_eq!(text(&system_info().unwrap().arch), "aarch64");
+        assert_eq!(rel_path().unwrap(), "debian/11/arm64/5.10.0-23-arm64.btf");
+
+        // 滚动发行版没有版本号
+        fake("arch", "", "x86_64", "6.5.3-arch1-1");
+        assert_eq!(text(&system_info().unwrap().version), "");
+
+        // 过长的内核版本被截断，仍以 NUL 结尾
+        let long = format!("5.4.0-40-{}", "x".repeat(200));
+        fake("ubuntu", "20.04", "x86_64", &long);
+        let info = system_info().unwrap();
+        assert_eq!(text(&info.kernel_release), &long[..127]);
+        assert!(rel_path().unwrap().contains(&long));
+
+        // 缺少版本号的发行版无法查找，给出原因
+        fake("ubuntu", "", "x86_64", "5.4.0-40-generic");
+        assert_eq!(system_info().err(), Some(-libc::ENOENT));
+        assert!(!last_error().is_empty());
+        assert_eq!(rel_path(), Err(-libc::ENOENT));
+        assert!(last_error().contains("running system"), "{}", last_error());
+    }
+}
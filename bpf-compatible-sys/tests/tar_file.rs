@@ -0,0 +1,88 @@
+//! `ensure_core_btf_with_tar_file`, for the running system with the default options
+mod common;
+
+use std::{
+    ffi::CString,
+    fs,
+    os::{raw::c_char, unix::ffi::OsStrExt},
+    path::Path,
+    ptr,
+};
+
+use bpf_compatible::{ensure_core_btf_with_tar_file, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::{
+    current_kernel_release,
+    fixture::{btf_of_arch, FixtureArchive},
+    SystemInfo,
+};
+use common::{last_error, take_btf};
+
+fn lookup_file(archive: &Path) -> Result<Option<Vec<u8>>, i32> {
+    let archive = CString::new(archive.as_os_str().as_bytes()).unwrap();
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_with_tar_file(&mut path, archive.as_ptr());
+    if err != 0 {
+        assert!(path.is_null());
+        return Err(err);
+    }
+    if path.is_null() {
+        return Ok(None);
+    }
+    let contents = take_btf(path, BPF_COMPAT_BTF_DELETED);
+    Ok(Some(contents))
+}
+
+fn has_native_btf() -> bool {
+    Path::new("/sys/kernel/btf/vmlinux").exists()
+}
+
+#[test]
+fn btf_of_the_running_system_is_taken_from_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let host = SystemInfo::detect().unwrap();
+    let file = dir.path().join("min_core_btfs.tar.gz");
+    fs::write(
+        &file,
+        FixtureArchive::new()
+            .file(&format!("btfhub-archive/{host}"), btf_of_arch(8, "rip"))
+            .gz(),
+    )
+    .unwrap();
+    assert_eq!(current_kernel_release().unwrap(), host.kernel_release);
+    // 内核自带 btf 时不从文件中提取
+    let expected = (!has_native_btf()).then(|| btf_of_arch(8, "rip"));
+    assert_eq!(lookup_file(&file), Ok(expected));
+}
+
+#[test]
+fn missing_file_fails_with_enoent() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("min_core_btfs.tar.gz");
+    // 即使内核自带 btf，文件也必须存在
+    assert_eq!(lookup_file(&missing), Err(-libc::ENOENT));
+    assert!(
+        last_error().contains("min_core_btfs.tar.gz"),
+        "{}",
+        last_error()
+    );
+
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_file(&mut path, ptr::null()),
+        -libc::EINVAL
+    );
+}
+
+#[test]
+fn non_archive_fails_with_einval_unless_native_btf_is_used() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("min_core_btfs.tar.gz");
+    fs::write(&file, [0xa5; 4096]).unwrap();
+    if has_native_btf() {
+        // 内核自带 btf 时不读取归档
+        assert_eq!(lookup_file(&file), Ok(None));
+    } else {
+        assert_eq!(lookup_file(&file), Err(-libc::EINVAL));
+        assert!(!last_error().is_empty());
+    }
+}
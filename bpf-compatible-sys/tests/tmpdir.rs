@@ -0,0 +1,57 @@
+//! Btfs extracted under a `TMPDIR` whose path isn't UTF-8
+//!
+//! This is the only test of the binary, so setting `TMPDIR` affects no other.
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs,
+    os::{raw::c_char, unix::ffi::OsStrExt},
+    path::Path,
+    ptr,
+};
+
+use bpf_compatible::{clean_core_btf_rs2, ensure_core_btf_for_system, BPF_COMPAT_BTF_DELETED};
+use bpf_compatible_rs::fixture::{minimal_valid_btf, FixtureArchive};
+
+#[test]
+fn non_utf8_tmpdir_is_kept_byte_for_byte() {
+    let dir = tempfile::tempdir().unwrap();
+    let tmpdir = dir.path().join(OsStr::from_bytes(b"btf-\xff\xfe"));
+    fs::create_dir(&tmpdir).unwrap();
+    std::env::set_var("TMPDIR", &tmpdir);
+
+    let tar = FixtureArchive::new()
+        .btf(
+            "ubuntu",
+            "20.04",
+            "x86_64",
+            "5.4.0-40-generic",
+            minimal_valid_btf(),
+        )
+        .gz();
+    let [distro, version, arch, release] =
+        ["ubuntu", "20.04", "x86_64", "5.4.0-40-generic"].map(|v| CString::new(v).unwrap());
+    let mut path: *const c_char = ptr::null();
+    let err = ensure_core_btf_for_system(
+        &mut path,
+        tar.as_ptr(),
+        tar.len(),
+        distro.as_ptr(),
+        version.as_ptr(),
+        arch.as_ptr(),
+        release.as_ptr(),
+    );
+    assert_eq!(err, 0);
+
+    // 返回的路径是磁盘上文件名的原始字节，没有经过有损的 UTF-8 转换
+    let extracted = Path::new(OsStr::from_bytes(
+        unsafe { CStr::from_ptr(path) }.to_bytes(),
+    ));
+    assert!(extracted.starts_with(&tmpdir), "{}", extracted.display());
+    assert_eq!(fs::read(extracted).unwrap(), minimal_valid_btf());
+    let extracted = extracted.to_path_buf();
+    assert_eq!(
+        clean_core_btf_rs2(path as *mut c_char),
+        BPF_COMPAT_BTF_DELETED
+    );
+    assert!(!extracted.exists());
+}
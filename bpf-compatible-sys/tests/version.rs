@@ -0,0 +1,80 @@
+//! What support tooling collects: the version, features and linked archive of the library
+use std::ffi::CStr;
+
+use bpf_compatible::{
+    bpf_compatible_archive_identity, bpf_compatible_features, bpf_compatible_version,
+    BPF_COMPAT_FEATURE_AUDIT_LOG, BPF_COMPAT_FEATURE_DOWNLOAD, BPF_COMPAT_FEATURE_FAKE_SYSTEM,
+    BPF_COMPAT_FEATURE_PAHOLE, BPF_COMPAT_FEATURE_XZ, BPF_COMPAT_FEATURE_ZSTD,
+};
+
+#[test]
+fn version_is_the_one_of_the_crate() {
+    let version = unsafe { CStr::from_ptr(bpf_compatible_version()) };
+    assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    // 字符串是静态的，每次返回同一个指针
+    assert_eq!(bpf_compatible_version(), bpf_compatible_version());
+    // 与清单中的版本一致
+    let manifest = include_str!("../Cargo.toml");
+    let line = manifest
+        .lines()
+        .find(|v| v.starts_with("version = "))
+        .unwrap();
+    assert_eq!(line, format!("version = \"{}\"", version.to_str().unwrap()));
+}
+
+#[test]
+fn header_defines_the_same_feature_bits() {
+    let header = include_str!("../btf_helpers.h");
+    let bits = [
+        ("AUDIT_LOG", BPF_COMPAT_FEATURE_AUDIT_LOG),
+        ("ZSTD", BPF_COMPAT_FEATURE_ZSTD),
+        ("XZ", BPF_COMPAT_FEATURE_XZ),
+        ("DOWNLOAD", BPF_COMPAT_FEATURE_DOWNLOAD),
+        ("FAKE_SYSTEM", BPF_COMPAT_FEATURE_FAKE_SYSTEM),
+        ("PAHOLE", BPF_COMPAT_FEATURE_PAHOLE),
+    ];
+    let defined = header
+        .lines()
+        .filter_map(|v| v.strip_prefix("#define BPF_COMPAT_FEATURE_"))
+        .count();
+    assert_eq!(defined, bits.len());
+    let mut seen = 0;
+    for (name, bit) in bits {
+        let define = format!(
+            "#define BPF_COMPAT_FEATURE_{} (1U << {})",
+            name,
+            bit.trailing_zeros()
+        );
+        assert!(header.contains(&define), "{define}");
+        // 每个特性占用不同的位
+        assert_eq!(bit.count_ones(), 1);
+        assert_eq!(seen & bit, 0);
+        seen |= bit;
+    }
+    // 未定义的位始终为 0
+    assert_eq!(bpf_compatible_features() & !seen, 0);
+}
+
+#[test]
+fn features_are_those_built_with() {
+    let features = bpf_compatible_features();
+    for (bit, enabled) in [
+        (BPF_COMPAT_FEATURE_AUDIT_LOG, cfg!(feature = "audit-log")),
+        (BPF_COMPAT_FEATURE_ZSTD, cfg!(feature = "zstd")),
+        (BPF_COMPAT_FEATURE_XZ, cfg!(feature = "xz")),
+        (BPF_COMPAT_FEATURE_DOWNLOAD, cfg!(feature = "download")),
+        (
+            BPF_COMPAT_FEATURE_FAKE_SYSTEM,
+            cfg!(feature = "fake-system"),
+        ),
+        (BPF_COMPAT_FEATURE_PAHOLE, cfg!(feature = "pahole")),
+    ] {
+        assert_eq!(features & bit != 0, enabled, "{bit:#x}");
+    }
+}
+
+#[test]
+fn identity_without_a_linked_archive_is_none() {
+    let identity = unsafe { CStr::from_ptr(bpf_compatible_archive_identity()) };
+    assert_eq!(identity.to_str().unwrap(), "none");
+}
@@ -0,0 +1,63 @@
+//! Lookups and handed out paths whatever the current directory
+//!
+//! This is the only test of the binary, so changing the current directory and
+//! `BPF_COMPATIBLE_BTF_PATH` affects no other.
+mod common;
+
+use std::{
+    fs,
+    os::raw::c_char,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use bpf_compatible::{
+    clean_core_btf_rs2, ensure_core_btf_with_tar_binary_opts, opts::BpfCompatOpts,
+    BPF_COMPAT_BTF_DELETED, BPF_COMPAT_PATH_FREED,
+};
+use bpf_compatible_rs::fixture::btf_of_arch;
+use common::{last_error, path_of, FakeRoot};
+
+/// The path handed out for `tar` with `opts`, and the result of cleaning it once read
+/// from another directory
+fn ensure_then_leave(tar: &[u8], opts: &BpfCompatOpts, cwd: &Path) -> (PathBuf, Vec<u8>, i32) {
+    std::env::set_current_dir(cwd).unwrap();
+    let mut path: *const c_char = ptr::null();
+    assert_eq!(
+        ensure_core_btf_with_tar_binary_opts(&mut path, tar.as_ptr(), tar.len(), opts),
+        0,
+        "{}",
+        last_error()
+    );
+    let handed_out = path_of(path);
+    // libbpf 可能在切换目录之后才打开文件
+    std::env::set_current_dir("/").unwrap();
+    let btf = fs::read(&handed_out).unwrap();
+    (handed_out, btf, clean_core_btf_rs2(path as *mut c_char))
+}
+
+#[test]
+fn paths_stay_valid_after_changing_directory() {
+    let root = FakeRoot::new();
+    let tar = root.archive(btf_of_arch(8, "archived")).gz();
+    let opts = root.opts();
+    let cwd = tempfile::tempdir().unwrap();
+
+    // 当前目录下同名的文件不影响归档中的匹配
+    let decoy = cwd.path().join(format!("btfhub-archive/{}", root.info));
+    fs::create_dir_all(decoy.parent().unwrap()).unwrap();
+    fs::write(&decoy, btf_of_arch(8, "decoy")).unwrap();
+    let (_, btf, cleaned) = ensure_then_leave(&tar, &opts, cwd.path());
+    assert_eq!(btf, btf_of_arch(8, "archived"));
+    assert_eq!(cleaned, BPF_COMPAT_BTF_DELETED);
+    assert_eq!(fs::read(&decoy).unwrap(), btf_of_arch(8, "decoy"));
+
+    // 相对的 BPF_COMPATIBLE_BTF_PATH 按调用时的当前目录解析
+    fs::write(cwd.path().join("forced.btf"), btf_of_arch(8, "forced")).unwrap();
+    std::env::set_var("BPF_COMPATIBLE_BTF_PATH", "forced.btf");
+    let (path, btf, cleaned) = ensure_then_leave(&tar, &opts, cwd.path());
+    std::env::remove_var("BPF_COMPATIBLE_BTF_PATH");
+    assert_eq!(path, cwd.path().join("forced.btf"));
+    assert_eq!(btf, btf_of_arch(8, "forced"));
+    assert_eq!(cleaned, BPF_COMPAT_PATH_FREED);
+}
@@ -0,0 +1,188 @@
+//! `ensure_core_btf_write_fd`, writing the btf to a descriptor of the caller
+//!
+//! Faking the system sets variables of the whole process, so this is the only test of the
+//! binary. Without the `fake-system` feature, only the running system is looked up.
+#![cfg(target_os = "linux")]
+mod common;
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    path::Path,
+    thread,
+};
+
+use bpf_compatible::ensure_core_btf_write_fd;
+use bpf_compatible_rs::{
+    fixture::{btf_of_arch, FixtureArchive},
+    SystemInfo,
+};
+
+fn is_open(fd: i32) -> bool {
+    unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+}
+
+/// Write the btf of `tar` to a pipe, returning the result and what the pipe received
+fn write_to_pipe(tar: &[u8]) -> (i32, Vec<u8>) {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let [reader, writer] = fds;
+    // 读取方较慢，写入方会遇到部分写入
+    let drain = thread::spawn(move || {
+        let mut reader = unsafe { File::from_raw_fd(reader) };
+        let mut received = vec![];
+        let mut chunk = [0u8; 509];
+        loop {
+            match reader.read(&mut chunk).unwrap() {
+                0 => return received,
+                n => received.extend_from_slice(&chunk[..n]),
+            }
+        }
+    });
+    let ret = ensure_core_btf_write_fd(writer, tar.as_ptr(), tar.len());
+    assert!(is_open(writer));
+    unsafe { libc::close(writer) };
+    (ret, drain.join().unwrap())
+}
+
+/// Write the btf of `tar` after the bytes already in a regular file, returning the result and the file
+fn write_to_file(tar: &[u8]) -> (i32, Vec<u8>) {
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(b"header").unwrap();
+    let ret = ensure_core_btf_write_fd(file.as_raw_fd(), tar.as_ptr(), tar.len());
+    assert!(is_open(file.as_raw_fd()));
+    // 偏移停在写入的末尾，没有被移回
+    let end = file.stream_position().unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut contents = vec![];
+    file.read_to_end(&mut contents).unwrap();
+    assert_eq!(end, contents.len() as u64);
+    (ret, contents)
+}
+
+#[test]
+fn btf_is_written_to_the_descriptor() {
+    let tar = FixtureArchive::new().gz();
+    assert_eq!(
+        ensure_core_btf_write_fd(-1, tar.as_ptr(), tar.len()),
+        -libc::EBADF
+    );
+    assert_eq!(
+        ensure_core_btf_write_fd(1, std::ptr::null(), tar.len()),
+        -libc::EINVAL
+    );
+
+    // 内核自带 btf 时不写入任何内容
+    let host = SystemInfo::detect().unwrap();
+    let tar = FixtureArchive::new()
+        .file(&format!("btfhub-archive/{host}"), btf_of_arch(8, "rip"))
+        .gz();
+    let expected = match Path::new("/sys/kernel/btf/vmlinux").exists() {
+        true => vec![],
+        false => btf_of_arch(8, "rip"),
+    };
+    assert_eq!(
+        write_to_pipe(&tar),
+        (expected.len() as i32, expected.clone())
+    );
+    assert_eq!(
+        write_to_file(&tar),
+        (expected.len() as i32, [&b"header"[..], &expected].concat())
+    );
+
+    #[cfg(feature = "fake-system")]
+    faked::writes();
+}
+
+#[cfg(feature = "fake-system")]
+mod faked {
+    use std::io::{self, Write};
+
+    use bpf_compatible::ensure_core_btf_write_fd;
+    use bpf_compatible_rs::{
+        ensure_core_btf_write,
+        fake::{FAKE_ARCH_ENV, FAKE_DISTRO_ENV, FAKE_KERNEL_ENV, FAKE_VERSION_ENV},
+        fixture::{btf_of_arch, FixtureArchive},
+        Error,
+    };
+
+    use super::{write_to_file, write_to_pipe};
+    use crate::common::last_error;
+
+    /// A writer accepting `room` bytes, then failing
+    struct Full {
+        room: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::ErrorKind::StorageFull.into());
+            }
+            let n = buf.len().min(self.room).min(3);
+            self.room -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub fn writes() {
+        // 伪造内核版本时不使用真实内核自带的 btf
+        std::env::set_var(FAKE_DISTRO_ENV, "ubuntu");
+        std::env::set_var(FAKE_VERSION_ENV, "20.04");
+        std::env::set_var(FAKE_ARCH_ENV, "x86_64");
+        std::env::set_var(FAKE_KERNEL_ENV, "5.4.0-40-generic");
+        // 足够大，管道的缓冲区放不下
+        let btf = btf_of_arch(8, &"r".repeat(200_000));
+        let tar = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-40-generic", btf.clone())
+            .gz();
+
+        let (ret, written) = write_to_pipe(&tar);
+        assert_eq!(ret as usize, btf.len(), "{}", last_error());
+        assert_eq!(written, btf);
+        let (ret, contents) = write_to_file(&tar);
+        assert_eq!(ret as usize, btf.len());
+        assert_eq!(contents, [&b"header"[..], &btf].concat());
+
+        // 读取方已关闭的管道
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe { libc::close(fds[0]) };
+        assert_eq!(
+            ensure_core_btf_write_fd(fds[1], tar.as_ptr(), tar.len()),
+            -libc::EPIPE
+        );
+        assert!(
+            last_error().contains(&format!("fd {}", fds[1])),
+            "{}",
+            last_error()
+        );
+        unsafe { libc::close(fds[1]) };
+        // 找不到 btf 时不写入
+        let other = FixtureArchive::new()
+            .btf("ubuntu", "20.04", "x86_64", "5.4.0-42-generic", btf.clone())
+            .gz();
+        assert_eq!(write_to_pipe(&other), (-libc::ENOENT, vec![]));
+
+        // Rust 中写入任意的 Write
+        let mut out = b"header".to_vec();
+        let (entry, size) = ensure_core_btf_write(&tar, &mut out).unwrap().unwrap();
+        assert_eq!(size as usize, btf.len());
+        assert_eq!(out, [&b"header"[..], &btf].concat());
+        assert_eq!(
+            entry.path.to_str(),
+            Some("btfhub-archive/ubuntu/20.04/x86_64/5.4.0-40-generic.btf")
+        );
+        let e = ensure_core_btf_write(&tar, &mut Full { room: 100 }).unwrap_err();
+        assert!(
+            matches!(&e, Error::FileWriteError(path, e)
+                if path == entry.path.to_str().unwrap() && e.kind() == io::ErrorKind::StorageFull),
+            "{e:?}"
+        );
+    }
+}